@@ -0,0 +1,267 @@
+//! # Change stream
+//!
+//! Every change to the leases table is recorded in `lease_history` by triggers. With
+//! `--change-stream` those rows are shipped in order to a file, one JSON object per line,
+//! or to a NATS subject, one message each:
+//!
+//! ```json
+//! {"seq":42,"op":"update","ip":"192.168.0.150","at":"2026-10-16T12:00:00Z",
+//!  "before":{"client_id":"01:ab","state":"reserved","expires_at":"2026-10-16T12:01:00Z"},
+//!  "after":{"client_id":"01:ab","state":"leased","expires_at":"2026-10-17T12:00:00Z"}}
+//! ```
+//!
+//! `op` is `insert`, `update` or `delete`, `before` is `null` for an insert & `after` for
+//! a delete. The last `seq` shipped is saved in the database once the sink has it, so the
+//! stream resumes there after a restart. A change can be shipped twice if dora stops in
+//! between, consumers should skip a `seq` they've already seen. History isn't pruned
+//! past the saved `seq`, so a sink that's down holds back pruning rather than losing
+//! changes. A gap in `seq` anyway, ex. history pruned while the stream was off, is logged
+//! & counted in `change_stream_missed_count`.
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+#[cfg(not(feature = "nats"))]
+use anyhow::bail;
+use anyhow::{Context, Result};
+use dora_core::{
+    chrono::{DateTime, SecondsFormat, Utc},
+    metrics::{CHANGE_STREAM_MISSED_COUNT, CHANGE_STREAM_SHIPPED_COUNT},
+    pretty::Hex,
+    tokio::{self, fs, io::AsyncWriteExt},
+    tracing::{error, info, warn},
+};
+use ip_manager::{sqlite::SqliteDb, Change, ChangeOp, HistoryEntry, IpManager, IpState};
+use serde_json::{json, Value};
+
+/// how often history is checked for new changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// most changes read from history at once
+const BATCH: usize = 500;
+/// subject used when a NATS destination doesn't name one
+#[cfg(feature = "nats")]
+const DEFAULT_SUBJECT: &str = "dora.leases";
+
+/// where changes are shipped
+#[derive(Debug)]
+pub(crate) enum Sink {
+    /// appended to as JSON lines
+    File { path: PathBuf, file: fs::File },
+    /// published one change per message
+    #[cfg(feature = "nats")]
+    Nats {
+        client: async_nats::Client,
+        subject: String,
+    },
+}
+
+impl Sink {
+    /// `dest` is `nats://host:port/subject` or a file path
+    pub(crate) async fn open(dest: &str) -> Result<Self> {
+        if dest.starts_with("nats://") {
+            return Self::nats(dest).await;
+        }
+        let path = PathBuf::from(dest.strip_prefix("file://").unwrap_or(dest));
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("failed to open change stream file {}", path.display()))?;
+        Ok(Sink::File { path, file })
+    }
+
+    #[cfg(feature = "nats")]
+    async fn nats(dest: &str) -> Result<Self> {
+        let (server, subject) = match dest.trim_start_matches("nats://").split_once('/') {
+            Some((server, subject)) if !subject.is_empty() => (server, subject),
+            Some((server, _)) => (server, DEFAULT_SUBJECT),
+            None => (dest.trim_start_matches("nats://"), DEFAULT_SUBJECT),
+        };
+        let client = async_nats::connect(format!("nats://{server}"))
+            .await
+            .with_context(|| format!("failed to connect to nats server {server}"))?;
+        Ok(Sink::Nats {
+            client,
+            subject: subject.to_owned(),
+        })
+    }
+
+    #[cfg(not(feature = "nats"))]
+    async fn nats(dest: &str) -> Result<Self> {
+        bail!("change stream {dest} needs dora to be built with the `nats` feature")
+    }
+
+    /// write `changes`, returning once the sink has all of them
+    async fn write(&mut self, changes: &[Value]) -> Result<()> {
+        match self {
+            Sink::File { path, file } => {
+                let mut buf = Vec::new();
+                for change in changes {
+                    serde_json::to_writer(&mut buf, change)?;
+                    buf.push(b'\n');
+                }
+                file.write_all(&buf).await?;
+                file.sync_data()
+                    .await
+                    .with_context(|| format!("failed to sync {}", path.display()))?;
+            }
+            #[cfg(feature = "nats")]
+            Sink::Nats { client, subject } => {
+                for change in changes {
+                    client
+                        .publish(subject.clone(), serde_json::to_vec(change)?.into())
+                        .await?;
+                }
+                client.flush().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// ship changes to `sink` as they're recorded, runs for the life of the server
+pub(crate) async fn run(ip_mgr: Arc<IpManager<SqliteDb>>, mut sink: Sink) {
+    let mut seq = loop {
+        match ip_mgr.change_cursor().await {
+            Ok(cursor) => break cursor,
+            Err(err) => error!(?err, "failed to read the change stream position, retrying"),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    };
+    info!(?seq, "starting change stream");
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        // keep going until caught up
+        loop {
+            match ship(&ip_mgr, &mut sink, &mut seq).await {
+                Ok(shipped) if shipped == BATCH => continue,
+                Ok(_) => break,
+                Err(err) => {
+                    error!(?err, "failed to ship lease changes, retrying");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// ship the next batch after `seq`, moving it to the last change shipped. `seq` is
+/// `None` before anything has been shipped
+async fn ship(
+    ip_mgr: &IpManager<SqliteDb>,
+    sink: &mut Sink,
+    seq: &mut Option<u64>,
+) -> Result<usize> {
+    let changes = ip_mgr.changes_after(seq.unwrap_or(0), BATCH).await?;
+    let (Some(first), Some(last)) = (changes.first(), changes.last()) else {
+        return Ok(0);
+    };
+    // only pruning deletes history, anything skipped was pruned before it was shipped
+    if let Some(shipped) = *seq {
+        let missed = first.seq - shipped - 1;
+        if missed > 0 {
+            warn!(
+                missed,
+                after = shipped,
+                "lease history was pruned before the change stream shipped it"
+            );
+            CHANGE_STREAM_MISSED_COUNT.inc_by(missed);
+        }
+    }
+    let records = changes.iter().map(record).collect::<Vec<_>>();
+    sink.write(&records).await?;
+    ip_mgr.set_change_cursor(last.seq).await?;
+    *seq = Some(last.seq);
+    CHANGE_STREAM_SHIPPED_COUNT.inc_by(changes.len() as u64);
+    Ok(changes.len())
+}
+
+/// the JSON shipped for `change`
+fn record(change: &Change) -> Value {
+    let op = change.op();
+    json!({
+        "seq": change.seq,
+        "op": match op {
+            ChangeOp::Insert => "insert",
+            ChangeOp::Update => "update",
+            ChangeOp::Delete => "delete",
+        },
+        "ip": change.after.ip,
+        "at": rfc3339(change.after.at),
+        "before": change
+            .before
+            .as_ref()
+            .filter(|_| op != ChangeOp::Insert)
+            .map(entry),
+        "after": (op != ChangeOp::Delete).then(|| entry(&change.after)),
+    })
+}
+
+fn entry(entry: &HistoryEntry) -> Value {
+    json!({
+        "client_id": entry.id.as_deref().map(|id| Hex(id).to_string()),
+        "state": match entry.state {
+            Some(IpState::Reserve) => "reserved",
+            Some(IpState::Lease) => "leased",
+            Some(IpState::Probate) => "probated",
+            None => "removed",
+        },
+        "expires_at": entry.expires_at.map(rfc3339),
+    })
+}
+
+fn rfc3339(time: std::time::SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, time::SystemTime};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ship() -> Result<()> {
+        let mgr = IpManager::without_ping(SqliteDb::new("sqlite::memory:").await?);
+        let ip = IpAddr::from([192, 168, 0, 150]);
+        let network = IpAddr::from([192, 168, 0, 0]);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let net = config::v4::Network::default();
+        mgr.try_ip(
+            ip,
+            network,
+            &[1, 0xab],
+            expires_at,
+            &net,
+            Some(IpState::Reserve),
+        )
+        .await?;
+        mgr.try_lease(ip, &[1, 0xab], expires_at, &net).await?;
+
+        let dir = std::env::temp_dir().join(format!("dora-change-stream-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("changes.jsonl");
+        let mut sink = Sink::open(path.to_str().unwrap()).await?;
+        let mut seq = None;
+        assert_eq!(ship(&mgr, &mut sink, &mut seq).await?, 2);
+        assert_eq!(ship(&mgr, &mut sink, &mut seq).await?, 0);
+        assert_eq!(mgr.change_cursor().await?, seq);
+
+        let lines = std::fs::read_to_string(&path)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Value>, _>>()?;
+        std::fs::remove_dir_all(&dir)?;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["op"], "insert");
+        assert_eq!(lines[0]["before"], Value::Null);
+        assert_eq!(lines[0]["after"]["state"], "reserved");
+        assert_eq!(lines[1]["op"], "update");
+        assert_eq!(lines[1]["ip"], "192.168.0.150");
+        assert_eq!(lines[1]["before"]["state"], "reserved");
+        assert_eq!(lines[1]["after"]["state"], "leased");
+        assert_eq!(lines[1]["after"]["client_id"], "01:ab");
+        Ok(())
+    }
+}
@@ -1,40 +1,66 @@
 #![allow(clippy::cognitive_complexity)]
-use std::sync::Arc;
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
-use config::DhcpConfig;
+use config::{wire::host_export::HostExport, DhcpConfig, LiveConfig};
 use dora_core::{
+    chrono::{DateTime, Utc},
     config::{
         cli::{self, Parser},
-        trace,
+        runtime, trace,
     },
     dhcproto::{v4, v6},
-    tokio::{self, runtime::Builder, signal, task::JoinHandle},
+    metrics::{self, PersistentStats},
+    server::{activation, ShutdownSummary},
+    tokio::{
+        self,
+        runtime::{Builder, Handle, Runtime},
+        signal::{
+            self,
+            unix::{signal as unix_signal, SignalKind},
+        },
+        sync::watch,
+        task::JoinHandle,
+    },
     tracing::*,
     Register, Server,
 };
-use external_api::{ExternalApi, Health};
-use ip_manager::{sqlite::SqliteDb, IpManager};
+use external_api::{
+    AllocatorStats, ClientProtection, ExternalApi, Health, PluginChains, ThrottleConfig,
+};
+use ip_manager::{backup::Backups, clock, hosts, sqlite::SqliteDb, IpManager};
 use leases::Leases;
 use message_type::MsgType;
+use shadow::Shadow;
 use static_addr::StaticAddr;
 
 #[cfg(not(target_env = "musl"))]
 use jemallocator::Jemalloc;
 
+mod change_stream;
+mod preflight;
+
+/// how often lease history past the retention period is pruned
+const PRUNE_HISTORY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// longest wait between wall clock checks for a staged config's `effective_at`
+const SWITCH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// how often the wall clock is checked for jumps when no expiries are being checked
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
 #[cfg(not(target_env = "musl"))]
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
 fn main() -> Result<()> {
+    // before any thread is started, it changes the environment
+    let inherited = activation::init();
     // parses from cli or environment var
     let config = cli::Config::parse();
-    let trace_config = trace::Config::parse(&config.dora_log)?;
-    debug!(?config, ?trace_config);
-    if let Err(err) = dotenv::dotenv() {
-        debug!(?err, ".env file not loaded");
-    }
 
     let mut builder = Builder::new_multi_thread();
     // configure thread name & enable IO/time
@@ -47,31 +73,243 @@ fn main() -> Result<()> {
     // build the runtime
     let rt = builder.build()?;
 
+    // the OTLP exporter (if enabled) runs on the runtime, so tracing is set up inside it
+    let trace_config = {
+        let _guard = rt.enter();
+        trace::Config::parse(&config.dora_log)?
+    };
+    debug!(?config, ?trace_config);
+    if !inherited.is_empty() {
+        info!(?inherited, "sockets passed by systemd");
+    }
+    if let Err(err) = dotenv::dotenv() {
+        debug!(?err, ".env file not loaded");
+    }
+    // kept here, a runtime can't be dropped from async code
+    let v4_rt = runtime::dedicated(
+        &format!("{}-v4", config.thread_name),
+        config.v4_threads,
+        config.v4_cpus.clone(),
+    )?;
+    let v6_rt = runtime::dedicated(
+        &format!("{}-v6", config.thread_name),
+        config.v6_threads,
+        config.v6_cpus.clone(),
+    )?;
+    let api_rt = runtime::dedicated(
+        &format!("{}-api", config.thread_name),
+        config.external_api_threads,
+        config.external_api_cpus.clone(),
+    )?;
+    let handle = |dedicated: &Option<Runtime>| dedicated.as_ref().unwrap_or(&rt).handle().clone();
+    let handles = Handles {
+        v4: handle(&v4_rt),
+        v6: handle(&v6_rt),
+        api: handle(&api_rt),
+    };
+
     rt.block_on(async move {
-        match dora_core::tokio::spawn(async move { start(config).await }).await {
+        match dora_core::tokio::spawn(async move { start(config, handles).await }).await {
             Err(err) => error!(?err, "failed to start server"),
             Ok(Err(err)) => error!(?err, "exited with error"),
             Ok(_) => debug!("exiting..."),
         }
     });
+    trace_config.shutdown();
 
     Ok(())
 }
 
-async fn start(config: cli::Config) -> Result<()> {
+/// the runtimes the servers & external api are spawned on, the main runtime unless
+/// they were given their own
+#[derive(Debug, Clone)]
+struct Handles {
+    v4: Handle,
+    v6: Handle,
+    api: Handle,
+}
+
+async fn start(config: cli::Config, handles: Handles) -> Result<()> {
     let database_url = config.database_url.clone();
     info!(?database_url, "using database at path");
     let dora_id = config.dora_id.clone();
     info!(?dora_id, "using id");
+    if config.dry_run {
+        warn!("dry run, responses & DDNS updates will not be sent");
+    }
+    if config.api_only {
+        info!("api only, the database is read-only & DHCP servers will not be started");
+    }
     // setting DORA_ID for other plugins
     std::env::set_var("DORA_ID", &dora_id);
 
     debug!("parsing DHCP config");
     let dhcp_cfg = Arc::new(DhcpConfig::parse(&config.config_path)?);
+    // parsed now so a bad staged config fails at startup instead of at switch time
+    let staged = config
+        .next_config_path
+        .as_deref()
+        .map(|path| staged_config(path, &dhcp_cfg))
+        .transpose()?;
+    let shadow = config
+        .shadow_config_path
+        .as_deref()
+        .map(|path| {
+            info!(?path, "evaluating requests against shadow config");
+            DhcpConfig::parse(path)
+                .map(Arc::new)
+                .with_context(|| format!("failed to parse shadow config {}", path.display()))
+        })
+        .transpose()?;
     debug!("starting database");
-    let ip_mgr = Arc::new(IpManager::new(SqliteDb::new(database_url).await?)?);
+    let database_key = config.database_key()?;
+    if database_key.is_some() {
+        info!("database encryption enabled");
+    }
+    if let Some(threshold) = config.clock_jump() {
+        clock::watch(threshold);
+        tokio::spawn(check_clock());
+    }
+    let ip_mgr = if config.api_only {
+        // the api never pings
+        IpManager::without_ping(SqliteDb::read_only(&database_url, database_key.as_deref()).await?)
+    } else {
+        let db = SqliteDb::with_key(&database_url, database_key.as_deref()).await;
+        let report = preflight::run(&config, &dhcp_cfg, &db).await;
+        if report.warned() {
+            eprint!("{report}");
+        } else {
+            debug!("{report}");
+        }
+        if report.failed() {
+            bail!("preflight checks failed, see the report above");
+        }
+        let store = db?;
+        if config.dry_run {
+            info!("dry run, ping checks are off");
+            IpManager::without_ping(store)
+        } else if report.ping {
+            IpManager::new(store)?
+        } else {
+            info!("no ICMP socket & no network has ping_check, ping is off");
+            IpManager::without_ping(store)
+        }
+    };
+    let ip_mgr = Arc::new(ip_mgr);
+    // pruning & checkpoints write to the database, they're left to the server it's from
+    if let Some(retention) = config
+        .lease_history_retention()
+        .filter(|_| !config.api_only)
+    {
+        // history the change stream hasn't shipped yet isn't pruned
+        let keep_unshipped = config.change_stream.is_some();
+        tokio::spawn(prune_history(
+            Arc::clone(&ip_mgr),
+            retention,
+            keep_unshipped,
+        ));
+    }
+    if let Some(dest) = config.change_stream.as_deref().filter(|_| !config.api_only) {
+        let sink = change_stream::Sink::open(dest).await?;
+        info!(dest, "streaming lease changes");
+        tokio::spawn(change_stream::run(Arc::clone(&ip_mgr), sink));
+    }
+    let backups = config
+        .backup_dir
+        .as_ref()
+        .map(|dir| Backups::new(dir, config.backup_keep as usize));
+    if let (Some(backups), Some(interval)) = (
+        backups.as_ref().filter(|_| !config.api_only),
+        config.backup_interval(),
+    ) {
+        info!(dir = ?backups.dir(), ?interval, "backing up leases database");
+        tokio::spawn(backup(Arc::clone(&ip_mgr), backups.clone(), interval));
+    }
+    let stats = match config
+        .stats_checkpoint_interval()
+        .filter(|_| !config.api_only)
+    {
+        Some(interval) => {
+            let stats = Arc::new(PersistentStats::default());
+            stats.restore(ip_mgr.load_stats().await?);
+            tokio::spawn(checkpoint_stats(
+                Arc::clone(&ip_mgr),
+                Arc::clone(&stats),
+                interval,
+            ));
+            Some(stats)
+        }
+        None => None,
+    };
+    let admin_token = config.external_api_admin_token()?;
+    if let Some(addr) = config.snmp_addr {
+        let base = config
+            .snmp_base_oid
+            .parse()
+            .with_context(|| format!("invalid snmp base oid {}", config.snmp_base_oid))?;
+        let agent = snmp::Agent::bind(addr, config.snmp_community()?, base)
+            .with_context(|| format!("failed to bind snmp agent to {addr}"))?;
+        info!(?addr, "starting snmp agent");
+        handles.api.spawn(async move {
+            if let Err(err) = agent.serve().await {
+                error!(?err, "snmp agent stopped");
+            }
+        });
+    }
+    if config.api_only {
+        return api_only(&config, dhcp_cfg, ip_mgr, admin_token, backups, &handles).await;
+    }
+
+    let live = LiveConfig::new(dhcp_cfg);
+    if let Some(next) = staged {
+        tokio::spawn(switch_config(live.clone(), next));
+    }
+    run(
+        &config,
+        live,
+        Arc::clone(&ip_mgr),
+        admin_token,
+        backups,
+        shadow.as_ref(),
+        &handles,
+    )
+    .await?;
+    // counts since the last checkpoint would be lost otherwise
+    if let Some(stats) = &stats {
+        save_stats(&ip_mgr, stats).await;
+    }
+    // leaves nothing in the WAL for the next start, or a backup of the file, to depend on
+    if let Err(err) = ip_mgr.checkpoint().await {
+        error!(?err, "failed to checkpoint the leases database");
+    }
+    info!("shutdown complete");
+    Ok(())
+}
+
+/// start the servers, plugins & external api with the `live` config, until shutdown.
+/// The plugins & api read the config from `live` for each message or request, so a
+/// staged config takes over without restarting anything
+async fn run(
+    config: &cli::Config,
+    live: LiveConfig,
+    ip_mgr: Arc<IpManager<SqliteDb>>,
+    admin_token: Option<String>,
+    backups: Option<Backups>,
+    shadow: Option<&Arc<DhcpConfig>>,
+    handles: &Handles,
+) -> Result<()> {
+    // interfaces, v6 & host export are fixed at startup, a staged config can't change them
+    let dhcp_cfg = live.load();
+    let export = dhcp_cfg.v4().host_export().map(|export| {
+        info!(path = ?export.path, format = ?export.format, "exporting lease hostnames");
+        tokio::spawn(export_hosts(Arc::clone(&ip_mgr), export.clone()))
+    });
     // start external api for healthchecks
-    let api = ExternalApi::new(config.external_api, Arc::clone(&ip_mgr));
+    let api = ExternalApi::new(config.external_api, live.clone(), Arc::clone(&ip_mgr))
+        .with_admin_token(admin_token)
+        .with_backups(backups)
+        .with_throttle(throttle(config))
+        .with_allocator_stats(allocator_stats);
     // start v4 server
     debug!("starting v4 server");
     let mut v4: Server<v4::Message> =
@@ -80,11 +318,30 @@ async fn start(config: cli::Config) -> Result<()> {
 
     // perhaps with only one plugin chain we will just register deps here
     // in order? we could get rid of derive macros & topo sort
-    MsgType::new(Arc::clone(&dhcp_cfg))?.register(&mut v4);
-    StaticAddr::new(Arc::clone(&dhcp_cfg))?.register(&mut v4);
+    let mut msg_type = MsgType::new(live.clone())?.with_known_clients(Arc::clone(&ip_mgr) as _);
+    if let Some(cfg) = dhcp_cfg.v4().identity() {
+        msg_type = msg_type.with_identity(identity::from_config(cfg));
+    }
     // leases plugin
-
-    Leases::new(Arc::clone(&dhcp_cfg), Arc::clone(&ip_mgr)).register(&mut v4);
+    let leases = Leases::new(live.clone(), Arc::clone(&ip_mgr)).with_dry_run(config.dry_run);
+    let renew_cache = leases.renew_cache();
+    let api = api.with_client_protection(ClientProtection {
+        renew: renew_cache.clone(),
+        flood: msg_type.flood_limiters(),
+    });
+    msg_type.register(&mut v4);
+    StaticAddr::new(live.clone())?.register(&mut v4);
+    leases.register(&mut v4);
+    if let Some(shadow) = shadow {
+        let mut msg_type =
+            MsgType::new(Arc::clone(shadow))?.with_known_clients(Arc::clone(&ip_mgr) as _);
+        if let Some(cfg) = shadow.v4().identity() {
+            msg_type = msg_type.with_identity(identity::from_config(cfg));
+        }
+        v4.postresponse::<Shadow, _>(
+            Shadow::new(live.clone(), Arc::clone(shadow), msg_type).await?,
+        );
+    }
 
     let v6 = if dhcp_cfg.has_v6() {
         // start v6 server
@@ -92,11 +349,16 @@ async fn start(config: cli::Config) -> Result<()> {
         let mut v6: Server<v6::Message> =
             Server::new(config.clone(), dhcp_cfg.v6().interfaces().to_owned())?;
         info!("starting v6 plugins");
-        MsgType::new(Arc::clone(&dhcp_cfg))?.register(&mut v6);
+        MsgType::new(live.clone())?
+            .with_addr_registry(Arc::clone(&ip_mgr) as _)
+            .with_dry_run(config.dry_run)
+            .register(&mut v6);
         Some(v6)
     } else {
         None
     };
+    let v6_chain = v6.as_ref().map(|v6| v6.plugin_chain()).transpose()?;
+    let api = api.with_plugin_chains(PluginChains::new(&v4.plugin_chain()?, v6_chain.as_deref()));
 
     debug!("changing health to good");
     api.sender()
@@ -105,19 +367,185 @@ async fn start(config: cli::Config) -> Result<()> {
         .context("error occurred in changing health status to Good")?;
 
     // if dropped, will stop server
-    let api_guard = api.serve();
-    match v6 {
-        Some(v6) => {
-            tokio::try_join!(
-                flatten(tokio::spawn(v4.start(shutdown_signal()))),
-                flatten(tokio::spawn(v6.start(shutdown_signal()))),
-            )?;
+    let api_guard = {
+        let _rt = handles.api.enter();
+        api.serve()
+    };
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let stopper = tokio::spawn(async move {
+        if let Err(err) = shutdown_signal().await {
+            error!(?err, "failed to listen for shutdown signals");
         }
-        None => {
-            tokio::spawn(v4.start(shutdown_signal())).await??;
+        let _ = stop_tx.send(true);
+    });
+    let res = match v6 {
+        Some(v6) => tokio::try_join!(
+            flatten(handles.v4.spawn(v4.start(stopped(stop_rx.clone())))),
+            flatten(handles.v6.spawn(v6.start(stopped(stop_rx.clone())))),
+        )
+        .map(|(v4, v6)| v4 + v6),
+        None => flatten(handles.v4.spawn(v4.start(stopped(stop_rx.clone())))).await,
+    };
+    stopper.abort();
+    if let Some(export) = export {
+        export.abort();
+    }
+    api_guard.stop().await;
+    let summary = res?;
+    if *stop_rx.borrow() {
+        shutdown_summary(&summary, renew_cache.map_or(0, |cache| cache.len()));
+    }
+    Ok(())
+}
+
+/// resolves on ctrl-c or SIGTERM, which is what systemd, docker & kubernetes stop
+/// dora with
+async fn shutdown_signal() -> Result<()> {
+    let mut term = match unix_signal(SignalKind::terminate()) {
+        Ok(term) => term,
+        Err(err) => {
+            warn!(
+                ?err,
+                "can't listen for SIGTERM, only ctrl-c will stop dora cleanly"
+            );
+            signal::ctrl_c().await?;
+            return Ok(());
         }
     };
-    drop(api_guard);
+    tokio::select! {
+        res = signal::ctrl_c() => res?,
+        _ = term.recv() => info!("caught SIGTERM"),
+    }
+    Ok(())
+}
+
+/// log what was lost by stopping. The renew cache only saves database lookups for
+/// early renewals, its leases are already written, so nothing is flushed from it
+fn shutdown_summary(summary: &ShutdownSummary, renew_cache: usize) {
+    info!(
+        in_flight = summary.in_flight,
+        finished = summary.finished,
+        dropped = summary.cancelled,
+        waited = ?summary.elapsed,
+        renew_cache_dropped = renew_cache,
+        "shutdown summary"
+    );
+}
+
+/// serve the external api alone, read-only, until shutdown. Staged configs aren't
+/// switched to, they only change how DHCP is answered
+async fn api_only(
+    config: &cli::Config,
+    dhcp_cfg: Arc<DhcpConfig>,
+    ip_mgr: Arc<IpManager<SqliteDb>>,
+    admin_token: Option<String>,
+    backups: Option<Backups>,
+    handles: &Handles,
+) -> Result<()> {
+    let api = ExternalApi::new(config.external_api, dhcp_cfg, ip_mgr)
+        .with_admin_token(admin_token)
+        .with_backups(backups)
+        .with_throttle(throttle(config))
+        .with_allocator_stats(allocator_stats)
+        .with_read_only(true);
+    api.sender()
+        .send(Health::Good)
+        .await
+        .context("error occurred in changing health status to Good")?;
+    let api_guard = {
+        let _rt = handles.api.enter();
+        api.serve()
+    };
+    shutdown_signal().await?;
+    api_guard.stop().await;
+    Ok(())
+}
+
+/// read a config for `next_config_path`, it must have an `effective_at`. The servers
+/// keep running across the switch, so it must listen on the same interfaces as `running`
+fn staged_config(path: &Path, running: &DhcpConfig) -> Result<Arc<DhcpConfig>> {
+    let cfg = DhcpConfig::parse(path)
+        .with_context(|| format!("failed to parse staged config {}", path.display()))?;
+    let at = cfg
+        .effective_at()
+        .with_context(|| format!("staged config {} has no effective_at", path.display()))?;
+    if cfg.v4().interfaces() != running.v4().interfaces() {
+        bail!(
+            "staged config {} listens on other v4 interfaces, restart with it instead",
+            path.display()
+        );
+    }
+    let same_v6 = match (cfg.has_v6(), running.has_v6()) {
+        (true, true) => cfg.v6().interfaces() == running.v6().interfaces(),
+        (has, running) => has == running,
+    };
+    if !same_v6 {
+        bail!(
+            "staged config {} changes the v6 server or its interfaces, restart with it instead",
+            path.display()
+        );
+    }
+    info!(path = ?path, effective_at = %at, "staged next config");
+    Ok(Arc::new(cfg))
+}
+
+/// swap `next` into the running config at its `effective_at`, right away if that has
+/// already passed. Messages being handled finish with the old config
+async fn switch_config(live: LiveConfig, next: Arc<DhcpConfig>) {
+    let at = next.effective_at();
+    metrics::CONFIG_NEXT_SWITCH.set(at.map_or(0, |at| at.timestamp()));
+    wait_until(at).await;
+    info!(effective_at = ?at, "switching to staged config");
+    live.store(next);
+    metrics::CONFIG_SWITCH_COUNT.inc();
+    metrics::CONFIG_NEXT_SWITCH.set(0);
+}
+
+/// resolves once the wall clock reaches `at`, never if nothing is staged. The clock is
+/// checked again every `SWITCH_CHECK_INTERVAL` so a clock step doesn't delay the switch
+async fn wait_until(at: Option<DateTime<Utc>>) {
+    let Some(at) = at else {
+        return std::future::pending().await;
+    };
+    // negative durations fail to convert, the time has passed
+    while let Ok(left) = (at - Utc::now()).to_std() {
+        if left.is_zero() {
+            break;
+        }
+        tokio::time::sleep(left.min(SWITCH_CHECK_INTERVAL)).await;
+    }
+}
+
+/// limits for the external api routes that scan the leases table
+fn throttle(config: &cli::Config) -> ThrottleConfig {
+    ThrottleConfig {
+        concurrency: config.external_api_concurrency as usize,
+        cache_ttl: config.external_api_cache_ttl(),
+    }
+}
+
+/// jemalloc's counters for `/v1/debug/memory`
+#[cfg(not(target_env = "musl"))]
+fn allocator_stats() -> Option<AllocatorStats> {
+    use jemalloc_ctl::{epoch, stats};
+    // the counters are cached, advancing the epoch refreshes them
+    epoch::advance().ok()?;
+    Some(AllocatorStats::new(
+        stats::allocated::read().ok()? as u64,
+        stats::active::read().ok()? as u64,
+        stats::resident::read().ok()? as u64,
+    ))
+}
+
+/// musl builds use the system allocator, which has no stats to report
+#[cfg(target_env = "musl")]
+fn allocator_stats() -> Option<AllocatorStats> {
+    None
+}
+
+/// resolves once the servers are told to stop
+async fn stopped(mut rx: watch::Receiver<bool>) -> Result<()> {
+    rx.wait_for(|stop| *stop).await?;
     Ok(())
 }
 
@@ -129,6 +557,90 @@ async fn flatten<T>(handle: JoinHandle<Result<T, anyhow::Error>>) -> Result<T, a
     }
 }
 
-async fn shutdown_signal() -> Result<()> {
-    signal::ctrl_c().await.map_err(|err| anyhow!(err))
+/// delete lease history older than `retention`, runs for the life of the server
+async fn prune_history(
+    ip_mgr: Arc<IpManager<SqliteDb>>,
+    retention: Duration,
+    keep_unshipped: bool,
+) {
+    let mut interval = tokio::time::interval(PRUNE_HISTORY_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = ip_mgr
+            .prune_history(SystemTime::now() - retention, keep_unshipped)
+            .await
+        {
+            error!(?err, "failed to prune lease history");
+        }
+    }
+}
+
+/// back up the leases database every `interval`, runs for the life of the server
+async fn backup(ip_mgr: Arc<IpManager<SqliteDb>>, backups: Backups, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    // the first tick is immediate, a restart loop shouldn't churn through backups
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(err) = backups.run(&ip_mgr).await {
+            error!(?err, "failed to back up the leases database");
+        }
+    }
+}
+
+/// read the expiry clock so jumps are logged when they happen rather than on the next
+/// allocation, runs for the life of the server
+async fn check_clock() {
+    let mut interval = tokio::time::interval(CLOCK_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        clock::now();
+    }
+}
+
+/// save the persistent counters every `interval`, runs for the life of the server
+async fn checkpoint_stats(
+    ip_mgr: Arc<IpManager<SqliteDb>>,
+    stats: Arc<PersistentStats>,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        save_stats(&ip_mgr, &stats).await;
+    }
+}
+
+async fn save_stats(ip_mgr: &IpManager<SqliteDb>, stats: &PersistentStats) {
+    if let Err(err) = ip_mgr.save_stats(&stats.sync()).await {
+        error!(?err, "failed to checkpoint stats");
+    }
+}
+
+/// write active lease hostnames to the configured file, it's only replaced when the
+/// contents change
+async fn export_hosts(ip_mgr: Arc<IpManager<SqliteDb>>, cfg: HostExport) {
+    let mut interval = tokio::time::interval(cfg.interval());
+    let mut last = None;
+    loop {
+        interval.tick().await;
+        let hosts = match ip_mgr.active_hostnames().await {
+            Ok(hosts) => hosts,
+            Err(err) => {
+                error!(?err, "failed to get lease hostnames");
+                continue;
+            }
+        };
+        let contents = hosts::render(&cfg, &hosts);
+        if last.as_ref() == Some(&contents) {
+            continue;
+        }
+        match hosts::write(&cfg.path, &contents).await {
+            Ok(()) => {
+                debug!(path = ?cfg.path, count = hosts.len(), "exported lease hostnames");
+                last = Some(contents);
+            }
+            Err(err) => error!(?err, path = ?cfg.path, "failed to write host export"),
+        }
+    }
 }
@@ -0,0 +1,200 @@
+//! # Preflight
+//!
+//! Checks run before any DHCP socket is bound, so everything dora is missing is reported
+//! together instead of startup stopping at the first problem:
+//!
+//! - the leases database opened & can be written
+//! - an ICMP socket can be opened for ping checks. If it can't & no network has
+//!   `ping_check`, ping is disabled rather than failing
+//! - `server_id`s are on the subnet of a bound interface, a warning only as they're often
+//!   virtual IPs
+//! - the DHCP ports can be bound, unless systemd passed a socket for them
+use std::{
+    fmt, io,
+    net::{SocketAddr, UdpSocket},
+};
+
+use config::DhcpConfig;
+use dora_core::{config::cli, server::activation};
+use icmp_ping::{IcmpEcho, Icmpv4};
+use ip_manager::sqlite::SqliteDb;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug)]
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+}
+
+/// the result of every check
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    checks: Vec<Check>,
+    /// an ICMP socket could be opened, ping checks can run
+    pub(crate) ping: bool,
+}
+
+impl Report {
+    fn push(&mut self, name: &'static str, status: Status, detail: impl Into<String>) {
+        self.checks.push(Check {
+            name,
+            status,
+            detail: detail.into(),
+        });
+    }
+
+    /// true if dora can't start
+    pub(crate) fn failed(&self) -> bool {
+        self.checks.iter().any(|check| check.status == Status::Fail)
+    }
+
+    /// true if anything needs attention
+    pub(crate) fn warned(&self) -> bool {
+        self.checks.iter().any(|check| check.status != Status::Ok)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "preflight checks:")?;
+        for check in &self.checks {
+            let status = match check.status {
+                Status::Ok => "ok",
+                Status::Warn => "warn",
+                Status::Fail => "FAIL",
+            };
+            writeln!(f, "  [{status:>4}] {:<9} {}", check.name, check.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run every check. `db` is the result of opening the leases database
+pub(crate) async fn run<E: fmt::Display>(
+    config: &cli::Config,
+    dhcp_cfg: &DhcpConfig,
+    db: &Result<SqliteDb, E>,
+) -> Report {
+    let mut report = Report::default();
+    database(&mut report, &config.database_url, db).await;
+    icmp(&mut report, dhcp_cfg);
+    server_ids(&mut report, dhcp_cfg);
+    port(&mut report, "dhcpv4", config.v4_addr);
+    if dhcp_cfg.has_v6() {
+        port(&mut report, "dhcpv6", config.v6_addr);
+    }
+    report
+}
+
+async fn database<E: fmt::Display>(report: &mut Report, url: &str, db: &Result<SqliteDb, E>) {
+    match db {
+        Err(err) => report.push("database", Status::Fail, format!("can't open {url}: {err}")),
+        Ok(db) => match db.check_writable().await {
+            Ok(()) => report.push("database", Status::Ok, format!("{url} is writable")),
+            Err(err) => report.push(
+                "database",
+                Status::Fail,
+                format!(
+                    "{url} can't be written ({err}), the file & its directory must be \
+                     writable by dora's user"
+                ),
+            ),
+        },
+    }
+}
+
+fn icmp(report: &mut Report, dhcp_cfg: &DhcpConfig) {
+    let needed = dhcp_cfg.v4().networks().any(|(_, net)| net.ping_check());
+    match IcmpEcho::<Icmpv4>::new() {
+        Ok(_) => {
+            report.ping = true;
+            report.push("icmp", Status::Ok, "ICMP socket opened for ping checks");
+        }
+        Err(err) if needed => report.push(
+            "icmp",
+            Status::Fail,
+            format!(
+                "can't open an ICMP socket ({err}) & a network has ping_check. Add dora's \
+                 group to net.ipv4.ping_group_range, give dora CAP_NET_RAW or turn off \
+                 ping_check"
+            ),
+        ),
+        Err(err) => report.push(
+            "icmp",
+            Status::Ok,
+            format!("can't open an ICMP socket ({err}), no network has ping_check so ping is off"),
+        ),
+    }
+}
+
+fn server_ids(report: &mut Report, dhcp_cfg: &DhcpConfig) {
+    let unreachable = dhcp_cfg.v4().unreachable_server_ids();
+    if unreachable.is_empty() {
+        report.push(
+            "server_id",
+            Status::Ok,
+            "configured server ids are on bound interface subnets",
+        );
+    }
+    for (owner, id) in unreachable {
+        report.push(
+            "server_id",
+            Status::Warn,
+            format!("{owner} server_id {id} is not on the subnet of any bound interface"),
+        );
+    }
+}
+
+fn port(report: &mut Report, name: &'static str, addr: SocketAddr) {
+    if activation::is_inherited(addr) {
+        report.push(name, Status::Ok, format!("{addr} was passed by systemd"));
+        return;
+    }
+    // only a test, the server binds its own socket once the checks pass
+    match UdpSocket::bind(addr) {
+        Ok(_) => report.push(name, Status::Ok, format!("{addr} is free")),
+        Err(err) => report.push(name, Status::Fail, bind_error(addr, &err)),
+    }
+}
+
+fn bind_error(addr: SocketAddr, err: &io::Error) -> String {
+    match err.kind() {
+        io::ErrorKind::AddrInUse => {
+            format!("{addr} is in use, is another DHCP server or dora already running?")
+        }
+        io::ErrorKind::PermissionDenied => {
+            format!("can't bind {addr}, run dora as root or give it CAP_NET_BIND_SERVICE")
+        }
+        _ => format!("can't bind {addr}: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut report = Report::default();
+        report.push("database", Status::Ok, "em.db is writable");
+        assert!(!report.warned());
+        report.push("server_id", Status::Warn, "class voip server_id 10.0.0.1");
+        assert!(report.warned() && !report.failed());
+
+        // a port that's already taken
+        let taken = UdpSocket::bind("127.0.0.1:0").unwrap();
+        port(&mut report, "dhcpv4", taken.local_addr().unwrap());
+        assert!(report.failed());
+        let out = report.to_string();
+        assert!(out.contains("[FAIL] dhcpv4"), "{out}");
+        assert!(out.contains("is in use"), "{out}");
+        assert!(out.contains("[  ok] database  em.db is writable"), "{out}");
+    }
+}
@@ -0,0 +1,432 @@
+//! ISC dhcpd.conf. Subnets (including those in shared networks & groups), ranges, pools,
+//! hosts with a `hardware ethernet` & `fixed-address` and named options are imported.
+//! ISC classes use a different expression language & aren't translated.
+use std::net::Ipv4Addr;
+
+use anyhow::{bail, Result};
+use ipnet::Ipv4Net;
+use serde_json::{json, Map};
+
+use super::{dora_config, named_option, option_value, Import, Network, Scope};
+
+/// dhcpd's `default-lease-time` when it isn't set
+const DEFAULT_LEASE_TIME: u32 = 43200;
+
+pub(super) fn import(input: &str) -> Result<Import> {
+    let stmts = parse(&mut tokenize(input)?.into_iter().peekable(), false)?;
+    let mut isc = Isc::default();
+    isc.block(&stmts, &Scope::new(DEFAULT_LEASE_TIME));
+
+    // hosts can be declared anywhere, they go in the network that contains them
+    for (stmt, (ip, mac, scope)) in std::mem::take(&mut isc.hosts) {
+        match isc.networks.iter_mut().find(|net| net.subnet.contains(&ip)) {
+            Some(net) => net.reservation(ip, &mac, &scope),
+            None => isc.unsupported_because(&stmt, format!("{ip} is not in any subnet")),
+        }
+    }
+    Ok(Import {
+        config: dora_config(Map::new(), isc.networks),
+        unsupported: isc.unsupported,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// a bare word or quoted string
+    Word(String),
+    Open,
+    Close,
+    Semi,
+}
+
+/// a statement ending in `;` or a block, commas between values are dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stmt {
+    words: Vec<String>,
+    block: Option<Vec<Stmt>>,
+    line: usize,
+}
+
+impl Stmt {
+    fn word(&self, i: usize) -> &str {
+        self.words.get(i).map(String::as_str).unwrap_or_default()
+    }
+
+    fn children(&self) -> &[Stmt] {
+        self.block.as_deref().unwrap_or_default()
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() || c == ',' => {}
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+            }
+            '{' => tokens.push((Token::Open, line)),
+            '}' => tokens.push((Token::Close, line)),
+            ';' => tokens.push((Token::Semi, line)),
+            '"' => {
+                let start = line;
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.extend(chars.next()),
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            word.push(c);
+                        }
+                        None => bail!("unterminated string starting on line {start}"),
+                    }
+                }
+                tokens.push((Token::Word(word), start));
+            }
+            c => {
+                let mut word = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '{' | '}' | ';' | ',' | '"' | '#') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push((Token::Word(word), line));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse<I>(tokens: &mut std::iter::Peekable<I>, nested: bool) -> Result<Vec<Stmt>>
+where
+    I: Iterator<Item = (Token, usize)>,
+{
+    let mut stmts = Vec::new();
+    let mut words = Vec::new();
+    let mut line = 0;
+    while let Some((token, at)) = tokens.next() {
+        if words.is_empty() {
+            line = at;
+        }
+        match token {
+            Token::Word(word) => words.push(word),
+            Token::Semi => {
+                if !words.is_empty() {
+                    stmts.push(Stmt {
+                        words: std::mem::take(&mut words),
+                        block: None,
+                        line,
+                    });
+                }
+            }
+            Token::Open => {
+                let block = parse(tokens, true)?;
+                stmts.push(Stmt {
+                    words: std::mem::take(&mut words),
+                    block: Some(block),
+                    line,
+                });
+                // `;` after a block is allowed
+                if let Some((Token::Semi, _)) = tokens.peek() {
+                    tokens.next();
+                }
+            }
+            Token::Close if nested => {
+                if !words.is_empty() {
+                    bail!("missing `;` on line {line}");
+                }
+                return Ok(stmts);
+            }
+            Token::Close => bail!("unexpected `}}` on line {at}"),
+        }
+    }
+    if nested {
+        bail!("missing `}}` for block on line {line}");
+    }
+    if !words.is_empty() {
+        bail!("missing `;` on line {line}");
+    }
+    Ok(stmts)
+}
+
+#[derive(Debug, Default)]
+struct Isc {
+    networks: Vec<Network>,
+    /// hosts & the statement they came from
+    hosts: Vec<(Stmt, (Ipv4Addr, String, Scope))>,
+    unsupported: Vec<String>,
+}
+
+impl Isc {
+    fn unsupported(&mut self, stmt: &Stmt) {
+        self.unsupported.push(format!(
+            "line {}: `{}` is not supported",
+            stmt.line,
+            stmt.words.join(" ")
+        ));
+    }
+
+    fn unsupported_because(&mut self, stmt: &Stmt, reason: impl AsRef<str>) {
+        self.unsupported.push(format!(
+            "line {}: `{}` left out: {}",
+            stmt.line,
+            stmt.words.join(" "),
+            reason.as_ref()
+        ));
+    }
+
+    /// apply a parameter statement to `scope`, returns false if `stmt` isn't one
+    fn param(&mut self, stmt: &Stmt, scope: &mut Scope) -> bool {
+        if stmt.block.is_some() {
+            return false;
+        }
+        let num = |i| stmt.word(i).parse::<u32>().ok();
+        match (stmt.word(0), stmt.words.len()) {
+            ("option", 2..) if stmt.word(1) == "space" || stmt.word(2) == "code" => {
+                self.unsupported_because(stmt, "option definitions aren't imported");
+            }
+            ("option", 3..) => {
+                let name = stmt.word(1);
+                match named_option(name) {
+                    Some((code, kind)) => match option_value(kind, &stmt.words[2..]) {
+                        Ok(value) => {
+                            scope.options.insert(code.to_string(), value);
+                        }
+                        Err(err) => self.unsupported_because(stmt, err),
+                    },
+                    None => self.unsupported_because(stmt, format!("unknown option `{name}`")),
+                }
+            }
+            ("default-lease-time", 2) if num(1).is_some() => {
+                scope.lease.default = num(1).unwrap_or_default()
+            }
+            ("min-lease-time", 2) if num(1).is_some() => scope.lease.min = num(1),
+            ("max-lease-time", 2) if num(1).is_some() => scope.lease.max = num(1),
+            ("authoritative", 1) => scope.authoritative = Some(true),
+            ("not", 2) if stmt.word(1) == "authoritative" => scope.authoritative = Some(false),
+            ("next-server", 2) if stmt.word(1).parse::<Ipv4Addr>().is_ok() => {
+                scope.boot.insert("next_server".into(), json!(stmt.word(1)));
+            }
+            ("filename", 2) => {
+                scope.boot.insert("file_name".into(), json!(stmt.word(1)));
+            }
+            ("server-name", 2) => {
+                scope.boot.insert("server_name".into(), json!(stmt.word(1)));
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// the top level, a `shared-network` or a `group`
+    fn block(&mut self, stmts: &[Stmt], parent: &Scope) {
+        let mut scope = parent.clone();
+        // parameters apply to the whole block, wherever they appear in it
+        let decls = stmts
+            .iter()
+            .filter(|stmt| !self.param(stmt, &mut scope))
+            .collect::<Vec<_>>();
+        for stmt in decls {
+            match (stmt.word(0), &stmt.block) {
+                ("subnet", Some(_)) => self.subnet(stmt, &scope),
+                ("shared-network" | "group", Some(children)) => self.block(children, &scope),
+                ("host", Some(_)) => self.host(stmt, &scope),
+                ("class" | "subclass", _) => self.unsupported_because(
+                    stmt,
+                    "ISC classes aren't imported, rewrite them as a client class `assert`",
+                ),
+                _ => self.unsupported(stmt),
+            }
+        }
+    }
+
+    fn subnet(&mut self, stmt: &Stmt, parent: &Scope) {
+        let subnet = match (
+            stmt.word(2),
+            stmt.word(1).parse::<Ipv4Addr>(),
+            stmt.word(3).parse::<Ipv4Addr>(),
+        ) {
+            ("netmask", Ok(ip), Ok(mask)) => Ipv4Net::with_netmask(ip, mask).ok(),
+            _ => None,
+        };
+        let Some(subnet) = subnet else {
+            self.unsupported_because(stmt, "expected `subnet <ip> netmask <mask>`");
+            return;
+        };
+        let mut scope = parent.clone();
+        let decls = stmt
+            .children()
+            .iter()
+            .filter(|stmt| !self.param(stmt, &mut scope))
+            .collect::<Vec<_>>();
+        let mut net = Network::new(subnet.trunc(), scope.clone());
+        for child in decls {
+            match (child.word(0), &child.block) {
+                ("range", None) => self.range(child, &scope, None, &mut net),
+                ("pool", Some(_)) => self.pool(child, &scope, &mut net),
+                ("host", Some(_)) => self.host(child, &scope),
+                _ => self.unsupported(child),
+            }
+        }
+        self.networks.push(net);
+    }
+
+    /// `range [dynamic-bootp] <start> [<end>]`
+    fn range(&mut self, stmt: &Stmt, scope: &Scope, class: Option<&str>, net: &mut Network) {
+        let words = match stmt.word(1) {
+            "dynamic-bootp" => &stmt.words[2..],
+            _ => &stmt.words[1..],
+        };
+        let ips = words
+            .iter()
+            .map(|ip| ip.parse::<Ipv4Addr>())
+            .collect::<Result<Vec<_>, _>>();
+        match ips.as_deref() {
+            Ok([ip]) => net.range(*ip, *ip, scope, class),
+            Ok([start, end]) => net.range(*start, *end, scope, class),
+            _ => self.unsupported_because(stmt, "expected `range <start> <end>`"),
+        }
+    }
+
+    fn pool(&mut self, stmt: &Stmt, parent: &Scope, net: &mut Network) {
+        let mut scope = parent.clone();
+        let decls = stmt
+            .children()
+            .iter()
+            .filter(|stmt| !self.param(stmt, &mut scope))
+            .collect::<Vec<_>>();
+        // a pool with restrictions would be opened to everyone, so it's left out entirely
+        if let Some(rule) = decls
+            .iter()
+            .find(|child| matches!(child.word(0), "allow" | "deny"))
+        {
+            self.unsupported_because(
+                rule,
+                "pool access rules aren't imported, restrict the range with a client class",
+            );
+            return;
+        }
+        for child in decls {
+            match child.word(0) {
+                "range" if child.block.is_none() => self.range(child, &scope, None, net),
+                _ => self.unsupported(child),
+            }
+        }
+    }
+
+    /// `host <name> { hardware ethernet <mac>; fixed-address <ip>; ... }`
+    fn host(&mut self, stmt: &Stmt, parent: &Scope) {
+        let mut scope = parent.clone();
+        // the network's boot fields already apply
+        scope.boot.clear();
+        let mut mac = None;
+        let mut ip = None;
+        for child in stmt.children() {
+            if self.param(child, &mut scope) {
+                continue;
+            }
+            match (child.word(0), child.words.len()) {
+                ("hardware", 3) if child.word(1) == "ethernet" => mac = super::mac(child.word(2)),
+                ("fixed-address", 2) => ip = child.word(1).parse::<Ipv4Addr>().ok(),
+                _ => self.unsupported(child),
+            }
+        }
+        match (ip, mac) {
+            (Some(ip), Some(mac)) => self.hosts.push((stmt.clone(), (ip, mac, scope))),
+            _ => self.unsupported_because(
+                stmt,
+                "hosts need a `hardware ethernet` & a single IPv4 `fixed-address`",
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static DHCPD: &str = r#"
+# global settings
+ddns-update-style none;
+default-lease-time 600;
+max-lease-time 7200;
+authoritative;
+option domain-name "example.org";
+option domain-name-servers ns1.example.org, ns2.example.org;
+
+class "phones" {
+    match if substring (option vendor-class-identifier, 0, 5) = "phone";
+}
+
+subnet 10.5.5.0 netmask 255.255.255.224 {
+    range 10.5.5.26 10.5.5.30;
+    option routers 10.5.5.1;
+    option broadcast-address 10.5.5.31;
+    pool {
+        allow members of "phones";
+        range 10.5.5.10 10.5.5.20;
+    }
+}
+
+group {
+    next-server 10.5.5.2;
+    host fantasia {
+        hardware ethernet 08:00:07:26:c0:a5;
+        fixed-address 10.5.5.25;
+        filename "fantasia.cfg";
+    }
+}
+"#;
+
+    #[test]
+    fn test_isc() {
+        let import = import(DHCPD).unwrap();
+        let net = &import.config["networks"]["10.5.5.0/27"];
+        assert_eq!(net["authoritative"], json!(true));
+        let ranges = net["ranges"].as_array().unwrap();
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0]["start"], json!("10.5.5.26"));
+        assert_eq!(ranges[0]["config"]["lease_time"]["default"], json!(600));
+        assert_eq!(ranges[0]["config"]["lease_time"]["max"], json!(7200));
+        let opts = &ranges[0]["options"]["values"];
+        assert_eq!(opts["15"]["value"], json!("example.org"));
+        assert_eq!(opts["3"]["value"], json!(["10.5.5.1"]));
+
+        let res = &net["reservations"][0];
+        assert_eq!(res["ip"], json!("10.5.5.25"));
+        assert_eq!(res["match"]["chaddr"], json!("08:00:07:26:c0:a5"));
+        assert_eq!(res["file_name"], json!("fantasia.cfg"));
+        assert_eq!(res["next_server"], json!("10.5.5.2"));
+
+        // name servers given by name, ddns, the class & the restricted pool
+        for line in ["line 8:", "line 3:", "line 10:", "line 19:"] {
+            assert!(
+                import.unsupported.iter().any(|u| u.starts_with(line)),
+                "{line} not in {:?}",
+                import.unsupported
+            );
+        }
+        super::super::validate(&import.config).unwrap();
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(import("subnet 10.0.0.0 netmask 255.0.0.0 {").is_err());
+        assert!(import("option routers 10.0.0.1").is_err());
+        assert!(import("}").is_err());
+        assert!(import("option domain-name \"x;").is_err());
+    }
+}
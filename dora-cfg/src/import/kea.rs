@@ -0,0 +1,545 @@
+//! Kea `Dhcp4` JSON. Subnets (including those in shared networks), pools, MAC
+//! reservations, `option-data` & client classes are imported. Kea's class `test`
+//! expressions use the same grammar as dora's `assert`, so they're copied as is.
+use std::net::Ipv4Addr;
+
+use anyhow::{Context, Result};
+use ipnet::Ipv4Net;
+use serde_json::{json, Map, Value};
+
+use super::{code_kind, dora_config, mac, named_option, option_value, Import, Network, Scope};
+
+/// Kea's `valid-lifetime` when it isn't set
+const DEFAULT_LIFETIME: u32 = 7200;
+
+/// keys that set inherited settings, handled by [`Kea::scope`]
+static SCOPE_KEYS: &[&str] = &[
+    "option-data",
+    "valid-lifetime",
+    "min-valid-lifetime",
+    "max-valid-lifetime",
+    "next-server",
+    "server-hostname",
+    "boot-file-name",
+    "authoritative",
+];
+
+/// keys that never affect what clients are given
+static IGNORED_KEYS: &[&str] = &["id", "comment", "user-context"];
+
+pub(super) fn import(input: &str) -> Result<Import> {
+    let root: Value =
+        serde_json::from_str(&strip_comments(input)).context("failed to parse Kea config")?;
+    let dhcp4 = root
+        .get("Dhcp4")
+        .and_then(Value::as_object)
+        .context("no `Dhcp4` section, only DHCPv4 configs can be imported")?;
+
+    let mut kea = Kea::default();
+    let mut scope = Scope::new(DEFAULT_LIFETIME);
+    kea.scope("Dhcp4", dhcp4, &mut scope);
+    let mut top = Map::new();
+    let mut reservations = Vec::new();
+    for (key, value) in dhcp4 {
+        let path = format!("Dhcp4.{key}");
+        match key.as_str() {
+            key if SCOPE_KEYS.contains(&key) || IGNORED_KEYS.contains(&key) => {}
+            "interfaces-config" => {
+                if let Some(interfaces) = kea.interfaces(&path, value) {
+                    top.insert("interfaces".into(), json!(interfaces));
+                }
+            }
+            // clients are identified by chaddr only
+            "match-client-id" => {
+                top.insert("chaddr_only".into(), json!(value == &Value::Bool(false)));
+            }
+            "subnet4" => kea.subnets(&path, value, &scope),
+            "shared-networks" => {
+                for (i, shared) in objects(value).enumerate() {
+                    let path = format!("{path}[{i}]");
+                    let mut scope = scope.clone();
+                    kea.scope(&path, shared, &mut scope);
+                    for (key, value) in shared {
+                        match key.as_str() {
+                            key if SCOPE_KEYS.contains(&key) || IGNORED_KEYS.contains(&key) => {}
+                            "name" => {}
+                            "subnet4" => kea.subnets(&format!("{path}.subnet4"), value, &scope),
+                            _ => kea.unsupported(format!("{path}.{key}")),
+                        }
+                    }
+                }
+            }
+            "client-classes" => {
+                let classes = kea.classes(&path, value);
+                if !classes.is_empty() {
+                    top.insert("client_classes".into(), json!({ "v4": classes }));
+                }
+            }
+            "reservations" => {
+                for (i, res) in objects(value).enumerate() {
+                    let path = format!("{path}[{i}]");
+                    if let Some(res) = kea.reservation(&path, res, &scope) {
+                        reservations.push((path, res));
+                    }
+                }
+            }
+            _ => kea.unsupported(path),
+        }
+    }
+
+    // global reservations go in the network that contains them
+    for (path, (ip, mac, scope)) in reservations {
+        match kea.networks.iter_mut().find(|net| net.subnet.contains(&ip)) {
+            Some(net) => net.reservation(ip, &mac, &scope),
+            None => kea.unsupported_because(path, format!("{ip} is not in any subnet")),
+        }
+    }
+    Ok(Import {
+        config: dora_config(top, kea.networks),
+        unsupported: kea.unsupported,
+    })
+}
+
+#[derive(Debug, Default)]
+struct Kea {
+    networks: Vec<Network>,
+    unsupported: Vec<String>,
+}
+
+impl Kea {
+    fn unsupported(&mut self, path: String) {
+        self.unsupported.push(format!("`{path}` is not supported"));
+    }
+
+    fn unsupported_because(&mut self, path: String, reason: impl AsRef<str>) {
+        self.unsupported
+            .push(format!("`{path}` left out: {}", reason.as_ref()));
+    }
+
+    /// apply the inherited settings in `obj` to `scope`
+    fn scope(&mut self, path: &str, obj: &Map<String, Value>, scope: &mut Scope) {
+        for (key, value) in obj {
+            let path = format!("{path}.{key}");
+            match key.as_str() {
+                "option-data" => self.options(&path, value, &mut scope.options),
+                "valid-lifetime" => match value.as_u64() {
+                    Some(secs) => scope.lease.default = secs as u32,
+                    None => self.unsupported_because(path, "not a number"),
+                },
+                "min-valid-lifetime" => scope.lease.min = value.as_u64().map(|secs| secs as u32),
+                "max-valid-lifetime" => scope.lease.max = value.as_u64().map(|secs| secs as u32),
+                "next-server" => match value.as_str() {
+                    // kea's way of leaving it unset
+                    Some("0.0.0.0") => {}
+                    Some(ip) if ip.parse::<Ipv4Addr>().is_ok() => {
+                        scope.boot.insert("next_server".into(), json!(ip));
+                    }
+                    _ => self.unsupported_because(path, "not an IPv4 address"),
+                },
+                "server-hostname" => {
+                    if let Some(name) = value.as_str().filter(|name| !name.is_empty()) {
+                        scope.boot.insert("server_name".into(), json!(name));
+                    }
+                }
+                "boot-file-name" => {
+                    if let Some(file) = value.as_str().filter(|file| !file.is_empty()) {
+                        scope.boot.insert("file_name".into(), json!(file));
+                    }
+                }
+                "authoritative" => scope.authoritative = value.as_bool(),
+                _ => {}
+            }
+        }
+    }
+
+    /// add `option-data` entries to `options`, replacing any with the same code
+    fn options(&mut self, path: &str, value: &Value, options: &mut Map<String, Value>) {
+        for (i, opt) in objects(value).enumerate() {
+            let path = format!("{path}[{i}]");
+            let name = opt.get("name").and_then(Value::as_str);
+            if opt.get("space").and_then(Value::as_str).unwrap_or("dhcp4") != "dhcp4" {
+                self.unsupported_because(path, "only options in the dhcp4 space are imported");
+                continue;
+            }
+            let named = name.and_then(named_option);
+            let code = opt
+                .get("code")
+                .and_then(Value::as_u64)
+                .and_then(|code| u8::try_from(code).ok())
+                .or(named.map(|(code, _)| code));
+            let Some(code) = code else {
+                self.unsupported_because(
+                    path,
+                    format!("unknown option `{}`", name.unwrap_or_default()),
+                );
+                continue;
+            };
+            let data = opt.get("data").and_then(Value::as_str).unwrap_or_default();
+            let csv = opt
+                .get("csv-format")
+                .and_then(Value::as_bool)
+                .unwrap_or(true);
+            let value = if csv {
+                let Some(kind) = named.map(|(_, kind)| kind).or_else(|| code_kind(code)) else {
+                    self.unsupported_because(
+                        path,
+                        format!("unknown type for option {code}, use `csv-format: false`"),
+                    );
+                    continue;
+                };
+                let values = data
+                    .split(',')
+                    .map(|v| v.trim().to_owned())
+                    .collect::<Vec<_>>();
+                match option_value(kind, &values) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        self.unsupported_because(path, err);
+                        continue;
+                    }
+                }
+            } else {
+                let hex = data.replace([' ', ':'], "");
+                json!({ "type": "hex", "value": hex.trim_start_matches("0x") })
+            };
+            if opt.get("always-send").and_then(Value::as_bool) == Some(true) {
+                self.unsupported_because(
+                    format!("{path}.always-send"),
+                    "dora only sends options in the parameter request list",
+                );
+            }
+            options.insert(code.to_string(), value);
+        }
+    }
+
+    fn interfaces(&mut self, path: &str, value: &Value) -> Option<Vec<String>> {
+        let obj = value.as_object()?;
+        for key in obj.keys().filter(|key| *key != "interfaces") {
+            self.unsupported(format!("{path}.{key}"));
+        }
+        // `*` is every interface, the same as leaving dora's list out. Addresses after
+        // the name aren't used, dora finds them itself
+        let interfaces = obj
+            .get("interfaces")?
+            .as_array()?
+            .iter()
+            .filter_map(Value::as_str)
+            .filter(|name| *name != "*")
+            .map(|name| name.split('/').next().unwrap_or(name).to_owned())
+            .collect::<Vec<_>>();
+        (!interfaces.is_empty()).then_some(interfaces)
+    }
+
+    fn subnets(&mut self, path: &str, value: &Value, parent: &Scope) {
+        for (i, subnet) in objects(value).enumerate() {
+            self.subnet(&format!("{path}[{i}]"), subnet, parent);
+        }
+    }
+
+    fn subnet(&mut self, path: &str, obj: &Map<String, Value>, parent: &Scope) {
+        let Some(subnet) = obj
+            .get("subnet")
+            .and_then(Value::as_str)
+            .and_then(|subnet| subnet.parse::<Ipv4Net>().ok())
+        else {
+            self.unsupported_because(path.to_owned(), "missing or invalid `subnet`");
+            return;
+        };
+        let mut scope = parent.clone();
+        self.scope(path, obj, &mut scope);
+        let class = obj.get("client-class").and_then(Value::as_str);
+        let mut net = Network::new(subnet.trunc(), scope.clone());
+        for (key, value) in obj {
+            let path = format!("{path}.{key}");
+            match key.as_str() {
+                key if SCOPE_KEYS.contains(&key) || IGNORED_KEYS.contains(&key) => {}
+                "subnet" | "client-class" => {}
+                "pools" => {
+                    for (i, pool) in objects(value).enumerate() {
+                        self.pool(&format!("{path}[{i}]"), pool, &scope, class, &mut net);
+                    }
+                }
+                "reservations" => {
+                    for (i, res) in objects(value).enumerate() {
+                        if let Some((ip, mac, scope)) =
+                            self.reservation(&format!("{path}[{i}]"), res, &scope)
+                        {
+                            net.reservation(ip, &mac, &scope);
+                        }
+                    }
+                }
+                _ => self.unsupported(path),
+            }
+        }
+        self.networks.push(net);
+    }
+
+    fn pool(
+        &mut self,
+        path: &str,
+        obj: &Map<String, Value>,
+        parent: &Scope,
+        class: Option<&str>,
+        net: &mut Network,
+    ) {
+        let Some((start, end)) = obj.get("pool").and_then(Value::as_str).and_then(pool) else {
+            self.unsupported_because(path.to_owned(), "missing or invalid `pool`");
+            return;
+        };
+        let mut scope = parent.clone();
+        let mut class = class;
+        for (key, value) in obj {
+            let path = format!("{path}.{key}");
+            match key.as_str() {
+                "option-data" => self.options(&path, value, &mut scope.options),
+                "client-class" => class = value.as_str(),
+                "pool" => {}
+                key if IGNORED_KEYS.contains(&key) => {}
+                _ => self.unsupported(path),
+            }
+        }
+        net.range(start, end, &scope, class);
+    }
+
+    /// a MAC reservation with an address, others can't be expressed in dora
+    fn reservation(
+        &mut self,
+        path: &str,
+        obj: &Map<String, Value>,
+        parent: &Scope,
+    ) -> Option<(Ipv4Addr, String, Scope)> {
+        if let Some(key) = ["client-id", "duid", "circuit-id", "flex-id"]
+            .into_iter()
+            .find(|key| obj.contains_key(*key))
+        {
+            self.unsupported_because(
+                path.to_owned(),
+                format!("reserved by `{key}`, dora only reserves by MAC"),
+            );
+            return None;
+        }
+        let Some(mac) = obj.get("hw-address").and_then(Value::as_str).and_then(mac) else {
+            self.unsupported_because(path.to_owned(), "missing or invalid `hw-address`");
+            return None;
+        };
+        let Some(ip) = obj
+            .get("ip-address")
+            .and_then(Value::as_str)
+            .and_then(|ip| ip.parse::<Ipv4Addr>().ok())
+        else {
+            self.unsupported_because(path.to_owned(), "reservations without an address");
+            return None;
+        };
+        let mut scope = parent.clone();
+        // the network's boot fields already apply
+        scope.boot.clear();
+        self.scope(path, obj, &mut scope);
+        for (key, value) in obj {
+            let path = format!("{path}.{key}");
+            match key.as_str() {
+                "hw-address" | "ip-address" => {}
+                "hostname" => {
+                    if let Some(name) = value.as_str().filter(|name| !name.is_empty()) {
+                        scope
+                            .options
+                            .insert("12".into(), json!({ "type": "str", "value": name }));
+                    }
+                }
+                key if SCOPE_KEYS.contains(&key) || IGNORED_KEYS.contains(&key) => {}
+                _ => self.unsupported(path),
+            }
+        }
+        Some((ip, mac, scope))
+    }
+
+    fn classes(&mut self, path: &str, value: &Value) -> Vec<Value> {
+        let mut classes = Vec::new();
+        for (i, obj) in objects(value).enumerate() {
+            let path = format!("{path}[{i}]");
+            let Some(name) = obj.get("name").and_then(Value::as_str) else {
+                self.unsupported_because(path, "missing `name`");
+                continue;
+            };
+            let Some(test) = obj.get("test").and_then(Value::as_str) else {
+                self.unsupported_because(path, format!("class `{name}` has no `test`"));
+                continue;
+            };
+            let mut scope = Scope::new(DEFAULT_LIFETIME);
+            self.scope(&path, obj, &mut scope);
+            let mut class = json!({
+                "name": name,
+                "assert": test,
+                "options": { "values": scope.options },
+            });
+            for (field, value) in scope.boot {
+                class[field] = value;
+            }
+            for key in obj.keys() {
+                match key.as_str() {
+                    "name" | "test" | "option-data" | "next-server" | "server-hostname"
+                    | "boot-file-name" => {}
+                    key if IGNORED_KEYS.contains(&key) => {}
+                    _ => self.unsupported(format!("{path}.{key}")),
+                }
+            }
+            classes.push(class);
+        }
+        classes
+    }
+}
+
+/// the objects in a JSON array, anything else is skipped
+fn objects(value: &Value) -> impl Iterator<Item = &Map<String, Value>> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object)
+}
+
+/// `"10.0.0.10 - 10.0.0.99"` or a prefix `"10.0.0.0/28"`
+fn pool(pool: &str) -> Option<(Ipv4Addr, Ipv4Addr)> {
+    match pool.split_once('-') {
+        Some((start, end)) => Some((start.trim().parse().ok()?, end.trim().parse().ok()?)),
+        None => {
+            let net = pool.trim().parse::<Ipv4Net>().ok()?;
+            Some((net.network(), net.broadcast()))
+        }
+    }
+}
+
+/// Kea allows `//`, `#` & `/* */` comments in its JSON
+fn strip_comments(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    let mut in_str = false;
+    while let Some(c) = chars.next() {
+        if in_str {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_str = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_str = true;
+                out.push(c);
+            }
+            ('#', _) | ('/', Some('/')) => {
+                // keep the newline so line numbers in errors still match
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        out.push(c);
+                        break;
+                    }
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = None;
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        out.push(c);
+                    }
+                    if prev == Some('*') && c == '/' {
+                        break;
+                    }
+                    prev = Some(c);
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static KEA: &str = r#"
+{
+    // leading comment
+    "Dhcp4": {
+        "interfaces-config": { "interfaces": [ "eth0/192.0.2.1" ] },
+        "valid-lifetime": 3600,
+        "match-client-id": false,
+        "lease-database": { "type": "memfile" },
+        "option-data": [
+            { "name": "domain-name-servers", "data": "192.0.2.1, 192.0.2.2" }
+        ],
+        "client-classes": [
+            { "name": "pxe", "test": "option[60].text == 'PXEClient'", "boot-file-name": "pxelinux.0" }
+        ],
+        "subnet4": [
+            {
+                "id": 1,
+                "subnet": "192.0.2.0/24",
+                "pools": [
+                    { "pool": "192.0.2.10 - 192.0.2.99" },
+                    { "pool": "192.0.2.128/26", "client-class": "pxe" }
+                ],
+                "option-data": [
+                    { "name": "routers", "data": "192.0.2.1" },
+                    { "code": 252, "data": "0A0B", "csv-format": false }
+                ],
+                "reservations": [
+                    { "hw-address": "1a:1b:1c:1d:1e:1f", "ip-address": "192.0.2.202", "hostname": "printer" },
+                    { "client-id": "01:11:22:33:44:55:66", "ip-address": "192.0.2.203" }
+                ],
+                "relay": { "ip-addresses": [ "192.0.2.254" ] }
+            }
+        ]
+    }
+}
+"#;
+
+    #[test]
+    fn test_kea() {
+        let import = import(KEA).unwrap();
+        let cfg = &import.config;
+        assert_eq!(cfg["interfaces"], json!(["eth0"]));
+        assert_eq!(cfg["chaddr_only"], json!(true));
+        assert_eq!(
+            cfg["client_classes"]["v4"][0]["file_name"],
+            json!("pxelinux.0")
+        );
+
+        let net = &cfg["networks"]["192.0.2.0/24"];
+        let range = &net["ranges"][0];
+        assert_eq!(range["start"], json!("192.0.2.10"));
+        assert_eq!(range["config"]["lease_time"]["default"], json!(3600));
+        // global & subnet options are merged into each range
+        let opts = &range["options"]["values"];
+        assert_eq!(opts["6"]["value"], json!(["192.0.2.1", "192.0.2.2"]));
+        assert_eq!(opts["3"]["value"], json!(["192.0.2.1"]));
+        assert_eq!(opts["252"], json!({ "type": "hex", "value": "0A0B" }));
+        assert_eq!(net["ranges"][1]["end"], json!("192.0.2.191"));
+        assert_eq!(net["ranges"][1]["class"], json!("pxe"));
+
+        let res = &net["reservations"];
+        assert_eq!(res.as_array().unwrap().len(), 1);
+        assert_eq!(res[0]["match"]["chaddr"], json!("1a:1b:1c:1d:1e:1f"));
+        assert_eq!(res[0]["options"]["values"]["12"]["value"], json!("printer"));
+
+        for path in ["lease-database", "relay", "reservations[1]"] {
+            assert!(
+                import.unsupported.iter().any(|u| u.contains(path)),
+                "{path} not in {:?}",
+                import.unsupported
+            );
+        }
+        super::super::validate(&import.config).unwrap();
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        assert_eq!(
+            strip_comments("{\"a\": \"//#\" /* x\ny */, # c\n}"),
+            "{\"a\": \"//#\" \n, \n}"
+        );
+    }
+}
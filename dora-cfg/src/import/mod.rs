@@ -0,0 +1,310 @@
+//! # Config import
+//!
+//! Translates a Kea `Dhcp4` config or a subset of ISC dhcpd.conf into a dora config.
+//! Anything without a dora equivalent is left out and listed in [`Import::unsupported`],
+//! the generated config should be reviewed before it's used.
+use std::net::Ipv4Addr;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use ipnet::Ipv4Net;
+use serde_json::{json, Map, Value};
+
+mod isc;
+mod kea;
+
+/// Config formats that can be imported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Source {
+    /// Kea JSON, only the `Dhcp4` section is used
+    Kea,
+    /// ISC dhcpd.conf
+    Isc,
+}
+
+/// A generated dora config
+#[derive(Debug)]
+pub struct Import {
+    /// the config in dora's wire format
+    pub config: Value,
+    /// constructs that were left out, with where they were found
+    pub unsupported: Vec<String>,
+}
+
+/// translate `input` to a dora config
+pub fn import(source: Source, input: &str) -> Result<Import> {
+    match source {
+        Source::Kea => kea::import(input),
+        Source::Isc => isc::import(input),
+    }
+}
+
+/// how an option's value is written in dora's `options` map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Ip,
+    IpList,
+    Str,
+    DomainList,
+    U8,
+    U16,
+    U32,
+    I32,
+    Bool,
+}
+
+/// names both formats use for common options, ISC names are listed where they differ
+static OPTIONS: &[(&str, u8, Kind)] = &[
+    ("subnet-mask", 1, Kind::Ip),
+    ("time-offset", 2, Kind::I32),
+    ("routers", 3, Kind::IpList),
+    ("time-servers", 4, Kind::IpList),
+    ("name-servers", 5, Kind::IpList),
+    ("domain-name-servers", 6, Kind::IpList),
+    ("log-servers", 7, Kind::IpList),
+    ("host-name", 12, Kind::Str),
+    ("domain-name", 15, Kind::Str),
+    ("root-path", 17, Kind::Str),
+    ("ip-forwarding", 19, Kind::Bool),
+    ("default-ip-ttl", 23, Kind::U8),
+    ("interface-mtu", 26, Kind::U16),
+    ("broadcast-address", 28, Kind::Ip),
+    ("nis-domain", 40, Kind::Str),
+    ("nis-servers", 41, Kind::IpList),
+    ("ntp-servers", 42, Kind::IpList),
+    ("netbios-name-servers", 44, Kind::IpList),
+    ("netbios-node-type", 46, Kind::U8),
+    ("dhcp-renewal-time", 58, Kind::U32),
+    ("dhcp-rebinding-time", 59, Kind::U32),
+    ("vendor-class-identifier", 60, Kind::Str),
+    ("tftp-server-name", 66, Kind::Str),
+    ("boot-file-name", 67, Kind::Str),
+    ("bootfile-name", 67, Kind::Str),
+    ("smtp-server", 69, Kind::IpList),
+    ("pop-server", 70, Kind::IpList),
+    ("www-server", 72, Kind::IpList),
+    ("pcode", 100, Kind::Str),
+    ("tcode", 101, Kind::Str),
+    ("v6-only-preferred", 108, Kind::U32),
+    ("domain-search", 119, Kind::DomainList),
+];
+
+/// the code & kind of a named option
+fn named_option(name: &str) -> Option<(u8, Kind)> {
+    OPTIONS
+        .iter()
+        .find(|(n, ..)| *n == name)
+        .map(|(_, code, kind)| (*code, *kind))
+}
+
+/// the kind of an option given by code, if it's one we know
+fn code_kind(code: u8) -> Option<Kind> {
+    OPTIONS
+        .iter()
+        .find(|(_, c, _)| *c == code)
+        .map(|(.., kind)| *kind)
+}
+
+/// an entry for dora's `options.values` from comma separated `values`
+fn option_value(kind: Kind, values: &[String]) -> Result<Value, String> {
+    fn one(values: &[String]) -> Result<&str, String> {
+        match values {
+            [value] => Ok(value),
+            _ => Err(format!("expected a single value, got {}", values.len())),
+        }
+    }
+    fn num<T: std::str::FromStr>(values: &[String]) -> Result<T, String> {
+        let value = one(values)?;
+        value
+            .parse()
+            .map_err(|_| format!("`{value}` is not a number"))
+    }
+    fn ip(value: &str) -> Result<Ipv4Addr, String> {
+        value
+            .parse()
+            .map_err(|_| format!("`{value}` is not an IPv4 address"))
+    }
+    Ok(match kind {
+        Kind::Ip => json!({ "type": "ip", "value": ip(one(values)?)? }),
+        Kind::IpList => json!({
+            "type": "ip",
+            "value": values.iter().map(|v| ip(v)).collect::<Result<Vec<_>, _>>()?,
+        }),
+        Kind::Str => json!({ "type": "str", "value": one(values)? }),
+        Kind::DomainList => json!({ "type": "domain", "value": values }),
+        Kind::U8 => json!({ "type": "u8", "value": num::<u8>(values)? }),
+        Kind::U16 => json!({ "type": "u16", "value": num::<u16>(values)? }),
+        Kind::U32 => json!({ "type": "u32", "value": num::<u32>(values)? }),
+        Kind::I32 => json!({ "type": "i32", "value": num::<i32>(values)? }),
+        Kind::Bool => {
+            let value = match one(values)? {
+                "true" | "on" | "1" => true,
+                "false" | "off" | "0" => false,
+                value => return Err(format!("`{value}` is not a boolean")),
+            };
+            json!({ "type": "bool", "value": value })
+        }
+    })
+}
+
+/// lease times in seconds, `default` is used if it's not set anywhere
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lease {
+    default: u32,
+    min: Option<u32>,
+    max: Option<u32>,
+}
+
+impl Lease {
+    fn config(&self) -> Value {
+        let mut lease_time = json!({ "default": self.default });
+        if let Some(min) = self.min {
+            lease_time["min"] = json!(min);
+        }
+        if let Some(max) = self.max {
+            lease_time["max"] = json!(max);
+        }
+        json!({ "lease_time": lease_time })
+    }
+}
+
+/// settings inherited from enclosing scopes, options are keyed by code
+#[derive(Debug, Clone)]
+struct Scope {
+    options: Map<String, Value>,
+    lease: Lease,
+    boot: Map<String, Value>,
+    authoritative: Option<bool>,
+}
+
+impl Scope {
+    fn new(default_lease: u32) -> Self {
+        Self {
+            options: Map::new(),
+            lease: Lease {
+                default: default_lease,
+                min: None,
+                max: None,
+            },
+            boot: Map::new(),
+            authoritative: None,
+        }
+    }
+}
+
+/// a network being built, reservations found outside of a subnet are added to the
+/// network whose subnet contains them
+#[derive(Debug)]
+struct Network {
+    subnet: Ipv4Net,
+    ranges: Vec<Value>,
+    reservations: Vec<Value>,
+    scope: Scope,
+}
+
+impl Network {
+    fn new(subnet: Ipv4Net, scope: Scope) -> Self {
+        Self {
+            subnet,
+            ranges: Vec::new(),
+            reservations: Vec::new(),
+            scope,
+        }
+    }
+
+    fn range(&mut self, start: Ipv4Addr, end: Ipv4Addr, scope: &Scope, class: Option<&str>) {
+        let mut range = json!({
+            "start": start,
+            "end": end,
+            "config": scope.lease.config(),
+            "options": { "values": scope.options },
+        });
+        if let Some(class) = class {
+            range["class"] = json!(class);
+        }
+        self.ranges.push(range);
+    }
+
+    fn reservation(&mut self, ip: Ipv4Addr, mac: &str, scope: &Scope) {
+        let mut res = json!({
+            "ip": ip,
+            "match": { "chaddr": mac },
+            "config": scope.lease.config(),
+            "options": { "values": scope.options },
+        });
+        for (field, value) in &scope.boot {
+            res[field] = value.clone();
+        }
+        self.reservations.push(res);
+    }
+
+    fn into_value(self) -> Value {
+        let mut net = Map::new();
+        if let Some(authoritative) = self.scope.authoritative {
+            net.insert("authoritative".into(), json!(authoritative));
+        }
+        net.extend(self.scope.boot);
+        net.insert("ranges".into(), Value::Array(self.ranges));
+        net.insert("reservations".into(), Value::Array(self.reservations));
+        Value::Object(net)
+    }
+}
+
+/// check the generated config is one dora accepts. `interfaces` are left out, they're
+/// looked up on this machine, which usually isn't the one the config is for
+pub fn validate(config: &Value) -> Result<()> {
+    let mut wire = serde_json::from_value::<config::wire::Config>(config.clone())?;
+    wire.interfaces = None;
+    for net in wire.networks.values_mut() {
+        net.interfaces = None;
+    }
+    config::v4::Config::try_from(wire)?;
+    Ok(())
+}
+
+/// the full config from the networks & any top level fields
+fn dora_config(mut top: Map<String, Value>, networks: Vec<Network>) -> Value {
+    let networks = networks
+        .into_iter()
+        .map(|net| (net.subnet.to_string(), net.into_value()))
+        .collect::<Map<_, _>>();
+    top.insert("networks".into(), Value::Object(networks));
+    Value::Object(top)
+}
+
+/// "aa:bb:cc:dd:ee:ff", also accepts `-` separators
+fn mac(value: &str) -> Option<String> {
+    let bytes = value
+        .split([':', '-'])
+        .map(|b| u8::from_str_radix(b, 16).ok().filter(|_| b.len() <= 2))
+        .collect::<Option<Vec<_>>>()?;
+    (bytes.len() == 6).then(|| {
+        bytes
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(":")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_option_value() {
+        let list = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            option_value(Kind::IpList, &list(&["1.1.1.1", "8.8.8.8"])).unwrap(),
+            json!({ "type": "ip", "value": ["1.1.1.1", "8.8.8.8"] })
+        );
+        assert_eq!(
+            option_value(Kind::U16, &list(&["1500"])).unwrap(),
+            json!({ "type": "u16", "value": 1500 })
+        );
+        assert!(option_value(Kind::Ip, &list(&["1.1.1.1", "8.8.8.8"])).is_err());
+        assert!(option_value(Kind::U8, &list(&["300"])).is_err());
+        assert_eq!(mac("AA-bb-cc-dd-ee-0f").unwrap(), "aa:bb:cc:dd:ee:0f");
+        assert!(mac("aa:bb:cc").is_none());
+    }
+}
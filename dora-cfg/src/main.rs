@@ -1,11 +1,18 @@
-use std::path::PathBuf;
+use std::{
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
 
-use anyhow::{Context, Result};
-use clap::{Parser, ValueEnum};
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 
-use config::wire;
+use config::{explain, v6, wire, wire::v6::ServerDuidInfo};
+use dora_core::{config::cli::DEFAULT_DATABASE_URL, pnet::util::MacAddr};
+use ip_manager::{reservations, sqlite::SqliteDb, Storage};
 use serde::de::DeserializeOwned;
 
+mod import;
+
 #[derive(Parser, Debug, Clone, PartialEq, Eq)]
 #[clap(author, version, about, long_about = None)]
 /// Cli tool for parsing config & JSON schema
@@ -19,6 +26,89 @@ pub struct Args {
     /// path to JSON schema. Config must be in JSON format and use `.json` extension
     #[clap(short = 's', long, value_parser)]
     pub schema: Option<PathBuf>,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// import reservations from a CSV or JSON file into the leases database. Reservations are
+    /// validated against the config at `--path`
+    ImportReservations {
+        /// path to reservations. `.csv` is parsed as CSV, anything else as JSON
+        #[clap(value_parser)]
+        file: PathBuf,
+        /// path to the leases database
+        #[clap(short = 'd', long, value_parser, default_value = DEFAULT_DATABASE_URL)]
+        database_url: String,
+        /// file containing the SQLCipher passphrase, if the database is encrypted
+        #[clap(long, env, value_parser)]
+        database_key_file: Option<PathBuf>,
+        /// validate only, do not write to the database
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// print the DHCPv6 server DUID the config resolves to & the persisted server id file
+    ShowServerDuid,
+    /// generate a new DHCPv6 server DUID and write it to the persisted server id file. The
+    /// previous file is kept with a `.bak` extension
+    RotateServerDuid {
+        /// DUID type, uses the `server_id` section of the config if not provided
+        #[clap(short = 't', long = "type", value_parser)]
+        duid_type: Option<DuidType>,
+        /// LLT/LL: link layer address (ipv6), EN/UUID: hex string. Random for EN if not provided
+        #[clap(short = 'i', long, value_parser)]
+        identifier: Option<String>,
+        /// enterprise number for EN
+        #[clap(short = 'e', long, value_parser)]
+        enterprise_id: Option<u32>,
+        /// confirm the rotation, every DHCPv6 client will see a new server id
+        #[clap(long)]
+        yes: bool,
+    },
+    /// compare the config at `--path` to a newer one. Changes that may invalidate existing
+    /// leases or reservations are marked with `!`
+    Diff {
+        /// the config to compare against, in the same formats as `--path`
+        #[clap(value_parser)]
+        new: PathBuf,
+    },
+    /// generate a dora config at `--path` from a Kea or ISC dhcpd config. Anything that
+    /// can't be translated is listed, review the result before using it
+    Import {
+        /// the config to translate
+        #[clap(value_parser)]
+        file: PathBuf,
+        /// format of `file`
+        #[clap(long, value_parser)]
+        from: import::Source,
+        /// replace the config at `--path` if it exists
+        #[clap(long)]
+        force: bool,
+    },
+    /// print the classes, address pool, lease times & options the config at `--path` would
+    /// answer a client with. Nothing is sent
+    Explain {
+        /// the client's MAC
+        #[clap(long, value_parser)]
+        mac: MacAddr,
+        /// relay (giaddr) or interface address the client's packets arrive from
+        #[clap(long, value_parser)]
+        subnet: Ipv4Addr,
+        /// an option the client sends as `code=value`, can be repeated. Opt 55 takes comma
+        /// separated codes (`55=1,3,6`), other values are hex with a `0x` prefix, IPv4
+        /// addresses, or text
+        #[clap(short = 'o', long = "options", value_parser)]
+        options: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DuidType {
+    Llt,
+    Ll,
+    En,
+    Uuid,
 }
 
 #[derive(Parser, Debug, Clone, PartialEq, Eq, ValueEnum)]
@@ -29,7 +119,10 @@ pub enum Format {
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    println!("found config at path = {}", args.path.display());
+    // `import` writes the config at `path`
+    if !matches!(args.command, Some(Command::Import { .. })) {
+        println!("found config at path = {}", args.path.display());
+    }
 
     parse_schema(&args)?;
     if let Some(format) = &args.format {
@@ -46,7 +139,211 @@ fn main() -> Result<()> {
             }
         }
     }
+    match &args.command {
+        Some(Command::ImportReservations {
+            file,
+            database_url,
+            database_key_file,
+            dry_run,
+        }) => import_reservations(
+            &args,
+            file,
+            database_url,
+            database_key_file.as_deref(),
+            *dry_run,
+        )?,
+        Some(Command::ShowServerDuid) => show_server_duid(&args)?,
+        Some(Command::RotateServerDuid {
+            duid_type,
+            identifier,
+            enterprise_id,
+            yes,
+        }) => {
+            let info = duid_type
+                .map(|ty| duid_info(ty, identifier.clone(), *enterprise_id))
+                .transpose()?;
+            rotate_server_duid(&args, info, *yes)?;
+        }
+        Some(Command::Diff { new }) => diff(&args, new)?,
+        Some(Command::Import { file, from, force }) => import_config(&args, file, *from, *force)?,
+        Some(Command::Explain {
+            mac,
+            subnet,
+            options,
+        }) => explain_client(&args, *mac, *subnet, options)?,
+        None => {}
+    }
+
+    Ok(())
+}
+
+fn import_reservations(
+    args: &Args,
+    file: &Path,
+    database_url: &str,
+    database_key_file: Option<&Path>,
+    dry_run: bool,
+) -> Result<()> {
+    let cfg = config::v4::Config::try_from(parse_wire::<wire::Config>(args)?)?;
+    let input = std::fs::read_to_string(file).context("failed to find reservations")?;
+    let res = match file.extension() {
+        Some(ext) if ext == "csv" => reservations::parse_csv(&input)?,
+        _ => reservations::parse_json(&input)?,
+    };
+    let errors = reservations::validate(&res, &cfg);
+    if !errors.is_empty() {
+        errors.iter().for_each(|err| eprintln!("{err}"));
+        bail!(
+            "{} of {} reservations failed validation",
+            errors.len(),
+            res.len()
+        );
+    }
+    if dry_run {
+        println!(
+            "validated {} reservations, dry run-- nothing written",
+            res.len()
+        );
+        return Ok(());
+    }
+    let key = database_key_file
+        .map(|path| std::fs::read_to_string(path).context("failed to read database key file"))
+        .transpose()?;
+    dora_core::tokio::runtime::Runtime::new()?.block_on(async {
+        // write to storage directly, `IpManager` needs a raw socket for ping
+        SqliteDb::with_key(database_url, key.as_deref().map(str::trim_end))
+            .await?
+            .insert_reservations(&res)
+            .await?;
+        anyhow::Ok(())
+    })?;
+    println!("imported {} reservations into {database_url}", res.len());
+    Ok(())
+}
+
+fn v6_config(args: &Args) -> Result<v6::Config> {
+    config::v4::Config::try_from(parse_wire::<wire::Config>(args)?)?
+        .v6()
+        .cloned()
+        .context("config has no v6 section")
+}
+
+fn duid_info(
+    ty: DuidType,
+    identifier: Option<String>,
+    enterprise_id: Option<u32>,
+) -> Result<ServerDuidInfo> {
+    let identifier = identifier.unwrap_or_default();
+    Ok(match ty {
+        DuidType::Llt => ServerDuidInfo::LLT {
+            htype: 0,
+            time: 0,
+            identifier,
+        },
+        DuidType::Ll => ServerDuidInfo::LL {
+            htype: 0,
+            identifier,
+        },
+        DuidType::En => ServerDuidInfo::EN {
+            enterprise_id: enterprise_id.unwrap_or_default(),
+            identifier,
+        },
+        DuidType::Uuid if identifier.is_empty() => {
+            bail!("--identifier is required for UUID type DUID")
+        }
+        DuidType::Uuid => ServerDuidInfo::UUID { identifier },
+    })
+}
+
+fn show_server_duid(args: &Args) -> Result<()> {
+    let cfg = v6_config(args)?;
+    let active = cfg
+        .server_id()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    println!("server DUID = {active}");
+    match (cfg.server_id_path(), cfg.persisted_server_id()?) {
+        (Some(path), Some(persisted)) => {
+            println!("persisted at {}", path.display());
+            println!("{persisted:#?}");
+        }
+        (Some(path), None) => println!("not yet persisted at {}", path.display()),
+        (None, _) => println!("server_id is not persisted"),
+    }
+    Ok(())
+}
+
+fn rotate_server_duid(args: &Args, info: Option<ServerDuidInfo>, yes: bool) -> Result<()> {
+    if !yes {
+        bail!(
+            "rotating the server DUID changes the server id seen by every DHCPv6 client, \
+             clients will fail to Renew and fall back to Rebind/Solicit after the server \
+             is restarted. Re-run with --yes to continue"
+        );
+    }
+    let rotation = v6_config(args)?.rotate_server_id(info.as_ref())?;
+    if let Some(previous) = &rotation.previous {
+        println!("previous DUID = {}", previous.identifier);
+    }
+    println!("new DUID = {}", rotation.current.identifier);
+    rotation
+        .warnings
+        .iter()
+        .for_each(|warning| eprintln!("warning: {warning}"));
+    Ok(())
+}
 
+fn diff(args: &Args, new: &Path) -> Result<()> {
+    let old = parse_wire::<wire::Config>(args)?;
+    let new = parse_wire_path::<wire::Config>(new)?;
+    // a diff against a config the server would refuse isn't much use
+    config::v4::Config::try_from(new.clone()).context("new config is invalid")?;
+    let changes = wire::diff::diff(&old, &new)?;
+    if changes.is_empty() {
+        println!("no changes");
+        return Ok(());
+    }
+    changes.iter().for_each(|change| println!("{change}"));
+    let breaking = changes.iter().filter(|change| change.breaking).count();
+    println!(
+        "{} changes, {breaking} may invalidate existing leases or reservations",
+        changes.len()
+    );
+    Ok(())
+}
+
+fn import_config(args: &Args, file: &Path, from: import::Source, force: bool) -> Result<()> {
+    if args.path.exists() && !force {
+        bail!(
+            "{} already exists, use --force to replace it",
+            args.path.display()
+        );
+    }
+    let input = std::fs::read_to_string(file).context("failed to find config to import")?;
+    let import = import::import(from, &input)?;
+    import
+        .unsupported
+        .iter()
+        .for_each(|unsupported| eprintln!("{unsupported}"));
+    std::fs::write(&args.path, serde_yaml::to_string(&import.config)?)
+        .context("failed to write config")?;
+    println!(
+        "wrote {} with {} constructs left out",
+        args.path.display(),
+        import.unsupported.len()
+    );
+    import::validate(&import.config).context("generated config is invalid, fix it before use")
+}
+
+fn explain_client(args: &Args, mac: MacAddr, subnet: Ipv4Addr, options: &[String]) -> Result<()> {
+    let cfg = config::v4::Config::try_from(parse_wire::<wire::Config>(args)?)?;
+    let opts = options
+        .iter()
+        .map(|opt| explain::parse_opt(opt))
+        .collect::<Result<Vec<_>>>()?;
+    let req = explain::request(&mac.octets(), &opts)?;
+    print!("{}", explain::explain(&cfg, subnet, &req)?);
     Ok(())
 }
 
@@ -73,9 +370,13 @@ fn parse_schema(args: &Args) -> Result<()> {
 }
 
 fn parse_wire<T: DeserializeOwned>(args: &Args) -> Result<T> {
-    let input = std::fs::read_to_string(&args.path).context("failed to find config")?;
+    parse_wire_path(&args.path)
+}
+
+fn parse_wire_path<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let input = std::fs::read_to_string(path).context("failed to find config")?;
 
-    Ok(match args.path.extension() {
+    Ok(match path.extension() {
         Some(ext) if ext == "json" => serde_json::from_str(&input)?,
         Some(ext) if ext == "yaml" => serde_yaml::from_str(&input)?,
         _ => match serde_json::from_str(&input) {
@@ -0,0 +1,88 @@
+//! Responses encoded into pooled buffers vs fresh ones, and pooled vs fresh plugin locals.
+//! The `threads` variants encode on several threads at once, like the receive loops do,
+//! to show what the pool's locks cost
+use std::{net::SocketAddr, thread};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dora_core::{
+    dhcproto::{v4, Encodable},
+    server::{msg::SerialMsg, typemap::TypeMap},
+};
+
+const THREADS: usize = 8;
+const PER_THREAD: usize = 1_000;
+
+/// a typical OFFER
+fn offer() -> v4::Message {
+    let mut msg = v4::Message::new(
+        [0, 0, 0, 0].into(),
+        [192, 168, 0, 100].into(),
+        [0, 0, 0, 0].into(),
+        [0, 0, 0, 0].into(),
+        &[0xde, 0xad, 0xbe, 0xef, 0x00, 0x01],
+    );
+    msg.set_opcode(v4::Opcode::BootReply);
+    let opts = msg.opts_mut();
+    opts.insert(v4::DhcpOption::MessageType(v4::MessageType::Offer));
+    opts.insert(v4::DhcpOption::ServerIdentifier([192, 168, 0, 1].into()));
+    opts.insert(v4::DhcpOption::SubnetMask([255, 255, 255, 0].into()));
+    opts.insert(v4::DhcpOption::Router(vec![[192, 168, 0, 1].into()]));
+    opts.insert(v4::DhcpOption::DomainNameServer(vec![
+        [8, 8, 8, 8].into(),
+        [1, 1, 1, 1].into(),
+    ]));
+    opts.insert(v4::DhcpOption::AddressLeaseTime(86_400));
+    msg
+}
+
+fn on_threads(f: impl Fn() + Sync) {
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| (0..PER_THREAD).for_each(|_| f()));
+        }
+    });
+}
+
+/// what a few plugins leave in the type map
+fn locals(map: &mut TypeMap) {
+    map.insert(vec![String::from("my_class")]);
+    map.insert(42_u32);
+    map.insert([192_u8, 168, 0, 100]);
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let msg = offer();
+    let addr: SocketAddr = "192.168.0.100:68".parse().unwrap();
+    let fresh = || black_box(SerialMsg::new(msg.to_vec().unwrap().into(), addr));
+    let pooled = || {
+        SerialMsg::from_msg(black_box(&msg), addr)
+            .unwrap()
+            .recycle()
+    };
+
+    c.bench_function("encode fresh", |b| b.iter(fresh));
+    c.bench_function("encode pooled", |b| b.iter(pooled));
+    c.bench_function("encode fresh, threads", |b| {
+        b.iter(|| on_threads(|| drop(fresh())))
+    });
+    c.bench_function("encode pooled, threads", |b| b.iter(|| on_threads(pooled)));
+
+    c.bench_function("typemap fresh", |b| {
+        b.iter(|| {
+            let mut map = TypeMap::new();
+            locals(&mut map);
+            black_box(map)
+        })
+    });
+    c.bench_function("typemap pooled", |b| {
+        b.iter(|| {
+            let mut map = TypeMap::pooled();
+            locals(&mut map);
+            black_box(&map);
+            map.recycle()
+        })
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
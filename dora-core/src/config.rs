@@ -31,20 +31,35 @@ pub mod cli {
     pub const DEFAULT_POLL: u64 = 60;
     /// default leases file path
     pub const DEFAULT_DATABASE_URL: &str = "/var/lib/dora/leases.db";
+    /// default days of lease history to keep
+    pub const DEFAULT_LEASE_HISTORY_DAYS: u64 = 90;
+    /// default requests each expensive external api route runs at once
+    pub const DEFAULT_EXTERNAL_API_CONCURRENCY: u64 = 4;
+    /// default ms expensive external api responses are cached
+    pub const DEFAULT_EXTERNAL_API_CACHE_MS: u64 = 1_000;
+    /// default number of database backups to keep
+    pub const DEFAULT_BACKUP_KEEP: u64 = 7;
     /// default dora id
     pub const DEFAULT_DORA_ID: &str = "dora_id";
     /// default log level. Can use this argument or DORA_LOG env var
     pub const DEFAULT_DORA_LOG: &str = "info";
+    /// default OID the SNMP agent's tables are under
+    pub const DEFAULT_SNMP_BASE_OID: &str = "1.3.6.1.4.1.13315.100.1";
+    /// community used when `snmp_community_file` isn't set
+    pub const DEFAULT_SNMP_COMMUNITY: &str = "public";
 
     use std::{
         net::{Ipv6Addr, SocketAddr},
-        path::PathBuf,
+        path::{Path, PathBuf},
         time::Duration,
     };
 
+    use anyhow::{ensure, Context, Result};
     pub use clap::Parser;
     use dhcproto::{v4, v6};
 
+    use super::runtime::CpuSet;
+
     #[derive(Parser, Debug, Clone, PartialEq, Eq)]
     #[clap(author, name = "dora", bin_name = "dora", about, long_about = None)]
     /// parses from cli & environment var. dora will load `.env` in the same dir as the binary as well
@@ -70,6 +85,15 @@ pub mod cli {
         /// default timeout, dora will respond within this window or drop
         #[clap(long, env, value_parser, default_value_t = DEFAULT_TIMEOUT)]
         pub timeout: u64,
+        /// seconds messages in flight at shutdown are given to finish, including their
+        /// lease writes & DDNS updates, before they're cancelled
+        #[clap(long, env, value_parser, default_value_t = DEFAULT_TIMEOUT)]
+        pub shutdown_grace_secs: u64,
+        /// tasks decoding received packets for each server. The socket is read by one loop
+        /// that hands packets to the workers in turn, so decoding & dispatch scale across
+        /// cores & each broadcast is still handled once
+        #[clap(long, env, value_parser = clap::value_parser!(u16).range(1..), default_value_t = 1)]
+        pub recv_workers: u16,
         /// max live messages before new messages will begin to be dropped
         #[clap(long, env, value_parser, default_value_t = DEFAULT_MAX_LIVE_MSGS)]
         pub max_live_msgs: usize,
@@ -82,6 +106,39 @@ pub mod cli {
         /// Worker thread name
         #[clap(long, env, value_parser, default_value = DEFAULT_THREAD_NAME)]
         pub thread_name: String,
+        /// worker threads for a runtime of the v4 server's own, so a flood of v6 or api
+        /// requests can't take them. By default it shares the main runtime
+        #[clap(long, env, value_parser)]
+        pub v4_threads: Option<usize>,
+        /// CPUs the v4 server's threads are pinned to, ex. `0-3,8`. Gives the v4 server
+        /// its own runtime, with one thread per CPU unless `v4_threads` is set
+        #[clap(long, env, value_parser)]
+        pub v4_cpus: Option<CpuSet>,
+        /// worker threads for a runtime of the v6 server's own. By default it shares the
+        /// main runtime
+        #[clap(long, env, value_parser)]
+        pub v6_threads: Option<usize>,
+        /// CPUs the v6 server's threads are pinned to, ex. `4-7`. Gives the v6 server its
+        /// own runtime, with one thread per CPU unless `v6_threads` is set
+        #[clap(long, env, value_parser)]
+        pub v6_cpus: Option<CpuSet>,
+        /// worker threads for a runtime of the external api's own. By default it shares
+        /// the main runtime
+        #[clap(long, env, value_parser)]
+        pub external_api_threads: Option<usize>,
+        /// CPUs the external api's threads are pinned to. Gives the external api its own
+        /// runtime, with one thread per CPU unless `external_api_threads` is set
+        #[clap(long, env, value_parser)]
+        pub external_api_cpus: Option<CpuSet>,
+        /// requests the expensive external api routes (leases, utilization, history) run
+        /// at once between them, more are answered with a 429 & Retry-After. At most 8, so
+        /// DHCP always has database connections
+        #[clap(long, env, value_parser = clap::value_parser!(u64).range(1..), default_value_t = DEFAULT_EXTERNAL_API_CONCURRENCY)]
+        pub external_api_concurrency: u64,
+        /// milliseconds responses of the expensive external api routes are cached for, 0
+        /// doesn't cache
+        #[clap(long, env, value_parser, default_value_t = DEFAULT_EXTERNAL_API_CACHE_MS)]
+        pub external_api_cache_ms: u64,
         /// ID of this instance
         #[clap(long, env, value_parser, default_value = DEFAULT_DORA_ID)]
         pub dora_id: String,
@@ -92,6 +149,74 @@ pub mod cli {
         /// NOTE: in memory sqlite db connection idle timeout is 5 mins
         #[clap(short, env, value_parser, default_value = DEFAULT_DATABASE_URL)]
         pub database_url: String,
+        /// file containing the passphrase used to encrypt the database with SQLCipher.
+        /// dora must be built with the `sqlcipher` feature
+        #[clap(long, env, value_parser)]
+        pub database_key_file: Option<PathBuf>,
+        /// days of lease history to keep, older history is pruned hourly. 0 keeps it forever
+        #[clap(long, env, value_parser, default_value_t = DEFAULT_LEASE_HISTORY_DAYS)]
+        pub lease_history_days: u64,
+        /// stream every change to the leases table, with the entry before & after it, to
+        /// this file as JSON lines or to `nats://host:port/subject`. NATS needs the `nats`
+        /// feature. Off unless it's set
+        #[clap(long, env, value_parser)]
+        pub change_stream: Option<String>,
+        /// directory backups of the leases database are written to. Backups are only made
+        /// on a schedule or by `POST /v1/backup` if it's set
+        #[clap(long, env, value_parser)]
+        pub backup_dir: Option<PathBuf>,
+        /// seconds between scheduled backups, 0 only makes them on request
+        #[clap(long, env, value_parser, default_value_t = 0)]
+        pub backup_interval_secs: u64,
+        /// number of backups to keep, the oldest are removed after each backup
+        #[clap(long, env, value_parser = clap::value_parser!(u64).range(1..), default_value_t = DEFAULT_BACKUP_KEEP)]
+        pub backup_keep: u64,
+        /// file containing the bearer token for admin-only external api requests, like
+        /// reading the raw config file or changing exclusions, quarantine, allocations &
+        /// backups. They are refused if this isn't set
+        #[clap(long, env, value_parser)]
+        pub external_api_admin_token_file: Option<PathBuf>,
+        /// config to switch to at its `effective_at` time. It's read & validated at
+        /// startup, if `effective_at` has already passed it's used right away
+        #[clap(long, env, value_parser)]
+        pub next_config_path: Option<PathBuf>,
+        /// seconds between checkpoints of the `_total_persistent` counters to the database,
+        /// they're restored at startup so they survive restarts. 0 disables them
+        #[clap(long, env, value_parser, default_value_t = 0)]
+        pub stats_checkpoint_secs: u64,
+        /// a wall clock jump larger than this many seconds (NTP step, VM resume) freezes
+        /// lease expiry, so leases aren't mass expired or extended. Expiry follows the
+        /// clock from before the jump until it's acknowledged with `POST
+        /// /v1/clock/acknowledge` or dora restarts. 0 disables the check
+        #[clap(long, env, value_parser, default_value_t = 60)]
+        pub clock_jump_secs: u64,
+        /// run every message through the plugins but never send a response. Would-be
+        /// responses are logged to the `dora::dry_run` target & counted in
+        /// `dhcpv4_dry_run_count`/`dhcpv6_dry_run_count`. No ICMP pings are sent for
+        /// `ping_check`. Leases are still written, so use a separate database
+        #[clap(long, env)]
+        pub dry_run: bool,
+        /// serve only the external api, with the database opened read-only. No DHCP
+        /// servers are started & api requests that change state are refused. For running
+        /// dashboards & audits against a copy of a server's database on another host
+        #[clap(long, env)]
+        pub api_only: bool,
+        /// config every message is also run through the plugins with, differences from the
+        /// running config's DISCOVER & REQUEST responses are logged to the `dora::shadow`
+        /// target & counted in `shadow_diff_count`. It allocates from its own in-memory
+        /// lease db & nothing is sent for it
+        #[clap(long, env, value_parser)]
+        pub shadow_config_path: Option<PathBuf>,
+        /// address of an SNMPv2c agent answering with pool utilization & the prometheus
+        /// counters, ex. `0.0.0.0:161`. There's no agent unless it's set
+        #[clap(long, env, value_parser)]
+        pub snmp_addr: Option<SocketAddr>,
+        /// file containing the community SNMP requests must use, `public` if it isn't set
+        #[clap(long, env, value_parser)]
+        pub snmp_community_file: Option<PathBuf>,
+        /// OID the SNMP agent's pool & metric tables are under
+        #[clap(long, env, value_parser, default_value = DEFAULT_SNMP_BASE_OID)]
+        pub snmp_base_oid: String,
     }
 
     impl Config {
@@ -100,6 +225,64 @@ pub mod cli {
             Duration::from_secs(self.timeout)
         }
 
+        /// how long in-flight messages are waited on at shutdown
+        pub fn shutdown_grace(&self) -> Duration {
+            Duration::from_secs(self.shutdown_grace_secs)
+        }
+
+        /// how long lease history is kept, `None` if it's never pruned
+        pub fn lease_history_retention(&self) -> Option<Duration> {
+            (self.lease_history_days > 0)
+                .then(|| Duration::from_secs(self.lease_history_days * 24 * 60 * 60))
+        }
+
+        /// how long expensive external api responses are cached
+        pub fn external_api_cache_ttl(&self) -> Duration {
+            Duration::from_millis(self.external_api_cache_ms)
+        }
+
+        /// how often the database is backed up, `None` if it's only backed up on request
+        pub fn backup_interval(&self) -> Option<Duration> {
+            (self.backup_interval_secs > 0).then(|| Duration::from_secs(self.backup_interval_secs))
+        }
+
+        /// how often persistent counters are saved, `None` if they aren't kept
+        pub fn stats_checkpoint_interval(&self) -> Option<Duration> {
+            (self.stats_checkpoint_secs > 0)
+                .then(|| Duration::from_secs(self.stats_checkpoint_secs))
+        }
+
+        /// largest wall clock jump allowed before lease expiry is frozen, `None` if jumps
+        /// aren't checked
+        pub fn clock_jump(&self) -> Option<Duration> {
+            (self.clock_jump_secs > 0).then(|| Duration::from_secs(self.clock_jump_secs))
+        }
+
+        /// read the database passphrase from `database_key_file`, trailing whitespace is trimmed
+        pub fn database_key(&self) -> Result<Option<String>> {
+            self.database_key_file
+                .as_deref()
+                .map(|path| read_secret(path, "database key"))
+                .transpose()
+        }
+
+        /// read the external api admin token from `external_api_admin_token_file`,
+        /// trailing whitespace is trimmed
+        pub fn external_api_admin_token(&self) -> Result<Option<String>> {
+            self.external_api_admin_token_file
+                .as_deref()
+                .map(|path| read_secret(path, "external api admin token"))
+                .transpose()
+        }
+
+        /// read the SNMP community from `snmp_community_file`, trailing whitespace is trimmed
+        pub fn snmp_community(&self) -> Result<String> {
+            match self.snmp_community_file.as_deref() {
+                Some(path) => read_secret(path, "snmp community"),
+                None => Ok(DEFAULT_SNMP_COMMUNITY.to_owned()),
+            }
+        }
+
         /// are we bound to the default dhcpv4 port?
         pub fn is_default_port_v4(&self) -> bool {
             self.v4_addr.port() == v4::SERVER_PORT
@@ -110,19 +293,186 @@ pub mod cli {
             self.v6_addr.port() == v6::SERVER_PORT
         }
     }
+
+    fn read_secret(path: &Path, name: &str) -> Result<String> {
+        let secret = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {name} file {}", path.display()))?;
+        let secret = secret.trim_end();
+        ensure!(
+            !secret.is_empty(),
+            "{name} file {} is empty",
+            path.display()
+        );
+        Ok(secret.to_owned())
+    }
+}
+
+pub mod runtime {
+    //! dedicated runtimes
+    //!
+    //! Everything runs on the main runtime by default. The v4 & v6 servers and the
+    //! external api can each be given a runtime of their own, so one of them being
+    //! flooded can't starve the others of worker threads, and its threads can be pinned
+    //! to a set of CPUs.
+    use std::{fmt, io, mem, str::FromStr};
+
+    use anyhow::{bail, ensure, Context, Result};
+    use tokio::runtime::{Builder, Runtime};
+    use tracing::{info, warn};
+
+    /// A set of CPUs, parsed from a list of CPUs & ranges like `0-3,8`
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct CpuSet(Vec<usize>);
+
+    impl CpuSet {
+        /// number of CPUs in the set
+        pub fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        /// `true` if the set has no CPUs, a parsed set never is
+        pub fn is_empty(&self) -> bool {
+            self.0.is_empty()
+        }
+
+        /// restrict the calling thread to these CPUs
+        pub fn pin_current_thread(&self) -> io::Result<()> {
+            // SAFETY: cpu_set_t is a plain bitmask, CPUs were checked against
+            // CPU_SETSIZE when parsing & pid 0 is the calling thread
+            unsafe {
+                let mut set: libc::cpu_set_t = mem::zeroed();
+                for cpu in &self.0 {
+                    libc::CPU_SET(*cpu, &mut set);
+                }
+                if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl FromStr for CpuSet {
+        type Err = anyhow::Error;
+
+        fn from_str(s: &str) -> Result<Self> {
+            let cpu = |s: &str| -> Result<usize> {
+                let cpu = s
+                    .trim()
+                    .parse::<usize>()
+                    .with_context(|| format!("`{s}` is not a CPU number"))?;
+                ensure!(
+                    cpu < libc::CPU_SETSIZE as usize,
+                    "CPU {cpu} is out of range"
+                );
+                Ok(cpu)
+            };
+            let mut cpus = Vec::new();
+            for part in s.split(',') {
+                match part.split_once('-') {
+                    Some((start, end)) => {
+                        let (start, end) = (cpu(start)?, cpu(end)?);
+                        if start > end {
+                            bail!("CPU range `{part}` is backwards");
+                        }
+                        cpus.extend(start..=end);
+                    }
+                    None => cpus.push(cpu(part)?),
+                }
+            }
+            cpus.sort_unstable();
+            cpus.dedup();
+            Ok(Self(cpus))
+        }
+    }
+
+    impl fmt::Display for CpuSet {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let cpus = self.0.iter().map(|cpu| cpu.to_string()).collect::<Vec<_>>();
+            write!(f, "{}", cpus.join(","))
+        }
+    }
+
+    /// a runtime for one of the servers or the external api, `None` if neither `threads`
+    /// nor `cpus` is set & it should share the main runtime. Worker threads are named
+    /// `thread_name` & pinned to `cpus`
+    pub fn dedicated(
+        thread_name: &str,
+        threads: Option<usize>,
+        cpus: Option<CpuSet>,
+    ) -> Result<Option<Runtime>> {
+        let Some(threads) = threads.or_else(|| cpus.as_ref().map(CpuSet::len)) else {
+            return Ok(None);
+        };
+        ensure!(
+            threads > 0,
+            "{thread_name} needs at least one worker thread"
+        );
+        let mut builder = Builder::new_multi_thread();
+        builder
+            .thread_name(thread_name)
+            .worker_threads(threads)
+            .enable_all();
+        info!(
+            thread_name,
+            threads,
+            cpus = ?cpus.as_ref().map(|cpus| cpus.to_string()),
+            "starting dedicated runtime"
+        );
+        if let Some(cpus) = cpus {
+            builder.on_thread_start(move || {
+                if let Err(err) = cpus.pin_current_thread() {
+                    warn!(?err, %cpus, "failed to pin thread to CPUs");
+                }
+            });
+        }
+        Ok(Some(builder.build()?))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_cpu_set() {
+            let cpus = "4-6,0, 2,5".parse::<CpuSet>().unwrap();
+            assert_eq!(cpus, CpuSet(vec![0, 2, 4, 5, 6]));
+            assert_eq!(cpus.to_string(), "0,2,4,5,6");
+            assert!("3-1".parse::<CpuSet>().is_err());
+            assert!("a".parse::<CpuSet>().is_err());
+            assert!("".parse::<CpuSet>().is_err());
+            assert!("100000".parse::<CpuSet>().is_err());
+        }
+
+        #[test]
+        fn test_dedicated() {
+            assert!(dedicated("test", None, None).unwrap().is_none());
+            assert!(dedicated("test", Some(0), None).is_err());
+            let rt = dedicated("test", None, Some("0".parse().unwrap()))
+                .unwrap()
+                .unwrap();
+            assert_eq!(rt.block_on(async { 1 + 1 }), 2);
+        }
+    }
 }
 
 pub mod trace {
     //! tracing configuration
+    //!
+    //! With the `otel` feature, spans are also exported over OTLP when
+    //! `OTEL_EXPORTER_OTLP_ENDPOINT` is set. Each DHCP transaction is one trace, with the
+    //! xid & client id on the root span and a child span per plugin.
     use anyhow::Result;
+    use tracing::Subscriber;
     use tracing_subscriber::{
         filter::EnvFilter,
         fmt::{
             self,
             format::{Format, PrettyFields},
         },
-        prelude::__tracing_subscriber_SubscriberExt,
-        util::SubscriberInitExt,
+        prelude::*,
+        registry::LookupSpan,
+        Layer,
     };
 
     use std::str;
@@ -131,6 +481,12 @@ pub mod trace {
 
     /// log as "json" or "standard" (unstructured)
     static DEFAULT_LOG_FORMAT: &str = "standard";
+    /// `service.name` of exported traces
+    #[cfg(feature = "otel")]
+    static DEFAULT_OTEL_SERVICE_NAME: &str = "dora";
+    /// which spans are exported, the exporter's own http client is always left out
+    #[cfg(feature = "otel")]
+    static DEFAULT_OTEL_FILTER: &str = "debug";
 
     /// Configuration for `tokio` runtime
     #[derive(Debug)]
@@ -148,35 +504,96 @@ pub mod trace {
             let filter = EnvFilter::try_new(dora_log)
                 .or_else(|_| EnvFilter::try_new("info"))?
                 .add_directive("hyper=off".parse()?);
+            // the filter only applies to logs, exported spans are filtered separately
+            let otel = otel_layer()?;
 
             match &log_frmt[..] {
                 "json" => {
                     tracing_subscriber::registry()
-                        .with(filter)
-                        .with(fmt::layer().json())
+                        .with(otel)
+                        .with(fmt::layer().json().with_filter(filter))
                         .init();
                 }
                 "pretty" => {
                     tracing_subscriber::registry()
-                        .with(filter)
+                        .with(otel)
                         .with(
                             fmt::layer()
                                 .event_format(
                                     Format::default().pretty().with_source_location(false),
                                 )
-                                .fmt_fields(PrettyFields::new()),
+                                .fmt_fields(PrettyFields::new())
+                                .with_filter(filter),
                         )
                         .init();
                 }
                 _ => {
                     tracing_subscriber::registry()
-                        .with(filter)
-                        .with(fmt::layer())
+                        .with(otel)
+                        .with(fmt::layer().with_filter(filter))
                         .init();
                 }
             }
 
             Ok(Self { log_frmt })
         }
+
+        /// flush any spans that haven't been exported yet, call before exiting
+        pub fn shutdown(&self) {
+            #[cfg(feature = "otel")]
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+
+    /// OTLP export layer, `None` if `OTEL_EXPORTER_OTLP_ENDPOINT` isn't set.
+    /// Must be called inside a tokio runtime, the batch exporter is spawned on it
+    #[cfg(feature = "otel")]
+    fn otel_layer<S>() -> Result<Option<Box<dyn Layer<S> + Send + Sync>>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    {
+        use opentelemetry::KeyValue;
+        use opentelemetry_otlp::WithExportConfig;
+        use opentelemetry_sdk::{runtime, trace, Resource};
+
+        let Ok(endpoint) = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") else {
+            return Ok(None);
+        };
+        let service: String = parse_var_with_err("OTEL_SERVICE_NAME", DEFAULT_OTEL_SERVICE_NAME)?;
+        let filter = EnvFilter::try_new(parse_var_with_err::<String, _>(
+            "OTEL_FILTER",
+            DEFAULT_OTEL_FILTER,
+        )?)?
+        .add_directive("hyper=off".parse()?)
+        .add_directive("h2=off".parse()?)
+        .add_directive("tonic=off".parse()?)
+        .add_directive("tower=off".parse()?);
+
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(
+                trace::config()
+                    .with_resource(Resource::new([KeyValue::new("service.name", service)])),
+            )
+            .install_batch(runtime::Tokio)?;
+        Ok(Some(
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(filter)
+                .boxed(),
+        ))
+    }
+
+    #[cfg(not(feature = "otel"))]
+    fn otel_layer<S>() -> Result<Option<Box<dyn Layer<S> + Send + Sync>>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    {
+        Ok(None)
     }
 }
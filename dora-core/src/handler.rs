@@ -24,11 +24,20 @@ pub trait Plugin<T>: Send + Sync + 'static {
     /// CANCEL-SAFETY: everything in handle must be cancel-safe. A top-level timeout can possibly kill this
     /// method
     async fn handle(&self, ctx: &mut MsgContext<T>) -> Result<Action>;
+
+    /// name of the span `handle` runs in, defaults to the type name without its path
+    /// or generics
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+        name.rsplit("::").next().unwrap_or(name)
+    }
 }
 
 /// A handler that is run after the response is returned. This moves the
 /// `MsgContext` instead of borrowing it, and as such only one such handler can
-/// be added.
+/// be added. [`MsgContext::outcome`] says whether the message was answered,
+/// dropped or failed.
 #[async_trait]
 pub trait PostResponse<T>: Send + Sync + 'static {
     /// what to execute during this step in the message lifecycle
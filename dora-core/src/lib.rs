@@ -34,6 +34,7 @@ pub mod env;
 pub mod handler;
 pub mod metrics;
 pub mod prelude;
+pub mod pretty;
 pub mod server;
 
 /// Register a plugin with the server
@@ -3,12 +3,13 @@
 //! # metrics
 //!
 //! contains statistics for server metrics
-use std::time::Instant;
+use std::{collections::HashMap, sync::Mutex, time::Instant};
 
 use lazy_static::lazy_static;
 use prometheus::{
-    register_int_counter, register_int_counter_vec, register_int_gauge, HistogramOpts,
-    HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    core::Collector, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec,
 };
 use prometheus_static_metric::make_static_metric;
 
@@ -68,6 +69,11 @@ lazy_static! {
     /// bytes recv DHCPv6
     pub static ref DHCPV6_BYTES_RECV: IntCounter = register_int_counter!("dhcpv6_bytes_recv", "DHCPv6 bytes recv").unwrap();
 
+    /// DHCPv4 responses not sent because of `--dry-run`
+    pub static ref DHCPV4_DRY_RUN_COUNT: IntCounter = register_int_counter!("dhcpv4_dry_run_count", "DHCPv4 responses not sent because of dry run").unwrap();
+    /// DHCPv6 responses not sent because of `--dry-run`
+    pub static ref DHCPV6_DRY_RUN_COUNT: IntCounter = register_int_counter!("dhcpv6_dry_run_count", "DHCPv6 responses not sent because of dry run").unwrap();
+
     /// histogram of response times for DHCPv4 reply
     pub static ref DHCPV4_REPLY_DURATION: HistogramVec = HistogramVec::new(
         HistogramOpts::new("dhpcv4_duration", "dhcpv4 duration (seconds)"),
@@ -120,25 +126,63 @@ lazy_static! {
     /// aggregate count of all sent messages types
     pub static ref V6_SENT_TYPE_COUNT: V6SentStats = V6SentStats::from(&V6_SENT_COUNT_VEC);
 
+    // counters that survive restarts, see `PersistentStats`
+
+    /// `recv_type_counts` including previous runs
+    pub static ref RECV_PERSISTENT: IntCounterVec = register_int_counter_vec!(
+        "dhcpv4_recv_total_persistent",
+        "DHCPv4 messages recv, kept across restarts",
+        &["message_type"]
+    )
+    .unwrap();
+    /// `sent_type_counts` including previous runs
+    pub static ref SENT_PERSISTENT: IntCounterVec = register_int_counter_vec!(
+        "dhcpv4_sent_total_persistent",
+        "DHCPv4 messages sent, kept across restarts",
+        &["message_type"]
+    )
+    .unwrap();
+    /// `v6_recv_type_counts` including previous runs
+    pub static ref V6_RECV_PERSISTENT: IntCounterVec = register_int_counter_vec!(
+        "dhcpv6_recv_total_persistent",
+        "DHCPv6 messages recv, kept across restarts",
+        &["v6_message_type"]
+    )
+    .unwrap();
+    /// `v6_sent_type_counts` including previous runs
+    pub static ref V6_SENT_PERSISTENT: IntCounterVec = register_int_counter_vec!(
+        "dhcpv6_sent_total_persistent",
+        "DHCPv6 messages sent, kept across restarts",
+        &["v6_message_type"]
+    )
+    .unwrap();
+
     /// # of in flight msgs
     pub static ref IN_FLIGHT: IntGauge =
         register_int_gauge!("in_flight", "count of currently processing messages").unwrap();
 
-    // TODO: set in external-api
-    /// # of declined IPs
-    // pub static ref DECLINED_ADDRS: IntGauge =
-        // register_int_gauge!("declined_addrs", "count of addresses currently on probation from decline").unwrap();
-
-    // TODO: set in external-api
-    /// # of leased IPs
-    // pub static ref LEASED_ADDRS: IntGauge =
-    //     register_int_gauge!("leased_addrs", "count of addresses currently leased").unwrap();
+    /// # of un-expired addresses per network, labeled by `state`: leased, reserved, probated or free.
+    /// Refreshed periodically by the external api
+    pub static ref LEASE_COUNT: IntGaugeVec = register_int_gauge_vec!(
+        "lease_count",
+        "count of addresses in each state per network",
+        &["network", "state"]
+    )
+    .unwrap();
 
     /// # of total addrs available
     pub static ref TOTAL_AVAILABLE_ADDRS: IntGauge =
         register_int_gauge!("total_available_addrs", "count of addresses currently leased").unwrap();
     /// server uptime
     pub static ref UPTIME: IntGauge = register_int_gauge!("uptime", "server uptime (seconds)").unwrap();
+    /// times the server switched to a staged config at its `effective_at`
+    pub static ref CONFIG_SWITCH_COUNT: IntCounter = register_int_counter!("config_switch_count", "count of switches to a staged config").unwrap();
+    /// `effective_at` of the staged config as a unix timestamp, 0 if nothing is staged
+    pub static ref CONFIG_NEXT_SWITCH: IntGauge = register_int_gauge!("config_next_switch_timestamp", "unix time the staged config takes effect").unwrap();
+    /// wall clock jumps larger than `--clock-jump-secs`
+    pub static ref CLOCK_JUMP_COUNT: IntCounter = register_int_counter!("clock_jump_count", "count of wall clock jumps that froze lease expiry").unwrap();
+    /// 1 while lease expiry is frozen after a clock jump
+    pub static ref CLOCK_JUMP_FROZEN: IntGauge = register_int_gauge!("clock_jump_frozen", "lease expiry is frozen after a wall clock jump").unwrap();
 
     // ICMP metrics
 
@@ -161,6 +205,21 @@ lazy_static! {
     )
     .unwrap();
 
+    /// 1 if the ICMP listener is running, 0 while it's being restarted. Labeled by `proto`: v4 or v6
+    pub static ref ICMP_LISTENER_UP: IntGaugeVec = register_int_gauge_vec!(
+        "icmp_listener_up",
+        "whether the ICMP reply listener is running",
+        &["proto"]
+    )
+    .unwrap();
+    /// times the ICMP listener was restarted after its socket failed
+    pub static ref ICMP_LISTENER_RESTART_COUNT: IntCounterVec = register_int_counter_vec!(
+        "icmp_listener_restart_count",
+        "count of ICMP listener restarts",
+        &["proto"]
+    )
+    .unwrap();
+
   /// histogram of response times for ping reply v6
     pub static ref ICMPV6_REPLY_DURATION: HistogramVec = HistogramVec::new(
         HistogramOpts::new("icmpv6_duration", "icmpv6 response time in seconds, only counts received pings"),
@@ -170,8 +229,309 @@ lazy_static! {
 
     // client protection metrics
 
+    /// allocations that failed because another writer got there first, or storage was busy
+    pub static ref ALLOC_CONFLICT_COUNT: IntCounter = register_int_counter!("alloc_conflict_count", "count of address allocations that conflicted with another writer and were retried or refused").unwrap();
     /// renew cached hit
     pub static ref RENEW_CACHE_HIT: IntCounter = register_int_counter!("renew_cache_hit_count", "count of renew cache hits inside of renewal time").unwrap();
+    /// renew cache lookups that didn't return a cached lease
+    pub static ref RENEW_CACHE_MISS: IntCounter = register_int_counter!("renew_cache_miss_count", "count of renew cache misses, no entry or outside of renewal time").unwrap();
+    /// clients in the renew cache
+    pub static ref RENEW_CACHE_SIZE: IntGauge = register_int_gauge!("renew_cache_size", "number of clients in the renew cache").unwrap();
+    /// clients currently refused by the flood limiter
+    pub static ref FLOOD_LIMITED_CLIENTS: IntGauge = register_int_gauge!("flood_limited_clients", "number of clients currently rate limited by flood protection").unwrap();
+    /// 1 while the flood limiter uses its tighter `pressure` limit for any network
+    pub static ref FLOOD_PRESSURE: IntGauge = register_int_gauge!("flood_pressure", "1 while pool utilization has tightened the flood threshold of a network").unwrap();
     /// flood threshold reached
     pub static ref FLOOD_THRESHOLD_COUNT: IntCounter = register_int_counter!("flood_threshold_count", "count of times flood threshold has been reached").unwrap();
+    /// packets refused by each flood limiter, by what it counts packets by
+    pub static ref FLOOD_THRESHOLD_KEY_COUNT: IntCounterVec = register_int_counter_vec!(
+        "flood_threshold_key_count",
+        "count of packets refused by each flood limiter",
+        &["key"]
+    )
+    .unwrap();
+    /// messages not answered because the client hashed to the load balancing peer
+    pub static ref LOAD_BALANCE_SKIP_COUNT: IntCounter = register_int_counter!("load_balance_skip_count", "count of messages left for the load balancing peer").unwrap();
+    /// messages not answered because the server was busy & the client hadn't retried long enough
+    pub static ref LOAD_SHED_COUNT: IntCounter = register_int_counter!("load_shed_count", "count of messages dropped by load shedding").unwrap();
+    /// DISCOVERs from clients that look like they use a randomized MAC. A rotated MAC starts
+    /// over with a DISCOVER, so the rate estimates how many randomized clients are joining
+    pub static ref RANDOM_MAC_DISCOVER_COUNT: IntCounter = register_int_counter!("random_mac_discover_count", "count of DISCOVERs from clients with a randomized MAC").unwrap();
+    /// packets that failed a sanity check, labeled by `check`: zero_xid, secs, hlen or no_client_id
+    pub static ref SANITY_CHECK_COUNT: IntCounterVec = register_int_counter_vec!(
+        "sanity_check_count",
+        "count of bogus packets caught by sanity checks",
+        &["check"]
+    )
+    .unwrap();
+    /// DDNS updates that weren't sent, labeled by `reason`: coalesced or rate_limited
+    pub static ref DDNS_SKIPPED_COUNT: IntCounterVec = register_int_counter_vec!(
+        "ddns_skipped_count",
+        "count of DDNS updates skipped because the record was just sent or the zone hit its rate limit",
+        &["reason"]
+    )
+    .unwrap();
+    /// messages dropped by loop protection, labeled by `reason`: chaddr, client_id or server_id
+    pub static ref LOOP_DROP_COUNT: IntCounterVec = register_int_counter_vec!(
+        "loop_drop_count",
+        "count of messages dropped because they came from one of our own MACs or addresses",
+        &["reason"]
+    )
+    .unwrap();
+    /// relayed messages dropped by a network's `relays` list, labeled by `reason`: giaddr or remote_id
+    pub static ref RELAY_DENIED_COUNT: IntCounterVec = register_int_counter_vec!(
+        "relay_denied_count",
+        "count of relayed messages from relays not allowed for the network",
+        &["reason"]
+    )
+    .unwrap();
+    /// DECLINEs that were ignored, labeled by `reason`: not_offered or rate_limited
+    pub static ref DECLINE_REJECTED_COUNT: IntCounterVec = register_int_counter_vec!(
+        "decline_rejected_count",
+        "count of DECLINE messages that did not probate an IP",
+        &["reason"]
+    )
+    .unwrap();
+    /// allocations refused because clients from the same OUI were at the network's `oui_limits`
+    pub static ref OUI_LIMITED_COUNT: IntCounter = register_int_counter!("oui_limited_count", "count of allocations refused by a network's OUI lease limit").unwrap();
+    /// leases to clients that also held a lease in another network, labeled by the network's
+    /// `multi_network_leases` policy. With `allow` every renewal counts
+    pub static ref NETWORK_MOVE_COUNT: IntCounterVec = register_int_counter_vec!(
+        "network_move_count",
+        "count of leases to clients holding a lease in another network",
+        &["policy"]
+    )
+    .unwrap();
+    /// leases released because the client leased an address in another network
+    pub static ref NETWORK_MOVE_RELEASED_COUNT: IntCounter = register_int_counter!("network_move_released_count", "count of leases released when their client moved networks").unwrap();
+    /// ip-manager calls made again because storage was busy
+    pub static ref STORAGE_RETRY_COUNT: IntCounter = register_int_counter!("storage_retry_count", "count of lease storage calls retried because storage was busy").unwrap();
+    /// lease changes written to the `--change-stream` sink
+    pub static ref CHANGE_STREAM_SHIPPED_COUNT: IntCounter = register_int_counter!("change_stream_shipped_count", "count of lease changes written to the change stream").unwrap();
+    /// lease changes pruned from history before the change stream shipped them
+    pub static ref CHANGE_STREAM_MISSED_COUNT: IntCounter = register_int_counter!("change_stream_missed_count", "count of lease changes pruned before the change stream shipped them").unwrap();
+    /// external api requests answered with a 429 because the route was busy
+    pub static ref EXTERNAL_API_THROTTLED_COUNT: IntCounterVec = register_int_counter_vec!(
+        "external_api_throttled_count",
+        "count of external api requests refused because the route was busy",
+        &["route"]
+    )
+    .unwrap();
+    /// external api requests answered from a route's response cache
+    pub static ref EXTERNAL_API_CACHE_HIT_COUNT: IntCounterVec = register_int_counter_vec!(
+        "external_api_cache_hit_count",
+        "count of external api requests answered from cache",
+        &["route"]
+    )
+    .unwrap();
+    /// leases database backups written to `--backup-dir`
+    pub static ref BACKUP_COUNT: IntCounter = register_int_counter!("backup_count", "count of leases database backups written").unwrap();
+    /// leases database backups that failed
+    pub static ref BACKUP_FAILURE_COUNT: IntCounter = register_int_counter!("backup_failure_count", "count of leases database backups that failed").unwrap();
+    /// unix time of the last leases database backup
+    pub static ref BACKUP_LAST: IntGauge = register_int_gauge!("backup_last_timestamp", "unix time of the last leases database backup").unwrap();
+    /// expired leases given back to their client within the network's `lease_grace`
+    pub static ref LEASE_GRACE_REGRANT_COUNT: IntCounter = register_int_counter!("lease_grace_regrant_count", "count of expired leases re-granted to their client within lease_grace").unwrap();
+    /// DISCOVERs & REQUESTs evaluated against the `--shadow-config-path` config
+    pub static ref SHADOW_EVAL_COUNT: IntCounter = register_int_counter!("shadow_eval_count", "count of messages evaluated against the shadow config").unwrap();
+    /// messages the shadow config would answer differently, labeled by what differs
+    pub static ref SHADOW_DIFF_COUNT: IntCounterVec = register_int_counter_vec!(
+        "shadow_diff_count",
+        "count of messages the shadow config would answer differently",
+        &["field"]
+    )
+    .unwrap();
+
+    /// options that failed to decode and were kept as raw bytes, labeled by option code
+    pub static ref MALFORMED_OPTION_COUNT: IntCounterVec = register_int_counter_vec!(
+        "malformed_option_count",
+        "count of received options that failed to decode",
+        &["code"]
+    )
+    .unwrap();
+
+    // client class metrics
+
+    /// count of times each configured client class matched, labeled by class name
+    pub static ref CLASS_MATCH_COUNT: IntCounterVec = register_int_counter_vec!(
+        "client_class_match_count",
+        "count of times a client class matched",
+        &["class"]
+    )
+    .unwrap();
+    /// count of responses that included options from a client class, labeled by class name
+    pub static ref CLASS_RESPONSE_COUNT: IntCounterVec = register_int_counter_vec!(
+        "client_class_response_count",
+        "count of responses using client class options",
+        &["class"]
+    )
+    .unwrap();
+    /// identity backend lookups, labeled by result: known, unknown, timeout or error
+    pub static ref IDENTITY_LOOKUP_COUNT: IntCounterVec = register_int_counter_vec!(
+        "identity_lookup_count",
+        "count of identity backend lookups by result",
+        &["result"]
+    )
+    .unwrap();
+    /// RFC 9686 ADDR-REG-INFORM messages, labeled by result: registered or invalid
+    pub static ref ADDR_REG_COUNT: IntCounterVec = register_int_counter_vec!(
+        "v6_addr_reg_count",
+        "count of v6 address registrations by result",
+        &["result"]
+    )
+    .unwrap();
+    /// messages dropped because a plugin was still running when the time budget ran
+    /// out, labeled by plugin
+    pub static ref PLUGIN_BUDGET_EXCEEDED_COUNT: IntCounterVec = register_int_counter_vec!(
+        "plugin_budget_exceeded_count",
+        "count of messages where a plugin ran past the time budget",
+        &["plugin"]
+    )
+    .unwrap();
+}
+
+tokio::task_local! {
+    static UNCOUNTED: ();
+}
+
+/// Run `fut` with [`counted`] false, for messages run through the plugins again that
+/// aren't real traffic, ex. to evaluate them against a shadow config
+pub async fn uncounted<F: std::future::Future>(fut: F) -> F::Output {
+    UNCOUNTED.scope((), fut).await
+}
+
+/// whether the running task's work should be counted, false within [`uncounted`]. Checked
+/// by the counters plugins & allocation bump while handling a message
+pub fn counted() -> bool {
+    UNCOUNTED.try_with(|_| ()).is_err()
+}
+
+/// live counters & the `_total_persistent` counter each is added to
+fn persisted() -> [(&'static IntCounterVec, &'static IntCounterVec); 4] {
+    [
+        (&*RECV_COUNT_VEC, &*RECV_PERSISTENT),
+        (&*SENT_COUNT_VEC, &*SENT_PERSISTENT),
+        (&*V6_RECV_COUNT_VEC, &*V6_RECV_PERSISTENT),
+        (&*V6_SENT_COUNT_VEC, &*V6_SENT_PERSISTENT),
+    ]
+}
+
+/// A persisted count, metric name, label value & count
+pub type PersistedCount = (String, String, u64);
+
+/// Keeps the `_total_persistent` counters in step with the live counters they mirror.
+/// Counts saved by a previous run are added back with [`PersistentStats::restore`],
+/// [`PersistentStats::sync`] then adds whatever the live counters counted since it last
+/// ran. The persistent counters only move when `sync` is called, so they lag the live
+/// ones by up to the checkpoint interval. Only one should exist per process
+#[derive(Debug, Default)]
+pub struct PersistentStats {
+    /// live counts at the last sync, by persistent metric name & label value
+    last: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl PersistentStats {
+    /// add counts saved by a previous run, names that aren't persisted anymore are ignored
+    pub fn restore(&self, saved: impl IntoIterator<Item = PersistedCount>) {
+        for (name, label, count) in saved {
+            if let Some((_, persistent)) = persisted()
+                .into_iter()
+                .find(|(_, persistent)| metric_name(persistent) == name)
+            {
+                persistent.with_label_values(&[&label]).inc_by(count);
+            }
+        }
+    }
+
+    /// add what was counted since the last sync to the persistent counters, returns the
+    /// current value of every persistent count to be saved
+    pub fn sync(&self) -> Vec<PersistedCount> {
+        let mut last = self.last.lock().unwrap();
+        let mut counts = Vec::new();
+        for (live, persistent) in persisted() {
+            let name = metric_name(persistent);
+            for (label, count) in label_counts(live) {
+                let prev = last.insert((name.clone(), label.clone()), count);
+                persistent
+                    .with_label_values(&[&label])
+                    .inc_by(count.saturating_sub(prev.unwrap_or(0)));
+            }
+            counts.extend(
+                label_counts(persistent)
+                    .into_iter()
+                    .map(|(label, count)| (name.clone(), label, count)),
+            );
+        }
+        counts
+    }
+}
+
+fn metric_name(vec: &IntCounterVec) -> String {
+    vec.desc()
+        .first()
+        .map(|desc| desc.fq_name.clone())
+        .unwrap_or_default()
+}
+
+/// the value of each label of a counter vec with a single label
+fn label_counts(vec: &IntCounterVec) -> Vec<(String, u64)> {
+    vec.collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .map(|metric| {
+            let label = metric
+                .get_label()
+                .first()
+                .map(|pair| pair.get_value().to_owned())
+                .unwrap_or_default();
+            (label, metric.get_counter().get_value() as u64)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(counts: &[PersistedCount], name: &str, label: &str) -> Option<u64> {
+        counts
+            .iter()
+            .find(|(n, l, _)| n == name && l == label)
+            .map(|(_, _, count)| *count)
+    }
+
+    #[test]
+    fn test_persistent_stats() {
+        // a label no message type uses, other tests share these counters
+        let label = "persist_test";
+        let stats = PersistentStats::default();
+        stats.restore([
+            (
+                "dhcpv4_sent_total_persistent".to_owned(),
+                label.to_owned(),
+                10,
+            ),
+            ("not_persisted".to_owned(), label.to_owned(), 5),
+        ]);
+        SENT_COUNT_VEC.with_label_values(&[label]).inc_by(3);
+        let counts = stats.sync();
+        assert_eq!(
+            count(&counts, "dhcpv4_sent_total_persistent", label),
+            Some(13)
+        );
+        assert_eq!(count(&counts, "not_persisted", label), None);
+
+        // only what was counted since the last sync is added
+        SENT_COUNT_VEC.with_label_values(&[label]).inc_by(2);
+        let counts = stats.sync();
+        assert_eq!(
+            count(&counts, "dhcpv4_sent_total_persistent", label),
+            Some(15)
+        );
+        let counts = stats.sync();
+        assert_eq!(
+            count(&counts, "dhcpv4_sent_total_persistent", label),
+            Some(15)
+        );
+    }
 }
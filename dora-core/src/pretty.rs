@@ -0,0 +1,244 @@
+//! Human readable rendering of DHCPv4 messages & options for logs and the api.
+//!
+//! Options are printed as `{code} {name}={value}` in code order, ex.
+//! `53 MessageType=Offer, 51 AddressLeaseTime=3600, 55 PRL=[1,3,6,15]`,
+//! instead of the nested `Debug` output of [`dhcproto`].
+use std::fmt;
+
+use dhcproto::v4;
+
+/// `Display` for a set of v4 options
+#[derive(Debug, Clone, Copy)]
+pub struct V4Opts<'a>(pub &'a v4::DhcpOptions);
+
+impl fmt::Display for V4Opts<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut opts = self.0.iter().collect::<Vec<_>>();
+        opts.sort_by_key(|(code, _)| u8::from(**code));
+        for (i, (code, opt)) in opts.into_iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", V4Opt(*code, opt))?;
+        }
+        Ok(())
+    }
+}
+
+/// `Display` for a single v4 option, `{code} {name}={value}`
+#[derive(Debug, Clone, Copy)]
+pub struct V4Opt<'a>(pub v4::OptionCode, pub &'a v4::DhcpOption);
+
+impl fmt::Display for V4Opt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let V4Opt(code, opt) = *self;
+        write!(
+            f,
+            "{} {}={}",
+            u8::from(code),
+            V4OptName(code),
+            V4OptValue(opt)
+        )
+    }
+}
+
+/// `Display` for the name of a v4 option, ex. `AddressLeaseTime`
+#[derive(Debug, Clone, Copy)]
+pub struct V4OptName(pub v4::OptionCode);
+
+impl fmt::Display for V4OptName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            v4::OptionCode::ParameterRequestList => f.write_str("PRL"),
+            v4::OptionCode::Unknown(_) => f.write_str("Unknown"),
+            code => write!(f, "{code:?}"),
+        }
+    }
+}
+
+/// `Display` for the value of a v4 option, ex. `3600` or `[1,3,6,15]`
+#[derive(Debug, Clone, Copy)]
+pub struct V4OptValue<'a>(pub &'a v4::DhcpOption);
+
+impl fmt::Display for V4OptValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_value(self.0, f)
+    }
+}
+
+/// `Display` for a v4 message, the header fields that matter for debugging followed by
+/// the options
+#[derive(Debug, Clone, Copy)]
+pub struct V4Msg<'a>(pub &'a v4::Message);
+
+impl fmt::Display for V4Msg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = self.0;
+        write!(
+            f,
+            "xid={:#010x} ciaddr={} yiaddr={} siaddr={} giaddr={} chaddr=",
+            msg.xid(),
+            msg.ciaddr(),
+            msg.yiaddr(),
+            msg.siaddr(),
+            msg.giaddr()
+        )?;
+        fmt_hex(msg.chaddr(), f)?;
+        write!(f, " opts=[{}]", V4Opts(msg.opts()))
+    }
+}
+
+/// `Display` for raw bytes as colon separated hex, ex. a chaddr or client id
+#[derive(Debug, Clone, Copy)]
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_hex(self.0, f)
+    }
+}
+
+fn fmt_value(opt: &v4::DhcpOption, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    use v4::DhcpOption as O;
+    match opt {
+        O::MessageType(ty) => write!(f, "{ty:?}"),
+        O::ParameterRequestList(codes) => fmt_list(codes.iter().map(|c| u8::from(*c)), f),
+        O::SubnetMask(addr)
+        | O::SwapServer(addr)
+        | O::BroadcastAddr(addr)
+        | O::RouterSolicitationAddr(addr)
+        | O::RequestedIpAddress(addr)
+        | O::ServerIdentifier(addr)
+        | O::SubnetSelection(addr) => write!(f, "{addr}"),
+        O::TimeServer(ips)
+        | O::NameServer(ips)
+        | O::Router(ips)
+        | O::DomainNameServer(ips)
+        | O::LogServer(ips)
+        | O::QuoteServer(ips)
+        | O::LprServer(ips)
+        | O::ImpressServer(ips)
+        | O::ResourceLocationServer(ips)
+        | O::XFontServer(ips)
+        | O::XDisplayManager(ips)
+        | O::NisServers(ips)
+        | O::NtpServers(ips)
+        | O::NetBiosNameServers(ips)
+        | O::NetBiosDatagramDistributionServer(ips) => fmt_list(ips.iter(), f),
+        O::TimeOffset(num) => write!(f, "{num}"),
+        O::DefaultTcpTtl(num) | O::DefaultIpTtl(num) | O::OptionOverload(num) => {
+            write!(f, "{num}")
+        }
+        O::ArpCacheTimeout(num)
+        | O::TcpKeepaliveInterval(num)
+        | O::AddressLeaseTime(num)
+        | O::Renewal(num)
+        | O::Rebinding(num) => write!(f, "{num}"),
+        O::BootFileSize(num)
+        | O::MaxDatagramSize(num)
+        | O::InterfaceMtu(num)
+        | O::MaxMessageSize(num) => write!(f, "{num}"),
+        O::IpForwarding(b)
+        | O::NonLocalSrcRouting(b)
+        | O::AllSubnetsLocal(b)
+        | O::PerformMaskDiscovery(b)
+        | O::MaskSupplier(b)
+        | O::PerformRouterDiscovery(b)
+        | O::EthernetEncapsulation(b)
+        | O::TcpKeepaliveGarbage(b) => write!(f, "{b}"),
+        O::Hostname(s)
+        | O::MeritDumpFile(s)
+        | O::DomainName(s)
+        | O::ExtensionsPath(s)
+        | O::NisDomain(s)
+        | O::RootPath(s)
+        | O::NetBiosScope(s)
+        | O::Message(s) => write!(f, "{s:?}"),
+        // vendor class is usually printable, ex. "MSFT 5.0"
+        O::ClassIdentifier(id) => match std::str::from_utf8(id) {
+            Ok(s) if !s.chars().any(char::is_control) => write!(f, "{s:?}"),
+            _ => fmt_hex(id, f),
+        },
+        O::ClientIdentifier(id) => fmt_hex(id, f),
+        O::Unknown(opt) => fmt_hex(opt.data(), f),
+        opt => write!(f, "{opt:?}"),
+    }
+}
+
+fn fmt_list<I>(items: I, f: &mut fmt::Formatter<'_>) -> fmt::Result
+where
+    I: IntoIterator,
+    I::Item: fmt::Display,
+{
+    f.write_str("[")?;
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            f.write_str(",")?;
+        }
+        write!(f, "{item}")?;
+    }
+    f.write_str("]")
+}
+
+/// colon separated hex, ex. `01:aa:bb`
+fn fmt_hex(bytes: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for (i, b) in bytes.iter().enumerate() {
+        if i > 0 {
+            f.write_str(":")?;
+        }
+        write!(f, "{b:02x}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_pretty_opts() {
+        let mut msg = v4::Message::new(
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            &[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff],
+        );
+        msg.set_xid(0x1234);
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Discover));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::ParameterRequestList(vec![
+                v4::OptionCode::SubnetMask,
+                v4::OptionCode::Router,
+                v4::OptionCode::DomainNameServer,
+                v4::OptionCode::DomainName,
+            ]));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::AddressLeaseTime(3600));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::Hostname("printer-3".into()));
+
+        assert_eq!(
+            V4Opts(msg.opts()).to_string(),
+            r#"12 Hostname="printer-3", 51 AddressLeaseTime=3600, 53 MessageType=Discover, 55 PRL=[1,3,6,15]"#
+        );
+        assert!(V4Msg(&msg)
+            .to_string()
+            .starts_with("xid=0x00001234 ciaddr=0.0.0.0"));
+        assert!(V4Msg(&msg)
+            .to_string()
+            .contains("chaddr=aa:bb:cc:dd:ee:ff opts=[12 Hostname"));
+
+        let unknown = v4::DhcpOption::Unknown(v4::UnknownOption::new(
+            v4::OptionCode::Unknown(224),
+            vec![1, 2],
+        ));
+        assert_eq!(
+            V4Opt(v4::OptionCode::Unknown(224), &unknown).to_string(),
+            "224 Unknown=01:02"
+        );
+    }
+}
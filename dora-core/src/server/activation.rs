@@ -0,0 +1,119 @@
+//! # Socket activation
+//!
+//! dora can take its DHCP sockets from a systemd socket unit (`sd_listen_fds(3)`) instead
+//! of binding them. The socket unit keeps them open while the service restarts, so during
+//! an upgrade packets that arrive as the old process drains & the new one starts wait in
+//! the socket's receive buffer rather than being dropped. A UDP socket passed in
+//! `LISTEN_FDS` is used in place of binding `v4_addr` or `v6_addr` when it's bound to the
+//! same address.
+use std::{
+    env, io,
+    net::{SocketAddr, UdpSocket},
+    os::fd::{FromRawFd, IntoRawFd, RawFd},
+    sync::OnceLock,
+};
+
+use tracing::warn;
+
+/// the first fd systemd passes, `SD_LISTEN_FDS_START`
+const LISTEN_FDS_START: RawFd = 3;
+
+/// the sockets passed to this process & the address each is bound to
+static INHERITED: OnceLock<Vec<(SocketAddr, UdpSocket)>> = OnceLock::new();
+
+/// Take the sockets passed to this process, returning the address of each. Call it
+/// before any other thread is started, `LISTEN_PID`, `LISTEN_FDS` & `LISTEN_FDNAMES`
+/// are removed from the environment so nothing dora starts takes them too. Later calls
+/// return the same addresses
+pub fn init() -> Vec<SocketAddr> {
+    INHERITED
+        .get_or_init(|| {
+            let fds = listen_fds();
+            for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+                env::remove_var(var);
+            }
+            fds.filter_map(udp_socket).collect()
+        })
+        .iter()
+        .map(|(addr, _)| *addr)
+        .collect()
+}
+
+/// the fds passed to this process, none if `LISTEN_PID` is another process's
+fn listen_fds() -> std::ops::Range<RawFd> {
+    let ours = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .filter(|_| ours)
+        .unwrap_or(0);
+    LISTEN_FDS_START..LISTEN_FDS_START + count
+}
+
+fn udp_socket(fd: RawFd) -> Option<(SocketAddr, UdpSocket)> {
+    // SAFETY: the fd was passed to this process for it to own & `init` only takes each
+    // one once
+    let socket = unsafe { socket2::Socket::from_raw_fd(fd) };
+    match (
+        socket.r#type(),
+        socket.local_addr().map(|addr| addr.as_socket()),
+    ) {
+        (Ok(socket2::Type::DGRAM), Ok(Some(addr))) => {
+            if let Err(err) = socket.set_cloexec(true) {
+                warn!(fd, ?err, "failed to set close-on-exec on inherited socket");
+            }
+            Some((addr, socket.into()))
+        }
+        _ => {
+            warn!(fd, "ignoring inherited fd, it's not a bound UDP socket");
+            // left open, it's not ours to close
+            let _ = socket.into_raw_fd();
+            None
+        }
+    }
+}
+
+/// was a socket bound to `addr` passed to this process?
+pub fn is_inherited(addr: SocketAddr) -> bool {
+    INHERITED
+        .get()
+        .is_some_and(|sockets| sockets.iter().any(|(bound, _)| *bound == addr))
+}
+
+/// Copies of the inherited sockets bound to `addr`, empty if there are none. The
+/// originals stay open for the life of the process, so a server can be stopped &
+/// started again, ex. for a config switch, without the socket ever closing
+pub fn udp_sockets(addr: SocketAddr) -> io::Result<Vec<UdpSocket>> {
+    INHERITED
+        .get()
+        .into_iter()
+        .flatten()
+        .filter(|(bound, _)| *bound == addr)
+        .map(|(_, socket)| socket.try_clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_udp_socket() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let (bound, socket) = udp_socket(udp.into_raw_fd()).unwrap();
+        assert_eq!(bound, addr);
+        assert_eq!(socket.local_addr().unwrap(), addr);
+
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = tcp.into_raw_fd();
+        assert!(udp_socket(fd).is_none());
+        // left open, close it
+        drop(unsafe { TcpListener::from_raw_fd(fd) });
+    }
+}
@@ -1,21 +1,26 @@
 //! context of current server message
 use chrono::{DateTime, Utc};
-use dhcproto::{v4, v6, Decodable, Decoder, Encodable};
+use dhcproto::{v4, v6, Decodable, Decoder, Encodable, Encoder};
 use pnet::ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
-use tracing::{error, trace};
+use tracing::{debug, error, trace};
 use unix_udp_sock::RecvMeta;
 
 use std::{
     fmt,
+    future::Future,
     io::{self, Error, ErrorKind},
-    net::{Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use crate::{
     metrics::{self, RECV_TYPE_COUNT, SENT_TYPE_COUNT, V6_RECV_TYPE_COUNT, V6_SENT_TYPE_COUNT},
-    server::{msg::SerialMsg, typemap::TypeMap, State},
+    server::{
+        msg::{SerialMsg, ENCODE_POOL},
+        typemap::TypeMap,
+        State,
+    },
 };
 
 /// Context is what will be passed to the [handler] traits and mutated by
@@ -33,6 +38,8 @@ pub struct MsgContext<T> {
     dst_addr: Option<SocketAddr>,
     /// time this context was created
     time: DateTime<Utc>,
+    /// when plugins must be done for a response to still be sent
+    budget: Budget,
     /// decoded from msg
     msg: T,
     /// decoded response msg  -- **CAREFUL** do not call `take()` on this before
@@ -52,6 +59,8 @@ pub struct MsgContext<T> {
     interface: Option<IpNetwork>,
     /// global unicast address
     global: Option<IpNetwork>,
+    /// what happened to the message, set by the server before post-response runs
+    outcome: Option<Outcome>,
 }
 
 impl<T: fmt::Debug> fmt::Debug for MsgContext<T> {
@@ -60,11 +69,13 @@ impl<T: fmt::Debug> fmt::Debug for MsgContext<T> {
             .field("src_addr", &self.src_addr)
             .field("dst_addr", &self.dst_addr)
             .field("time", &self.time)
+            .field("budget", &self.budget)
             .field("id", &self.id)
             .field("is_live", &self.is_live)
             .field("msg", &self.msg)
             .field("resp_msg", &self.resp_msg)
             .field("interface", &self.interface)
+            .field("outcome", &self.outcome)
             .finish()
     }
 }
@@ -74,6 +85,8 @@ impl<T> Drop for MsgContext<T> {
         if self.is_live {
             self.state.dec_live_msgs();
         }
+        // plugin locals are dropped, the map's allocation goes to the next message
+        std::mem::take(&mut self.type_map).recycle();
     }
 }
 
@@ -115,6 +128,23 @@ impl<T> MsgContext<T> {
         self.time
     }
 
+    /// Set the time by which plugins must be done. The server sets this from its
+    /// timeout before running the plugins
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.budget = Budget::until(deadline);
+    }
+
+    /// The time budget shared by the plugins handling this message. It's `Copy`, so it
+    /// can bound an await that also borrows the `MsgContext`
+    pub fn budget(&self) -> Budget {
+        self.budget
+    }
+
+    /// Time left before the deadline, `None` if there is no deadline
+    pub fn remaining_budget(&self) -> Option<Duration> {
+        self.budget.remaining()
+    }
+
     /// Store a value in the current `MsgContext` based on a type.
     /// This value will be available across any step in the lifecycle of a
     /// request
@@ -182,6 +212,24 @@ impl<T> MsgContext<T> {
         self.dst_addr = Some(addr);
     }
 
+    /// The server state, ex. to see how busy the server is
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// What happened to the message: answered, dropped or failed. Set by the server
+    /// once plugins are done, so it's only available to the [`PostResponse`] handler
+    ///
+    /// [`PostResponse`]: crate::handler::PostResponse
+    pub fn outcome(&self) -> Option<&Outcome> {
+        self.outcome.as_ref()
+    }
+
+    /// record what happened to the message
+    pub(crate) fn set_outcome(&mut self, outcome: Outcome) {
+        self.outcome = Some(outcome);
+    }
+
     /// Decrement the `state.live_msgs` counter and mark this as not live
     /// This gets done before passing the `MsgContext` to the postresponse
     /// plugins.
@@ -193,13 +241,35 @@ impl<T> MsgContext<T> {
     }
 }
 
-impl<T: Encodable + Decodable> MsgContext<T> {
+impl<T: Clone> MsgContext<T> {
+    /// A copy of the received message to run through plugins again, ex. against
+    /// another config. It has the same id, addresses & interface, but no response,
+    /// locals, outcome or deadline, and doesn't count towards `state.live_msgs`
+    pub fn replay(&self) -> Self {
+        Self {
+            msg_buf: self.msg_buf.clone(),
+            src_addr: self.src_addr,
+            meta: self.meta,
+            dst_addr: None,
+            time: self.time,
+            budget: Budget::default(),
+            msg: self.msg.clone(),
+            type_map: TypeMap::new(),
+            resp_msg: None,
+            id: self.id,
+            state: Arc::clone(&self.state),
+            is_live: false,
+            interface: self.interface,
+            global: self.global,
+            outcome: None,
+        }
+    }
+}
+
+impl<T: Encodable + DecodeMsg> MsgContext<T> {
     /// Create a `MsgContext` with state
     pub fn new(msg_buf: SerialMsg, meta: RecvMeta, state: Arc<State>) -> io::Result<Self> {
-        let msg = {
-            let mut decoder = Decoder::new(msg_buf.bytes());
-            T::decode(&mut decoder).map_err(|op| io::Error::new(io::ErrorKind::InvalidData, op))?
-        };
+        let msg = T::decode_msg(msg_buf.bytes())?;
 
         Ok(Self {
             msg_buf,
@@ -207,17 +277,21 @@ impl<T: Encodable + Decodable> MsgContext<T> {
             meta,
             dst_addr: None,
             time: Utc::now(),
+            budget: Budget::default(),
             msg,
-            type_map: TypeMap::new(),
+            type_map: TypeMap::pooled(),
             resp_msg: None,
             id: state.inc_id(),
             state,
             is_live: true,
             interface: None,
             global: None,
+            outcome: None,
         })
     }
+}
 
+impl<T: Encodable + Decodable> MsgContext<T> {
     /// Decode the currently held binary data in `resp_msg` using [`Decoder`] into a message.
     /// A decoded DHCP query.
     ///
@@ -273,7 +347,406 @@ impl<T: Encodable + Decodable> MsgContext<T> {
     }
 }
 
+/// Decoding of messages as they come off the wire
+pub trait DecodeMsg: Decodable {
+    /// decode a received message
+    fn decode_msg(buf: &[u8]) -> io::Result<Self> {
+        let mut decoder = Decoder::new(buf);
+        Self::decode(&mut decoder).map_err(|op| io::Error::new(io::ErrorKind::InvalidData, op))
+    }
+}
+
+impl DecodeMsg for v6::Message {}
+
+impl DecodeMsg for v4::Message {
+    /// Falls back to [`decode_v4_tolerant`] when strict decoding fails, so a single
+    /// malformed option (usually opt 82 from a misbehaving relay) doesn't drop the message
+    fn decode_msg(buf: &[u8]) -> io::Result<Self> {
+        let mut decoder = Decoder::new(buf);
+        v4::Message::decode(&mut decoder).or_else(|err| {
+            decode_v4_tolerant(buf).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, err))
+        })
+    }
+}
+
+/// length of the fixed v4 header, including the magic cookie
+const V4_HEADER_LEN: usize = 240;
+
+/// Decode a v4 message one option at a time. Options that fail to decode are kept as
+/// [`v4::DhcpOption::Unknown`] with their raw bytes, so they are still echoed back
+/// but ignored by anything expecting the parsed type, and are counted in
+/// `malformed_option_count`. Returns `None` if the header itself doesn't decode.
+pub fn decode_v4_tolerant(buf: &[u8]) -> Option<v4::Message> {
+    let mut header = buf.get(..V4_HEADER_LEN)?.to_vec();
+    header.push(u8::from(v4::OptionCode::End));
+    let mut msg = v4::Message::decode(&mut Decoder::new(&header)).ok()?;
+
+    for (code, data) in concat_opts(&buf[V4_HEADER_LEN..], malformed_opt) {
+        match decode_opt(code, &data) {
+            Ok(opt) => {
+                msg.opts_mut().insert(opt);
+            }
+            Err(err) => {
+                debug!(?err, code, "keeping malformed option as unknown");
+                malformed_opt(code);
+                msg.opts_mut()
+                    .insert(v4::DhcpOption::Unknown(v4::UnknownOption::new(
+                        code.into(),
+                        data,
+                    )));
+            }
+        }
+    }
+    Some(msg)
+}
+
+/// A v4 message broken down for troubleshooting, see [`inspect_v4`]
+#[derive(Debug)]
+pub struct V4Inspection {
+    /// the fixed header, with no options
+    pub header: v4::Message,
+    /// `(code, data, decoded)` of each option in the order received, long options joined
+    pub opts: Vec<(u8, Vec<u8>, io::Result<v4::DhcpOption>)>,
+    /// an option cut off by the end of the packet, it & anything after it are dropped
+    pub truncated: Option<u8>,
+}
+
+/// Split a v4 message the way [`decode_v4_tolerant`] does, keeping each option's raw
+/// data & why it failed to decode. Nothing is counted in the metrics. Returns `None`
+/// if the header doesn't decode
+pub fn inspect_v4(buf: &[u8]) -> Option<V4Inspection> {
+    let mut header = buf.get(..V4_HEADER_LEN)?.to_vec();
+    header.push(u8::from(v4::OptionCode::End));
+    let header = v4::Message::decode(&mut Decoder::new(&header)).ok()?;
+    let mut truncated = None;
+    let opts = concat_opts(&buf[V4_HEADER_LEN..], |code| truncated = Some(code))
+        .into_iter()
+        .map(|(code, data)| {
+            let opt = decode_opt(code, &data);
+            (code, data, opt)
+        })
+        .collect();
+    Some(V4Inspection {
+        header,
+        opts,
+        truncated,
+    })
+}
+
+/// Raw `(code, data)` of each option in an options section. An option that appears
+/// more than once is a long option split by the sender, its data is concatenated in
+/// the order received (RFC 3396). `truncated` is called with the code of an option
+/// cut off by the end of the section.
+fn concat_opts(mut opts: &[u8], mut truncated: impl FnMut(u8)) -> Vec<(u8, Vec<u8>)> {
+    let mut raw: Vec<(u8, Vec<u8>)> = Vec::new();
+    while let Some((&code, rest)) = opts.split_first() {
+        match v4::OptionCode::from(code) {
+            v4::OptionCode::Pad => {
+                opts = rest;
+                continue;
+            }
+            v4::OptionCode::End => break,
+            _ => {}
+        }
+        // truncated options can't be kept, drop them and whatever follows
+        let Some((&len, rest)) = rest.split_first() else {
+            truncated(code);
+            break;
+        };
+        let len = len as usize;
+        let Some(data) = rest.get(..len) else {
+            truncated(code);
+            break;
+        };
+        match raw.iter_mut().find(|(c, _)| *c == code) {
+            Some((_, buf)) => buf.extend_from_slice(data),
+            None => raw.push((code, data.to_vec())),
+        }
+        opts = &rest[len..];
+    }
+    raw
+}
+
+/// decode a single option from its (possibly concatenated) data
+fn decode_opt(code: u8, data: &[u8]) -> io::Result<v4::DhcpOption> {
+    let to_io = |err| io::Error::new(ErrorKind::InvalidData, err);
+    if data.len() <= u8::MAX as usize {
+        let buf = [&[code, data.len() as u8][..], data].concat();
+        return v4::DhcpOption::decode(&mut Decoder::new(&buf)).map_err(to_io);
+    }
+    // only the options decoder joins long options back together
+    let mut buf = Vec::with_capacity(data.len() + data.len() / 255 * 2 + 3);
+    v4::encode_long_opt_bytes(code.into(), data, &mut Encoder::new(&mut buf)).map_err(to_io)?;
+    buf.push(u8::from(v4::OptionCode::End));
+    v4::DhcpOptions::decode(&mut Decoder::new(&buf))
+        .map_err(to_io)?
+        .get(code.into())
+        .cloned()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "failed to decode long option"))
+}
+
+fn malformed_opt(code: u8) {
+    metrics::MALFORMED_OPTION_COUNT
+        .with_label_values(&[&code.to_string()])
+        .inc();
+}
+
 // v4 specific functions
+/// Overrides where a response is sent, instead of the RFC 2131 rules in
+/// [`MsgContext::resp_addr`]. Plugins set this with `set_local`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ReplyTo {
+    /// unicast to `ciaddr` on the client port. If `ciaddr` is zero the default rules apply
+    Ciaddr,
+    /// always broadcast on the client port
+    Broadcast,
+    /// send to a helper address on the server port, as if it were the relay
+    Helper(Ipv4Addr),
+}
+
+/// Encodes v4 response options in a fixed order instead of the encoder's default, for
+/// clients that expect e.g. opt 53 first. Codes in the list go first, in list order, then
+/// the rest ascending by code. Plugins set this with `set_local`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionOrder(Arc<[v4::OptionCode]>);
+
+impl OptionOrder {
+    /// `first` is encoded before any other options, an empty list only sorts by code
+    pub fn new<I: IntoIterator<Item = v4::OptionCode>>(first: I) -> Self {
+        Self(first.into_iter().collect())
+    }
+
+    /// options in the order they will be encoded
+    pub fn sort<'a>(&self, opts: &'a v4::DhcpOptions) -> Vec<&'a v4::DhcpOption> {
+        let mut sorted = opts
+            .iter()
+            .filter(|(code, _)| !matches!(code, v4::OptionCode::Pad | v4::OptionCode::End))
+            .map(|(code, opt)| {
+                let pos = self
+                    .0
+                    .iter()
+                    .position(|c| c == code)
+                    .unwrap_or(self.0.len());
+                ((pos, u8::from(*code)), opt)
+            })
+            .collect::<Vec<_>>();
+        sorted.sort_unstable_by_key(|(key, _)| *key);
+        sorted.into_iter().map(|(_, opt)| opt).collect()
+    }
+
+    /// encode `msg`, the header is encoded as usual & the options are replaced
+    pub fn encode(&self, msg: &v4::Message) -> io::Result<Vec<u8>> {
+        let to_io = |err| io::Error::new(ErrorKind::InvalidData, err);
+        let mut buf = ENCODE_POOL.take();
+        msg.encode(&mut Encoder::new(&mut buf)).map_err(to_io)?;
+        buf.truncate(V4_HEADER_LEN);
+        for opt in self.sort(msg.opts()) {
+            match opt {
+                // raw data (config blobs, malformed options echoed back) can be
+                // longer than 255 bytes, split it into consecutive options (RFC 3396)
+                v4::DhcpOption::Unknown(opt) if opt.data().len() > u8::MAX as usize => {
+                    v4::encode_long_opt_bytes(opt.code(), opt.data(), &mut Encoder::new(&mut buf))
+                        .map_err(to_io)?
+                }
+                opt => buf.extend(opt.to_vec().map_err(to_io)?),
+            }
+        }
+        buf.push(u8::from(v4::OptionCode::End));
+        Ok(buf)
+    }
+}
+
+/// Changes made to a v4 response once every plugin has run, so workarounds for broken
+/// clients can live in config. Plugins set this with `set_local`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RespTransforms {
+    /// options set in the response whether or not the client asked for them, replacing
+    /// any a plugin set
+    pub insert: Vec<v4::DhcpOption>,
+    /// options removed from the response
+    pub remove: Vec<v4::OptionCode>,
+    /// upper bound for the lease time (opt 51), T1 & T2 are lowered to fit. The lease
+    /// is still stored with its full length
+    pub max_lease: Option<Duration>,
+    /// set the broadcast flag, so the response is broadcast by us or the relay
+    pub broadcast: bool,
+}
+
+impl RespTransforms {
+    /// apply the transforms to `resp`
+    pub fn apply(&self, resp: &mut v4::Message) {
+        use v4::{DhcpOption as O, OptionCode as C};
+
+        for opt in &self.insert {
+            resp.opts_mut().insert(opt.clone());
+        }
+        for code in &self.remove {
+            resp.opts_mut().remove(*code);
+        }
+        if let Some(max) = self.max_lease.map(whole_seconds) {
+            let lease = match resp.opts().get(C::AddressLeaseTime) {
+                Some(O::AddressLeaseTime(lease)) => Some(*lease),
+                _ => None,
+            };
+            if matches!(lease, Some(lease) if lease > max) {
+                resp.opts_mut().insert(O::AddressLeaseTime(max));
+                // default T1 & T2 from RFC 2131 4.4.5
+                if let Some(O::Renewal(t1)) = resp.opts().get(C::Renewal) {
+                    let t1 = (*t1).min(max / 2);
+                    resp.opts_mut().insert(O::Renewal(t1));
+                }
+                if let Some(O::Rebinding(t2)) = resp.opts().get(C::Rebinding) {
+                    let t2 = (*t2).min((max as u64 * 7 / 8) as u32);
+                    resp.opts_mut().insert(O::Rebinding(t2));
+                }
+            }
+        }
+        if self.broadcast {
+            resp.set_flags(resp.flags().set_broadcast());
+        }
+    }
+}
+
+/// The deadline plugins share, so a slow lookup in one doesn't leave no time for the
+/// rest or for sending the response. Get it with [`MsgContext::budget`]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Budget {
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    /// a budget that runs out at `deadline`
+    pub fn until(deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+        }
+    }
+
+    /// the deadline, if any
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// time left, zero once the deadline has passed. `None` if there is no deadline
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// whether the deadline has passed
+    pub fn is_exceeded(&self) -> bool {
+        self.remaining() == Some(Duration::ZERO)
+    }
+
+    /// bound `fut` by the remaining budget, it errors with `Elapsed` if it isn't done
+    /// in time
+    pub fn bound<F: Future>(&self, fut: F) -> tokio::time::Timeout<F> {
+        tokio::time::timeout(self.remaining().unwrap_or(Duration::MAX), fut)
+    }
+
+    /// bound `fut` by the remaining budget less `reserve`, leaving that much for the work
+    /// that has to happen after it, ex. sending the response
+    pub fn bound_reserving<F: Future>(&self, reserve: Duration, fut: F) -> tokio::time::Timeout<F> {
+        let budget = self
+            .remaining()
+            .map_or(Duration::MAX, |left| left.saturating_sub(reserve));
+        tokio::time::timeout(budget, fut)
+    }
+
+    /// bound `fut` by the remaining budget or `max`, whichever is shorter
+    pub fn bound_max<F: Future>(&self, max: Duration, fut: F) -> tokio::time::Timeout<F> {
+        let budget = self.remaining().map_or(max, |left| left.min(max));
+        tokio::time::timeout(budget, fut)
+    }
+}
+
+/// What the server did with a message, see [`MsgContext::outcome`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// a response was sent, or logged if `--dry-run` is on
+    Answered(Answered),
+    /// no response was sent
+    Dropped {
+        /// why
+        reason: DropReason,
+    },
+    /// a plugin returned an error, or the response couldn't be encoded or sent
+    Error {
+        /// the plugin that failed, `None` if it happened after the plugins ran
+        plugin: Option<&'static str>,
+        /// the error, formatted
+        error: String,
+    },
+}
+
+/// Why no response was sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// the plugin returned `Action::NoResponse`
+    Plugin(&'static str),
+    /// the plugin ran past the time budget
+    Budget(&'static str),
+    /// the plugins didn't finish before the server timeout
+    Timeout,
+    /// the plugins finished without setting a response
+    NoResponse,
+}
+
+/// The response a client was sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answered {
+    /// message type of the response
+    pub msg_type: RespType,
+    /// yiaddr for v4, the first IA address for v6. `None` if no address was given
+    pub yiaddr: Option<IpAddr>,
+    /// lease time for v4 (opt 51), valid lifetime of the address for v6
+    pub lease: Option<Duration>,
+}
+
+/// The message type of a v4 or v6 response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RespType {
+    /// v4 opt 53, `None` for BOOTP replies
+    V4(Option<v4::MessageType>),
+    /// v6 message type
+    V6(v6::MessageType),
+}
+
+impl Answered {
+    /// what `resp` gives the client
+    pub fn v4(resp: &v4::Message) -> Self {
+        let lease = match resp.opts().get(v4::OptionCode::AddressLeaseTime) {
+            Some(v4::DhcpOption::AddressLeaseTime(secs)) => Some(Duration::from_secs(*secs as u64)),
+            _ => None,
+        };
+        Self {
+            msg_type: RespType::V4(resp.opts().msg_type()),
+            yiaddr: Some(resp.yiaddr())
+                .filter(|ip| !ip.is_unspecified())
+                .map(IpAddr::V4),
+            lease,
+        }
+    }
+
+    /// what `resp` gives the client, the address is the first IA address found at the
+    /// top level or in an IA_NA
+    pub fn v6(resp: &v6::Message) -> Self {
+        let ia = resp.opts().iter().find_map(|opt| match opt {
+            v6::DhcpOption::IAAddr(ia) => Some(ia),
+            v6::DhcpOption::IANA(iana) => iana.opts.iter().find_map(|opt| match opt {
+                v6::DhcpOption::IAAddr(ia) => Some(ia),
+                _ => None,
+            }),
+            _ => None,
+        });
+        Self {
+            msg_type: RespType::V6(resp.msg_type()),
+            yiaddr: ia.map(|ia| IpAddr::V6(ia.addr)),
+            lease: ia.map(|ia| Duration::from_secs(ia.valid_life as u64)),
+        }
+    }
+}
+
 impl MsgContext<v4::Message> {
     /// get the interface for the message. this should always be set
     pub fn interface(&self) -> Option<Ipv4Network> {
@@ -305,6 +778,8 @@ impl MsgContext<v4::Message> {
     //
     // 5. In all cases, when 'giaddr' is zero, the server broadcasts any NAK
     // messages to 0xffffffff.
+    //
+    // If a [`ReplyTo`] local is set, it takes precedence over the rules above.
     pub fn resp_addr(
         &mut self,
         default_port: bool,
@@ -315,13 +790,16 @@ impl MsgContext<v4::Message> {
         let giaddr = req.giaddr();
         let ciaddr = req.ciaddr();
 
+        // the response flag can be forced on by `RespTransforms`
         let (giaddr_zero, ciaddr_zero, broadcast) = (
             req.giaddr().is_unspecified(),
             req.ciaddr().is_unspecified(),
-            req.flags().broadcast(),
+            req.flags().broadcast()
+                || matches!(self.resp_msg(), Some(resp) if resp.flags().broadcast()),
         );
         //
         let yiaddr = self.resp_msg().map(|msg| msg.yiaddr());
+        let reply_to = self.get_local::<ReplyTo>().copied();
         // TODO: set siaddr (dnsmasq does this)? ciaddr?
 
         if !default_port {
@@ -330,6 +808,18 @@ impl MsgContext<v4::Message> {
             // back to the source ip:port as unicast.
             // This is useful for testing
             self.msg_buf().addr()
+        } else if let Some(ReplyTo::Helper(helper)) = reply_to {
+            trace!(?helper, "responding using configured helper address");
+            if !giaddr_zero {
+                self.resp_msg.as_mut().map(|resp| resp.set_giaddr(giaddr));
+            }
+            (helper, v4::SERVER_PORT).into()
+        } else if let Some(ReplyTo::Broadcast) = reply_to {
+            trace!("configured to always broadcast");
+            (Ipv4Addr::BROADCAST, v4::CLIENT_PORT).into()
+        } else if matches!(reply_to, Some(ReplyTo::Ciaddr)) && !ciaddr_zero {
+            trace!("configured to unicast to ciaddr");
+            (ciaddr, v4::CLIENT_PORT).into()
         } else if !giaddr_zero {
             // relay situation: giaddr nonzero
             // use giaddr
@@ -680,22 +1170,27 @@ impl MsgContext<v4::Message> {
     }
     /// Populate the opts with lease times
     /// looks in `decoded_msg` for `DhcpOption::ParameterRequestList` and provides any options
-    /// in `decoded_resp_msg` that match both in `opts` and in the param req list
+    /// in `decoded_resp_msg` that match both in `opts` and in the param req list.
+    /// `renew` & `rebind` (opts 58 & 59) are only added if set
     pub fn populate_opts_lease(
         &mut self,
         param_opts: &v4::DhcpOptions,
         lease: Duration,
-        renew: Duration,
-        rebind: Duration,
+        renew: Option<Duration>,
+        rebind: Option<Duration>,
     ) -> Option<()> {
         self.populate_opts(param_opts)?; // add time
         let resp = self.resp_msg.as_mut()?;
         resp.opts_mut()
             .insert(v4::DhcpOption::AddressLeaseTime(whole_seconds(lease)));
-        resp.opts_mut()
-            .insert(v4::DhcpOption::Renewal(whole_seconds(renew)));
-        resp.opts_mut()
-            .insert(v4::DhcpOption::Rebinding(whole_seconds(rebind)));
+        if let Some(renew) = renew {
+            resp.opts_mut()
+                .insert(v4::DhcpOption::Renewal(whole_seconds(renew)));
+        }
+        if let Some(rebind) = rebind {
+            resp.opts_mut()
+                .insert(v4::DhcpOption::Rebinding(whole_seconds(rebind)));
+        }
         Some(())
     }
 }
@@ -889,6 +1384,44 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_budget() -> anyhow::Result<()> {
+        let (msg, addr, state) = blank_msg()?;
+        let meta = RecvMeta {
+            addr,
+            ..RecvMeta::default()
+        };
+        let mut ctx = MsgContext::<v4::Message>::new(
+            SerialMsg::new(Bytes::from(msg.to_vec()?), addr),
+            meta,
+            state,
+        )?;
+        // no deadline, nothing is bounded
+        assert_eq!(ctx.remaining_budget(), None);
+        assert!(!ctx.budget().is_exceeded());
+        assert_eq!(ctx.budget().bound(async { 1 }).await?, 1);
+
+        ctx.set_deadline(Instant::now() + Duration::from_secs(60));
+        let left = ctx.remaining_budget().unwrap();
+        assert!(left > Duration::from_secs(50) && left <= Duration::from_secs(60));
+        // a shorter max wins
+        let budget = ctx.budget();
+        assert!(budget
+            .bound_max(Duration::from_millis(10), std::future::pending::<()>())
+            .await
+            .is_err());
+
+        ctx.set_deadline(Instant::now());
+        assert_eq!(ctx.remaining_budget(), Some(Duration::ZERO));
+        assert!(ctx.budget().is_exceeded());
+        assert!(ctx
+            .budget()
+            .bound(std::future::pending::<()>())
+            .await
+            .is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_subnet_subnet_selection() -> anyhow::Result<()> {
         let (mut msg, addr, state) = blank_msg()?;
@@ -979,8 +1512,8 @@ mod tests {
         ctx.populate_opts_lease(
             &opts,
             Duration::from_secs(3600),
-            Duration::from_secs(3600 / 2),
-            Duration::from_secs(3600 - (3600 * 7 / 8)),
+            Some(Duration::from_secs(3600 / 2)),
+            Some(Duration::from_secs(3600 - (3600 * 7 / 8))),
         );
         // expect Router to be avail in ctx
         assert_opt(&ctx, v4::DhcpOption::Router(vec![[1, 2, 3, 4].into()]));
@@ -988,6 +1521,20 @@ mod tests {
         assert_opt(&ctx, v4::DhcpOption::Renewal(3600 / 2));
         assert_opt(&ctx, v4::DhcpOption::Rebinding(3600 - (3600 * 7 / 8)));
 
+        // T1 & T2 that aren't configured are left out
+        ctx.resp_msg = Some(v4::Message::new(
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            &[1, 2, 3, 4, 5, 6],
+        ));
+        ctx.populate_opts_lease(&opts, Duration::from_secs(3600), None, None);
+        assert_opt(&ctx, v4::DhcpOption::AddressLeaseTime(3600));
+        let resp = ctx.resp_msg().unwrap();
+        assert!(resp.opts().get(v4::OptionCode::Renewal).is_none());
+        assert!(resp.opts().get(v4::OptionCode::Rebinding).is_none());
+
         Ok(())
     }
 
@@ -1028,4 +1575,293 @@ mod tests {
         assert_opt(&ctx, v4::DhcpOption::RelayAgentInformation(backup));
         Ok(())
     }
+
+    /// byte-for-byte encoding of the options section, some old clients depend on it
+    #[test]
+    fn test_option_order() -> anyhow::Result<()> {
+        use v4::{DhcpOption as O, OptionCode as C};
+
+        let opts = [
+            O::Router(vec![[192, 168, 0, 1].into()]),
+            O::DomainNameServer(vec![[8, 8, 8, 8].into()]),
+            O::AddressLeaseTime(3600),
+            O::SubnetMask([255, 255, 255, 0].into()),
+            O::ServerIdentifier([192, 168, 0, 1].into()),
+            O::MessageType(v4::MessageType::Ack),
+        ];
+        #[rustfmt::skip]
+        let cases: [(&[C], &[u8]); 2] = [
+            (
+                &[C::MessageType, C::ServerIdentifier, C::AddressLeaseTime],
+                &[
+                    53, 1, 5,
+                    54, 4, 192, 168, 0, 1,
+                    51, 4, 0, 0, 0x0e, 0x10,
+                    1, 4, 255, 255, 255, 0,
+                    3, 4, 192, 168, 0, 1,
+                    6, 4, 8, 8, 8, 8,
+                    255,
+                ],
+            ),
+            (
+                &[],
+                &[
+                    1, 4, 255, 255, 255, 0,
+                    3, 4, 192, 168, 0, 1,
+                    6, 4, 8, 8, 8, 8,
+                    51, 4, 0, 0, 0x0e, 0x10,
+                    53, 1, 5,
+                    54, 4, 192, 168, 0, 1,
+                    255,
+                ],
+            ),
+        ];
+        for (first, expected) in cases {
+            let order = OptionOrder::new(first.iter().copied());
+            // insertion order must not matter
+            for rev in [false, true] {
+                let (mut msg, _, _) = blank_msg()?;
+                let mut ordered = opts.clone();
+                if rev {
+                    ordered.reverse();
+                }
+                for opt in ordered {
+                    msg.opts_mut().insert(opt);
+                }
+                let buf = order.encode(&msg)?;
+                assert_eq!(&buf[..V4_HEADER_LEN], &msg.to_vec()?[..V4_HEADER_LEN]);
+                assert_eq!(&buf[V4_HEADER_LEN..], expected, "order {first:?}");
+                // still decodes to the same options
+                assert_eq!(
+                    v4::Message::decode(&mut Decoder::new(&buf))?.opts(),
+                    msg.opts()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// random option sets of the kind dora sends, including options over 255 bytes, must
+    /// survive every encode & decode path: decode(encode(x)) == x
+    #[test]
+    fn test_opts_roundtrip() -> anyhow::Result<()> {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+        use v4::{DhcpOption as O, OptionCode as C};
+
+        let mut rng = StdRng::seed_from_u64(0x6f70_7473);
+        for _ in 0..200 {
+            let (mut msg, _, _) = blank_msg()?;
+            let opts = msg.opts_mut();
+            opts.insert(O::MessageType(v4::MessageType::Ack));
+            opts.insert(O::ServerIdentifier(rng.gen::<u32>().into()));
+            opts.insert(O::AddressLeaseTime(rng.gen()));
+            opts.insert(O::SubnetMask(rng.gen::<u32>().into()));
+            // up to 400 bytes of routers
+            let routers = (0..rng.gen_range(1..=100))
+                .map(|_| rng.gen::<u32>().into())
+                .collect();
+            opts.insert(O::Router(routers));
+            let prl = (0..rng.gen_range(1..=300))
+                .map(|_| C::from(rng.gen_range(1..=254u8)))
+                .collect();
+            opts.insert(O::ParameterRequestList(prl));
+            // raw data from config or echoed back, 224-254 are site-specific
+            for code in 224..=(224 + rng.gen_range(0..4u8)) {
+                let data = (0..rng.gen_range(0..=700)).map(|_| rng.gen()).collect();
+                opts.insert(O::Unknown(v4::UnknownOption::new(code.into(), data)));
+            }
+
+            let encoded = [
+                msg.to_vec()?,
+                OptionOrder::new([C::MessageType]).encode(&msg)?,
+            ];
+            for buf in encoded {
+                // options are well formed, none run past the end of the message
+                let mut wire = &buf[V4_HEADER_LEN..];
+                while let [code, len, rest @ ..] = wire {
+                    if *code == u8::from(C::End) {
+                        break;
+                    }
+                    assert!(rest.len() > *len as usize, "opt {code} past the end");
+                    wire = &rest[*len as usize..];
+                }
+                assert_eq!(wire.first(), Some(&u8::from(C::End)));
+
+                assert_eq!(
+                    v4::Message::decode(&mut Decoder::new(&buf))?.opts(),
+                    msg.opts()
+                );
+                assert_eq!(decode_v4_tolerant(&buf).unwrap().opts(), msg.opts());
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_resp_transforms() -> anyhow::Result<()> {
+        use v4::{DhcpOption as O, OptionCode as C};
+
+        let (mut msg, _, _) = blank_msg()?;
+        msg.opts_mut().insert(O::AddressLeaseTime(86400));
+        msg.opts_mut().insert(O::Renewal(43200));
+        msg.opts_mut().insert(O::Rebinding(75600));
+        msg.opts_mut().insert(O::VendorExtensions(vec![1, 2, 3]));
+        msg.opts_mut()
+            .insert(O::DomainNameServer(vec![[8, 8, 8, 8].into()]));
+
+        let transforms = RespTransforms {
+            insert: vec![O::ClassIdentifier(b"HTTPClient".to_vec())],
+            remove: vec![C::VendorExtensions],
+            max_lease: Some(Duration::from_secs(3600)),
+            broadcast: true,
+        };
+        transforms.apply(&mut msg);
+        assert_eq!(
+            msg.opts().get(C::ClassIdentifier),
+            Some(&O::ClassIdentifier(b"HTTPClient".to_vec()))
+        );
+        assert!(msg.opts().get(C::VendorExtensions).is_none());
+        assert!(msg.opts().get(C::DomainNameServer).is_some());
+        assert_eq!(
+            msg.opts().get(C::AddressLeaseTime),
+            Some(&O::AddressLeaseTime(3600))
+        );
+        assert_eq!(msg.opts().get(C::Renewal), Some(&O::Renewal(1800)));
+        assert_eq!(msg.opts().get(C::Rebinding), Some(&O::Rebinding(3150)));
+        assert!(msg.flags().broadcast());
+
+        // shorter leases are left alone
+        msg.opts_mut().insert(O::AddressLeaseTime(600));
+        RespTransforms::default().apply(&mut msg);
+        transforms.apply(&mut msg);
+        assert_eq!(
+            msg.opts().get(C::AddressLeaseTime),
+            Some(&O::AddressLeaseTime(600))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tolerant_long_opt() -> anyhow::Result<()> {
+        let (msg, _, _) = blank_msg()?;
+        let mut buf = msg.to_vec()?;
+        buf.truncate(V4_HEADER_LEN);
+        // opt 224 split in 2, with another option in between
+        buf.extend([224, 2, 1, 2, 53, 1, 1, 224, 1, 3, 255]);
+        let msg = decode_v4_tolerant(&buf).unwrap();
+        assert_eq!(msg.opts().msg_type(), Some(v4::MessageType::Discover));
+        assert_eq!(
+            msg.opts().get(v4::OptionCode::from(224)),
+            Some(&v4::DhcpOption::Unknown(v4::UnknownOption::new(
+                v4::OptionCode::from(224),
+                vec![1, 2, 3]
+            )))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_inspect_v4() -> anyhow::Result<()> {
+        let (msg, _, _) = blank_msg()?;
+        let mut buf = msg.to_vec()?;
+        buf.truncate(V4_HEADER_LEN);
+        // a good opt 53, an opt 82 whose circuit id runs past the option, then a
+        // truncated opt 12
+        buf.extend([53, 1, 1, 82, 3, 1, 5, 0xaa, 12, 4, b'a']);
+        let inspected = inspect_v4(&buf).unwrap();
+        assert_eq!(inspected.header.xid(), msg.xid());
+        assert_eq!(inspected.opts.len(), 2);
+        assert!(matches!(
+            inspected.opts[0],
+            (
+                53,
+                _,
+                Ok(v4::DhcpOption::MessageType(v4::MessageType::Discover))
+            )
+        ));
+        assert_eq!(inspected.opts[1].0, 82);
+        assert_eq!(inspected.opts[1].1, [1, 5, 0xaa]);
+        assert!(inspected.opts[1].2.is_err());
+        assert_eq!(inspected.truncated, Some(12));
+        assert!(inspect_v4(&buf[..100]).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tolerant_decode() -> anyhow::Result<()> {
+        let (mut msg, addr, state) = blank_msg()?;
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Discover));
+        let mut buf = msg.to_vec()?;
+        // replace End with an opt 82 whose circuit id runs past the option
+        let end = buf
+            .iter()
+            .rposition(|b| *b == u8::from(v4::OptionCode::End))
+            .unwrap();
+        buf.truncate(end);
+        buf.extend([82, 3, 1, 5, 0xaa, 255]);
+        assert!(v4::Message::decode(&mut Decoder::new(&buf)).is_err());
+
+        let meta = RecvMeta {
+            addr,
+            ..RecvMeta::default()
+        };
+        let mut ctx =
+            MsgContext::<v4::Message>::new(SerialMsg::new(Bytes::from(buf), addr), meta, state)?;
+        assert_eq!(ctx.msg().opts().msg_type(), Some(v4::MessageType::Discover));
+        let relay = v4::DhcpOption::Unknown(v4::UnknownOption::new(
+            v4::OptionCode::RelayAgentInformation,
+            vec![1, 5, 0xaa],
+        ));
+        assert_eq!(
+            ctx.msg().opts().get(v4::OptionCode::RelayAgentInformation),
+            Some(&relay)
+        );
+        assert_eq!(
+            metrics::MALFORMED_OPTION_COUNT
+                .with_label_values(&["82"])
+                .get(),
+            1
+        );
+
+        // raw opt 82 is still echoed back
+        ctx.set_resp_msg(msg);
+        ctx.populate_opts(&v4::DhcpOptions::new());
+        assert_opt(&ctx, relay);
+
+        // a truncated header is still an error
+        let (_, addr, state) = blank_msg()?;
+        assert!(MsgContext::<v4::Message>::new(
+            SerialMsg::new(Bytes::from_static(&[1, 1, 6, 0]), addr),
+            RecvMeta::default(),
+            state,
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_answered() -> anyhow::Result<()> {
+        let (mut resp, _, _) = blank_msg()?;
+        resp.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Nak));
+        assert_eq!(
+            Answered::v4(&resp),
+            Answered {
+                msg_type: RespType::V4(Some(v4::MessageType::Nak)),
+                yiaddr: None,
+                lease: None,
+            }
+        );
+
+        resp.set_yiaddr(Ipv4Addr::new(192, 168, 0, 10));
+        resp.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Ack));
+        resp.opts_mut()
+            .insert(v4::DhcpOption::AddressLeaseTime(3600));
+        let answered = Answered::v4(&resp);
+        assert_eq!(answered.yiaddr, Some([192, 168, 0, 10].into()));
+        assert_eq!(answered.lease, Some(Duration::from_secs(3600)));
+        Ok(())
+    }
 }
@@ -5,25 +5,30 @@
 use anyhow::{Context, Result};
 use dhcproto::{v4, v6, Decodable, Encodable};
 use pnet::datalink::NetworkInterface;
-use tokio::{sync::mpsc, time};
+use tokio::{sync::mpsc, task::JoinSet, time};
 use tokio_stream::StreamExt;
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info, instrument, trace, warn};
+use tracing::{debug, debug_span, error, field, info, instrument, trace, warn, Instrument, Span};
 use unix_udp_sock::{Source, Transmit, UdpSocket};
 
 use std::{
     any::{Any, TypeId},
+    collections::HashMap,
     fmt,
     future::Future,
+    io,
     marker::Send,
+    net::SocketAddr,
     os::unix::prelude::{FromRawFd, IntoRawFd},
     sync::Arc,
     time::{Duration, Instant},
 };
 
+pub mod activation;
 pub mod context;
 pub mod ioctl;
 pub mod msg;
+pub mod pool;
 pub mod state;
 pub mod typemap;
 pub(crate) mod udp;
@@ -31,8 +36,12 @@ pub(crate) mod udp;
 use crate::{
     config::cli::{Config, ALL_DHCP_RELAY_AGENTS_AND_SERVERS},
     handler::*,
-    metrics,
-    server::{context::MsgContext, msg::SerialMsg, udp::UdpStream},
+    metrics, pretty,
+    server::{
+        context::{Answered, DropReason, MsgContext, OptionOrder, Outcome, RespTransforms},
+        msg::SerialMsg,
+        udp::{self, UdpStream},
+    },
 };
 use topo_sort::DependencyTree;
 
@@ -47,6 +56,8 @@ pub(crate) type PostResponseFn<T> = Arc<dyn PostResponse<T>>;
 pub struct Server<T> {
     /// all the plugins the server will use expressed as a dependency tree
     plugins: DependencyTree<TypeId, PluginFn<T>>,
+    /// each plugin's dependencies, as they were registered
+    deps: HashMap<TypeId, Vec<TypeId>>,
     /// there can only be one post response plugin as it consumes `MsgContext<T>`
     postresponse: Option<PostResponseFn<T>>,
     /// additional application state
@@ -75,6 +86,7 @@ where
 
         Ok(Server {
             plugins: DependencyTree::new(),
+            deps: HashMap::new(),
             postresponse: None,
             state,
             config,
@@ -99,9 +111,34 @@ where
         let plugin = plugin.into();
         let id = <P as Any>::type_id(&plugin);
         self.plugins.add(id, plugin, dependencies.as_ref());
+        self.deps.insert(id, dependencies.to_vec());
         self
     }
 
+    /// the plugins in the order they will run, with the plugins each one depends on.
+    /// Errors if the dependencies have a cycle
+    pub fn plugin_chain(&self) -> Result<Vec<PluginInfo>> {
+        let name = |id: &TypeId| self.plugins.get(id).map(|plugin| plugin.name());
+        Ok(self
+            .plugins
+            .sorted_keys()?
+            .iter()
+            .filter_map(|id| {
+                Some(PluginInfo {
+                    name: name(id)?,
+                    depends_on: self
+                        .deps
+                        .get(id)
+                        .into_iter()
+                        .flatten()
+                        // a dependency that was never registered is skipped when sorting
+                        .map(|dep| name(dep).unwrap_or("<unregistered>"))
+                        .collect(),
+                })
+            })
+            .collect())
+    }
+
     /// Add plugin to the postresponse list of handlers
     pub fn postresponse<P, U>(&mut self, plugin: U) -> &mut Self
     where
@@ -143,15 +180,37 @@ where
     /// if None - No response
     async fn run_handlers(&self, ctx: &mut MsgContext<T>) -> Option<()> {
         for handler in &*self.plugins {
-            match handler.handle(ctx).await {
+            let span = debug_span!("plugin", name = handler.name());
+            let budget = ctx.budget();
+            let Ok(res) = budget.bound(handler.handle(ctx).instrument(span)).await else {
+                metrics::PLUGIN_BUDGET_EXCEEDED_COUNT
+                    .with_label_values(&[handler.name()])
+                    .inc();
+                warn!(
+                    plugin = handler.name(),
+                    "time budget exceeded, dropping message"
+                );
+                ctx.set_outcome(Outcome::Dropped {
+                    reason: DropReason::Budget(handler.name()),
+                });
+                return None;
+            };
+            match res {
                 Ok(Action::Respond) => return Some(()),
                 Ok(Action::NoResponse) => {
                     // remove the resp_msg if we don't plan to send a response
                     ctx.resp_msg_mut().take();
+                    ctx.set_outcome(Outcome::Dropped {
+                        reason: DropReason::Plugin(handler.name()),
+                    });
                     return None;
                 }
                 Err(ref err) => {
                     warn!(?err);
+                    ctx.set_outcome(Outcome::Error {
+                        plugin: Some(handler.name()),
+                        error: format!("{err:#}"),
+                    });
                     // The client will not get a response if we encounter an error
                     return None;
                 }
@@ -165,7 +224,59 @@ where
     async fn run_post_response_handler(&self, mut ctx: MsgContext<T>) {
         ctx.mark_as_not_live();
         if let Some(ref handler) = self.postresponse {
-            handler.handle(ctx).await;
+            handler
+                .handle(ctx)
+                .instrument(debug_span!("post_response"))
+                .await;
+        }
+    }
+}
+
+/// A plugin in a server's chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginInfo {
+    /// the plugin's [`Plugin::name`]
+    pub name: &'static str,
+    /// plugins it was registered to run after
+    pub depends_on: Vec<&'static str>,
+}
+
+impl fmt::Display for PluginInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.depends_on.is_empty() {
+            write!(f, " (after {})", self.depends_on.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// how long tasks still running after the shutdown grace are given to exit once cancelled
+const CANCEL_WAIT: Duration = Duration::from_secs(1);
+
+/// what happened to the messages being processed when a server was stopped
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ShutdownSummary {
+    /// messages being processed when the server stopped receiving
+    pub in_flight: usize,
+    /// messages that finished within the shutdown grace
+    pub finished: usize,
+    /// messages still being processed after the grace, they were dropped without a
+    /// response
+    pub cancelled: usize,
+    /// time spent waiting on in-flight messages
+    pub elapsed: Duration,
+}
+
+impl std::ops::Add for ShutdownSummary {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            in_flight: self.in_flight + other.in_flight,
+            finished: self.finished + other.finished,
+            cancelled: self.cancelled + other.cancelled,
+            elapsed: self.elapsed.max(other.elapsed),
         }
     }
 }
@@ -219,8 +330,22 @@ struct RunInner<T> {
 }
 
 impl RunInner<v4::Message> {
+    /// root span for the transaction, plugin spans are its children
+    fn span(&self) -> Span {
+        let msg = self.ctx.msg();
+        let client_id = match msg.opts().get(v4::OptionCode::ClientIdentifier) {
+            Some(v4::DhcpOption::ClientIdentifier(id)) => Some(pretty::Hex(id)),
+            _ => None,
+        };
+        debug_span!(
+            "v4",
+            xid = msg.xid(),
+            chaddr = %pretty::Hex(msg.chaddr()),
+            client_id = client_id.map(field::display),
+        )
+    }
+
     /// Process handlers
-    #[instrument(name = "v4", level = "debug", skip_all)]
     async fn run(mut self) -> Result<()> {
         let start = Instant::now();
         if let Err(err) = self.ctx.recv_metrics() {
@@ -236,47 +361,90 @@ impl RunInner<v4::Message> {
             .find(|int| int.index == ifindex)
             .with_context(|| format!("can't find interface {ifindex}"))?;
         trace!(meta = ?self.ctx.meta(), ?interface, "received datagram");
+        self.ctx.set_deadline(start + timeout);
 
         let resp = match time::timeout(timeout, self.service.run_handlers(&mut self.ctx)).await {
             // WARNING: any use of `?` inside this block will return early and stop post_response from running
             Ok(Some(())) => {
                 let iname = interface.name.as_str();
+                // before the address is picked, the broadcast flag may be forced on
+                if let Some(transforms) = self.ctx.get_local::<RespTransforms>().cloned() {
+                    if let Some(resp) = self.ctx.resp_msg_mut() {
+                        transforms.apply(resp);
+                    }
+                }
                 let dst_addr = self.ctx.resp_addr(
                     self.service.config.is_default_port_v4(),
                     socket2::SockRef::from(&*self.soc),
                 );
 
-                if let Some(resp) = self.ctx.resp_msg() {
+                let outcome = if let Some(resp) = self.ctx.resp_msg() {
                     let msg_type = resp.opts().msg_type();
-                    if let Ok(msg) = SerialMsg::from_msg(resp, dst_addr) {
-                        // https://github.com/imp/dnsmasq/blob/master/src/forward.c#L70
-                        // set source IP to the same IP that was used in recv'd destination (ipi_spec_dst)
-                        // otherwise use iface idx
-                        let packet_src =
-                            source.map(Source::Ip).unwrap_or(Source::Interface(ifindex));
-                        metrics::DHCPV4_BYTES_SENT.inc_by(msg.bytes().len() as u64);
-                        let transmit = Transmit::new(dst_addr, msg.msg()).src_ip(packet_src);
-
-                        debug!(
-                            opcode = ?resp.opcode(),
-                            msg_type = ?msg_type,
-                            ?dst_addr,
-                            ?iname,
-                            source = ?packet_src,
-                            %resp,
-                        );
-                        self.ctx.set_dst_addr(dst_addr);
-                        if let Err(err) = self.soc.send_msg(&self.udpstate, transmit).await {
-                            error!(?err);
+                    let encoded = match self.ctx.get_local::<OptionOrder>() {
+                        Some(order) => order
+                            .encode(resp)
+                            .map(|buf| SerialMsg::new(buf.into(), dst_addr)),
+                        None => SerialMsg::from_msg(resp, dst_addr),
+                    };
+                    match encoded {
+                        Ok(msg) => {
+                            // https://github.com/imp/dnsmasq/blob/master/src/forward.c#L70
+                            // set source IP to the same IP that was used in recv'd destination (ipi_spec_dst)
+                            // otherwise use iface idx
+                            let packet_src =
+                                source.map(Source::Ip).unwrap_or(Source::Interface(ifindex));
+
+                            debug!(
+                                opcode = ?resp.opcode(),
+                                msg_type = ?msg_type,
+                                ?dst_addr,
+                                ?iname,
+                                source = ?packet_src,
+                                resp = %pretty::V4Msg(resp),
+                            );
+                            let answered = Outcome::Answered(Answered::v4(resp));
+                            self.ctx.set_dst_addr(dst_addr);
+                            let outcome = if self.service.config.dry_run {
+                                metrics::DHCPV4_DRY_RUN_COUNT.inc();
+                                dry_run(msg_type, dst_addr, iname, msg.bytes());
+                                answered
+                            } else {
+                                metrics::DHCPV4_BYTES_SENT.inc_by(msg.bytes().len() as u64);
+                                let transmit =
+                                    Transmit::new(dst_addr, msg.msg()).src_ip(packet_src);
+                                match self.soc.send_msg(&self.udpstate, transmit).await {
+                                    Ok(_) => answered,
+                                    Err(err) => {
+                                        error!(?err);
+                                        send_error(err)
+                                    }
+                                }
+                            };
+                            msg.recycle();
+                            outcome
+                        }
+                        Err(err) => {
+                            error!(?err, "failed to encode response");
+                            send_error(err)
                         }
                     }
-                }
+                } else {
+                    Outcome::Dropped {
+                        reason: DropReason::NoResponse,
+                    }
+                };
+                self.ctx.set_outcome(outcome);
                 Ok(())
             }
-            // no response
+            // no response, `run_handlers` recorded why
             Ok(None) => Ok(()),
             // drop timeouts
-            Err(error) => Err(anyhow::anyhow!(error)),
+            Err(error) => {
+                self.ctx.set_outcome(Outcome::Dropped {
+                    reason: DropReason::Timeout,
+                });
+                Err(anyhow::anyhow!(error))
+            }
         };
         if let Err(err) = self.ctx.sent_metrics(start.elapsed()) {
             warn!(?err, "error counting sent metrics");
@@ -295,12 +463,13 @@ impl RunTask<v4::Message> {
             cancel,
             _shutdown_complete,
         } = self;
+        let span = inner.span();
         tokio::select! {
             _ = cancel.cancelled() => {
                 trace!("task received shutdown notifier");
                 Ok(())
             }
-            res = inner.run() => {
+            res = inner.run().instrument(span) => {
                 res
             }
         }
@@ -314,12 +483,13 @@ impl RunTask<v6::Message> {
             cancel,
             _shutdown_complete,
         } = self;
+        let span = inner.span();
         tokio::select! {
             _ = cancel.cancelled() => {
                 trace!("task received shutdown notifier");
                 Ok(())
             }
-            res = inner.run() => {
+            res = inner.run().instrument(span) => {
                 res
             }
         }
@@ -327,8 +497,21 @@ impl RunTask<v6::Message> {
 }
 
 impl RunInner<v6::Message> {
+    /// root span for the transaction, plugin spans are its children
+    fn span(&self) -> Span {
+        let msg = self.ctx.msg();
+        let client_id = match msg.opts().get(v6::OptionCode::ClientId) {
+            Some(v6::DhcpOption::ClientId(id)) => Some(pretty::Hex(id)),
+            _ => None,
+        };
+        debug_span!(
+            "v6",
+            xid = %pretty::Hex(&msg.xid()),
+            client_id = client_id.map(field::display),
+        )
+    }
+
     /// Process handlers
-    #[instrument(name = "v6", level = "debug", skip_all)]
     async fn run(mut self) -> Result<()> {
         let start = Instant::now();
         if let Err(err) = self.ctx.recv_metrics() {
@@ -343,6 +526,7 @@ impl RunInner<v6::Message> {
             .find(|int| int.index == ifindex)
             .with_context(|| format!("can't find interface {ifindex}"))?;
         trace!(meta = ?self.ctx.meta(), ?interface, "received datagram");
+        self.ctx.set_deadline(start + timeout);
 
         let resp = match time::timeout(timeout, self.service.run_handlers(&mut self.ctx)).await {
             // WARNING: any use of `?` inside this block will return early and stop post_response from running
@@ -350,29 +534,58 @@ impl RunInner<v6::Message> {
                 let iname = interface.name.as_str();
                 let dst_addr = self.ctx.resp_addr(self.service.config.is_default_port_v6());
 
-                if let Some(resp) = self.ctx.resp_msg() {
+                let outcome = if let Some(resp) = self.ctx.resp_msg() {
                     let msg_type = resp.msg_type();
-                    if let Ok(msg) = SerialMsg::from_msg(resp, dst_addr) {
-                        debug!(
-                            ?msg_type,
-                            ?dst_addr,
-                            ?iname,
-                            %resp,
-                            "message created"
-                        );
-                        metrics::DHCPV6_BYTES_SENT.inc_by(msg.bytes().len() as u64);
-                        self.ctx.set_dst_addr(dst_addr);
-                        if let Err(err) = self.soc.send_to(msg.bytes(), dst_addr).await {
-                            error!(?err);
+                    match SerialMsg::from_msg(resp, dst_addr) {
+                        Ok(msg) => {
+                            debug!(
+                                ?msg_type,
+                                ?dst_addr,
+                                ?iname,
+                                %resp,
+                                "message created"
+                            );
+                            let answered = Outcome::Answered(Answered::v6(resp));
+                            self.ctx.set_dst_addr(dst_addr);
+                            let outcome = if self.service.config.dry_run {
+                                metrics::DHCPV6_DRY_RUN_COUNT.inc();
+                                dry_run(msg_type, dst_addr, iname, msg.bytes());
+                                answered
+                            } else {
+                                metrics::DHCPV6_BYTES_SENT.inc_by(msg.bytes().len() as u64);
+                                match self.soc.send_to(msg.bytes(), dst_addr).await {
+                                    Ok(_) => answered,
+                                    Err(err) => {
+                                        error!(?err);
+                                        send_error(err)
+                                    }
+                                }
+                            };
+                            msg.recycle();
+                            outcome
+                        }
+                        Err(err) => {
+                            error!(?err, "failed to encode response");
+                            send_error(err)
                         }
                     }
-                }
+                } else {
+                    Outcome::Dropped {
+                        reason: DropReason::NoResponse,
+                    }
+                };
+                self.ctx.set_outcome(outcome);
                 Ok(())
             }
-            // no response
+            // no response, `run_handlers` recorded why
             Ok(None) => Ok(()),
             // drop timeouts
-            Err(error) => Err(anyhow::anyhow!(error)),
+            Err(error) => {
+                self.ctx.set_outcome(Outcome::Dropped {
+                    reason: DropReason::Timeout,
+                });
+                Err(anyhow::anyhow!(error))
+            }
         };
         if let Err(err) = self.ctx.sent_metrics(start.elapsed()) {
             warn!(?err, "error counting sent metrics");
@@ -383,6 +596,27 @@ impl RunInner<v6::Message> {
     }
 }
 
+/// the outcome of a response that couldn't be encoded or sent
+fn send_error(err: io::Error) -> Outcome {
+    Outcome::Error {
+        plugin: None,
+        error: err.to_string(),
+    }
+}
+
+/// log a response that `--dry-run` kept from being sent, `packet` is hex so it can be
+/// fed to the external api's `/v1/decode`
+fn dry_run(msg_type: impl fmt::Debug, dst_addr: SocketAddr, iname: &str, packet: &[u8]) {
+    info!(
+        target: "dora::dry_run",
+        ?msg_type,
+        ?dst_addr,
+        ?iname,
+        packet = %pretty::Hex(packet),
+        "dry run, response not sent"
+    );
+}
+
 // This is unfortunate,
 // the key problem is that Server/Service is defined over T, and yet
 // they need to call send code to handle broadcast/multicast differently for v4/v6
@@ -391,25 +625,36 @@ impl RunInner<v6::Message> {
 // I'd rather let the compiler copy-paste for me, as parameterizing the future is not
 // without its own hurdles (would require allocating the future see `experiment_runtask` branch).
 macro_rules! impl_server {
-    ($t:ty) => {
+    ($t:ty, $version:literal) => {
         impl Server<$t> {
             /// start server with parsed config values
-            pub async fn start<F>(self, shutdown: F) -> Result<()>
+            pub async fn start<F>(self, shutdown: F) -> Result<ShutdownSummary>
             where
                 F: Future<Output = Result<()>>,
             {
-                self.listen(shutdown).await?;
-                Ok(())
+                self.listen(shutdown).await
             }
 
             /// listen on a given address, consumes `self`
             /// The future startup_complete is intended for post startup tasks ex:
             /// setting dora's health status to Good and is required here as
             /// listen will not return unless an error occurs
-            pub async fn listen<F>(self, shutdown: F) -> Result<()>
+            pub async fn listen<F>(self, shutdown: F) -> Result<ShutdownSummary>
             where
                 F: Future<Output = Result<()>>,
             {
+                let chain = self
+                    .plugin_chain()
+                    .context("creating list of services failed in topological sort")?;
+                info!(
+                    version = $version,
+                    plugins = %chain
+                        .iter()
+                        .map(|plugin| plugin.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" -> "),
+                    "plugin order"
+                );
                 let mut service = self
                     .into_service()
                     .context("creating list of services failed in topological sort")?;
@@ -428,63 +673,136 @@ macro_rules! impl_server {
                     }
                 }
 
-                info!("notifying tasks of shutdown...");
+                // the receive loops were dropped with `service.listen()`, nothing new is
+                // picked up from here on
                 let Service {
                     mut shutdown_complete_rx,
                     shutdown_complete_tx,
                     cancel,
-                    ..
+                    plugins,
+                    state,
                 } = service;
-                // When `cancel` is called, all tasks which have `CancellationToken`d will
-                // receive the cancel signal and can exit
-                cancel.cancel();
+                let grace = plugins.config.shutdown_grace();
+                let start = Instant::now();
+                let in_flight = state.live_msgs();
+                info!(in_flight, ?grace, "waiting for in-flight messages to finish...");
                 // Drop final `Sender` so the `Receiver` below can complete
                 drop(shutdown_complete_tx);
                 // Wait for all active tasks to finish processing. As the `Sender`
                 // handle held by the listener has been dropped above, the only remaining
                 // `Sender` instances are held by connection handler tasks. When those drop,
                 // the `mpsc` channel will close and `recv()` will return `None`.
-                if let Err(_) =
-                    time::timeout(Duration::from_secs(3), shutdown_complete_rx.recv()).await
+                let drained = time::timeout(grace, shutdown_complete_rx.recv())
+                    .await
+                    .is_ok();
+                let cancelled = if drained { 0 } else { state.live_msgs() };
+                // When `cancel` is called, all tasks which have `CancellationToken`d will
+                // receive the cancel signal and can exit
+                cancel.cancel();
+                if !drained
+                    && time::timeout(CANCEL_WAIT, shutdown_complete_rx.recv())
+                        .await
+                        .is_err()
                 {
-                    error!("tasks did not finish within 3 seconds-- exiting anyway");
-                } else {
-                    info!("all tasks finished cleanly");
+                    error!("tasks did not exit after being cancelled-- exiting anyway");
                 }
-
-                Ok(())
+                let summary = ShutdownSummary {
+                    in_flight,
+                    finished: in_flight.saturating_sub(cancelled),
+                    cancelled,
+                    elapsed: start.elapsed(),
+                };
+                if cancelled > 0 {
+                    warn!(
+                        server = $version,
+                        in_flight,
+                        cancelled,
+                        "messages didn't finish within the shutdown grace & were dropped"
+                    );
+                }
+                info!(
+                    server = $version,
+                    in_flight = summary.in_flight,
+                    finished = summary.finished,
+                    cancelled = summary.cancelled,
+                    elapsed = ?summary.elapsed,
+                    "server stopped"
+                );
+                Ok(summary)
             }
         }
 
         impl Service<$t> {
-            // handles listening on UDP and spawning a new task per `MsgContext`
+            // one socket, or the ones passed by systemd, each read by its own task. The
+            // server's sockets aren't multiplied with SO_REUSEPORT, the kernel would
+            // deliver every broadcast & multicast to each of them
             async fn listen(&mut self) -> Result<()> {
-                let soc = self.create_socket().await?;
+                let mut loops = JoinSet::new();
+                let mut sockets = self.inherited_sockets()?;
+                if sockets.is_empty() {
+                    sockets.push(self.create_socket().await?);
+                }
+                for soc in sockets {
+                    loops.spawn(Self::recv_loop(
+                        Arc::new(soc),
+                        Arc::clone(&self.state),
+                        Arc::clone(&self.plugins),
+                        self.cancel.clone(),
+                        self.shutdown_complete_tx.clone(),
+                    ));
+                }
+                // the set is dropped on shutdown, which aborts the loops
+                while let Some(res) = loops.join_next().await {
+                    res??;
+                }
+                Ok(())
+            }
 
-                let udp_recv = Arc::new(soc);
-                let udp_send = Arc::clone(&udp_recv);
+            // handles listening on UDP and spawning a new task per `MsgContext`
+            async fn recv_loop(
+                udp_send: Arc<UdpSocket>,
+                state: Arc<State>,
+                service: Arc<ServiceInner<$t>>,
+                cancel: CancellationToken,
+                shutdown_complete_tx: mpsc::Sender<()>,
+            ) -> Result<()> {
+                let udp_recv = Arc::clone(&udp_send);
                 let udp_state = Arc::new(unix_udp_sock::UdpState::new());
-
-                let mut ctx_stream = UdpStream::<$t, _>::new(udp_recv, self.state.clone());
-                while let Some(ctx) = ctx_stream.next().await {
-                    if let Ok(ctx) = ctx {
-                        self.state.inc_live_msgs().await;
-                        let cancel = self.cancel.clone();
-                        let _shutdown_complete = self.shutdown_complete_tx.clone();
+                let workers = service.config.recv_workers as usize;
+
+                let dispatch = {
+                    let state = state.clone();
+                    move |ctx: MsgContext<$t>| {
+                        let state = state.clone();
+                        let cancel = cancel.clone();
+                        let _shutdown_complete = shutdown_complete_tx.clone();
                         let task = RunTask {
                             inner: RunInner {
                                 ctx,
                                 soc: udp_send.clone(),
-                                service: self.plugins.clone(),
+                                service: service.clone(),
                                 udpstate: udp_state.clone(),
                             },
                             cancel,
                             _shutdown_complete,
                         };
-                        // TODO: when `JoinSet` is removed from unstable-- add handles
-                        // here.
-                        // Using JoinSet will likely mean that we no longer need `_shutdown_complete`
-                        tokio::spawn(task.run());
+                        async move {
+                            state.inc_live_msgs().await;
+                            // TODO: when `JoinSet` is removed from unstable-- add handles
+                            // here.
+                            // Using JoinSet will likely mean that we no longer need `_shutdown_complete`
+                            tokio::spawn(task.run());
+                        }
+                    }
+                };
+                if workers > 1 {
+                    udp::fan_out(udp_recv, state, workers, dispatch).await?;
+                    return Ok(());
+                }
+                let mut ctx_stream = UdpStream::<$t, _>::new(udp_recv, state);
+                while let Some(ctx) = ctx_stream.next().await {
+                    if let Ok(ctx) = ctx {
+                        dispatch(ctx).await;
                     }
                 }
                 Ok(())
@@ -493,10 +811,28 @@ macro_rules! impl_server {
     };
 }
 
-impl_server!(v4::Message);
-impl_server!(v6::Message);
+impl_server!(v4::Message, "v4");
+impl_server!(v6::Message, "v6");
 
 impl Service<v4::Message> {
+    /// the sockets for `v4_addr` passed by systemd, see [`activation`]
+    fn inherited_sockets(&self) -> Result<Vec<unix_udp_sock::UdpSocket>> {
+        let addr = self.plugins.config.v4_addr;
+        let sockets = activation::udp_sockets(addr)?;
+        if !sockets.is_empty() {
+            info!(?addr, count = sockets.len(), "using inherited UDP sockets");
+        }
+        sockets
+            .into_iter()
+            .map(|soc| {
+                soc.set_nonblocking(true)
+                    .context("failed to set nonblocking mode on socket")?;
+                soc.set_broadcast(true).context("failed to set_broadcast")?;
+                Ok(unix_udp_sock::UdpSocket::from_std(soc)?)
+            })
+            .collect()
+    }
+
     #[instrument(name = "v4", level = "debug", skip_all)]
     async fn create_socket(&self) -> Result<unix_udp_sock::UdpSocket> {
         let addr = self.plugins.config.v4_addr;
@@ -530,6 +866,35 @@ impl Service<v4::Message> {
 }
 
 impl Service<v6::Message> {
+    /// the sockets for `v6_addr` passed by systemd, see [`activation`]. A socket unit
+    /// can't join multicast groups, so that's done here
+    fn inherited_sockets(&self) -> Result<Vec<unix_udp_sock::UdpSocket>> {
+        let addr = self.plugins.config.v6_addr;
+        let sockets = activation::udp_sockets(addr)?;
+        if !sockets.is_empty() {
+            info!(
+                ?addr,
+                count = sockets.len(),
+                "using inherited v6 UDP sockets"
+            );
+        }
+        sockets
+            .into_iter()
+            .map(|soc| {
+                soc.set_nonblocking(true)
+                    .context("failed to set nonblocking mode on socket")?;
+                for int in &self.plugins.interfaces {
+                    match soc.join_multicast_v6(&ALL_DHCP_RELAY_AGENTS_AND_SERVERS, int.index) {
+                        // joined by the process that had the socket before
+                        Err(err) if err.kind() == io::ErrorKind::AddrInUse => {}
+                        res => res.context("join v6 multicast")?,
+                    }
+                }
+                Ok(unix_udp_sock::UdpSocket::from_std(soc)?)
+            })
+            .collect()
+    }
+
     #[instrument(name = "v6", level = "debug", skip_all)]
     async fn create_socket(&self) -> Result<unix_udp_sock::UdpSocket> {
         let addr = self.plugins.config.v6_addr;
@@ -1,9 +1,61 @@
 //! SerialMsg defines raw bytes and an addr
 use bytes::Bytes;
-use dhcproto::{Decodable, Encodable};
+use dhcproto::{Decodable, Encodable, Encoder};
 
 use std::{io, net::SocketAddr};
 
+use crate::server::pool::Pool;
+
+/// buffers responses are encoded into. Received messages don't need one, their bytes are
+/// split off the socket's read buffer, which reclaims them once the `MsgContext` is dropped
+pub static ENCODE_POOL: BufPool = BufPool::new(1_024);
+
+/// room for a response that fits in an ethernet frame without reallocating
+const BUF_CAPACITY: usize = 1_500;
+/// buffers that grew larger than this aren't kept, so one big response doesn't pin memory
+const MAX_POOLED_CAPACITY: usize = 16 * 1_024;
+
+/// A pool of reusable byte buffers
+#[derive(Debug)]
+pub struct BufPool {
+    bufs: Pool<Vec<u8>>,
+}
+
+impl BufPool {
+    /// an empty pool that keeps about `max` buffers
+    pub const fn new(max: usize) -> Self {
+        Self {
+            bufs: Pool::new(max),
+        }
+    }
+
+    /// an empty buffer, reused from the pool if one is available
+    pub fn take(&self) -> Vec<u8> {
+        self.bufs
+            .take()
+            .unwrap_or_else(|| Vec::with_capacity(BUF_CAPACITY))
+    }
+
+    /// return a buffer to the pool
+    pub fn put(&self, mut buf: Vec<u8>) {
+        if buf.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        buf.clear();
+        self.bufs.put(buf);
+    }
+
+    /// number of buffers waiting to be reused
+    pub fn len(&self) -> usize {
+        self.bufs.len()
+    }
+
+    /// `true` if there are no buffers to reuse
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 // use crate::udp::UdpRecv;
 
 /// A message pulled from TCP or UDP and serialized to bytes, stored with a
@@ -22,16 +74,28 @@ impl SerialMsg {
         SerialMsg { message, addr }
     }
 
-    /// Constructs a new `SerialMsg` from another `SerialMsg` and a `SocketAddr`
+    /// Constructs a new `SerialMsg` from another `SerialMsg` and a `SocketAddr`. The
+    /// message is encoded into a buffer from [`ENCODE_POOL`], give it back with
+    /// [`recycle`] once it's sent
+    ///
+    /// [`recycle`]: SerialMsg::recycle
     pub fn from_msg<T: Encodable>(msg: &T, addr: SocketAddr) -> io::Result<Self> {
+        let mut buf = ENCODE_POOL.take();
+        if let Err(err) = msg.encode(&mut Encoder::new(&mut buf)) {
+            ENCODE_POOL.put(buf);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
         Ok(SerialMsg {
-            message: msg
-                .to_vec()
-                .map_err(|op| io::Error::new(io::ErrorKind::InvalidData, op))?
-                .into(),
+            message: buf.into(),
             addr,
         })
     }
+
+    /// Return the buffer to [`ENCODE_POOL`]. Nothing else should still hold the bytes,
+    /// if something does they're copied
+    pub fn recycle(self) {
+        ENCODE_POOL.put(Vec::from(self.message));
+    }
     /// Get a reference to the bytes
     pub fn bytes(&self) -> &[u8] {
         &self.message
@@ -62,3 +126,37 @@ impl SerialMsg {
         T::from_bytes(&self.message).map_err(|op| io::Error::new(io::ErrorKind::InvalidData, op))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buf_pool() {
+        let pool = BufPool::new(1);
+        let mut buf = pool.take();
+        assert!(buf.capacity() >= BUF_CAPACITY);
+        buf.extend([1, 2, 3]);
+        let ptr = buf.as_ptr();
+        pool.put(buf);
+        pool.put(Vec::with_capacity(10));
+        // only `max` are kept, split between the shards
+        assert_eq!(pool.len(), 1);
+        let buf = pool.take();
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+        assert!(pool.is_empty());
+
+        pool.put(Vec::with_capacity(MAX_POOLED_CAPACITY + 1));
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn test_recycle() {
+        let msg = dhcproto::v4::Message::default();
+        let addr = "192.168.0.1:68".parse().unwrap();
+        let serial = SerialMsg::from_msg(&msg, addr).unwrap();
+        assert_eq!(serial.to_msg::<dhcproto::v4::Message>().unwrap(), msg);
+        serial.recycle();
+    }
+}
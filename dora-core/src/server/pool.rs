@@ -0,0 +1,94 @@
+//! Pools of allocations that are reused between messages
+//!
+//! Every worker thread runs receive loops, so a single lock would be taken by all of them
+//! for each message. The pool is split into shards instead & each thread sticks to one,
+//! values taken on one thread & returned on another just move between shards.
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+
+/// number of shards, more than most servers have worker threads
+const SHARDS: usize = 16;
+
+/// A pool of values, sharded by thread
+#[derive(Debug)]
+pub struct Pool<T> {
+    shards: [Mutex<Vec<T>>; SHARDS],
+    /// most values kept in each shard, any more returned are dropped
+    max: usize,
+}
+
+impl<T> Pool<T> {
+    /// an empty pool that keeps about `max` values
+    pub const fn new(max: usize) -> Self {
+        Self {
+            shards: [const { Mutex::new(Vec::new()) }; SHARDS],
+            max: max.div_ceil(SHARDS),
+        }
+    }
+
+    /// a value from this thread's shard, if it has one
+    pub fn take(&self) -> Option<T> {
+        self.shard().lock().unwrap().pop()
+    }
+
+    /// return a value to this thread's shard, it's dropped if the shard is full
+    pub fn put(&self, val: T) {
+        let mut shard = self.shard().lock().unwrap();
+        if shard.len() < self.max {
+            shard.push(val);
+        }
+    }
+
+    /// number of values waiting to be reused
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    /// `true` if there are no values to reuse
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn shard(&self) -> &Mutex<Vec<T>> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        thread_local! {
+            static SHARD: usize = NEXT.fetch_add(1, Ordering::Relaxed) % SHARDS;
+        }
+        &self.shards[SHARD.with(|shard| *shard)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_max() {
+        // one per shard
+        let pool = Pool::new(SHARDS);
+        pool.put(1);
+        pool.put(2);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.take(), Some(1));
+        assert!(pool.is_empty());
+        assert_eq!(pool.take(), None);
+    }
+
+    #[test]
+    fn test_pool_threads() {
+        let pool = Pool::new(SHARDS * 4);
+        std::thread::scope(|s| {
+            for i in 0..4 {
+                let pool = &pool;
+                s.spawn(move || pool.put(i));
+            }
+        });
+        // each thread's value went to its own shard or joined another's, none dropped
+        assert_eq!(pool.len(), 4);
+    }
+}
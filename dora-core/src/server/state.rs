@@ -56,6 +56,12 @@ impl State {
         self.live_limit - self.live_msgs.available_permits()
     }
 
+    /// Return the max number of live queries
+    #[inline]
+    pub fn live_limit(&self) -> usize {
+        self.live_limit
+    }
+
     /// Increment the context id
     #[inline]
     pub fn inc_id(&self) -> usize {
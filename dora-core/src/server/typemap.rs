@@ -7,6 +7,8 @@ use std::{
     hash::{BuildHasherDefault, Hasher},
 };
 
+use crate::server::pool::Pool;
+
 /// A TypeId is already a hash, so we don't need to hash it
 #[derive(Default)]
 struct TypeIdHash(u64);
@@ -29,6 +31,11 @@ impl Hasher for TypeIdHash {
 
 type AnyTypeMap = HashMap<TypeId, Box<dyn Any + Send + Sync>, BuildHasherDefault<TypeIdHash>>;
 
+/// maps of recycled `TypeMap`s, see [`TypeMap::pooled`]
+static MAP_POOL: Pool<Box<AnyTypeMap>> = Pool::new(1_024);
+/// maps that grew past this many entries aren't kept
+const MAX_POOLED_ENTRIES: usize = 64;
+
 /// This is a HashMap of values, stored based on [`TypeId`]. Every type has a
 /// unique `TypeId` generated by the compiler, we are using this id to store in
 /// a value in a map of `Box<dyn Any>` based on a type. Then retrieving that
@@ -55,6 +62,31 @@ impl TypeMap {
         TypeMap { map: None }
     }
 
+    /// Make a new `TypeMap` that reuses the allocation of a recycled one, if there is one
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::TypeMap;
+    /// let mut map = TypeMap::pooled();
+    /// map.insert(10_usize);
+    /// map.recycle();
+    /// assert!(TypeMap::pooled().get::<usize>().is_none());
+    /// ```
+    pub fn pooled() -> TypeMap {
+        TypeMap {
+            map: MAP_POOL.take(),
+        }
+    }
+
+    /// Drop the values & keep the map's allocation for the next [`TypeMap::pooled`]
+    pub fn recycle(mut self) {
+        if let Some(mut map) = self.map.take() {
+            if map.capacity() <= MAX_POOLED_ENTRIES {
+                map.clear();
+                MAP_POOL.put(map);
+            }
+        }
+    }
+
     /// Insert a type into the map. If the type already exists, it will be
     /// returned.
     ///
@@ -2,13 +2,15 @@
 use dhcproto::{Decodable, Encodable};
 use futures::ready;
 use pin_project::pin_project;
+use tokio::{sync::mpsc, task::JoinSet};
 // use tokio::net::UdpSocket;
-use tokio_stream::Stream;
+use tokio_stream::{Stream, StreamExt};
 use tokio_util::codec::BytesCodec; // , udp::UdpFramed};
 use unix_udp_sock::{framed::UdpFramed, UdpSocket};
 
 use std::{
     borrow::Borrow,
+    future::Future,
     io,
     marker::PhantomData,
     pin::Pin,
@@ -18,7 +20,7 @@ use std::{
 
 use crate::{
     handler::{MsgContext, State},
-    server::msg::SerialMsg,
+    server::{context::DecodeMsg, msg::SerialMsg},
 };
 
 /// Abstracts reading buffers off of a tokio `net::UdpStream` and converting
@@ -52,7 +54,7 @@ where
 
 impl<T, S> Stream for UdpStream<T, S>
 where
-    T: Decodable + Encodable,
+    T: DecodeMsg + Encodable,
     S: Borrow<UdpSocket>,
 {
     type Item = io::Result<MsgContext<T>>;
@@ -69,3 +71,104 @@ where
         }
     }
 }
+
+/// datagrams queued for each worker, a full queue holds up the reading loop
+const WORKER_QUEUE: usize = 256;
+
+/// Reads datagrams off `soc` & hands them to `workers` tasks in turn, each decodes its
+/// share & passes the messages to `handle`. The workers share the one socket, so each
+/// datagram is handled once. Separate SO_REUSEPORT sockets would spread unicast the same
+/// way, but the kernel delivers every broadcast & multicast to each of them. Datagrams
+/// that don't decode are dropped
+pub(crate) async fn fan_out<T, F, Fut>(
+    soc: Arc<UdpSocket>,
+    state: Arc<State>,
+    workers: usize,
+    handle: F,
+) -> io::Result<()>
+where
+    T: DecodeMsg + Encodable + Send + 'static,
+    F: Fn(MsgContext<T>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    // dropped with the reading loop, which aborts the workers
+    let mut decoders = JoinSet::new();
+    let mut queues = Vec::with_capacity(workers);
+    for _ in 0..workers.max(1) {
+        let (tx, mut rx) = mpsc::channel(WORKER_QUEUE);
+        let state = Arc::clone(&state);
+        let handle = handle.clone();
+        decoders.spawn(async move {
+            while let Some((buf, meta)) = rx.recv().await {
+                let msg = SerialMsg::new(buf, meta.addr);
+                if let Ok(ctx) = MsgContext::<T>::new(msg, meta, Arc::clone(&state)) {
+                    handle(ctx).await;
+                }
+            }
+        });
+        queues.push(tx);
+    }
+    let mut frames = UdpFramed::new(soc, BytesCodec::new());
+    for queue in queues.iter().cycle() {
+        let Some(frame) = frames.next().await else {
+            break;
+        };
+        let (buf, meta) = frame?;
+        if queue.send((buf.freeze(), meta)).await.is_err() {
+            // only if a worker panicked
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use dhcproto::v4;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fan_out_broadcast_once() -> io::Result<()> {
+        let soc = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).await?;
+        let port = soc.local_addr()?.port();
+        let handled = Arc::new(AtomicUsize::new(0));
+        let count = Arc::clone(&handled);
+        let reader = tokio::spawn(fan_out(
+            Arc::new(soc),
+            Arc::new(State::new(10)),
+            4,
+            move |_: MsgContext<v4::Message>| {
+                count.fetch_add(1, Ordering::SeqCst);
+                async {}
+            },
+        ));
+
+        let mut msg = v4::Message::default();
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Discover));
+        let client = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        client.set_broadcast(true)?;
+        client.send_to(&msg.to_vec().unwrap(), (Ipv4Addr::BROADCAST, port))?;
+
+        let handled_once = async {
+            while handled.load(Ordering::SeqCst) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), handled_once)
+            .await
+            .expect("broadcast wasn't handled");
+        // give any copies time to arrive
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        reader.abort();
+        assert_eq!(handled.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+}
@@ -5,6 +5,37 @@
 //!
 //! /health
 //! /ping
+//! /metrics
+//! /metrics-text
+//! /openapi.json
+//! /v1/reservations/import (POST)
+//! /v1/classes/stats
+//! /v1/v6/server-id
+//! /v1/v6/server-id/rotate (POST)
+//! /v1/quarantine
+//! /v1/quarantine/{ip} (DELETE)
+//! /v1/utilization
+//! /v1/ranges
+//! /v1/leases
+//! /v1/leases/{ip}/history
+//! /v1/leases/fingerprints
+//! /v1/backup (POST)
+//! /v1/backup/latest
+//! /v1/exclusions (GET, POST, DELETE)
+//! /v1/allocations (POST)
+//! /v1/clock/acknowledge (POST)
+//! /v1/config
+//! /v1/decode (POST)
+//! /v1/explain (POST)
+//! /v1/debug/client-protection
+//! /v1/debug/client-protection/{client_id} (DELETE)
+//! /v1/debug/memory
+//! /v1/debug/plugins
+//! /ui (with the `ui` feature)
+//!
+//! A read-only api, see [`ExternalApi::with_read_only`], refuses the requests above that
+//! change leases or server state. Routes that scan the leases table are limited & cached,
+//! see [`ThrottleConfig`].
 #![warn(
     missing_debug_implementations,
     missing_docs,
@@ -17,14 +48,137 @@
 #![allow(clippy::cognitive_complexity, clippy::too_many_arguments)]
 
 use anyhow::{bail, Result};
-use axum::{extract::Extension, routing, Router};
-use ip_manager::{IpManager, Storage};
+use axum::{extract::Extension, middleware, routing, Router};
+use config::LiveConfig;
+use ip_manager::{backup::Backups, IpManager, Storage};
 use tokio::{net::TcpListener, sync::mpsc, task::JoinHandle};
 use tracing::{error, info, trace};
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use crate::models::AllocatorProbe;
+pub use crate::models::{
+    AdminToken, AllocatorStats, ClientProtection, Health, PluginChains, State,
+};
+use crate::throttle::Throttle;
+pub use crate::throttle::ThrottleConfig;
+
+/// how often the `lease_count` & client protection gauges are refreshed, and pool
+/// utilization is fed back to the flood limiters
+const LEASE_METRICS_INTERVAL: Duration = Duration::from_secs(30);
 
-use std::{net::SocketAddr, sync::Arc};
+/// OpenAPI spec for every endpoint, generated from the handlers & [`models`] and served at
+/// `/openapi.json`
+#[derive(OpenApi, Debug)]
+#[openapi(
+    info(
+        title = "dora external api",
+        version = "1",
+        description = "Health checks, metrics, diagnostics & runtime administration for the dora DHCP server. A dora started with `--api-only` serves a read-only copy of the leases database and refuses requests that change leases or server state with 403"
+    ),
+    paths(
+        handlers::ok,
+        handlers::ping,
+        handlers::metrics,
+        handlers::metrics_text,
+        handlers::openapi,
+        handlers::import_reservations,
+        handlers::class_stats,
+        handlers::server_id,
+        handlers::rotate_server_id,
+        handlers::quarantined,
+        handlers::clear_quarantine,
+        handlers::utilization,
+        handlers::ranges,
+        handlers::leases,
+        handlers::lease_history,
+        handlers::fingerprints,
+        handlers::backup,
+        handlers::latest_backup,
+        handlers::exclusions,
+        handlers::add_exclusion,
+        handlers::remove_exclusion,
+        handlers::allocate,
+        handlers::acknowledge_clock,
+        handlers::config,
+        handlers::decode,
+        handlers::explain,
+        handlers::client_protection,
+        handlers::clear_flood,
+        handlers::memory,
+        handlers::plugins
+    ),
+    components(schemas(
+        models::Health,
+        models::HealthReport,
+        models::ImportResult,
+        models::ClassStats,
+        models::ServerId,
+        models::ClockJump,
+        models::Quarantined,
+        models::LeaseFingerprint,
+        models::LeaseState,
+        models::Lease,
+        models::LeasePage,
+        models::LeaseHistoryEntry,
+        models::Backup,
+        models::ExclusionRange,
+        models::RangeUtilization,
+        models::LeaseTimes,
+        models::RangeInfo,
+        models::RenewCacheStats,
+        models::LimitedClient,
+        models::FloodStats,
+        models::ClientProtectionReport,
+        models::MemoryReport,
+        models::AllocatorStats,
+        models::PluginChains,
+        models::PluginEntry,
+        models::DecodedV4,
+        models::DecodedOption,
+        models::AllocationRequest,
+        models::Allocation,
+        models::ExplainRequest,
+        ip_manager::reservations::Invalid,
+        config::PersistIdentifier,
+        config::wire::v4::Boot,
+        config::wire::v4::Exclusion,
+        config::wire::v6::ServerDuidInfo,
+        config::v6::DuidRotation,
+        config::explain::Explanation,
+        config::explain::ExplainedOption
+    )),
+    modifiers(&AdminSecurity)
+)]
+pub struct ApiDoc;
+
+/// the `adminToken` scheme that admin-only operations list under `security`
+struct AdminSecurity;
+
+impl Modify for AdminSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "adminToken",
+                SecurityScheme::Http(
+                    HttpBuilder::new()
+                        .scheme(HttpAuthScheme::Bearer)
+                        .description(Some("the token from `--external-api-admin-token-file`"))
+                        .build(),
+                ),
+            );
+        }
+    }
+}
 
-pub use crate::models::{Health, State};
+/// Web UI for small deployments without a metrics stack, served at `/ui`. Shows pool
+/// utilization, leases, reservations & client protection from the JSON endpoints
+#[cfg(feature = "ui")]
+pub static UI: &str = include_str!("../ui/index.html");
 
 /// The task runner for the [`ExternalApi`]
 ///
@@ -34,6 +188,14 @@ pub struct ExternalApiGuard {
     task_handle: JoinHandle<()>,
 }
 
+impl ExternalApiGuard {
+    /// stop the api & wait for the task to exit, so its address can be bound again
+    pub async fn stop(mut self) {
+        self.task_handle.abort();
+        let _ = (&mut self.task_handle).await;
+    }
+}
+
 impl Drop for ExternalApiGuard {
     fn drop(&mut self) {
         trace!("ExternalApiRunner drop called");
@@ -49,12 +211,20 @@ pub struct ExternalApi<S> {
     rx: mpsc::Receiver<Health>,
     addr: SocketAddr,
     state: State,
+    cfg: LiveConfig,
     ip_mgr: Arc<IpManager<S>>,
+    admin_token: AdminToken,
+    protection: ClientProtection,
+    allocator: AllocatorProbe,
+    plugins: PluginChains,
+    backups: Option<Backups>,
+    throttle: ThrottleConfig,
+    read_only: bool,
 }
 
 impl<S: Storage> ExternalApi<S> {
     /// Create a new ExternalApi instance
-    pub fn new(addr: SocketAddr, ip_mgr: Arc<IpManager<S>>) -> Self {
+    pub fn new(addr: SocketAddr, cfg: impl Into<LiveConfig>, ip_mgr: Arc<IpManager<S>>) -> Self {
         trace!("starting external api");
         let (tx, rx) = mpsc::channel(10);
         let state = models::blank_health();
@@ -63,10 +233,63 @@ impl<S: Storage> ExternalApi<S> {
             rx,
             addr,
             state,
+            cfg: cfg.into(),
             ip_mgr,
+            admin_token: AdminToken::default(),
+            protection: ClientProtection::default(),
+            allocator: AllocatorProbe::default(),
+            plugins: PluginChains::default(),
+            backups: None,
+            throttle: ThrottleConfig::default(),
+            read_only: false,
         }
     }
 
+    /// bearer token required for admin-only requests, these are refused when no token is set
+    pub fn with_admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_token = AdminToken::new(token);
+        self
+    }
+
+    /// renew cache & flood limiters to report on at `/v1/debug/client-protection`
+    pub fn with_client_protection(mut self, protection: ClientProtection) -> Self {
+        self.protection = protection;
+        self
+    }
+
+    /// read the global allocator's stats for `/v1/debug/memory`. The binary picks the
+    /// allocator, so it provides this
+    pub fn with_allocator_stats(mut self, stats: fn() -> Option<AllocatorStats>) -> Self {
+        self.allocator = AllocatorProbe(Some(stats));
+        self
+    }
+
+    /// the plugin order of the v4 & v6 servers, for `/v1/debug/plugins`
+    pub fn with_plugin_chains(mut self, plugins: PluginChains) -> Self {
+        self.plugins = plugins;
+        self
+    }
+
+    /// where `POST /v1/backup` writes backups & `/v1/backup/latest` reads them from, both
+    /// are 404 if this isn't set
+    pub fn with_backups(mut self, backups: Option<Backups>) -> Self {
+        self.backups = backups;
+        self
+    }
+
+    /// concurrency limit & cache TTL of the routes that scan the leases table
+    pub fn with_throttle(mut self, throttle: ThrottleConfig) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// refuse requests that change leases or server state with a 403, for an api serving a
+    /// copy of another server's database
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
     /// clone the health sender channel
     pub fn sender(&self) -> mpsc::Sender<Health> {
         self.tx.clone()
@@ -89,21 +312,165 @@ impl<S: Storage> ExternalApi<S> {
         Ok(())
     }
 
+    /// periodically refresh the per-network `lease_count` & client protection gauges
+    async fn refresh_metrics(
+        cfg: LiveConfig,
+        ip_mgr: Arc<IpManager<S>>,
+        protection: ClientProtection,
+    ) -> Result<()> {
+        let mut interval = tokio::time::interval(LEASE_METRICS_INTERVAL);
+        loop {
+            interval.tick().await;
+            match handlers::range_utilization(&cfg.load(), &ip_mgr).await {
+                Ok(ranges) => {
+                    models::set_lease_metrics(&ranges);
+                    for (network, utilization) in models::network_utilization(&ranges) {
+                        for flood in &protection.flood {
+                            flood.cache().set_utilization(network, utilization);
+                        }
+                    }
+                }
+                Err(err) => error!(?err, "failed to refresh lease metrics"),
+            }
+            models::set_client_protection_metrics(&protection.report());
+        }
+    }
+
     /// serve the HTTP external api
-    async fn run(addr: SocketAddr, state: State, ip_mgr: Arc<IpManager<S>>) -> Result<()> {
+    async fn run(
+        addr: SocketAddr,
+        state: State,
+        cfg: LiveConfig,
+        ip_mgr: Arc<IpManager<S>>,
+        admin_token: AdminToken,
+        protection: ClientProtection,
+        allocator: AllocatorProbe,
+        plugins: PluginChains,
+        backups: Option<Backups>,
+        throttle: ThrottleConfig,
+        read_only: bool,
+    ) -> Result<()> {
         let tcp = TcpListener::bind(&addr).await?;
+        // the routes share one limit, each has its own cache
+        let permits = throttle::permits(throttle);
+        let throttled = |route: &'static str, method: routing::MethodRouter| {
+            method.layer(middleware::from_fn_with_state(
+                Throttle::new(route, throttle, permits.clone()),
+                throttle::limit,
+            ))
+        };
         // Provides:
         // /health
         // /ping
         // /metrics
         // /metrics-text
+        // /openapi.json
+        // /v1/reservations/import
+        // /v1/classes/stats
+        // /v1/v6/server-id
+        // /v1/v6/server-id/rotate
+        // /v1/quarantine
+        // /v1/quarantine/:ip
+        // /v1/utilization
+        // /v1/ranges
+        // /v1/leases
+        // /v1/leases/:ip/history
+        // /v1/leases/fingerprints
+        // /v1/backup
+        // /v1/backup/latest
+        // /v1/exclusions
+        // /v1/allocations
+        // /v1/clock/acknowledge
+        // /v1/config
+        // /v1/decode
+        // /v1/explain
+        // /v1/debug/client-protection
+        // /v1/debug/client-protection/:client_id
+        // /v1/debug/memory
+        // /v1/debug/plugins
+        // /ui
         let app = Router::new()
-            .route("/health", routing::get(handlers::ok))
+            .route("/health", routing::get(handlers::ok::<S>))
             .route("/ping", routing::get(handlers::ping))
             .route("/metrics", routing::get(handlers::metrics))
             .route("/metrics-text", routing::get(handlers::metrics_text))
+            .route("/openapi.json", routing::get(handlers::openapi))
+            .route(
+                "/v1/reservations/import",
+                routing::post(handlers::import_reservations::<S>),
+            )
+            .route("/v1/classes/stats", routing::get(handlers::class_stats))
+            .route("/v1/v6/server-id", routing::get(handlers::server_id))
+            .route(
+                "/v1/v6/server-id/rotate",
+                routing::post(handlers::rotate_server_id),
+            )
+            .route("/v1/quarantine", routing::get(handlers::quarantined::<S>))
+            .route(
+                "/v1/utilization",
+                throttled("utilization", routing::get(handlers::utilization::<S>)),
+            )
+            .route(
+                "/v1/ranges",
+                throttled("ranges", routing::get(handlers::ranges::<S>)),
+            )
+            .route(
+                "/v1/leases",
+                throttled("leases", routing::get(handlers::leases::<S>)),
+            )
+            .route(
+                "/v1/quarantine/:ip",
+                routing::delete(handlers::clear_quarantine::<S>),
+            )
+            .route(
+                "/v1/leases/:ip/history",
+                throttled("lease_history", routing::get(handlers::lease_history::<S>)),
+            )
+            .route(
+                "/v1/leases/fingerprints",
+                throttled("fingerprints", routing::get(handlers::fingerprints::<S>)),
+            )
+            .route("/v1/backup", routing::post(handlers::backup::<S>))
+            .route("/v1/backup/latest", routing::get(handlers::latest_backup))
+            .route(
+                "/v1/exclusions",
+                routing::get(handlers::exclusions::<S>)
+                    .post(handlers::add_exclusion::<S>)
+                    .delete(handlers::remove_exclusion::<S>),
+            )
+            .route("/v1/allocations", routing::post(handlers::allocate::<S>))
+            .route(
+                "/v1/clock/acknowledge",
+                routing::post(handlers::acknowledge_clock),
+            )
+            .route("/v1/config", routing::get(handlers::config))
+            .route("/v1/decode", routing::post(handlers::decode))
+            .route("/v1/explain", routing::post(handlers::explain))
+            .route(
+                "/v1/debug/client-protection",
+                routing::get(handlers::client_protection),
+            )
+            .route(
+                "/v1/debug/client-protection/:client_id",
+                routing::delete(handlers::clear_flood),
+            )
+            .route("/v1/debug/memory", routing::get(handlers::memory::<S>))
+            .route("/v1/debug/plugins", routing::get(handlers::plugins))
             .layer(Extension(state))
-            .layer(Extension(ip_mgr));
+            .layer(Extension(cfg))
+            .layer(Extension(ip_mgr))
+            .layer(Extension(admin_token))
+            .layer(Extension(protection))
+            .layer(Extension(allocator))
+            .layer(Extension(plugins))
+            .layer(Extension(backups));
+        #[cfg(feature = "ui")]
+        let app = app.route("/ui", routing::get(handlers::ui));
+        let app = if read_only {
+            app.layer(middleware::from_fn(handlers::refuse_writes))
+        } else {
+            app
+        };
 
         tracing::debug!("external API listening on {}", addr);
 
@@ -116,13 +483,35 @@ impl<S: Storage> ExternalApi<S> {
     pub fn start(mut self) -> JoinHandle<()> {
         let state = self.state.clone();
         let addr = self.addr;
+        let cfg = self.cfg.clone();
         let ip_mgr = self.ip_mgr.clone();
+        let admin_token = self.admin_token.clone();
+        let protection = self.protection.clone();
+        let allocator = self.allocator;
+        let plugins = self.plugins.clone();
+        let backups = self.backups.clone();
+        let throttle = self.throttle;
+        let read_only = self.read_only;
         // if tx is not cloned, health listen will never update since ExternalApi is owner
 
         tokio::spawn(async move {
-            if let Err(err) =
-                tokio::try_join!(ExternalApi::run(addr, state, ip_mgr), self.listen_status())
-            {
+            if let Err(err) = tokio::try_join!(
+                ExternalApi::run(
+                    addr,
+                    state,
+                    cfg.clone(),
+                    ip_mgr.clone(),
+                    admin_token,
+                    protection.clone(),
+                    allocator,
+                    plugins,
+                    backups,
+                    throttle,
+                    read_only
+                ),
+                ExternalApi::refresh_metrics(cfg, ip_mgr, protection),
+                self.listen_status()
+            ) {
                 error!(?err, "health task returning, this should not happen")
             }
         })
@@ -138,27 +527,93 @@ impl<S: Storage> ExternalApi<S> {
 
 mod handlers {
 
-    use crate::models::{Health, State};
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use crate::models::{
+        self, AdminToken, Allocation, AllocationRequest, AllocatorProbe, Backup, ClassStats,
+        ClientProtection, ClientProtectionReport, ClockJump, ConfigQuery, DecodeQuery, DecodedV4,
+        Encoding, ExclusionRange, ExplainRequest, Health, HealthReport, HistoryQuery, ImportFormat,
+        ImportQuery, ImportResult, Lease, LeaseFingerprint, LeaseHistoryEntry, LeasePage,
+        LeasesQuery, MemoryReport, PluginChains, Quarantined, RangeInfo, RangeUtilization,
+        ServerId, State,
+    };
     use axum::{
         body::Body,
-        extract::Extension,
+        extract::{Extension, Path, Query, Request},
         http::header,
-        http::{Response, StatusCode},
+        http::{HeaderMap, Method, Response, StatusCode},
+        middleware::Next,
         response::IntoResponse,
+        Json,
+    };
+    use base64::Engine;
+    use config::{
+        explain::{self, Explanation},
+        v4::{Exclusions, NetRangeIter},
+        v6::DuidRotation,
+        wire::{v4::Exclusion, v6::ServerDuidInfo},
+        DhcpConfig, LiveConfig,
+    };
+    use dora_core::chrono::{DateTime, SecondsFormat, Utc};
+    use dora_core::metrics::{CLASS_MATCH_COUNT, CLASS_RESPONSE_COUNT, START_TIME, UPTIME};
+    use dora_core::pretty::Hex;
+    use dora_core::server::context::inspect_v4;
+    use ip_manager::{
+        backup::Backups, clock, reservations, Cursor, HistoryFilter, IpError, IpManager, IpState,
+        LeaseFilter, Storage,
     };
-    use dora_core::metrics::{START_TIME, UPTIME};
     use prometheus::{Encoder, ProtobufEncoder, TextEncoder};
-    use tracing::error;
+    use tracing::{error, info, warn};
+    use utoipa::OpenApi;
 
-    pub(crate) async fn ok(
+    /// overall health, the status code follows `status`
+    ///
+    /// The body also reports the ICMP listener. Ping checks are skipped while it's down,
+    /// but addresses are still handed out
+    #[utoipa::path(
+        get,
+        path = "/health",
+        operation_id = "health",
+        responses(
+            (status = 200, description = "healthy", body = HealthReport),
+            (status = 500, description = "unhealthy", body = HealthReport)
+        )
+    )]
+    pub(crate) async fn ok<S: Storage>(
         Extension(state): Extension<State>,
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
     ) -> Result<impl IntoResponse, std::convert::Infallible> {
-        Ok(match *state.lock() {
+        let status = *state.lock();
+        let code = match status {
             Health::Good => StatusCode::OK,
             Health::Bad => StatusCode::INTERNAL_SERVER_ERROR,
-        })
+        };
+        Ok((
+            code,
+            Json(HealthReport {
+                status,
+                icmp_listener: if ip_mgr.icmp_healthy() {
+                    Health::Good
+                } else {
+                    Health::Bad
+                },
+            }),
+        ))
     }
 
+    /// prometheus metrics, protobuf format
+    #[utoipa::path(
+        get,
+        path = "/metrics",
+        operation_id = "metrics",
+        responses(
+            (status = 200, description = "metrics", body = String, content_type = "application/vnd.google.protobuf")
+        )
+    )]
     pub(crate) async fn metrics() -> Result<impl IntoResponse, std::convert::Infallible> {
         UPTIME.set(START_TIME.elapsed().as_secs() as i64);
         let encoder = ProtobufEncoder::new();
@@ -178,6 +633,15 @@ mod handlers {
         }
     }
 
+    /// prometheus metrics, text format
+    #[utoipa::path(
+        get,
+        path = "/metrics-text",
+        operation_id = "metricsText",
+        responses(
+            (status = 200, description = "metrics", body = String, content_type = "text/plain")
+        )
+    )]
     pub(crate) async fn metrics_text() -> Result<impl IntoResponse, std::convert::Infallible> {
         UPTIME.set(START_TIME.elapsed().as_secs() as i64);
         let encoder = TextEncoder::new();
@@ -197,91 +661,3304 @@ mod handlers {
         }
     }
 
+    /// liveness check
+    #[utoipa::path(
+        get,
+        path = "/ping",
+        operation_id = "ping",
+        responses(
+            (status = 200, description = "the api is up")
+        )
+    )]
     pub(crate) async fn ping() -> impl IntoResponse {
         StatusCode::OK
     }
-}
 
-/// Various models for API responses
-pub mod models {
-    use parking_lot::Mutex;
-    use serde::{Deserialize, Serialize};
-    use std::{fmt, sync::Arc};
+    /// this document
+    #[utoipa::path(
+        get,
+        path = "/openapi.json",
+        operation_id = "openapi",
+        responses(
+            (status = 200, description = "OpenAPI spec", body = Object)
+        )
+    )]
+    pub(crate) async fn openapi() -> impl IntoResponse {
+        Json(crate::ApiDoc::openapi())
+    }
 
-    /// The overall health of the system
-    pub type State = Arc<Mutex<Health>>;
-    /// Health is binary Good/Bad at the moment
-    #[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone, Eq)]
-    #[serde(rename_all = "UPPERCASE")]
-    pub enum Health {
-        /// Report good health
-        Good,
-        /// Report bad health
-        Bad,
+    /// the bundled web UI, a single page built on the JSON endpoints
+    #[cfg(feature = "ui")]
+    pub(crate) async fn ui() -> impl IntoResponse {
+        axum::response::Html(crate::UI)
     }
 
-    impl fmt::Display for Health {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            write!(
-                f,
-                "{}",
-                match *self {
-                    Health::Good => "GOOD",
-                    Health::Bad => "BAD",
-                }
+    /// match & response counts for each configured client class
+    #[utoipa::path(
+        get,
+        path = "/v1/classes/stats",
+        operation_id = "classStats",
+        responses(
+            (status = 200, description = "class counters", body = [ClassStats])
+        )
+    )]
+    pub(crate) async fn class_stats(
+        Extension(cfg): Extension<LiveConfig>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        let cfg = cfg.load();
+        let stats = cfg
+            .v4()
+            .classes()
+            .map(|classes| {
+                classes
+                    .names()
+                    .map(|name| ClassStats {
+                        name: name.to_owned(),
+                        matched: CLASS_MATCH_COUNT.with_label_values(&[name]).get(),
+                        responses: CLASS_RESPONSE_COUNT.with_label_values(&[name]).get(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        Ok(Json(stats))
+    }
+
+    /// the DUID the server is answering with & the DUID persisted on disk
+    #[utoipa::path(
+        get,
+        path = "/v1/v6/server-id",
+        operation_id = "serverId",
+        responses(
+            (status = 200, description = "server DUID", body = ServerId),
+            (status = 404, description = "v6 is not configured", body = String, content_type = "text/plain")
+        )
+    )]
+    pub(crate) async fn server_id(
+        Extension(cfg): Extension<LiveConfig>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        let cfg = cfg.load();
+        if !cfg.has_v6() {
+            return Ok((StatusCode::NOT_FOUND, "v6 is not configured").into_response());
+        }
+        let v6 = cfg.v6();
+        let persisted = match v6.persisted_server_id() {
+            Ok(persisted) => persisted,
+            Err(err) => {
+                error!(?err, "failed to read persisted server id");
+                return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+            }
+        };
+        let active = v6
+            .server_id()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        Ok(Json(ServerId {
+            restart_pending: matches!(&persisted, Some(p) if p.identifier != active),
+            path: v6.server_id_path().map(|p| p.display().to_string()),
+            active,
+            persisted,
+        })
+        .into_response())
+    }
+
+    /// generate & persist a new DUID, admin only
+    ///
+    /// An empty body uses the configured `server_id` params. The new DUID is only used
+    /// after a restart
+    #[utoipa::path(
+        post,
+        path = "/v1/v6/server-id/rotate",
+        operation_id = "rotateServerId",
+        request_body = Option<ServerDuidInfo>,
+        responses(
+            (status = 200, description = "rotated", body = DuidRotation),
+            (status = 400, description = "rotation failed", body = String, content_type = "text/plain"),
+            (status = 401, description = "missing or invalid admin token"),
+            (status = 403, description = "no admin token is configured", body = String, content_type = "text/plain"),
+            (status = 404, description = "v6 is not configured", body = String, content_type = "text/plain")
+        ),
+        security(("adminToken" = []))
+    )]
+    pub(crate) async fn rotate_server_id(
+        Extension(cfg): Extension<LiveConfig>,
+        Extension(admin_token): Extension<AdminToken>,
+        headers: HeaderMap,
+        body: Option<Json<ServerDuidInfo>>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        if let Err(resp) = require_admin(&admin_token, &headers) {
+            return Ok(resp);
+        }
+        let cfg = cfg.load();
+        if !cfg.has_v6() {
+            return Ok((StatusCode::NOT_FOUND, "v6 is not configured").into_response());
+        }
+        let info = body.map(|Json(info)| info);
+        Ok(match cfg.v6().rotate_server_id(info.as_ref()) {
+            Ok(rotation) => {
+                rotation
+                    .warnings
+                    .iter()
+                    .for_each(|warning| warn!(%warning, "server DUID rotated"));
+                Json(rotation).into_response()
+            }
+            Err(err) => (StatusCode::BAD_REQUEST, format!("{err:#}")).into_response(),
+        })
+    }
+
+    /// IPs that hit `max_probations` & won't be leased until cleared
+    #[utoipa::path(
+        get,
+        path = "/v1/quarantine",
+        operation_id = "quarantined",
+        responses(
+            (status = 200, description = "quarantined IPs", body = [Quarantined])
+        )
+    )]
+    pub(crate) async fn quarantined<S: Storage>(
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        Ok(match ip_mgr.quarantined().await {
+            Ok(list) => Json(
+                list.into_iter()
+                    .map(|probation| Quarantined {
+                        ip: probation.ip,
+                        probations: probation.count,
+                        last_probated: DateTime::<Utc>::from(probation.last_probated)
+                            .to_rfc3339_opts(SecondsFormat::Secs, true),
+                    })
+                    .collect::<Vec<_>>(),
             )
+            .into_response(),
+            Err(err) => {
+                error!(?err, "failed to get quarantined IPs");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        })
+    }
+
+    /// release a quarantined IP, admin only
+    #[utoipa::path(
+        delete,
+        path = "/v1/quarantine/{ip}",
+        operation_id = "clearQuarantine",
+        params(("ip" = String, Path, description = "IPv4 address, only v4 is quarantined")),
+        responses(
+            (status = 204, description = "released"),
+            (status = 400, description = "not an IPv4 address", body = String, content_type = "text/plain"),
+            (status = 401, description = "missing or invalid admin token"),
+            (status = 403, description = "no admin token is configured", body = String, content_type = "text/plain"),
+            (status = 404, description = "the IP isn't quarantined")
+        ),
+        security(("adminToken" = []))
+    )]
+    pub(crate) async fn clear_quarantine<S: Storage>(
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+        Extension(admin_token): Extension<AdminToken>,
+        Path(ip): Path<Ipv4Addr>,
+        headers: HeaderMap,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        if let Err(resp) = require_admin(&admin_token, &headers) {
+            return Ok(resp);
         }
+        Ok(match ip_mgr.clear_quarantine(ip.into()).await {
+            Ok(true) => StatusCode::NO_CONTENT.into_response(),
+            Ok(false) => StatusCode::NOT_FOUND.into_response(),
+            Err(err) => {
+                error!(?err, "failed to clear quarantine");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        })
     }
 
-    pub(crate) fn blank_health() -> State {
-        Arc::new(Mutex::new(Health::Bad))
+    /// changes to an IP's lease entry, oldest first
+    ///
+    /// `since`/`until` bound the window & `limit` keeps the most recent changes. `at`
+    /// instead returns only the change that left the IP held at that time, or nothing if
+    /// it was free
+    #[utoipa::path(
+        get,
+        path = "/v1/leases/{ip}/history",
+        operation_id = "leaseHistory",
+        params(("ip" = String, Path, description = "IPv4 address, the leases table only holds v4"), HistoryQuery),
+        responses(
+            (status = 200, description = "lease changes", body = [LeaseHistoryEntry]),
+            (status = 400, description = "invalid IPv4 address or timestamp", body = String, content_type = "text/plain"),
+            (status = 429, description = "the endpoint is busy, retry after `Retry-After` seconds", body = String, content_type = "text/plain", headers(("Retry-After" = u64)))
+        )
+    )]
+    pub(crate) async fn lease_history<S: Storage>(
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+        Path(ip): Path<Ipv4Addr>,
+        Query(query): Query<HistoryQuery>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        let (since, until, at) = match (
+            parse_time(query.since.as_deref()),
+            parse_time(query.until.as_deref()),
+            parse_time(query.at.as_deref()),
+        ) {
+            (Ok(since), Ok(until), Ok(at)) => (since, until, at),
+            (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => {
+                return Ok(
+                    (StatusCode::BAD_REQUEST, format!("invalid timestamp: {err}")).into_response(),
+                );
+            }
+        };
+        let history = match at {
+            Some(at) => ip_mgr
+                .held_at(ip.into(), at.into())
+                .await
+                .map(|entry| entry.into_iter().collect()),
+            None => {
+                let filter = HistoryFilter {
+                    since: since.map(Into::into),
+                    until: until.map(Into::into),
+                    limit: query.limit.unwrap_or(HistoryFilter::default().limit),
+                };
+                ip_mgr.lease_history(ip.into(), &filter).await
+            }
+        };
+        Ok(match history {
+            Ok(list) => Json(
+                list.into_iter()
+                    .map(LeaseHistoryEntry::from)
+                    .collect::<Vec<_>>(),
+            )
+            .into_response(),
+            Err(err) => {
+                error!(?err, "failed to get lease history");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::time::Duration;
+    /// a page of un-expired lease entries in IP order
+    #[utoipa::path(
+        get,
+        path = "/v1/leases",
+        operation_id = "leases",
+        params(LeasesQuery),
+        responses(
+            (status = 200, description = "lease entries", body = LeasePage),
+            (status = 400, description = "invalid query", body = String, content_type = "text/plain"),
+            (status = 429, description = "the endpoint is busy, retry after `Retry-After` seconds", body = String, content_type = "text/plain", headers(("Retry-After" = u64)))
+        )
+    )]
+    pub(crate) async fn leases<S: Storage>(
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+        Query(query): Query<LeasesQuery>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        let filter = LeaseFilter {
+            state: query.state.map(IpState::from),
+            ..LeaseFilter::default()
+        };
+        let cursor = Cursor {
+            after: query.after.map(IpAddr::V4),
+            limit: query
+                .limit
+                .unwrap_or(Cursor::DEFAULT_LIMIT)
+                .clamp(1, LeasesQuery::MAX_LIMIT),
+        };
+        Ok(match ip_mgr.select_page(&filter, cursor).await {
+            Ok(page) => Json(LeasePage {
+                leases: page.entries.into_iter().map(Lease::from).collect(),
+                next: page.next.and_then(|next| next.after),
+            })
+            .into_response(),
+            Err(err) => {
+                error!(?err, "failed to get leases");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        })
+    }
 
-    use ip_manager::sqlite::SqliteDb;
+    /// DHCP fingerprints of active leases, recorded with `store_fingerprints`
+    #[utoipa::path(
+        get,
+        path = "/v1/leases/fingerprints",
+        operation_id = "fingerprints",
+        responses(
+            (status = 200, description = "fingerprints by leased IP", body = [LeaseFingerprint]),
+            (status = 429, description = "the endpoint is busy, retry after `Retry-After` seconds", body = String, content_type = "text/plain", headers(("Retry-After" = u64)))
+        )
+    )]
+    pub(crate) async fn fingerprints<S: Storage>(
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        Ok(match ip_mgr.active_fingerprints().await {
+            Ok(list) => Json(
+                list.into_iter()
+                    .map(|(ip, fingerprint)| LeaseFingerprint {
+                        ip,
+                        fingerprint: fingerprint.fingerprint,
+                        prl: fingerprint.prl,
+                        vendor_class: fingerprint.vendor_class,
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .into_response(),
+            Err(err) => {
+                error!(?err, "failed to get lease fingerprints");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        })
+    }
 
-    use super::*;
-    #[tokio::test]
-    async fn test_health() -> anyhow::Result<()> {
-        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
-        let api = ExternalApi::new("0.0.0.0:8889".parse().unwrap(), mgr);
-        let _handle = api.serve();
-        // wait for server to come up
-        tokio::time::sleep(Duration::from_secs(1)).await;
-        let r = reqwest::get("http://0.0.0.0:8889/health")
-            .await?
-            .error_for_status();
-        // initial health state will be BAD i.e. 500
-        match r {
-            Ok(_) => {}
+    /// back up the leases database now, admin only
+    ///
+    /// The oldest backups past `--backup-keep` are removed
+    #[utoipa::path(
+        post,
+        path = "/v1/backup",
+        operation_id = "backup",
+        responses(
+            (status = 200, description = "the backup written", body = Backup),
+            (status = 401, description = "missing or invalid admin token"),
+            (status = 403, description = "no admin token is configured", body = String, content_type = "text/plain"),
+            (status = 404, description = "backups are not configured", body = String, content_type = "text/plain"),
+            (status = 500, description = "the backup failed")
+        ),
+        security(("adminToken" = []))
+    )]
+    pub(crate) async fn backup<S: Storage>(
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+        Extension(backups): Extension<Option<Backups>>,
+        Extension(admin_token): Extension<AdminToken>,
+        headers: HeaderMap,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        if let Err(resp) = require_admin(&admin_token, &headers) {
+            return Ok(resp);
+        }
+        let Some(backups) = backups else {
+            return Ok((StatusCode::NOT_FOUND, "backups are not configured").into_response());
+        };
+        Ok(match backups.run(&ip_mgr).await {
+            Ok(backup) => Json(Backup::from(backup)).into_response(),
             Err(err) => {
-                assert_eq!(
-                    err.status(),
-                    Some(reqwest::StatusCode::INTERNAL_SERVER_ERROR)
-                );
+                error!(?err, "failed to back up the leases database");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
             }
+        })
+    }
+
+    /// the newest backup of the leases database
+    #[utoipa::path(
+        get,
+        path = "/v1/backup/latest",
+        operation_id = "latestBackup",
+        responses(
+            (status = 200, description = "the newest backup", body = Backup),
+            (status = 404, description = "backups are not configured, or there are none yet", body = String, content_type = "text/plain")
+        )
+    )]
+    pub(crate) async fn latest_backup(
+        Extension(backups): Extension<Option<Backups>>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        let Some(backups) = backups else {
+            return Ok((StatusCode::NOT_FOUND, "backups are not configured").into_response());
+        };
+        Ok(match backups.latest().await {
+            Ok(Some(backup)) => Json(Backup::from(backup)).into_response(),
+            Ok(None) => (StatusCode::NOT_FOUND, "no backups yet").into_response(),
+            Err(err) => {
+                error!(?err, "failed to list backups");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        })
+    }
+
+    /// RFC 3339 timestamp
+    fn parse_time(s: Option<&str>) -> Result<Option<DateTime<Utc>>, dora_core::chrono::ParseError> {
+        s.map(|s| DateTime::parse_from_rfc3339(s).map(|t| t.with_timezone(&Utc)))
+            .transpose()
+    }
+
+    /// the parsed config with secrets masked
+    ///
+    /// `?raw=true` returns the config file as it was read, including secrets, and
+    /// requires the admin token
+    #[utoipa::path(
+        get,
+        path = "/v1/config",
+        operation_id = "config",
+        params(ConfigQuery),
+        responses(
+            (status = 200, description = "config, JSON unless `raw` is set", content(("application/json" = Object), ("text/plain" = String))),
+            (status = 401, description = "missing or invalid admin token"),
+            (status = 403, description = "no admin token is configured", body = String, content_type = "text/plain")
+        ),
+        security((), ("adminToken" = []))
+    )]
+    pub(crate) async fn config(
+        Extension(cfg): Extension<LiveConfig>,
+        Extension(admin_token): Extension<AdminToken>,
+        Query(query): Query<ConfigQuery>,
+        headers: HeaderMap,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        let cfg = cfg.load();
+        if !query.raw {
+            return Ok(Json(cfg.wire().clone()).into_response());
+        }
+        if let Err(resp) = require_admin(&admin_token, &headers) {
+            return Ok(resp);
+        }
+        Ok(cfg.raw().to_owned().into_response())
+    }
+
+    /// 403 for requests that aren't reads, in a read-only api. `/v1/decode` & `/v1/explain`
+    /// are POSTs but only read
+    pub(crate) async fn refuse_writes(req: Request, next: Next) -> Response<Body> {
+        let read = matches!(*req.method(), Method::GET | Method::HEAD)
+            || (req.method() == Method::POST
+                && ["/v1/decode", "/v1/explain"].contains(&req.uri().path()));
+        if read {
+            return next.run(req).await;
+        }
+        (
+            StatusCode::FORBIDDEN,
+            "read-only, this api serves a copy of the leases database",
+        )
+            .into_response()
+    }
+
+    /// 403 if no admin token is configured, 401 if the request doesn't carry it as a
+    /// bearer token
+    fn require_admin(admin_token: &AdminToken, headers: &HeaderMap) -> Result<(), Response<Body>> {
+        if admin_token.is_unset() {
+            return Err((StatusCode::FORBIDDEN, "no admin token is configured").into_response());
+        }
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !bearer.is_some_and(|token| admin_token.matches(token)) {
+            warn!("refused admin request, missing or invalid admin token");
+            return Err(StatusCode::UNAUTHORIZED.into_response());
         }
         Ok(())
     }
-    // very simple test for existence of metrics endpoint
-    #[tokio::test]
-    async fn test_metrics() -> anyhow::Result<()> {
-        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
-        let api = ExternalApi::new("0.0.0.0:8888".parse().unwrap(), mgr);
-        let _handle = api.serve();
-        // wait for server to come up
-        tokio::time::sleep(Duration::from_secs(1)).await;
-        let bytes = reqwest::get("http://0.0.0.0:8888/metrics")
-            .await?
-            .error_for_status()?
-            .bytes()
-            .await;
-        assert!(bytes.is_ok());
 
+    /// renew cache size & hit ratio, and the clients the flood limiters are refusing
+    #[utoipa::path(
+        get,
+        path = "/v1/debug/client-protection",
+        operation_id = "clientProtection",
+        responses(
+            (status = 200, description = "client protection state", body = ClientProtectionReport)
+        )
+    )]
+    pub(crate) async fn client_protection(
+        Extension(protection): Extension<ClientProtection>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        let report = protection.report();
+        models::set_client_protection_metrics(&report);
+        Ok(Json(report))
+    }
+
+    /// allocator stats & the sizes of the caches plugins keep per client
+    #[utoipa::path(
+        get,
+        path = "/v1/debug/memory",
+        operation_id = "memory",
+        responses(
+            (status = 200, description = "memory usage", body = MemoryReport)
+        )
+    )]
+    pub(crate) async fn memory<S: Storage>(
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+        Extension(protection): Extension<ClientProtection>,
+        Extension(allocator): Extension<AllocatorProbe>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        Ok(Json(MemoryReport {
+            allocator: allocator.0.and_then(|read| read()),
+            ping_cache: ip_mgr.ping_cache_len(),
+            renew_cache: protection.renew.as_ref().map(|renew| renew.len()),
+            flood_keys: (!protection.flood.is_empty()).then(|| {
+                protection
+                    .flood
+                    .iter()
+                    .map(|flood| flood.cache().len())
+                    .sum()
+            }),
+        }))
+    }
+
+    /// the plugins of each server in the order they run
+    #[utoipa::path(
+        get,
+        path = "/v1/debug/plugins",
+        operation_id = "plugins",
+        responses(
+            (status = 200, description = "plugin order", body = PluginChains)
+        )
+    )]
+    pub(crate) async fn plugins(
+        Extension(plugins): Extension<PluginChains>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        Ok(Json(plugins))
+    }
+
+    /// forget a client's flood state after a false positive, admin only
+    ///
+    /// The client id is a limiter's key in hex, with or without `:` separators, it's
+    /// cleared from every limiter
+    #[utoipa::path(
+        delete,
+        path = "/v1/debug/client-protection/{client_id}",
+        operation_id = "clearFlood",
+        params(("client_id" = String, Path, description = "hex client id, with or without `:` separators")),
+        responses(
+            (status = 204, description = "cleared"),
+            (status = 400, description = "client id must be hex", body = String, content_type = "text/plain"),
+            (status = 401, description = "missing or invalid admin token"),
+            (status = 403, description = "no admin token is configured", body = String, content_type = "text/plain"),
+            (status = 404, description = "the client isn't tracked")
+        ),
+        security(("adminToken" = []))
+    )]
+    pub(crate) async fn clear_flood(
+        Extension(protection): Extension<ClientProtection>,
+        Extension(admin_token): Extension<AdminToken>,
+        Path(client_id): Path<String>,
+        headers: HeaderMap,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        if let Err(resp) = require_admin(&admin_token, &headers) {
+            return Ok(resp);
+        }
+        let Some(id) = parse_hex(&client_id) else {
+            return Ok((StatusCode::BAD_REQUEST, "client id must be hex").into_response());
+        };
+        // not short-circuited, the same key may be tracked by more than one limiter
+        let cleared = protection
+            .flood
+            .iter()
+            .filter(|flood| flood.cache().clear(&id[..]))
+            .count()
+            > 0;
+        Ok(if cleared {
+            info!(%client_id, "cleared flood state for client");
+            StatusCode::NO_CONTENT.into_response()
+        } else {
+            StatusCode::NOT_FOUND.into_response()
+        })
+    }
+
+    /// go back to the wall clock for lease expiry after a clock jump froze it, admin only
+    ///
+    /// Leases expire or are extended by the jump from then on
+    #[utoipa::path(
+        post,
+        path = "/v1/clock/acknowledge",
+        operation_id = "acknowledgeClock",
+        responses(
+            (status = 200, description = "acknowledged", body = ClockJump),
+            (status = 401, description = "missing or invalid admin token"),
+            (status = 403, description = "no admin token is configured", body = String, content_type = "text/plain"),
+            (status = 404, description = "lease expiry isn't frozen", body = String, content_type = "text/plain")
+        ),
+        security(("adminToken" = []))
+    )]
+    pub(crate) async fn acknowledge_clock(
+        Extension(admin_token): Extension<AdminToken>,
+        headers: HeaderMap,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        if let Err(resp) = require_admin(&admin_token, &headers) {
+            return Ok(resp);
+        }
+        Ok(match clock::acknowledge() {
+            Some(offset_secs) => Json(ClockJump { offset_secs }).into_response(),
+            None => (StatusCode::NOT_FOUND, "lease expiry isn't frozen").into_response(),
+        })
+    }
+
+    /// `01:aa:bb` or `01aabb`
+    fn parse_hex(s: &str) -> Option<Vec<u8>> {
+        let s = s.replace(':', "");
+        if s.is_empty() || s.len() % 2 != 0 {
+            return None;
+        }
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
+
+    /// decode a hex or base64 DHCPv4 packet with the decoder the server uses
+    ///
+    /// For troubleshooting captures. Options that fail to decode are still listed, with
+    /// their raw bytes & the error
+    #[utoipa::path(
+        post,
+        path = "/v1/decode",
+        operation_id = "decode",
+        params(DecodeQuery),
+        request_body(content = String, content_type = "text/plain", description = "the packet from the BOOTP op field on, as hex (`:` or `-` separators allowed) or base64"),
+        responses(
+            (status = 200, description = "decoded packet", body = DecodedV4),
+            (status = 400, description = "the body isn't valid hex or base64, or the header doesn't decode", body = String, content_type = "text/plain")
+        )
+    )]
+    pub(crate) async fn decode(
+        Query(query): Query<DecodeQuery>,
+        body: String,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        let packet = body
+            .chars()
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect::<String>();
+        let encoding = query.encoding.unwrap_or_else(|| {
+            if packet
+                .chars()
+                .all(|c| c.is_ascii_hexdigit() || c == ':' || c == '-')
+            {
+                Encoding::Hex
+            } else {
+                Encoding::Base64
+            }
+        });
+        let buf = match encoding {
+            Encoding::Hex => parse_hex(&packet.replace('-', "")),
+            Encoding::Base64 => base64::engine::general_purpose::STANDARD
+                .decode(&packet)
+                .ok(),
+        };
+        let Some(buf) = buf else {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                format!("packet is not valid {encoding}"),
+            )
+                .into_response());
+        };
+        Ok(match inspect_v4(&buf) {
+            Some(inspection) => Json(DecodedV4::from(&inspection)).into_response(),
+            None => (
+                StatusCode::BAD_REQUEST,
+                "packet is too short or its header doesn't decode",
+            )
+                .into_response(),
+        })
+    }
+
+    /// what the running config would answer a client with
+    ///
+    /// Matched classes, reservation or range, lease times & the requested options. Nothing
+    /// is sent or allocated
+    #[utoipa::path(
+        post,
+        path = "/v1/explain",
+        operation_id = "explain",
+        request_body = ExplainRequest,
+        responses(
+            (status = 200, description = "what the client would get", body = Explanation),
+            (status = 400, description = "invalid mac or option", body = String, content_type = "text/plain"),
+            (status = 500, description = "client classes failed to evaluate")
+        )
+    )]
+    pub(crate) async fn explain(
+        Extension(cfg): Extension<LiveConfig>,
+        Json(req): Json<ExplainRequest>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        let cfg = cfg.load();
+        let Some(mac) = parse_hex(&req.mac).filter(|mac| mac.len() <= 16) else {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                "mac must be at most 16 hex bytes".to_owned(),
+            )
+                .into_response());
+        };
+        let msg = req
+            .options
+            .iter()
+            .map(|opt| explain::parse_opt(opt))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .and_then(|opts| explain::request(&mac, &opts));
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(err) => return Ok((StatusCode::BAD_REQUEST, format!("{err:#}")).into_response()),
+        };
+        Ok(match explain::explain(cfg.v4(), req.subnet, &msg) {
+            Ok(explanation) => Json(explanation).into_response(),
+            Err(err) => {
+                error!(?err, "failed to evaluate client classes");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        })
+    }
+
+    /// exclusions added at runtime, these apply on top of the config `except` lists
+    #[utoipa::path(
+        get,
+        path = "/v1/exclusions",
+        operation_id = "exclusions",
+        responses(
+            (status = 200, description = "exclusions", body = [ExclusionRange])
+        )
+    )]
+    pub(crate) async fn exclusions<S: Storage>(
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        Ok(match ip_mgr.exclusions().await {
+            Ok(list) => Json(
+                list.into_iter()
+                    .map(ExclusionRange::from)
+                    .collect::<Vec<_>>(),
+            )
+            .into_response(),
+            Err(err) => {
+                error!(?err, "failed to get exclusions");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        })
+    }
+
+    /// persist an exclusion, admin only
+    ///
+    /// The body is a single IP, a CIDR or a `start`/`end` object
+    #[utoipa::path(
+        post,
+        path = "/v1/exclusions",
+        operation_id = "addExclusion",
+        request_body = Exclusion,
+        responses(
+            (status = 201, description = "added", body = ExclusionRange),
+            (status = 400, description = "start is after end", body = String, content_type = "text/plain"),
+            (status = 401, description = "missing or invalid admin token"),
+            (status = 403, description = "no admin token is configured", body = String, content_type = "text/plain")
+        ),
+        security(("adminToken" = []))
+    )]
+    pub(crate) async fn add_exclusion<S: Storage>(
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+        Extension(admin_token): Extension<AdminToken>,
+        headers: HeaderMap,
+        Json(exclusion): Json<Exclusion>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        if let Err(resp) = require_admin(&admin_token, &headers) {
+            return Ok(resp);
+        }
+        let range = std::ops::RangeInclusive::from(exclusion);
+        if range.start() > range.end() {
+            return Ok((StatusCode::BAD_REQUEST, "start must not be after end").into_response());
+        }
+        Ok(match ip_mgr.add_exclusion(range.clone()).await {
+            Ok(()) => (StatusCode::CREATED, Json(ExclusionRange::from(range))).into_response(),
+            Err(err) => {
+                error!(?err, "failed to add exclusion");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        })
+    }
+
+    /// remove an exclusion added at runtime, admin only
+    #[utoipa::path(
+        delete,
+        path = "/v1/exclusions",
+        operation_id = "removeExclusion",
+        params(ExclusionRange),
+        responses(
+            (status = 204, description = "removed"),
+            (status = 401, description = "missing or invalid admin token"),
+            (status = 403, description = "no admin token is configured", body = String, content_type = "text/plain"),
+            (status = 404, description = "no exclusion with exactly this start & end")
+        ),
+        security(("adminToken" = []))
+    )]
+    pub(crate) async fn remove_exclusion<S: Storage>(
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+        Extension(admin_token): Extension<AdminToken>,
+        Query(range): Query<ExclusionRange>,
+        headers: HeaderMap,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        if let Err(resp) = require_admin(&admin_token, &headers) {
+            return Ok(resp);
+        }
+        Ok(
+            match ip_mgr.remove_exclusion(range.start..=range.end).await {
+                Ok(true) => StatusCode::NO_CONTENT.into_response(),
+                Ok(false) => StatusCode::NOT_FOUND.into_response(),
+                Err(err) => {
+                    error!(?err, "failed to remove exclusion");
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            },
+        )
+    }
+
+    /// longest an allocation can be held for, a week
+    const MAX_ALLOCATION_MINUTES: u32 = 7 * 24 * 60;
+
+    /// hold the next free address in a network for a client that hasn't booted yet
+    ///
+    /// Admin only, ex. for a VM being provisioned. The address is reserved until `minutes`
+    /// pass or the client claims it, asking again for the same client returns the same
+    /// address
+    #[utoipa::path(
+        post,
+        path = "/v1/allocations",
+        operation_id = "allocate",
+        request_body = AllocationRequest,
+        responses(
+            (status = 201, description = "address reserved until `expires_at` or until the client claims it", body = Allocation),
+            (status = 400, description = "invalid mac, client_id or minutes", body = String, content_type = "text/plain"),
+            (status = 401, description = "missing or invalid admin token"),
+            (status = 403, description = "no admin token is configured", body = String, content_type = "text/plain"),
+            (status = 404, description = "no network for subnet", body = String, content_type = "text/plain"),
+            (status = 409, description = "no free address in network", body = String, content_type = "text/plain")
+        ),
+        security(("adminToken" = []))
+    )]
+    pub(crate) async fn allocate<S: Storage>(
+        Extension(cfg): Extension<LiveConfig>,
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+        Extension(admin_token): Extension<AdminToken>,
+        headers: HeaderMap,
+        Json(req): Json<AllocationRequest>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        if let Err(resp) = require_admin(&admin_token, &headers) {
+            return Ok(resp);
+        }
+        let cfg = cfg.load();
+        let bad_request = |msg: &'static str| Ok((StatusCode::BAD_REQUEST, msg).into_response());
+        let Some(mac) = parse_hex(&req.mac).filter(|mac| mac.len() == 6) else {
+            return bad_request("mac must be 6 hex bytes");
+        };
+        // the server keys leases on opt 61 if the client sends it, otherwise chaddr
+        let id = match req.client_id.as_deref().map(parse_hex) {
+            None => mac.clone(),
+            Some(Some(id)) => id,
+            Some(None) => return bad_request("client_id must be hex"),
+        };
+        if !(1..=MAX_ALLOCATION_MINUTES).contains(&req.minutes) {
+            return bad_request("minutes must be from 1 to 10080");
+        }
+        let Some(network) = cfg.v4().network(req.subnet) else {
+            return Ok((StatusCode::NOT_FOUND, "no network for subnet").into_response());
+        };
+        // on the same clock as lease expiry, so it isn't thrown off by a clock jump
+        let expires_at = clock::now() + Duration::from_secs(u64::from(req.minutes) * 60);
+        for range in network.ranges_with_class(None) {
+            match ip_mgr
+                .reserve_first(range, network, &id, expires_at, Some(IpState::Reserve))
+                .await
+            {
+                Ok(IpAddr::V4(ip)) => {
+                    info!(?ip, mac = %req.mac, minutes = req.minutes, "allocated address");
+                    return Ok((
+                        StatusCode::CREATED,
+                        Json(Allocation {
+                            ip,
+                            subnet: network.subnet(),
+                            mac: Hex(&mac).to_string(),
+                            client_id: Hex(&id).to_string(),
+                            expires_at: DateTime::<Utc>::from(expires_at)
+                                .to_rfc3339_opts(SecondsFormat::Secs, true),
+                        }),
+                    )
+                        .into_response());
+                }
+                Ok(IpAddr::V6(_)) => {}
+                Err(IpError::DbError(err)) => {
+                    error!(?err, "failed to allocate address");
+                    return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+                }
+                Err(IpError::Busy(err)) => {
+                    warn!(?err, "storage busy, failed to allocate address");
+                    return Ok(StatusCode::SERVICE_UNAVAILABLE.into_response());
+                }
+                // range is full, try the next
+                Err(_) => {}
+            }
+        }
+        Ok((StatusCode::CONFLICT, "no free address in network").into_response())
+    }
+
+    /// address usage for every configured range, ordered by range start
+    pub(crate) async fn range_utilization<S: Storage>(
+        cfg: &DhcpConfig,
+        ip_mgr: &IpManager<S>,
+    ) -> Result<Vec<RangeUtilization>, IpError<S::Error>> {
+        let mut ranges = Vec::new();
+        for (subnet, network) in cfg.v4().networks() {
+            for range in network.ranges() {
+                let counts = ip_mgr.range_counts(range).await?;
+                let total = range.total_addrs();
+                ranges.push(RangeUtilization {
+                    network: subnet.to_string(),
+                    start: range.start(),
+                    end: range.end(),
+                    total,
+                    free: total.saturating_sub(counts.used()),
+                    leased: counts.leased,
+                    reserved: counts.reserved,
+                    probated: counts.probated,
+                });
+            }
+        }
+        ranges.sort_by_key(|range| range.start);
+        Ok(ranges)
+    }
+
+    /// free/used/probated counts per range, for capacity planning
+    #[utoipa::path(
+        get,
+        path = "/v1/utilization",
+        operation_id = "utilization",
+        responses(
+            (status = 200, description = "range usage", body = [RangeUtilization]),
+            (status = 429, description = "the endpoint is busy, retry after `Retry-After` seconds", body = String, content_type = "text/plain", headers(("Retry-After" = u64)))
+        )
+    )]
+    pub(crate) async fn utilization<S: Storage>(
+        Extension(cfg): Extension<LiveConfig>,
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        let cfg = cfg.load();
+        Ok(match range_utilization(&cfg, &ip_mgr).await {
+            Ok(ranges) => Json(ranges).into_response(),
+            Err(err) => {
+                error!(?err, "failed to get utilization");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        })
+    }
+
+    /// configured ranges with their exclusions, lease times & class gating, and live counts
+    ///
+    /// `total` & `free` also leave out runtime exclusions, which `/v1/utilization` doesn't
+    #[utoipa::path(
+        get,
+        path = "/v1/ranges",
+        operation_id = "ranges",
+        responses(
+            (status = 200, description = "ranges", body = [RangeInfo]),
+            (status = 429, description = "the endpoint is busy, retry after `Retry-After` seconds", body = String, content_type = "text/plain", headers(("Retry-After" = u64)))
+        )
+    )]
+    pub(crate) async fn ranges<S: Storage>(
+        Extension(cfg): Extension<LiveConfig>,
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        let cfg = cfg.load();
+        Ok(match range_info(&cfg, &ip_mgr).await {
+            Ok(ranges) => Json(ranges).into_response(),
+            Err(err) => {
+                error!(?err, "failed to get ranges");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        })
+    }
+
+    async fn range_info<S: Storage>(
+        cfg: &DhcpConfig,
+        ip_mgr: &IpManager<S>,
+    ) -> Result<Vec<RangeInfo>, IpError<S::Error>> {
+        let runtime = Exclusions::new(ip_mgr.exclusions().await?);
+        let mut ranges = Vec::new();
+        for (subnet, network) in cfg.v4().networks() {
+            for range in network.ranges() {
+                let counts = ip_mgr.range_counts(range).await?;
+                let addrs = range.addrs();
+                let total =
+                    NetRangeIter::new(addrs.clone(), &range.exclusions().merge(&runtime)).count();
+                ranges.push(RangeInfo {
+                    network: subnet.to_string(),
+                    start: range.start(),
+                    end: range.end(),
+                    lease_time: range.lease().into(),
+                    class: range.class().map(str::to_owned),
+                    restricted: range.restricted().to_vec(),
+                    exclusions: range
+                        .exclusions()
+                        .iter()
+                        .map(ExclusionRange::from)
+                        .collect(),
+                    runtime_exclusions: runtime
+                        .iter()
+                        .filter(|block| {
+                            block.start() <= addrs.end() && block.end() >= addrs.start()
+                        })
+                        .map(ExclusionRange::from)
+                        .collect(),
+                    total,
+                    free: total.saturating_sub(counts.used()),
+                    leased: counts.leased,
+                    reserved: counts.reserved,
+                    probated: counts.probated,
+                });
+            }
+        }
+        ranges.sort_by_key(|range| range.start);
+        Ok(ranges)
+    }
+
+    /// parse, validate & write a batch of reservations, admin only
+    ///
+    /// Nothing is written if any reservation fails validation or if `dry_run` is set
+    #[utoipa::path(
+        post,
+        path = "/v1/reservations/import",
+        operation_id = "importReservations",
+        params(ImportQuery),
+        request_body(content = String, content_type = ["application/json", "text/csv"], description = "a JSON list of reservations, or `mac,ip,hostname,options` CSV rows"),
+        responses(
+            (status = 200, description = "imported or validated", body = ImportResult),
+            (status = 400, description = "the body failed to parse (text) or reservations failed validation (json)", content(("application/json" = ImportResult), ("text/plain" = String))),
+            (status = 401, description = "missing or invalid admin token"),
+            (status = 403, description = "no admin token is configured", body = String, content_type = "text/plain")
+        ),
+        security(("adminToken" = []))
+    )]
+    pub(crate) async fn import_reservations<S: Storage>(
+        Extension(cfg): Extension<LiveConfig>,
+        Extension(ip_mgr): Extension<Arc<IpManager<S>>>,
+        Extension(admin_token): Extension<AdminToken>,
+        headers: HeaderMap,
+        Query(query): Query<ImportQuery>,
+        body: String,
+    ) -> Result<impl IntoResponse, std::convert::Infallible> {
+        if let Err(resp) = require_admin(&admin_token, &headers) {
+            return Ok(resp);
+        }
+        let cfg = cfg.load();
+        let parsed = match query.format {
+            ImportFormat::Csv => reservations::parse_csv(&body),
+            ImportFormat::Json => reservations::parse_json(&body),
+        };
+        let res = match parsed {
+            Ok(res) => res,
+            Err(err) => {
+                return Ok((StatusCode::BAD_REQUEST, err.to_string()).into_response());
+            }
+        };
+        let errors = reservations::validate(&res, cfg.v4());
+        let status = if !errors.is_empty() {
+            StatusCode::BAD_REQUEST
+        } else if query.dry_run {
+            StatusCode::OK
+        } else if let Err(err) = ip_mgr.import_reservations(&res).await {
+            error!(?err, "failed to import reservations");
+            return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        } else {
+            StatusCode::OK
+        };
+        let imported = if errors.is_empty() && !query.dry_run {
+            res.len()
+        } else {
+            0
+        };
+        Ok((
+            status,
+            Json(ImportResult {
+                imported,
+                dry_run: query.dry_run,
+                errors,
+            }),
+        )
+            .into_response())
+    }
+}
+
+mod throttle {
+    //! Endpoints that scan the leases table compete with DHCP for sqlite, so a dashboard
+    //! polling them during an allocation storm slows down leasing. The throttled routes
+    //! run at most `concurrency` requests at once between them, always fewer than the
+    //! database has connections, & answer the rest with a 429 and `Retry-After`. `200`
+    //! responses are cached for `cache_ttl` by path & query, so pollers asking for the
+    //! same thing share one query.
+
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use axum::{
+        body::{Body, Bytes},
+        extract::{Request, State},
+        http::{header, HeaderValue, Method, Response, StatusCode},
+        middleware::Next,
+        response::IntoResponse,
+    };
+    use dora_core::metrics::{EXTERNAL_API_CACHE_HIT_COUNT, EXTERNAL_API_THROTTLED_COUNT};
+    use ip_manager::sqlite::POOL_SIZE;
+    use parking_lot::Mutex;
+    use tokio::sync::Semaphore;
+    use tracing::{debug, error, warn};
+
+    /// most responses cached per route
+    const MAX_CACHED: usize = 64;
+    /// responses larger than this aren't cached
+    const MAX_CACHED_BODY: usize = 1024 * 1024;
+    /// database connections the throttled routes never take, so DHCP isn't left waiting
+    /// for the pool
+    const RESERVED_CONNECTIONS: usize = 2;
+
+    /// Limits for the throttled routes: `/v1/utilization`, `/v1/ranges`, `/v1/leases`,
+    /// `/v1/leases/{ip}/history` & `/v1/leases/fingerprints`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ThrottleConfig {
+        /// requests the routes run at once between them, from 1 to the database pool size
+        /// less 2
+        pub concurrency: usize,
+        /// how long responses are cached, zero doesn't cache
+        pub cache_ttl: Duration,
+    }
+
+    impl Default for ThrottleConfig {
+        fn default() -> Self {
+            Self {
+                concurrency: 4,
+                cache_ttl: Duration::from_secs(1),
+            }
+        }
+    }
+
+    /// the limit shared by every throttled route
+    pub(crate) fn permits(config: ThrottleConfig) -> Arc<Semaphore> {
+        let max = POOL_SIZE as usize - RESERVED_CONNECTIONS;
+        if config.concurrency > max {
+            warn!(
+                concurrency = config.concurrency,
+                max, "external api concurrency is more than the database pool allows, lowered"
+            );
+        }
+        Arc::new(Semaphore::new(config.concurrency.clamp(1, max)))
+    }
+
+    /// the cache of one route & the limit it shares
+    #[derive(Debug, Clone)]
+    pub(crate) struct Throttle {
+        route: &'static str,
+        ttl: Duration,
+        permits: Arc<Semaphore>,
+        cache: Arc<Mutex<HashMap<String, Cached>>>,
+    }
+
+    #[derive(Debug)]
+    struct Cached {
+        at: Instant,
+        content_type: Option<HeaderValue>,
+        body: Bytes,
+    }
+
+    impl Throttle {
+        /// `permits` is the limit from [`permits`], shared with the other routes
+        pub(crate) fn new(
+            route: &'static str,
+            config: ThrottleConfig,
+            permits: Arc<Semaphore>,
+        ) -> Self {
+            Self {
+                route,
+                ttl: config.cache_ttl,
+                permits,
+                cache: Arc::default(),
+            }
+        }
+
+        fn cached(&self, key: &str) -> Option<Response<Body>> {
+            let cache = self.cache.lock();
+            let cached = cache.get(key).filter(|c| c.at.elapsed() < self.ttl)?;
+            let mut resp = Response::new(Body::from(cached.body.clone()));
+            if let Some(content_type) = &cached.content_type {
+                resp.headers_mut()
+                    .insert(header::CONTENT_TYPE, content_type.clone());
+            }
+            Some(resp)
+        }
+
+        fn cache(&self, key: String, content_type: Option<HeaderValue>, body: Bytes) {
+            let mut cache = self.cache.lock();
+            cache.retain(|_, c| c.at.elapsed() < self.ttl);
+            if cache.len() < MAX_CACHED {
+                cache.insert(
+                    key,
+                    Cached {
+                        at: Instant::now(),
+                        content_type,
+                        body,
+                    },
+                );
+            }
+        }
+
+        /// seconds a throttled client should wait, a hint rather than a promise. A query
+        /// that finishes within the TTL leaves its answer in the cache, but with no cache
+        /// or a query that outlasts the TTL the retry may be throttled again
+        fn retry_after(&self) -> u64 {
+            self.ttl.as_secs().max(1)
+        }
+    }
+
+    /// answer from the cache, or run the request if the route has room for it
+    pub(crate) async fn limit(
+        State(throttle): State<Throttle>,
+        req: Request,
+        next: Next,
+    ) -> Response<Body> {
+        let key = req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| req.uri().path(), |pq| pq.as_str())
+            .to_owned();
+        let cacheable = req.method() == Method::GET && !throttle.ttl.is_zero();
+        if cacheable {
+            if let Some(resp) = throttle.cached(&key) {
+                EXTERNAL_API_CACHE_HIT_COUNT
+                    .with_label_values(&[throttle.route])
+                    .inc();
+                return resp;
+            }
+        }
+        let Ok(_permit) = throttle.permits.try_acquire() else {
+            debug!(route = throttle.route, "throttled external api request");
+            EXTERNAL_API_THROTTLED_COUNT
+                .with_label_values(&[throttle.route])
+                .inc();
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, throttle.retry_after().to_string())],
+                "too many requests for this endpoint, retry later",
+            )
+                .into_response();
+        };
+        let resp = next.run(req).await;
+        if !cacheable || resp.status() != StatusCode::OK {
+            return resp;
+        }
+        // handlers build the whole body in memory already, buffering it costs nothing
+        let (parts, body) = resp.into_parts();
+        let body = match axum::body::to_bytes(body, usize::MAX).await {
+            Ok(body) => body,
+            Err(err) => {
+                error!(?err, "failed to read response body");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        if body.len() <= MAX_CACHED_BODY {
+            throttle.cache(
+                key,
+                parts.headers.get(header::CONTENT_TYPE).cloned(),
+                body.clone(),
+            );
+        }
+        Response::from_parts(parts, Body::from(body))
+    }
+}
+
+/// Various models for API responses
+pub mod models {
+    use client_protection::{FloodLimiter, RenewThreshold};
+    use config::{LeaseTime, PersistIdentifier};
+    use dora_core::{
+        chrono::{DateTime, SecondsFormat, Utc},
+        dhcproto::v4,
+        metrics::{FLOOD_LIMITED_CLIENTS, FLOOD_PRESSURE, LEASE_COUNT, RENEW_CACHE_SIZE},
+        pretty::{Hex, V4OptName, V4OptValue},
+        server::{context::V4Inspection, PluginInfo},
+    };
+    use ip_manager::{reservations::Invalid, HistoryEntry, IpState};
+    use parking_lot::Mutex;
+    use serde::{Deserialize, Serialize};
+    use std::{
+        collections::HashMap,
+        fmt,
+        net::{IpAddr, Ipv4Addr},
+        ops::RangeInclusive,
+        sync::Arc,
+        time::SystemTime,
+    };
+    use utoipa::{IntoParams, ToSchema};
+
+    /// The overall health of the system
+    pub type State = Arc<Mutex<Health>>;
+    /// Health is binary Good/Bad at the moment
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone, Eq)]
+    #[serde(rename_all = "UPPERCASE")]
+    #[derive(ToSchema)]
+    pub enum Health {
+        /// Report good health
+        Good,
+        /// Report bad health
+        Bad,
+    }
+
+    impl fmt::Display for Health {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "{}",
+                match *self {
+                    Health::Good => "GOOD",
+                    Health::Bad => "BAD",
+                }
+            )
+        }
+    }
+
+    /// Body of `/health`
+    #[derive(Serialize, Debug, PartialEq, Copy, Clone, Eq, ToSchema)]
+    pub struct HealthReport {
+        /// overall health, also reflected in the status code
+        pub status: Health,
+        /// the listener for ping check replies
+        pub icmp_listener: Health,
+    }
+
+    pub(crate) fn blank_health() -> State {
+        Arc::new(Mutex::new(Health::Bad))
+    }
+
+    /// Input format for reservation import
+    #[derive(Deserialize, Debug, Default, PartialEq, Copy, Clone, Eq)]
+    #[serde(rename_all = "lowercase")]
+    #[derive(ToSchema)]
+    pub enum ImportFormat {
+        /// `mac,ip,hostname,options` rows
+        Csv,
+        /// a list of reservation objects
+        #[default]
+        Json,
+    }
+
+    /// Query params for `/v1/reservations/import`
+    #[derive(Deserialize, Debug, Default, PartialEq, Copy, Clone, Eq, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct ImportQuery {
+        /// body format, defaults to JSON
+        #[serde(default)]
+        #[param(inline, default = "json")]
+        pub format: ImportFormat,
+        /// validate only, do not write to storage
+        #[serde(default)]
+        #[param(default = false)]
+        pub dry_run: bool,
+    }
+
+    /// Counters for a single client class
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct ClassStats {
+        /// class name
+        pub name: String,
+        /// times the class expression evaluated to true
+        pub matched: u64,
+        /// responses that included options from this class
+        pub responses: u64,
+    }
+
+    /// The DHCPv6 server DUID
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct ServerId {
+        /// hex encoded DUID the server is currently using
+        pub active: String,
+        /// where the DUID is persisted, `None` if it is not persisted
+        pub path: Option<String>,
+        /// contents of the persisted file
+        pub persisted: Option<PersistIdentifier>,
+        /// the persisted DUID was rotated & takes effect on restart
+        pub restart_pending: bool,
+    }
+
+    /// A wall clock jump that was acknowledged
+    #[derive(Serialize, Debug, PartialEq, Clone, Copy, Eq, ToSchema)]
+    pub struct ClockJump {
+        /// seconds the wall clock was ahead of the clock lease expiry was frozen to,
+        /// negative if it was behind
+        pub offset_secs: i64,
+    }
+
+    /// An IP that is quarantined after too many probations
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct Quarantined {
+        /// quarantined IP
+        #[schema(value_type = String)]
+        pub ip: IpAddr,
+        /// times the IP was probated
+        pub probations: u32,
+        /// RFC 3339 timestamp of the last probation
+        #[schema(format = DateTime)]
+        pub last_probated: String,
+    }
+
+    /// DHCP fingerprint of the client holding an active lease
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct LeaseFingerprint {
+        /// leased IP
+        #[schema(value_type = String)]
+        pub ip: IpAddr,
+        /// hash of the PRL, vendor class & option codes, same as `pkt4.fingerprint`
+        pub fingerprint: String,
+        /// opt 55 (parameter request list) as comma separated codes
+        pub prl: String,
+        /// opt 60 (vendor class identifier)
+        pub vendor_class: Option<String>,
+    }
+
+    /// Entry state to filter `/v1/leases` by
+    #[derive(Deserialize, Debug, PartialEq, Copy, Clone, Eq)]
+    #[serde(rename_all = "lowercase")]
+    #[derive(ToSchema)]
+    pub enum LeaseState {
+        /// offered, not yet leased
+        Reserved,
+        /// leased
+        Leased,
+        /// on probation after a decline or a ping reply
+        Probated,
+    }
+
+    impl From<LeaseState> for IpState {
+        fn from(state: LeaseState) -> Self {
+            match state {
+                LeaseState::Reserved => IpState::Reserve,
+                LeaseState::Leased => IpState::Lease,
+                LeaseState::Probated => IpState::Probate,
+            }
+        }
+    }
+
+    /// Query params for `/v1/leases`
+    #[derive(Deserialize, Debug, Default, PartialEq, Copy, Clone, Eq, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct LeasesQuery {
+        /// only entries in this state
+        #[param(inline)]
+        pub state: Option<LeaseState>,
+        /// start after this IP, the `next` of the previous page
+        #[param(value_type = Option<String>, format = "ipv4")]
+        pub after: Option<Ipv4Addr>,
+        /// max entries in the page
+        #[param(minimum = 1, maximum = 1000, default = 100)]
+        pub limit: Option<usize>,
+    }
+
+    impl LeasesQuery {
+        /// largest page returned
+        pub const MAX_LIMIT: usize = 1000;
+    }
+
+    /// An un-expired lease entry
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct Lease {
+        /// the entry's IP
+        #[schema(value_type = String)]
+        pub ip: IpAddr,
+        /// subnet the IP was handed out from
+        #[schema(value_type = String)]
+        pub network: IpAddr,
+        /// hex encoded client id
+        pub client_id: Option<String>,
+        /// `reserved`, `leased` or `probated`
+        #[schema(value_type = LeaseState)]
+        pub state: &'static str,
+        /// RFC 3339 expiry
+        #[schema(format = DateTime)]
+        pub expires_at: String,
+    }
+
+    impl From<ip_manager::State> for Lease {
+        fn from(entry: ip_manager::State) -> Self {
+            let state = match entry {
+                ip_manager::State::Reserved(_) => "reserved",
+                ip_manager::State::Leased(_) => "leased",
+                ip_manager::State::Probated(_) => "probated",
+            };
+            let info = entry.into();
+            Self {
+                ip: info.ip(),
+                network: info.network(),
+                client_id: info.id().map(|id| Hex(id).to_string()),
+                state,
+                expires_at: DateTime::<Utc>::from(info.expires_at())
+                    .to_rfc3339_opts(SecondsFormat::Secs, true),
+            }
+        }
+    }
+
+    /// Body of `/v1/leases`, entries are in IP order
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct LeasePage {
+        /// entries in this page
+        pub leases: Vec<Lease>,
+        /// pass as `after` to get the next page, `None` if this was the last one
+        #[schema(value_type = Option<String>)]
+        pub next: Option<IpAddr>,
+    }
+
+    /// A backup of the leases database
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct Backup {
+        /// the backup file, on the server
+        pub path: String,
+        /// size in bytes
+        pub size: u64,
+        /// RFC 3339 time the backup finished
+        #[schema(format = DateTime)]
+        pub created_at: String,
+    }
+
+    impl From<ip_manager::backup::BackupInfo> for Backup {
+        fn from(backup: ip_manager::backup::BackupInfo) -> Self {
+            Self {
+                path: backup.path.display().to_string(),
+                size: backup.size,
+                created_at: DateTime::<Utc>::from(backup.created_at)
+                    .to_rfc3339_opts(SecondsFormat::Secs, true),
+            }
+        }
+    }
+
+    /// Query params for `/v1/leases/{ip}/history`, timestamps are RFC 3339
+    #[derive(Deserialize, Debug, Default, PartialEq, Clone, Eq, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct HistoryQuery {
+        /// only changes at or after this time
+        #[param(format = DateTime)]
+        pub since: Option<String>,
+        /// only changes at or before this time
+        #[param(format = DateTime)]
+        pub until: Option<String>,
+        /// max changes returned, the most recent are kept
+        pub limit: Option<usize>,
+        /// only the change that left the IP held at this time
+        #[param(format = DateTime)]
+        pub at: Option<String>,
+    }
+
+    /// A single change to an IP's lease entry
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct LeaseHistoryEntry {
+        /// hex encoded client id
+        pub client_id: Option<String>,
+        /// `reserved`, `leased`, `probated` or `removed`
+        #[schema(value_type = String)]
+        pub state: &'static str,
+        /// RFC 3339 expiry after the change, `None` if the entry was removed
+        #[schema(format = DateTime)]
+        pub expires_at: Option<String>,
+        /// RFC 3339 timestamp of the change
+        #[schema(format = DateTime)]
+        pub at: String,
+    }
+
+    impl From<HistoryEntry> for LeaseHistoryEntry {
+        fn from(entry: HistoryEntry) -> Self {
+            let rfc3339 =
+                |t: SystemTime| DateTime::<Utc>::from(t).to_rfc3339_opts(SecondsFormat::Secs, true);
+            Self {
+                client_id: entry.id.as_deref().map(|id| Hex(id).to_string()),
+                state: match entry.state {
+                    Some(IpState::Reserve) => "reserved",
+                    Some(IpState::Lease) => "leased",
+                    Some(IpState::Probate) => "probated",
+                    None => "removed",
+                },
+                expires_at: entry.expires_at.map(rfc3339),
+                at: rfc3339(entry.at),
+            }
+        }
+    }
+
+    /// Query params for `/v1/config`
+    #[derive(Deserialize, Debug, Default, PartialEq, Copy, Clone, Eq, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct ConfigQuery {
+        /// return the config file as read, secrets included. Admin only
+        #[serde(default)]
+        #[param(default = false)]
+        pub raw: bool,
+    }
+
+    /// Bearer token for admin-only requests, never printed by `Debug`
+    #[derive(Default, Clone, PartialEq, Eq)]
+    pub struct AdminToken(Option<Arc<str>>);
+
+    impl AdminToken {
+        /// an empty token is the same as no token
+        pub fn new(token: Option<String>) -> Self {
+            Self(token.filter(|t| !t.is_empty()).map(Arc::from))
+        }
+
+        pub(crate) fn is_unset(&self) -> bool {
+            self.0.is_none()
+        }
+
+        /// compare without exiting early on the first differing byte
+        pub(crate) fn matches(&self, token: &str) -> bool {
+            self.0.as_deref().is_some_and(|expected| {
+                expected.len() == token.len()
+                    && expected
+                        .bytes()
+                        .zip(token.bytes())
+                        .fold(0, |acc, (a, b)| acc | (a ^ b))
+                        == 0
+            })
+        }
+    }
+
+    impl fmt::Debug for AdminToken {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_tuple("AdminToken")
+                .field(&self.0.as_ref().map(|_| "<redacted>"))
+                .finish()
+        }
+    }
+
+    /// An inclusive block of excluded addresses, also the query params for
+    /// `DELETE /v1/exclusions`
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone, Eq, ToSchema, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct ExclusionRange {
+        /// first excluded address
+        #[schema(value_type = String, format = "ipv4")]
+        #[param(value_type = String, format = "ipv4")]
+        pub start: Ipv4Addr,
+        /// last excluded address
+        #[schema(value_type = String, format = "ipv4")]
+        #[param(value_type = String, format = "ipv4")]
+        pub end: Ipv4Addr,
+    }
+
+    impl From<RangeInclusive<Ipv4Addr>> for ExclusionRange {
+        fn from(range: RangeInclusive<Ipv4Addr>) -> Self {
+            Self {
+                start: *range.start(),
+                end: *range.end(),
+            }
+        }
+    }
+
+    /// Address usage for a single range
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct RangeUtilization {
+        /// subnet the range belongs to
+        pub network: String,
+        /// first address in the range
+        #[schema(value_type = String, format = "ipv4")]
+        pub start: Ipv4Addr,
+        /// last address in the range
+        #[schema(value_type = String, format = "ipv4")]
+        pub end: Ipv4Addr,
+        /// addresses in the range, less exclusions
+        pub total: usize,
+        /// addresses available to hand out
+        pub free: usize,
+        /// un-expired leases
+        pub leased: usize,
+        /// offered but not yet leased
+        pub reserved: usize,
+        /// on probation or quarantined
+        pub probated: usize,
+    }
+
+    /// Lease times in seconds, an infinite lease is 4294967295
+    #[derive(Serialize, Debug, PartialEq, Copy, Clone, Eq, ToSchema)]
+    pub struct LeaseTimes {
+        /// used when the client doesn't ask for a lease time
+        pub default: u64,
+        /// shortest lease a client can ask for
+        pub min: u64,
+        /// longest lease a client can ask for
+        pub max: u64,
+    }
+
+    impl From<LeaseTime> for LeaseTimes {
+        fn from(lease: LeaseTime) -> Self {
+            Self {
+                default: lease.get_default().as_secs(),
+                min: lease.get_min().as_secs(),
+                max: lease.get_max().as_secs(),
+            }
+        }
+    }
+
+    /// A configured range with its live usage
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct RangeInfo {
+        /// subnet the range belongs to
+        pub network: String,
+        /// first address in the range
+        #[schema(value_type = String, format = "ipv4")]
+        pub start: Ipv4Addr,
+        /// last address in the range
+        #[schema(value_type = String, format = "ipv4")]
+        pub end: Ipv4Addr,
+        /// lease times for addresses from the range
+        pub lease_time: LeaseTimes,
+        /// only clients in this class get addresses from the range
+        pub class: Option<String>,
+        /// classes whose clients can't get addresses from the range
+        pub restricted: Vec<String>,
+        /// the range's `except` list
+        pub exclusions: Vec<ExclusionRange>,
+        /// exclusions added at runtime that overlap the range
+        pub runtime_exclusions: Vec<ExclusionRange>,
+        /// addresses in the range, less config & runtime exclusions
+        pub total: usize,
+        /// addresses available to hand out
+        pub free: usize,
+        /// un-expired leases
+        pub leased: usize,
+        /// offered but not yet leased
+        pub reserved: usize,
+        /// on probation or quarantined
+        pub probated: usize,
+    }
+
+    /// sum range utilization per network into the `lease_count` gauges
+    pub(crate) fn set_lease_metrics(ranges: &[RangeUtilization]) {
+        let mut networks: HashMap<&str, [usize; 4]> = HashMap::new();
+        for range in ranges {
+            let counts = networks.entry(&range.network).or_default();
+            counts[0] += range.leased;
+            counts[1] += range.reserved;
+            counts[2] += range.probated;
+            counts[3] += range.free;
+        }
+        for (network, counts) in networks {
+            for (state, count) in ["leased", "reserved", "probated", "free"]
+                .into_iter()
+                .zip(counts)
+            {
+                LEASE_COUNT
+                    .with_label_values(&[network, state])
+                    .set(count as i64);
+            }
+        }
+    }
+
+    /// percent of addresses in use in each network with addresses, by network address
+    pub(crate) fn network_utilization(ranges: &[RangeUtilization]) -> Vec<(Ipv4Addr, u8)> {
+        let mut networks: HashMap<&str, (usize, usize)> = HashMap::new();
+        for range in ranges {
+            let (used, total) = networks.entry(&range.network).or_default();
+            *used += range.total - range.free;
+            *total += range.total;
+        }
+        let mut utilization = networks
+            .into_iter()
+            .filter(|(_, (_, total))| *total > 0)
+            // `network` is the subnet's CIDR, from `range_utilization`
+            .filter_map(|(network, (used, total))| {
+                let (addr, _) = network.split_once('/')?;
+                Some((addr.parse().ok()?, (used * 100 / total) as u8))
+            })
+            .collect::<Vec<_>>();
+        utilization.sort_unstable();
+        utilization
+    }
+
+    /// Shared handles to the caches the plugins use for client protection, `None` or empty
+    /// when they aren't configured
+    #[derive(Debug, Clone, Default)]
+    pub struct ClientProtection {
+        /// renew threshold cache from the leases plugin
+        pub renew: Option<Arc<RenewThreshold<Vec<u8>>>>,
+        /// flood limiters from the message type plugin
+        pub flood: Vec<Arc<FloodLimiter>>,
+    }
+
+    impl ClientProtection {
+        pub(crate) fn report(&self) -> ClientProtectionReport {
+            ClientProtectionReport {
+                renew_cache: self.renew.as_ref().map(|renew| {
+                    let stats = renew.stats();
+                    RenewCacheStats {
+                        entries: stats.entries,
+                        hits: stats.hits,
+                        misses: stats.misses,
+                        hit_ratio: stats.hit_ratio(),
+                    }
+                }),
+                flood: (!self.flood.is_empty()).then(|| {
+                    let mut limited =
+                        self.flood
+                            .iter()
+                            .flat_map(|flood| {
+                                flood.cache().limited().into_iter().map(|(id, wait)| {
+                                    LimitedClient {
+                                        key_hex: Hex(&id).to_string(),
+                                        key: flood.name().to_owned(),
+                                        retry_after_ms: wait.as_millis() as u64,
+                                    }
+                                })
+                            })
+                            .collect::<Vec<_>>();
+                    limited.sort_by(|a, b| (&a.key, &a.key_hex).cmp(&(&b.key, &b.key_hex)));
+                    FloodStats {
+                        keys: self.flood.iter().map(|f| f.name().to_owned()).collect(),
+                        tracked: self.flood.iter().map(|f| f.cache().len()).sum(),
+                        limited,
+                        under_pressure: self.flood.iter().any(|f| f.cache().under_pressure()),
+                    }
+                }),
+            }
+        }
+    }
+
+    /// Reads the global allocator's stats, `None` inside when the binary didn't provide
+    /// a way to
+    #[derive(Debug, Clone, Copy, Default)]
+    pub(crate) struct AllocatorProbe(pub(crate) Option<fn() -> Option<AllocatorStats>>);
+
+    /// Global allocator stats, in bytes
+    #[derive(Serialize, Debug, PartialEq, Clone, Copy, ToSchema)]
+    pub struct AllocatorStats {
+        /// allocated by the application
+        pub allocated: u64,
+        /// in active pages, a multiple of the page size & at least `allocated`
+        pub active: u64,
+        /// physically resident, including allocator metadata
+        pub resident: u64,
+        /// share of active pages that isn't allocated, `1 - allocated / active`
+        pub fragmentation: f64,
+    }
+
+    impl AllocatorStats {
+        /// stats from the allocator's counters
+        pub fn new(allocated: u64, active: u64, resident: u64) -> Self {
+            let fragmentation = if active == 0 {
+                0.0
+            } else {
+                1.0 - allocated.min(active) as f64 / active as f64
+            };
+            Self {
+                allocated,
+                active,
+                resident,
+                fragmentation,
+            }
+        }
+    }
+
+    /// Body of `/v1/debug/memory`, caches that aren't configured are `null`
+    #[derive(Serialize, Debug, PartialEq, Clone, ToSchema)]
+    pub struct MemoryReport {
+        /// jemalloc stats, `null` when built without jemalloc
+        pub allocator: Option<AllocatorStats>,
+        /// addresses with a cached ping result, approximate
+        pub ping_cache: u64,
+        /// clients in the renew threshold cache
+        pub renew_cache: Option<usize>,
+        /// clients with flood limiter state
+        pub flood_keys: Option<usize>,
+    }
+
+    /// Body of `/v1/debug/plugins`, each server's plugins in the order they run. `v6`
+    /// is `null` when the v6 server isn't running
+    #[derive(Serialize, Debug, PartialEq, Eq, Clone, Default, ToSchema)]
+    pub struct PluginChains {
+        /// v4 server plugins
+        pub v4: Vec<PluginEntry>,
+        /// v6 server plugins
+        pub v6: Option<Vec<PluginEntry>>,
+    }
+
+    impl PluginChains {
+        /// from the chains the servers report with `plugin_chain`
+        pub fn new(v4: &[PluginInfo], v6: Option<&[PluginInfo]>) -> Self {
+            let entries = |chain: &[PluginInfo]| {
+                chain
+                    .iter()
+                    .map(|plugin| PluginEntry {
+                        name: plugin.name.to_owned(),
+                        depends_on: plugin.depends_on.iter().map(|d| d.to_string()).collect(),
+                    })
+                    .collect()
+            };
+            Self {
+                v4: entries(v4),
+                v6: v6.map(entries),
+            }
+        }
+    }
+
+    /// A plugin & the plugins it was registered to run after
+    #[derive(Serialize, Debug, PartialEq, Eq, Clone, ToSchema)]
+    pub struct PluginEntry {
+        /// plugin name
+        pub name: String,
+        /// plugins it runs after, `<unregistered>` for a dependency that isn't in the chain
+        pub depends_on: Vec<String>,
+    }
+
+    /// Body of `/v1/debug/client-protection`, sections that aren't configured are `null`
+    #[derive(Serialize, Debug, PartialEq, Clone, ToSchema)]
+    pub struct ClientProtectionReport {
+        /// renew threshold cache
+        pub renew_cache: Option<RenewCacheStats>,
+        /// flood limiter
+        pub flood: Option<FloodStats>,
+    }
+
+    /// Renew threshold cache counters, hits & misses are since startup
+    #[derive(Serialize, Debug, PartialEq, Clone, ToSchema)]
+    pub struct RenewCacheStats {
+        /// clients in the cache
+        pub entries: usize,
+        /// renewals answered from the cache
+        pub hits: u64,
+        /// renewals that weren't cached or were past the threshold
+        pub misses: u64,
+        /// hits / (hits + misses)
+        pub hit_ratio: f64,
+    }
+
+    /// Flood limiter state, summed over the limiters
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct FloodStats {
+        /// what each limiter counts packets by, ex. `client_id` or `giaddr+chaddr`
+        pub keys: Vec<String>,
+        /// keys with rate limit state, limited or not
+        pub tracked: usize,
+        /// clients currently being refused
+        pub limited: Vec<LimitedClient>,
+        /// pool utilization has switched at least one network to the tighter `pressure`
+        /// limit
+        pub under_pressure: bool,
+    }
+
+    /// A client the flood limiter is refusing
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct LimitedClient {
+        /// the limiter's key in hex, for a `client_id` limiter opt 61 or the chaddr. This is
+        /// what `DELETE /v1/debug/client-protection/{client_id}` takes
+        pub key_hex: String,
+        /// the limiter refusing it
+        pub key: String,
+        /// time until the next packet from this client is answered
+        pub retry_after_ms: u64,
+    }
+
+    /// set the `renew_cache_size` & `flood_limited_clients` gauges
+    pub(crate) fn set_client_protection_metrics(report: &ClientProtectionReport) {
+        if let Some(renew) = &report.renew_cache {
+            RENEW_CACHE_SIZE.set(renew.entries as i64);
+        }
+        if let Some(flood) = &report.flood {
+            FLOOD_LIMITED_CLIENTS.set(flood.limited.len() as i64);
+            FLOOD_PRESSURE.set(flood.under_pressure as i64);
+        }
+    }
+
+    /// Packet encoding for `/v1/decode`
+    #[derive(Deserialize, Debug, PartialEq, Copy, Clone, Eq)]
+    #[serde(rename_all = "lowercase")]
+    #[derive(ToSchema)]
+    pub enum Encoding {
+        /// hex digits, optionally separated by `:` or `-`
+        Hex,
+        /// standard base64
+        Base64,
+    }
+
+    impl fmt::Display for Encoding {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(match self {
+                Encoding::Hex => "hex",
+                Encoding::Base64 => "base64",
+            })
+        }
+    }
+
+    /// Query params for `/v1/decode`
+    #[derive(Deserialize, Debug, Default, PartialEq, Copy, Clone, Eq, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct DecodeQuery {
+        /// packet encoding, guessed from the body if unset
+        #[param(inline)]
+        pub encoding: Option<Encoding>,
+    }
+
+    /// Body of `/v1/decode`, a DHCPv4 packet broken down field by field
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct DecodedV4 {
+        /// `BootRequest` or `BootReply`
+        pub opcode: String,
+        /// hardware type, ex. `Eth`
+        pub htype: String,
+        /// hardware address length
+        pub hlen: u8,
+        /// relay hops
+        pub hops: u8,
+        /// transaction id, ex. `0x00001234`
+        pub xid: String,
+        /// seconds since the client started
+        pub secs: u16,
+        /// broadcast flag
+        pub broadcast: bool,
+        /// client address
+        #[schema(value_type = String, format = "ipv4")]
+        pub ciaddr: Ipv4Addr,
+        /// your (client) address
+        #[schema(value_type = String, format = "ipv4")]
+        pub yiaddr: Ipv4Addr,
+        /// next server address
+        #[schema(value_type = String, format = "ipv4")]
+        pub siaddr: Ipv4Addr,
+        /// relay address
+        #[schema(value_type = String, format = "ipv4")]
+        pub giaddr: Ipv4Addr,
+        /// client hardware address, hex
+        pub chaddr: String,
+        /// server host name, if set
+        pub sname: Option<String>,
+        /// boot file name, if set
+        pub file: Option<String>,
+        /// options in the order received, split long options are joined
+        pub options: Vec<DecodedOption>,
+        /// code of an option cut off by the end of the packet, it & anything after it
+        /// are dropped
+        pub truncated: Option<u8>,
+    }
+
+    /// A v4 option from `/v1/decode`
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct DecodedOption {
+        /// option code
+        pub code: u8,
+        /// option name, `Unknown` if dhcproto doesn't know the code
+        pub name: String,
+        /// the decoded value as dora logs it, unset if it failed to decode
+        pub value: Option<String>,
+        /// raw option data, hex
+        pub hex: String,
+        /// the raw data as text, if it's all printable
+        pub text: Option<String>,
+        /// why the option failed to decode
+        pub error: Option<String>,
+    }
+
+    impl From<&V4Inspection> for DecodedV4 {
+        fn from(inspection: &V4Inspection) -> Self {
+            let msg = &inspection.header;
+            // sname & file are NUL padded, some clients also NUL terminate strings
+            let text = |bytes: &[u8]| {
+                std::str::from_utf8(bytes)
+                    .ok()
+                    .map(|s| s.trim_end_matches('\0'))
+                    .filter(|s| !s.is_empty() && !s.chars().any(char::is_control))
+                    .map(str::to_owned)
+            };
+            Self {
+                opcode: format!("{:?}", msg.opcode()),
+                htype: format!("{:?}", msg.htype()),
+                hlen: msg.hlen(),
+                hops: msg.hops(),
+                xid: format!("{:#010x}", msg.xid()),
+                secs: msg.secs(),
+                broadcast: msg.flags().broadcast(),
+                ciaddr: msg.ciaddr(),
+                yiaddr: msg.yiaddr(),
+                siaddr: msg.siaddr(),
+                giaddr: msg.giaddr(),
+                chaddr: Hex(msg.chaddr()).to_string(),
+                sname: msg.sname().and_then(text),
+                file: msg.fname().and_then(text),
+                options: inspection
+                    .opts
+                    .iter()
+                    .map(|(code, data, opt)| DecodedOption {
+                        code: *code,
+                        name: V4OptName(v4::OptionCode::from(*code)).to_string(),
+                        value: opt.as_ref().ok().map(|opt| V4OptValue(opt).to_string()),
+                        hex: Hex(data).to_string(),
+                        text: text(data),
+                        error: opt.as_ref().err().map(|err| err.to_string()),
+                    })
+                    .collect(),
+                truncated: inspection.truncated,
+            }
+        }
+    }
+
+    /// Body of `POST /v1/allocations`
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct AllocationRequest {
+        /// network address of the subnet to allocate from, or any address in it
+        #[schema(value_type = String, format = "ipv4")]
+        pub subnet: Ipv4Addr,
+        /// MAC of the client the address is held for
+        pub mac: String,
+        /// hex client id (opt 61) the client will send, if it sends one. Defaults to `mac`
+        pub client_id: Option<String>,
+        /// minutes the address is held for if the client doesn't claim it, at most a week
+        #[schema(minimum = 1, maximum = 10080)]
+        pub minutes: u32,
+    }
+
+    /// Body of `POST /v1/explain`
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct ExplainRequest {
+        /// hex chaddr of the client
+        pub mac: String,
+        /// relay (giaddr) or interface address the client's packets arrive from, picks
+        /// the network
+        #[schema(value_type = String, format = "ipv4")]
+        pub subnet: Ipv4Addr,
+        /// options the client sends as `code=value`, ex. `55=1,3,6`
+        #[serde(default)]
+        pub options: Vec<String>,
+    }
+
+    /// An address held for a client that hasn't booted yet
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct Allocation {
+        /// the held address
+        #[schema(value_type = String, format = "ipv4")]
+        pub ip: Ipv4Addr,
+        /// subnet of the network the address is in
+        #[schema(value_type = String, format = "ipv4")]
+        pub subnet: Ipv4Addr,
+        /// MAC the address is held for
+        pub mac: String,
+        /// hex client id the address is held for
+        pub client_id: String,
+        /// RFC 3339 time the address is released if the client hasn't claimed it
+        #[schema(format = DateTime)]
+        pub expires_at: String,
+    }
+
+    /// Result of a reservation import
+    #[derive(Serialize, Debug, PartialEq, Clone, Eq, ToSchema)]
+    pub struct ImportResult {
+        /// number of reservations written
+        pub imported: usize,
+        /// whether this was a dry run
+        pub dry_run: bool,
+        /// reservations that failed validation
+        pub errors: Vec<Invalid>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::Duration,
+    };
+
+    use config::DhcpConfig;
+    use ip_manager::sqlite::SqliteDb;
+
+    use super::*;
+
+    static SAMPLE_YAML: &str = include_str!("../../libs/config/sample/config.yaml");
+
+    /// serve the api built by `api` on a free port. Returns its base url once it
+    /// answers `/ping`, so tests don't share fixed ports or sleep
+    async fn serve(
+        api: impl FnOnce(SocketAddr) -> ExternalApi<SqliteDb>,
+    ) -> anyhow::Result<(String, ExternalApiGuard)> {
+        // the OS picks the port, it's released for the api to bind
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let handle = api(addr).serve();
+        let url = format!("http://{addr}");
+        for _ in 0..500 {
+            if reqwest::get(format!("{url}/ping")).await.is_ok() {
+                return Ok((url, handle));
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        bail!("external api on {addr} did not come up")
+    }
+
+    #[tokio::test]
+    async fn test_health() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| ExternalApi::new(addr, cfg, mgr)).await?;
+        let r = reqwest::get(format!("{url}/health"))
+            .await?
+            .error_for_status();
+        // initial health state will be BAD i.e. 500
+        match r {
+            Ok(_) => {}
+            Err(err) => {
+                assert_eq!(
+                    err.status(),
+                    Some(reqwest::StatusCode::INTERNAL_SERVER_ERROR)
+                );
+            }
+        }
+        Ok(())
+    }
+    // very simple test for existence of metrics endpoint
+    #[tokio::test]
+    async fn test_metrics() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| ExternalApi::new(addr, cfg, mgr)).await?;
+        let bytes = reqwest::get(format!("{url}/metrics"))
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await;
+        assert!(bytes.is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_reservations() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr.clone()).with_admin_token(Some("letmein".to_owned()))
+        })
+        .await?;
+        let client = reqwest::Client::new();
+        let body = r#"[{ "mac": "aa:bb:cc:dd:ee:01", "ip": "192.168.0.200" }]"#;
+        // admin only, nothing is written without the token
+        let r = client
+            .post(format!("{url}/v1/reservations/import"))
+            .body(body)
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let r = client
+            .post(format!("{url}/v1/reservations/import"))
+            .bearer_auth("wrong")
+            .body(body)
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::UNAUTHORIZED);
+        assert!(mgr
+            .reservation(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01])
+            .await?
+            .is_none());
+
+        // 192.168.0.100 is inside a dynamic range
+        let r = client
+            .post(format!("{url}/v1/reservations/import?format=csv"))
+            .bearer_auth("letmein")
+            .body("aa:bb:cc:dd:ee:01,192.168.0.100,,")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let r = client
+            .post(format!("{url}/v1/reservations/import?dry_run=true"))
+            .bearer_auth("letmein")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        assert_eq!(r.json::<serde_json::Value>().await?["imported"], 0);
+        assert!(mgr
+            .reservation(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01])
+            .await?
+            .is_none());
+
+        client
+            .post(format!("{url}/v1/reservations/import"))
+            .bearer_auth("letmein")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        assert!(mgr
+            .reservation(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01])
+            .await?
+            .is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_class_stats() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| ExternalApi::new(addr, cfg.clone(), mgr)).await?;
+        let stats = reqwest::get(format!("{url}/v1/classes/stats"))
+            .await?
+            .error_for_status()?
+            .json::<Vec<serde_json::Value>>()
+            .await?;
+        assert_eq!(
+            stats.len(),
+            cfg.v4().classes().map(|c| c.names().count()).unwrap_or(0)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_only() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr)
+                .with_admin_token(Some("letmein".to_owned()))
+                .with_read_only(true)
+        })
+        .await?;
+        reqwest::get(format!("{url}/v1/utilization"))
+            .await?
+            .error_for_status()?;
+
+        let client = reqwest::Client::new();
+        let r = client
+            .post(format!(
+                "{url}/v1/exclusions?start=192.168.0.112&end=192.168.0.115"
+            ))
+            .bearer_auth("letmein")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::FORBIDDEN);
+        let r = client
+            .delete(format!("{url}/v1/quarantine/192.168.0.112"))
+            .bearer_auth("letmein")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::FORBIDDEN);
+        // decoding only reads
+        let r = client
+            .post(format!("{url}/v1/decode"))
+            .body("not a packet")
+            .send()
+            .await?;
+        assert_ne!(r.status(), reqwest::StatusCode::FORBIDDEN);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_server_id() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(include_str!(
+            "../../libs/config/sample/config_v6_no_persist.yaml"
+        ))?);
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr).with_admin_token(Some("letmein".to_owned()))
+        })
+        .await?;
+        let id = reqwest::get(format!("{url}/v1/v6/server-id"))
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await?;
+        assert!(id["path"].is_null());
+        assert!(!id["active"].as_str().unwrap().is_empty());
+        let client = reqwest::Client::new();
+        let r = client
+            .post(format!("{url}/v1/v6/server-id/rotate"))
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::UNAUTHORIZED);
+        // nothing to rotate when the DUID isn't persisted
+        let r = client
+            .post(format!("{url}/v1/v6/server-id/rotate"))
+            .bearer_auth("letmein")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_quarantine() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr).with_admin_token(Some("letmein".to_owned()))
+        })
+        .await?;
+        let list = reqwest::get(format!("{url}/v1/quarantine"))
+            .await?
+            .error_for_status()?
+            .json::<Vec<serde_json::Value>>()
+            .await?;
+        assert!(list.is_empty());
+        let client = reqwest::Client::new();
+        let r = client
+            .delete(format!("{url}/v1/quarantine/192.168.0.170"))
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let r = client
+            .delete(format!("{url}/v1/quarantine/192.168.0.170"))
+            .bearer_auth("letmein")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::NOT_FOUND);
+        // v6 is refused before it reaches storage
+        let r = client
+            .delete(format!("{url}/v1/quarantine/2001:db8::1"))
+            .bearer_auth("letmein")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_utilization() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| ExternalApi::new(addr, cfg.clone(), mgr)).await?;
+        let ranges = reqwest::get(format!("{url}/v1/utilization"))
+            .await?
+            .error_for_status()?
+            .json::<Vec<serde_json::Value>>()
+            .await?;
+        assert_eq!(
+            ranges.len(),
+            cfg.v4()
+                .networks()
+                .map(|(_, net)| net.ranges().len())
+                .sum::<usize>()
+        );
+        // nothing is leased yet
+        assert!(ranges.iter().all(|r| r["free"] == r["total"]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ranges() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        mgr.add_exclusion("192.168.0.112".parse()?..="192.168.0.115".parse()?)
+            .await?;
+        let (url, _handle) = serve(|addr| ExternalApi::new(addr, cfg, mgr)).await?;
+        let ranges = reqwest::get(format!("{url}/v1/ranges"))
+            .await?
+            .error_for_status()?
+            .json::<Vec<serde_json::Value>>()
+            .await?;
+        let range = ranges
+            .iter()
+            .find(|r| r["start"] == "192.168.0.100")
+            .expect("range is configured");
+        assert_eq!(range["network"], "192.168.0.0/24");
+        assert_eq!(
+            range["lease_time"],
+            serde_json::json!({ "default": 3600, "min": 1200, "max": 4800 })
+        );
+        assert_eq!(
+            range["exclusions"],
+            serde_json::json!([{ "start": "192.168.0.123", "end": "192.168.0.124" }])
+        );
+        assert_eq!(
+            range["runtime_exclusions"],
+            serde_json::json!([{ "start": "192.168.0.112", "end": "192.168.0.115" }])
+        );
+        // 51 addresses, less 2 from the config & 4 added at runtime
+        assert_eq!(range["total"], 45);
+        assert_eq!(range["free"], 45);
+        let class = ranges
+            .iter()
+            .find(|r| r["start"] == "10.0.0.10")
+            .expect("range is configured");
+        assert_eq!(class["class"], "my_class");
+        assert_eq!(class["runtime_exclusions"], serde_json::json!([]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_exclusions() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr.clone()).with_admin_token(Some("letmein".to_owned()))
+        })
+        .await?;
+        let client = reqwest::Client::new();
+        let r = client
+            .post(format!("{url}/v1/exclusions"))
+            .json(&serde_json::json!("192.168.0.112/30"))
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let r = client
+            .post(format!("{url}/v1/exclusions"))
+            .bearer_auth("letmein")
+            .json(&serde_json::json!({ "start": "192.168.0.120", "end": "192.168.0.110" }))
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let r = client
+            .post(format!("{url}/v1/exclusions"))
+            .bearer_auth("letmein")
+            .json(&serde_json::json!("192.168.0.112/30"))
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::CREATED);
+        let list = reqwest::get(format!("{url}/v1/exclusions"))
+            .await?
+            .error_for_status()?
+            .json::<Vec<serde_json::Value>>()
+            .await?;
+        assert_eq!(
+            list,
+            vec![serde_json::json!({ "start": "192.168.0.112", "end": "192.168.0.115" })]
+        );
+
+        let r = client
+            .delete(format!(
+                "{url}/v1/exclusions?start=192.168.0.112&end=192.168.0.115"
+            ))
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let r = client
+            .delete(format!(
+                "{url}/v1/exclusions?start=192.168.0.112&end=192.168.0.115"
+            ))
+            .bearer_auth("letmein")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::NO_CONTENT);
+        let r = client
+            .delete(format!(
+                "{url}/v1/exclusions?start=192.168.0.112&end=192.168.0.115"
+            ))
+            .bearer_auth("letmein")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::NOT_FOUND);
+        assert!(mgr.exclusions().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_config() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr).with_admin_token(Some("letmein".to_owned()))
+        })
+        .await?;
+        let parsed = reqwest::get(format!("{url}/v1/config"))
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await?;
+        assert!(parsed["networks"].is_object());
+
+        let client = reqwest::Client::new();
+        let r = client
+            .get(format!("{url}/v1/config?raw=true"))
+            .bearer_auth("wrong")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let raw = client
+            .get(format!("{url}/v1/config?raw=true"))
+            .bearer_auth("letmein")
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        assert_eq!(raw, SAMPLE_YAML);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_memory() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let flood = Arc::new(client_protection::FloodLimiter::new(
+            config::v4::FloodThreshold::new(1, Duration::from_secs(60)),
+        ));
+        assert!(flood.cache().is_allowed(&vec![1, 2, 0xab]));
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr)
+                .with_client_protection(ClientProtection {
+                    renew: None,
+                    flood: vec![flood],
+                })
+                .with_allocator_stats(|| Some(AllocatorStats::new(750, 1_000, 2_000)))
+        })
+        .await?;
+        let report = reqwest::get(format!("{url}/v1/debug/memory"))
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await?;
+        assert_eq!(report["allocator"]["active"], 1_000);
+        assert_eq!(report["allocator"]["fragmentation"], 0.25);
+        assert_eq!(report["ping_cache"], 0);
+        assert!(report["renew_cache"].is_null());
+        assert_eq!(report["flood_keys"], 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_plugins() -> anyhow::Result<()> {
+        use dora_core::server::PluginInfo;
+
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let v4 = [
+            PluginInfo {
+                name: "MsgType",
+                depends_on: vec![],
+            },
+            PluginInfo {
+                name: "Leases",
+                depends_on: vec!["MsgType", "<unregistered>"],
+            },
+        ];
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr).with_plugin_chains(PluginChains::new(&v4, None))
+        })
+        .await?;
+        let chains = reqwest::get(format!("{url}/v1/debug/plugins"))
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await?;
+        assert_eq!(
+            chains,
+            serde_json::json!({
+                "v4": [
+                    { "name": "MsgType", "depends_on": [] },
+                    { "name": "Leases", "depends_on": ["MsgType", "<unregistered>"] },
+                ],
+                "v6": null,
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_client_protection() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let flood = Arc::new(client_protection::FloodLimiter::new(
+            config::v4::FloodThreshold::new(1, Duration::from_secs(60)),
+        ));
+        assert!(flood.cache().is_allowed(&vec![1, 2, 0xab]));
+        assert!(!flood.cache().is_allowed(&vec![1, 2, 0xab]));
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr)
+                .with_admin_token(Some("letmein".to_owned()))
+                .with_client_protection(ClientProtection {
+                    renew: None,
+                    flood: vec![flood.clone()],
+                })
+        })
+        .await?;
+        let report = reqwest::get(format!("{url}/v1/debug/client-protection"))
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await?;
+        assert!(report["renew_cache"].is_null());
+        assert_eq!(report["flood"]["tracked"], 1);
+        assert_eq!(report["flood"]["under_pressure"], false);
+        assert_eq!(report["flood"]["keys"], serde_json::json!(["client_id"]));
+        assert_eq!(report["flood"]["limited"][0]["key_hex"], "01:02:ab");
+        assert_eq!(report["flood"]["limited"][0]["key"], "client_id");
+
+        let client = reqwest::Client::new();
+        let r = client
+            .delete(format!("{url}/v1/debug/client-protection/01:02:ab"))
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let r = client
+            .delete(format!("{url}/v1/debug/client-protection/0102ab"))
+            .bearer_auth("letmein")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::NO_CONTENT);
+        let r = client
+            .delete(format!("{url}/v1/debug/client-protection/01:02:ab"))
+            .bearer_auth("letmein")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::NOT_FOUND);
+        assert!(flood.cache().is_allowed(&vec![1, 2, 0xab]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_acknowledge_clock() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr).with_admin_token(Some("letmein".to_owned()))
+        })
+        .await?;
+        let client = reqwest::Client::new();
+        let r = client
+            .post(format!("{url}/v1/clock/acknowledge"))
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::UNAUTHORIZED);
+        // clock jumps aren't watched here, so expiry is never frozen
+        let r = client
+            .post(format!("{url}/v1/clock/acknowledge"))
+            .bearer_auth("letmein")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::NOT_FOUND);
+        Ok(())
+    }
+    #[test]
+    fn test_network_utilization() {
+        let range = |network: &str, total, free| models::RangeUtilization {
+            network: network.to_owned(),
+            start: Ipv4Addr::UNSPECIFIED,
+            end: Ipv4Addr::UNSPECIFIED,
+            total,
+            free,
+            leased: total - free,
+            reserved: 0,
+            probated: 0,
+        };
+        assert!(models::network_utilization(&[]).is_empty());
+        // ranges in a network are summed, networks without addresses are left out
+        assert_eq!(
+            models::network_utilization(&[
+                range("10.0.0.0/24", 10, 0),
+                range("10.0.0.0/24", 10, 10),
+                range("10.1.0.0/24", 100, 90),
+                range("10.2.0.0/24", 0, 0),
+            ]),
+            vec![
+                (Ipv4Addr::new(10, 0, 0, 0), 50),
+                (Ipv4Addr::new(10, 1, 0, 0), 10)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lease_history() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let ip = "192.168.0.150".parse::<IpAddr>()?;
+        mgr.try_ip(
+            ip,
+            "192.168.0.0".parse()?,
+            &[1, 0xab],
+            std::time::SystemTime::now() + Duration::from_secs(3600),
+            &config::v4::Network::default(),
+            Some(ip_manager::IpState::Lease),
+        )
+        .await?;
+        let (url, _handle) = serve(|addr| ExternalApi::new(addr, cfg, mgr.clone())).await?;
+        let history = reqwest::get(format!("{url}/v1/leases/192.168.0.150/history"))
+            .await?
+            .error_for_status()?
+            .json::<Vec<serde_json::Value>>()
+            .await?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["client_id"], "01:ab");
+        assert_eq!(history[0]["state"], "leased");
+
+        let held = reqwest::get(format!(
+            "{url}/v1/leases/192.168.0.150/history?at=2000-01-01T00:00:00Z"
+        ))
+        .await?
+        .error_for_status()?
+        .json::<Vec<serde_json::Value>>()
+        .await?;
+        assert!(held.is_empty());
+
+        let r = reqwest::get(format!(
+            "{url}/v1/leases/192.168.0.150/history?since=yesterday"
+        ))
+        .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::BAD_REQUEST);
+        // only v4 is stored, v6 is refused before it reaches storage
+        let r = reqwest::get(format!("{url}/v1/leases/2001:db8::1/history")).await?;
+        assert_eq!(r.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let page = reqwest::get(format!("{url}/v1/leases?state=leased"))
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await?;
+        assert_eq!(page["leases"][0]["ip"], "192.168.0.150");
+        assert_eq!(page["leases"][0]["network"], "192.168.0.0");
+        assert_eq!(page["leases"][0]["client_id"], "01:ab");
+        assert!(page["next"].is_null());
+        let page = reqwest::get(format!("{url}/v1/leases?after=192.168.0.150"))
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await?;
+        assert_eq!(page["leases"], serde_json::json!([]));
+        let r = reqwest::get(format!("{url}/v1/leases?state=expired")).await?;
+        assert_eq!(r.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        mgr.set_fingerprint(
+            ip,
+            &[1, 0xab],
+            &ip_manager::LeaseFingerprint {
+                fingerprint: "0123456789abcdef".to_owned(),
+                prl: "1,3,6".to_owned(),
+                vendor_class: None,
+            },
+        )
+        .await?;
+        let fingerprints = reqwest::get(format!("{url}/v1/leases/fingerprints"))
+            .await?
+            .error_for_status()?
+            .json::<Vec<serde_json::Value>>()
+            .await?;
+        assert_eq!(
+            fingerprints,
+            [serde_json::json!({
+                "ip": "192.168.0.150",
+                "fingerprint": "0123456789abcdef",
+                "prl": "1,3,6",
+                "vendor_class": null,
+            })]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_allocations() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr).with_admin_token(Some("letmein".to_owned()))
+        })
+        .await?;
+        let client = reqwest::Client::new();
+        let allocate = |body: serde_json::Value| {
+            client
+                .post(format!("{url}/v1/allocations"))
+                .bearer_auth("letmein")
+                .json(&body)
+                .send()
+        };
+
+        // admin only
+        let r = client
+            .post(format!("{url}/v1/allocations"))
+            .json(&serde_json::json!({
+                "subnet": "192.168.0.0", "mac": "01:02:03:04:05:06", "minutes": 10
+            }))
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let r = allocate(serde_json::json!({
+            "subnet": "192.168.0.0", "mac": "01:02:03:04:05:06", "minutes": 10
+        }))
+        .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::CREATED);
+        let first = r.json::<serde_json::Value>().await?;
+        let ip = first["ip"].as_str().unwrap().parse::<Ipv4Addr>()?;
+        assert!((Ipv4Addr::new(192, 168, 0, 100)..=Ipv4Addr::new(192, 168, 0, 150)).contains(&ip));
+        assert_eq!(first["client_id"], "01:02:03:04:05:06");
+
+        // same client gets the same address, another client gets a different one
+        let again = allocate(serde_json::json!({
+            "subnet": "192.168.0.1", "mac": "01:02:03:04:05:06", "minutes": 10
+        }))
+        .await?
+        .json::<serde_json::Value>()
+        .await?;
+        assert_eq!(again["ip"], first["ip"]);
+        let other = allocate(serde_json::json!({
+            "subnet": "192.168.0.0", "mac": "01:02:03:04:05:07", "client_id": "01:01:02:03:04:05:07", "minutes": 10
+        }))
+        .await?
+        .json::<serde_json::Value>()
+        .await?;
+        assert_ne!(other["ip"], first["ip"]);
+        assert_eq!(other["client_id"], "01:01:02:03:04:05:07");
+
+        let r = allocate(serde_json::json!({
+            "subnet": "192.168.0.0", "mac": "01:02:03", "minutes": 10
+        }))
+        .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::BAD_REQUEST);
+        let r = allocate(serde_json::json!({
+            "subnet": "192.168.0.0", "mac": "01:02:03:04:05:06", "minutes": 0
+        }))
+        .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::BAD_REQUEST);
+        let r = allocate(serde_json::json!({
+            "subnet": "192.168.0.0", "mac": "01:02:03:04:05:06", "minutes": 10081
+        }))
+        .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::BAD_REQUEST);
+        let r = allocate(serde_json::json!({
+            "subnet": "10.9.9.0", "mac": "01:02:03:04:05:06", "minutes": 10
+        }))
+        .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode() -> anyhow::Result<()> {
+        use base64::Engine;
+        use dora_core::dhcproto::{v4, Encodable};
+
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| ExternalApi::new(addr, cfg, mgr)).await?;
+
+        let mut msg = v4::Message::default();
+        msg.set_xid(0x1234)
+            .set_chaddr(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Discover));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::Hostname("printer".to_owned()));
+        let buf = msg.to_vec()?;
+        let hex = buf.iter().map(|b| format!("{b:02x}")).collect::<String>();
+        let b64 = base64::engine::general_purpose::STANDARD.encode(&buf);
+
+        let client = reqwest::Client::new();
+        for body in [hex, b64] {
+            let decoded = client
+                .post(format!("{url}/v1/decode"))
+                .body(body)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<serde_json::Value>()
+                .await?;
+            assert_eq!(decoded["xid"], "0x00001234");
+            assert_eq!(decoded["chaddr"], "aa:bb:cc:dd:ee:ff");
+            let opts = decoded["options"].as_array().unwrap();
+            assert!(opts.contains(&serde_json::json!({
+                "code": 12,
+                "name": "Hostname",
+                "value": "\"printer\"",
+                "hex": "70:72:69:6e:74:65:72",
+                "text": "printer",
+                "error": null,
+            })));
+            assert!(opts.iter().any(|opt| opt["name"] == "MessageType"));
+            assert!(decoded["truncated"].is_null());
+        }
+
+        let r = client
+            .post(format!("{url}/v1/decode?encoding=hex"))
+            .body("not hex")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::BAD_REQUEST);
+        let r = client
+            .post(format!("{url}/v1/decode"))
+            .body("01:01:06:00")
+            .send()
+            .await?;
+        assert_eq!(r.status(), reqwest::StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_explain() -> anyhow::Result<()> {
+        use external_api_client::{models::ExplainRequest, Client};
+
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| ExternalApi::new(addr, cfg, mgr)).await?;
+        let client = Client::new(&url);
+
+        let explanation = client
+            .explain(&ExplainRequest {
+                mac: "aa:bb:cc:dd:ee:01".to_owned(),
+                subnet: [192, 168, 0, 1].into(),
+                options: vec!["55=1,3".to_owned()],
+            })
+            .await?;
+        assert_eq!(explanation.network.as_deref(), Some("192.168.0.0/24"));
+        let range = explanation.range.unwrap();
+        assert_eq!(range.start, Ipv4Addr::new(192, 168, 0, 100));
+        assert_eq!(explanation.lease_secs, Some(3600));
+        assert_eq!(
+            explanation
+                .options
+                .iter()
+                .map(|opt| opt.code)
+                .collect::<Vec<_>>(),
+            [1, 3]
+        );
+
+        let err = client
+            .explain(&ExplainRequest {
+                mac: "aa:bb:cc:dd:ee:01".to_owned(),
+                subnet: [192, 168, 0, 1].into(),
+                options: vec!["55".to_owned()],
+            })
+            .await;
+        assert!(matches!(
+            err,
+            Err(external_api_client::Error::Status { status, .. })
+                if status == reqwest::StatusCode::BAD_REQUEST
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_openapi() -> anyhow::Result<()> {
+        use external_api_client::{models::ExclusionRange, Client};
+
+        let spec = serde_json::to_value(ApiDoc::openapi())?;
+        let mut paths = spec["paths"]
+            .as_object()
+            .expect("spec has paths")
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        paths.sort_unstable();
+        // every route in `run`
+        let mut routes = vec![
+            "/health",
+            "/ping",
+            "/metrics",
+            "/metrics-text",
+            "/openapi.json",
+            "/v1/reservations/import",
+            "/v1/classes/stats",
+            "/v1/v6/server-id",
+            "/v1/v6/server-id/rotate",
+            "/v1/quarantine",
+            "/v1/quarantine/{ip}",
+            "/v1/utilization",
+            "/v1/ranges",
+            "/v1/leases",
+            "/v1/leases/{ip}/history",
+            "/v1/leases/fingerprints",
+            "/v1/backup",
+            "/v1/backup/latest",
+            "/v1/exclusions",
+            "/v1/allocations",
+            "/v1/clock/acknowledge",
+            "/v1/config",
+            "/v1/decode",
+            "/v1/explain",
+            "/v1/debug/client-protection",
+            "/v1/debug/client-protection/{client_id}",
+            "/v1/debug/memory",
+            "/v1/debug/plugins",
+        ];
+        routes.sort_unstable();
+        assert_eq!(paths, routes);
+
+        // every `$ref` points at a schema registered in `ApiDoc`
+        fn refs<'a>(value: &'a serde_json::Value, found: &mut Vec<&'a str>) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    found.extend(map.get("$ref").and_then(|r| r.as_str()));
+                    map.values().for_each(|value| refs(value, found));
+                }
+                serde_json::Value::Array(list) => list.iter().for_each(|value| refs(value, found)),
+                _ => {}
+            }
+        }
+        let mut found = Vec::new();
+        refs(&spec, &mut found);
+        assert!(!found.is_empty());
+        for r in found {
+            let name = r
+                .strip_prefix("#/components/schemas/")
+                .expect("refs are to schemas");
+            assert!(
+                spec["components"]["schemas"].get(name).is_some(),
+                "{r} is not registered"
+            );
+        }
+        assert_eq!(
+            spec["components"]["securitySchemes"]["adminToken"]["scheme"],
+            "bearer"
+        );
+        // every route that changes state needs the admin token, decode & explain only read
+        for (path, ops) in spec["paths"].as_object().unwrap() {
+            for method in ["post", "delete"] {
+                if ops.get(method).is_none() || ["/v1/decode", "/v1/explain"].contains(&&**path) {
+                    continue;
+                }
+                assert_eq!(
+                    ops[method]["security"],
+                    serde_json::json!([{ "adminToken": [] }]),
+                    "{method} {path} isn't admin only"
+                );
+            }
+        }
+
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr).with_admin_token(Some("letmein".to_owned()))
+        })
+        .await?;
+        let client = Client::new(&url).with_admin_token("letmein");
+        assert_eq!(client.openapi().await?, spec);
+        // initial health state is BAD but the report is still returned
+        assert_eq!(
+            client.health().await?.status,
+            external_api_client::models::Health::Bad
+        );
+        client.ping().await?;
+        client.class_stats().await?;
+        assert!(!client.utilization().await?.is_empty());
+        assert_eq!(
+            client.ranges().await?.len(),
+            client.utilization().await?.len()
+        );
+
+        let range = ExclusionRange {
+            start: "192.168.0.112".parse()?,
+            end: "192.168.0.115".parse()?,
+        };
+        assert_eq!(client.add_exclusion(range).await?, range);
+        assert_eq!(client.exclusions().await?, vec![range]);
+        assert!(client.remove_exclusion(range).await?);
+        assert!(!client.remove_exclusion(range).await?);
+        let allocation = client
+            .allocate(&external_api_client::models::AllocationRequest {
+                subnet: "192.168.0.0".parse()?,
+                mac: "01:02:03:04:05:06".into(),
+                client_id: None,
+                minutes: 5,
+            })
+            .await?;
+        assert_eq!(allocation.client_id, allocation.mac);
+
+        let page = client
+            .leases(&external_api_client::models::LeasesQuery::default())
+            .await?;
+        assert!(page.next.is_none());
+        // backups aren't configured
+        assert!(client.backup().await.is_err());
+
+        let report = client.client_protection().await?;
+        assert!(report.renew_cache.is_none() && report.flood.is_none());
+        assert!(client.decode("01:01:06:00").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_throttle() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr.clone()).with_throttle(ThrottleConfig {
+                concurrency: 1,
+                cache_ttl: Duration::from_secs(60),
+            })
+        })
+        .await?;
+        let leases = |url: String| async move {
+            reqwest::get(url)
+                .await?
+                .error_for_status()?
+                .json::<serde_json::Value>()
+                .await
+        };
+        let page = leases(format!("{url}/v1/leases")).await?;
+        assert_eq!(page["leases"], serde_json::json!([]));
+        mgr.try_ip(
+            "192.168.0.150".parse()?,
+            "192.168.0.0".parse()?,
+            &[1, 0xab],
+            std::time::SystemTime::now() + Duration::from_secs(3600),
+            &config::v4::Network::default(),
+            Some(ip_manager::IpState::Lease),
+        )
+        .await?;
+        // answered from the cache
+        assert_eq!(leases(format!("{url}/v1/leases")).await?, page);
+        let page = leases(format!("{url}/v1/leases?state=leased")).await?;
+        assert_eq!(page["leases"][0]["ip"], "192.168.0.150");
+
+        // a request over the limit is refused rather than queued, the limit is shared
+        // between routes
+        let config = ThrottleConfig {
+            concurrency: 1,
+            cache_ttl: Duration::ZERO,
+        };
+        let permits = throttle::permits(config);
+        let slow = |route: &'static str| {
+            routing::get(|| async {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                "done"
+            })
+            .layer(middleware::from_fn_with_state(
+                Throttle::new(route, config, permits.clone()),
+                throttle::limit,
+            ))
+        };
+        let slow = Router::new()
+            .route("/slow", slow("slow"))
+            .route("/slower", slow("slower"));
+        let tcp = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = tcp.local_addr()?;
+        let server = tokio::spawn(async move { axum::serve(tcp, slow).await });
+        let (a, b) = tokio::join!(
+            reqwest::get(format!("http://{addr}/slow")),
+            reqwest::get(format!("http://{addr}/slower"))
+        );
+        server.abort();
+        let (a, b) = (a?, b?);
+        let refused = if a.status() == reqwest::StatusCode::OK {
+            b
+        } else {
+            a
+        };
+        assert_eq!(refused.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(refused.headers()["retry-after"], "1");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backup() -> anyhow::Result<()> {
+        use external_api_client::Client;
+
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let dir = std::env::temp_dir().join(format!("dora-api-backups-{}", std::process::id()));
+        let (url, _handle) = serve(|addr| {
+            ExternalApi::new(addr, cfg, mgr)
+                .with_backups(Some(Backups::new(&dir, 2)))
+                .with_admin_token(Some("letmein".to_owned()))
+        })
+        .await?;
+        assert!(matches!(
+            Client::new(&url).backup().await,
+            Err(external_api_client::Error::Status { status, .. })
+                if status == reqwest::StatusCode::UNAUTHORIZED
+        ));
+        let client = Client::new(&url).with_admin_token("letmein");
+        assert!(matches!(
+            client.latest_backup().await,
+            Err(external_api_client::Error::Status { status, .. })
+                if status == reqwest::StatusCode::NOT_FOUND
+        ));
+        let backup = client.backup().await?;
+        assert!(backup.path.starts_with(&*dir.to_string_lossy()));
+        assert!(backup.size > 0);
+        assert_eq!(client.latest_backup().await?, backup);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "ui")]
+    #[tokio::test]
+    async fn test_ui() -> anyhow::Result<()> {
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let cfg = Arc::new(DhcpConfig::parse_str(SAMPLE_YAML)?);
+        let (url, _handle) =
+            serve(|addr| ExternalApi::new(addr, cfg, mgr).with_read_only(true)).await?;
+        let r = reqwest::get(format!("{url}/ui"))
+            .await?
+            .error_for_status()?;
+        assert!(r.headers()["content-type"]
+            .to_str()?
+            .starts_with("text/html"));
+        let page = r.text().await?;
+        // every endpoint the page reads from
+        for path in [
+            "/v1/ranges",
+            "/v1/leases",
+            "/v1/config",
+            "/v1/debug/client-protection",
+        ] {
+            assert!(page.contains(path), "{path}");
+        }
         Ok(())
     }
 }
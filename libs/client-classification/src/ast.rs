@@ -27,6 +27,9 @@ pub enum Expr {
     Src,
     Dst,
     Len,
+    // now
+    Hour,
+    Weekday,
     // pkt
     Mac,
     Hlen,
@@ -37,12 +40,17 @@ pub enum Expr {
     SiAddr,
     MsgType,
     TransId,
+    Secs,
+    Fingerprint,
+    UserClass,
     // operation (expr, start, len) where len of None means 'all'
     Substring(Box<Expr>, isize, Option<isize>),
     Concat(Box<Expr>, Box<Expr>),
     IfElse(Box<Expr>, Box<Expr>, Box<Expr>),
     Hexstring(Box<Expr>, String),
     Split(Box<Expr>, Box<Expr>, usize),
+    // (expr, network, prefix len), network is stored masked
+    InNet(Box<Expr>, Ipv4Addr, u8),
     // prefix
     Not(Box<Expr>),
     // postfix
@@ -85,6 +93,8 @@ pub enum ParseErr {
     Concat(String),
     #[error("'split parse error with: {0}")]
     Split(String),
+    #[error("in_net parse error with: {0}")]
+    InNet(String),
     #[error("expected option but found: {0}")]
     Option(Expr),
     #[error("bool parse error with: {0}")]
@@ -134,6 +144,9 @@ fn parse_expr(pairs: Pairs<Rule>, pratt: &PrattParser<Rule>) -> ParseResult<Expr
                 Rule::pkt_base_src => Expr::Src,
                 Rule::pkt_base_dst => Expr::Dst,
                 Rule::pkt_base_len => Expr::Len,
+                // now
+                Rule::now_hour => Expr::Hour,
+                Rule::now_weekday => Expr::Weekday,
                 // pkt
                 Rule::pkt_mac => Expr::Mac,
                 Rule::pkt_hlen => Expr::Hlen,
@@ -144,6 +157,9 @@ fn parse_expr(pairs: Pairs<Rule>, pratt: &PrattParser<Rule>) -> ParseResult<Expr
                 Rule::pkt_siaddr => Expr::SiAddr,
                 Rule::pkt_msgtype => Expr::MsgType,
                 Rule::pkt_transid => Expr::TransId,
+                Rule::pkt_secs => Expr::Secs,
+                Rule::pkt_fingerprint => Expr::Fingerprint,
+                Rule::pkt_userclass => Expr::UserClass,
                 Rule::ip => Expr::Ip(primary.as_str().parse()?),
                 Rule::string => Expr::String(parse_string(primary)),
                 Rule::option => Expr::Option(parse_num(primary)?),
@@ -216,6 +232,23 @@ fn parse_expr(pairs: Pairs<Rule>, pratt: &PrattParser<Rule>) -> ParseResult<Expr
                     )?;
                     Expr::Hexstring(Box::new(expr), separator)
                 }
+                Rule::in_net => {
+                    let mut inner = primary.into_inner();
+                    let net = parse_string(
+                        inner
+                            .next_back()
+                            .ok_or_else(|| ParseErr::InNet(inner.to_string()))?,
+                    );
+                    let expr = parse_expr(
+                        inner
+                            .next_back()
+                            .ok_or_else(|| ParseErr::InNet(inner.to_string()))?
+                            .into_inner(),
+                        pratt,
+                    )?;
+                    let (ip, prefix) = parse_cidr(&net)?;
+                    Expr::InNet(Box::new(expr), ip, prefix)
+                }
                 Rule::ifelse => {
                     let mut inner = primary.into_inner();
                     let c = inner
@@ -285,6 +318,23 @@ fn parse_string_inner(primary: Pair<Rule>) -> String {
     parse_str(primary.into_inner().as_str())
 }
 
+/// parse `a.b.c.d/len` into the masked network addr & prefix len
+fn parse_cidr(net: &str) -> ParseResult<(Ipv4Addr, u8)> {
+    let (ip, prefix) = net
+        .split_once('/')
+        .ok_or_else(|| ParseErr::InNet(net.to_owned()))?;
+    let ip: Ipv4Addr = ip.parse()?;
+    let prefix: u8 = prefix.parse()?;
+    if prefix > 32 {
+        return Err(ParseErr::InNet(net.to_owned()));
+    }
+    Ok((Ipv4Addr::from(u32::from(ip) & netmask(prefix)), prefix))
+}
+
+pub(crate) fn netmask(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
 fn parse_num<F: std::str::FromStr>(primary: Pair<Rule>) -> Result<F, F::Err> {
     primary.into_inner().as_str().parse()
 }
@@ -0,0 +1,181 @@
+//! # DHCP fingerprints
+//!
+//! Clients of the same OS/firmware ask for the same options in the same order, so the
+//! parameter request list (opt 55), vendor class (opt 60) & which other options a client
+//! sends identify the device type, the same inputs fingerbank uses. The fingerprint is a
+//! hash of those, the parts are kept so they can be looked up in a fingerprint database.
+//!
+//! Options that depend on the message type or the relay (50, 51, 53, 54, 82) are left
+//! out, so a client has the same fingerprint in its DISCOVER, REQUEST & renewals.
+use std::fmt;
+
+use dhcproto::v4;
+
+/// options whose presence varies between messages from the same client
+const VARYING_OPTS: &[v4::OptionCode] = &[
+    v4::OptionCode::RequestedIpAddress,
+    v4::OptionCode::AddressLeaseTime,
+    v4::OptionCode::MessageType,
+    v4::OptionCode::ServerIdentifier,
+    v4::OptionCode::RelayAgentInformation,
+];
+
+/// the fingerprint of a DHCPv4 client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// opt 55 codes, in the order the client sent them
+    pub prl: Vec<u8>,
+    /// opt 60, lossy utf8
+    pub vendor_class: Option<String>,
+    /// codes of the other options the client sent, in code order
+    pub opts: Vec<u8>,
+}
+
+impl Fingerprint {
+    /// fingerprint of the client that sent `req`
+    pub fn new(req: &v4::Message) -> Self {
+        let opts = req.opts();
+        let prl = match opts.get(v4::OptionCode::ParameterRequestList) {
+            Some(v4::DhcpOption::ParameterRequestList(codes)) => {
+                codes.iter().map(|code| u8::from(*code)).collect()
+            }
+            _ => Vec::new(),
+        };
+        let vendor_class = match opts.get(v4::OptionCode::ClassIdentifier) {
+            Some(v4::DhcpOption::ClassIdentifier(class)) => {
+                Some(String::from_utf8_lossy(class).into_owned())
+            }
+            _ => None,
+        };
+        let mut codes = opts
+            .iter()
+            .map(|(code, _)| *code)
+            .filter(|code| {
+                !VARYING_OPTS.contains(code)
+                    && *code != v4::OptionCode::ParameterRequestList
+                    && *code != v4::OptionCode::ClassIdentifier
+            })
+            .map(u8::from)
+            .collect::<Vec<_>>();
+        codes.sort_unstable();
+        Self {
+            prl,
+            vendor_class,
+            opts: codes,
+        }
+    }
+
+    /// the PRL as comma separated codes, ex. `1,3,6,15,119,252`, the format fingerprint
+    /// databases use
+    pub fn prl_str(&self) -> String {
+        join(&self.prl)
+    }
+
+    /// 64-bit FNV-1a of the PRL, vendor class & option codes as 16 hex chars. It's
+    /// stable across versions so it can be stored & compared
+    pub fn hash(&self) -> String {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut write = |bytes: &[u8]| {
+            for b in bytes {
+                hash ^= *b as u64;
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        };
+        // lengths separate the fields, so bytes can't move from one to the next
+        write(&(self.prl.len() as u32).to_be_bytes());
+        write(&self.prl);
+        let vendor = self.vendor_class.as_deref().unwrap_or_default().as_bytes();
+        write(&(vendor.len() as u32).to_be_bytes());
+        write(vendor);
+        write(&(self.opts.len() as u32).to_be_bytes());
+        write(&self.opts);
+        format!("{hash:016x}")
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} prl={} opts={}",
+            self.hash(),
+            self.prl_str(),
+            join(&self.opts)
+        )?;
+        if let Some(vendor) = &self.vendor_class {
+            write!(f, " vendor={vendor:?}")?;
+        }
+        Ok(())
+    }
+}
+
+fn join(codes: &[u8]) -> String {
+    codes
+        .iter()
+        .map(|code| code.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn msg(msg_type: v4::MessageType, prl: &[u8], vendor: Option<&[u8]>) -> v4::Message {
+        let uns = Ipv4Addr::UNSPECIFIED;
+        let mut msg = v4::Message::new(uns, uns, uns, uns, &[1, 2, 3, 4, 5, 6]);
+        msg.opts_mut().insert(v4::DhcpOption::MessageType(msg_type));
+        msg.opts_mut().insert(v4::DhcpOption::ParameterRequestList(
+            prl.iter().map(|code| (*code).into()).collect(),
+        ));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::Hostname("phone".to_owned()));
+        if let Some(vendor) = vendor {
+            msg.opts_mut()
+                .insert(v4::DhcpOption::ClassIdentifier(vendor.to_vec()));
+        }
+        msg
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let android = Some(&b"android-dhcp-13"[..]);
+        let discover = Fingerprint::new(&msg(
+            v4::MessageType::Discover,
+            &[1, 3, 6, 15, 26, 28, 51, 58, 59, 43, 114],
+            android,
+        ));
+        assert_eq!(discover.prl_str(), "1,3,6,15,26,28,51,58,59,43,114");
+        assert_eq!(discover.vendor_class.as_deref(), Some("android-dhcp-13"));
+        assert_eq!(discover.opts, [12]);
+        assert_eq!(discover.hash().len(), 16);
+
+        // a REQUEST carries opts 50 & 54 too, the fingerprint is the same
+        let mut req = msg(
+            v4::MessageType::Request,
+            &[1, 3, 6, 15, 26, 28, 51, 58, 59, 43, 114],
+            android,
+        );
+        req.opts_mut()
+            .insert(v4::DhcpOption::RequestedIpAddress([192, 168, 0, 2].into()));
+        req.opts_mut()
+            .insert(v4::DhcpOption::ServerIdentifier([192, 168, 0, 1].into()));
+        assert_eq!(Fingerprint::new(&req).hash(), discover.hash());
+
+        // PRL order & vendor class change it
+        let reordered = Fingerprint::new(&msg(
+            v4::MessageType::Discover,
+            &[1, 3, 6, 15, 26, 28, 51, 58, 59, 114, 43],
+            android,
+        ));
+        assert_ne!(reordered.hash(), discover.hash());
+        let no_vendor = Fingerprint::new(&msg(
+            v4::MessageType::Discover,
+            &[1, 3, 6, 15, 26, 28, 51, 58, 59, 43, 114],
+            None,
+        ));
+        assert_ne!(no_vendor.hash(), discover.hash());
+    }
+}
@@ -5,11 +5,13 @@ use std::{
     str,
 };
 
-use dhcproto::{v4, Decoder};
+use dhcproto::{v4, Decoder, Encodable};
 use thiserror::Error;
 
 pub mod ast;
 pub use ast::{Expr, ParseErr, ParseResult};
+pub mod fingerprint;
+pub use fingerprint::Fingerprint;
 
 pub const DROP_CLASS: &str = "DROP";
 // the following classes can be used in `member()`
@@ -18,6 +20,99 @@ pub const ALL_CLASS: &str = "ALL";
 pub const KNOWN_CLASS: &str = "KNOWN";
 pub const UNKNOWN_CLASS: &str = "UNKNOWN";
 pub const BOOTP_CLASS: &str = "BOOTP";
+/// packets that failed a sanity check with the `tag` policy
+pub const SUSPECT_CLASS: &str = "SUSPECT";
+/// clients the identity backend returned a VLAN for, ex. `VLAN_20`
+pub const VLAN_PREFIX_CLASS: &str = "VLAN_";
+/// clients that look like they're using a randomized MAC, see [`is_random_mac`]
+pub const RANDOM_MAC_CLASS: &str = "RANDOM_MAC";
+
+/// user class option (RFC 3004)
+const USER_CLASS_CODE: u8 = 77;
+
+/// locally administered prefixes that hypervisors & container runtimes hand out, they
+/// don't rotate so aren't counted as randomized (QEMU/KVM, Docker)
+const STABLE_LOCAL_PREFIXES: &[&[u8]] = &[&[0x52, 0x54, 0x00], &[0x02, 0x42]];
+
+/// vendor classes (opt 60) of network boot firmware, which may use a locally
+/// administered MAC but doesn't rotate it
+const NETBOOT_VENDOR_PREFIXES: &[&[u8]] = &[b"PXEClient", b"HTTPClient"];
+
+/// heuristic for a client using a randomized ("private") MAC: chaddr is a unicast,
+/// locally administered address outside of known VM/container prefixes, and the option
+/// fingerprint isn't one of network boot firmware. A client id (opt 61) with a different
+/// hardware address means the chaddr isn't the client's identity, so it isn't counted
+pub fn is_random_mac(req: &v4::Message) -> bool {
+    let chaddr = req.chaddr();
+    let [first, ..] = chaddr else {
+        return false;
+    };
+    if chaddr.len() != 6 || first & 0x02 == 0 || first & 0x01 != 0 {
+        return false;
+    }
+    if STABLE_LOCAL_PREFIXES
+        .iter()
+        .any(|prefix| chaddr.starts_with(prefix))
+    {
+        return false;
+    }
+    let opts = req.opts();
+    if let Some(v4::DhcpOption::ClassIdentifier(class)) = opts.get(v4::OptionCode::ClassIdentifier)
+    {
+        if NETBOOT_VENDOR_PREFIXES
+            .iter()
+            .any(|prefix| class.starts_with(prefix))
+        {
+            return false;
+        }
+    }
+    match opts.get(v4::OptionCode::ClientIdentifier) {
+        // htype 1 (ethernet) followed by a MAC
+        Some(v4::DhcpOption::ClientIdentifier(id)) if id.len() == 7 && id[0] == 1 => {
+            id[1..] == *chaddr
+        }
+        _ => true,
+    }
+}
+
+/// the user classes (opt 77) the client sent, see [`decode_user_classes`]
+pub fn user_classes(opts: &v4::DhcpOptions) -> Vec<String> {
+    match opts.get(v4::OptionCode::from(USER_CLASS_CODE)) {
+        Some(v4::DhcpOption::Unknown(opt)) => decode_user_classes(opt.data()),
+        Some(opt) => {
+            let Ok(buf) = opt.to_vec() else {
+                return Vec::new();
+            };
+            // long options are split into consecutive options with the same code
+            let mut data = Vec::with_capacity(buf.len());
+            let mut buf = &buf[..];
+            while let [_code, len, rest @ ..] = buf {
+                let len = (*len as usize).min(rest.len());
+                data.extend_from_slice(&rest[..len]);
+                buf = &rest[len..];
+            }
+            decode_user_classes(&data)
+        }
+        None => Vec::new(),
+    }
+}
+
+/// decode the data of a user class option. RFC 3004 encodes it as a list of classes,
+/// each one prefixed by its length. Some clients (ex. iPXE, Windows) send a single class
+/// without a length, data that isn't a well formed list is taken as one class
+pub fn decode_user_classes(data: &[u8]) -> Vec<String> {
+    let mut classes = Vec::new();
+    let mut buf = data;
+    while let [len, rest @ ..] = buf {
+        let len = *len as usize;
+        if len == 0 || len > rest.len() {
+            return vec![String::from_utf8_lossy(data).into_owned()];
+        }
+        classes.push(String::from_utf8_lossy(&rest[..len]).into_owned());
+        buf = &rest[len..];
+    }
+    classes
+}
 
 pub fn parse_builtin_vendor(s: &str) -> Option<&str> {
     s.strip_prefix(VENDOR_PREFIX_CLASS)
@@ -164,6 +259,21 @@ pub struct PacketDetails<'a> {
     pub src: Ipv4Addr,
     pub dst: Ipv4Addr,
     pub len: usize,
+    /// time the packet was received
+    pub now: Now,
+    /// packet failed a sanity check, adds the `SUSPECT` class
+    pub suspect: bool,
+    /// classes assigned to the client by the identity backend
+    pub tags: Vec<String>,
+}
+
+/// local time used by `now.hour` & `now.weekday`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Now {
+    /// hour of the day, 0-23
+    pub hour: u32,
+    /// day of the week, 0 (Sunday) - 6 (Saturday)
+    pub weekday: u32,
 }
 
 // useful for testing
@@ -174,6 +284,9 @@ impl Default for PacketDetails<'_> {
             src: Ipv4Addr::new(192, 168, 0, 1),
             dst: Ipv4Addr::new(192, 168, 0, 1),
             len: 513,
+            now: Now::default(),
+            suspect: false,
+            tags: Vec::new(),
         }
     }
 }
@@ -204,6 +317,9 @@ pub fn eval(expr: &Expr, args: &Args) -> Result<Val, EvalErr> {
         E::Dst => Val::Int(u32::from(args.pkt.dst)),
         E::Src => Val::Int(u32::from(args.pkt.src)),
         E::Len => Val::Int(args.pkt.len as u32),
+        // now
+        E::Hour => Val::Int(args.pkt.now.hour),
+        E::Weekday => Val::Int(args.pkt.now.weekday),
         // TODO: can probably use msg.chaddr() instead of an explicit param here
         E::Mac => Val::Bytes(args.chaddr.to_vec()),
         E::Hlen => Val::Int(args.msg.hlen() as u32),
@@ -217,6 +333,15 @@ pub fn eval(expr: &Expr, args: &Args) -> Result<Val, EvalErr> {
             None => Val::Empty,
         },
         E::TransId => Val::Int(args.msg.xid()),
+        E::Secs => Val::Int(args.msg.secs() as u32),
+        // v6 & BOOTP messages have no v4 options to fingerprint
+        E::Fingerprint if args.msg.opts().iter().next().is_none() => Val::Empty,
+        E::Fingerprint => Val::String(Fingerprint::new(args.msg).hash()),
+        // all user classes, comma separated, `split` picks out one of several
+        E::UserClass => match args.opts.get(&USER_CLASS_CODE.into()) {
+            Some(opt) => Val::String(decode_user_classes(opt.data()).join(",")),
+            None => Val::Empty,
+        },
         E::Ip(ip) => Val::Int(u32::from_be_bytes(ip.octets())),
         // prefix
         E::Not(rhs) => Val::Bool(!is_bool(eval(rhs, args)?)?),
@@ -295,6 +420,16 @@ pub fn eval(expr: &Expr, args: &Args) -> Result<Val, EvalErr> {
                 .collect::<Result<Vec<_>, _>>()?
                 .join(sep),
         ),
+        E::InNet(expr, net, prefix) => {
+            let ip = match eval(expr, args)? {
+                Val::Int(i) => i,
+                Val::Bytes(b) if b.len() == 4 => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+                // missing option or relay sub-opt
+                Val::Empty => return Ok(Val::Bool(false)),
+                err => return Err(EvalErr::ExpectedInt(err)),
+            };
+            Val::Bool(ip & ast::netmask(*prefix) == u32::from(*net))
+        }
         E::Member(s) => Val::Bool(args.member.contains(s)),
     })
 }
@@ -403,6 +538,56 @@ mod tests {
     use crate::ast::*;
     use std::{collections::HashMap, net::Ipv4Addr};
 
+    #[test]
+    fn test_random_mac() {
+        let uns = Ipv4Addr::UNSPECIFIED;
+        let msg = |chaddr: &[u8], opts: Vec<v4::DhcpOption>| {
+            let mut msg = v4::Message::new(uns, uns, uns, uns, chaddr);
+            for opt in opts {
+                msg.opts_mut().insert(opt);
+            }
+            msg
+        };
+        let random = [0xda, 0xa1, 0x19, 0x3c, 0x4d, 0x5e];
+        assert!(is_random_mac(&msg(&random, vec![])));
+        let mut id = vec![1];
+        id.extend(random);
+        assert!(is_random_mac(&msg(
+            &random,
+            vec![v4::DhcpOption::ClientIdentifier(id)]
+        )));
+        // globally unique, multicast, VM & container addresses
+        assert!(!is_random_mac(&msg(
+            &[0xd8, 0xa1, 0x19, 0x3c, 0x4d, 0x5e],
+            vec![]
+        )));
+        assert!(!is_random_mac(&msg(
+            &[0xdb, 0xa1, 0x19, 0x3c, 0x4d, 0x5e],
+            vec![]
+        )));
+        assert!(!is_random_mac(&msg(
+            &[0x52, 0x54, 0x00, 0x3c, 0x4d, 0x5e],
+            vec![]
+        )));
+        assert!(!is_random_mac(&msg(
+            &[0x02, 0x42, 0xac, 0x11, 0x00, 0x02],
+            vec![]
+        )));
+        // PXE firmware & a client id for another hardware address
+        assert!(!is_random_mac(&msg(
+            &random,
+            vec![v4::DhcpOption::ClassIdentifier(
+                b"PXEClient:Arch:00007".to_vec()
+            )]
+        )));
+        assert!(!is_random_mac(&msg(
+            &random,
+            vec![v4::DhcpOption::ClientIdentifier(vec![
+                1, 0xd8, 0xa1, 0x19, 0x3c, 0x4d, 0x5e
+            ])]
+        )));
+    }
+
     #[test]
     fn test_opt_exists() {
         let tokens = PredicateParser::parse(Rule::expr, "not option[123].exists").unwrap();
@@ -484,6 +669,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_now() {
+        let args = Args {
+            chaddr: &hex::decode("010203040506").unwrap(),
+            opts: HashMap::new(),
+            msg: &v4::Message::default(),
+            member: HashSet::new(),
+            pkt: PacketDetails {
+                now: Now {
+                    hour: 2,
+                    weekday: 6,
+                },
+                ..PacketDetails::default()
+            },
+        };
+        assert_eq!(
+            eval(&ast::parse("now.hour == 2").unwrap(), &args).unwrap(),
+            Val::Bool(true)
+        );
+        assert_eq!(
+            eval(
+                &ast::parse("(now.hour == 1 or now.hour == 2) and now.weekday == 6").unwrap(),
+                &args
+            )
+            .unwrap(),
+            Val::Bool(true)
+        );
+        assert_eq!(
+            eval(&ast::parse("now.weekday == 0").unwrap(), &args).unwrap(),
+            Val::Bool(false)
+        );
+    }
+
     #[test]
     fn test_substring_all() {
         let args = Args {
@@ -711,6 +929,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_user_class() {
+        // RFC 3004 list
+        assert_eq!(decode_user_classes(b"\x04iPXE\x05image"), ["iPXE", "image"]);
+        // a single class without a length
+        assert_eq!(decode_user_classes(b"MSFT 5.0"), ["MSFT 5.0"]);
+        assert!(decode_user_classes(b"").is_empty());
+
+        let mut msg = v4::Message::default();
+        msg.opts_mut()
+            .insert(v4::DhcpOption::Unknown(UnknownOption::new(
+                USER_CLASS_CODE.into(),
+                b"\x04iPXE\x08pxe-prov".to_vec(),
+            )));
+        assert_eq!(user_classes(msg.opts()), ["iPXE", "pxe-prov"]);
+
+        let mut opts = HashMap::new();
+        opts.insert(
+            USER_CLASS_CODE.into(),
+            UnknownOption::new(USER_CLASS_CODE.into(), b"\x04iPXE\x08pxe-prov".to_vec()),
+        );
+        let args = Args {
+            chaddr: &hex::decode("DEADBEEF").unwrap(),
+            opts,
+            msg: &msg,
+            member: HashSet::new(),
+            pkt: PacketDetails::default(),
+        };
+        for (expr, expected) in [
+            ("pkt4.userclass == 'iPXE,pxe-prov'", true),
+            ("split(pkt4.userclass, ',', 2) == 'pxe-prov'", true),
+            ("pkt4.userclass.exists", true),
+        ] {
+            let expr = ast::parse(expr).unwrap();
+            assert_eq!(eval(&expr, &args).unwrap(), Val::Bool(expected));
+        }
+        let args = Args {
+            opts: HashMap::new(),
+            ..args
+        };
+        let expr = ast::parse("pkt4.userclass.exists").unwrap();
+        assert_eq!(eval(&expr, &args).unwrap(), Val::Bool(false));
+    }
+
     #[test]
     fn test_msg_hdr() {
         let options = HashMap::new();
@@ -722,11 +984,14 @@ mod tests {
             Ipv4Addr::new(4, 4, 4, 4),
             "123456".as_bytes(),
         );
-        msg.set_xid(1234).set_htype(v4::HType::Eth).set_opts({
-            let mut opts = v4::DhcpOptions::new();
-            opts.insert(v4::DhcpOption::MessageType(v4::MessageType::Offer));
-            opts
-        });
+        msg.set_xid(1234)
+            .set_secs(7)
+            .set_htype(v4::HType::Eth)
+            .set_opts({
+                let mut opts = v4::DhcpOptions::new();
+                opts.insert(v4::DhcpOption::MessageType(v4::MessageType::Offer));
+                opts
+            });
 
         let args = Args {
             chaddr: &hex::decode("DEADBEEF").unwrap(),
@@ -758,6 +1023,54 @@ mod tests {
         let expr = ast::parse("pkt4.transid == 1234").unwrap();
         let val = eval(&expr, &args).unwrap();
         assert_eq!(val, Val::Bool(true));
+
+        let expr = ast::parse("pkt4.secs == 7").unwrap();
+        let val = eval(&expr, &args).unwrap();
+        assert_eq!(val, Val::Bool(true));
+    }
+
+    #[test]
+    fn test_in_net() {
+        let mut opts = HashMap::new();
+        opts.insert(
+            v4::OptionCode::RequestedIpAddress,
+            UnknownOption::new(v4::OptionCode::RequestedIpAddress, vec![192, 168, 1, 50]),
+        );
+        let mut msg = v4::Message::default();
+        msg.set_giaddr(Ipv4Addr::new(10, 1, 20, 1));
+
+        let args = Args {
+            chaddr: &hex::decode("DEADBEEF").unwrap(),
+            opts,
+            msg: &msg,
+            member: HashSet::new(),
+            pkt: PacketDetails::default(),
+        };
+
+        for (expr, expected) in [
+            ("in_net(pkt4.giaddr, '10.1.0.0/16')", true),
+            ("in_net(pkt4.giaddr, '10.2.0.0/16')", false),
+            // host bits in the network are ignored
+            ("in_net(pkt4.giaddr, '10.1.20.7/24')", true),
+            ("in_net(pkt4.giaddr, '10.1.20.1/32')", true),
+            ("in_net(pkt4.giaddr, '0.0.0.0/0')", true),
+            ("in_net(10.1.255.255, '10.1.0.0/16')", true),
+            ("in_net(option[50].hex, '192.168.1.0/24')", true),
+            // missing option
+            ("in_net(option[54].hex, '0.0.0.0/0')", false),
+            ("not in_net(pkt4.ciaddr, '10.0.0.0/8')", true),
+        ] {
+            assert_eq!(
+                eval(&ast::parse(expr).unwrap(), &args).unwrap(),
+                Val::Bool(expected),
+                "{expr}"
+            );
+        }
+
+        assert!(ast::parse("in_net(pkt4.giaddr, '10.1.0.0')").is_err());
+        assert!(ast::parse("in_net(pkt4.giaddr, '10.1.0.0/33')").is_err());
+        assert!(ast::parse("in_net(pkt4.giaddr, 'foo/8')").is_err());
+        assert!(eval(&ast::parse("in_net('foo', '10.0.0.0/8')").unwrap(), &args).is_err());
     }
 
     #[test]
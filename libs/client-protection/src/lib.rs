@@ -1,25 +1,62 @@
 //! Client protection
 //!
 //!
-use config::v4::FloodThreshold;
+use config::v4::{FloodKey, FloodThreshold};
 // TODO: consider switching both to Mutex<Hashmap<>>.
 // the caches are all locked immediately and written to, so dashmap is probably overkill
 // (governor uses dashmap internally by default by we can turn off the "dashmap" feature)
-use dashmap::DashMap;
-use governor::{clock::DefaultClock, state::keyed::DefaultKeyedStateStore, Quota, RateLimiter};
+use dashmap::{DashMap, DashSet};
+use governor::{
+    clock::{Clock, DefaultClock},
+    DefaultDirectRateLimiter, Quota, RateLimiter,
+};
 use tracing::{debug, trace};
 
 use std::{
     borrow::Borrow,
     fmt,
     hash::Hash,
+    net::{IpAddr, Ipv4Addr},
     num::NonZeroU32,
+    sync::atomic::{AtomicU64, Ordering},
     time::{Duration, Instant},
 };
 
 pub struct RenewThreshold<K> {
     percentage: u64,
     cache: DashMap<K, RenewExpiry>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K: Eq + Hash> fmt::Debug for RenewThreshold<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RenewThreshold")
+            .field("percentage", &self.percentage)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// point in time counters for a [`RenewThreshold`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RenewStats {
+    /// clients in the cache, including ones past the threshold
+    pub entries: usize,
+    /// lookups that returned a cached lease
+    pub hits: u64,
+    /// lookups for clients not in the cache or past the threshold
+    pub misses: u64,
+}
+
+impl RenewStats {
+    /// hits / lookups, 0 if there were no lookups
+    pub fn hit_ratio(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.,
+            total => self.hits as f64 / total as f64,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -59,6 +96,8 @@ impl<K: Eq + Hash + Clone> RenewThreshold<K> {
         Self {
             percentage: percentage as u64,
             cache: DashMap::new(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
         }
     }
     // insert id into cache with lease time, replacing existing entry
@@ -73,18 +112,71 @@ impl<K: Eq + Hash + Clone> RenewThreshold<K> {
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
     {
-        self.cache
+        let remaining = self
+            .cache
             .get(id)
             .map(|e| *e)
-            .and_then(|entry| entry.get_remaining())
+            .and_then(|entry| entry.get_remaining());
+        let counter = if remaining.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        remaining
     }
     pub fn remove(&self, id: &K) -> Option<(K, RenewExpiry)> {
         self.cache.remove(id)
     }
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+    pub fn stats(&self) -> RenewStats {
+        RenewStats {
+            entries: self.cache.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
 }
 
+/// Rate limits each client separately. Every client gets its own limiter so that the
+/// state for a single client can be inspected & cleared.
+///
+/// If the threshold has a `pressure` limit, [`FloodCache::set_utilization`] switches a
+/// network to it while the network's pool utilization is at or above its `high_water`.
+/// Clients of other networks keep the normal limit
 pub struct FloodCache<K: Hash + Eq + Clone> {
-    rl: RateLimiter<K, DefaultKeyedStateStore<K>, DefaultClock>,
+    quota: Quota,
+    /// `high_water` & the tighter quota used at or above it
+    pressure: Option<(u8, Quota)>,
+    /// networks at or above `high_water`
+    pressured: DashSet<Ipv4Addr>,
+    clients: DashMap<K, FloodState>,
+}
+
+struct FloodState {
+    rl: DefaultDirectRateLimiter,
+    // counts every packet when there is a pressure limit, but only refuses them while
+    // the client's network is under pressure. Neither limiter starts over when pressure
+    // comes & goes, so a flip doesn't hand out a fresh quota
+    pressure_rl: Option<DefaultDirectRateLimiter>,
+    // set when the client was last refused, until the next packet is allowed
+    limited_until: Option<Instant>,
+}
+
+impl<K: Hash + Eq + Clone> fmt::Debug for FloodCache<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FloodCache")
+            .field("quota", &self.quota)
+            .field("pressure", &self.pressure)
+            .field("pressured", &self.pressured.len())
+            .field("clients", &self.clients.len())
+            .finish()
+    }
 }
 
 impl<K> FloodCache<K>
@@ -100,23 +192,208 @@ where
         // let rate = cfg.packets() / cfg.period().as_secs() as u32;
         // debug!("creating flood cache threshold {:?} packets/sec", rate);
 
+        #[allow(deprecated)]
+        let quota = |packets: u32| {
+            Quota::new(
+                NonZeroU32::new(packets).expect("conversion will not fail"),
+                cfg.period(),
+            )
+            .expect("don't pass Duration of 0")
+        };
         Self {
-            #[allow(deprecated)]
-            rl: RateLimiter::keyed(
-                Quota::new(
-                    NonZeroU32::new(cfg.packets()).expect("conversion will not fail"),
-                    cfg.period(),
-                )
-                .expect("don't pass Duration of 0"),
-            ),
+            quota: quota(cfg.packets()),
+            pressure: cfg
+                .pressure()
+                .map(|pressure| (pressure.high_water(), quota(pressure.packets()))),
+            pressured: DashSet::new(),
+            clients: DashMap::new(),
         }
     }
+    /// count a packet from `id`, the pressure limit never applies
     pub fn is_allowed(&self, id: &K) -> bool {
-        let res = self.rl.check_key(id);
-        if let Err(not_until) = &res {
-            trace!(?not_until, ?id, "reached threshold for client")
+        self.is_allowed_in(id, None)
+    }
+    /// count a packet from `id` for `network`, with the pressure limit if the network is
+    /// under pressure
+    pub fn is_allowed_in(&self, id: &K, network: Option<Ipv4Addr>) -> bool {
+        let pressured = network.is_some_and(|network| self.pressured.contains(&network));
+        let mut state = match self.clients.get_mut(id) {
+            Some(state) => state,
+            None => self
+                .clients
+                .entry(id.clone())
+                .or_insert_with(|| FloodState {
+                    rl: RateLimiter::direct(self.quota),
+                    pressure_rl: self.pressure.map(|(_, quota)| RateLimiter::direct(quota)),
+                    limited_until: None,
+                }),
+        };
+        let now = DefaultClock::default().now();
+        let normal = state.rl.check().err();
+        let pressure = state
+            .pressure_rl
+            .as_ref()
+            .and_then(|rl| rl.check().err())
+            .filter(|_| pressured);
+        match normal
+            .into_iter()
+            .chain(pressure)
+            .map(|not_until| not_until.wait_time_from(now))
+            .max()
+        {
+            None => {
+                state.limited_until = None;
+                true
+            }
+            Some(wait) => {
+                trace!(?wait, ?id, pressured, "reached threshold for client");
+                state.limited_until = Some(Instant::now() + wait);
+                false
+            }
+        }
+    }
+    /// clients that are currently refused & how long until their next packet is allowed
+    pub fn limited(&self) -> Vec<(K, Duration)> {
+        let now = Instant::now();
+        self.clients
+            .iter()
+            .filter_map(|entry| {
+                let until = entry.limited_until.filter(|until| *until > now)?;
+                Some((entry.key().clone(), until - now))
+            })
+            .collect()
+    }
+    /// forget the rate limit state of a client, it starts over with a full quota. Returns
+    /// `false` if the client wasn't being tracked
+    pub fn clear<Q>(&self, id: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.clients.remove(id).is_some()
+    }
+    /// feed back the pool utilization (percent) of `network`, switching its clients to or
+    /// from the `pressure` limit. Returns whether the pressure limit is now in effect for
+    /// the network, always `false` if there is none
+    pub fn set_utilization(&self, network: Ipv4Addr, percent: u8) -> bool {
+        let pressured = matches!(self.pressure, Some((high_water, _)) if percent >= high_water);
+        let changed = if pressured {
+            self.pressured.insert(network)
+        } else {
+            self.pressured.remove(&network).is_some()
+        };
+        if changed {
+            debug!(
+                ?network,
+                percent, pressured, "pool pressure changed flood threshold"
+            );
+        }
+        pressured
+    }
+    /// the tighter `pressure` limit is in effect for at least one network
+    pub fn under_pressure(&self) -> bool {
+        !self.pressured.is_empty()
+    }
+    /// number of clients being tracked, limited or not
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+/// the parts of a packet a [`FloodLimiter`] can count it by
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FloodKeyParts<'a> {
+    pub client_id: &'a [u8],
+    pub chaddr: &'a [u8],
+    pub giaddr: Ipv4Addr,
+    pub source: IpAddr,
+    /// the network the packet is for, the pressure limit is tracked per network
+    pub network: Option<Ipv4Addr>,
+}
+
+/// A [`FloodCache`] counting packets by the key from its config, ex. the relay a packet
+/// came through rather than the client, for floods that rotate client ids behind one relay
+pub struct FloodLimiter {
+    key: Vec<FloodKey>,
+    name: String,
+    cache: FloodCache<Vec<u8>>,
+}
+
+impl fmt::Debug for FloodLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FloodLimiter")
+            .field("key", &self.name)
+            .field("cache", &self.cache)
+            .finish()
+    }
+}
+
+impl FloodLimiter {
+    pub fn new(cfg: FloodThreshold) -> Self {
+        Self {
+            key: cfg.key().to_vec(),
+            name: cfg.key_name(),
+            cache: FloodCache::new(cfg),
+        }
+    }
+    /// what the limiter counts by, ex. `giaddr+chaddr`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn cache(&self) -> &FloodCache<Vec<u8>> {
+        &self.cache
+    }
+    /// The key a packet is counted under. A one part key is the part's bytes, so a
+    /// `client_id` key is the client id. The parts of a tuple are each prefixed with their
+    /// length as a big endian u16, so no two tuples have the same key.
+    ///
+    /// `None` if the key is only `giaddr`/`source_ip` parts & they're all 0.0.0.0, an
+    /// unrelayed packet from a client without an address. Counting those would put every
+    /// such client on the link in one bucket, so the limiter lets them through
+    pub fn key(&self, parts: &FloodKeyParts<'_>) -> Option<Vec<u8>> {
+        let unspecified = |part: &FloodKey| match part {
+            FloodKey::Giaddr => parts.giaddr.is_unspecified(),
+            FloodKey::SourceIp => parts.source.is_unspecified(),
+            FloodKey::ClientId | FloodKey::Chaddr => false,
+        };
+        if self.key.iter().all(unspecified) {
+            return None;
+        }
+        let part = |part: FloodKey| match part {
+            FloodKey::ClientId => parts.client_id.to_vec(),
+            FloodKey::Chaddr => parts.chaddr.to_vec(),
+            FloodKey::Giaddr => parts.giaddr.octets().to_vec(),
+            FloodKey::SourceIp => match parts.source.to_canonical() {
+                IpAddr::V4(ip) => ip.octets().to_vec(),
+                IpAddr::V6(ip) => ip.octets().to_vec(),
+            },
+        };
+        Some(match &self.key[..] {
+            [one] => part(*one),
+            tuple => tuple
+                .iter()
+                .flat_map(|p| {
+                    let bytes = part(*p);
+                    // a client id split across options (RFC 3396) can pass 255 bytes but
+                    // not a packet's length, the prefix never has to cut a part short
+                    let len = u16::try_from(bytes.len()).unwrap_or(u16::MAX);
+                    len.to_be_bytes()
+                        .into_iter()
+                        .chain(bytes.into_iter().take(usize::from(len)))
+                })
+                .collect(),
+        })
+    }
+    /// count the packet, `false` if its key is over the limit. Packets without a key are
+    /// always allowed, see [`FloodLimiter::key`]
+    pub fn is_allowed(&self, parts: &FloodKeyParts<'_>) -> bool {
+        match self.key(parts) {
+            Some(key) => self.cache.is_allowed_in(&key, parts.network),
+            None => true,
         }
-        res.is_ok()
     }
 }
 
@@ -169,6 +446,139 @@ mod tests {
         assert!(!cache.is_allowed(&[4, 3, 2, 1]));
     }
 
+    #[test]
+    fn test_flood_clear() {
+        let cache = FloodCache::new(FloodThreshold::new(1, Duration::from_secs(60)));
+        assert!(cache.is_allowed(&vec![1, 2, 3, 4]));
+        assert!(!cache.is_allowed(&vec![1, 2, 3, 4]));
+        assert!(cache.is_allowed(&vec![4, 3, 2, 1]));
+
+        let limited = cache.limited();
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].0, vec![1, 2, 3, 4]);
+        assert!(limited[0].1 > Duration::from_secs(50));
+
+        assert!(cache.clear(&[1, 2, 3, 4][..]));
+        assert!(!cache.clear(&[1, 2, 3, 4][..]));
+        assert!(cache.limited().is_empty());
+        assert!(cache.is_allowed(&vec![1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_flood_pressure() {
+        let cache = FloodCache::new(
+            FloodThreshold::new(3, Duration::from_secs(60))
+                .with_pressure(Some(config::v4::FloodPressure::new(80, 1))),
+        );
+        let (full, empty) = (
+            Some(Ipv4Addr::new(10, 0, 0, 0)),
+            Some(Ipv4Addr::new(10, 1, 0, 0)),
+        );
+        assert!(!cache.set_utilization(full.unwrap(), 79));
+        assert!(cache.is_allowed_in(&[1, 2, 3, 4], full));
+
+        // pool filled up, existing & new clients of that network get the tighter limit
+        assert!(cache.set_utilization(full.unwrap(), 80));
+        assert!(!cache.set_utilization(empty.unwrap(), 10));
+        assert!(cache.under_pressure());
+        assert!(!cache.is_allowed_in(&[1, 2, 3, 4], full));
+        assert!(cache.is_allowed_in(&[4, 3, 2, 1], full));
+        assert!(!cache.is_allowed_in(&[4, 3, 2, 1], full));
+        // other networks & callers without one keep the normal limit
+        assert!(cache.is_allowed_in(&[5, 5, 5, 5], empty));
+        assert!(cache.is_allowed_in(&[5, 5, 5, 5], empty));
+        assert!(cache.is_allowed(&[6, 6, 6, 6]));
+        assert!(cache.is_allowed(&[6, 6, 6, 6]));
+
+        // the normal limit once it drains, without a fresh quota, both have 1 of 3 left
+        assert!(!cache.set_utilization(full.unwrap(), 50));
+        assert!(!cache.under_pressure());
+        assert!(cache.is_allowed_in(&[1, 2, 3, 4], full));
+        assert!(!cache.is_allowed_in(&[1, 2, 3, 4], full));
+        assert!(cache.is_allowed_in(&[4, 3, 2, 1], full));
+        assert!(!cache.is_allowed_in(&[4, 3, 2, 1], full));
+
+        // no pressure limit configured
+        let cache = FloodCache::new(FloodThreshold::new(3, Duration::from_secs(60)));
+        assert!(!cache.set_utilization(full.unwrap(), 100));
+        assert!(!cache.under_pressure());
+    }
+
+    #[test]
+    fn test_flood_limiter_key() {
+        let parts = |client_id: &'static [u8], giaddr: [u8; 4]| FloodKeyParts {
+            client_id,
+            chaddr: &[0xaa, 0xbb],
+            giaddr: giaddr.into(),
+            source: IpAddr::from(giaddr),
+            network: None,
+        };
+        let threshold = FloodThreshold::new(2, Duration::from_secs(60));
+        let by_client = FloodLimiter::new(threshold.clone());
+        assert_eq!(by_client.name(), "client_id");
+        assert_eq!(
+            by_client.key(&parts(&[1, 2], [10, 0, 0, 1])),
+            Some(vec![1, 2])
+        );
+
+        let by_relay = FloodLimiter::new(threshold.clone().with_key(vec![FloodKey::Giaddr]));
+        // a rotating client id behind one relay is still counted together
+        assert!(by_relay.is_allowed(&parts(&[1], [10, 0, 0, 1])));
+        assert!(by_relay.is_allowed(&parts(&[2], [10, 0, 0, 1])));
+        assert!(!by_relay.is_allowed(&parts(&[3], [10, 0, 0, 1])));
+        assert!(by_relay.is_allowed(&parts(&[3], [10, 0, 0, 2])));
+        assert_eq!(by_relay.cache().len(), 2);
+
+        let tuple = FloodLimiter::new(threshold.with_key(vec![FloodKey::Giaddr, FloodKey::Chaddr]));
+        assert_eq!(tuple.name(), "giaddr+chaddr");
+        assert_eq!(
+            tuple.key(&parts(&[1], [10, 0, 0, 1])),
+            Some(vec![0, 4, 10, 0, 0, 1, 0, 2, 0xaa, 0xbb])
+        );
+        // a long part doesn't wrap the length prefix
+        let long = [7; 300];
+        let by_id = FloodLimiter::new(
+            FloodThreshold::new(2, Duration::from_secs(60))
+                .with_key(vec![FloodKey::ClientId, FloodKey::Giaddr]),
+        );
+        let key = by_id
+            .key(&FloodKeyParts {
+                client_id: &long,
+                ..parts(&[], [10, 0, 0, 1])
+            })
+            .unwrap();
+        assert_eq!(&key[..2], &300u16.to_be_bytes());
+        assert_eq!(key.len(), 2 + 300 + 2 + 4);
+
+        // unrelayed clients without an address aren't all counted as one relay
+        for _ in 0..5 {
+            assert!(by_relay.is_allowed(&parts(&[1], [0, 0, 0, 0])));
+        }
+        assert_eq!(by_relay.key(&parts(&[1], [0, 0, 0, 0])), None);
+        assert!(tuple.key(&parts(&[1], [0, 0, 0, 0])).is_some());
+    }
+
+    #[test]
+    fn test_renew_stats() {
+        let cache = RenewThreshold::new(50);
+        cache.insert(vec![1, 2, 3, 4], Duration::from_secs(60));
+        assert!(cache.threshold(&[1, 2, 3, 4][..]).is_some());
+        assert!(cache.threshold(&[4, 3, 2, 1][..]).is_none());
+        assert!(cache.threshold(&[1, 2, 3, 4][..]).is_some());
+
+        let stats = cache.stats();
+        assert_eq!(
+            stats,
+            RenewStats {
+                entries: 1,
+                hits: 2,
+                misses: 1
+            }
+        );
+        assert!((stats.hit_ratio() - 2. / 3.).abs() < f64::EPSILON);
+        assert_eq!(RenewStats::default().hit_ratio(), 0.);
+    }
+
     #[test]
     fn test_renew_remaining() {
         let renew = RenewExpiry::new(Instant::now(), Duration::from_secs(5), 50);
@@ -1,32 +1,125 @@
 //! # Client Classes
 
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    net::Ipv4Addr,
+    ops::RangeInclusive,
+    time::Duration,
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use client_classification::{ast, Args, Expr, PacketDetails, Val};
-use dora_core::dhcproto::{
-    self,
-    v4::{self, OptionCode, UnknownOption},
-    Decodable, Decoder, Encodable,
+use dora_core::{
+    dhcproto::{
+        self,
+        v4::{self, OptionCode, UnknownOption},
+        v6, Encodable,
+    },
+    server::context::RespTransforms,
 };
+use ipnet::Ipv4Net;
 use topo_sort::DependencyTree;
 use tracing::{error, trace, warn};
 
 use crate::wire;
 pub use client_classification;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct ClientClasses {
     /// list of classes, order is topologically sorted based on use of `member` dependencies in the expression
     pub(crate) classes: HashMap<String, ClientClass>,
     pub(crate) original_order: Vec<String>,
     pub(crate) topo_order: Vec<String>,
+    /// some class, range or reservation references `KNOWN` or `UNKNOWN`
+    pub(crate) uses_known: bool,
 }
 
 impl ClientClasses {
     pub fn find(&self, name: &str) -> Option<&ClientClass> {
         self.classes.get(name)
     }
+    /// `true` if any class uses `member('KNOWN')` or `member('UNKNOWN')`, or a range or
+    /// reservation is limited to either class. The caller must then look up the client
+    /// before evaluating
+    pub fn uses_known(&self) -> bool {
+        self.uses_known
+    }
+    /// server identifier from the first matched class (in config order) that sets one
+    pub fn server_id(&self, matched_classes: Option<&[String]>) -> Option<Ipv4Addr> {
+        let matched = matched_classes?;
+        self.original_order
+            .iter()
+            .filter(|name| matched.contains(name))
+            .find_map(|name| self.find(name)?.server_id)
+    }
+    /// boot fields from matched classes, each field is taken from the first class (in config
+    /// order) that sets it
+    pub fn boot(&self, matched_classes: Option<&[String]>) -> wire::v4::Boot {
+        let Some(matched) = matched_classes else {
+            return wire::v4::Boot::default();
+        };
+        self.original_order
+            .iter()
+            .filter(|name| matched.contains(name))
+            .filter_map(|name| self.find(name))
+            .fold(wire::v4::Boot::default(), |boot, class| {
+                boot.or(&class.boot)
+            })
+    }
+    /// response transforms of all matched classes: every removed option, the lowest max
+    /// lease time & the broadcast flag if any class sets it
+    pub fn transforms(&self, matched_classes: Option<&[String]>) -> Option<RespTransforms> {
+        let matched = matched_classes?;
+        self.original_order
+            .iter()
+            .filter(|name| matched.contains(name))
+            .filter_map(|name| self.find(name)?.transforms.as_ref())
+            .fold(None, |ret: Option<RespTransforms>, class| {
+                let Some(mut ret) = ret else {
+                    return Some(class.clone());
+                };
+                for code in &class.remove {
+                    if !ret.remove.contains(code) {
+                        ret.remove.push(*code);
+                    }
+                }
+                ret.max_lease = match (ret.max_lease, class.max_lease) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+                ret.broadcast |= class.broadcast;
+                Some(ret)
+            })
+    }
+    /// all class server identifiers
+    pub(crate) fn server_ids(&self) -> impl Iterator<Item = (&str, Ipv4Addr)> {
+        self.classes
+            .values()
+            .filter_map(|class| Some((class.name.as_str(), class.server_id?)))
+    }
+    /// names of configured classes, in the order they appear in the config
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.original_order.iter().map(|name| name.as_str())
+    }
+    /// the `matched` classes that are configured, built-in classes & identity tags are left
+    /// out. Paired with whether the class has options for the response
+    pub fn configured<'a>(
+        &'a self,
+        matched: &'a [String],
+    ) -> impl Iterator<Item = (&'a str, bool)> + 'a {
+        matched
+            .iter()
+            .filter_map(|name| self.find(name))
+            .map(|class| (class.name.as_str(), class.options.iter().next().is_some()))
+    }
+    /// names of the classes whose `allow`/`deny` lists keep their clients out of `range`
+    pub(crate) fn restricted(&self, range: &RangeInclusive<Ipv4Addr>) -> Vec<String> {
+        self.original_order
+            .iter()
+            .filter(|name| matches!(self.find(name), Some(class) if !class.allows(range)))
+            .cloned()
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -35,6 +128,11 @@ pub struct ClientClass {
     // TODO: client classes assertion won't work with sub-options right now
     pub(crate) assert: Expr,
     pub(crate) options: v4::DhcpOptions,
+    pub(crate) server_id: Option<Ipv4Addr>,
+    pub(crate) boot: wire::v4::Boot,
+    pub(crate) allow: Option<Vec<Ipv4Net>>,
+    pub(crate) deny: Vec<Ipv4Net>,
+    pub(crate) transforms: Option<RespTransforms>,
 }
 
 impl TryFrom<wire::client_classes::ClientClasses> for ClientClasses {
@@ -45,10 +143,33 @@ impl TryFrom<wire::client_classes::ClientClasses> for ClientClasses {
         let original_order = cfg.v4.iter().map(|c| c.name.clone()).collect();
         let mut dep_tree = DependencyTree::new();
         let mut classes = HashMap::new();
+        let mut uses_known = false;
         for class in cfg.v4.into_iter() {
             let assert = ast::parse(&class.assert)
                 .with_context(|| format!("failed to parse client class {}", class.name))?;
+            class
+                .boot
+                .validate()
+                .with_context(|| format!("invalid boot fields in client class {}", class.name))?;
+            if class.name == client_classification::DROP_CLASS
+                && (class.allow.is_some() || !class.deny.is_empty())
+            {
+                // DROP clients never get a response, restricting them does nothing
+                bail!("client class {} can't have allow or deny lists", class.name);
+            }
+            for transform in &class.transforms {
+                transform
+                    .validate()
+                    .map_err(anyhow::Error::msg)
+                    .with_context(|| {
+                        format!("invalid transforms in client class {}", class.name)
+                    })?;
+            }
             let deps = client_classification::get_class_dependencies(&assert);
+            uses_known |= deps.iter().any(|dep| {
+                dep == client_classification::KNOWN_CLASS
+                    || dep == client_classification::UNKNOWN_CLASS
+            });
             let name = class.name.clone();
             dep_tree.add(name.clone(), name, deps);
             classes.insert(
@@ -57,6 +178,11 @@ impl TryFrom<wire::client_classes::ClientClasses> for ClientClasses {
                     name: class.name,
                     assert,
                     options: class.options.get(),
+                    server_id: class.server_id,
+                    boot: class.boot,
+                    allow: class.allow,
+                    deny: class.deny,
+                    transforms: resp_transforms(&class.transforms),
                 },
             );
         }
@@ -65,17 +191,20 @@ impl TryFrom<wire::client_classes::ClientClasses> for ClientClasses {
             classes,
             original_order,
             topo_order: dep_tree.topological_sort()?,
+            uses_known,
         })
     }
 }
 
 impl ClientClasses {
-    /// evaluate all client classes, returning a list of classes that match
+    /// evaluate all client classes, returning a list of classes that match.
+    /// `known` is whether the client has a reservation or active lease, if it was looked up
     pub fn eval(
         &self,
         req: &dhcproto::v4::Message,
-        pkt: PacketDetails,
+        mut pkt: PacketDetails,
         bootp_enabled: bool,
+        known: Option<bool>,
     ) -> Result<Vec<String>> {
         let (chaddr, opts) = to_unknown_opts(req)?;
         let vendor_builtin = client_classification::create_builtin_vendor(req);
@@ -83,6 +212,7 @@ impl ClientClasses {
         let is_bootp = bootp_enabled
             && req.opts().msg_type().is_none()
             && req.opcode() == v4::Opcode::BootRequest;
+        let suspect = pkt.suspect;
 
         if let Err(err) = vendor_builtin {
             // log error but don't stop evaluation
@@ -105,6 +235,26 @@ impl ClientClasses {
                 if is_bootp {
                     set.insert(client_classification::BOOTP_CLASS.to_owned());
                 }
+                // add "SUSPECT"
+                if suspect {
+                    set.insert(client_classification::SUSPECT_CLASS.to_owned());
+                }
+                // add "RANDOM_MAC"
+                if client_classification::is_random_mac(req) {
+                    set.insert(client_classification::RANDOM_MAC_CLASS.to_owned());
+                }
+                // classes from the identity backend
+                set.extend(std::mem::take(&mut pkt.tags));
+                // add "KNOWN" or "UNKNOWN"
+                match known {
+                    Some(true) => {
+                        set.insert(client_classification::KNOWN_CLASS.to_owned());
+                    }
+                    Some(false) => {
+                        set.insert(client_classification::UNKNOWN_CLASS.to_owned());
+                    }
+                    None => {}
+                }
                 set
             },
             msg: req,
@@ -140,19 +290,194 @@ impl ClientClasses {
 }
 
 impl ClientClass {
+    /// can clients in this class get addresses from `range`? A subnet in `allow` or `deny`
+    /// applies to a range when the whole range is inside it
+    pub fn allows(&self, range: &RangeInclusive<Ipv4Addr>) -> bool {
+        let covers = |net: &Ipv4Net| net.contains(range.start()) && net.contains(range.end());
+        self.allow
+            .as_ref()
+            .map(|allow| allow.iter().any(covers))
+            .unwrap_or(true)
+            && !self.deny.iter().any(covers)
+    }
     pub fn eval(&self, args: &Args) -> bool {
-        trace!(name = ?self.name, expr = ?self.assert, chaddr = ?args.chaddr, "evaluating expression");
-        match client_classification::eval(&self.assert, args) {
-            Ok(Val::Bool(true)) => true,
-            Ok(Val::Bool(false)) => false,
-            res => {
-                error!(name = ?self.name, ?res, "expression didn't evaluate to true/false");
-                false
+        eval_assert(&self.name, &self.assert, args)
+    }
+}
+
+/// the wire transform list as [`RespTransforms`], `None` if there are none
+fn resp_transforms(transforms: &[wire::client_classes::Transform]) -> Option<RespTransforms> {
+    use wire::client_classes::Transform;
+
+    if transforms.is_empty() {
+        return None;
+    }
+    let mut ret = RespTransforms::default();
+    for transform in transforms {
+        match *transform {
+            Transform::RemoveOption(code) => ret.remove.push(code.into()),
+            Transform::MaxLeaseTime(secs) => {
+                let secs = Duration::from_secs(secs as u64);
+                ret.max_lease = Some(ret.max_lease.map_or(secs, |max| max.min(secs)));
+            }
+            Transform::Broadcast => ret.broadcast = true,
+        }
+    }
+    Some(ret)
+}
+
+fn eval_assert(name: &str, assert: &Expr, args: &Args) -> bool {
+    trace!(?name, expr = ?assert, chaddr = ?args.chaddr, "evaluating expression");
+    match client_classification::eval(assert, args) {
+        Ok(Val::Bool(true)) => true,
+        Ok(Val::Bool(false)) => false,
+        res => {
+            error!(?name, ?res, "expression didn't evaluate to true/false");
+            false
+        }
+    }
+}
+
+/// DHCPv6 client classes. Expressions use the same grammar as v4, `option[N]` reads the v6
+/// option with code `N` (only codes up to 254 can be referenced) and `pkt4` fields are empty.
+/// The only built-in class is `ALL`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ClientClassesV6 {
+    pub(crate) classes: HashMap<String, ClientClassV6>,
+    pub(crate) original_order: Vec<String>,
+    pub(crate) topo_order: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientClassV6 {
+    pub(crate) name: String,
+    pub(crate) assert: Expr,
+    pub(crate) options: v6::DhcpOptions,
+}
+
+impl TryFrom<Vec<wire::client_classes::ClientClassV6>> for ClientClassesV6 {
+    type Error = anyhow::Error;
+
+    fn try_from(cfg: Vec<wire::client_classes::ClientClassV6>) -> Result<Self, Self::Error> {
+        let original_order = cfg.iter().map(|c| c.name.clone()).collect();
+        let mut dep_tree = DependencyTree::new();
+        let mut classes = HashMap::new();
+        for class in cfg.into_iter() {
+            let assert = ast::parse(&class.assert)
+                .with_context(|| format!("failed to parse v6 client class {}", class.name))?;
+            let deps = client_classification::get_class_dependencies(&assert);
+            if let Some(dep) = deps.iter().find(|dep| {
+                [
+                    client_classification::KNOWN_CLASS,
+                    client_classification::UNKNOWN_CLASS,
+                    client_classification::BOOTP_CLASS,
+                    client_classification::SUSPECT_CLASS,
+                    client_classification::RANDOM_MAC_CLASS,
+                ]
+                .contains(&dep.as_str())
+            }) {
+                bail!(
+                    "v6 client class {} uses {dep}, which is only evaluated for v4",
+                    class.name
+                );
             }
+            let name = class.name.clone();
+            dep_tree.add(name.clone(), name, deps);
+            classes.insert(
+                class.name.clone(),
+                ClientClassV6 {
+                    name: class.name,
+                    assert,
+                    options: class.options.get(),
+                },
+            );
         }
+
+        Ok(Self {
+            classes,
+            original_order,
+            topo_order: dep_tree.topological_sort()?,
+        })
     }
 }
 
+impl ClientClassesV6 {
+    pub fn find(&self, name: &str) -> Option<&ClientClassV6> {
+        self.classes.get(name)
+    }
+    /// names of configured classes, in the order they appear in the config
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.original_order.iter().map(|name| name.as_str())
+    }
+    /// the `matched` classes that are configured, `ALL` is left out. Paired with whether
+    /// the class has options for the response
+    pub fn configured<'a>(
+        &'a self,
+        matched: &'a [String],
+    ) -> impl Iterator<Item = (&'a str, bool)> + 'a {
+        matched
+            .iter()
+            .filter_map(|name| self.find(name))
+            .map(|class| (class.name.as_str(), class.options.iter().next().is_some()))
+    }
+    /// evaluate all v6 client classes, returning a list of classes that match
+    pub fn eval(&self, req: &v6::Message) -> Result<Vec<String>> {
+        // the expression engine works on v4 messages, pkt4 fields read from a blank one
+        let blank = v4::Message::default();
+        let mut args = Args {
+            chaddr: &[],
+            member: [client_classification::ALL_CLASS.to_owned()]
+                .into_iter()
+                .collect(),
+            msg: &blank,
+            opts: to_unknown_opts_v6(req)?,
+            pkt: PacketDetails::default(),
+        };
+        for name in &self.topo_order {
+            let class = self.classes.get(name).context("class not found")?;
+            if eval_assert(&class.name, &class.assert, &args) {
+                args.member.insert(class.name.to_owned());
+            }
+        }
+
+        Ok(args.member.into_iter().collect())
+    }
+    /// options of the matched classes merged together, earlier classes in the `client_classes`
+    /// list win duplicates
+    pub fn collect_opts(&self, matched_classes: Option<&[String]>) -> Option<v6::DhcpOptions> {
+        let matched = matched_classes?;
+        self.original_order
+            .iter()
+            .filter(|name| matched.contains(name))
+            .filter_map(|name| self.find(name))
+            .fold(None, |ret, class| {
+                Some(match ret {
+                    Some(opts) => crate::v6::merge_opts(&opts, &class.options),
+                    None => class.options.clone(),
+                })
+            })
+    }
+}
+
+/// v6 options as v4 `UnknownOption`s so `option[N]` can read them, codes that don't fit in
+/// a byte are skipped. Only the first instance of a repeated option is kept
+fn to_unknown_opts_v6(req: &v6::Message) -> Result<HashMap<OptionCode, UnknownOption>> {
+    let mut opts = HashMap::new();
+    for opt in req.opts().iter() {
+        let code = u16::from(v6::OptionCode::from(opt));
+        let Ok(code @ 1..=254) = u8::try_from(code) else {
+            continue;
+        };
+        // encoded as 2 byte code, 2 byte len, data
+        let buf = opt
+            .to_vec()
+            .context("failed to convert options in v6 client_classes")?;
+        opts.entry(OptionCode::from(code))
+            .or_insert_with(|| UnknownOption::new(OptionCode::from(code), buf[4..].to_vec()));
+    }
+    Ok(opts)
+}
+
 fn to_unknown_opts(
     req: &dhcproto::v4::Message,
 ) -> Result<(&[u8], HashMap<OptionCode, UnknownOption>)> {
@@ -162,19 +487,39 @@ fn to_unknown_opts(
         req.chaddr(),
         req.opts()
             .iter()
+            // opt 82 that failed to parse is kept as raw bytes by dora-core, skip it
+            // so sub-option lookups don't error on every class that references `relay4`
+            .filter(|(k, v)| {
+                !(**k == OptionCode::RelayAgentInformation
+                    && matches!(v, v4::DhcpOption::Unknown(_)))
+            })
             .map(|(k, v)| {
-                Ok((*k, {
-                    // using UnknownOption here so that the data section is easy to get
-                    let opt = v.to_vec()?;
-                    let mut d = Decoder::new(&opt);
-                    UnknownOption::decode(&mut d)?
-                }))
+                // using UnknownOption here so that the data section is easy to get
+                Ok((
+                    *k,
+                    match v {
+                        v4::DhcpOption::Unknown(opt) => opt.clone(),
+                        v => UnknownOption::new(*k, opt_data(&v.to_vec()?)),
+                    },
+                ))
             })
             .collect::<Result<HashMap<_, _>>>()
             .context("failed to convert options in client_classes")?,
     ))
 }
 
+/// data section of an encoded option, options longer than 255 bytes are encoded as
+/// consecutive options with the same code (RFC 3396) and are joined back together
+fn opt_data(mut buf: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(buf.len());
+    while let [_code, len, rest @ ..] = buf {
+        let len = (*len as usize).min(rest.len());
+        data.extend_from_slice(&rest[..len]);
+        buf = &rest[len..];
+    }
+    data
+}
+
 /// merge `b` into `a`, favoring `b` where there are duplicates
 fn merge_opts(a: &v4::DhcpOptions, b: Option<v4::DhcpOptions>) -> Option<v4::DhcpOptions> {
     match b {
@@ -219,6 +564,11 @@ mod tests {
                             opts.insert(v4::DhcpOption::AddressLeaseTime(10));
                             opts
                         },
+                        server_id: None,
+                        boot: wire::v4::Boot::default(),
+                        allow: None,
+                        deny: Vec::new(),
+                        transforms: None,
                     },
                 ),
                 (
@@ -233,6 +583,11 @@ mod tests {
                             opts.insert(v4::DhcpOption::TimeOffset(50));
                             opts
                         },
+                        server_id: None,
+                        boot: wire::v4::Boot::default(),
+                        allow: None,
+                        deny: Vec::new(),
+                        transforms: None,
                     },
                 ),
                 (
@@ -246,11 +601,17 @@ mod tests {
                             opts.insert(v4::DhcpOption::ArpCacheTimeout(1));
                             opts
                         },
+                        server_id: None,
+                        boot: wire::v4::Boot::default(),
+                        allow: None,
+                        deny: Vec::new(),
+                        transforms: None,
                     },
                 ),
             ]
             .into_iter()
             .collect(),
+            uses_known: false,
         };
         let opts = classes.collect_opts(Some(&["foo".to_owned(), "bar".to_owned()]));
         // includes opts from "foo" and "bar", favouring "foo" for duplicates because it shows up earlier in the `client_classes` list
@@ -281,16 +642,22 @@ mod tests {
                         opts.insert(v4::DhcpOption::AddressLeaseTime(10));
                         opts
                     },
+                    server_id: None,
+                    boot: wire::v4::Boot::default(),
+                    allow: None,
+                    deny: Vec::new(),
+                    transforms: None,
                 },
             )]
             .into_iter()
             .collect(),
+            uses_known: false,
         };
         let uns = Ipv4Addr::UNSPECIFIED;
         let bootp = v4::Message::new(uns, uns, uns, uns, &[1, 2, 3, 4, 5, 6]);
         // msg is a bootp message because it has empty opts
         let res = classes
-            .eval(&bootp, PacketDetails::default(), true)
+            .eval(&bootp, PacketDetails::default(), true, None)
             .unwrap();
         assert_eq!(
             res.iter().collect::<HashSet<_>>(),
@@ -306,7 +673,9 @@ mod tests {
         msg.opts_mut()
             .insert(v4::DhcpOption::ClassIdentifier(b"docsis3.0".to_vec()));
         // msg is a bootp message because it has empty opts
-        let res = classes.eval(&msg, PacketDetails::default(), true).unwrap();
+        let res = classes
+            .eval(&msg, PacketDetails::default(), true, None)
+            .unwrap();
         assert_eq!(
             res.iter().collect::<HashSet<_>>(),
             [
@@ -318,4 +687,168 @@ mod tests {
             .collect::<HashSet<_>>()
         );
     }
+
+    #[test]
+    fn eval_known() {
+        let classes = ClientClasses {
+            original_order: vec!["known".to_owned()],
+            topo_order: vec!["known".to_owned()],
+            classes: [(
+                "known".to_owned(),
+                ClientClass {
+                    name: "known".to_owned(),
+                    assert: ast::parse("member('KNOWN')").unwrap(),
+                    options: v4::DhcpOptions::new(),
+                    server_id: None,
+                    boot: wire::v4::Boot::default(),
+                    allow: None,
+                    deny: Vec::new(),
+                    transforms: None,
+                },
+            )]
+            .into_iter()
+            .collect(),
+            uses_known: true,
+        };
+        let uns = Ipv4Addr::UNSPECIFIED;
+        let mut msg = v4::Message::new(uns, uns, uns, uns, &[1, 2, 3, 4, 5, 6]);
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Discover));
+
+        let res = classes
+            .eval(&msg, PacketDetails::default(), false, Some(true))
+            .unwrap();
+        assert!(res.contains(&"known".to_owned()));
+        assert!(res.contains(&"KNOWN".to_owned()));
+
+        let res = classes
+            .eval(&msg, PacketDetails::default(), false, Some(false))
+            .unwrap();
+        assert!(!res.contains(&"known".to_owned()));
+        assert!(res.contains(&"UNKNOWN".to_owned()));
+    }
+
+    #[test]
+    fn eval_random_mac() {
+        let classes = ClientClasses::default();
+        let uns = Ipv4Addr::UNSPECIFIED;
+        let mut msg = v4::Message::new(uns, uns, uns, uns, &[0xda, 2, 3, 4, 5, 6]);
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Discover));
+        let res = classes
+            .eval(&msg, PacketDetails::default(), false, None)
+            .unwrap();
+        assert!(res.contains(&"RANDOM_MAC".to_owned()));
+
+        let msg = v4::Message::new(uns, uns, uns, uns, &[0xd8, 2, 3, 4, 5, 6]);
+        let res = classes
+            .eval(&msg, PacketDetails::default(), false, None)
+            .unwrap();
+        assert!(!res.contains(&"RANDOM_MAC".to_owned()));
+    }
+
+    #[test]
+    fn eval_malformed_relay() {
+        let classes = ClientClasses {
+            original_order: vec!["no_relay".to_owned()],
+            topo_order: vec!["no_relay".to_owned()],
+            classes: [(
+                "no_relay".to_owned(),
+                ClientClass {
+                    name: "no_relay".to_owned(),
+                    assert: ast::parse("not relay4[1].exists").unwrap(),
+                    options: v4::DhcpOptions::new(),
+                    server_id: None,
+                    boot: wire::v4::Boot::default(),
+                    allow: None,
+                    deny: Vec::new(),
+                    transforms: None,
+                },
+            )]
+            .into_iter()
+            .collect(),
+            uses_known: false,
+        };
+        let uns = Ipv4Addr::UNSPECIFIED;
+        let mut msg = v4::Message::new(uns, uns, uns, uns, &[1, 2, 3, 4, 5, 6]);
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Discover));
+        // sub-opt 1 claims 5 bytes but only has 1
+        msg.opts_mut()
+            .insert(v4::DhcpOption::Unknown(UnknownOption::new(
+                OptionCode::RelayAgentInformation,
+                vec![1, 5, 0xaa],
+            )));
+
+        let res = classes
+            .eval(&msg, PacketDetails::default(), false, None)
+            .unwrap();
+        assert!(res.contains(&"no_relay".to_owned()));
+    }
+
+    #[test]
+    fn eval_long_opt() {
+        // 300 bytes, split into 2 options on the wire
+        let data = (0..300).map(|i| i as u8).collect::<Vec<_>>();
+        let classes = ClientClasses {
+            original_order: vec!["long".to_owned()],
+            topo_order: vec!["long".to_owned()],
+            classes: [(
+                "long".to_owned(),
+                ClientClass {
+                    name: "long".to_owned(),
+                    assert: ast::parse(format!("option[224].hex == 0x{}", hex::encode(&data)))
+                        .unwrap(),
+                    options: v4::DhcpOptions::new(),
+                    server_id: None,
+                    boot: wire::v4::Boot::default(),
+                    allow: None,
+                    deny: Vec::new(),
+                    transforms: None,
+                },
+            )]
+            .into_iter()
+            .collect(),
+            uses_known: false,
+        };
+        let uns = Ipv4Addr::UNSPECIFIED;
+        let mut msg = v4::Message::new(uns, uns, uns, uns, &[1, 2, 3, 4, 5, 6]);
+        msg.opts_mut()
+            .insert(v4::DhcpOption::Unknown(UnknownOption::new(
+                OptionCode::from(224),
+                data,
+            )));
+
+        let res = classes
+            .eval(&msg, PacketDetails::default(), false, None)
+            .unwrap();
+        assert!(res.contains(&"long".to_owned()));
+        assert_eq!(opt_data(&[224, 2, 1, 2, 224, 1, 3]), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn class_server_id() {
+        let cfg: wire::client_classes::ClientClasses = serde_yaml::from_str(
+            "
+v4:
+  - name: a
+    assert: 'true'
+  - name: b
+    assert: 'true'
+    server_id: 10.0.0.10
+  - name: c
+    assert: 'true'
+    server_id: 10.0.0.20
+",
+        )
+        .unwrap();
+        let classes = ClientClasses::try_from(cfg).unwrap();
+        // config order decides precedence, not match order
+        assert_eq!(
+            classes.server_id(Some(&["a".to_owned(), "c".to_owned(), "b".to_owned()])),
+            Some(Ipv4Addr::new(10, 0, 0, 10))
+        );
+        assert_eq!(classes.server_id(Some(&["a".to_owned()])), None);
+        assert_eq!(classes.server_id(None), None);
+    }
 }
@@ -0,0 +1,189 @@
+//! # Exclusions
+//!
+//! Excluded addresses of a range, for both v4 & v6. They're kept as sorted & merged blocks
+//! of the address as an integer, so skipping over them doesn't depend on how many addresses
+//! are excluded
+use std::{
+    fmt::Debug,
+    net::{Ipv4Addr, Ipv6Addr},
+    ops::{RangeInclusive, Sub},
+};
+
+use tracing::warn;
+
+/// integer an address is stored as, `u32` for v4 & `u128` for v6
+pub trait AddrInt: Copy + Ord + Debug + Sub<Output = Self> + TryInto<usize> {
+    const ZERO: Self;
+    const ONE: Self;
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+    fn saturating_add(self, rhs: Self) -> Self;
+}
+
+macro_rules! addr_int {
+    ($($int:ty),*) => {
+        $(
+            impl AddrInt for $int {
+                const ZERO: Self = 0;
+                const ONE: Self = 1;
+                fn checked_add(self, rhs: Self) -> Option<Self> {
+                    <$int>::checked_add(self, rhs)
+                }
+                fn saturating_add(self, rhs: Self) -> Self {
+                    <$int>::saturating_add(self, rhs)
+                }
+            }
+        )*
+    };
+}
+
+addr_int!(u32, u128);
+
+/// an address that can be excluded from a range
+pub trait Addr: Copy + Ord + Debug + From<Self::Int> + Into<Self::Int> {
+    type Int: AddrInt;
+}
+
+impl Addr for Ipv4Addr {
+    type Int = u32;
+}
+
+impl Addr for Ipv6Addr {
+    type Int = u128;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exclusions<A: Addr> {
+    blocks: Vec<RangeInclusive<A::Int>>,
+}
+
+impl<A: Addr> Default for Exclusions<A> {
+    fn default() -> Self {
+        Self { blocks: Vec::new() }
+    }
+}
+
+impl<A: Addr> Exclusions<A> {
+    pub fn new<I>(ranges: I) -> Self
+    where
+        I: IntoIterator<Item = RangeInclusive<A>>,
+    {
+        let mut ranges = ranges
+            .into_iter()
+            .filter(|range| {
+                if range.is_empty() {
+                    warn!(?range, "ignoring exclusion with start after end");
+                }
+                !range.is_empty()
+            })
+            .map(|range| (*range.start()).into()..=(*range.end()).into())
+            .collect::<Vec<RangeInclusive<A::Int>>>();
+        ranges.sort_by_key(|range| *range.start());
+
+        let mut blocks: Vec<RangeInclusive<A::Int>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match blocks.last_mut() {
+                // overlapping or adjacent, extend the previous block
+                Some(last) if *range.start() <= last.end().saturating_add(A::Int::ONE) => {
+                    if range.end() > last.end() {
+                        *last = *last.start()..=*range.end();
+                    }
+                }
+                _ => blocks.push(range),
+            }
+        }
+        Self { blocks }
+    }
+    /// combine with another set of exclusions
+    pub fn merge(&self, other: &Exclusions<A>) -> Exclusions<A> {
+        if other.is_empty() {
+            return self.clone();
+        }
+        Self::new(self.iter().chain(other.iter()))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+    /// the excluded blocks, in order
+    pub fn iter(&self) -> impl Iterator<Item = RangeInclusive<A>> + '_ {
+        self.blocks
+            .iter()
+            .map(|block| A::from(*block.start())..=A::from(*block.end()))
+    }
+    fn block(&self, ip: A::Int) -> Option<&RangeInclusive<A::Int>> {
+        // first block that doesn't end before `ip`
+        let idx = self.blocks.partition_point(|block| *block.end() < ip);
+        self.blocks.get(idx).filter(|block| block.contains(&ip))
+    }
+    pub fn contains(&self, ip: &A) -> bool {
+        self.block((*ip).into()).is_some()
+    }
+    /// the first address at or after `ip` that isn't excluded
+    pub fn skip(&self, ip: A) -> Option<A> {
+        match self.block(ip.into()) {
+            // blocks are merged, so the address after a block is never excluded
+            Some(block) => block.end().checked_add(A::Int::ONE).map(A::from),
+            None => Some(ip),
+        }
+    }
+    /// number of excluded addresses inside `range`, saturating if it doesn't fit
+    pub fn count_in(&self, range: &RangeInclusive<A>) -> A::Int {
+        let (start, end): (A::Int, A::Int) = ((*range.start()).into(), (*range.end()).into());
+        self.blocks
+            .iter()
+            .filter(|block| *block.start() <= end && *block.end() >= start)
+            .map(|block| {
+                (end.min(*block.end()) - start.max(*block.start())).saturating_add(A::Int::ONE)
+            })
+            .fold(A::Int::ZERO, AddrInt::saturating_add)
+    }
+}
+
+/// iterates the addresses of a range, jumping over excluded blocks
+#[derive(Debug)]
+pub struct NetRangeIter<'a, A: Addr> {
+    exclusions: &'a Exclusions<A>,
+    next: Option<A>,
+    end: A,
+}
+
+impl<'a, A: Addr> NetRangeIter<'a, A> {
+    pub fn new(range: RangeInclusive<A>, exclusions: &'a Exclusions<A>) -> Self {
+        Self {
+            exclusions,
+            next: Some(*range.start()).filter(|start| start <= range.end()),
+            end: *range.end(),
+        }
+    }
+}
+
+impl<A: Addr> Iterator for NetRangeIter<'_, A> {
+    type Item = A;
+
+    // jumps over excluded blocks
+    fn next(&mut self) -> Option<Self::Item> {
+        let ip = self
+            .exclusions
+            .skip(self.next.take()?)
+            .filter(|ip| *ip <= self.end)?;
+        if ip < self.end {
+            self.next = Into::<A::Int>::into(ip)
+                .checked_add(A::Int::ONE)
+                .map(A::from);
+        }
+        Some(ip)
+    }
+    // counted without iterating, saturates at `usize::MAX` for huge v6 ranges
+    fn count(self) -> usize {
+        match self.next {
+            Some(start) => {
+                let range = start..=self.end;
+                let (start_int, end_int): (A::Int, A::Int) = (start.into(), self.end.into());
+                let total = (end_int - start_int).saturating_add(A::Int::ONE);
+                (total - self.exclusions.count_in(&range))
+                    .try_into()
+                    .unwrap_or(usize::MAX)
+            }
+            None => 0,
+        }
+    }
+}
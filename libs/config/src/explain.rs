@@ -0,0 +1,383 @@
+//! # Explain
+//!
+//! Works out what the config would answer a hypothetical client with, without a packet
+//! being sent or an address allocated. Client classes are evaluated, then the
+//! reservation or range is picked the same way as static-addr & leases, and the options
+//! the client asked for (opt 55) are collected from it.
+//!
+//! There is no received packet, so `pkt.*` & `now.*` in class expressions see
+//! placeholder values, and `KNOWN` only counts reservations, not active leases. Router &
+//! netmask filled from the listening interface aren't included.
+use std::{fmt, net::Ipv4Addr, ops::RangeInclusive, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use client_classification::PacketDetails;
+use dora_core::{
+    dhcproto::{
+        v4::{DhcpOption, DhcpOptions, Message, MessageType, OptionCode},
+        Decodable, Decoder,
+    },
+    pretty::{V4OptName, V4OptValue},
+};
+use ipnet::Ipv4Net;
+use serde::Serialize;
+
+use crate::{
+    v4::{Config, Network, Reserved},
+    wire::v4::Boot,
+};
+
+/// what the config would answer a client with
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Explanation {
+    /// network the subnet is in, unset if no network matches & the client gets no answer
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>, example = "192.168.0.0/24"))]
+    pub network: Option<Ipv4Net>,
+    /// client classes that matched
+    pub classes: Vec<String>,
+    /// the client matched the `DROP` class & gets no answer
+    pub dropped: bool,
+    /// the client's reserved address
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>, format = "ipv4"))]
+    pub reserved: Option<Ipv4Addr>,
+    /// range the address comes from, if the client has no reservation
+    #[cfg_attr(feature = "openapi", schema(schema_with = range_schema))]
+    pub range: Option<RangeInclusive<Ipv4Addr>>,
+    /// opt 51
+    pub lease_secs: Option<u64>,
+    /// opt 58
+    pub renew_secs: Option<u64>,
+    /// opt 59
+    pub rebind_secs: Option<u64>,
+    /// options the client asked for that it would get, in request order
+    pub options: Vec<ExplainedOption>,
+    /// `siaddr`/`sname`/`file`
+    pub boot: Boot,
+}
+
+/// an option in an [`Explanation`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ExplainedOption {
+    pub code: u8,
+    pub name: String,
+    /// the value as dora logs it
+    pub value: String,
+}
+
+// `RangeInclusive` serializes as `{"start": .., "end": ..}`
+#[cfg(feature = "openapi")]
+fn range_schema() -> utoipa::openapi::Object {
+    use utoipa::openapi::schema::{ObjectBuilder, SchemaFormat, SchemaType};
+
+    let ip = || {
+        ObjectBuilder::new()
+            .schema_type(SchemaType::String)
+            .format(Some(SchemaFormat::Custom("ipv4".to_owned())))
+    };
+    ObjectBuilder::new()
+        .nullable(true)
+        .property("start", ip())
+        .required("start")
+        .property("end", ip())
+        .required("end")
+        .build()
+}
+
+/// a DISCOVER from `chaddr` carrying `opts`, pairs of an option code & its data
+pub fn request(chaddr: &[u8], opts: &[(u8, Vec<u8>)]) -> Result<Message> {
+    let mut buf = Vec::new();
+    for (code, data) in opts {
+        if data.len() > u8::MAX as usize {
+            bail!("option {code} is longer than 255 bytes");
+        }
+        buf.push(*code);
+        buf.push(data.len() as u8);
+        buf.extend_from_slice(data);
+    }
+    buf.push(u8::from(OptionCode::End));
+    let decoded =
+        DhcpOptions::decode(&mut Decoder::new(&buf)).context("failed to decode options")?;
+
+    let uns = Ipv4Addr::UNSPECIFIED;
+    let mut msg = Message::new(uns, uns, uns, uns, chaddr);
+    for (_, opt) in decoded.iter() {
+        msg.opts_mut().insert(opt.clone());
+    }
+    msg.opts_mut()
+        .insert(DhcpOption::MessageType(MessageType::Discover));
+    Ok(msg)
+}
+
+/// parse `code=value`. Opt 55 takes comma separated codes, other values are hex with a
+/// `0x` prefix, comma separated IPv4 addresses, or text
+pub fn parse_opt(s: &str) -> Result<(u8, Vec<u8>)> {
+    let (code, value) = s
+        .split_once('=')
+        .with_context(|| format!("expected `code=value`, got `{s}`"))?;
+    let code: u8 = code
+        .trim()
+        .parse()
+        .with_context(|| format!("`{code}` is not an option code"))?;
+    let data = if code == u8::from(OptionCode::ParameterRequestList) {
+        value
+            .split(',')
+            .map(|c| c.trim().parse::<u8>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("`{value}` is not a list of option codes"))?
+    } else if let Some(hex) = value.strip_prefix("0x") {
+        hex::decode(hex).with_context(|| format!("`{value}` is not valid hex"))?
+    } else if let Ok(ips) = value
+        .split(',')
+        .map(|ip| ip.trim().parse::<Ipv4Addr>())
+        .collect::<Result<Vec<_>, _>>()
+    {
+        ips.iter().flat_map(|ip| ip.octets()).collect()
+    } else {
+        value.as_bytes().to_vec()
+    };
+    Ok((code, data))
+}
+
+/// what `cfg` would answer `req` with, if it arrived from `subnet`
+pub fn explain(cfg: &Config, subnet: Ipv4Addr, req: &Message) -> Result<Explanation> {
+    let Some((net, network)) = cfg.networks().find(|(net, _)| net.contains(&subnet)) else {
+        return Ok(Explanation::default());
+    };
+    let known = cfg
+        .classes()
+        .is_some_and(|classes| classes.uses_known())
+        .then(|| network.has_reservation(req.chaddr(), req.opts()));
+    let pkt = PacketDetails {
+        src: subnet,
+        ..PacketDetails::default()
+    };
+    let classes = cfg.eval_client_classes(req, pkt, known).transpose()?;
+    let mut explanation = Explanation {
+        network: Some(*net),
+        classes: classes.clone().unwrap_or_default(),
+        ..Explanation::default()
+    };
+    explanation.classes.sort();
+    if explanation
+        .classes
+        .iter()
+        .any(|class| class == client_classification::DROP_CLASS)
+    {
+        explanation.dropped = true;
+        return Ok(explanation);
+    }
+    let classes = classes.as_deref();
+
+    let base = cfg.boot(Some(network), classes);
+    let (opts, lease, boot) = match reservation(network, req, classes) {
+        Some(res) => {
+            explanation.reserved = Some(res.ip());
+            (res.opts(), res.lease(), res.boot().or(&base))
+        }
+        None => {
+            let requested = match req.opts().get(OptionCode::RequestedIpAddress) {
+                Some(DhcpOption::RequestedIpAddress(ip)) => Some(*ip),
+                _ => None,
+            };
+            let range = requested
+                .and_then(|ip| network.range(ip, classes))
+                .or_else(|| network.ranges_with_class(classes).next());
+            let Some(range) = range else {
+                explanation.boot = base;
+                return Ok(explanation);
+            };
+            explanation.range = Some(range.addrs());
+            (range.opts(), range.lease(), range.boot().or(&base))
+        }
+    };
+    let requested_lease = match req.opts().get(OptionCode::AddressLeaseTime) {
+        Some(DhcpOption::AddressLeaseTime(secs)) => Some(Duration::from_secs(*secs as u64)),
+        _ => None,
+    };
+    let (lease, renew, rebind) = lease.determine_lease(requested_lease);
+    explanation.lease_secs = Some(lease.as_secs());
+    explanation.renew_secs = renew.map(|t| t.as_secs());
+    explanation.rebind_secs = rebind.map(|t| t.as_secs());
+
+    let param_opts = cfg.collect_opts(opts, classes);
+    if let Some(DhcpOption::ParameterRequestList(codes)) =
+        req.opts().get(OptionCode::ParameterRequestList)
+    {
+        explanation.options = codes
+            .iter()
+            .filter_map(|code| param_opts.get(*code).map(|opt| (*code, opt)))
+            .map(|(code, opt)| ExplainedOption {
+                code: code.into(),
+                name: V4OptName(code).to_string(),
+                value: V4OptValue(opt).to_string(),
+            })
+            .collect();
+    }
+    explanation.boot = boot;
+    Ok(explanation)
+}
+
+/// the reservation static-addr would use for `req`
+fn reservation<'a>(
+    network: &'a Network,
+    req: &Message,
+    classes: Option<&[String]>,
+) -> Option<&'a Reserved> {
+    <[u8; 6]>::try_from(req.chaddr())
+        .ok()
+        .and_then(|mac| network.get_reserved_mac(mac.into(), classes))
+        .or_else(|| network.search_reserved_opt(req.opts(), classes))
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(network) = self.network else {
+            return writeln!(f, "no network matches, the client gets no answer");
+        };
+        writeln!(f, "network: {network}")?;
+        writeln!(f, "classes: {}", self.classes.join(", "))?;
+        if self.dropped {
+            return writeln!(f, "matched the DROP class, the client gets no answer");
+        }
+        match (&self.reserved, &self.range) {
+            (Some(ip), _) => writeln!(f, "reserved: {ip}")?,
+            (None, Some(range)) => writeln!(f, "range: {}-{}", range.start(), range.end())?,
+            (None, None) => writeln!(f, "no range matches, the client gets no address")?,
+        }
+        if let Some(lease) = self.lease_secs {
+            write!(f, "lease: {lease}s")?;
+            if let Some(renew) = self.renew_secs {
+                write!(f, " renew: {renew}s")?;
+            }
+            if let Some(rebind) = self.rebind_secs {
+                write!(f, " rebind: {rebind}s")?;
+            }
+            writeln!(f)?;
+        }
+        if let Some(next_server) = self.boot.next_server {
+            writeln!(f, "next server: {next_server}")?;
+        }
+        if let Some(server_name) = &self.boot.server_name {
+            writeln!(f, "server name: {server_name}")?;
+        }
+        if let Some(file_name) = &self.boot.file_name {
+            writeln!(f, "file name: {file_name}")?;
+        }
+        writeln!(f, "options:")?;
+        for opt in &self.options {
+            writeln!(f, "    {} {}={}", opt.code, opt.name, opt.value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DhcpConfig;
+
+    static CFG: &str = r#"
+client_classes:
+    v4:
+        -
+            name: pxe
+            assert: "option[60].text == 'PXEClient'"
+            options:
+                values:
+                    6:
+                        type: ip
+                        value: [10.0.0.53]
+networks:
+    192.168.0.0/24:
+        ranges:
+            -
+                start: 192.168.0.100
+                end: 192.168.0.199
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        3:
+                            type: ip
+                            value: [192.168.0.1]
+                        6:
+                            type: ip
+                            value: [1.1.1.1]
+        reservations:
+            -
+                ip: 192.168.0.50
+                config:
+                    lease_time:
+                        default: 7200
+                options:
+                    values:
+                        3:
+                            type: ip
+                            value: [192.168.0.1]
+                match:
+                    chaddr: aa:bb:cc:dd:ee:ff
+"#;
+
+    #[test]
+    fn test_parse_opt() {
+        assert_eq!(parse_opt("55=1,3,6").unwrap(), (55, vec![1, 3, 6]));
+        assert_eq!(parse_opt("61=0x01aabb").unwrap(), (61, vec![1, 0xaa, 0xbb]));
+        assert_eq!(
+            parse_opt("50=192.168.0.120").unwrap(),
+            (50, vec![192, 168, 0, 120])
+        );
+        assert_eq!(
+            parse_opt("60=PXEClient").unwrap(),
+            (60, b"PXEClient".to_vec())
+        );
+        assert!(parse_opt("55").is_err());
+        assert!(parse_opt("300=foo").is_err());
+    }
+
+    #[test]
+    fn test_explain() {
+        let cfg = DhcpConfig::parse_str(CFG).unwrap();
+        let subnet = Ipv4Addr::new(192, 168, 0, 1);
+
+        let req = request(&[1, 2, 3, 4, 5, 6], &[(55, vec![1, 3, 6])]).unwrap();
+        let explanation = explain(cfg.v4(), subnet, &req).unwrap();
+        assert_eq!(explanation.network, Some("192.168.0.0/24".parse().unwrap()));
+        assert_eq!(
+            explanation.range,
+            Some([192, 168, 0, 100].into()..=[192, 168, 0, 199].into())
+        );
+        assert_eq!(explanation.lease_secs, Some(3600));
+        // subnet mask isn't configured, only the router & dns server are answered
+        assert_eq!(
+            explanation
+                .options
+                .iter()
+                .map(|opt| (opt.code, opt.value.as_str()))
+                .collect::<Vec<_>>(),
+            [(3, "[192.168.0.1]"), (6, "[1.1.1.1]")]
+        );
+
+        // the class's dns server takes precedence over the range's
+        let req = request(
+            &[1, 2, 3, 4, 5, 6],
+            &[(55, vec![6]), (60, b"PXEClient".to_vec())],
+        )
+        .unwrap();
+        let explanation = explain(cfg.v4(), subnet, &req).unwrap();
+        assert!(explanation.classes.contains(&"pxe".to_owned()));
+        assert_eq!(explanation.options[0].value, "[10.0.0.53]");
+
+        // reservation
+        let req = request(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff], &[(55, vec![3])]).unwrap();
+        let explanation = explain(cfg.v4(), subnet, &req).unwrap();
+        assert_eq!(explanation.reserved, Some([192, 168, 0, 50].into()));
+        assert_eq!(explanation.lease_secs, Some(7200));
+
+        // no network
+        let explanation = explain(cfg.v4(), [10, 0, 0, 1].into(), &req).unwrap();
+        assert_eq!(explanation, Explanation::default());
+    }
+}
@@ -0,0 +1,172 @@
+//! # UEFI HTTP boot
+//!
+//! HTTP boot clients send a vendor class (opt 60) starting with `HTTPClient` & their
+//! architecture in opt 93 (RFC 4578). A network's `http_boot` maps architectures to boot
+//! URLs. A client whose architecture has one is answered with the URL in opt 67, and in
+//! `file` if it fits, and with `HTTPClient` in opt 60, which UEFI firmware requires before
+//! it will boot from the offer (UEFI spec 24.7).
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use dora_core::dhcproto::{
+    v4::{DhcpOption, DhcpOptions, OptionCode, UnknownOption},
+    Encodable,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// vendor class HTTP boot clients start theirs with & the one sent back to them
+pub const HTTP_CLIENT: &[u8] = b"HTTPClient";
+/// client system architecture
+pub const ARCH: u8 = 93;
+/// boot file name
+pub const BOOTFILE: u8 = 67;
+
+/// the HTTP boot architecture types from the IANA processor architecture registry that
+/// can be named in config, others are given by number
+const NAMES: [(&str, u16); 6] = [
+    ("x86", 0x0f),
+    ("x64", 0x10),
+    ("ebc", 0x11),
+    ("arm32", 0x12),
+    ("arm64", 0x13),
+    ("pcbios", 0x14),
+];
+
+/// a client system architecture type, ex. `x64` (16)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Arch(pub u16);
+
+impl FromStr for Arch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((_, code)) = NAMES.iter().find(|(name, _)| *name == s) {
+            return Ok(Arch(*code));
+        }
+        let code = match s.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => s.parse(),
+        };
+        code.map(Arch)
+            .map_err(|_| anyhow!("unknown architecture {s:?}, use a name or a number"))
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match NAMES.iter().find(|(_, code)| *code == self.0) {
+            Some((name, _)) => f.write_str(name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Arch {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        // yaml keys can be names or plain numbers
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Key {
+            Code(u16),
+            Name(String),
+        }
+        match Key::deserialize(de)? {
+            Key::Code(code) => Ok(Arch(code)),
+            Key::Name(name) => name.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+impl Serialize for Arch {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.collect_str(self)
+    }
+}
+
+/// boot URLs must be http(s) & fit in one option
+pub fn validate(urls: &BTreeMap<Arch, String>) -> Result<()> {
+    for (arch, url) in urls {
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            bail!("http_boot url for {arch} must start with http:// or https://");
+        }
+        if url.len() > u8::MAX as usize {
+            bail!("http_boot url for {arch} must be at most 255 bytes");
+        }
+    }
+    Ok(())
+}
+
+/// the architectures in opt 93 if `opts` is from an HTTP boot client, empty otherwise
+pub fn client_arches(opts: &DhcpOptions) -> Vec<Arch> {
+    let http_client = matches!(
+        opts.get(OptionCode::ClassIdentifier),
+        Some(DhcpOption::ClassIdentifier(class)) if class.starts_with(HTTP_CLIENT)
+    );
+    if !http_client {
+        return Vec::new();
+    }
+    let data = match opts.get(OptionCode::from(ARCH)) {
+        None => return Vec::new(),
+        Some(DhcpOption::Unknown(opt)) => opt.data().to_vec(),
+        Some(opt) => match opt.to_vec() {
+            Ok(buf) => buf.get(2..).unwrap_or_default().to_vec(),
+            Err(_) => return Vec::new(),
+        },
+    };
+    data.chunks_exact(2)
+        .map(|code| Arch(u16::from_be_bytes([code[0], code[1]])))
+        .collect()
+}
+
+/// opt 60 & opt 67 for a client booting from `url`
+pub fn opts(url: &str) -> [DhcpOption; 2] {
+    [
+        DhcpOption::ClassIdentifier(HTTP_CLIENT.to_vec()),
+        DhcpOption::Unknown(UnknownOption::new(
+            OptionCode::from(BOOTFILE),
+            url.as_bytes().to_vec(),
+        )),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arch() {
+        assert_eq!("x64".parse::<Arch>().unwrap(), Arch(16));
+        assert_eq!("0x1c".parse::<Arch>().unwrap(), Arch(28));
+        assert_eq!("40".parse::<Arch>().unwrap(), Arch(40));
+        assert!("sparc".parse::<Arch>().is_err());
+        assert_eq!(Arch(19).to_string(), "arm64");
+        assert_eq!(Arch(28).to_string(), "28");
+
+        let urls: BTreeMap<Arch, String> = serde_yaml::from_str(
+            "x64: http://10.0.0.5/x64/shimx64.efi\n28: https://boot.example.com/riscv64.efi\n",
+        )
+        .unwrap();
+        assert_eq!(urls.len(), 2);
+        assert!(validate(&urls).is_ok());
+        let tftp = BTreeMap::from([(Arch(16), "tftp://10.0.0.5/shimx64.efi".to_owned())]);
+        assert!(validate(&tftp).is_err());
+    }
+
+    #[test]
+    fn test_client_arches() {
+        let mut opts = DhcpOptions::new();
+        opts.insert(DhcpOption::Unknown(UnknownOption::new(
+            OptionCode::from(ARCH),
+            vec![0, 0x10, 0, 0x07],
+        )));
+        // PXE clients send opt 93 too
+        opts.insert(DhcpOption::ClassIdentifier(
+            b"PXEClient:Arch:00007:UNDI:003016".to_vec(),
+        ));
+        assert!(client_arches(&opts).is_empty());
+        opts.insert(DhcpOption::ClassIdentifier(
+            b"HTTPClient:Arch:00016:UNDI:003001".to_vec(),
+        ));
+        assert_eq!(client_arches(&opts), vec![Arch(16), Arch(7)]);
+    }
+}
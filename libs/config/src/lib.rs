@@ -1,11 +1,17 @@
 pub mod client_classes;
+pub mod exclusions;
+pub mod explain;
+pub mod http_boot;
+pub mod timezone;
 pub mod v4;
 pub mod v6;
 pub mod wire;
 
-use std::{env, path::Path, time::Duration};
+use std::{env, fmt, path::Path, sync::Arc, time::Duration};
 
 use anyhow::{bail, Context, Result};
+use arc_swap::ArcSwap;
+use dora_core::chrono::{DateTime, Utc};
 use dora_core::dhcproto::v6::duid::Duid;
 use dora_core::pnet::{
     self,
@@ -17,15 +23,43 @@ use serde::{Deserialize, Serialize};
 use tracing::debug;
 use wire::v6::ServerDuidInfo;
 /// server config
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct DhcpConfig {
     v4: v4::Config,
+    /// when a staged config replaces the running one
+    effective_at: Option<DateTime<Utc>>,
+    /// config as parsed, before conversion to the runtime types
+    wire: wire::Config,
+    /// config file contents, may contain inline secrets
+    raw: String,
+}
+
+// `raw` is left out so inline secrets never end up in logs
+impl fmt::Debug for DhcpConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DhcpConfig")
+            .field("v4", &self.v4)
+            .field("effective_at", &self.effective_at)
+            .finish_non_exhaustive()
+    }
 }
 
 impl DhcpConfig {
     pub fn v4(&self) -> &v4::Config {
         &self.v4
     }
+    /// the parsed config, serializing it masks secrets (see [`wire::Secret`])
+    pub fn wire(&self) -> &wire::Config {
+        &self.wire
+    }
+    /// the config file exactly as it was read, including any inline secrets
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+    /// the `effective_at` time, only used when this config is staged with `next_config_path`
+    pub fn effective_at(&self) -> Option<DateTime<Utc>> {
+        self.effective_at
+    }
     pub fn has_v6(&self) -> bool {
         self.v4.v6().is_some()
     }
@@ -34,6 +68,32 @@ impl DhcpConfig {
     }
 }
 
+/// The running config, shared by the plugins & api. A staged config is swapped in
+/// with [`LiveConfig::store`] without restarting the servers, readers [`LiveConfig::load`]
+/// a snapshot & keep it for as long as they need a consistent view
+#[derive(Debug, Clone)]
+pub struct LiveConfig(Arc<ArcSwap<DhcpConfig>>);
+
+impl LiveConfig {
+    pub fn new(cfg: Arc<DhcpConfig>) -> Self {
+        Self(Arc::new(ArcSwap::new(cfg)))
+    }
+    /// the config right now
+    pub fn load(&self) -> Arc<DhcpConfig> {
+        self.0.load_full()
+    }
+    /// replace the config, messages already being handled finish with the old one
+    pub fn store(&self, cfg: Arc<DhcpConfig>) {
+        self.0.store(cfg)
+    }
+}
+
+impl From<Arc<DhcpConfig>> for LiveConfig {
+    fn from(cfg: Arc<DhcpConfig>) -> Self {
+        Self::new(cfg)
+    }
+}
+
 /// server instance config
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EnvConfig {
@@ -58,20 +118,32 @@ impl DhcpConfig {
     /// attempts to decode the config first as JSON, then YAML, finally erroring if neither work
     pub fn parse<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
-        let config = v4::Config::new(
+        Self::parse_str(
             std::fs::read_to_string(path)
                 .with_context(|| format!("failed to find config at {}", &path.display()))?,
-        )?;
-        debug!(?config);
-
-        Ok(Self { v4: config })
+        )
     }
     /// attempts to decode the config first as JSON, then YAML, finally erroring if neither work
     pub fn parse_str<S: AsRef<str>>(s: S) -> Result<Self> {
-        let config = v4::Config::new(s.as_ref())?;
+        let wire = wire::Config::parse(s.as_ref())?;
+        let effective_at = wire
+            .effective_at
+            .as_deref()
+            .map(|at| {
+                DateTime::parse_from_rfc3339(at)
+                    .map(|at| at.with_timezone(&Utc))
+                    .with_context(|| format!("invalid effective_at {at:?}, expected RFC 3339"))
+            })
+            .transpose()?;
+        let config = v4::Config::try_from(wire.clone())?;
         debug!(?config);
 
-        Ok(Self { v4: config })
+        Ok(Self {
+            v4: config,
+            effective_at,
+            wire,
+            raw: s.as_ref().to_owned(),
+        })
     }
 }
 
@@ -103,13 +175,72 @@ pub fn backup_ivp4_interface(interface: Option<&str>) -> Result<Ipv4Network> {
 
 /// Returns:
 /// - interfaces matching the list supplied that are 'up' and have an IPv4
-/// - OR any 'up' interfaces that also have an IPv4
-pub fn v4_find_interfaces(interfaces: Option<Vec<String>>) -> Result<Vec<NetworkInterface>> {
-    let found_interfaces = pnet::datalink::interfaces()
-        .into_iter()
+/// - OR any 'up' interfaces that also have an IPv4 & aren't in `deny`
+pub fn v4_find_interfaces(
+    interfaces: Option<Vec<String>>,
+    deny: &[String],
+) -> Result<Vec<NetworkInterface>> {
+    select_v4_interfaces(pnet::datalink::interfaces(), interfaces, deny)
+}
+
+fn select_v4_interfaces(
+    all: Vec<NetworkInterface>,
+    interfaces: Option<Vec<String>>,
+    deny: &[String],
+) -> Result<Vec<NetworkInterface>> {
+    let deny = deny
+        .iter()
+        .map(|name| interface_name(name))
+        .collect::<Vec<_>>();
+    if let Some(name) = interfaces
+        .iter()
+        .flatten()
+        .find(|name| deny.contains(&interface_name(name)))
+    {
+        bail!("interface {name} is in both `interfaces` and `deny_interfaces`");
+    }
+    let found_interfaces = all
+        .iter()
         .filter(|e| e.is_up() && !e.ips.is_empty() && e.ips.iter().any(|i| i.is_ipv4()))
+        .filter(|e| !deny.contains(&e.name.as_str()))
+        .cloned()
         .collect::<Vec<_>>();
-    found_or_default(found_interfaces, interfaces)
+    Ok(match interfaces {
+        Some(interfaces) => interfaces
+            .iter()
+            .map(|interface| {
+                let name = interface_name(interface);
+                match found_interfaces.iter().find(|i| i.name == name) {
+                    Some(i) => Ok(i.clone()),
+                    None => Err(missing_interface(&all, name)),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        None => found_interfaces,
+    })
+}
+
+/// `ip link` lists VLAN sub-interfaces as `eth0.100@eth0`, the interface name is the
+/// part before `@`
+pub(crate) fn interface_name(name: &str) -> &str {
+    name.split_once('@').map_or(name, |(name, _)| name)
+}
+
+/// why `name` couldn't be used
+fn missing_interface(all: &[NetworkInterface], name: &str) -> anyhow::Error {
+    match all.iter().find(|i| i.name == name) {
+        Some(_) => anyhow::anyhow!("interface {name} is down or has no IPv4 address"),
+        None if name.contains('.') => anyhow::anyhow!(
+            "unable to find interface {name}, VLAN sub-interfaces must exist before dora starts"
+        ),
+        None => {
+            let names = all.iter().map(|i| i.name.as_str()).collect::<Vec<_>>();
+            anyhow::anyhow!(
+                "unable to find interface {name}, available interfaces: {}",
+                names.join(", ")
+            )
+        }
+    }
 }
 
 /// Returns:
@@ -141,16 +272,44 @@ fn found_or_default(
     })
 }
 
+/// lease time of 0xffffffff means the lease never expires (RFC 2131)
+pub const INFINITE_LEASE: Duration = Duration::from_secs(u32::MAX as u64);
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct LeaseTime {
     default: Duration,
     min: Duration,
     max: Duration,
+    /// T1 (opt 58) as a percentage of the lease time
+    renew_percent: Option<u8>,
+    /// T2 (opt 59) as a percentage of the lease time
+    rebind_percent: Option<u8>,
 }
 
 impl LeaseTime {
     pub fn new(default: Duration, min: Duration, max: Duration) -> Self {
-        Self { default, min, max }
+        Self {
+            default,
+            min,
+            max,
+            renew_percent: None,
+            rebind_percent: None,
+        }
+    }
+    /// send T1 & T2 as percentages of the lease time. Unset ones aren't sent, the client
+    /// then uses 50% & 87.5% (RFC 2131 4.4.5)
+    pub fn with_renewal(mut self, renew_percent: Option<u8>, rebind_percent: Option<u8>) -> Self {
+        self.renew_percent = renew_percent;
+        self.rebind_percent = rebind_percent;
+        self
+    }
+    /// a lease time that never expires, encoded as 0xffffffff in opt 51
+    pub fn infinite() -> Self {
+        Self::new(INFINITE_LEASE, INFINITE_LEASE, INFINITE_LEASE)
+    }
+    /// true if this lease never expires
+    pub fn is_infinite(&self) -> bool {
+        self.default == INFINITE_LEASE
     }
     pub fn get_default(&self) -> Duration {
         self.default
@@ -161,35 +320,42 @@ impl LeaseTime {
     pub fn get_max(&self) -> Duration {
         self.max
     }
-    /// calculate the lease time based on a possible requested time
-    pub fn determine_lease(&self, requested: Option<Duration>) -> (Duration, Duration, Duration) {
-        let LeaseTime { default, min, max } = *self;
-        match requested {
-            // time must be larger than `min` and smaller than `max`
-            Some(req) => {
-                let t = req.clamp(min, max);
-                (t, renew(t), rebind(t))
-            }
-            None => (default, renew(default), rebind(default)),
+    /// calculate the lease time based on a possible requested time, with T1 & T2 if they
+    /// are configured
+    pub fn determine_lease(
+        &self,
+        requested: Option<Duration>,
+    ) -> (Duration, Option<Duration>, Option<Duration>) {
+        let LeaseTime {
+            default, min, max, ..
+        } = *self;
+        // infinite leases have infinite T1 & T2 also, the client never renews
+        if self.is_infinite() {
+            return (INFINITE_LEASE, Some(INFINITE_LEASE), Some(INFINITE_LEASE));
         }
+        // time must be larger than `min` and smaller than `max`
+        let t = requested.map_or(default, |req| req.clamp(min, max));
+        let (renew, rebind) = self.renewal(t);
+        (t, renew, rebind)
+    }
+    /// T1 & T2 for a lease of `t`, `None` for those that aren't configured
+    pub fn renewal(&self, t: Duration) -> (Option<Duration>, Option<Duration>) {
+        let percent = |p: u8| t * p as u32 / 100;
+        (
+            self.renew_percent.map(percent),
+            self.rebind_percent.map(percent),
+        )
     }
-}
-
-pub fn renew(t: Duration) -> Duration {
-    t / 2
-}
-
-pub fn rebind(t: Duration) -> Duration {
-    t * 7 / 8
 }
 
 pub fn generate_random_bytes(len: usize) -> Vec<u8> {
-    let mut ident = Vec::with_capacity(len);
+    let mut ident = vec![0; len];
     rand::thread_rng().fill_bytes(&mut ident);
     ident
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct PersistIdentifier {
     pub identifier: String,
     pub duid_config: ServerDuidInfo,
@@ -213,3 +379,78 @@ impl PersistIdentifier {
         Ok(Duid::from(duid_bytes))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effective_at() {
+        let cfg =
+            DhcpConfig::parse_str(r#"{"effective_at": "2026-10-17T02:00:00-04:00"}"#).unwrap();
+        assert_eq!(
+            cfg.effective_at(),
+            Some("2026-10-17T06:00:00Z".parse::<DateTime<Utc>>().unwrap())
+        );
+        assert_eq!(DhcpConfig::parse_str("{}").unwrap().effective_at(), None);
+        assert!(DhcpConfig::parse_str(r#"{"effective_at": "tonight"}"#).is_err());
+    }
+
+    #[test]
+    fn test_live_config() {
+        let running = Arc::new(DhcpConfig::parse_str("{}").unwrap());
+        let live = LiveConfig::from(Arc::clone(&running));
+        let reader = live.clone();
+        let held = reader.load();
+
+        let next = Arc::new(
+            DhcpConfig::parse_str(r#"{"effective_at": "2026-10-17T02:00:00-04:00"}"#).unwrap(),
+        );
+        live.store(Arc::clone(&next));
+        // clones see the swap, a loaded snapshot is unchanged
+        assert_eq!(reader.load(), next);
+        assert_eq!(held, running);
+    }
+
+    /// an interface that's up with addresses `ips`
+    pub(crate) fn iface(name: &str, index: u32, ips: &[&str]) -> NetworkInterface {
+        NetworkInterface {
+            name: name.to_owned(),
+            description: String::new(),
+            index,
+            mac: None,
+            ips: ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+            // IFF_UP
+            flags: 1,
+        }
+    }
+
+    #[test]
+    fn test_select_v4_interfaces() {
+        let all = vec![
+            iface("lo", 1, &["127.0.0.1/8"]),
+            iface("eth0", 2, &["192.168.0.1/24"]),
+            iface("eth0.100", 3, &["10.0.100.1/24"]),
+            iface("eth1", 4, &["fe80::1/64"]),
+        ];
+        let names =
+            |found: Vec<NetworkInterface>| found.into_iter().map(|i| i.name).collect::<Vec<_>>();
+        let list = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            names(select_v4_interfaces(all.clone(), None, &list(&["lo"])).unwrap()),
+            ["eth0", "eth0.100"]
+        );
+        // `ip link` style names are accepted
+        assert_eq!(
+            names(select_v4_interfaces(all.clone(), Some(list(&["eth0.100@eth0"])), &[]).unwrap()),
+            ["eth0.100"]
+        );
+        // no IPv4
+        assert!(select_v4_interfaces(all.clone(), Some(list(&["eth1"])), &[]).is_err());
+        // sub-interface that doesn't exist
+        assert!(select_v4_interfaces(all.clone(), Some(list(&["eth0.200"])), &[]).is_err());
+        // both allowed & denied
+        assert!(select_v4_interfaces(all, Some(list(&["eth0"])), &list(&["eth0"])).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! # Timezone options
+//!
+//! A timezone is configured as a tz database name in TCode (opt 101, RFC 4833). From it
+//! dora derives PCode (opt 100), the POSIX TZ string for clients that can't look up tz
+//! names, when the config is loaded, and the legacy time offset (opt 2) when a response
+//! is built, so it follows daylight saving time. Either is left alone if it's configured.
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use dora_core::{
+    chrono::{DateTime, Datelike, Duration, NaiveDateTime, Offset, TimeZone, Timelike, Utc},
+    chrono_tz::{OffsetComponents, OffsetName, Tz},
+    dhcproto::{
+        v4::{DhcpOption, DhcpOptions, OptionCode},
+        Encodable,
+    },
+};
+
+/// POSIX TZ string
+pub const PCODE: u8 = 100;
+/// tz database name
+pub const TCODE: u8 = 101;
+
+/// the tz database zone named `name`
+pub fn parse(name: &str) -> Result<Tz> {
+    Tz::from_str(name).map_err(|err| anyhow!("unknown timezone {name:?}: {err}"))
+}
+
+/// the POSIX TZ string for `tz` with the rules in effect during `year`, ex.
+/// `EST5EDT,M3.2.0,M11.1.0` for `America/New_York`
+pub fn posix(tz: Tz, year: i32) -> String {
+    let start = Utc.with_ymd_and_hms(year, 1, 1, 0, 0, 0).unwrap();
+    let is_dst = |at: DateTime<Utc>| {
+        tz.offset_from_utc_datetime(&at.naive_utc()).dst_offset() != Duration::zero()
+    };
+    // the first instant of each change in or out of DST during the year
+    let mut changes = Vec::new();
+    for day in 0..366 {
+        let from = start + Duration::days(day);
+        let to = from + Duration::days(1);
+        if from.year() != year || is_dst(from) == is_dst(to) {
+            continue;
+        }
+        let (mut lo, mut hi) = (from, to);
+        while hi - lo > Duration::seconds(1) {
+            let mid = lo + (hi - lo) / 2;
+            if is_dst(mid) == is_dst(from) {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        changes.push(hi);
+    }
+    let std_at = changes
+        .iter()
+        .copied()
+        .find(|at| !is_dst(*at))
+        .unwrap_or(start);
+    let std = tz.offset_from_utc_datetime(&std_at.naive_utc());
+    let std_secs = std.fix().local_minus_utc();
+    let mut out = format!("{}{}", abbreviation(std.abbreviation()), offset(std_secs));
+    let (Some(dst_at), Some(end_at)) = (
+        changes.iter().copied().find(|at| is_dst(*at)),
+        changes.iter().copied().find(|at| !is_dst(*at)),
+    ) else {
+        return out;
+    };
+    let dst = tz.offset_from_utc_datetime(&dst_at.naive_utc());
+    let dst_secs = dst.fix().local_minus_utc();
+    out.push_str(&abbreviation(dst.abbreviation()));
+    if dst_secs != std_secs + 3600 {
+        out.push_str(&offset(dst_secs));
+    }
+    // rule times are local time from before the change
+    out.push_str(&rule(
+        dst_at.naive_utc() + Duration::seconds(std_secs as i64),
+    ));
+    out.push_str(&rule(
+        end_at.naive_utc() + Duration::seconds(dst_secs as i64),
+    ));
+    out
+}
+
+/// set the time offset (opt 2) for `now` if `opts` has a TCode naming a timezone & no
+/// time offset of its own
+pub fn set_time_offset(opts: &mut DhcpOptions, now: DateTime<Utc>) {
+    if opts.get(OptionCode::TimeOffset).is_some() {
+        return;
+    }
+    let Some(tz) = tcode(opts) else {
+        return;
+    };
+    let offset = tz.offset_from_utc_datetime(&now.naive_utc()).fix();
+    opts.insert(DhcpOption::TimeOffset(offset.local_minus_utc()));
+}
+
+/// the timezone named in TCode, `None` if there is none or it's not a tz database name
+fn tcode(opts: &DhcpOptions) -> Option<Tz> {
+    let data = match opts.get(OptionCode::from(TCODE))? {
+        DhcpOption::Unknown(opt) => opt.data().to_vec(),
+        opt => {
+            let buf = opt.to_vec().ok()?;
+            buf.get(2..)?.to_vec()
+        }
+    };
+    std::str::from_utf8(&data)
+        .ok()
+        .and_then(|name| name.parse().ok())
+}
+
+/// abbreviations that aren't all letters, like `+03`, are quoted
+fn abbreviation(abbr: &str) -> String {
+    if abbr.len() >= 3 && abbr.chars().all(|c| c.is_ascii_alphabetic()) {
+        abbr.to_owned()
+    } else {
+        format!("<{abbr}>")
+    }
+}
+
+/// POSIX offsets are the time added to local time to get UTC, the opposite sign of
+/// `local_minus_utc`
+fn offset(local_minus_utc: i32) -> String {
+    let secs = -local_minus_utc;
+    let sign = if secs < 0 { "-" } else { "" };
+    format!("{sign}{}", hms(secs.unsigned_abs()))
+}
+
+/// `h`, `h:mm` or `h:mm:ss`, whichever is shortest
+fn hms(secs: u32) -> String {
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match (m, s) {
+        (0, 0) => h.to_string(),
+        (m, 0) => format!("{h}:{m:02}"),
+        (m, s) => format!("{h}:{m:02}:{s:02}"),
+    }
+}
+
+/// `,Mm.w.d[/time]`, week 5 is the last of the month. The time is left out if it's
+/// the default 02:00
+fn rule(local: NaiveDateTime) -> String {
+    let date = local.date();
+    let last_week = (date + Duration::days(7)).month() != date.month();
+    let week = if last_week {
+        5
+    } else {
+        (date.day() - 1) / 7 + 1
+    };
+    let mut rule = format!(
+        ",M{}.{}.{}",
+        date.month(),
+        week,
+        date.weekday().num_days_from_sunday()
+    );
+    let secs = local.num_seconds_from_midnight();
+    if secs != 2 * 3600 {
+        rule.push('/');
+        rule.push_str(&hms(secs));
+    }
+    rule
+}
+
+#[cfg(test)]
+mod tests {
+    use dora_core::dhcproto::v4::UnknownOption;
+
+    use super::*;
+
+    #[test]
+    fn test_posix() {
+        for (name, expected) in [
+            ("America/New_York", "EST5EDT,M3.2.0,M11.1.0"),
+            ("Europe/London", "GMT0BST,M3.5.0/1,M10.5.0"),
+            ("Europe/Berlin", "CET-1CEST,M3.5.0,M10.5.0/3"),
+            ("Australia/Sydney", "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+            ("Asia/Tokyo", "JST-9"),
+            ("Asia/Kolkata", "IST-5:30"),
+            ("Asia/Riyadh", "<+03>-3"),
+            ("UTC", "UTC0"),
+        ] {
+            assert_eq!(posix(parse(name).unwrap(), 2024), expected, "{name}");
+        }
+        assert!(parse("Mars/Olympus_Mons").is_err());
+    }
+
+    #[test]
+    fn test_time_offset() {
+        let mut opts = DhcpOptions::new();
+        opts.insert(DhcpOption::Unknown(UnknownOption::new(
+            OptionCode::from(TCODE),
+            b"America/New_York".to_vec(),
+        )));
+        let winter = Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap();
+        let summer = Utc.with_ymd_and_hms(2024, 7, 15, 12, 0, 0).unwrap();
+
+        let mut resp = opts.clone();
+        set_time_offset(&mut resp, winter);
+        assert_eq!(
+            resp.get(OptionCode::TimeOffset),
+            Some(&DhcpOption::TimeOffset(-5 * 3600))
+        );
+        let mut resp = opts.clone();
+        set_time_offset(&mut resp, summer);
+        assert_eq!(
+            resp.get(OptionCode::TimeOffset),
+            Some(&DhcpOption::TimeOffset(-4 * 3600))
+        );
+        // a configured offset is kept
+        opts.insert(DhcpOption::TimeOffset(0));
+        set_time_offset(&mut opts, summer);
+        assert_eq!(
+            opts.get(OptionCode::TimeOffset),
+            Some(&DhcpOption::TimeOffset(0))
+        );
+        // no timezone
+        let mut opts = DhcpOptions::new();
+        set_time_offset(&mut opts, summer);
+        assert!(opts.get(OptionCode::TimeOffset).is_none());
+    }
+}
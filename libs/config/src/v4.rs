@@ -1,32 +1,41 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap},
     net::Ipv4Addr,
     ops::RangeInclusive,
     time::Duration,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use client_classification::PacketDetails;
 use dora_core::{
+    chrono::Utc,
     dhcproto::{
         self,
-        v4::{DhcpOption, DhcpOptions, Message, OptionCode},
+        v4::{relay, DhcpOption, DhcpOptions, Message, OptionCode},
     },
     pnet::{
         datalink::NetworkInterface,
         ipnetwork::{IpNetwork, Ipv4Network},
         util::MacAddr,
     },
+    server::context::{OptionOrder, ReplyTo, RespTransforms},
 };
-use ipnet::{Ipv4AddrRange, Ipv4Net};
-use tracing::debug;
+use ipnet::Ipv4Net;
+use tracing::{debug, warn};
 
-use crate::{client_classes::ClientClasses, wire, LeaseTime};
+use crate::{
+    client_classes::{ClientClasses, ClientClassesV6},
+    http_boot, timezone, wire, LeaseTime,
+};
 
 // re-export wire Ddns since it doesn't need to be modified (yet)
 pub use wire::v4::ddns::Ddns;
+pub use wire::v4::{Boot, Fill, MultiNetworkPolicy, Oui};
+pub use wire::{FloodKey, KnownPolicy, SanityPolicy};
 
 pub const DEFAULT_LEASE_TIME: Duration = Duration::from_secs(86_400);
+/// RFC 8925 minimum value for V6ONLY_WAIT
+pub const MIN_V6_ONLY_WAIT: Duration = Duration::from_secs(300);
 
 /// server config for dhcpv4
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -34,26 +43,98 @@ pub struct Config {
     /// interfaces that are either explicitly bound by the config or
     /// are up & ipv4
     interfaces: Vec<NetworkInterface>,
+    /// address of each interface bound to a network with `interfaces`, by index
+    bound: HashMap<u32, Ipv4Network>,
     chaddr_only: bool,
     bootp_enable: bool,
     rapid_commit: bool,
-    flood_threshold: Option<FloodThreshold>,
+    option_order: Option<OptionOrder>,
+    known_clients: KnownPolicy,
+    store_fingerprints: bool,
+    flood_thresholds: Vec<FloodThreshold>,
+    decline_threshold: Option<FloodThreshold>,
+    load_balance: Option<LoadBalance>,
+    sanity_checks: Option<SanityChecks>,
+    loop_protection: Option<LoopProtection>,
+    load_shedding: Option<LoadShedding>,
     cache_threshold: Option<u32>,
     /// used to make a selection on which network or subnet to use
     networks: HashMap<Ipv4Net, Network>,
     v6: Option<crate::v6::Config>,
     client_classes: Option<ClientClasses>,
     ddns: Option<Ddns>,
+    identity: Option<wire::identity::Identity>,
+    host_export: Option<wire::host_export::HostExport>,
 }
 
 impl TryFrom<wire::Config> for Config {
     type Error = anyhow::Error;
-    fn try_from(cfg: wire::Config) -> Result<Self> {
-        let interfaces = crate::v4_find_interfaces(cfg.interfaces.clone())?;
+    fn try_from(mut cfg: wire::Config) -> Result<Self> {
+        let interfaces = crate::v4_find_interfaces(cfg.interfaces.clone(), &cfg.deny_interfaces)?;
 
         debug!(?interfaces, "using v4 interfaces");
+        let bound = bind_networks(
+            &interfaces,
+            cfg.networks
+                .iter()
+                .map(|(subnet, net)| (subnet, net.interfaces.as_deref())),
+        )?;
+        for (subnet, net) in &cfg.networks {
+            net.boot
+                .validate()
+                .and_then(|_| net.ranges.iter().try_for_each(|r| r.boot.validate()))
+                .and_then(|_| net.reservations.iter().try_for_each(|r| r.boot.validate()))
+                .with_context(|| format!("invalid boot fields in network {subnet}"))?;
+            wire::v4::validate_mtu(net.mtu, None)
+                .and_then(|_| {
+                    net.ranges
+                        .iter()
+                        .try_for_each(|r| wire::v4::validate_mtu(r.mtu, Some(&r.options)))
+                })
+                .and_then(|_| {
+                    net.reservations
+                        .iter()
+                        .try_for_each(|r| wire::v4::validate_mtu(None, Some(&r.options)))
+                })
+                .with_context(|| format!("invalid mtu in network {subnet}"))?;
+            wire::v4::validate_oui_limits(&net.oui_limits)
+                .with_context(|| format!("invalid oui_limits in network {subnet}"))?;
+            http_boot::validate(&net.http_boot)
+                .with_context(|| format!("invalid http_boot in network {subnet}"))?;
+            net.ranges
+                .iter()
+                .map(|r| &r.config)
+                .chain(net.reservations.iter().map(|r| &r.config))
+                .try_for_each(wire::v4::NetworkConfig::validate)
+                .with_context(|| format!("invalid renew/rebind times in network {subnet}"))?;
+        }
+        if let Some(identity) = &cfg.identity {
+            identity.validate().context("invalid identity config")?;
+        }
+        if let Some(export) = &cfg.host_export {
+            export.validate().context("invalid host_export config")?;
+        }
+        if let Some(ddns) = &cfg.ddns {
+            ddns.validate().context("invalid ddns config")?;
+        }
+        // v6 classes are kept with the v6 config
+        let v6_classes = cfg
+            .client_classes
+            .as_mut()
+            .map(|classes| std::mem::take(&mut classes.v6))
+            .filter(|classes| !classes.is_empty())
+            .map(ClientClassesV6::try_from)
+            .transpose()
+            .context("unable to parse v6 client_classes config")?;
+        // classes are needed first, their allow/deny lists are resolved per range
+        let client_classes = cfg
+            .client_classes
+            .map(ClientClasses::try_from)
+            .transpose()
+            .context("unable to parse client_classes config")?;
+        let default_authoritative = cfg.authoritative;
         // transform wire::Config into a more optimized format
-        let networks = cfg
+        let networks: HashMap<Ipv4Net, Network> = cfg
             .networks
             .into_iter()
             .map(|(subnet, net)| {
@@ -62,18 +143,45 @@ impl TryFrom<wire::Config> for Config {
                     reservations,
                     ping_check,
                     probation_period,
+                    max_probations,
+                    probation_decay,
+                    lease_grace,
                     authoritative,
                     server_id,
                     ping_timeout_ms,
-                    server_name,
-                    file_name,
+                    boot,
+                    http_boot,
+                    ipv6_only_preferred,
+                    v6_only_wait,
+                    reply_to,
+                    mtu,
+                    relays,
+                    oui_limits,
+                    multi_network_leases,
+                    fill,
+                    interfaces: _,
                 } = net;
 
-                let ranges = ranges.into_iter().map(|range| range.into()).collect();
+                let ranges = ranges
+                    .into_iter()
+                    .map(|range| {
+                        let mut range = NetRange::from(range);
+                        if let Some(classes) = &client_classes {
+                            range.restricted = classes.restricted(&range.addrs);
+                        }
+                        inherit_mtu(&mut range.opts, mtu);
+                        range
+                    })
+                    .collect();
+                let reserved = |res: &wire::v4::ReservedIp| {
+                    let mut reserved = Reserved::from(res);
+                    inherit_mtu(&mut reserved.opts, mtu);
+                    reserved
+                };
                 let reserved_macs = reservations
                     .iter()
                     .filter_map(|res| match &res.condition {
-                        wire::v4::Condition::Mac(mac) => Some((*mac, res.into())),
+                        wire::v4::Condition::Mac(mac) => Some((*mac, reserved(res))),
                         _ => None,
                     })
                     .collect();
@@ -85,7 +193,9 @@ impl TryFrom<wire::Config> for Config {
                                 // TODO: we only support matching on a single option currently.
                                 // A reservation can match on chaddr OR a single option value.
                                 match match_opts.values.0.iter().next() {
-                                    Some((code, opt)) => Some((*code, (opt.clone(), res.into()))),
+                                    Some((code, opt)) => {
+                                        Some((*code, (opt.clone(), reserved(res))))
+                                    }
                                     _ => None,
                                 }
                             }
@@ -93,18 +203,71 @@ impl TryFrom<wire::Config> for Config {
                         }
                     })
                     .collect();
+                let reserved_hostnames = reservations
+                    .iter()
+                    .filter_map(|res| match &res.condition {
+                        wire::v4::Condition::Hostname(name) => {
+                            Some((name.to_ascii_lowercase(), reserved(res)))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let reserved_user_classes = reservations
+                    .iter()
+                    .filter_map(|res| match &res.condition {
+                        wire::v4::Condition::UserClass(class) => {
+                            Some((class.clone(), reserved(res)))
+                        }
+                        _ => None,
+                    })
+                    .collect();
                 let network = Network {
                     server_id,
                     subnet,
                     ping_check,
                     probation_period: Duration::from_secs(probation_period),
+                    max_probations,
+                    probation_decay: probation_decay.map(Duration::from_secs),
+                    lease_grace: lease_grace.map(Duration::from_secs),
                     ranges,
                     reserved_macs,
                     reserved_opts,
-                    authoritative,
+                    reserved_hostnames,
+                    reserved_user_classes,
+                    authoritative: authoritative.or(default_authoritative).unwrap_or_else(|| {
+                        warn!(
+                            ?subnet,
+                            "authoritative is not set for the network or globally, defaulting to true"
+                        );
+                        wire::default_authoritative()
+                    }),
                     ping_timeout_ms: Duration::from_millis(ping_timeout_ms),
-                    server_name,
-                    file_name,
+                    boot,
+                    http_boot,
+                    v6_only_wait: ipv6_only_preferred.then(|| {
+                        let wait = Duration::from_secs(v6_only_wait as u64);
+                        if wait < MIN_V6_ONLY_WAIT {
+                            warn!(
+                                ?subnet,
+                                ?wait,
+                                "v6_only_wait is below MIN_V6ONLY_WAIT, using minimum"
+                            );
+                        }
+                        wait.max(MIN_V6_ONLY_WAIT)
+                    }),
+                    mtu,
+                    relays: relays.map(RelayAcl::from),
+                    oui_limits: oui_limits
+                        .into_iter()
+                        .map(|limit| (limit.oui, limit.max_leases))
+                        .collect(),
+                    multi_network_leases,
+                    fill,
+                    reply_to: reply_to.map(|reply_to| match reply_to {
+                        wire::v4::ReplyTo::Ciaddr => ReplyTo::Ciaddr,
+                        wire::v4::ReplyTo::Broadcast => ReplyTo::Broadcast,
+                        wire::v4::ReplyTo::Helper(ip) => ReplyTo::Helper(ip),
+                    }),
                 };
                 // set total addr space for metrics
                 dora_core::metrics::TOTAL_AVAILABLE_ADDRS.set(network.total_addrs() as i64);
@@ -112,16 +275,92 @@ impl TryFrom<wire::Config> for Config {
             })
             .collect();
 
+        // a range or reservation can be limited to `KNOWN`/`UNKNOWN` or `RANDOM_MAC`
+        // clients without any configured class referring to them, those built-ins still
+        // need evaluating
+        let refs_builtin = |builtins: &[&str]| {
+            networks.values().any(|net| {
+                net.ranges
+                    .iter()
+                    .filter_map(|range| range.class())
+                    .chain(net.reservations().filter_map(|res| res.class()))
+                    .any(|class| builtins.contains(&class))
+            })
+        };
+        let refs_known = refs_builtin(&[
+            client_classification::KNOWN_CLASS,
+            client_classification::UNKNOWN_CLASS,
+        ]);
+        let refs_random = refs_builtin(&[client_classification::RANDOM_MAC_CLASS]);
+        let client_classes = match client_classes {
+            Some(mut classes) => {
+                classes.uses_known |= refs_known;
+                Some(classes)
+            }
+            // class tags from the identity backend need evaluating as well
+            None if refs_known || refs_random || cfg.identity.is_some() => Some(ClientClasses {
+                uses_known: refs_known,
+                ..ClientClasses::default()
+            }),
+            None => None,
+        };
+
+        let loop_protection = cfg.loop_protection.map(|lp| {
+            let addrs = interfaces
+                .iter()
+                .flat_map(|int| int.ips.iter())
+                .filter_map(|ip| match ip {
+                    IpNetwork::V4(net) => Some(net.ip()),
+                    IpNetwork::V6(_) => None,
+                })
+                .chain(networks.values().filter_map(|net| net.server_id))
+                .chain(
+                    client_classes
+                        .iter()
+                        .flat_map(|classes| classes.server_ids().map(|(_, id)| id)),
+                );
+            LoopProtection::new(
+                interfaces
+                    .iter()
+                    .filter_map(|int| int.mac)
+                    .map(|mac| mac.octets()),
+                addrs,
+                Duration::from_secs(lp.log_secs),
+            )
+        });
+
         Ok(Self {
             interfaces,
+            bound,
             networks,
             chaddr_only: cfg.chaddr_only,
             bootp_enable: cfg.bootp_enable,
             rapid_commit: cfg.rapid_commit,
-            flood_threshold: cfg.flood_protection_threshold.map(|f| FloodThreshold {
-                packets: f.packets.get(),
-                period: Duration::from_secs(f.secs.get() as u64),
-            }),
+            known_clients: cfg.known_clients,
+            store_fingerprints: cfg.store_fingerprints,
+            option_order: cfg
+                .option_order
+                .map(|codes| OptionOrder::new(codes.into_iter().map(OptionCode::from))),
+            flood_thresholds: cfg
+                .flood_protection_threshold
+                .map(wire::MaybeList::into_vec)
+                .map(flood_thresholds)
+                .transpose()
+                .context("invalid flood_protection_threshold")?
+                .unwrap_or_default(),
+            decline_threshold: cfg
+                .decline_threshold
+                .map(|f| match (&f.pressure, &f.key) {
+                    (Some(_), _) => Err(anyhow::anyhow!(
+                        "pressure is only supported for flood_protection_threshold"
+                    )),
+                    (_, Some(_)) => Err(anyhow::anyhow!(
+                        "key is only supported for flood_protection_threshold"
+                    )),
+                    (None, None) => FloodThreshold::try_from(f),
+                })
+                .transpose()
+                .context("invalid decline_threshold")?,
             // error if threshold exists and > 100
             cache_threshold: {
                 let threshold = cfg.cache_threshold;
@@ -138,16 +377,29 @@ impl TryFrom<wire::Config> for Config {
             .transpose()?,
             v6: cfg
                 .v6
-                .map(crate::v6::Config::try_from)
+                .map(|v6| {
+                    crate::v6::Config::try_from(v6).map(|v6| v6.with_client_classes(v6_classes))
+                })
                 .transpose()
                 .context("unable to parse v6 config")?,
-            client_classes: cfg
-                .client_classes
-                .map(ClientClasses::try_from)
-                .transpose()
-                .context("unable to parse client_classes config")?,
+            client_classes,
             ddns: cfg.ddns,
-        })
+            identity: cfg.identity,
+            host_export: cfg.host_export,
+            load_balance: cfg
+                .load_balance
+                .map(LoadBalance::try_from)
+                .transpose()
+                .context("unable to parse load_balance config")?,
+            sanity_checks: cfg.sanity_checks.map(SanityChecks::from),
+            loop_protection,
+            load_shedding: cfg
+                .load_shedding
+                .map(LoadShedding::try_from)
+                .transpose()
+                .context("unable to parse load_shedding config")?,
+        }
+        .check_server_ids())
     }
 }
 
@@ -155,12 +407,41 @@ impl Config {
     pub fn ddns(&self) -> Option<&Ddns> {
         self.ddns.as_ref()
     }
+    /// the identity backend config, if there is one
+    pub fn identity(&self) -> Option<&wire::identity::Identity> {
+        self.identity.as_ref()
+    }
+    /// where & how active lease hostnames are exported, if they are
+    pub fn host_export(&self) -> Option<&wire::host_export::HostExport> {
+        self.host_export.as_ref()
+    }
     pub fn v6(&self) -> Option<&crate::v6::Config> {
         self.v6.as_ref()
     }
-    /// return the flood threshold config
-    pub fn flood_threshold(&self) -> Option<FloodThreshold> {
-        self.flood_threshold.clone()
+    /// return the flood limiters, each with its own key & quota. Empty if flood
+    /// protection is off
+    pub fn flood_thresholds(&self) -> &[FloodThreshold] {
+        &self.flood_thresholds
+    }
+    /// return the per-client DECLINE rate limit
+    pub fn decline_threshold(&self) -> Option<FloodThreshold> {
+        self.decline_threshold.clone()
+    }
+    /// return the RFC 3074 load balancing config
+    pub fn load_balance(&self) -> Option<LoadBalance> {
+        self.load_balance
+    }
+    /// return the bogus packet checks config
+    pub fn sanity_checks(&self) -> Option<SanityChecks> {
+        self.sanity_checks
+    }
+    /// return the loop protection config, with our MACs & addresses
+    pub fn loop_protection(&self) -> Option<&LoopProtection> {
+        self.loop_protection.as_ref()
+    }
+    /// return the load shedding config
+    pub fn load_shedding(&self) -> Option<LoadShedding> {
+        self.load_shedding
     }
     /// return the renew threshold config
     pub fn cache_threshold(&self) -> Option<u32> {
@@ -171,32 +452,84 @@ impl Config {
         &self,
         req: &dhcproto::v4::Message,
         pkt: PacketDetails,
+        known: Option<bool>,
     ) -> Option<Result<Vec<String>>> {
         self.client_classes
             .as_ref()
-            .map(|classes| classes.eval(req, pkt, self.bootp_enabled()))
+            .map(|classes| classes.eval(req, pkt, self.bootp_enabled(), known))
     }
     pub fn classes(&self) -> Option<&ClientClasses> {
         self.client_classes.as_ref()
     }
     /// Returns:
-    ///     - `server_id` of `Network` belonging to `ip`
+    ///     - `server_id` of the first matched client class that has one
+    ///     - OR `server_id` of `Network` belonging to `ip`
     ///     - OR interface at index `iface`
-    pub fn server_id(&self, iface: u32, ip: Ipv4Addr) -> Option<Ipv4Addr> {
-        self.network(ip)
-            .and_then(|net| net.server_id)
+    pub fn server_id(
+        &self,
+        iface: u32,
+        ip: Ipv4Addr,
+        classes: Option<&[String]>,
+    ) -> Option<Ipv4Addr> {
+        self.client_classes
+            .as_ref()
+            .and_then(|c| c.server_id(classes))
+            .or_else(|| self.network(ip).and_then(|net| net.server_id))
             .or_else(|| self.find_network(iface).map(|i| i.ip()))
     }
 
+    /// response transforms from the matched client classes
+    pub fn transforms(&self, classes: Option<&[String]>) -> Option<RespTransforms> {
+        self.client_classes.as_ref()?.transforms(classes)
+    }
+
+    /// server id overrides are often virtual IPs that move between servers, so they are not
+    /// required to be assigned locally, but they should be on a subnet of a bound interface
+    fn check_server_ids(self) -> Self {
+        for (owner, id) in self.unreachable_server_ids() {
+            warn!(
+                %owner,
+                server_id = ?id,
+                "server_id is not on any bound interface subnet"
+            );
+        }
+        self
+    }
+
+    /// `server_id`s of networks & client classes that aren't on the subnet of any bound
+    /// interface, with what they're configured on, ex. `network 192.168.0.0/24` or
+    /// `class voip`
+    pub fn unreachable_server_ids(&self) -> Vec<(String, Ipv4Addr)> {
+        let reachable = |id: Ipv4Addr| {
+            self.interfaces
+                .iter()
+                .flat_map(|int| int.ips.iter())
+                .any(|ip| matches!(ip, IpNetwork::V4(net) if net.contains(id)))
+        };
+        let networks = self.networks.iter().filter_map(|(subnet, net)| {
+            let id = net.server_id.filter(|id| !reachable(*id))?;
+            Some((format!("network {subnet}"), id))
+        });
+        let classes = self
+            .client_classes
+            .iter()
+            .flat_map(|classes| classes.server_ids())
+            .filter(|(_, id)| !reachable(*id))
+            .map(|(class, id)| (format!("class {class}"), id));
+        networks.chain(classes).collect()
+    }
+
     /// return the optional explicitly bound interfaces if there are any
     pub fn interfaces(&self) -> &[NetworkInterface] {
         self.interfaces.as_slice()
     }
     /// Returns:
-    ///     - if the config has an interface, return that
-    ///     - OR find iface_index and return that
-    ///     - OR use default interface
+    ///     - if the interface is bound to a network, its address in that network
+    ///     - OR the first IPv4 of the interface at iface_index
     pub fn find_network(&self, iface_index: u32) -> Option<Ipv4Network> {
+        if let Some(addr) = self.bound.get(&iface_index) {
+            return Some(*addr);
+        }
         self.find_interface(iface_index).and_then(|int| {
             int.ips.iter().find_map(|ip| match ip {
                 IpNetwork::V4(ip) => Some(*ip),
@@ -225,6 +558,21 @@ impl Config {
         self.rapid_commit
     }
 
+    /// fixed encoding order for response options, if configured
+    pub fn option_order(&self) -> Option<&OptionOrder> {
+        self.option_order.as_ref()
+    }
+
+    /// what makes a client `KNOWN`
+    pub fn known_clients(&self) -> KnownPolicy {
+        self.known_clients
+    }
+
+    /// whether lease fingerprints are recorded
+    pub fn store_fingerprints(&self) -> bool {
+        self.store_fingerprints
+    }
+
     /// If opt 61 (client id) exists return that, otherwise return `chaddr` from the message
     /// header.
     pub fn client_id<'a>(&self, msg: &'a Message) -> &'a [u8] {
@@ -251,6 +599,20 @@ impl Config {
         })
     }
 
+    /// boot fields for a response before the address is chosen, matched classes take
+    /// precedence over the network. The range or reservation is applied on top of this
+    pub fn boot(&self, network: Option<&Network>, matched_classes: Option<&[String]>) -> Boot {
+        let classes = self
+            .client_classes
+            .as_ref()
+            .map(|classes| classes.boot(matched_classes))
+            .unwrap_or_default();
+        match network {
+            Some(net) => classes.or(&net.boot),
+            None => classes,
+        }
+    }
+
     /// given a list of matched classes and a range
     /// return all options merged for parameter request list
     pub fn collect_opts(
@@ -261,11 +623,14 @@ impl Config {
         // TODO: there may be a way to reduce the use of `clone` here
         // maybe by providing the config to MsgContext
         // in reality, we only need to clone the messages that actually match the param request list
-        self.client_classes
+        let mut opts = self
+            .client_classes
             .as_ref()
             // range opts
             .map(|classes| merge_opts(opts.clone(), classes.collect_opts(matched_classes)))
-            .unwrap_or_else(|| opts.clone())
+            .unwrap_or_else(|| opts.clone());
+        timezone::set_time_offset(&mut opts, Utc::now());
+        opts
     }
 
     /// get a `NetRange` within a subnet that contains the given IP & any matching client classes
@@ -284,6 +649,10 @@ impl Config {
     pub fn get_first(&self) -> Option<(&Ipv4Net, &Network)> {
         self.networks.iter().next()
     }
+    /// iterate over all configured networks
+    pub fn networks(&self) -> impl Iterator<Item = (&Ipv4Net, &Network)> {
+        self.networks.iter()
+    }
     /// Create a new DhcpConfig for the server. Pass in the wire
     /// config format from yaml
     pub fn yaml<S: AsRef<str>>(input: S) -> Result<Self> {
@@ -317,17 +686,43 @@ pub struct Network {
     /// Reserved addresses based on opt
     /// Currently only support matching on a single option
     reserved_opts: HashMap<OptionCode, (DhcpOption, Reserved)>,
+    /// Reserved addresses based on hostname (opt 12), keys are lowercase
+    reserved_hostnames: HashMap<String, Reserved>,
+    /// Reserved addresses based on a user class (opt 77)
+    reserved_user_classes: HashMap<String, Reserved>,
     /// Will send an ICMP echo request to an IP before OFFER
     /// Should this be a global configuration?
     ping_check: bool,
     ping_timeout_ms: Duration,
     /// how long a DECLINE or ping check will be put on probation for
     probation_period: Duration,
+    /// probations before an IP is quarantined
+    max_probations: Option<u32>,
+    /// time without a probation after which the probation count resets
+    probation_decay: Option<Duration>,
+    /// how long after expiry a lease can be re-granted to its client
+    lease_grace: Option<Duration>,
     /// with authoritative == true then dora will always try to respond
     /// to REQUEST/INFORM
     authoritative: bool,
-    server_name: Option<String>,
-    file_name: Option<String>,
+    /// `siaddr`/`sname`/`file`
+    boot: Boot,
+    /// boot URLs for UEFI HTTP boot clients
+    http_boot: BTreeMap<http_boot::Arch, String>,
+    /// if set, network is IPv6-only preferred (RFC 8925) & this is V6ONLY_WAIT
+    v6_only_wait: Option<Duration>,
+    /// overrides the default response address
+    reply_to: Option<ReplyTo>,
+    /// interface MTU (opt 26) for ranges & reservations that don't set one
+    mtu: Option<u16>,
+    /// relay agents allowed to forward requests
+    relays: Option<RelayAcl>,
+    /// max leases per MAC vendor prefix
+    oui_limits: HashMap<Oui, u32>,
+    /// what happens to a client's leases in other networks when it leases here
+    multi_network_leases: MultiNetworkPolicy,
+    /// order ranges are tried in
+    fill: Fill,
 }
 
 impl Network {
@@ -347,11 +742,41 @@ impl Network {
         self.authoritative = authoritative;
         self
     }
+    pub fn set_probation_period(&mut self, probation_period: Duration) -> &mut Self {
+        self.probation_period = probation_period;
+        self
+    }
+    pub fn set_max_probations(&mut self, max_probations: Option<u32>) -> &mut Self {
+        self.max_probations = max_probations;
+        self
+    }
+    pub fn set_probation_decay(&mut self, probation_decay: Option<Duration>) -> &mut Self {
+        self.probation_decay = probation_decay;
+        self
+    }
+    pub fn set_lease_grace(&mut self, lease_grace: Option<Duration>) -> &mut Self {
+        self.lease_grace = lease_grace;
+        self
+    }
+    pub fn set_v6_only_wait(&mut self, v6_only_wait: Option<Duration>) -> &mut Self {
+        self.v6_only_wait = v6_only_wait;
+        self
+    }
+    pub fn set_reply_to(&mut self, reply_to: Option<ReplyTo>) -> &mut Self {
+        self.reply_to = reply_to;
+        self
+    }
     pub fn server_name(&self) -> Option<&str> {
-        self.server_name.as_deref()
+        self.boot.server_name.as_deref()
     }
     pub fn file_name(&self) -> Option<&str> {
-        self.file_name.as_deref()
+        self.boot.file_name.as_deref()
+    }
+    pub fn next_server(&self) -> Option<Ipv4Addr> {
+        self.boot.next_server
+    }
+    pub fn boot(&self) -> &Boot {
+        &self.boot
     }
     pub fn subnet(&self) -> Ipv4Addr {
         self.subnet.network()
@@ -372,6 +797,14 @@ impl Network {
             .iter()
             .filter(move |range| range.match_class(classes))
     }
+    /// all reservations in the config for this network
+    pub fn reservations(&self) -> impl Iterator<Item = &Reserved> {
+        self.reserved_macs
+            .values()
+            .chain(self.reserved_opts.values().map(|(_, res)| res))
+            .chain(self.reserved_hostnames.values())
+            .chain(self.reserved_user_classes.values())
+    }
     /// get reservation based on mac & matched client classes
     pub fn get_reserved_mac(&self, mac: MacAddr, classes: Option<&[String]>) -> Option<&Reserved> {
         let res = self.reserved_macs.get(&mac)?;
@@ -381,6 +814,10 @@ impl Network {
             None
         }
     }
+    /// `true` if there is a reservation for `mac`, whatever its class
+    pub fn has_reserved_mac(&self, mac: MacAddr) -> bool {
+        self.reserved_macs.contains_key(&mac)
+    }
     /// Based on a `DhcpOption`, find if there is a reservation where
     /// the value matches
     pub fn get_reserved_opt(&self, opt: &DhcpOption) -> Option<&Reserved> {
@@ -389,8 +826,39 @@ impl Network {
             _ => None,
         }
     }
+    /// get reservation based on hostname (case-insensitive) & matched client classes
+    pub fn get_reserved_hostname(
+        &self,
+        hostname: &str,
+        classes: Option<&[String]>,
+    ) -> Option<&Reserved> {
+        // some clients include trailing NULs in opt 12
+        let hostname = hostname.trim_end_matches('\0').to_ascii_lowercase();
+        let res = self.reserved_hostnames.get(&hostname)?;
+        if res.match_class(classes) {
+            Some(res)
+        } else {
+            None
+        }
+    }
+    /// get reservation for any of the user classes (opt 77) in `opts` & matched client
+    /// classes, in the order the client sent them
+    pub fn get_reserved_user_class(
+        &self,
+        opts: &DhcpOptions,
+        classes: Option<&[String]>,
+    ) -> Option<&Reserved> {
+        if self.reserved_user_classes.is_empty() {
+            return None;
+        }
+        client_classification::user_classes(opts)
+            .iter()
+            .filter_map(|class| self.reserved_user_classes.get(class))
+            .find(|res| res.match_class(classes))
+    }
     /// Given some `opts`, search to see if there is a match with a reservation
-    /// client classes must also match
+    /// client classes must also match. User class (opt 77) then hostname (opt 12)
+    /// reservations are checked after all other options
     pub fn search_reserved_opt(
         &self,
         opts: &DhcpOptions,
@@ -403,7 +871,33 @@ impl Network {
                 }
             }
         }
-        None
+        if let Some(res) = self.get_reserved_user_class(opts, classes) {
+            return Some(res);
+        }
+        match opts.get(OptionCode::Hostname) {
+            Some(DhcpOption::Hostname(name)) => self.get_reserved_hostname(name, classes),
+            _ => None,
+        }
+    }
+    /// `true` if the client has any reservation in this network by mac, option, user class
+    /// or hostname.
+    /// Client classes are ignored, this is used to determine the `KNOWN` built-in class
+    pub fn has_reservation(&self, chaddr: &[u8], opts: &DhcpOptions) -> bool {
+        let mac = <[u8; 6]>::try_from(chaddr).ok().map(MacAddr::from);
+        matches!(mac, Some(mac) if self.reserved_macs.contains_key(&mac))
+            || opts
+                .iter()
+                .any(|(_, opt)| self.get_reserved_opt(opt).is_some())
+            || matches!(
+                opts.get(OptionCode::Hostname),
+                Some(DhcpOption::Hostname(name)) if self.reserved_hostnames.contains_key(
+                    &name.trim_end_matches('\0').to_ascii_lowercase()
+                )
+            )
+            || (!self.reserved_user_classes.is_empty()
+                && client_classification::user_classes(opts)
+                    .iter()
+                    .any(|class| self.reserved_user_classes.contains_key(class)))
     }
     /// Return `true` if ip is in a range for a given `network`, `false` otherwise
     pub fn in_range<I: Into<Ipv4Addr>>(&self, ip: I) -> bool {
@@ -418,6 +912,14 @@ impl Network {
         // if classes exist, look for a range that contains `ip` and matching class
         self.ranges.iter().find(|r| r.contains_class(&ip, classes))
     }
+    /// lease time for addresses that don't come from a range or a configured reservation,
+    /// ex. imported reservations. Networks have no lease config of their own, so this is
+    /// the first range's, or [`DEFAULT_LEASE_TIME`] if there are no ranges
+    pub fn lease(&self) -> LeaseTime {
+        self.ranges.first().map(|r| r.lease()).unwrap_or_else(|| {
+            LeaseTime::new(DEFAULT_LEASE_TIME, DEFAULT_LEASE_TIME, DEFAULT_LEASE_TIME)
+        })
+    }
     /// is ping check enabled for this range? should we ping an IP before offering?
     pub fn ping_check(&self) -> bool {
         self.ping_check
@@ -430,9 +932,178 @@ impl Network {
     pub fn probation_period(&self) -> Duration {
         self.probation_period
     }
+    /// Returns how many times an IP may be probated before it is quarantined
+    pub fn max_probations(&self) -> Option<u32> {
+        self.max_probations
+    }
+    /// Returns how long an IP must go without a probation for its count to reset
+    pub fn probation_decay(&self) -> Option<Duration> {
+        self.probation_decay
+    }
+    /// Returns how long after expiry a client can REQUEST its lease back
+    pub fn lease_grace(&self) -> Option<Duration> {
+        self.lease_grace
+    }
     pub fn total_addrs(&self) -> usize {
         self.ranges.iter().map(|range| range.total_addrs()).sum()
     }
+    /// Returns V6ONLY_WAIT if this network is IPv6-only preferred (RFC 8925)
+    pub fn v6_only_wait(&self) -> Option<Duration> {
+        self.v6_only_wait
+    }
+    /// Returns the configured response address override, if any
+    pub fn reply_to(&self) -> Option<ReplyTo> {
+        self.reply_to
+    }
+    /// the network's interface MTU (opt 26), if set
+    pub fn mtu(&self) -> Option<u16> {
+        self.mtu
+    }
+    pub fn set_relays(&mut self, relays: Option<RelayAcl>) -> &mut Self {
+        self.relays = relays;
+        self
+    }
+    /// the relay agents allowed to forward requests, if limited
+    pub fn relays(&self) -> Option<&RelayAcl> {
+        self.relays.as_ref()
+    }
+    pub fn set_oui_limits(&mut self, oui_limits: HashMap<Oui, u32>) -> &mut Self {
+        self.oui_limits = oui_limits;
+        self
+    }
+    /// the OUI of `chaddr` & its max leases, if the OUI is limited on this network
+    pub fn oui_limit(&self, chaddr: &[u8]) -> Option<(Oui, u32)> {
+        let oui = Oui::from_chaddr(chaddr)?;
+        self.oui_limits.get(&oui).map(|max| (oui, *max))
+    }
+    pub fn set_multi_network_leases(&mut self, policy: MultiNetworkPolicy) -> &mut Self {
+        self.multi_network_leases = policy;
+        self
+    }
+    /// what happens to a client's leases in other networks when it leases here
+    pub fn multi_network_leases(&self) -> MultiNetworkPolicy {
+        self.multi_network_leases
+    }
+    pub fn set_fill(&mut self, fill: Fill) -> &mut Self {
+        self.fill = fill;
+        self
+    }
+    /// order ranges are tried in
+    pub fn fill(&self) -> Fill {
+        self.fill
+    }
+    pub fn set_http_boot(&mut self, urls: BTreeMap<http_boot::Arch, String>) -> &mut Self {
+        self.http_boot = urls;
+        self
+    }
+    /// the boot URL for a UEFI HTTP boot client sending `opts`, `None` if it isn't one or
+    /// none of its architectures has a URL
+    pub fn http_boot_url(&self, opts: &DhcpOptions) -> Option<&str> {
+        if self.http_boot.is_empty() {
+            return None;
+        }
+        http_boot::client_arches(opts)
+            .iter()
+            .find_map(|arch| self.http_boot.get(arch))
+            .map(String::as_str)
+    }
+}
+
+impl Fill {
+    /// sort `ranges`, given with the number of addresses in use in each, into the order
+    /// they should be tried in. Ties keep config order
+    pub fn order<'a>(&self, mut ranges: Vec<(&'a NetRange, usize)>) -> Vec<&'a NetRange> {
+        // exclusions aren't counted, they don't change the order much & counting them
+        // means walking the range
+        let size =
+            |range: &NetRange| (u32::from(range.end()) - u32::from(range.start())) as u64 + 1;
+        match self {
+            Fill::Sequential => {}
+            // used_a / size_a < used_b / size_b, without dividing
+            Fill::Fair => ranges.sort_by(|(a, used_a), (b, used_b)| {
+                (*used_a as u64 * size(b)).cmp(&(*used_b as u64 * size(a)))
+            }),
+            Fill::MostFreeFirst => ranges.sort_by_key(|(range, used)| {
+                std::cmp::Reverse(size(range).saturating_sub(*used as u64))
+            }),
+        }
+        ranges.into_iter().map(|(range, _)| range).collect()
+    }
+}
+
+/// Relay agents allowed to forward requests for a network
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RelayAcl {
+    giaddrs: Vec<RangeInclusive<Ipv4Addr>>,
+    remote_ids: Vec<Vec<u8>>,
+}
+
+/// why a relayed request was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayDenied {
+    /// `giaddr` isn't an allowed relay
+    Giaddr,
+    /// no opt 82 remote-id, or not an allowed one
+    RemoteId,
+}
+
+impl RelayDenied {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RelayDenied::Giaddr => "giaddr",
+            RelayDenied::RemoteId => "remote_id",
+        }
+    }
+}
+
+impl From<wire::v4::Relays> for RelayAcl {
+    fn from(relays: wire::v4::Relays) -> Self {
+        Self {
+            giaddrs: relays.giaddrs.into_iter().map(Into::into).collect(),
+            remote_ids: relays
+                .remote_ids
+                .into_iter()
+                .map(|id| match id.strip_prefix("0x").map(hex::decode) {
+                    Some(Ok(bytes)) => bytes,
+                    _ => id.into_bytes(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl RelayAcl {
+    pub fn new(giaddrs: Vec<RangeInclusive<Ipv4Addr>>, remote_ids: Vec<Vec<u8>>) -> Self {
+        Self {
+            giaddrs,
+            remote_ids,
+        }
+    }
+    /// why `req` isn't allowed, `None` if it is. Requests that weren't relayed are allowed
+    pub fn check(&self, req: &Message) -> Option<RelayDenied> {
+        let giaddr = req.giaddr();
+        if giaddr.is_unspecified() {
+            return None;
+        }
+        if !self.giaddrs.is_empty() && !self.giaddrs.iter().any(|r| r.contains(&giaddr)) {
+            return Some(RelayDenied::Giaddr);
+        }
+        if !self.remote_ids.is_empty() {
+            let remote_id = match req.opts().get(OptionCode::RelayAgentInformation) {
+                Some(DhcpOption::RelayAgentInformation(info)) => {
+                    match info.get(relay::RelayCode::AgentRemoteId) {
+                        Some(relay::RelayInfo::AgentRemoteId(id)) => Some(id),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            if !remote_id.is_some_and(|id| self.remote_ids.contains(id)) {
+                return Some(RelayDenied::RemoteId);
+            }
+        }
+        None
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -443,8 +1114,12 @@ pub struct NetRange {
     /// a lease time
     lease: LeaseTime,
     opts: DhcpOptions,
-    exclude: HashSet<Ipv4Addr>,
+    exclude: Exclusions,
     class: Option<String>,
+    boot: Boot,
+    /// classes whose clients may not get addresses from this range, from the class
+    /// `allow`/`deny` lists
+    restricted: Vec<String>,
 }
 
 impl NetRange {
@@ -453,8 +1128,10 @@ impl NetRange {
             addrs,
             lease,
             opts: DhcpOptions::default(),
-            exclude: HashSet::default(),
+            exclude: Exclusions::default(),
             class: None,
+            boot: Boot::default(),
+            restricted: Vec::new(),
         }
     }
     /// get the range of IPs this range offers
@@ -487,6 +1164,10 @@ impl NetRange {
         self.contains(ip) && self.match_class(classes)
     }
     pub fn match_class(&self, classes: Option<&[String]>) -> bool {
+        // any matched class that restricts its clients from this range rules it out
+        if matches!(classes, Some(classes) if classes.iter().any(|c| self.restricted.contains(c))) {
+            return false;
+        }
         // if range has no class, this expression is always true
         // if range has a class, it must match an entry in the list
         self.class
@@ -501,13 +1182,10 @@ impl NetRange {
     }
     /// return an iterator over the range
     pub fn iter(&self) -> NetRangeIter<'_> {
-        NetRangeIter {
-            exclusions: &self.exclude,
-            iter: Ipv4AddrRange::new(self.start(), self.end()),
-        }
+        NetRangeIter::new(self.addrs(), &self.exclude)
     }
-    /// returns a set of excluded ipv4 addrs
-    pub fn exclusions(&self) -> &HashSet<Ipv4Addr> {
+    /// returns the excluded ipv4 addrs
+    pub fn exclusions(&self) -> &Exclusions {
         &self.exclude
     }
     /// count the total number of addresses that could possibly be
@@ -519,36 +1197,21 @@ impl NetRange {
     pub fn class(&self) -> Option<&str> {
         self.class.as_deref()
     }
-}
-
-#[derive(Debug)]
-pub struct NetRangeIter<'a> {
-    exclusions: &'a HashSet<Ipv4Addr>,
-    iter: Ipv4AddrRange,
-}
-
-impl<'a> NetRangeIter<'a> {
-    pub fn new(iter: Ipv4AddrRange, exclusions: &'a HashSet<Ipv4Addr>) -> Self {
-        Self { iter, exclusions }
+    /// classes whose clients can't get addresses from this range
+    pub fn restricted(&self) -> &[String] {
+        &self.restricted
+    }
+    /// boot fields that override the class & network ones for addresses from this range
+    pub fn boot(&self) -> &Boot {
+        &self.boot
     }
 }
 
-impl Iterator for NetRangeIter<'_> {
-    type Item = Ipv4Addr;
+/// excluded v4 addresses of a range
+pub type Exclusions = crate::exclusions::Exclusions<Ipv4Addr>;
 
-    // skips any IPs in exclusions
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            let next = self.iter.next()?;
-            if !self.exclusions.contains(&next) {
-                return Some(next);
-            }
-        }
-    }
-    fn count(self) -> usize {
-        self.iter.count() - self.exclusions.len()
-    }
-}
+/// iterates the v4 addresses of a range, jumping over excluded blocks
+pub type NetRangeIter<'a> = crate::exclusions::NetRangeIter<'a, Ipv4Addr>;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Reserved {
@@ -560,6 +1223,7 @@ pub struct Reserved {
     lease: LeaseTime,
     opts: DhcpOptions,
     class: Option<String>,
+    boot: Boot,
 }
 
 impl Reserved {
@@ -580,6 +1244,10 @@ impl Reserved {
     pub fn class(&self) -> Option<&str> {
         self.class.as_deref()
     }
+    /// boot fields that override the class & network ones for this reservation
+    pub fn boot(&self) -> &Boot {
+        &self.boot
+    }
     /// given a list of matched classes, determine if this reservation has a match
     ///         if reservation has no class, this expression is always true
     /// if reservation has a class, it must match an entry in the list
@@ -596,30 +1264,105 @@ impl Reserved {
     }
 }
 
+impl From<wire::v4::NetworkConfig> for LeaseTime {
+    fn from(cfg: wire::v4::NetworkConfig) -> Self {
+        if cfg.infinite {
+            LeaseTime::infinite()
+        } else {
+            LeaseTime::from(cfg.lease_time).with_renewal(cfg.renew_percent, cfg.rebind_percent)
+        }
+    }
+}
+
 impl From<wire::v4::IpRange> for NetRange {
     fn from(range: wire::v4::IpRange) -> Self {
-        let lease = range.config.lease_time.into();
-        let opts = range.options.get();
+        let lease = range.config.into();
+        let mut opts = range.options.get();
+        if let Some(mtu) = range.mtu {
+            opts.insert(DhcpOption::InterfaceMtu(mtu));
+        }
         NetRange {
             addrs: range.range,
             opts,
             lease,
-            exclude: range.except.into_iter().collect(),
+            exclude: Exclusions::new(range.except.into_iter().map(Into::into)),
             class: range.class,
+            boot: range.boot,
+            restricted: Vec::new(),
         }
     }
 }
 
 impl From<&wire::v4::ReservedIp> for Reserved {
     fn from(res: &wire::v4::ReservedIp) -> Self {
-        let lease = res.config.lease_time.into();
+        let lease = res.config.clone().into();
         Reserved {
             lease,
             ip: res.ip,
             opts: res.options.as_ref().clone(),
             class: res.class.clone(),
+            boot: res.boot.clone(),
+        }
+    }
+}
+
+/// add the network `mtu` to options that don't have opt 26
+/// check each network maps to at most one interface, returning the address of every
+/// interface bound to a network with `interfaces`
+fn bind_networks<'a>(
+    interfaces: &[NetworkInterface],
+    networks: impl IntoIterator<Item = (&'a Ipv4Net, Option<&'a [String]>)>,
+) -> Result<HashMap<u32, Ipv4Network>> {
+    let addr_in = |int: &NetworkInterface, subnet: &Ipv4Net| {
+        int.ips.iter().find_map(|ip| match ip {
+            IpNetwork::V4(ip) if subnet.contains(&ip.ip()) => Some(*ip),
+            _ => None,
+        })
+    };
+    let mut bound: HashMap<u32, (Ipv4Net, Ipv4Network)> = HashMap::new();
+    let mut unbound = Vec::new();
+    for (subnet, names) in networks {
+        let Some(names) = names else {
+            unbound.push(subnet);
+            continue;
+        };
+        for name in names {
+            let name = crate::interface_name(name);
+            let int = interfaces
+                .iter()
+                .find(|int| int.name == name)
+                .with_context(|| format!("network {subnet}: not listening on interface {name}"))?;
+            let addr = addr_in(int, subnet).with_context(|| {
+                format!("network {subnet}: interface {name} has no address in the subnet")
+            })?;
+            if let Some((other, _)) = bound.insert(int.index, (*subnet, addr)) {
+                bail!("interface {name} is bound to both network {other} and {subnet}");
+            }
+        }
+    }
+    for subnet in unbound {
+        let matched = interfaces
+            .iter()
+            .filter(|int| addr_in(int, subnet).is_some())
+            .map(|int| int.name.as_str())
+            .collect::<Vec<_>>();
+        if matched.len() > 1 {
+            bail!(
+                "network {subnet} matches interfaces {}, pick one with `interfaces`",
+                matched.join(", ")
+            );
         }
     }
+    Ok(bound
+        .into_iter()
+        .map(|(index, (_, addr))| (index, addr))
+        .collect())
+}
+
+fn inherit_mtu(opts: &mut DhcpOptions, mtu: Option<u16>) {
+    if let Some(mtu) = mtu.filter(|_| opts.get(OptionCode::InterfaceMtu).is_none()) {
+        opts.insert(DhcpOption::InterfaceMtu(mtu));
+    }
 }
 
 /// merge `b` into `a`, favoring `a` where there are duplicates
@@ -637,52 +1380,619 @@ fn merge_opts(mut a: DhcpOptions, b: Option<DhcpOptions>) -> DhcpOptions {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct FloodThreshold {
-    packets: u32,
-    period: Duration,
+/// RFC 3074 mixing table for the Pearson hash
+const LB_MIX_TABLE: [u8; 256] = [
+    251, 175, 119, 215, 81, 14, 79, 191, 103, 49, 181, 143, 186, 157, 0, 232, 31, 32, 55, 60, 152,
+    58, 17, 237, 174, 70, 160, 144, 220, 90, 57, 223, 59, 3, 18, 140, 111, 166, 203, 196, 134, 243,
+    124, 95, 222, 179, 197, 65, 180, 48, 36, 15, 107, 46, 233, 130, 165, 30, 123, 161, 209, 23, 97,
+    16, 40, 91, 219, 61, 100, 10, 210, 109, 250, 127, 22, 138, 29, 108, 244, 67, 207, 9, 178, 204,
+    74, 98, 126, 249, 167, 116, 34, 77, 193, 200, 121, 5, 20, 113, 71, 35, 128, 13, 182, 94, 25,
+    226, 227, 199, 75, 27, 41, 245, 230, 224, 43, 225, 177, 26, 155, 150, 212, 142, 218, 115, 241,
+    73, 88, 105, 39, 114, 62, 255, 192, 201, 145, 214, 168, 158, 221, 148, 154, 122, 12, 84, 82,
+    163, 44, 139, 228, 236, 205, 242, 217, 11, 187, 146, 159, 64, 86, 239, 195, 42, 106, 198, 118,
+    112, 184, 172, 87, 2, 173, 117, 176, 229, 247, 253, 137, 185, 99, 164, 102, 147, 45, 66, 231,
+    52, 141, 211, 194, 206, 246, 238, 56, 110, 78, 248, 63, 240, 189, 93, 92, 51, 53, 183, 19, 171,
+    72, 50, 33, 104, 101, 69, 8, 252, 83, 120, 76, 135, 85, 54, 202, 125, 188, 213, 96, 235, 136,
+    208, 162, 129, 190, 132, 156, 38, 47, 1, 7, 254, 24, 4, 216, 131, 89, 21, 28, 133, 37, 153,
+    149, 80, 170, 68, 6, 169, 234, 151,
+];
+
+/// Load balancing between two servers (RFC 3074). Clients are hashed into one of 256
+/// buckets, the primary serves buckets below `split` & the secondary serves the rest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadBalance {
+    primary: bool,
+    split: u16,
+    max_secs: u16,
 }
 
-impl Default for FloodThreshold {
-    fn default() -> Self {
-        Self {
-            packets: 6,
-            period: Duration::from_secs(5),
+impl TryFrom<wire::LoadBalance> for LoadBalance {
+    type Error = anyhow::Error;
+
+    fn try_from(lb: wire::LoadBalance) -> Result<Self> {
+        if lb.split > 256 {
+            anyhow::bail!("load_balance split must be between 0 and 256");
         }
+        Ok(Self {
+            primary: lb.role == wire::LoadBalanceRole::Primary,
+            split: lb.split,
+            max_secs: lb.max_secs,
+        })
     }
 }
 
-impl FloodThreshold {
-    pub fn new(packets: u32, period: Duration) -> Self {
-        Self { packets, period }
+impl LoadBalance {
+    pub fn new(primary: bool, split: u16, max_secs: u16) -> Self {
+        Self {
+            primary,
+            split,
+            max_secs,
+        }
     }
-    pub fn packets(&self) -> u32 {
-        self.packets
+    /// RFC 3074 hash of a client identifier (opt 61, or chaddr if not present)
+    pub fn bucket(id: &[u8]) -> u8 {
+        id.iter()
+            .rev()
+            .fold(id.len() as u8, |hash, b| LB_MIX_TABLE[(hash ^ b) as usize])
     }
-    pub fn period(&self) -> Duration {
-        self.period
+    /// should this server answer a client with id `id` that has been trying for `secs`
+    pub fn serves(&self, id: &[u8], secs: u16) -> bool {
+        if self.max_secs != 0 && secs >= self.max_secs {
+            return true;
+        }
+        let primary_bucket = (Self::bucket(id) as u16) < self.split;
+        primary_bucket == self.primary
     }
 }
 
-#[cfg(test)]
-mod tests {
+/// Checks for packets no real client would send
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SanityChecks {
+    policy: SanityPolicy,
+    max_secs: u16,
+}
 
-    use dora_core::dhcproto::v4;
+/// the sanity check a packet failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Insane {
+    /// transaction id is 0
+    ZeroXid,
+    /// `secs` is larger than `max_secs`
+    Secs,
+    /// `hlen` is too long or doesn't match `htype`
+    Hlen,
+    /// `chaddr` is all zeros & there is no client id to identify the client by
+    NoClientId,
+}
 
-    use super::*;
+impl Insane {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Insane::ZeroXid => "zero_xid",
+            Insane::Secs => "secs",
+            Insane::Hlen => "hlen",
+            Insane::NoClientId => "no_client_id",
+        }
+    }
+}
 
-    pub static SAMPLE_YAML: &str = include_str!("../sample/config.yaml");
-    pub static CIRC_YAML: &str = include_str!("../sample/circular_deps.yaml");
+impl From<wire::SanityChecks> for SanityChecks {
+    fn from(checks: wire::SanityChecks) -> Self {
+        Self::new(checks.policy, checks.max_secs)
+    }
+}
 
-    // test we can decode from wire
-    #[test]
-    fn test_sample() {
-        let cfg = Config::new(SAMPLE_YAML).unwrap();
-        // test a range decoded properly
-        let net = cfg.network([192, 168, 0, 1]).unwrap();
-        assert_eq!(net.ranges()[0].start(), Ipv4Addr::from([192, 168, 0, 100]));
-        assert_eq!(
-            net.ranges()[0].opts().get(v4::OptionCode::Router),
+impl SanityChecks {
+    pub fn new(policy: SanityPolicy, max_secs: u16) -> Self {
+        Self { policy, max_secs }
+    }
+    pub fn policy(&self) -> SanityPolicy {
+        self.policy
+    }
+    /// run all checks on `req`, returning the first one that fails
+    pub fn check(&self, req: &Message) -> Option<Insane> {
+        // ethernet & IEEE 802 both use 6 byte MACs, chaddr has room for 16 bytes
+        let hlen_ok = match u8::from(req.htype()) {
+            1 | 6 => req.hlen() == 6,
+            _ => req.hlen() <= 16,
+        };
+        if req.xid() == 0 {
+            Some(Insane::ZeroXid)
+        } else if self.max_secs != 0 && req.secs() > self.max_secs {
+            Some(Insane::Secs)
+        } else if !hlen_ok {
+            Some(Insane::Hlen)
+        } else if req.chaddr().iter().all(|b| *b == 0)
+            && req.opts().get(OptionCode::ClientIdentifier).is_none()
+        {
+            Some(Insane::NoClientId)
+        } else {
+            None
+        }
+    }
+}
+
+/// Recognizes messages that came from dora itself, ex. replies a misconfigured relay
+/// sends back to us
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopProtection {
+    /// MACs of the interfaces we're bound to
+    macs: Vec<[u8; 6]>,
+    /// interface addresses & configured server identifiers
+    addrs: Vec<Ipv4Addr>,
+    log_interval: Duration,
+}
+
+/// why a message looks like one of our own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Looped {
+    /// `chaddr` is one of our MACs
+    Chaddr,
+    /// the client id is one of our MACs, with or without the htype prefix
+    ClientId,
+    /// `ciaddr` is one of our addresses or server identifiers
+    ServerId,
+}
+
+impl Looped {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Looped::Chaddr => "chaddr",
+            Looped::ClientId => "client_id",
+            Looped::ServerId => "server_id",
+        }
+    }
+}
+
+impl LoopProtection {
+    pub fn new(
+        macs: impl IntoIterator<Item = [u8; 6]>,
+        addrs: impl IntoIterator<Item = Ipv4Addr>,
+        log_interval: Duration,
+    ) -> Self {
+        let mut macs = macs
+            .into_iter()
+            // loopback & some virtual interfaces have no MAC
+            .filter(|mac| mac.iter().any(|b| *b != 0))
+            .collect::<Vec<_>>();
+        macs.sort_unstable();
+        macs.dedup();
+        let mut addrs = addrs
+            .into_iter()
+            .filter(|ip| !ip.is_unspecified())
+            .collect::<Vec<_>>();
+        addrs.sort_unstable();
+        addrs.dedup();
+        Self {
+            macs,
+            addrs,
+            log_interval,
+        }
+    }
+    /// log at most one dropped message per interval
+    pub fn log_interval(&self) -> Duration {
+        self.log_interval
+    }
+    /// `Some` if `req` looks like it was sent by us
+    pub fn check(&self, req: &Message) -> Option<Looped> {
+        let ours = |bytes: &[u8]| self.macs.iter().any(|mac| mac[..] == *bytes);
+        let client_id = match req.opts().get(OptionCode::ClientIdentifier) {
+            Some(DhcpOption::ClientIdentifier(id)) => id.as_slice(),
+            _ => &[],
+        };
+        if ours(req.chaddr()) {
+            Some(Looped::Chaddr)
+        } else if ours(client_id) || matches!(client_id, [1, mac @ ..] if ours(mac)) {
+            Some(Looped::ClientId)
+        } else if self.addrs.contains(&req.ciaddr()) {
+            Some(Looped::ServerId)
+        } else {
+            None
+        }
+    }
+}
+
+/// Answer only clients that have been retrying a while when the server is busy, so the
+/// ones that waited longest aren't starved by new arrivals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadShedding {
+    high_water: u8,
+    min_secs: u16,
+}
+
+impl TryFrom<wire::LoadShedding> for LoadShedding {
+    type Error = anyhow::Error;
+
+    fn try_from(shed: wire::LoadShedding) -> Result<Self> {
+        if !(1..=100).contains(&shed.high_water) {
+            anyhow::bail!("load_shedding high_water must be between 1 and 100");
+        }
+        Ok(Self::new(shed.high_water, shed.min_secs))
+    }
+}
+
+impl LoadShedding {
+    pub fn new(high_water: u8, min_secs: u16) -> Self {
+        Self {
+            high_water,
+            min_secs,
+        }
+    }
+    /// should a client that has been trying for `secs` be dropped with `live` of `limit`
+    /// messages in flight
+    pub fn sheds(&self, live: usize, limit: usize, secs: u16) -> bool {
+        live * 100 >= limit * self.high_water as usize && secs < self.min_secs
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FloodThreshold {
+    packets: u32,
+    period: Duration,
+    pressure: Option<FloodPressure>,
+    /// what packets are counted by, the parts make up a tuple
+    key: Vec<FloodKey>,
+}
+
+/// A tighter flood threshold for when pools are nearly full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloodPressure {
+    high_water: u8,
+    packets: u32,
+}
+
+impl Default for FloodThreshold {
+    fn default() -> Self {
+        Self {
+            packets: 6,
+            period: Duration::from_secs(5),
+            pressure: None,
+            key: vec![FloodKey::ClientId],
+        }
+    }
+}
+
+impl TryFrom<wire::FloodThreshold> for FloodThreshold {
+    type Error = anyhow::Error;
+
+    fn try_from(f: wire::FloodThreshold) -> Result<Self> {
+        let pressure = f
+            .pressure
+            .map(|p| {
+                if !(1..=100).contains(&p.high_water) {
+                    anyhow::bail!("pressure high_water must be between 1 and 100");
+                }
+                if p.packets > f.packets {
+                    anyhow::bail!("pressure packets must not be more than packets");
+                }
+                Ok(FloodPressure::new(p.high_water, p.packets.get()))
+            })
+            .transpose()?;
+        let mut threshold = Self::new(f.packets.get(), Duration::from_secs(f.secs.get() as u64))
+            .with_pressure(pressure);
+        if let Some(key) = f.key {
+            let key = key.into_vec();
+            if key.is_empty() {
+                anyhow::bail!("key must have at least one part");
+            }
+            if key
+                .iter()
+                .enumerate()
+                .any(|(i, part)| key[..i].contains(part))
+            {
+                anyhow::bail!("key {key:?} has the same part twice");
+            }
+            threshold = threshold.with_key(key);
+        }
+        Ok(threshold)
+    }
+}
+
+/// each limiter must have its own key, two with the same one would count the same packets.
+/// The parts of a tuple can be in any order, `[giaddr, chaddr]` is `[chaddr, giaddr]`
+fn flood_thresholds(list: Vec<wire::FloodThreshold>) -> Result<Vec<FloodThreshold>> {
+    let thresholds = list
+        .into_iter()
+        .map(FloodThreshold::try_from)
+        .collect::<Result<Vec<_>>>()?;
+    // parts are never repeated within a key, so equal length & containment is equality
+    let same_key = |a: &[FloodKey], b: &[FloodKey]| {
+        a.len() == b.len() && a.iter().all(|part| b.contains(part))
+    };
+    for (i, threshold) in thresholds.iter().enumerate() {
+        if thresholds[..i]
+            .iter()
+            .any(|t| same_key(&t.key, &threshold.key))
+        {
+            anyhow::bail!("more than one limiter is keyed by {}", threshold.key_name());
+        }
+    }
+    Ok(thresholds)
+}
+
+impl FloodThreshold {
+    pub fn new(packets: u32, period: Duration) -> Self {
+        Self {
+            packets,
+            period,
+            pressure: None,
+            key: vec![FloodKey::ClientId],
+        }
+    }
+    pub fn with_pressure(mut self, pressure: Option<FloodPressure>) -> Self {
+        self.pressure = pressure;
+        self
+    }
+    pub fn with_key(mut self, key: Vec<FloodKey>) -> Self {
+        self.key = key;
+        self
+    }
+    pub fn packets(&self) -> u32 {
+        self.packets
+    }
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+    /// the limit used while pools are under pressure, if configured
+    pub fn pressure(&self) -> Option<FloodPressure> {
+        self.pressure
+    }
+    /// the parts packets are counted by
+    pub fn key(&self) -> &[FloodKey] {
+        &self.key
+    }
+    /// the key's parts joined with `+`, ex. `giaddr+chaddr`
+    pub fn key_name(&self) -> String {
+        self.key
+            .iter()
+            .map(FloodKey::as_str)
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+}
+
+impl FloodPressure {
+    pub fn new(high_water: u8, packets: u32) -> Self {
+        Self {
+            high_water,
+            packets,
+        }
+    }
+    /// utilization percent at which the tighter limit applies
+    pub fn high_water(&self) -> u8 {
+        self.high_water
+    }
+    /// packets allowed per period under pressure
+    pub fn packets(&self) -> u32 {
+        self.packets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use dora_core::dhcproto::v4;
+
+    use super::*;
+
+    pub static SAMPLE_YAML: &str = include_str!("../sample/config.yaml");
+    pub static CIRC_YAML: &str = include_str!("../sample/circular_deps.yaml");
+
+    #[test]
+    fn test_bind_networks() {
+        use crate::tests::iface;
+        let interfaces = vec![
+            iface("eth0", 2, &["192.168.0.1/24", "10.0.0.1/24"]),
+            iface("eth0.100", 3, &["10.0.100.1/24"]),
+            iface("eth1", 4, &["10.0.100.2/24"]),
+        ];
+        let net = |s: &str| s.parse::<Ipv4Net>().unwrap();
+        let list = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (lan, other, vlan) = (
+            net("192.168.0.0/24"),
+            net("10.0.0.0/24"),
+            net("10.0.100.0/24"),
+        );
+        let eth0 = list(&["eth0"]);
+        let vlan_ifaces = list(&["eth0.100@eth0"]);
+
+        // the network bound to eth0 uses the address in its subnet
+        let bound = bind_networks(
+            &interfaces,
+            [
+                (&other, Some(eth0.as_slice())),
+                (&vlan, Some(vlan_ifaces.as_slice())),
+            ],
+        )
+        .unwrap();
+        assert_eq!(bound[&2], "10.0.0.1/24".parse::<Ipv4Network>().unwrap());
+        assert_eq!(bound[&3], "10.0.100.1/24".parse::<Ipv4Network>().unwrap());
+        // unbound & matching a single interface, or relayed
+        assert!(bind_networks(&interfaces, [(&lan, None), (&net("172.16.0.0/16"), None)]).is_ok());
+        // unbound & matching both eth0.100 & eth1
+        assert!(bind_networks(&interfaces, [(&vlan, None)]).is_err());
+        // eth0 has no address in the subnet
+        assert!(bind_networks(&interfaces, [(&vlan, Some(eth0.as_slice()))]).is_err());
+        // not listening on eth2
+        let eth2 = list(&["eth2"]);
+        assert!(bind_networks(&interfaces, [(&lan, Some(eth2.as_slice()))]).is_err());
+        // eth0 bound to two networks
+        assert!(bind_networks(
+            &interfaces,
+            [
+                (&lan, Some(eth0.as_slice())),
+                (&other, Some(eth0.as_slice()))
+            ]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_flood_pressure() {
+        let wire = |pressure: &str| -> wire::FloodThreshold {
+            serde_yaml::from_str(&format!("packets: 6\nsecs: 5\n{pressure}")).unwrap()
+        };
+        let threshold =
+            FloodThreshold::try_from(wire("pressure:\n  high_water: 90\n  packets: 2")).unwrap();
+        assert_eq!(threshold.packets(), 6);
+        assert_eq!(threshold.pressure(), Some(FloodPressure::new(90, 2)));
+        assert_eq!(FloodThreshold::try_from(wire("")).unwrap().pressure(), None);
+        // must be a tighter limit
+        assert!(
+            FloodThreshold::try_from(wire("pressure:\n  high_water: 90\n  packets: 7")).is_err()
+        );
+        assert!(
+            FloodThreshold::try_from(wire("pressure:\n  high_water: 0\n  packets: 2")).is_err()
+        );
+    }
+
+    #[test]
+    fn test_flood_key() {
+        let parse = |yaml: &str| {
+            flood_thresholds(
+                serde_yaml::from_str::<wire::MaybeList<wire::FloodThreshold>>(yaml)
+                    .unwrap()
+                    .into_vec(),
+            )
+        };
+        let thresholds = parse("packets: 6\nsecs: 5").unwrap();
+        assert_eq!(thresholds[0].key(), [FloodKey::ClientId]);
+
+        let thresholds = parse(
+            "- packets: 6\n  secs: 5\n- packets: 200\n  secs: 5\n  key: giaddr\n\
+             - packets: 20\n  secs: 5\n  key: [giaddr, chaddr]",
+        )
+        .unwrap();
+        assert_eq!(
+            thresholds.iter().map(|t| t.key_name()).collect::<Vec<_>>(),
+            ["client_id", "giaddr", "giaddr+chaddr"]
+        );
+        assert_eq!(thresholds[1].packets(), 200);
+
+        assert!(
+            parse("- packets: 6\n  secs: 5\n- packets: 2\n  secs: 5\n  key: client_id").is_err()
+        );
+        assert!(parse(
+            "- packets: 6\n  secs: 5\n  key: [giaddr, chaddr]\n\
+             - packets: 2\n  secs: 5\n  key: [chaddr, giaddr]"
+        )
+        .is_err());
+        assert!(parse("packets: 6\nsecs: 5\nkey: []").is_err());
+        assert!(parse("packets: 6\nsecs: 5\nkey: [chaddr, chaddr]").is_err());
+        assert!(
+            serde_yaml::from_str::<wire::FloodThreshold>("packets: 6\nsecs: 5\nkey: vlan").is_err()
+        );
+    }
+
+    #[test]
+    fn test_load_shedding() {
+        let shed = LoadShedding::new(80, 4);
+        assert!(!shed.sheds(79, 100, 0));
+        assert!(shed.sheds(80, 100, 0));
+        assert!(shed.sheds(100, 100, 3));
+        assert!(!shed.sheds(100, 100, 4));
+        assert!(LoadShedding::try_from(wire::LoadShedding {
+            high_water: 0,
+            min_secs: 4
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_sanity_checks() {
+        let uns = Ipv4Addr::UNSPECIFIED;
+        let checks = SanityChecks::new(SanityPolicy::Drop, 3_600);
+        let mut msg = Message::new(uns, uns, uns, uns, &[1, 2, 3, 4, 5, 6]);
+        msg.set_xid(1);
+        assert_eq!(checks.check(&msg), None);
+
+        msg.set_xid(0);
+        assert_eq!(checks.check(&msg), Some(Insane::ZeroXid));
+        msg.set_xid(1);
+
+        msg.set_secs(3_601);
+        assert_eq!(checks.check(&msg), Some(Insane::Secs));
+        assert_eq!(SanityChecks::new(SanityPolicy::Drop, 0).check(&msg), None);
+        msg.set_secs(0);
+
+        // 8 byte chaddr on ethernet
+        let mut msg = Message::new(uns, uns, uns, uns, &[1; 8]);
+        msg.set_xid(1);
+        assert_eq!(checks.check(&msg), Some(Insane::Hlen));
+
+        let mut msg = Message::new(uns, uns, uns, uns, &[0; 6]);
+        msg.set_xid(1);
+        assert_eq!(checks.check(&msg), Some(Insane::NoClientId));
+        msg.opts_mut()
+            .insert(DhcpOption::ClientIdentifier(vec![1, 2, 3]));
+        assert_eq!(checks.check(&msg), None);
+    }
+
+    #[test]
+    fn test_loop_protection() {
+        let uns = Ipv4Addr::UNSPECIFIED;
+        let ours = [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff];
+        let lp = LoopProtection::new(
+            [ours, [0; 6]],
+            [Ipv4Addr::new(192, 168, 0, 1), uns],
+            Duration::from_secs(60),
+        );
+        let msg = Message::new(uns, uns, uns, uns, &ours);
+        assert_eq!(lp.check(&msg), Some(Looped::Chaddr));
+
+        let mut msg = Message::new(uns, uns, uns, uns, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(lp.check(&msg), None);
+        // all-zero MACs & addresses are never ours
+        let zero = Message::new(uns, uns, uns, uns, &[0; 6]);
+        assert_eq!(lp.check(&zero), None);
+
+        let mut id = vec![1];
+        id.extend(ours);
+        msg.opts_mut().insert(DhcpOption::ClientIdentifier(id));
+        assert_eq!(lp.check(&msg), Some(Looped::ClientId));
+        msg.opts_mut()
+            .insert(DhcpOption::ClientIdentifier(ours.to_vec()));
+        assert_eq!(lp.check(&msg), Some(Looped::ClientId));
+
+        let msg = Message::new([192, 168, 0, 1].into(), uns, uns, uns, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(lp.check(&msg), Some(Looped::ServerId));
+    }
+
+    #[test]
+    fn test_load_balance() {
+        let ids = (0..=255u8)
+            .map(|i| vec![1, 2, 3, 4, 5, i])
+            .collect::<Vec<_>>();
+        let primary = LoadBalance::new(true, 128, 3);
+        let secondary = LoadBalance::new(false, 128, 3);
+        // every client is served by exactly one of the pair
+        assert!(ids
+            .iter()
+            .all(|id| primary.serves(id, 0) != secondary.serves(id, 0)));
+        // both answer once secs reaches max_secs
+        assert!(ids
+            .iter()
+            .all(|id| primary.serves(id, 3) && secondary.serves(id, 3)));
+        // split of 256 is all primary
+        let all = LoadBalance::new(true, 256, 0);
+        assert!(ids.iter().all(|id| all.serves(id, 0)));
+        assert!(LoadBalance::try_from(wire::LoadBalance {
+            role: wire::LoadBalanceRole::Primary,
+            split: 257,
+            max_secs: 3,
+        })
+        .is_err());
+    }
+
+    // test we can decode from wire
+    #[test]
+    fn test_sample() {
+        let cfg = Config::new(SAMPLE_YAML).unwrap();
+        // test a range decoded properly
+        let net = cfg.network([192, 168, 0, 1]).unwrap();
+        assert_eq!(net.ranges()[0].start(), Ipv4Addr::from([192, 168, 0, 100]));
+        assert_eq!(
+            net.ranges()[0].opts().get(v4::OptionCode::Router),
             Some(&v4::DhcpOption::Router(vec![Ipv4Addr::from([
                 192, 168, 0, 1
             ])]))
@@ -706,10 +2016,14 @@ mod tests {
                 default: Duration::from_secs(5),
                 min: Duration::from_secs(3),
                 max: Duration::from_secs(10),
+                renew_percent: None,
+                rebind_percent: None,
             },
             opts: DhcpOptions::new(),
-            exclude: HashSet::new(),
+            exclude: Exclusions::default(),
             class: Some("foo".to_owned()),
+            boot: Boot::default(),
+            restricted: Vec::new(),
         };
         // class matches
         assert!(range.match_class(Some(&["foo".to_owned()])));
@@ -724,10 +2038,14 @@ mod tests {
                 default: Duration::from_secs(5),
                 min: Duration::from_secs(3),
                 max: Duration::from_secs(10),
+                renew_percent: None,
+                rebind_percent: None,
             },
             opts: DhcpOptions::new(),
-            exclude: HashSet::new(),
+            exclude: Exclusions::default(),
             class: None,
+            boot: Boot::default(),
+            restricted: Vec::new(),
         };
         // no classes to match -> true
         assert!(range.match_class(None));
@@ -747,7 +2065,7 @@ mod tests {
         // get matching classes
         // TODO: what should we do if there is an error processing client classes?
         let matched = cfg
-            .eval_client_classes(&msg, PacketDetails::default())
+            .eval_client_classes(&msg, PacketDetails::default(), None)
             .unwrap()
             .ok();
         assert_eq!(
@@ -760,6 +2078,15 @@ mod tests {
                 .iter()
                 .collect::<std::collections::HashSet<_>>()
         );
+        // metrics are counted by class name, built-in classes are left out
+        assert_eq!(
+            cfg.classes()
+                .unwrap()
+                .configured(matched.as_deref().unwrap())
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>(),
+            vec!["my_class"]
+        );
         let net = cfg
             .range([10, 0, 0, 1], [10, 0, 0, 100], matched.as_deref())
             .unwrap();
@@ -776,7 +2103,7 @@ mod tests {
             .insert(DhcpOption::Hostname("hostname".to_owned()));
 
         let matched = cfg
-            .eval_client_classes(&msg, PacketDetails::default())
+            .eval_client_classes(&msg, PacketDetails::default(), None)
             .unwrap()
             .ok();
         // if a_class matches, other classes using `member` will eval to true also
@@ -794,30 +2121,98 @@ mod tests {
 
     #[test]
     fn test_range_lease_time() {
+        let lease = LeaseTime {
+            default: Duration::from_secs(5),
+            min: Duration::from_secs(3),
+            max: Duration::from_secs(10),
+            renew_percent: None,
+            rebind_percent: None,
+        };
         let range = NetRange::new(
             Ipv4Addr::new(192, 168, 0, 1)..=Ipv4Addr::new(192, 168, 0, 100),
-            LeaseTime {
-                default: Duration::from_secs(5),
-                min: Duration::from_secs(3),
-                max: Duration::from_secs(10),
-            },
+            lease,
         );
 
-        // selects max
+        // selects max, T1 & T2 aren't configured so they aren't sent
         let (lease, renew, rebind) = range.lease().determine_lease(Some(Duration::from_secs(11)));
         assert_eq!(lease.as_secs(), 10);
-        assert_eq!(renew.as_secs(), 5);
-        assert_eq!(rebind.as_secs(), (10 * 7 / 8));
+        assert_eq!((renew, rebind), (None, None));
         // selects min
-        let (lease, renew, rebind) = range.lease().determine_lease(Some(Duration::from_secs(2)));
+        let (lease, _, _) = range.lease().determine_lease(Some(Duration::from_secs(2)));
         assert_eq!(lease.as_secs(), 3);
-        assert_eq!(renew.as_secs(), 3 / 2);
-        assert_eq!(rebind.as_secs(), (3 * 7 / 8));
         // select default
-        let (lease, renew, rebind) = range.lease().determine_lease(None);
+        let (lease, _, _) = range.lease().determine_lease(None);
         assert_eq!(lease.as_secs(), 5);
-        assert_eq!(renew.as_secs(), 5 / 2);
-        assert_eq!(rebind.as_secs(), (5 * 7 / 8));
+
+        // percentages of whichever lease time was picked
+        let range = NetRange::new(
+            Ipv4Addr::new(192, 168, 0, 1)..=Ipv4Addr::new(192, 168, 0, 100),
+            range.lease().with_renewal(Some(25), Some(60)),
+        );
+        let (lease, renew, rebind) = range.lease().determine_lease(Some(Duration::from_secs(11)));
+        assert_eq!(lease.as_secs(), 10);
+        assert_eq!(renew, Some(Duration::from_millis(2500)));
+        assert_eq!(rebind, Some(Duration::from_secs(6)));
+        let (_, renew, rebind) = range.lease().determine_lease(None);
+        assert_eq!(renew, Some(Duration::from_millis(1250)));
+        assert_eq!(rebind, Some(Duration::from_secs(3)));
+        // only T1 set
+        let lease = range.lease().with_renewal(Some(25), None);
+        assert_eq!(
+            lease.renewal(Duration::from_secs(100)),
+            (Some(Duration::from_secs(25)), None)
+        );
+    }
+
+    #[test]
+    fn test_renewal_percent_validation() {
+        let cfg = |yaml: &str| {
+            serde_yaml::from_str::<wire::v4::NetworkConfig>(&format!(
+                "lease_time:\n  default: 3600\n{yaml}"
+            ))
+            .unwrap()
+        };
+        assert!(cfg("").validate().is_ok());
+        assert!(cfg("renew_percent: 25\nrebind_percent: 50")
+            .validate()
+            .is_ok());
+        assert!(cfg("renew_percent: 80").validate().is_ok());
+        assert!(cfg("rebind_percent: 60").validate().is_ok());
+        // T1 must be before T2, including the 87.5% default
+        assert!(cfg("renew_percent: 50\nrebind_percent: 50")
+            .validate()
+            .is_err());
+        assert!(cfg("renew_percent: 90").validate().is_err());
+        assert!(cfg("rebind_percent: 40").validate().is_err());
+        // T2 before the lease ends
+        assert!(cfg("renew_percent: 50\nrebind_percent: 100")
+            .validate()
+            .is_err());
+        assert!(cfg("renew_percent: 0").validate().is_err());
+
+        let lease = LeaseTime::from(cfg("renew_percent: 25\nrebind_percent: 50"));
+        let (_, renew, rebind) = lease.determine_lease(None);
+        assert_eq!(renew, Some(Duration::from_secs(900)));
+        assert_eq!(rebind, Some(Duration::from_secs(1800)));
+    }
+
+    #[test]
+    fn test_infinite_lease_time() {
+        let range = NetRange::from(wire::v4::IpRange {
+            range: Ipv4Addr::new(192, 168, 0, 1)..=Ipv4Addr::new(192, 168, 0, 100),
+            options: wire::v4::Options::default(),
+            config: serde_yaml::from_str("lease_time:\n  default: 3600\ninfinite: true").unwrap(),
+            except: vec![],
+            class: None,
+            boot: Boot::default(),
+            mtu: None,
+        });
+        assert!(range.lease().is_infinite());
+        // requested lease time is ignored, T1/T2 also infinite
+        let (lease, renew, rebind) = range.lease().determine_lease(Some(Duration::from_secs(60)));
+        assert_eq!(lease, crate::INFINITE_LEASE);
+        assert_eq!(renew, Some(crate::INFINITE_LEASE));
+        assert_eq!(rebind, Some(crate::INFINITE_LEASE));
     }
 
     #[test]
@@ -828,24 +2223,80 @@ mod tests {
                 default: Duration::from_secs(5),
                 min: Duration::from_secs(3),
                 max: Duration::from_secs(10),
+                renew_percent: None,
+                rebind_percent: None,
             },
-            exclude: HashSet::from([
-                [192, 168, 0, 1].into(),
-                [192, 168, 0, 2].into(),
-                [192, 168, 0, 3].into(),
-                [192, 168, 0, 4].into(),
+            exclude: Exclusions::new([
+                [192, 168, 0, 1].into()..=[192, 168, 0, 1].into(),
+                [192, 168, 0, 2].into()..=[192, 168, 0, 2].into(),
+                [192, 168, 0, 3].into()..=[192, 168, 0, 3].into(),
+                [192, 168, 0, 4].into()..=[192, 168, 0, 4].into(),
             ]),
             opts: DhcpOptions::default(),
             class: None,
+            boot: Boot::default(),
+            restricted: Vec::new(),
         };
         // excluded causes us to skip 1-4
-        assert!(range.iter().eq(Ipv4AddrRange::new(
+        assert!(range.iter().eq(ipnet::Ipv4AddrRange::new(
             [192, 168, 0, 5].into(),
             [192, 168, 0, 100].into(),
         )));
         assert_eq!(range.total_addrs(), 100 - 4);
     }
 
+    #[test]
+    fn test_exclusions() {
+        let ip = |last: u8| Ipv4Addr::new(192, 168, 0, last);
+        let exclusions = Exclusions::new([
+            ip(20)..=ip(29),
+            ip(5)..=ip(5),
+            // overlaps & extends 20-29
+            ip(25)..=ip(34),
+            // adjacent to 5
+            ip(6)..=ip(9),
+        ]);
+        assert_eq!(
+            exclusions.iter().collect::<Vec<_>>(),
+            vec![ip(5)..=ip(9), ip(20)..=ip(34)]
+        );
+        assert!(exclusions.contains(&ip(34)));
+        assert!(!exclusions.contains(&ip(35)));
+        assert_eq!(exclusions.skip(ip(4)), Some(ip(4)));
+        assert_eq!(exclusions.skip(ip(21)), Some(ip(35)));
+        assert_eq!(exclusions.count_in(&(ip(8)..=ip(22))), 5);
+
+        let range = NetRange {
+            addrs: ip(1)..=ip(40),
+            lease: LeaseTime {
+                default: Duration::from_secs(5),
+                min: Duration::from_secs(3),
+                max: Duration::from_secs(10),
+                renew_percent: None,
+                rebind_percent: None,
+            },
+            exclude: exclusions.clone(),
+            opts: DhcpOptions::default(),
+            class: None,
+            boot: Boot::default(),
+            restricted: Vec::new(),
+        };
+        let ips = range.iter().collect::<Vec<_>>();
+        assert_eq!(ips.len(), range.total_addrs());
+        assert_eq!(range.total_addrs(), 40 - 5 - 15);
+        assert!(ips.iter().all(|ip| !exclusions.contains(ip)));
+        // range ends inside an exclusion
+        assert_eq!(NetRangeIter::new(ip(18)..=ip(21), &exclusions).count(), 2);
+        assert_eq!(NetRangeIter::new(ip(21)..=ip(22), &exclusions).next(), None);
+
+        let wire: Vec<wire::v4::Exclusion> = serde_yaml::from_str(
+            "[192.168.0.5, 192.168.0.16/30, {start: 192.168.0.40, end: 192.168.0.41}]",
+        )
+        .unwrap();
+        let exclusions = Exclusions::new(wire.into_iter().map(Into::into));
+        assert_eq!(exclusions.count_in(&(ip(0)..=ip(255))), 1 + 4 + 2);
+    }
+
     #[test]
     fn test_big_range() {
         let range = NetRange::new(
@@ -854,6 +2305,8 @@ mod tests {
                 default: Duration::from_secs(5),
                 min: Duration::from_secs(3),
                 max: Duration::from_secs(10),
+                renew_percent: None,
+                rebind_percent: None,
             },
         );
         assert_eq!(range.iter().count(), 256 * 4);
@@ -868,9 +2321,12 @@ mod tests {
                 default: Duration::from_secs(5),
                 min: Duration::from_secs(3),
                 max: Duration::from_secs(10),
+                renew_percent: None,
+                rebind_percent: None,
             },
             opts: DhcpOptions::default(),
             class: None,
+            boot: Boot::default(),
         };
         // another value just to make sure we select the right one
         let mut another = res.clone();
@@ -899,4 +2355,576 @@ mod tests {
             .unwrap();
         assert_eq!(res.ip, Ipv4Addr::new(192, 168, 0, 120));
     }
+
+    #[test]
+    fn test_reserved_hostname() {
+        let res = Reserved {
+            ip: [192, 168, 0, 120].into(),
+            lease: LeaseTime {
+                default: Duration::from_secs(5),
+                min: Duration::from_secs(3),
+                max: Duration::from_secs(10),
+                renew_percent: None,
+                rebind_percent: None,
+            },
+            opts: DhcpOptions::default(),
+            class: None,
+            boot: Boot::default(),
+        };
+        let net = Network {
+            subnet: "192.168.0.0/24".parse().unwrap(),
+            reserved_hostnames: HashMap::from([("printer-3".to_owned(), res)]),
+            ..Default::default()
+        };
+        let mut opts = DhcpOptions::default();
+        opts.insert(DhcpOption::Hostname("Printer-3\0".to_owned()));
+        let res = net.search_reserved_opt(&opts, None).unwrap();
+        assert_eq!(res.ip, Ipv4Addr::new(192, 168, 0, 120));
+
+        opts.insert(DhcpOption::Hostname("printer-4".to_owned()));
+        assert!(net.search_reserved_opt(&opts, None).is_none());
+    }
+
+    #[test]
+    fn test_reserved_user_class() {
+        let res = Reserved {
+            ip: [192, 168, 0, 130].into(),
+            lease: LeaseTime {
+                default: Duration::from_secs(5),
+                min: Duration::from_secs(3),
+                max: Duration::from_secs(10),
+                renew_percent: None,
+                rebind_percent: None,
+            },
+            opts: DhcpOptions::default(),
+            class: None,
+            boot: Boot::default(),
+        };
+        let net = Network {
+            subnet: "192.168.0.0/24".parse().unwrap(),
+            reserved_user_classes: HashMap::from([("pxe-prov".to_owned(), res)]),
+            ..Default::default()
+        };
+        let user_class = |data: &[u8]| {
+            let mut opts = DhcpOptions::default();
+            opts.insert(DhcpOption::Unknown(v4::UnknownOption::new(
+                OptionCode::from(77),
+                data.to_vec(),
+            )));
+            opts
+        };
+        // any of the classes in the list matches
+        let opts = user_class(b"\x04iPXE\x08pxe-prov");
+        let res = net.search_reserved_opt(&opts, None).unwrap();
+        assert_eq!(res.ip, Ipv4Addr::new(192, 168, 0, 130));
+        assert!(net.has_reservation(&[1, 2, 3, 4, 5, 6], &opts));
+        // a single class without a length
+        assert!(net
+            .search_reserved_opt(&user_class(b"pxe-prov"), None)
+            .is_some());
+
+        let opts = user_class(b"\x04iPXE");
+        assert!(net.search_reserved_opt(&opts, None).is_none());
+        assert!(!net.has_reservation(&[1, 2, 3, 4, 5, 6], &opts));
+    }
+
+    #[test]
+    fn test_relays() {
+        let relays: wire::v4::Relays = serde_yaml::from_str(
+            "giaddrs:\n  - 10.0.0.1\n  - 10.1.0.0/16\nremote_ids:\n  - switch-1\n  - 0xaabb\n",
+        )
+        .unwrap();
+        let acl = RelayAcl::from(relays);
+        let uns = Ipv4Addr::UNSPECIFIED;
+        let remote_id = |id: &[u8]| {
+            let mut info = relay::RelayAgentInformation::default();
+            info.insert(relay::RelayInfo::AgentRemoteId(id.to_vec()));
+            DhcpOption::RelayAgentInformation(info)
+        };
+
+        // not relayed
+        let msg = Message::new(uns, uns, uns, uns, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(acl.check(&msg), None);
+
+        let mut msg = Message::new(
+            uns,
+            uns,
+            uns,
+            Ipv4Addr::new(10, 2, 0, 1),
+            &[1, 2, 3, 4, 5, 6],
+        );
+        assert_eq!(acl.check(&msg), Some(RelayDenied::Giaddr));
+        msg.set_giaddr(Ipv4Addr::new(10, 1, 5, 1));
+        assert_eq!(acl.check(&msg), Some(RelayDenied::RemoteId));
+        msg.opts_mut().insert(remote_id(b"switch-2"));
+        assert_eq!(acl.check(&msg), Some(RelayDenied::RemoteId));
+        msg.opts_mut().insert(remote_id(b"switch-1"));
+        assert_eq!(acl.check(&msg), None);
+        msg.set_giaddr(Ipv4Addr::new(10, 0, 0, 1));
+        msg.opts_mut().insert(remote_id(&[0xaa, 0xbb]));
+        assert_eq!(acl.check(&msg), None);
+
+        // an empty list allows any relay
+        msg.set_giaddr(Ipv4Addr::new(172, 16, 0, 1));
+        assert_eq!(RelayAcl::default().check(&msg), None);
+    }
+
+    #[test]
+    fn test_oui_limits() {
+        let yaml = |limits: &str| {
+            format!(
+                r#"
+networks:
+    10.0.0.0/24:
+        ranges:
+            -
+                start: 10.0.0.10
+                end: 10.0.0.20
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        1:
+                            type: ip
+                            value: 255.255.255.0
+        oui_limits:
+{limits}
+"#
+            )
+        };
+        let cfg = Config::new(yaml(
+            "            - oui: \"00:11:22\"\n              max_leases: 2\n            - oui: aa-bb-cc\n              max_leases: 0",
+        ))
+        .unwrap();
+        let net = cfg.network([10, 0, 0, 1]).unwrap();
+        assert_eq!(
+            net.oui_limit(&[0, 0x11, 0x22, 1, 2, 3]),
+            Some((Oui([0, 0x11, 0x22]), 2))
+        );
+        assert_eq!(
+            net.oui_limit(&[0xaa, 0xbb, 0xcc, 1, 2, 3]),
+            Some((Oui([0xaa, 0xbb, 0xcc]), 0))
+        );
+        assert_eq!(net.oui_limit(&[0, 0x11, 0x23, 1, 2, 3]), None);
+        // not a MAC
+        assert_eq!(net.oui_limit(&[0, 0x11, 0x22]), None);
+        assert_eq!(Oui([0xaa, 0xbb, 0xcc]).to_string(), "aa:bb:cc");
+
+        assert!(Config::new(yaml(
+            "            - oui: \"00:11:22:33\"\n              max_leases: 2"
+        ))
+        .is_err());
+        assert!(Config::new(yaml(
+            "            - oui: \"00:11:22\"\n              max_leases: 2\n            - oui: \"001122\"\n              max_leases: 3"
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_authoritative() {
+        let yaml = |global: &str, net: &str| {
+            format!(
+                r#"
+{global}
+networks:
+    10.0.0.0/24:
+{net}
+        ranges:
+            -
+                start: 10.0.0.10
+                end: 10.0.0.20
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        1:
+                            type: ip
+                            value: 255.255.255.0
+"#
+            )
+        };
+        let authoritative = |global: &str, net: &str| {
+            Config::new(yaml(global, net))
+                .unwrap()
+                .network([10, 0, 0, 1])
+                .unwrap()
+                .authoritative()
+        };
+        let net_true = "        authoritative: true";
+        let net_false = "        authoritative: false";
+        // neither set
+        assert!(authoritative("", ""));
+        // global default only
+        assert!(!authoritative("authoritative: false", ""));
+        assert!(authoritative("authoritative: true", ""));
+        // network only
+        assert!(!authoritative("", net_false));
+        assert!(authoritative("", net_true));
+        // the network overrides the global default
+        assert!(authoritative("authoritative: false", net_true));
+        assert!(!authoritative("authoritative: true", net_false));
+    }
+
+    #[test]
+    fn test_mtu() {
+        let yaml = |range_opts: &str| {
+            format!(
+                r#"
+networks:
+    10.0.0.0/24:
+        mtu: 1400
+        ranges:
+            -
+                start: 10.0.0.10
+                end: 10.0.0.20
+                mtu: 9000
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        1:
+                            type: ip
+                            value: 255.255.255.0
+            -
+                start: 10.0.0.100
+                end: 10.0.0.120
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+{range_opts}
+        reservations:
+            -
+                ip: 10.0.0.50
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        1:
+                            type: ip
+                            value: 255.255.255.0
+                match:
+                    chaddr: aa:bb:cc:dd:ee:ff
+"#
+            )
+        };
+        let indent = " ".repeat(24);
+        let opt = |code: u8, ty: &str, value: &str| {
+            format!("{indent}{code}:\n{indent}    type: {ty}\n{indent}    value: {value}\n")
+        };
+        let mask = opt(1, "ip", "255.255.255.0");
+        let mtu = |opts: &DhcpOptions| match opts.get(OptionCode::InterfaceMtu) {
+            Some(DhcpOption::InterfaceMtu(mtu)) => Some(*mtu),
+            _ => None,
+        };
+
+        let cfg = Config::new(yaml(&mask)).unwrap();
+        let net = cfg.network([10, 0, 0, 1]).unwrap();
+        assert_eq!(net.mtu(), Some(1400));
+        // the range's own mtu wins, the other range & the reservation inherit
+        assert_eq!(mtu(net.ranges()[0].opts()), Some(9000));
+        assert_eq!(mtu(net.ranges()[1].opts()), Some(1400));
+        let res = net
+            .get_reserved_mac("aa:bb:cc:dd:ee:ff".parse().unwrap(), None)
+            .unwrap();
+        assert_eq!(mtu(res.opts()), Some(1400));
+
+        // a hand-encoded opt 26 still overrides the network
+        let cfg = Config::new(yaml(&opt(26, "u16", "1500"))).unwrap();
+        let net = cfg.network([10, 0, 0, 1]).unwrap();
+        assert_eq!(mtu(net.ranges()[1].opts()), Some(1500));
+        // but too small or the wrong size is an error
+        assert!(Config::new(yaml(&opt(26, "u16", "60"))).is_err());
+        assert!(Config::new(yaml(&opt(26, "u32", "1500"))).is_err());
+        assert!(Config::new(yaml(&mask).replace("mtu: 1400", "mtu: 40")).is_err());
+    }
+
+    #[test]
+    fn test_boot() {
+        let yaml = |server_name: &str| {
+            format!(
+                r#"
+networks:
+    10.0.0.0/24:
+        next_server: 10.0.0.5
+        file_name: "net.efi"
+        ranges:
+            -
+                start: 10.0.0.10
+                end: 10.0.0.20
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        1:
+                            type: ip
+                            value: 255.255.255.0
+                file_name: "range.efi"
+client_classes:
+    v4:
+        -
+            name: pxe
+            assert: "option[60].exists"
+            next_server: 10.0.0.6
+            server_name: "{server_name}"
+"#
+            )
+        };
+        let cfg = Config::new(yaml("tftp.example.org")).unwrap();
+        let net = cfg.network([10, 0, 0, 1]).unwrap();
+        assert_eq!(net.next_server(), Some([10, 0, 0, 5].into()));
+
+        // network only
+        let boot = cfg.boot(Some(net), None);
+        assert_eq!(&boot, net.boot());
+        // class overrides the network, unset class fields fall back to it
+        let boot = cfg.boot(Some(net), Some(&["pxe".to_owned()]));
+        assert_eq!(
+            boot,
+            Boot {
+                next_server: Some([10, 0, 0, 6].into()),
+                server_name: Some("tftp.example.org".to_owned()),
+                file_name: Some("net.efi".to_owned()),
+            }
+        );
+        // range overrides both
+        let range = net.range(Ipv4Addr::new(10, 0, 0, 10), None).unwrap();
+        assert_eq!(
+            range.boot().or(&boot).file_name.as_deref(),
+            Some("range.efi")
+        );
+
+        let uns = Ipv4Addr::UNSPECIFIED;
+        let mut msg = v4::Message::new(uns, uns, [10, 0, 0, 1].into(), uns, &[1, 2, 3, 4, 5, 6]);
+        range.boot().apply(&mut msg);
+        // range doesn't set next_server, siaddr is left alone
+        assert_eq!(msg.siaddr(), Ipv4Addr::new(10, 0, 0, 1));
+        boot.apply(&mut msg);
+        assert_eq!(msg.siaddr(), Ipv4Addr::new(10, 0, 0, 6));
+
+        // sname is 64 bytes with the null terminator
+        assert!(Config::new(yaml(&"a".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn test_class_restrictions() {
+        let yaml = |drop: &str| {
+            format!(
+                r#"
+networks:
+    10.0.0.0/24:
+        ranges:
+            -
+                start: 10.0.0.10
+                end: 10.0.0.20
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        1:
+                            type: ip
+                            value: 255.255.255.0
+            -
+                start: 10.0.0.100
+                end: 10.0.0.120
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        1:
+                            type: ip
+                            value: 255.255.255.0
+    10.0.1.0/24:
+        ranges:
+            -
+                start: 10.0.1.10
+                end: 10.0.1.20
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        1:
+                            type: ip
+                            value: 255.255.255.0
+client_classes:
+    v4:
+        -
+            name: phones
+            assert: "option[60].exists"
+            allow:
+                - 10.0.0.96/27
+        -
+            name: guests
+            assert: "option[12].exists"
+            deny:
+                - 10.0.0.0/24
+        -
+            name: DROP
+            assert: "option[77].exists"
+{drop}
+"#
+            )
+        };
+        let cfg = Config::new(yaml("")).unwrap();
+        let net = cfg.network([10, 0, 0, 1]).unwrap();
+        let starts = |classes: Option<&[String]>| {
+            net.ranges_with_class(classes)
+                .map(|r| r.start())
+                .collect::<Vec<_>>()
+        };
+        let phones = ["phones".to_owned()];
+        let guests = ["guests".to_owned()];
+        let both = ["phones".to_owned(), "guests".to_owned()];
+        // unrestricted
+        assert_eq!(
+            starts(None),
+            vec![Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 100)]
+        );
+        // only the allowed range
+        assert_eq!(starts(Some(&phones)), vec![Ipv4Addr::new(10, 0, 0, 100)]);
+        assert_eq!(net.range(Ipv4Addr::new(10, 0, 0, 10), Some(&phones)), None);
+        // the whole network is denied
+        assert!(starts(Some(&guests)).is_empty());
+        // restrictions from every matched class apply
+        assert!(starts(Some(&both)).is_empty());
+        // phones are kept out of the other network too, guests aren't
+        let other = cfg.network([10, 0, 1, 1]).unwrap();
+        assert_eq!(other.ranges_with_class(Some(&phones)).count(), 0);
+        assert_eq!(other.ranges_with_class(Some(&guests)).count(), 1);
+        assert_eq!(other.ranges()[0].restricted(), &phones[..]);
+
+        // DROP clients never get a response, so restricting them is a config error
+        assert!(Config::new(yaml("            deny: [10.0.1.0/24]")).is_err());
+    }
+
+    #[test]
+    fn test_fill_order() {
+        let lease = LeaseTime {
+            default: Duration::from_secs(3600),
+            min: Duration::from_secs(3600),
+            max: Duration::from_secs(3600),
+            renew_percent: None,
+            rebind_percent: None,
+        };
+        let range = |start: u8, end: u8| {
+            NetRange::new(
+                Ipv4Addr::new(10, 0, 0, start)..=Ipv4Addr::new(10, 0, 0, end),
+                lease,
+            )
+        };
+        // 10 addrs with 5 used, 100 with 20 used, 50 with 25 used
+        let ranges = [range(1, 10), range(101, 200), range(201, 250)];
+        let used = vec![(&ranges[0], 5), (&ranges[1], 20), (&ranges[2], 25)];
+        let starts = |fill: Fill| {
+            fill.order(used.clone())
+                .iter()
+                .map(|range| range.start().octets()[3])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(starts(Fill::Sequential), [1, 101, 201]);
+        // 20% before 50% & 50%, which keep config order
+        assert_eq!(starts(Fill::Fair), [101, 1, 201]);
+        // 80 free, 25 free, 5 free
+        assert_eq!(starts(Fill::MostFreeFirst), [101, 201, 1]);
+
+        let cfg = Config::new(
+            r#"
+networks:
+    10.0.0.0/24:
+        fill: most_free_first
+        ranges:
+            -
+                start: 10.0.0.10
+                end: 10.0.0.20
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values: {}
+"#,
+        )
+        .unwrap();
+        let net = cfg.network([10, 0, 0, 1]).unwrap();
+        assert_eq!(net.fill(), Fill::MostFreeFirst);
+        assert!(Config::new(
+            r#"
+networks:
+    10.0.0.0/24:
+        fill: random
+"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_class_transforms() {
+        let yaml = |transforms: &str| {
+            format!(
+                r#"
+networks: {{}}
+client_classes:
+    v4:
+        -
+            name: cameras
+            assert: "option[60].exists"
+            transforms:
+                - remove_option: 43
+                - max_lease_time: 3600
+        -
+            name: old_cameras
+            assert: "option[12].exists"
+            transforms:
+{transforms}
+"#
+            )
+        };
+        let cfg = Config::new(yaml("                - broadcast\n                - remove_option: 119\n                - max_lease_time: 600")).unwrap();
+        assert_eq!(cfg.transforms(None), None);
+        assert_eq!(cfg.transforms(Some(&["other".to_owned()])), None);
+        assert_eq!(
+            cfg.transforms(Some(&["cameras".to_owned()])),
+            Some(RespTransforms {
+                insert: Vec::new(),
+                remove: vec![OptionCode::VendorExtensions],
+                max_lease: Some(Duration::from_secs(3600)),
+                broadcast: false,
+            })
+        );
+        // every removed option, the lowest lease time & broadcast from either class
+        assert_eq!(
+            cfg.transforms(Some(&["old_cameras".to_owned(), "cameras".to_owned()])),
+            Some(RespTransforms {
+                insert: Vec::new(),
+                remove: vec![OptionCode::VendorExtensions, OptionCode::DomainSearch],
+                max_lease: Some(Duration::from_secs(600)),
+                broadcast: true,
+            })
+        );
+
+        assert!(Config::new(yaml("                - remove_option: 53")).is_err());
+        assert!(Config::new(yaml("                - max_lease_time: 0")).is_err());
+        assert!(Config::new(yaml("                - clamp_lease_time: 60")).is_err());
+    }
+
+    #[test]
+    fn test_has_reservation() {
+        let cfg = Config::new(SAMPLE_YAML).unwrap();
+        let net = cfg.network([192, 168, 0, 1]).unwrap();
+        let opts = DhcpOptions::default();
+        assert!(net.has_reservation(&hex::decode("aabbccddeeff").unwrap(), &opts));
+        assert!(!net.has_reservation(&hex::decode("aabbccddee00").unwrap(), &opts));
+
+        let mut opts = DhcpOptions::default();
+        opts.insert(DhcpOption::Hostname("PRINTER-3".to_owned()));
+        assert!(net.has_reservation(&hex::decode("aabbccddee00").unwrap(), &opts));
+    }
 }
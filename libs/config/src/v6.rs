@@ -2,7 +2,8 @@ use hex;
 use std::{
     collections::HashMap,
     net::Ipv6Addr,
-    path::Path,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
     str::FromStr,
     time::{Duration, SystemTime},
 };
@@ -12,15 +13,17 @@ use dora_core::{
     anyhow::Result,
     dhcproto::{
         v4::HType,
-        v6::{duid::Duid, DhcpOptions},
+        v6::{self, duid::Duid, DhcpOptions},
     },
     pnet::ipnetwork::{IpNetwork, Ipv6Network},
     pnet::{self, datalink::NetworkInterface},
 };
 use ipnet::Ipv6Net;
-use tracing::debug;
+use serde::Serialize;
+use tracing::{debug, warn};
 
 use crate::{
+    client_classes::ClientClassesV6,
     generate_random_bytes,
     wire::{self, v6::ServerDuidInfo},
     LeaseTime, PersistIdentifier,
@@ -41,6 +44,20 @@ pub struct Config {
     /// used to make a selection on which network or subnet to use
     networks: HashMap<Ipv6Net, Network>,
     server_id: Duid,
+    /// where the server id is persisted, `None` if `persist` is false
+    server_id_path: Option<PathBuf>,
+    /// the `server_id` section of the config, if there is one
+    server_id_info: Option<ServerDuidInfo>,
+    /// link local address used for LLT/LL DUIDs without an explicit identifier
+    link_local: Ipv6Addr,
+    /// `client_classes.v6`
+    client_classes: Option<ClientClassesV6>,
+    /// rapid commit for interfaces without an override
+    rapid_commit: bool,
+    /// `interface_overrides` by interface name
+    interface_overrides: HashMap<String, wire::v6::InterfaceOverride>,
+    /// RFC 9686 address registration, `None` if it's off
+    addr_registration: Option<wire::v6::AddrRegistration>,
 }
 
 impl Config {
@@ -48,6 +65,44 @@ impl Config {
     pub fn server_id(&self) -> &[u8] {
         self.server_id.as_ref()
     }
+    /// path of the persisted server id file, `None` if the server id is not persisted
+    pub fn server_id_path(&self) -> Option<&Path> {
+        self.server_id_path.as_deref()
+    }
+    /// read the persisted server id file, `None` if it doesn't exist. This may differ from
+    /// [`server_id`](Self::server_id) if the file was rotated since the server started
+    pub fn persisted_server_id(&self) -> Result<Option<PersistIdentifier>> {
+        match self.server_id_path() {
+            Some(path) if path.exists() => Ok(Some(
+                PersistIdentifier::from_json(path)
+                    .context("can not read server identifier json")?,
+            )),
+            _ => Ok(None),
+        }
+    }
+    /// Generate a new server DUID and persist it, the running server keeps using the
+    /// current DUID until it is restarted. If `info` is `None` the configured `server_id`
+    /// section is used (LLT if there is none)
+    pub fn rotate_server_id(&self, info: Option<&ServerDuidInfo>) -> Result<DuidRotation> {
+        let path = self
+            .server_id_path()
+            .context("server_id is not persisted, change the server_id config instead")?;
+        let default = ServerDuidInfo::LLT {
+            htype: 0,
+            time: 0,
+            identifier: String::new(),
+        };
+        let info = info.or(self.server_id_info.as_ref()).unwrap_or(&default);
+        let mut rotation = rotate_duid(info, self.link_local, path)?;
+        if matches!(&self.server_id_info, Some(cfg_info) if cfg_info != info) {
+            rotation.warnings.push(
+                "DUID type/params differ from the server_id config, the persisted DUID will be \
+                 regenerated from the config on the next start unless the config is updated"
+                    .to_owned(),
+            );
+        }
+        Ok(rotation)
+    }
     /// return the optional explicitly bound interfaces if there are any
     pub fn interfaces(&self) -> &[NetworkInterface] {
         self.interfaces.as_slice()
@@ -83,6 +138,18 @@ impl Config {
                 .collect()
         })
     }
+    /// whether a Solicit received on `iface_index` with the Rapid Commit option gets a
+    /// Reply (RFC 8415 18.3.1). An interface override wins over the `v6` setting
+    pub fn rapid_commit(&self, iface_index: u32) -> bool {
+        self.find_interface(iface_index)
+            .and_then(|int| self.interface_overrides.get(&int.name))
+            .and_then(|o| o.rapid_commit)
+            .unwrap_or(self.rapid_commit)
+    }
+    /// RFC 9686 address registration settings, `None` if ADDR-REG-INFORM isn't answered
+    pub fn addr_registration(&self) -> Option<&wire::v6::AddrRegistration> {
+        self.addr_registration.as_ref()
+    }
     // find the interface at the index `iface_index`
     fn find_interface(&self, iface_index: u32) -> Option<&NetworkInterface> {
         self.interfaces.iter().find(|e| e.index == iface_index)
@@ -104,6 +171,25 @@ impl Config {
         })
     }
 
+    /// get the `Network` whose prefix contains `link`, the longest prefix wins if networks
+    /// are nested
+    pub fn get_network_by_link(&self, link: Ipv6Addr) -> Option<&Network> {
+        self.networks
+            .iter()
+            .filter(|(subnet, _)| subnet.contains(&link))
+            .max_by_key(|(subnet, _)| subnet.prefix_len())
+            .map(|(_, network)| network)
+    }
+
+    /// select the `Network` for a message, by the relay `link` address if there is one,
+    /// otherwise by the receiving interface
+    pub fn select_network(&self, iface_index: u32, link: Option<Ipv6Addr>) -> Option<&Network> {
+        match link.filter(|link| !link.is_unspecified()) {
+            Some(link) => self.get_network_by_link(link),
+            None => self.get_network(iface_index),
+        }
+    }
+
     /// gets options (which have been already merged with global opts) for the network of `iface_index` or the global options
     pub fn get_opts(&self, iface_index: u32) -> Option<&DhcpOptions> {
         self.get_network(iface_index)
@@ -111,6 +197,46 @@ impl Config {
             .or(self.opts.as_ref())
     }
 
+    /// options for a client, the same precedence as v4: options of the selected network
+    /// (already merged with global opts) or the global options, with anything they don't set
+    /// filled in from the matched client classes
+    pub fn collect_opts(
+        &self,
+        iface_index: u32,
+        link: Option<Ipv6Addr>,
+        matched_classes: Option<&[String]>,
+    ) -> Option<DhcpOptions> {
+        let opts = self
+            .select_network(iface_index, link)
+            .map(|n| n.opts())
+            .or(self.opts.as_ref());
+        let class_opts = self
+            .client_classes
+            .as_ref()
+            .and_then(|classes| classes.collect_opts(matched_classes));
+        match (opts, class_opts) {
+            (Some(opts), Some(class_opts)) => Some(merge_opts(opts, &class_opts)),
+            (Some(opts), None) => Some(opts.clone()),
+            (None, class_opts) => class_opts,
+        }
+    }
+
+    /// evaluate `client_classes.v6`, `None` if there are none configured
+    pub fn eval_client_classes(&self, req: &v6::Message) -> Option<Result<Vec<String>>> {
+        self.client_classes
+            .as_ref()
+            .map(|classes| classes.eval(req))
+    }
+
+    pub fn classes(&self) -> Option<&ClientClassesV6> {
+        self.client_classes.as_ref()
+    }
+
+    pub(crate) fn with_client_classes(mut self, classes: Option<ClientClassesV6>) -> Self {
+        self.client_classes = classes;
+        self
+    }
+
     /// get the first `Network`
     pub fn get_first(&self) -> Option<(&Ipv6Net, &Network)> {
         self.networks.iter().next()
@@ -118,7 +244,7 @@ impl Config {
 }
 
 /// merge `b` into `a`, favoring `a` where there are duplicates
-fn merge_opts(a: &DhcpOptions, b: DhcpOptions) -> DhcpOptions {
+pub(crate) fn merge_opts(a: &DhcpOptions, b: &DhcpOptions) -> DhcpOptions {
     let mut opts = a.clone();
     for opt in b.iter() {
         if opts.get(opt.into()).is_none() {
@@ -142,12 +268,46 @@ pub struct Network {
     probation_period: Duration,
     /// Whether we are authoritative for this network (default: true)
     authoritative: bool,
+    /// address pools on the network, all within `subnet`
+    ranges: Vec<NetRange>,
 }
 
 impl Network {
     pub fn subnet(&self) -> Ipv6Addr {
         self.subnet.network()
     }
+    /// default valid lifetime for the network
+    pub fn valid(&self) -> LeaseTime {
+        self.valid
+    }
+    /// default preferred lifetime for the network
+    pub fn preferred(&self) -> LeaseTime {
+        self.preferred
+    }
+    pub fn ranges(&self) -> &[NetRange] {
+        &self.ranges
+    }
+    /// ranges a client with `classes` may allocate from
+    pub fn ranges_with_class<'a, 'b: 'a>(
+        &'a self,
+        classes: Option<&'b [String]>,
+    ) -> impl Iterator<Item = &'a NetRange> + 'a {
+        self.ranges
+            .iter()
+            .filter(move |range| range.match_class(classes))
+    }
+    /// is the IP within the network's prefix
+    pub fn contains(&self, ip: &Ipv6Addr) -> bool {
+        self.subnet.contains(ip)
+    }
+    /// is the IP within any of the ranges on this network
+    pub fn in_range(&self, ip: Ipv6Addr) -> bool {
+        self.ranges.iter().any(|r| r.contains(&ip))
+    }
+    /// get the range that contains `ip` & matches the client classes
+    pub fn range(&self, ip: Ipv6Addr, classes: Option<&[String]>) -> Option<&NetRange> {
+        self.ranges.iter().find(|r| r.contains_class(&ip, classes))
+    }
     pub fn authoritative(&self) -> bool {
         self.authoritative
     }
@@ -169,6 +329,112 @@ impl Network {
     }
 }
 
+/// An address pool, the v6 counterpart of [`crate::v4::NetRange`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetRange {
+    addrs: RangeInclusive<Ipv6Addr>,
+    valid: LeaseTime,
+    preferred: LeaseTime,
+    opts: DhcpOptions,
+    exclude: Exclusions,
+    class: Option<String>,
+}
+
+impl NetRange {
+    pub fn new(addrs: RangeInclusive<Ipv6Addr>, valid: LeaseTime, preferred: LeaseTime) -> Self {
+        Self {
+            addrs,
+            valid,
+            preferred,
+            opts: DhcpOptions::default(),
+            exclude: Exclusions::default(),
+            class: None,
+        }
+    }
+    /// get the range of IPs this range offers
+    pub fn addrs(&self) -> RangeInclusive<Ipv6Addr> {
+        self.addrs.clone()
+    }
+    /// get the starting IP of the range
+    pub fn start(&self) -> Ipv6Addr {
+        *self.addrs.start()
+    }
+    /// get the ending IP of the range
+    pub fn end(&self) -> Ipv6Addr {
+        *self.addrs.end()
+    }
+    /// return the option parameters that should be included (if requested)
+    pub fn opts(&self) -> &DhcpOptions {
+        &self.opts
+    }
+    /// get the valid lifetime
+    pub fn valid(&self) -> LeaseTime {
+        self.valid
+    }
+    /// get the preferred lifetime
+    pub fn preferred(&self) -> LeaseTime {
+        self.preferred
+    }
+    /// returns true if the range contains a given IP
+    pub fn contains(&self, ip: &Ipv6Addr) -> bool {
+        !self.exclude.contains(ip) && self.addrs.contains(ip)
+    }
+    /// contains the IP and matches a class
+    pub fn contains_class(&self, ip: &Ipv6Addr, classes: Option<&[String]>) -> bool {
+        self.contains(ip) && self.match_class(classes)
+    }
+    /// a range with a class only matches if the class is in `classes`
+    pub fn match_class(&self, classes: Option<&[String]>) -> bool {
+        self.class
+            .as_ref()
+            .map(|name| classes.map(|c| c.contains(name)).unwrap_or(false))
+            .unwrap_or(true)
+    }
+    /// return an iterator over the range
+    pub fn iter(&self) -> NetRangeIter<'_> {
+        NetRangeIter::new(self.addrs(), &self.exclude)
+    }
+    /// returns the excluded addrs
+    pub fn exclusions(&self) -> &Exclusions {
+        &self.exclude
+    }
+    /// count the total number of addresses that could possibly be
+    /// handed out minus exclusions
+    pub fn total_addrs(&self) -> u128 {
+        let (start, end) = (u128::from(self.start()), u128::from(self.end()));
+        if start > end {
+            return 0;
+        }
+        // a full /0 doesn't fit, saturate instead
+        (end - start)
+            .saturating_add(1)
+            .saturating_sub(self.exclude.count_in(&self.addrs))
+    }
+    /// return configured class if present
+    pub fn class(&self) -> Option<&str> {
+        self.class.as_deref()
+    }
+}
+
+impl From<wire::v6::IpRange> for NetRange {
+    fn from(range: wire::v6::IpRange) -> Self {
+        NetRange {
+            addrs: range.range,
+            valid: range.config.lease_time.into(),
+            preferred: range.config.preferred_time.into(),
+            opts: range.options.get(),
+            exclude: Exclusions::new(range.except.into_iter().map(Into::into)),
+            class: range.class,
+        }
+    }
+}
+
+/// excluded v6 addresses of a range
+pub type Exclusions = crate::exclusions::Exclusions<Ipv6Addr>;
+
+/// iterates the v6 addresses of a range, jumping over excluded blocks
+pub type NetRangeIter<'a> = crate::exclusions::NetRangeIter<'a, Ipv6Addr>;
+
 // TODO: replace with is_unicast_global from std when released
 pub const fn is_unicast_global(ip: &Ipv6Addr) -> bool {
     !(ip.is_multicast()
@@ -266,6 +532,66 @@ fn generate_duid_and_persist(
     Ok(duid)
 }
 
+/// the outcome of rotating the persisted server DUID
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DuidRotation {
+    /// the DUID that was persisted before rotation, if any
+    pub previous: Option<PersistIdentifier>,
+    /// the newly persisted DUID
+    pub current: PersistIdentifier,
+    /// things to be aware of before restarting the server
+    pub warnings: Vec<String>,
+}
+
+/// Generate a new DUID from `info` and persist it at `path`. The previous file is kept
+/// with a `.bak` extension & the new file is written to a temp file then renamed, so a
+/// failed write never leaves a truncated identifier behind.
+pub fn rotate_duid(
+    info: &ServerDuidInfo,
+    link_layer: Ipv6Addr,
+    path: &Path,
+) -> Result<DuidRotation> {
+    let previous = if path.exists() {
+        Some(PersistIdentifier::from_json(path).context("can not read server identifier json")?)
+    } else {
+        None
+    };
+    let duid =
+        generate_duid_from_config(info, link_layer).context("can not generate duid from config")?;
+    let current = PersistIdentifier {
+        identifier: hex::encode(duid.as_ref()),
+        duid_config: info.clone(),
+    };
+    if matches!(&previous, Some(prev) if prev.identifier == current.identifier) {
+        bail!("generated DUID is identical to the current one, supply a different identifier");
+    }
+    if previous.is_some() {
+        std::fs::copy(path, path.with_extension("bak"))
+            .context("can not back up server identifier json")?;
+    }
+    let tmp = path.with_extension("tmp");
+    current
+        .to_json(&tmp)
+        .context("can not write server identifier json")?;
+    std::fs::rename(&tmp, path).context("can not replace server identifier json")?;
+
+    let mut warnings =
+        vec!["the running server keeps using the previous DUID until it is restarted".to_owned()];
+    if previous.is_some() {
+        warnings.push(
+            "after restart, clients will ignore replies to Renew carrying the previous server id \
+             and fall back to Rebind or Solicit, expect a spike in traffic"
+                .to_owned(),
+        );
+    }
+    Ok(DuidRotation {
+        previous,
+        current,
+        warnings,
+    })
+}
+
 impl TryFrom<wire::v6::Config> for Config {
     type Error = anyhow::Error;
 
@@ -281,6 +607,13 @@ impl TryFrom<wire::v6::Config> for Config {
                 })
             })
             .context("unable to find a link local ip")?;
+        let server_id_info = cfg.server_id.as_ref().map(|id| id.info.clone());
+        let server_id_path = match &cfg.server_id {
+            None => Some(PathBuf::from(DEFAULT_SERVER_ID_FILE_PATH)),
+            Some(id) if !id.persist => None,
+            Some(id) if id.path.is_empty() => Some(PathBuf::from(DEFAULT_SERVER_ID_FILE_PATH)),
+            Some(id) => Some(PathBuf::from(&id.path)),
+        };
         let server_id = match cfg.server_id {
             None => {
                 // if server id file exists, then use it
@@ -330,6 +663,14 @@ impl TryFrom<wire::v6::Config> for Config {
         };
         let global_opts = cfg.options;
         debug!(?interfaces, ?server_id, "v6 interfaces that will be used");
+        for name in cfg.interface_overrides.keys() {
+            if !interfaces.iter().any(|int| &int.name == name) {
+                warn!(
+                    interface = %name,
+                    "v6 interface override doesn't match any interface in use"
+                );
+            }
+        }
         let networks = cfg
             .networks
             .into_iter()
@@ -342,8 +683,26 @@ impl TryFrom<wire::v6::Config> for Config {
                     config,
                     options,
                     interfaces: net_interfaces,
+                    ranges,
                 } = net;
 
+                let ranges = ranges
+                    .into_iter()
+                    .map(|range| {
+                        if range.range.is_empty()
+                            || !subnet.contains(range.range.start())
+                            || !subnet.contains(range.range.end())
+                        {
+                            bail!(
+                                "range {:?} must be a non-empty range within network {}",
+                                range.range,
+                                subnet
+                            );
+                        }
+                        Ok(NetRange::from(range))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
                 // If any interfaces are explicitly set for the network,
                 // find them. If the interface can't be found return an error.
                 let net_interfaces = net_interfaces
@@ -381,11 +740,12 @@ impl TryFrom<wire::v6::Config> for Config {
                     probation_period: Duration::from_secs(probation_period),
                     authoritative,
                     ping_timeout_ms: Duration::from_millis(ping_timeout_ms),
-                    // merge global with network opts OR just return network options if no global exist
+                    // fill in network opts from global, network opts win on duplicates
                     options: match &global_opts {
-                        Some(a) => merge_opts(a.as_ref(), options.get()),
+                        Some(global) => merge_opts(&options.get(), global.as_ref()),
                         None => options.get(),
                     },
+                    ranges,
                 };
                 Ok((subnet, network))
             })
@@ -396,14 +756,21 @@ impl TryFrom<wire::v6::Config> for Config {
             networks,
             opts: global_opts.map(|o| o.get()),
             server_id,
+            server_id_path,
+            server_id_info,
+            link_local: link_local.ip(),
+            client_classes: None,
+            rapid_commit: cfg.rapid_commit,
+            interface_overrides: cfg.interface_overrides,
+            addr_registration: cfg.addr_registration,
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{v4::Config, PersistIdentifier};
-    use std::path::Path;
+    use crate::{v4::Config, wire::v6::ServerDuidInfo, PersistIdentifier};
+    use std::{net::Ipv6Addr, path::Path};
 
     pub static TEST_SERVER_ID_FILE_PATH: &str = "./server_id"; //can not use include_str because sometimes it doesn't exist.
     pub static CONFIG_V6_YAML: &str = include_str!("../sample/config_v6.yaml");
@@ -476,4 +843,307 @@ mod tests {
         println!("server_id: {:?}", server_id);
         assert!(!server_id_path.exists());
     }
+
+    /// test rotating a persisted server id keeps a backup & refuses identical DUIDs
+    #[test]
+    fn test_v6_rotate_duid() {
+        let path = Path::new("./server_id_rotate");
+        let en = ServerDuidInfo::EN {
+            enterprise_id: 0,
+            identifier: String::new(),
+        };
+        let first = super::rotate_duid(&en, "fe80::1".parse().unwrap(), path).unwrap();
+        assert!(first.previous.is_none());
+
+        let second = super::rotate_duid(&en, "fe80::1".parse().unwrap(), path).unwrap();
+        assert_eq!(second.previous, Some(first.current.clone()));
+        assert_ne!(second.current.identifier, first.current.identifier);
+        assert_eq!(
+            PersistIdentifier::from_json(&path.with_extension("bak")).unwrap(),
+            first.current
+        );
+        assert_eq!(PersistIdentifier::from_json(path).unwrap(), second.current);
+
+        let uuid = ServerDuidInfo::UUID {
+            identifier: "0123456789abcdef".to_owned(),
+        };
+        super::rotate_duid(&uuid, "fe80::1".parse().unwrap(), path).unwrap();
+        assert!(super::rotate_duid(&uuid, "fe80::1".parse().unwrap(), path).is_err());
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(path.with_extension("bak")).unwrap();
+    }
+
+    /// ranges parse with exclusions & classes, and count addresses without iterating
+    #[test]
+    fn test_v6_net_range() {
+        use super::{Exclusions, NetRange};
+        use crate::wire;
+
+        let range: wire::v6::IpRange = serde_yaml::from_str(
+            "
+start: 2001:db8:1::100
+end: 2001:db8:1::1ff
+config:
+    lease_time:
+        default: 3600
+    preferred_time:
+        default: 1800
+except:
+    - 2001:db8:1::100
+    - start: 2001:db8:1::110
+      end: 2001:db8:1::11f
+class: my_class
+",
+        )
+        .unwrap();
+        let range = NetRange::from(range);
+        let ip = |n: u16| std::net::Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, n);
+        assert!(!range.contains(&ip(0x100)));
+        assert!(range.contains(&ip(0x101)));
+        assert!(!range.contains(&ip(0x115)));
+        assert_eq!(range.total_addrs(), 256 - 17);
+        assert_eq!(range.iter().nth(15), Some(ip(0x120)));
+        assert_eq!(range.valid().get_default().as_secs(), 3600);
+        assert_eq!(range.preferred().get_default().as_secs(), 1800);
+
+        assert!(!range.match_class(None));
+        assert!(range.contains_class(&ip(0x101), Some(&["my_class".to_owned()])));
+
+        let exclusions = Exclusions::new([ip(5)..=ip(9), ip(8)..=ip(20), ip(21)..=ip(21)]);
+        assert_eq!(exclusions.iter().collect::<Vec<_>>(), vec![ip(5)..=ip(21)]);
+        assert_eq!(exclusions.skip(ip(6)), Some(ip(22)));
+        assert_eq!(exclusions.count_in(&(ip(0)..=ip(10))), 6);
+    }
+
+    /// v6 rapid commit is off unless set under `v6`, whatever the v4 setting, and can be
+    /// changed per interface
+    #[test]
+    fn test_v6_rapid_commit() {
+        let int = crate::v6_find_interfaces(None).unwrap().remove(0);
+        let v6 = |v6_yaml: &str| {
+            Config::new(format!(
+                "
+rapid_commit: true
+v6:
+    server_id:
+        type: LLT
+        persist: false
+{v6_yaml}
+    networks: {{}}
+"
+            ))
+            .unwrap()
+            .v6()
+            .unwrap()
+            .clone()
+        };
+        assert!(!v6("").rapid_commit(int.index));
+        assert!(v6("    rapid_commit: true").rapid_commit(int.index));
+        let cfg = v6(&format!(
+            "
+    rapid_commit: true
+    interface_overrides:
+        {}:
+            rapid_commit: false
+",
+            int.name
+        ));
+        assert!(!cfg.rapid_commit(int.index));
+        // an interface we don't know about uses the `v6` setting
+        assert!(cfg.rapid_commit(u32::MAX));
+    }
+
+    #[test]
+    fn test_v6_addr_registration() {
+        let v6 = |v6_yaml: &str| {
+            Config::new(format!(
+                "
+v6:
+    server_id:
+        type: LLT
+        persist: false
+{v6_yaml}
+    networks: {{}}
+"
+            ))
+            .unwrap()
+            .v6()
+            .unwrap()
+            .clone()
+        };
+        assert!(v6("").addr_registration().is_none());
+        assert!(
+            !v6("    addr_registration: {}")
+                .addr_registration()
+                .unwrap()
+                .ddns
+        );
+        assert!(
+            v6("    addr_registration:\n        ddns: true")
+                .addr_registration()
+                .unwrap()
+                .ddns
+        );
+    }
+
+    /// networks are picked by link address, network options win over class options, which
+    /// only fill in what the network & global options don't set
+    #[test]
+    fn test_v6_class_opts() {
+        use dora_core::dhcproto::v6;
+
+        let cfg = Config::new(
+            "
+v6:
+    server_id:
+        type: LLT
+        persist: false
+    options:
+        values:
+            23:
+                type: ip
+                value: 2001:db8::1
+    networks:
+        2001:db8:1::/48:
+            config:
+                lease_time:
+                    default: 3600
+                preferred_time:
+                    default: 3600
+            options:
+                values:
+                    31:
+                        type: ip
+                        value: 2001:db8:1::123
+        2001:db8:1:2::/64:
+            config:
+                lease_time:
+                    default: 3600
+                preferred_time:
+                    default: 3600
+            options:
+                values:
+                    23:
+                        type: ip
+                        value: 2001:db8:1:2::53
+client_classes:
+    v6:
+        - name: preferred
+          assert: option[7].hex == 0x0a
+          options:
+              values:
+                  23:
+                      type: ip
+                      value: 2001:db8::99
+                  24:
+                      type: hex
+                      value: 03666f6f00
+",
+        )
+        .unwrap();
+        let v6_cfg = cfg.v6().unwrap();
+
+        let mut msg = v6::Message::new(v6::MessageType::InformationRequest);
+        msg.opts_mut().insert(v6::DhcpOption::Preference(10));
+        let classes = v6_cfg.eval_client_classes(&msg).unwrap().unwrap();
+        assert!(classes.contains(&"preferred".to_owned()));
+        let msg = v6::Message::new(v6::MessageType::InformationRequest);
+        let classes_none = v6_cfg.eval_client_classes(&msg).unwrap().unwrap();
+        assert!(!classes_none.contains(&"preferred".to_owned()));
+
+        let dns = |opts: &v6::DhcpOptions| match opts.get(v6::OptionCode::DomainNameServers) {
+            Some(v6::DhcpOption::DomainNameServers(addrs)) => addrs.clone(),
+            _ => panic!("expected dns servers"),
+        };
+        let (search, sntp) = (v6::OptionCode::from(24), v6::OptionCode::from(31));
+
+        // the more specific /64 is selected, its dns wins over the class
+        let opts = v6_cfg
+            .collect_opts(0, Some("2001:db8:1:2::1".parse().unwrap()), Some(&classes))
+            .unwrap();
+        assert_eq!(
+            dns(&opts),
+            vec!["2001:db8:1:2::53".parse::<Ipv6Addr>().unwrap()]
+        );
+        assert!(opts.get(search).is_some());
+        assert!(opts.get(sntp).is_none());
+
+        // the /48, global dns fills the gap & no class matched
+        let opts = v6_cfg
+            .collect_opts(
+                0,
+                Some("2001:db8:1::1".parse().unwrap()),
+                Some(&classes_none),
+            )
+            .unwrap();
+        assert_eq!(dns(&opts), vec!["2001:db8::1".parse::<Ipv6Addr>().unwrap()]);
+        assert!(opts.get(search).is_none());
+        assert!(opts.get(sntp).is_some());
+
+        // no network for the link, global options
+        let opts = v6_cfg
+            .collect_opts(0, Some("2001:db8:9::1".parse().unwrap()), Some(&classes))
+            .unwrap();
+        assert_eq!(dns(&opts), vec!["2001:db8::1".parse::<Ipv6Addr>().unwrap()]);
+        assert!(opts.get(search).is_some());
+    }
+
+    /// network options win over global options, which only fill in what the network doesn't set
+    #[test]
+    fn test_v6_network_opts_precedence() {
+        use dora_core::dhcproto::v6;
+
+        let cfg = Config::new(
+            "
+v6:
+    server_id:
+        type: LLT
+        persist: false
+    options:
+        values:
+            23:
+                type: ip
+                value: 2001:db8::1
+            31:
+                type: ip
+                value: 2001:db8::123
+    networks:
+        2001:db8:1::/64:
+            config:
+                lease_time:
+                    default: 3600
+                preferred_time:
+                    default: 3600
+            options:
+                values:
+                    23:
+                        type: ip
+                        value: 2001:db8:1::53
+",
+        )
+        .unwrap();
+        let v6_cfg = cfg.v6().unwrap();
+        let opts = v6_cfg
+            .get_network_by_link("2001:db8:1::1".parse().unwrap())
+            .unwrap()
+            .opts();
+        assert_eq!(
+            opts.get(v6::OptionCode::DomainNameServers),
+            Some(&v6::DhcpOption::DomainNameServers(vec!["2001:db8:1::53"
+                .parse()
+                .unwrap()]))
+        );
+        assert!(opts.get(v6::OptionCode::from(31)).is_some());
+        // global options are untouched
+        assert_eq!(
+            v6_cfg
+                .get_opts(u32::MAX)
+                .unwrap()
+                .get(v6::OptionCode::DomainNameServers),
+            Some(&v6::DhcpOption::DomainNameServers(vec!["2001:db8::1"
+                .parse()
+                .unwrap()]))
+        );
+    }
 }
@@ -1,12 +1,21 @@
 //! # Client Classes
 
+use std::net::Ipv4Addr;
+
+use ipnet::Ipv4Net;
 use serde::{Deserialize, Serialize};
 
-use crate::wire::v4::Options;
+use crate::wire::{
+    v4::{Boot, Options},
+    v6,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ClientClasses {
+    #[serde(default)]
     pub(crate) v4: Vec<ClientClass>,
+    #[serde(default)]
+    pub(crate) v6: Vec<ClientClassV6>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -15,4 +24,53 @@ pub struct ClientClass {
     pub(crate) assert: String,
     #[serde(default)]
     pub(crate) options: Options,
+    /// server identifier used for clients matching this class
+    #[serde(default)]
+    pub(crate) server_id: Option<Ipv4Addr>,
+    /// `siaddr`/`sname`/`file` for clients matching this class
+    #[serde(flatten)]
+    pub(crate) boot: Boot,
+    /// if set, clients in this class may only get addresses from ranges inside these subnets
+    #[serde(default)]
+    pub(crate) allow: Option<Vec<Ipv4Net>>,
+    /// clients in this class may not get addresses from ranges inside these subnets
+    #[serde(default)]
+    pub(crate) deny: Vec<Ipv4Net>,
+    /// changes made to responses for clients in this class, after options are added
+    #[serde(default)]
+    pub(crate) transforms: Vec<Transform>,
+}
+
+/// A change made to the response, workarounds for clients that mishandle some responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    /// leave this option out of the response
+    RemoveOption(u8),
+    /// lease time in seconds the client is told at most, T1 & T2 are lowered to fit
+    MaxLeaseTime(u32),
+    /// set the broadcast flag in the response
+    Broadcast,
+}
+
+impl Transform {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match self {
+            // 53 makes it a DHCP message, 0 & 255 aren't options
+            Transform::RemoveOption(code @ (0 | 53 | 255)) => {
+                Err(format!("option {code} can't be removed"))
+            }
+            Transform::MaxLeaseTime(0) => Err("max_lease_time must be at least 1".to_owned()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// a DHCPv6 class, `options` are given to matching clients where the network doesn't set them
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ClientClassV6 {
+    pub(crate) name: String,
+    pub(crate) assert: String,
+    #[serde(default)]
+    pub(crate) options: v6::Options,
 }
@@ -0,0 +1,564 @@
+//! # Config diff
+//!
+//! A semantic diff between two configs, used by `dora-cfg diff` to review config
+//! changes. Networks, ranges & reservations are matched by subnet, bounds & `match`
+//! rather than by position, and changes that leave existing leases or reservations
+//! invalid are flagged as breaking.
+use std::{collections::BTreeSet, fmt, net::Ipv4Addr, ops::RangeInclusive};
+
+use anyhow::Result;
+use dora_core::dhcproto::v4::{DhcpOptions, OptionCode};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::wire::{
+    client_classes::ClientClasses,
+    v4::{Condition, IpRange, Net, Options, ReservedIp},
+    Config,
+};
+
+/// A single difference between two configs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// where in the config, ex. `networks.10.0.0.0/24.ranges.10.0.0.10-10.0.0.99`
+    pub path: String,
+    /// what changed
+    pub what: String,
+    /// existing leases or reservations may no longer be valid
+    pub breaking: bool,
+}
+
+impl Change {
+    fn new(path: impl Into<String>, what: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            what: what.into(),
+            breaking: false,
+        }
+    }
+
+    fn breaking(path: impl Into<String>, what: impl Into<String>) -> Self {
+        Self {
+            breaking: true,
+            ..Self::new(path, what)
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = if self.breaking { '!' } else { ' ' };
+        write!(f, "{flag} {}: {}", self.path, self.what)
+    }
+}
+
+/// changes needed to get from `old` to `new`, top-level fields first then by network
+pub fn diff(old: &Config, new: &Config) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    fields("", old, new, &["networks", "client_classes"], &mut changes)?;
+
+    let subnets = old
+        .networks
+        .keys()
+        .chain(new.networks.keys())
+        .collect::<BTreeSet<_>>();
+    for subnet in subnets {
+        let path = format!("networks.{subnet}");
+        match (old.networks.get(subnet), new.networks.get(subnet)) {
+            (Some(_), None) => changes.push(Change::breaking(
+                path,
+                "network removed, its leases & reservations will not be renewed",
+            )),
+            (None, Some(_)) => changes.push(Change::new(path, "network added")),
+            (Some(old), Some(new)) => network(&path, old, new, &mut changes)?,
+            (None, None) => {}
+        }
+    }
+
+    classes(old, new, &mut changes);
+    Ok(changes)
+}
+
+fn network(path: &str, old: &Net, new: &Net, changes: &mut Vec<Change>) -> Result<()> {
+    fields(path, old, new, &["ranges", "reservations"], changes)?;
+
+    for range in &old.ranges {
+        let key = format!("{path}.ranges.{}", range_key(&range.range));
+        match new.ranges.iter().find(|r| r.range == range.range) {
+            Some(new_range) => ip_range(&key, range, new_range, changes)?,
+            None => {
+                let stranded = uncovered(&range.range, &new.ranges);
+                if stranded == 0 {
+                    changes.push(Change::new(key, "range removed, new ranges cover it"));
+                } else {
+                    changes.push(Change::breaking(
+                        key,
+                        format!(
+                            "range removed, leases on {stranded} addresses will not be renewed"
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+    for range in &new.ranges {
+        if !old.ranges.iter().any(|r| r.range == range.range) {
+            let key = format!("{path}.ranges.{}", range_key(&range.range));
+            changes.push(Change::new(key, "range added"));
+        }
+    }
+
+    for res in &old.reservations {
+        let key = format!("{path}.reservations.{}", condition(&res.condition));
+        match new
+            .reservations
+            .iter()
+            .find(|r| r.condition == res.condition)
+        {
+            Some(new_res) => reservation(&key, res, new_res, changes)?,
+            None => changes.push(Change::breaking(
+                key,
+                format!("reservation of {} removed", res.ip),
+            )),
+        }
+    }
+    for res in &new.reservations {
+        if !old
+            .reservations
+            .iter()
+            .any(|r| r.condition == res.condition)
+        {
+            let key = format!("{path}.reservations.{}", condition(&res.condition));
+            changes.push(Change::new(key, format!("reservation of {} added", res.ip)));
+        }
+    }
+    Ok(())
+}
+
+fn ip_range(path: &str, old: &IpRange, new: &IpRange, changes: &mut Vec<Change>) -> Result<()> {
+    fields(
+        path,
+        old,
+        new,
+        &["start", "end", "options", "except", "class"],
+        changes,
+    )?;
+    options(path, &old.options, &new.options, changes);
+    if old.class != new.class {
+        // clients that were allowed before may not be anymore
+        changes.push(Change::breaking(
+            format!("{path}.class"),
+            format!(
+                "changed from {} to {}, clients outside the class will not renew",
+                class_name(&old.class),
+                class_name(&new.class)
+            ),
+        ));
+    }
+    for except in &new.except {
+        if !old.except.contains(except) {
+            let excluded = RangeInclusive::from(except.clone());
+            changes.push(Change::breaking(
+                format!("{path}.except"),
+                format!(
+                    "excluded {}, leases on it will not be renewed",
+                    range_key(&excluded)
+                ),
+            ));
+        }
+    }
+    for except in &old.except {
+        if !new.except.contains(except) {
+            let excluded = RangeInclusive::from(except.clone());
+            changes.push(Change::new(
+                format!("{path}.except"),
+                format!("no longer excluded {}", range_key(&excluded)),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn reservation(
+    path: &str,
+    old: &ReservedIp,
+    new: &ReservedIp,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    if old.ip != new.ip {
+        changes.push(Change::breaking(
+            format!("{path}.ip"),
+            format!(
+                "changed from {} to {}, the client moves on its next renewal",
+                old.ip, new.ip
+            ),
+        ));
+    }
+    fields(path, old, new, &["ip", "match", "options"], changes)?;
+    options(path, &old.options, &new.options, changes);
+    Ok(())
+}
+
+/// v4 & v6 classes by name, a class removed & another added with the same `assert` is
+/// taken as a rename
+fn classes(old: &Config, new: &Config, changes: &mut Vec<Change>) {
+    fn names<'a>(classes: &'a Option<ClientClasses>, v6: bool) -> Vec<(&'a str, &'a str, Value)> {
+        let Some(classes) = classes else {
+            return Vec::new();
+        };
+        if v6 {
+            classes
+                .v6
+                .iter()
+                .map(|c| (&c.name[..], &c.assert[..], to_value(c)))
+                .collect()
+        } else {
+            classes
+                .v4
+                .iter()
+                .map(|c| (&c.name[..], &c.assert[..], to_value(c)))
+                .collect()
+        }
+    }
+    for (v6, prefix) in [(false, "client_classes.v4"), (true, "client_classes.v6")] {
+        let old = names(&old.client_classes, v6);
+        let new = names(&new.client_classes, v6);
+        let removed = old
+            .iter()
+            .filter(|(name, ..)| !new.iter().any(|(n, ..)| n == name))
+            .map(|(name, assert, _)| (*name, *assert))
+            .collect::<Vec<_>>();
+        let added = new
+            .iter()
+            .filter(|(name, ..)| !old.iter().any(|(n, ..)| n == name))
+            .map(|(name, assert, _)| (*name, *assert))
+            .collect::<Vec<_>>();
+        let mut renamed: Vec<&str> = Vec::new();
+        for (name, assert) in &removed {
+            let path = format!("{prefix}.{name}");
+            match added
+                .iter()
+                .find(|(n, a)| a == assert && !renamed.contains(n))
+            {
+                Some((new_name, _)) => {
+                    renamed.push(new_name);
+                    changes.push(Change::new(path, format!("renamed to {new_name}")));
+                }
+                None => changes.push(Change::new(path, "class removed")),
+            }
+        }
+        for (name, _) in &added {
+            if !renamed.contains(name) {
+                changes.push(Change::new(format!("{prefix}.{name}"), "class added"));
+            }
+        }
+        for (name, assert, value) in &old {
+            let Some((_, new_assert, new_value)) = new.iter().find(|(n, ..)| n == name) else {
+                continue;
+            };
+            let path = format!("{prefix}.{name}");
+            if assert != new_assert {
+                changes.push(Change::new(
+                    format!("{path}.assert"),
+                    format!("changed from `{assert}` to `{new_assert}`"),
+                ));
+            } else if value != new_value {
+                changes.push(Change::new(path, "changed"));
+            }
+        }
+    }
+}
+
+/// compare the serialized fields of `old` & `new` other than `skip`, nested values are
+/// compared whole
+fn fields<T: Serialize>(
+    path: &str,
+    old: &T,
+    new: &T,
+    skip: &[&str],
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    let (Value::Object(old), Value::Object(new)) =
+        (serde_json::to_value(old)?, serde_json::to_value(new)?)
+    else {
+        return Ok(());
+    };
+    let keys = old
+        .keys()
+        .chain(new.keys())
+        .filter(|key| !skip.contains(&key.as_str()))
+        .collect::<BTreeSet<_>>();
+    for key in keys {
+        let path = if path.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{path}.{key}")
+        };
+        let old = old.get(key).filter(|v| !v.is_null());
+        let new = new.get(key).filter(|v| !v.is_null());
+        // nested values are too long to print
+        let nested = |v: &Value| v.is_object() || v.is_array();
+        match (old, new) {
+            (Some(old), Some(new)) if old != new && (nested(old) || nested(new)) => {
+                changes.push(Change::new(path, "changed"))
+            }
+            (Some(old), Some(new)) if old != new => {
+                changes.push(Change::new(path, format!("changed from {old} to {new}")))
+            }
+            (Some(_), None) => changes.push(Change::new(path, "removed")),
+            (None, Some(new)) if nested(new) => changes.push(Change::new(path, "set")),
+            (None, Some(new)) => changes.push(Change::new(path, format!("set to {new}"))),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn options(path: &str, old: &Options, new: &Options, changes: &mut Vec<Change>) {
+    let old: &DhcpOptions = old.as_ref();
+    let new: &DhcpOptions = new.as_ref();
+    let codes = old
+        .iter()
+        .chain(new.iter())
+        .map(|(code, _)| u8::from(*code))
+        .collect::<BTreeSet<_>>();
+    for code in codes {
+        let path = format!("{path}.options.{code}");
+        match (
+            old.get(OptionCode::from(code)),
+            new.get(OptionCode::from(code)),
+        ) {
+            (Some(old), Some(new)) if old != new => changes.push(Change::new(
+                path,
+                format!("changed from {old:?} to {new:?}"),
+            )),
+            (Some(_), None) => changes.push(Change::new(path, "removed")),
+            (None, Some(new)) => changes.push(Change::new(path, format!("set to {new:?}"))),
+            _ => {}
+        }
+    }
+}
+
+/// addresses in `range` outside of all `ranges`
+fn uncovered(range: &RangeInclusive<Ipv4Addr>, ranges: &[IpRange]) -> u64 {
+    let (start, end) = (u32::from(*range.start()), u32::from(*range.end()));
+    let mut covered = ranges
+        .iter()
+        .map(|r| (u32::from(*r.range.start()), u32::from(*r.range.end())))
+        .filter(|(s, e)| *s <= end && *e >= start)
+        .map(|(s, e)| (s.max(start), e.min(end)))
+        .collect::<Vec<_>>();
+    covered.sort_unstable();
+    let mut total = 0;
+    // first address not yet counted as covered
+    let mut next = start as u64;
+    for (s, e) in covered {
+        let (s, e) = (s as u64, e as u64);
+        if e >= next {
+            total += e + 1 - s.max(next);
+            next = e + 1;
+        }
+    }
+    (end as u64 + 1 - start as u64) - total
+}
+
+fn range_key(range: &RangeInclusive<Ipv4Addr>) -> String {
+    if range.start() == range.end() {
+        range.start().to_string()
+    } else {
+        format!("{}-{}", range.start(), range.end())
+    }
+}
+
+fn condition(condition: &Condition) -> String {
+    match condition {
+        Condition::Mac(mac) => format!("chaddr={mac}"),
+        Condition::Hostname(hostname) => format!("hostname={hostname}"),
+        Condition::UserClass(class) => format!("user_class={class}"),
+        Condition::Options(opts) => format!("options={}", to_value(opts)),
+    }
+}
+
+fn class_name(class: &Option<String>) -> &str {
+    class.as_deref().unwrap_or("none")
+}
+
+fn to_value<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static OLD: &str = r#"
+networks:
+    192.168.0.0/24:
+        ranges:
+            -
+                start: 192.168.0.100
+                end: 192.168.0.150
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        3:
+                            type: ip
+                            value: 192.168.0.1
+            -
+                start: 192.168.0.200
+                end: 192.168.0.210
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values: {}
+        reservations:
+            -
+                ip: 192.168.0.160
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values: {}
+                match:
+                    chaddr: aa:bb:cc:dd:ee:ff
+    10.0.0.0/24:
+        ranges: []
+client_classes:
+    v4:
+        -
+            name: phones
+            assert: "option[60].hex == 'android-dhcp-9'"
+            options:
+                values: {}
+"#;
+
+    static NEW: &str = r#"
+networks:
+    192.168.0.0/24:
+        ranges:
+            -
+                start: 192.168.0.100
+                end: 192.168.0.150
+                config:
+                    lease_time:
+                        default: 7200
+                options:
+                    values:
+                        3:
+                            type: ip
+                            value: 192.168.0.254
+                except:
+                    - 192.168.0.120
+            -
+                start: 192.168.0.200
+                end: 192.168.0.205
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values: {}
+        reservations:
+            -
+                ip: 192.168.0.161
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values: {}
+                match:
+                    chaddr: aa:bb:cc:dd:ee:ff
+client_classes:
+    v4:
+        -
+            name: android
+            assert: "option[60].hex == 'android-dhcp-9'"
+            options:
+                values: {}
+"#;
+
+    fn find<'a>(changes: &'a [Change], path: &str) -> &'a Change {
+        changes
+            .iter()
+            .find(|c| c.path == path)
+            .unwrap_or_else(|| panic!("no change at {path} in {changes:#?}"))
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Config::parse(OLD).unwrap();
+        let new = Config::parse(NEW).unwrap();
+        assert!(diff(&old, &old).unwrap().is_empty());
+
+        let changes = diff(&old, &new).unwrap();
+        let net = "networks.192.168.0.0/24";
+        assert!(find(&changes, "networks.10.0.0.0/24").breaking);
+        let range = format!("{net}.ranges.192.168.0.100-192.168.0.150");
+        assert!(!find(&changes, &format!("{range}.config")).breaking);
+        assert!(!find(&changes, &format!("{range}.options.3")).breaking);
+        assert!(find(&changes, &format!("{range}.except")).breaking);
+        // 6 of the 11 addresses are still in a range
+        let removed = find(
+            &changes,
+            &format!("{net}.ranges.192.168.0.200-192.168.0.210"),
+        );
+        assert!(removed.breaking && removed.what.contains("5 addresses"));
+        assert!(
+            !find(
+                &changes,
+                &format!("{net}.ranges.192.168.0.200-192.168.0.205")
+            )
+            .breaking
+        );
+        assert!(
+            find(
+                &changes,
+                &format!("{net}.reservations.chaddr=aa:bb:cc:dd:ee:ff.ip")
+            )
+            .breaking
+        );
+        let renamed = find(&changes, "client_classes.v4.phones");
+        assert_eq!(renamed.what, "renamed to android");
+        assert!(!changes
+            .iter()
+            .any(|c| c.path == "client_classes.v4.android"));
+    }
+
+    #[test]
+    fn test_uncovered() {
+        let range = |start: [u8; 4], end: [u8; 4]| IpRange {
+            range: start.into()..=end.into(),
+            options: Options::default(),
+            config: crate::wire::v4::NetworkConfig {
+                lease_time: crate::wire::MinMax {
+                    default: 3600u32.try_into().unwrap(),
+                    min: None,
+                    max: None,
+                },
+                infinite: false,
+                renew_percent: None,
+                rebind_percent: None,
+            },
+            except: Vec::new(),
+            class: None,
+            boot: Default::default(),
+            mtu: None,
+        };
+        let old = Ipv4Addr::from([10, 0, 0, 10])..=Ipv4Addr::from([10, 0, 0, 19]);
+        assert_eq!(uncovered(&old, &[]), 10);
+        assert_eq!(uncovered(&old, &[range([10, 0, 0, 0], [10, 0, 0, 255])]), 0);
+        // overlapping ranges aren't counted twice
+        assert_eq!(
+            uncovered(
+                &old,
+                &[
+                    range([10, 0, 0, 12], [10, 0, 0, 15]),
+                    range([10, 0, 0, 14], [10, 0, 0, 16]),
+                ]
+            ),
+            5
+        );
+    }
+}
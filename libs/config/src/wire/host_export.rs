@@ -0,0 +1,65 @@
+//! # Host export
+//!
+//! Active leases with a hostname are written to a file on an interval, for DNS servers
+//! that include a generated hosts or zone file instead of taking DDNS updates
+
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// `host_export` section of the config
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HostExport {
+    /// the file is replaced whenever its contents change
+    pub path: PathBuf,
+    #[serde(default)]
+    pub format: HostFormat,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// appended to hostnames that aren't fully qualified
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// record TTL, only used by the zone format
+    #[serde(default = "default_ttl")]
+    pub ttl: u32,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostFormat {
+    /// `/etc/hosts` style, `{ip} {name}`
+    #[default]
+    Hosts,
+    /// BIND zone fragment of A records, meant to be `$INCLUDE`d
+    Zone,
+}
+
+impl HostExport {
+    /// time between exports
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+    pub fn validate(&self) -> Result<()> {
+        if self.interval_secs == 0 {
+            bail!("host_export interval_secs must be greater than 0");
+        }
+        if self.path.as_os_str().is_empty() {
+            bail!("host_export path must not be empty");
+        }
+        if let Some(domain) = &self.domain {
+            if domain.trim_matches('.').is_empty() {
+                bail!("host_export domain must not be empty");
+            }
+        }
+        Ok(())
+    }
+}
+
+pub const fn default_interval_secs() -> u64 {
+    60
+}
+
+pub const fn default_ttl() -> u32 {
+    300
+}
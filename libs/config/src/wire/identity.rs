@@ -0,0 +1,109 @@
+//! # Identity backends
+//!
+//! An external directory asked about each client during classification, see the
+//! `identity` crate
+
+use std::{net::SocketAddr, time::Duration};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::wire::Secret;
+
+/// `identity` section of the config
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Identity {
+    /// time a lookup may hold up a response, a slower backend leaves the client unknown
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(flatten)]
+    pub backend: Backend,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    Radius(Radius),
+    Ldap(Ldap),
+}
+
+/// MAC authentication against a RADIUS server, an Access-Accept makes the client known
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Radius {
+    pub server: SocketAddr,
+    /// shared secret, can be a `${env:VAR}` or `${file:/path}` reference
+    pub secret: Secret,
+    /// sent as NAS-Identifier if set
+    #[serde(default)]
+    pub nas_identifier: Option<String>,
+}
+
+/// search an LDAP directory, a matching entry makes the client known
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Ldap {
+    /// ex. `ldap://10.0.0.6:389` or `ldaps://...`
+    pub url: String,
+    /// anonymous bind if not set
+    #[serde(default)]
+    pub bind_dn: Option<String>,
+    #[serde(default)]
+    pub bind_password: Option<Secret>,
+    pub base_dn: String,
+    /// `{mac}` is replaced with the chaddr as `aa:bb:cc:dd:ee:ff` & `{client_id}` with the
+    /// client id as hex
+    #[serde(default = "default_ldap_filter")]
+    pub filter: String,
+    /// attribute holding the client's VLAN id
+    #[serde(default)]
+    pub vlan_attr: Option<String>,
+    /// attribute holding class tags, each value is added to the client's classes
+    #[serde(default)]
+    pub class_attr: Option<String>,
+}
+
+impl Identity {
+    /// the lookup budget
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+    pub fn validate(&self) -> Result<()> {
+        if self.timeout_ms == 0 {
+            bail!("identity timeout_ms must be greater than 0");
+        }
+        if let Backend::Ldap(ldap) = &self.backend {
+            if !ldap.filter.contains("{mac}") && !ldap.filter.contains("{client_id}") {
+                bail!("identity ldap filter must contain {{mac}} or {{client_id}}");
+            }
+            if ldap.bind_dn.is_some() != ldap.bind_password.is_some() {
+                bail!("identity ldap bind_dn and bind_password must be set together");
+            }
+        }
+        Ok(())
+    }
+}
+
+pub const fn default_timeout_ms() -> u64 {
+    200
+}
+
+pub fn default_ldap_filter() -> String {
+    "(macAddress={mac})".to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secrets_redacted() {
+        let identity: Identity =
+            serde_yaml::from_str("radius:\n    server: 10.0.0.5:1812\n    secret: s3cret\n")
+                .unwrap();
+        let Backend::Radius(radius) = &identity.backend else {
+            panic!("expected radius");
+        };
+        assert_eq!(radius.secret.expose(), "s3cret");
+        assert!(!format!("{identity:?}").contains("s3cret"));
+        assert!(!serde_yaml::to_string(&identity).unwrap().contains("s3cret"));
+    }
+}
@@ -1,38 +1,198 @@
-use std::{collections::HashMap, num::NonZeroU32, time::Duration};
+use std::{collections::HashMap, fmt, num::NonZeroU32, time::Duration};
 
+use anyhow::{Context, Result};
 use ipnet::Ipv4Net;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{wire::client_classes::ClientClasses, LeaseTime};
 
 pub mod client_classes;
+pub mod diff;
+pub mod host_export;
+pub mod identity;
 pub mod v4;
 pub mod v6;
 
 /// top-level config type
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Config {
+    /// RFC 3339 time a config staged with `next_config_path` replaces the running one
+    pub effective_at: Option<String>,
     pub interfaces: Option<Vec<String>>,
+    /// interfaces left out when `interfaces` isn't set
+    #[serde(default)]
+    pub deny_interfaces: Vec<String>,
     #[serde(default = "default_chaddr_only")]
     pub chaddr_only: bool,
-    pub flood_protection_threshold: Option<FloodThreshold>,
+    /// default for networks that don't set `authoritative`, true if neither does
+    pub authoritative: Option<bool>,
+    /// one limiter or a list of them, each with its own `key` & quota
+    pub flood_protection_threshold: Option<MaybeList<FloodThreshold>>,
+    /// max DECLINEs per client in a period, any more are ignored
+    pub decline_threshold: Option<FloodThreshold>,
+    /// RFC 3074 load balancing with a peer server
+    pub load_balance: Option<LoadBalance>,
+    /// checks for obviously bogus v4 packets
+    pub sanity_checks: Option<SanityChecks>,
+    /// drop messages that came from dora itself, ex. a relay looping our replies back
+    pub loop_protection: Option<LoopProtection>,
+    /// favor clients that have been retrying longest while the server is busy
+    pub load_shedding: Option<LoadShedding>,
     #[serde(default = "default_cache_threshold")]
     pub cache_threshold: u32,
     #[serde(default = "default_bootp_enable")]
     pub bootp_enable: bool,
     #[serde(default = "default_rapid_commit")]
     pub rapid_commit: bool,
+    /// encode response options in a fixed order, these codes first & the rest by code
+    pub option_order: Option<Vec<u8>>,
+    /// what makes a client part of the `KNOWN` built-in class
+    #[serde(default)]
+    pub known_clients: KnownPolicy,
+    /// record each lease's DHCP fingerprint, for `/v1/leases/fingerprints`
+    #[serde(default)]
+    pub store_fingerprints: bool,
     #[serde(default)]
     pub networks: HashMap<Ipv4Net, v4::Net>,
     pub v6: Option<v6::Config>,
     pub client_classes: Option<ClientClasses>,
     pub ddns: Option<v4::ddns::Ddns>,
+    /// external directory consulted during classification
+    pub identity: Option<identity::Identity>,
+    /// write active lease hostnames to a hosts or zone file
+    pub host_export: Option<host_export::HostExport>,
+}
+
+impl Config {
+    /// decode as JSON, then YAML if that fails
+    pub fn parse(input: &str) -> Result<Self> {
+        match serde_json::from_str(input) {
+            Ok(cfg) => Ok(cfg),
+            Err(_err) => Ok(serde_yaml::from_str(input)?),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct FloodThreshold {
     pub packets: NonZeroU32,
     pub secs: NonZeroU32,
+    /// tighter limit for the clients of a network while it's at or above `high_water`
+    /// percent utilization. Only for `flood_protection_threshold`
+    pub pressure: Option<FloodPressure>,
+    /// what packets are counted by, one part or a list of them that's counted as a tuple.
+    /// The client id if unset. Only for `flood_protection_threshold`
+    pub key: Option<MaybeList<FloodKey>>,
+}
+
+/// a part of the key the flood limiter counts packets by
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum FloodKey {
+    /// opt 61, or chaddr if it's absent or `chaddr_only` is set
+    ClientId,
+    /// the client's hardware address
+    Chaddr,
+    /// the relay the packet came through, 0.0.0.0 if it wasn't relayed
+    Giaddr,
+    /// source IP of the packet, the relay's for relayed packets. A key of only `giaddr`
+    /// & `source_ip` doesn't count packets where they're all 0.0.0.0, so unrelayed
+    /// clients without an address aren't limited as one
+    SourceIp,
+}
+
+impl FloodKey {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FloodKey::ClientId => "client_id",
+            FloodKey::Chaddr => "chaddr",
+            FloodKey::Giaddr => "giaddr",
+            FloodKey::SourceIp => "source_ip",
+        }
+    }
+}
+
+/// `packets` allowed per `secs` while pools are under pressure
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloodPressure {
+    pub high_water: u8,
+    pub packets: NonZeroU32,
+}
+
+/// Split clients between two servers by hashing the client id (RFC 3074)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadBalance {
+    /// which side of the split this server is
+    pub role: LoadBalanceRole,
+    /// number of the 256 hash buckets served by the primary, the secondary serves the rest
+    #[serde(default = "default_lb_split")]
+    pub split: u16,
+    /// answer regardless of bucket once the client's `secs` reaches this value,
+    /// so clients are still served if the peer is down
+    #[serde(default = "default_lb_max_secs")]
+    pub max_secs: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadBalanceRole {
+    Primary,
+    Secondary,
+}
+
+/// Reject packets that no real client sends: xid 0, very large `secs`, `hlen` that
+/// doesn't match `htype`, or an all-zero `chaddr` without a client id
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SanityChecks {
+    /// what to do with a packet that fails a check
+    #[serde(default)]
+    pub policy: SanityPolicy,
+    /// packets with `secs` above this fail, 0 disables the check
+    #[serde(default = "default_sanity_max_secs")]
+    pub max_secs: u16,
+}
+
+/// Drop messages whose `chaddr` or client id is one of our interface MACs, or whose
+/// `ciaddr` is one of our server identifiers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopProtection {
+    /// log at most one dropped message per this many seconds, all of them are counted
+    #[serde(default = "default_loop_log_secs")]
+    pub log_secs: u64,
+}
+
+/// While the messages in flight are at or above `high_water` percent of the live message
+/// limit, only clients whose `secs` field has reached `min_secs` are answered
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadShedding {
+    #[serde(default = "default_shed_high_water")]
+    pub high_water: u8,
+    #[serde(default = "default_shed_min_secs")]
+    pub min_secs: u16,
+}
+
+/// which clients are members of the `KNOWN` class, everyone else is `UNKNOWN`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KnownPolicy {
+    /// clients with an active lease or a reservation
+    #[default]
+    LeaseOrReservation,
+    /// only clients with a reservation. A client that got a lease while `UNKNOWN` stays
+    /// `UNKNOWN`, so it can be kept in a quarantine range until it's reserved
+    Reservation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SanityPolicy {
+    /// don't respond
+    #[default]
+    Drop,
+    /// log & process as normal
+    Log,
+    /// add the `SUSPECT` built-in client class & process as normal
+    Tag,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
@@ -50,10 +210,38 @@ pub const fn default_authoritative() -> bool {
     true
 }
 
+pub const fn default_lb_split() -> u16 {
+    128
+}
+
+pub const fn default_lb_max_secs() -> u16 {
+    3
+}
+
+pub const fn default_sanity_max_secs() -> u16 {
+    3_600
+}
+
+pub const fn default_loop_log_secs() -> u64 {
+    60
+}
+
+pub const fn default_shed_high_water() -> u8 {
+    80
+}
+
+pub const fn default_shed_min_secs() -> u16 {
+    4
+}
+
 pub const fn default_probation() -> u64 {
     86_400
 }
 
+pub const fn default_v6_only_wait() -> u32 {
+    1_800
+}
+
 pub const fn default_chaddr_only() -> bool {
     false
 }
@@ -81,17 +269,104 @@ impl From<MinMax> for LeaseTime {
             .max
             .map(|n| Duration::from_secs(n.get() as u64))
             .unwrap_or(default);
-        Self { default, min, max }
+        Self::new(default, min, max)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A config value that shouldn't end up in logs, like TSIG key data. It can be
+/// written inline or as a `${env:VAR}` or `${file:/path}` reference, which is
+/// resolved when the config is parsed. `Debug` never prints the value, and
+/// serializing writes back the reference, or a placeholder for inline values.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret {
+    value: String,
+    reference: Option<String>,
+}
+
+impl Secret {
+    /// written in place of inline secrets when serializing
+    pub const REDACTED: &'static str = "<redacted>";
+
+    /// resolve `${env:VAR}` & `${file:/path}` references, anything else is taken
+    /// as the secret itself. Trailing whitespace is trimmed from files
+    pub fn resolve(input: &str) -> Result<Self> {
+        let Some(reference) = input
+            .strip_prefix("${")
+            .and_then(|rest| rest.strip_suffix('}'))
+        else {
+            return Ok(Self::from(input));
+        };
+        let value = if let Some(var) = reference.strip_prefix("env:") {
+            std::env::var(var).with_context(|| format!("failed to read env var {var}"))?
+        } else if let Some(path) = reference.strip_prefix("file:") {
+            std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read secret file {path}"))?
+                .trim_end()
+                .to_owned()
+        } else {
+            anyhow::bail!(
+                "unknown secret reference {input}, expected ${{env:VAR}} or ${{file:/path}}"
+            );
+        };
+        Ok(Self {
+            value,
+            reference: Some(input.to_owned()),
+        })
+    }
+
+    /// the resolved secret
+    pub fn expose(&self) -> &str {
+        &self.value
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self {
+            value: value.to_owned(),
+            reference: None,
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.reference {
+            Some(reference) => write!(f, "Secret({reference})"),
+            None => write!(f, "Secret({})", Self::REDACTED),
+        }
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.reference.as_deref().unwrap_or(Self::REDACTED))
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Self::resolve(&s).map_err(|err| serde::de::Error::custom(format!("{err:#}")))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(untagged)]
-pub(crate) enum MaybeList<T> {
+pub enum MaybeList<T> {
     Val(T),
     List(Vec<T>),
 }
 
+impl<T> MaybeList<T> {
+    pub fn into_vec(self) -> Vec<T> {
+        match self {
+            MaybeList::Val(val) => vec![val],
+            MaybeList::List(list) => list,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -106,4 +381,35 @@ mod tests {
         let s = serde_yaml::to_string(&cfg).unwrap();
         println!("{s}");
     }
+
+    #[test]
+    fn test_secret() {
+        use super::Secret;
+
+        std::env::set_var("DORA_TEST_SECRET", "hunter2");
+        let secret = Secret::resolve("${env:DORA_TEST_SECRET}").unwrap();
+        assert_eq!(secret.expose(), "hunter2");
+        assert!(!format!("{secret:?}").contains("hunter2"));
+        assert_eq!(
+            serde_json::to_string(&secret).unwrap(),
+            r#""${env:DORA_TEST_SECRET}""#
+        );
+
+        let path = std::env::temp_dir().join("dora_test_secret");
+        std::fs::write(&path, "s3cret\n").unwrap();
+        let secret = Secret::resolve(&format!("${{file:{}}}", path.display())).unwrap();
+        assert_eq!(secret.expose(), "s3cret");
+        std::fs::remove_file(path).unwrap();
+
+        let inline = Secret::resolve("inline").unwrap();
+        assert_eq!(inline.expose(), "inline");
+        assert!(!format!("{inline:?}").contains("inline"));
+        assert_eq!(
+            serde_json::to_string(&inline).unwrap(),
+            format!("\"{}\"", Secret::REDACTED)
+        );
+
+        assert!(Secret::resolve("${env:DORA_TEST_SECRET_MISSING}").is_err());
+        assert!(Secret::resolve("${vault:foo}").is_err());
+    }
 }
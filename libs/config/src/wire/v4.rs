@@ -2,8 +2,12 @@
 //!
 //! ## Reservations
 //!
-//! Reservations are supported based on `chaddr`, or `options`. Currently, only a single
+//! Reservations are supported based on `chaddr`, `hostname`, `user_class` or `options`. Currently, only a single
 //! options may be specified for a match. There is no AND/OR logic for matching on options.
+//! `hostname` matches the client's hostname (opt 12) case-insensitively, so devices that
+//! keep a provisioned hostname get their address even if the MAC changes.
+//! `user_class` matches any of the user classes (opt 77) the client sends, for clients
+//! like provisioning images that identify themselves with one.
 //!
 //! ## Parameter request options
 //!
@@ -11,6 +15,19 @@
 //! an IP from that reservation or range, it will also use the corresponding `options`
 //! to respond to any parameter request list values.
 //!
+//! ## Timezone
+//!
+//! `tcode` (opt 101) with type `tz` takes a tz database name, ex. `America/New_York`,
+//! which is checked when the config is loaded. `pcode` (opt 100) is filled in with the
+//! zone's POSIX TZ string & `time_offset` (opt 2) with its current UTC offset, computed
+//! for each response so it follows daylight saving time. A configured `pcode` or
+//! `time_offset` is sent as-is.
+//!
+//! ## Infinite leases
+//!
+//! Setting `infinite: true` in the `config` of a range or reservation will hand out
+//! leases that never expire (opt 51 is 0xffffffff). The `lease_time` is ignored.
+//!
 //! ## Ping check
 //!
 //! `ping_check` set to true will ping before assigning an IP
@@ -22,6 +39,19 @@
 //! (meaning the address is in use), dora will not attempt to lease the IP inside of
 //! the probation period.
 //!
+//! An IP that keeps getting probated is likely in use by a host that isn't using DHCP.
+//! `max_probations` sets how many times an IP may be probated before it is
+//! quarantined, quarantined IPs are never handed out until they are cleared through
+//! the external API. `probation_decay` (seconds) forgives past probations, if an IP
+//! hasn't been probated for this long its count starts over.
+//!
+//! ## Lease grace
+//!
+//! A client that was offline when its lease ran out REQUESTs the address it had when
+//! it returns. Normally the lease is gone & the REQUEST is NAKed, `lease_grace`
+//! (seconds) re-grants the expired lease instead if it expired within that long & the
+//! IP hasn't been given to another client since.
+//!
 //! ## Chaddr Only
 //!
 //! Normally, client id is determined by (opt 60) client identifier, if it is
@@ -40,17 +70,114 @@
 //!  belonging to the subnet.
 //! Non-authoritative INFORM packets received from the clients on a
 //! non-authoritative network will be ignored.
-use std::{collections::HashMap, hash::Hash, net::Ipv4Addr, ops::RangeInclusive};
+//!
+//! `authoritative` can be set at the top level as the default for every network,
+//! and per network to override it. If it's set in neither place, the network is
+//! authoritative and a warning is logged at startup.
+//!
+//! ## IPv6-Only Preferred
+//!
+//! Setting `ipv6_only_preferred` on a network enables RFC 8925. Clients that include
+//! option 108 in their parameter request list will get an OFFER/ACK with `yiaddr`
+//! of 0.0.0.0 and option 108 set to `v6_only_wait` (default 1800 seconds). No
+//! address is allocated for these clients.
+//!
+//! ## Boot fields
+//!
+//! `next_server` (the `siaddr` header field), `server_name` (`sname`) & `file_name`
+//! (`file`) can be set on a network, client class, range or reservation. Each field is
+//! taken from the most specific place it's set: the reservation or range the address
+//! came from, then the first matching client class in config order, then the network.
+//! `siaddr` defaults to the server id if `next_server` isn't set anywhere.
+//!
+//! ## UEFI HTTP boot
+//!
+//! `http_boot` on a network maps client architectures (opt 93) to boot URLs, ex.
+//! `x64: http://10.0.0.5/efi/shimx64.efi`. Architectures are `x86`, `x64`, `ebc`,
+//! `arm32`, `arm64`, `pcbios` or an IANA architecture type number. Clients with an
+//! `HTTPClient` vendor class (opt 60) & a listed architecture get the URL in opt 67,
+//! whether or not they ask for it, & `HTTPClient` back in opt 60. The URL is also put in
+//! `file` when it fits, a range or reservation `file_name` still takes its place there.
+//!
+//! ## Interface MTU
+//!
+//! `mtu` on a network or range is sent as opt 26. A range's `mtu` overrides the
+//! network's, and ranges & reservations without one get the network's. Like any other
+//! option it's only sent to clients that have 26 in their parameter request list. The
+//! minimum is 68 (RFC 2132), and `mtu` can't be set together with an opt 26 in the
+//! same `options` map.
+//!
+//! ## Reply routing
+//!
+//! `reply_to` overrides the RFC 2131 rules for where responses are sent. `ciaddr`
+//! always unicasts to `ciaddr` when it is set, `broadcast` always broadcasts, and
+//! `helper: <ip>` sends the response to the given address on port 67. This is useful
+//! when relays sit behind NAT and `giaddr` is not reachable.
+//!
+//! ## Relay access control
+//!
+//! `relays` limits which relay agents may forward requests for a network. `giaddrs`
+//! takes the same forms as `except` (an IP, a CIDR or a `start`/`end` pair), and
+//! `remote_ids` lists the allowed relay agent information (opt 82) remote-ids,
+//! compared as text or as hex when prefixed with `0x`. A relayed request whose `giaddr`
+//! isn't listed, or without an allowed remote-id, is dropped. Requests that weren't
+//! relayed are unaffected.
+//!
+//! ## OUI lease limits
+//!
+//! `oui_limits` caps how many addresses clients from one vendor can hold in a network at
+//! once. Each entry has an `oui`, the first 3 bytes of the MAC (`chaddr`) like
+//! `"00:11:22"`, and `max_leases`. Offers and leases to clients with that prefix count
+//! towards the limit until they expire or are released, regardless of client id, so
+//! devices that churn client ids can't exhaust the pool. Once the limit is reached new
+//! clients from the OUI are not offered an address (and REQUESTs are NAKed when
+//! authoritative), clients that already hold an address can keep renewing it.
+//!
+//! ## Clients in multiple networks
+//!
+//! `multi_network_leases` decides what happens to a client's leases in other networks
+//! once it's leased an address in this one, ex. a laptop that moved to another VLAN.
+//! With `release` (the default) they're released, with `allow` the client keeps them
+//! & can hold leases in several networks at once. Offers don't count, only a lease
+//! (ACK, or an allocation for BOOTP) applies the policy.
+//!
+//! ## Range fill
+//!
+//! When a client doesn't ask for an address it can have, ranges are tried in config
+//! order with `fill: sequential` (the default), so the first range is used up before
+//! the second is touched. `fair` tries the range with the lowest share of its addresses
+//! in use first, spreading clients over the ranges as if they were one pool.
+//! `most_free_first` tries the range with the most free addresses first. Both count the
+//! addresses in use in each range when an address is picked, ties keep config order.
+//!
+//! ## Interface binding
+//!
+//! Top level `interfaces` lists the interfaces dora listens on, when it's not set every
+//! interface that's up with an IPv4 address is used, except those in `deny_interfaces`.
+//! VLAN sub-interfaces are named like any other interface (`eth0.100`), the `ip link`
+//! form `eth0.100@eth0` is also accepted. A network can be tied to its interfaces with
+//! `interfaces`, each one must have an address in the network's subnet. Otherwise the
+//! network is matched to the interface with an address in its subnet. Startup fails if
+//! a network matches more than one interface or an interface is bound to more than one
+//! network. Networks with no matching interface are only served through relays.
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    net::Ipv4Addr,
+    ops::RangeInclusive,
+};
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use base64::Engine;
 use dora_core::{
+    chrono::{Datelike, Utc},
     dhcproto::{
         v4::{self, DhcpOption, DhcpOptions, OptionCode},
         Decodable, Decoder, Encodable, Encoder,
     },
     pnet::util::MacAddr,
 };
+use ipnet::Ipv4Net;
 use serde::{de, Deserialize, Deserializer, Serialize};
 use tracing::warn;
 use trust_dns_proto::{
@@ -58,7 +185,10 @@ use trust_dns_proto::{
     serialize::binary::{BinEncodable, BinEncoder},
 };
 
-use crate::wire::{MaybeList, MinMax};
+use crate::{
+    http_boot, timezone,
+    wire::{MaybeList, MinMax},
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Net {
@@ -77,13 +207,226 @@ pub struct Net {
     /// probation period in seconds
     #[serde(default = "super::default_probation")]
     pub probation_period: u64,
-    /// Whether we are authoritative for this network (default: true)
-    #[serde(default = "super::default_authoritative")]
-    pub authoritative: bool,
+    /// number of probations before an IP is quarantined, unlimited if not set
+    pub max_probations: Option<u32>,
+    /// seconds without a probation before an IP's probation count is reset
+    pub probation_decay: Option<u64>,
+    /// seconds after a lease expires that its client can still REQUEST it back, if no
+    /// other client has been given the IP
+    pub lease_grace: Option<u64>,
+    /// Whether we are authoritative for this network, overrides the global
+    /// `authoritative`. If neither is set, true
+    pub authoritative: Option<bool>,
+    #[serde(flatten)]
+    pub boot: Boot,
+    /// boot URLs for UEFI HTTP boot clients, by architecture
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub http_boot: BTreeMap<http_boot::Arch, String>,
+    /// RFC 8925, clients requesting opt 108 on this network are told to
+    /// use IPv6-only instead of being allocated an address
+    #[serde(default)]
+    pub ipv6_only_preferred: bool,
+    /// V6ONLY_WAIT in seconds, sent back in opt 108
+    #[serde(default = "super::default_v6_only_wait")]
+    pub v6_only_wait: u32,
+    /// override where responses for this network are sent
+    pub reply_to: Option<ReplyTo>,
+    /// interface MTU (opt 26) for ranges & reservations that don't set one
+    pub mtu: Option<u16>,
+    /// relay agents allowed to forward requests for this network
+    pub relays: Option<Relays>,
+    /// lease caps for MAC vendor prefixes
+    #[serde(default)]
+    pub oui_limits: Vec<OuiLimit>,
+    /// what happens to a client's leases in other networks when it leases here
+    #[serde(default)]
+    pub multi_network_leases: MultiNetworkPolicy,
+    /// order ranges are tried in
+    #[serde(default)]
+    pub fill: Fill,
+    /// interfaces this network is served on, the interface must have an address in
+    /// the network's subnet
+    pub interfaces: Option<Vec<String>>,
+}
+
+/// Order ranges are tried in when picking an address, see [module docs](self#range-fill)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fill {
+    /// config order
+    #[default]
+    Sequential,
+    /// lowest utilization first
+    Fair,
+    /// most free addresses first
+    MostFreeFirst,
+}
+
+/// A client's leases in other networks once it leases an address in this one, see
+/// [module docs](self#clients-in-multiple-networks)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiNetworkPolicy {
+    /// release them, the client moved
+    #[default]
+    Release,
+    /// keep them, the client may hold leases in several networks
+    Allow,
+}
+
+/// Max leases for clients whose MAC starts with `oui`, see
+/// [module docs](self#oui-lease-limits)
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct OuiLimit {
+    pub oui: Oui,
+    pub max_leases: u32,
+}
+
+/// The first 3 bytes of a MAC address, written as hex with optional `:` or `-`
+/// separators
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Oui(pub [u8; 3]);
+
+impl Oui {
+    /// the OUI of a `chaddr`, `None` if it's too short to be a MAC
+    pub fn from_chaddr(chaddr: &[u8]) -> Option<Self> {
+        (chaddr.len() >= 6).then(|| Self([chaddr[0], chaddr[1], chaddr[2]]))
+    }
+}
+
+impl TryFrom<String> for Oui {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let digits = s.replace([':', '-'], "");
+        hex::decode(&digits)
+            .ok()
+            .and_then(|bytes| <[u8; 3]>::try_from(bytes).ok())
+            .map(Oui)
+            .ok_or_else(|| format!("invalid oui {s:?}, expected 3 hex bytes like 00:11:22"))
+    }
+}
+
+impl From<Oui> for String {
+    fn from(oui: Oui) -> Self {
+        oui.to_string()
+    }
+}
+
+impl std::fmt::Display for Oui {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [a, b, c] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}")
+    }
+}
+
+/// `oui_limits` can't list the same OUI twice
+pub fn validate_oui_limits(limits: &[OuiLimit]) -> Result<()> {
+    let mut seen = std::collections::HashSet::new();
+    for limit in limits {
+        if !seen.insert(limit.oui) {
+            anyhow::bail!("oui {} is listed more than once", limit.oui);
+        }
+    }
+    Ok(())
+}
+
+/// Relay agents allowed to forward requests for a network, see
+/// [module docs](self#relay-access-control). An empty list allows anything
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Relays {
+    #[serde(default)]
+    pub giaddrs: Vec<Exclusion>,
+    #[serde(default)]
+    pub remote_ids: Vec<String>,
+}
+
+/// DHCP header fields for network booting, see [module docs](self#boot-fields)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Boot {
+    /// `siaddr`, the server to boot from
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>, format = "ipv4"))]
+    pub next_server: Option<Ipv4Addr>,
+    /// `sname`
     pub server_name: Option<String>,
+    /// `file`, the boot file name
     pub file_name: Option<String>,
 }
 
+/// RFC 2132 5.1, the smallest MTU a client may be given
+pub const MIN_MTU: u16 = 68;
+
+/// check an `mtu` setting along with the `options` of the same scope, see
+/// [module docs](self#interface-mtu)
+pub fn validate_mtu(mtu: Option<u16>, opts: Option<&Options>) -> Result<()> {
+    let hand_encoded = match opts.and_then(|opts| opts.as_ref().get(OptionCode::InterfaceMtu)) {
+        None => None,
+        Some(DhcpOption::InterfaceMtu(mtu)) => Some(*mtu),
+        Some(_) => anyhow::bail!("option 26 must be a u16, consider using `mtu` instead"),
+    };
+    if mtu.is_some() && hand_encoded.is_some() {
+        anyhow::bail!("`mtu` and option 26 are both set, remove option 26");
+    }
+    if let Some(mtu) = mtu.or(hand_encoded).filter(|mtu| *mtu < MIN_MTU) {
+        anyhow::bail!("mtu {mtu} is below the minimum of {MIN_MTU}");
+    }
+    Ok(())
+}
+
+impl Boot {
+    /// `sname` & `file` are fixed size & null terminated
+    pub const MAX_SERVER_NAME: usize = 63;
+    pub const MAX_FILE_NAME: usize = 127;
+
+    pub fn validate(&self) -> Result<()> {
+        if matches!(&self.server_name, Some(name) if name.len() > Self::MAX_SERVER_NAME) {
+            anyhow::bail!(
+                "server_name must be at most {} bytes",
+                Self::MAX_SERVER_NAME
+            );
+        }
+        if matches!(&self.file_name, Some(name) if name.len() > Self::MAX_FILE_NAME) {
+            anyhow::bail!("file_name must be at most {} bytes", Self::MAX_FILE_NAME);
+        }
+        Ok(())
+    }
+
+    /// fields set in `self`, falling back to `other`
+    pub fn or(&self, other: &Boot) -> Boot {
+        Boot {
+            next_server: self.next_server.or(other.next_server),
+            server_name: self
+                .server_name
+                .clone()
+                .or_else(|| other.server_name.clone()),
+            file_name: self.file_name.clone().or_else(|| other.file_name.clone()),
+        }
+    }
+
+    /// overwrite the header fields that are set, leaving the rest alone
+    pub fn apply(&self, msg: &mut v4::Message) {
+        if let Some(next_server) = self.next_server {
+            msg.set_siaddr(next_server);
+        }
+        if let Some(name) = &self.server_name {
+            msg.set_sname_str(name);
+        }
+        if let Some(name) = &self.file_name {
+            msg.set_fname_str(name);
+        }
+    }
+}
+
+/// `ciaddr`, `broadcast`, or `helper: <ip>`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplyTo {
+    Ciaddr,
+    Broadcast,
+    Helper(Ipv4Addr),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct IpRange {
     // RangeInclusive includes `start`/`end` so flatten will parse those fields
@@ -92,13 +435,104 @@ pub struct IpRange {
     pub options: Options,
     pub config: NetworkConfig,
     #[serde(default)]
-    pub except: Vec<Ipv4Addr>,
+    pub except: Vec<Exclusion>,
     pub class: Option<String>,
+    #[serde(flatten)]
+    pub boot: Boot,
+    /// interface MTU (opt 26), overrides the network `mtu`
+    pub mtu: Option<u16>,
+}
+
+/// Addresses in a range that are never handed out. A single IP, a CIDR block or
+/// a `start`/`end` pair
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Exclusion {
+    Ip(Ipv4Addr),
+    Net(Ipv4Net),
+    Range { start: Ipv4Addr, end: Ipv4Addr },
+}
+
+// untagged, so the schema is spelled out rather than derived
+#[cfg(feature = "openapi")]
+impl<'s> utoipa::ToSchema<'s> for Exclusion {
+    fn schema() -> (
+        &'s str,
+        utoipa::openapi::RefOr<utoipa::openapi::schema::Schema>,
+    ) {
+        use utoipa::openapi::schema::{ObjectBuilder, OneOfBuilder, SchemaFormat, SchemaType};
+
+        let ip = || {
+            ObjectBuilder::new()
+                .schema_type(SchemaType::String)
+                .format(Some(SchemaFormat::Custom("ipv4".to_owned())))
+        };
+        (
+            "Exclusion",
+            OneOfBuilder::new()
+                .description(Some("a single IP, a CIDR block or a `start`/`end` pair"))
+                .item(
+                    ObjectBuilder::new()
+                        .schema_type(SchemaType::String)
+                        .description(Some("IP or CIDR"))
+                        .example(Some("192.168.0.10".into())),
+                )
+                .item(
+                    ObjectBuilder::new()
+                        .property("start", ip())
+                        .required("start")
+                        .property("end", ip())
+                        .required("end"),
+                )
+                .into(),
+        )
+    }
+}
+
+impl From<Exclusion> for RangeInclusive<Ipv4Addr> {
+    fn from(exclusion: Exclusion) -> Self {
+        match exclusion {
+            Exclusion::Ip(ip) => ip..=ip,
+            Exclusion::Net(net) => net.network()..=net.broadcast(),
+            Exclusion::Range { start, end } => start..=end,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct NetworkConfig {
     pub lease_time: MinMax,
+    /// lease never expires, `lease_time` is ignored
+    #[serde(default)]
+    pub infinite: bool,
+    /// T1 (opt 58) as a percentage of the lease time, opt 58 isn't sent if unset
+    pub renew_percent: Option<u8>,
+    /// T2 (opt 59) as a percentage of the lease time, opt 59 isn't sent if unset
+    pub rebind_percent: Option<u8>,
+}
+
+impl NetworkConfig {
+    /// T1 must come before T2, and T2 before the lease ends. Unset values are checked as
+    /// the client defaults, 50% & 87.5%
+    pub fn validate(&self) -> Result<()> {
+        if self.renew_percent == Some(0) {
+            anyhow::bail!("renew_percent must be above 0");
+        }
+        // in tenths of a percent, to compare with the 87.5% default
+        let renew = self.renew_percent.map_or(500, |p| p as u32 * 10);
+        let rebind = self.rebind_percent.map_or(875, |p| p as u32 * 10);
+        if renew >= rebind {
+            anyhow::bail!(
+                "renew_percent ({}%) must be below rebind_percent ({}%)",
+                renew as f32 / 10.,
+                rebind as f32 / 10.
+            );
+        }
+        if rebind >= 1000 {
+            anyhow::bail!("rebind_percent must be below 100");
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
@@ -132,6 +566,8 @@ pub struct ReservedIp {
     pub condition: Condition,
     pub config: NetworkConfig,
     pub class: Option<String>,
+    #[serde(flatten)]
+    pub boot: Boot,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
@@ -140,6 +576,10 @@ pub enum Condition {
     #[serde(rename = "chaddr")]
     Mac(MacAddr),
     Options(Options),
+    /// match on the hostname (opt 12) sent by the client, case-insensitive
+    Hostname(String),
+    /// match on any of the user classes (opt 77) sent by the client
+    UserClass(String),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
@@ -166,6 +606,16 @@ enum Opt {
     B64(String),
     Hex(String),
     SubOption(HashMap<u8, Opt>),
+    Routes(Vec<Route>),
+    /// tz database name, only for TCode (opt 101)
+    Tz(String),
+}
+
+/// a classless static route (opt 121)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct Route {
+    prefix: Ipv4Net,
+    gateway: Ipv4Addr,
 }
 
 impl<'de> serde::Deserialize<'de> for Opts {
@@ -206,7 +656,10 @@ impl<'de> serde::Deserialize<'de> for Opts {
             "ntp_servers" => 42,
             "vendor_extensions" => 43,
             "netbios_name_servers" => 44,
+            "pcode" => 100,
+            "tcode" => 101,
             "domain_search" => 119,
+            "classless_static_routes" => 121,
         };
 
         // inner key type to handle string name or number
@@ -228,12 +681,24 @@ impl<'de> serde::Deserialize<'de> for Opts {
         }
         // decode what was on the wire to a map
         let map: HashMap<OptKey, Opt> = Deserialize::deserialize(de)?;
+        // PCode is derived from a `tz` TCode, unless it's configured
+        let pcode = match map.get(&OptKey(timezone::TCODE)) {
+            Some(Opt::Tz(name)) if !map.contains_key(&OptKey(timezone::PCODE)) => {
+                Some(timezone::parse(name).map_err(de::Error::custom)?)
+            }
+            _ => None,
+        };
         // we'll encode the map to buf so we can use DhcpOptions::decode
         let mut buf = vec![];
         let mut enc = Encoder::new(&mut buf);
         for (code, opt) in map {
             write_opt(&mut enc, code.0, opt).map_err(de::Error::custom)?;
         }
+        if let Some(tz) = pcode {
+            let posix = timezone::posix(tz, Utc::now().year());
+            write_opt(&mut enc, timezone::PCODE, Opt::Str(MaybeList::Val(posix)))
+                .map_err(de::Error::custom)?;
+        }
         // write `End` so DhcpOptions can decode
         enc.write_u8(OptionCode::End.into())
             .map_err(de::Error::custom)?;
@@ -390,6 +855,26 @@ fn write_opt(enc: &mut Encoder<'_>, code: u8, opt: Opt) -> anyhow::Result<()> {
 
             v4::encode_long_opt_bytes(OptionCode::from(code), &sub_buf, enc)?;
         }
+        // RFC 3442, each route is encoded as:
+        // [prefix len][significant octets of prefix][gateway]
+        Opt::Routes(routes) => {
+            let mut buf = Vec::with_capacity(routes.len() * 9);
+            for Route { prefix, gateway } in routes {
+                let len = prefix.prefix_len();
+                buf.push(len);
+                buf.extend_from_slice(&prefix.network().octets()[..len.div_ceil(8) as usize]);
+                buf.extend_from_slice(&gateway.octets());
+            }
+            v4::encode_long_opt_bytes(OptionCode::from(code), &buf, enc)?;
+        }
+        Opt::Tz(name) => {
+            ensure!(
+                code == timezone::TCODE,
+                "type tz is only valid for tcode (opt 101), not opt {code}"
+            );
+            timezone::parse(&name)?;
+            v4::encode_long_opt_bytes(OptionCode::from(code), name.as_bytes(), enc)?;
+        }
     }
     Ok(())
 }
@@ -494,7 +979,7 @@ fn to_opt(code: &OptionCode, opt: &DhcpOption) -> Option<(u8, Opt)> {
 }
 
 pub mod ddns {
-    use std::net::SocketAddr;
+    use std::{net::SocketAddr, num::NonZeroU32, time::Duration};
 
     use super::*;
 
@@ -519,6 +1004,38 @@ pub mod ddns {
         pub forward: Vec<DdnsServer>,
         pub reverse: Vec<DdnsServer>,
         pub tsig_keys: HashMap<String, TsigKey>,
+        /// skip an update if the same record was sent less than its TTL ago, so clients
+        /// that renew often or flap don't repeat it
+        #[serde(default = "default_true")]
+        pub coalesce: bool,
+        /// max updates sent to each zone in a period, any more are skipped
+        pub zone_rate_limit: Option<ZoneRateLimit>,
+        /// how long to wait for a DNS server to answer an update
+        #[serde(default = "default_timeout_ms")]
+        pub timeout_ms: u64,
+        /// times a failed update is sent again
+        #[serde(default = "default_retries")]
+        pub retries: u32,
+    }
+
+    fn default_timeout_ms() -> u64 {
+        5_000
+    }
+    fn default_retries() -> u32 {
+        2
+    }
+
+    /// `updates` allowed per zone every `secs`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+    pub struct ZoneRateLimit {
+        pub updates: NonZeroU32,
+        pub secs: NonZeroU32,
+    }
+
+    impl ZoneRateLimit {
+        pub fn period(&self) -> Duration {
+            Duration::from_secs(self.secs.get() as u64)
+        }
     }
 
     impl Default for Ddns {
@@ -530,6 +1047,10 @@ pub mod ddns {
                 forward: Vec::new(),
                 reverse: Vec::new(),
                 tsig_keys: HashMap::default(),
+                coalesce: true,
+                zone_rate_limit: None,
+                timeout_ms: default_timeout_ms(),
+                retries: default_retries(),
             }
         }
     }
@@ -539,13 +1060,18 @@ pub mod ddns {
         pub name: Name,
         pub key: Option<String>,
         pub ip: SocketAddr,
+        /// RFC 2317, for a reverse zone delegated for a subnet smaller than a /24. `name`
+        /// is the delegated zone, ex. `0-63.2.0.192.in-addr.arpa.` for `192.0.2.0/26`. Names
+        /// must be valid hostname labels, so use `-` rather than RFC 2317's `/`
+        pub classless: Option<Ipv4Net>,
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
     pub struct TsigKey {
         #[serde(with = "tsig_algo")]
         pub algorithm: TsigAlgorithm,
-        pub data: String,
+        /// b64 key data, inline or a `${env:VAR}`/`${file:/path}` reference
+        pub data: crate::wire::Secret,
     }
 
     mod tsig_algo {
@@ -624,6 +1150,18 @@ pub mod ddns {
         pub fn override_no_updates(&self) -> bool {
             self.override_no_updates
         }
+        pub fn coalesce(&self) -> bool {
+            self.coalesce
+        }
+        pub fn zone_rate_limit(&self) -> Option<&ZoneRateLimit> {
+            self.zone_rate_limit.as_ref()
+        }
+        pub fn timeout(&self) -> Duration {
+            Duration::from_millis(self.timeout_ms)
+        }
+        pub fn retries(&self) -> u32 {
+            self.retries
+        }
         pub fn keys(&self) -> impl Iterator<Item = (&str, &TsigKey)> {
             self.tsig_keys.iter().map(|(name, k)| (name.as_str(), k))
         }
@@ -642,6 +1180,27 @@ pub mod ddns {
         pub fn match_longest_reverse(&self, arpa_domain: &Name) -> Option<&DdnsServer> {
             match_longest_fqdn(&self.reverse, arpa_domain)
         }
+        /// the classless (RFC 2317) reverse zone with the longest prefix containing `ip`
+        pub fn match_classless(&self, ip: Ipv4Addr) -> Option<&DdnsServer> {
+            self.reverse
+                .iter()
+                .filter(|srv| matches!(srv.classless, Some(net) if net.contains(&ip)))
+                .max_by_key(|srv| srv.classless.map(|net| net.prefix_len()))
+        }
+        /// classless reverse zones must be for subnets smaller than a /24
+        pub fn validate(&self) -> Result<()> {
+            for srv in &self.reverse {
+                if let Some(net) = srv.classless {
+                    if net.prefix_len() <= 24 {
+                        anyhow::bail!(
+                            "classless reverse zone {} is for {net}, it must be smaller than a /24",
+                            srv.name
+                        );
+                    }
+                }
+            }
+            Ok(())
+        }
     }
 
     fn match_longest_fqdn<'a>(list: &'a [DdnsServer], fqdn: &Name) -> Option<&'a DdnsServer> {
@@ -689,7 +1248,6 @@ pub mod ddns {
 mod tests {
     use super::ddns::*;
     use super::*;
-    use ipnet::Ipv4Net;
 
     use dora_core::dhcproto::Name;
 
@@ -712,26 +1270,31 @@ mod tests {
                     name: "example.com.".parse().unwrap(),
                     key: None,
                     ip: ([8, 8, 8, 8], 53).into(),
+                    classless: None,
                 },
                 DdnsServer {
                     name: "other.example.com.".parse().unwrap(),
                     key: None,
                     ip: ([8, 8, 8, 8], 53).into(),
+                    classless: None,
                 },
                 DdnsServer {
                     name: "foo.example.com.".parse().unwrap(),
                     key: None,
                     ip: ([8, 8, 8, 8], 53).into(),
+                    classless: None,
                 },
                 DdnsServer {
                     name: "a.baz.foo.example.com.".parse().unwrap(),
                     key: None,
                     ip: ([8, 8, 8, 8], 53).into(),
+                    classless: None,
                 },
                 DdnsServer {
                     name: "bing.net.".parse().unwrap(),
                     key: None,
                     ip: ([8, 8, 8, 8], 53).into(),
+                    classless: None,
                 },
             ],
             ..Ddns::default()
@@ -743,6 +1306,7 @@ mod tests {
                 name: "example.com.".parse().unwrap(),
                 key: None,
                 ip: ([8, 8, 8, 8], 53).into(),
+                classless: None,
             }
         );
         let fwd = ddns.match_longest_forward(&"other.example.com.".parse::<Name>().unwrap());
@@ -752,6 +1316,7 @@ mod tests {
                 name: "other.example.com.".parse().unwrap(),
                 key: None,
                 ip: ([8, 8, 8, 8], 53).into(),
+                classless: None,
             }
         );
         let fwd = ddns.match_longest_forward(&"b.foo.example.com.".parse::<Name>().unwrap());
@@ -761,6 +1326,7 @@ mod tests {
                 name: "foo.example.com.".parse().unwrap(),
                 key: None,
                 ip: ([8, 8, 8, 8], 53).into(),
+                classless: None,
             }
         );
         let fwd = ddns.match_longest_forward(&"bang.net.".parse::<Name>().unwrap());
@@ -775,11 +1341,13 @@ mod tests {
                     name: "example.com.".parse().unwrap(),
                     key: None,
                     ip: ([8, 8, 8, 8], 53).into(),
+                    classless: None,
                 },
                 DdnsServer {
                     name: "*".parse().unwrap(),
                     key: None,
                     ip: ([8, 8, 8, 8], 53).into(),
+                    classless: None,
                 },
             ],
             ..Ddns::default()
@@ -791,6 +1359,7 @@ mod tests {
                 name: "*".parse().unwrap(),
                 key: None,
                 ip: ([8, 8, 8, 8], 53).into(),
+                classless: None,
             }
         );
     }
@@ -803,16 +1372,19 @@ mod tests {
                     name: "8.8.8.8.in-addr.arpa.".parse().unwrap(),
                     key: None,
                     ip: ([8, 8, 8, 8], 53).into(),
+                    classless: None,
                 },
                 DdnsServer {
                     name: "168.192.in-addr.arpa.".parse().unwrap(),
                     key: None,
                     ip: ([8, 8, 8, 8], 53).into(),
+                    classless: None,
                 },
                 DdnsServer {
                     name: "1.192.in-addr.arpa.".parse().unwrap(),
                     key: None,
                     ip: ([8, 8, 8, 8], 53).into(),
+                    classless: None,
                 },
             ],
             ..Ddns::default()
@@ -824,6 +1396,7 @@ mod tests {
                 name: "168.192.in-addr.arpa.".parse().unwrap(),
                 key: None,
                 ip: ([8, 8, 8, 8], 53).into(),
+                classless: None,
             }
         );
 
@@ -840,10 +1413,46 @@ mod tests {
                 name: "1.192.in-addr.arpa.".parse().unwrap(),
                 key: None,
                 ip: ([8, 8, 8, 8], 53).into(),
+                classless: None,
             }
         );
     }
 
+    #[test]
+    fn test_classless_reverse() {
+        let srv = |name: &str, classless: Option<&str>| DdnsServer {
+            name: name.parse().unwrap(),
+            key: None,
+            ip: ([8, 8, 8, 8], 53).into(),
+            classless: classless.map(|net| net.parse().unwrap()),
+        };
+        let ddns = Ddns {
+            reverse: vec![
+                srv("2.0.192.in-addr.arpa.", None),
+                srv("0-63.2.0.192.in-addr.arpa.", Some("192.0.2.0/26")),
+                srv("0-15.2.0.192.in-addr.arpa.", Some("192.0.2.0/28")),
+            ],
+            ..Ddns::default()
+        };
+        assert!(ddns.validate().is_ok());
+        // the longest prefix wins
+        assert_eq!(
+            ddns.match_classless([192, 0, 2, 5].into()).unwrap().name,
+            "0-15.2.0.192.in-addr.arpa.".parse::<Name>().unwrap()
+        );
+        assert_eq!(
+            ddns.match_classless([192, 0, 2, 20].into()).unwrap().name,
+            "0-63.2.0.192.in-addr.arpa.".parse::<Name>().unwrap()
+        );
+        assert_eq!(ddns.match_classless([192, 0, 2, 100].into()), None);
+
+        let ddns = Ddns {
+            reverse: vec![srv("2.0.192.in-addr.arpa.", Some("192.0.2.0/24"))],
+            ..Ddns::default()
+        };
+        assert!(ddns.validate().is_err());
+    }
+
     // test we can encode/decode sample
     #[test]
     fn test_sample() {
@@ -872,4 +1481,112 @@ mod tests {
         println!("{vendor:?}");
         // TODO: add test for sub-opts in vendor extensions
     }
+
+    #[test]
+    fn test_domain_search() {
+        let opts: Opts = serde_yaml::from_str(
+            r#"
+domain_search:
+    type: domain
+    value: ["example.com.", "foo.example.com."]
+"#,
+        )
+        .unwrap();
+        // second name is compressed with a pointer to the start of the option data
+        assert_eq!(
+            opts.0.get(OptionCode::from(119)).unwrap().to_vec().unwrap(),
+            [
+                &[119, 19][..],
+                b"\x07example\x03com\x00",
+                b"\x03foo\xc0\x00"
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_classless_static_routes() {
+        let opts: Opts = serde_yaml::from_str(
+            r#"
+classless_static_routes:
+    type: routes
+    value:
+        - prefix: 0.0.0.0/0
+          gateway: 192.168.0.1
+        - prefix: 10.0.0.0/8
+          gateway: 192.168.0.2
+        - prefix: 10.10.128.0/20
+          gateway: 192.168.0.3
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            opts.0.get(OptionCode::from(121)).unwrap().to_vec().unwrap(),
+            vec![
+                121, 19, // code & len
+                0, 192, 168, 0, 1, // default route
+                8, 10, 192, 168, 0, 2, // 10.0.0.0/8
+                20, 10, 10, 128, 192, 168, 0, 3 // 10.10.128.0/20
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timezone() {
+        let opts: Opts = serde_yaml::from_str(
+            r#"
+tcode:
+    type: tz
+    value: America/New_York
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            opts.0.get(OptionCode::from(101)).unwrap().to_vec().unwrap(),
+            [&[101, 16][..], b"America/New_York"].concat()
+        );
+        assert_eq!(
+            opts.0.get(OptionCode::from(100)).unwrap().to_vec().unwrap(),
+            [&[100, 22][..], b"EST5EDT,M3.2.0,M11.1.0"].concat()
+        );
+
+        // a configured pcode is kept
+        let opts: Opts = serde_yaml::from_str(
+            r#"
+100:
+    type: str
+    value: EST5
+101:
+    type: tz
+    value: America/New_York
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            opts.0.get(OptionCode::from(100)).unwrap().to_vec().unwrap(),
+            [&[100, 4][..], b"EST5"].concat()
+        );
+
+        for bad in [
+            "tcode:\n    type: tz\n    value: America/Nowhere",
+            "42:\n    type: tz\n    value: America/New_York",
+        ] {
+            assert!(serde_yaml::from_str::<Opts>(bad).is_err());
+        }
+    }
+
+    #[test]
+    fn test_reply_to() {
+        let net: Net = serde_yaml::from_str("reply_to: broadcast").unwrap();
+        assert_eq!(net.reply_to, Some(ReplyTo::Broadcast));
+
+        let net: Net = serde_yaml::from_str("reply_to:\n  helper: 10.0.0.1").unwrap();
+        assert_eq!(
+            net.reply_to,
+            Some(ReplyTo::Helper(Ipv4Addr::new(10, 0, 0, 1)))
+        );
+
+        let net: Net = serde_yaml::from_str("ping_check: true").unwrap();
+        assert_eq!(net.reply_to, None);
+    }
 }
@@ -23,6 +23,33 @@ pub struct Config {
     // TODO: better defaults than blank? pull information from the system
     #[serde(default)]
     pub options: Option<Options>,
+    /// answer a Solicit carrying the Rapid Commit option with a Reply instead of an
+    /// Advertise. Independent of the v4 `rapid_commit`
+    #[serde(default)]
+    pub rapid_commit: bool,
+    /// settings for individual interfaces, by name
+    #[serde(default)]
+    pub interface_overrides: HashMap<String, InterfaceOverride>,
+    /// accept address registrations from SLAAC clients (RFC 9686), off if not present
+    #[serde(default)]
+    pub addr_registration: Option<AddrRegistration>,
+}
+
+/// RFC 9686 address registration
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct AddrRegistration {
+    /// add AAAA & PTR records for registered addresses. v6 has no `ddns` section, the
+    /// top-level `ddns` servers & keys used for v4 leases are used. Only done for clients
+    /// that send a Client FQDN option
+    #[serde(default)]
+    pub ddns: bool,
+}
+
+/// v6 settings that can be changed for a single interface, unset fields use the `v6`
+/// level setting
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct InterfaceOverride {
+    pub rapid_commit: Option<bool>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -44,6 +71,9 @@ pub struct Net {
     /// Whether we are authoritative for this network (default: true)
     #[serde(default = "super::default_authoritative")]
     pub authoritative: bool,
+    /// address pools on the network
+    #[serde(default)]
+    pub ranges: Vec<IpRange>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -62,6 +92,7 @@ impl Default for DuidType {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 #[serde(tag = "type")]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum ServerDuidInfo {
     LLT {
         #[serde(default)]
@@ -119,10 +150,31 @@ pub struct IpRange {
     // RangeInclusive includes `start`/`end` so flatten will parse those fields
     #[serde(flatten)]
     pub range: RangeInclusive<Ipv6Addr>,
+    #[serde(default)]
     pub options: Options,
     pub config: NetworkConfig,
     #[serde(default)]
-    pub except: Vec<Ipv6Addr>,
+    pub except: Vec<Exclusion>,
+    pub class: Option<String>,
+}
+
+/// an excluded address, a subnet, or a `start`/`end` pair
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Exclusion {
+    Ip(Ipv6Addr),
+    Net(Ipv6Net),
+    Range { start: Ipv6Addr, end: Ipv6Addr },
+}
+
+impl From<Exclusion> for RangeInclusive<Ipv6Addr> {
+    fn from(exclusion: Exclusion) -> Self {
+        match exclusion {
+            Exclusion::Ip(ip) => ip..=ip,
+            Exclusion::Net(net) => net.network()..=net.broadcast(),
+            Exclusion::Range { start, end } => start..=end,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
@@ -1,4 +1,4 @@
-use std::{fs::File, io::Read, str::FromStr};
+use std::{fs::File, io::Read, str::FromStr, time::Duration};
 
 use config::wire::v4::ddns::TsigAlgorithm;
 use ddns::{
@@ -39,7 +39,7 @@ async fn main() -> Result<()> {
         anyhow::bail!("failed to create tsigner")
     };
 
-    let mut client = Updater::new(([127, 0, 0, 1], 53).into(), None).await?;
+    let client = Updater::new(([127, 0, 0, 1], 53).into(), None, Duration::from_secs(5), 0).await?;
     // forward
     dbg!(
         client
@@ -2,7 +2,7 @@ use dora_core::dhcproto::{v4::HType, Name, NameError};
 use ring::digest::{Context, SHA256};
 use trust_dns_client::serialize::binary::BinEncoder;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct DhcId {
     ty: IdType,
     id: Vec<u8>,
@@ -1,8 +1,16 @@
 #![allow(clippy::too_many_arguments)]
 
-use std::{net::Ipv4Addr, str::FromStr};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use config::v4::{Ddns, NetRange};
+use config::{
+    v4::{Ddns, NetRange},
+    wire::v4::ddns::DdnsServer,
+};
 use dora_core::{
     dhcproto::{
         v4::{
@@ -12,20 +20,31 @@ use dora_core::{
         },
         Name, NameError,
     },
+    metrics,
     prelude::MsgContext,
-    tracing::{debug, error, info},
+    tracing::{debug, error, info, warn},
 };
 use trust_dns_client::rr::dnssec::tsig::TSigner;
 
 pub mod dhcid;
+mod throttle;
 pub mod update;
 
 use dhcid::DhcId;
 
-use crate::update::Updater;
+use crate::{
+    throttle::{Sent, Throttle},
+    update::{Pool, UpdateError, Updater},
+};
 
+/// Sends DDNS updates. Repeated updates are coalesced & zones are rate limited as the
+/// `ddns` config asks, and connections to DNS servers are reused, so the same
+/// `DdnsUpdate` should be kept for every update
 #[derive(Debug, Default)]
-pub struct DdnsUpdate;
+pub struct DdnsUpdate {
+    throttle: Throttle,
+    pool: Pool,
+}
 
 #[derive(thiserror::Error, Debug)]
 pub enum DdnsError {
@@ -51,7 +70,7 @@ pub enum Action<'a> {
 
 impl DdnsUpdate {
     pub fn new() -> Self {
-        Self
+        Self::default()
     }
     pub async fn update(
         &self,
@@ -181,54 +200,113 @@ impl DdnsUpdate {
             error!("address lease time not available for DDNS update");
             return Err(DdnsError::SendFailed);
         };
+        self.send(
+            cfg,
+            duid,
+            leased.into(),
+            domain,
+            forward,
+            reverse,
+            *lease_length,
+        )
+        .await
+    }
+
+    /// add AAAA & PTR records for an address a SLAAC client registered (RFC 9686), the
+    /// TTL is derived from the address's valid lifetime
+    pub async fn register_v6(
+        &self,
+        cfg: &Ddns,
+        duid: DhcId,
+        domain: Name,
+        addr: Ipv6Addr,
+        valid_lifetime: u32,
+    ) -> Result<(), DdnsError> {
+        if !cfg.enable_updates() {
+            info!("got address registration but DDNS updates are disabled. No update performed");
+            return Err(DdnsError::NoUpdate);
+        }
+        if domain.is_empty() {
+            error!(?domain, "registered FQDN was empty. No update performed");
+            return Err(DdnsError::NoUpdate);
+        }
+        self.send(cfg, duid, addr.into(), domain, true, true, valid_lifetime)
+            .await
+    }
+
+    async fn send(
+        &self,
+        cfg: &Ddns,
+        duid: DhcId,
+        leased: IpAddr,
+        domain: Name,
+        forward: bool,
+        reverse: bool,
+        lease_length: u32,
+    ) -> Result<(), DdnsError> {
+        let ttl = Duration::from_secs(update::calculate_ttl(lease_length) as u64);
+        let content = |reverse| Sent {
+            reverse,
+            name: domain.clone(),
+            addr: leased,
+            dhcid: duid.clone(),
+        };
         if forward {
-            if let Some(srv) = cfg.match_longest_forward(&domain) {
-                let tsig = if let Some(key_name) = &srv.key {
-                    Some(tsigner(key_name, cfg)?)
-                } else {
-                    None
-                };
+            let sent = content(false);
+            if let Some(srv) = cfg
+                .match_longest_forward(&domain)
+                .filter(|srv| !self.skip(cfg, &srv.name, &sent))
+            {
                 let zone = srv.name.clone();
-                // todo: likely re-creating the same client for each update
-                // should cache this in parent type
-                let mut client = Updater::new(srv.ip, tsig).await?;
+                let client = self.updater(cfg, srv).await?;
 
                 // todo: zone origin same as domain?
                 match client
-                    .forward(zone, domain.clone(), duid.clone(), leased, *lease_length)
+                    .forward(zone, domain.clone(), duid.clone(), leased, lease_length)
                     .await
                 {
                     Ok(_) => {
                         info!(?domain, "successfully updated DNS");
+                        self.throttle.record(sent, ttl, Instant::now());
                     }
                     Err(err) => {
                         error!(?err, ?domain, "failed to update DNS");
+                        self.evict(srv, &err);
                     }
                 }
             }
         }
         if reverse {
+            let sent = content(true);
             let rev_ip = crate::update::reverse_ip(leased);
             let arpa_name = Name::from_str(&rev_ip).unwrap();
-            if let Some(srv) = cfg.match_longest_reverse(&arpa_name) {
-                let tsig = if let Some(key_name) = &srv.key {
-                    Some(tsigner(key_name, cfg)?)
-                } else {
-                    None
-                };
+            // RFC 2317 zones are checked first, the PTR is owned by a name in the delegated zone
+            let classless = match leased {
+                IpAddr::V4(ip) => cfg.match_classless(ip).map(|srv| (srv, ip)),
+                IpAddr::V6(_) => None,
+            };
+            let target = match classless {
+                Some((srv, ip)) => Some((srv, crate::update::classless_reverse(ip, &srv.name)?)),
+                None => cfg
+                    .match_longest_reverse(&arpa_name)
+                    .map(|srv| (srv, arpa_name)),
+            };
+            if let Some((srv, owner)) = target.filter(|(srv, _)| !self.skip(cfg, &srv.name, &sent))
+            {
                 let zone = srv.name.clone();
-                // todo: should cache this in parent type
-                let mut client = Updater::new(srv.ip, tsig).await?;
+                let client = self.updater(cfg, srv).await?;
 
                 match client
-                    .reverse(zone, domain.clone(), duid.clone(), leased, *lease_length)
+                    .reverse(zone, owner, domain.clone(), duid.clone(), lease_length)
                     .await
                 {
                     Ok(_) => {
                         info!(?domain, "successfully updated DNS");
+                        self.throttle.record(sent, ttl, Instant::now());
                     }
                     Err(err) => {
                         error!(?err, ?domain, "failed to update DNS");
+                        self.evict(srv, &err);
                     }
                 }
             }
@@ -236,6 +314,58 @@ impl DdnsUpdate {
 
         Ok(())
     }
+
+    /// the pooled updater for `srv`, connecting a new one if there isn't one
+    async fn updater(&self, cfg: &Ddns, srv: &DdnsServer) -> Result<Arc<Updater>, DdnsError> {
+        let key = srv.key.as_deref();
+        if let Some(updater) = self.pool.get(srv.ip, key) {
+            return Ok(updater);
+        }
+        let tsig = key.map(|key_name| tsigner(key_name, cfg)).transpose()?;
+        let updater = Arc::new(Updater::new(srv.ip, tsig, cfg.timeout(), cfg.retries()).await?);
+        self.pool.insert(key, updater.clone());
+        Ok(updater)
+    }
+
+    /// drop the updater for `srv` if `err` means the server couldn't be reached, rather
+    /// than it refusing the update
+    fn evict(&self, srv: &DdnsServer, err: &UpdateError) {
+        if !matches!(err, UpdateError::ResponseCode(_)) {
+            self.pool.remove(srv.ip, srv.key.as_deref());
+        }
+    }
+
+    /// whether to skip sending `sent` to `zone`, because the same record was just sent or
+    /// the zone is over its rate limit
+    fn skip(&self, cfg: &Ddns, zone: &Name, sent: &Sent) -> bool {
+        let now = Instant::now();
+        if cfg.coalesce() && self.throttle.is_recent(sent, now) {
+            metrics::DDNS_SKIPPED_COUNT
+                .with_label_values(&["coalesced"])
+                .inc();
+            debug!(
+                domain = ?sent.name,
+                addr = ?sent.addr,
+                reverse = sent.reverse,
+                "same record sent within its TTL, skipping update"
+            );
+            return true;
+        }
+        if let Some(limit) = cfg.zone_rate_limit() {
+            if !self.throttle.allow(zone, limit, now) {
+                metrics::DDNS_SKIPPED_COUNT
+                    .with_label_values(&["rate_limited"])
+                    .inc();
+                warn!(
+                    ?zone,
+                    domain = ?sent.name,
+                    "zone is over its DDNS rate limit, skipping update"
+                );
+                return true;
+            }
+        }
+        false
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -255,7 +385,7 @@ pub fn tsigner(key_name: &str, config: &Ddns) -> Result<TSigner, TsigError> {
     };
     // create new tsigner
     Ok(TSigner::new(
-        key.data.as_bytes().to_owned(),
+        key.data.expose().as_bytes().to_owned(),
         key.algorithm.clone(),
         Name::from_ascii(key_name).unwrap(), // TODO: remove unwrap
         // ??
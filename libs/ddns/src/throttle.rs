@@ -0,0 +1,145 @@
+//! # throttle
+//!
+//! A client that renews often or flaps between states asks for the same update over
+//! and over. Records that were sent are remembered until their TTL runs out, an update
+//! with the same name, address & DHCID in that window is coalesced into the one already
+//! sent. Independently, each zone can be limited to a number of updates per period.
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use config::wire::v4::ddns::ZoneRateLimit;
+use dora_core::dhcproto::Name;
+
+use crate::dhcid::DhcId;
+
+/// once this many records are remembered, expired ones are cleared out
+const MAX_SENT: usize = 10_000;
+
+/// the content of an update
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Sent {
+    pub(crate) reverse: bool,
+    pub(crate) name: Name,
+    pub(crate) addr: IpAddr,
+    pub(crate) dhcid: DhcId,
+}
+
+/// updates counted towards a zone's limit since `start`
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Throttle {
+    /// when each sent record's TTL runs out
+    sent: Mutex<HashMap<Sent, Instant>>,
+    zones: Mutex<HashMap<Name, Window>>,
+}
+
+impl Throttle {
+    /// whether the same record was sent & its TTL hasn't run out at `now`
+    pub(crate) fn is_recent(&self, sent: &Sent, now: Instant) -> bool {
+        matches!(self.sent.lock().unwrap().get(sent), Some(until) if *until > now)
+    }
+
+    /// remember `sent` went out at `now` with `ttl`
+    pub(crate) fn record(&self, sent: Sent, ttl: Duration, now: Instant) {
+        let mut recent = self.sent.lock().unwrap();
+        if recent.len() >= MAX_SENT {
+            recent.retain(|_, until| *until > now);
+        }
+        recent.insert(sent, now + ttl);
+    }
+
+    /// count an update to `zone` at `now`, `false` if the zone is over `limit`
+    pub(crate) fn allow(&self, zone: &Name, limit: &ZoneRateLimit, now: Instant) -> bool {
+        let mut zones = self.zones.lock().unwrap();
+        let window = zones.entry(zone.clone()).or_insert(Window {
+            start: now,
+            count: 0,
+        });
+        if now.saturating_duration_since(window.start) >= limit.period() {
+            *window = Window {
+                start: now,
+                count: 0,
+            };
+        }
+        if window.count >= limit.updates.get() {
+            return false;
+        }
+        window.count += 1;
+        true
+    }
+
+    /// number of records remembered
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.sent.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::*;
+
+    fn sent(addr: [u8; 4]) -> Sent {
+        Sent {
+            reverse: false,
+            name: "host.example.com.".parse().unwrap(),
+            addr: addr.into(),
+            dhcid: DhcId::chaddr(vec![1, 2, 3, 4, 5, 6]),
+        }
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let throttle = Throttle::default();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(600);
+        let a = sent([192, 168, 0, 10]);
+        assert!(!throttle.is_recent(&a, now));
+
+        throttle.record(a.clone(), ttl, now);
+        assert!(throttle.is_recent(&a, now + Duration::from_secs(599)));
+        // new address, the record changed
+        assert!(!throttle.is_recent(&sent([192, 168, 0, 11]), now));
+        // the TTL ran out
+        assert!(!throttle.is_recent(&a, now + ttl));
+
+        // expired records are cleared once there are enough of them
+        for i in 0..MAX_SENT {
+            let mut s = sent([10, 0, 0, 1]);
+            s.dhcid = DhcId::chaddr((i as u64).to_be_bytes().to_vec());
+            throttle.record(s, ttl, now);
+        }
+        throttle.record(a, ttl, now + ttl);
+        assert_eq!(throttle.len(), 1);
+    }
+
+    #[test]
+    fn test_zone_rate_limit() {
+        let throttle = Throttle::default();
+        let now = Instant::now();
+        let limit = ZoneRateLimit {
+            updates: NonZeroU32::new(2).unwrap(),
+            secs: NonZeroU32::new(10).unwrap(),
+        };
+        let zone: Name = "example.com.".parse().unwrap();
+        let other: Name = "other.com.".parse().unwrap();
+        assert!(throttle.allow(&zone, &limit, now));
+        assert!(throttle.allow(&zone, &limit, now + Duration::from_secs(1)));
+        assert!(!throttle.allow(&zone, &limit, now + Duration::from_secs(2)));
+        // zones are counted separately
+        assert!(throttle.allow(&other, &limit, now + Duration::from_secs(2)));
+        // next period
+        assert!(throttle.allow(&zone, &limit, now + Duration::from_secs(10)));
+    }
+}
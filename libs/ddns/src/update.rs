@@ -1,48 +1,114 @@
 use std::{
+    collections::HashMap,
+    fmt,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, Mutex},
     time::Duration,
 };
 
 use dora_core::{
     dhcproto::{Name, NameError},
     tokio,
-    tokio::{net::UdpSocket, task::JoinHandle},
+    tokio::{
+        net::{TcpStream, UdpSocket},
+        task::JoinHandle,
+    },
     tracing::{debug, error, info},
     trust_dns_proto::{xfer::FirstAnswer, DnsHandle},
 };
 use trust_dns_client::{
-    client::AsyncClient, op::ResponseCode, rr::dnssec::tsig::TSigner, udp::UdpClientStream,
+    client::AsyncClient,
+    op::{Message, ResponseCode},
+    proto::{
+        iocompat::AsyncIoTokioAsStd,
+        xfer::{DnsMultiplexer, DnsResponse},
+    },
+    rr::dnssec::tsig::TSigner,
+    serialize::binary::BinEncodable,
+    tcp::TcpClientStream,
+    udp::UdpClientStream,
 };
 
 use crate::dhcid::DhcId;
 
+/// updates longer than this go over TCP: the 512 byte UDP limit, less room for the TSIG
+/// record that's added when the update is signed
+const MAX_UDP_LEN: usize = 400;
+
+/// Sends updates to one DNS server. Updates go over UDP unless they're too large for a
+/// UDP message or the response comes back truncated, then TCP is used. Failed sends are
+/// retried `retries` times
 pub struct Updater {
+    dst: SocketAddr,
+    tsig: Option<Arc<TSigner>>,
+    timeout: Duration,
+    retries: u32,
+    udp: AsyncClient,
+    handle: JoinHandle<Result<(), NameError>>,
+    /// connected the first time TCP is needed, & again if the connection closes
+    tcp: tokio::sync::Mutex<Option<TcpConn>>,
+}
+
+struct TcpConn {
     client: AsyncClient,
     handle: JoinHandle<Result<(), NameError>>,
 }
 
+impl TcpConn {
+    async fn connect(
+        dst: SocketAddr,
+        tsig: Option<Arc<TSigner>>,
+        timeout: Duration,
+    ) -> Result<Self, UpdateError> {
+        let (stream, sender) =
+            TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::with_timeout(dst, timeout);
+        let multiplexer = DnsMultiplexer::with_timeout(stream, sender, timeout, tsig);
+        let (client, bg) = AsyncClient::connect(multiplexer).await?;
+        let handle = tokio::spawn(bg);
+        Ok(Self { client, handle })
+    }
+}
+
+impl Drop for TcpConn {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
 impl Updater {
-    pub async fn new(dst: SocketAddr, tsig: Option<TSigner>) -> Result<Self, UpdateError> {
-        // todo: create stream per forward/reverse server
+    pub async fn new(
+        dst: SocketAddr,
+        tsig: Option<TSigner>,
+        timeout: Duration,
+        retries: u32,
+    ) -> Result<Self, UpdateError> {
+        let tsig = tsig.map(Arc::new);
         let stream = UdpClientStream::<UdpSocket, TSigner>::with_timeout_and_signer_and_bind_addr(
             dst,
-            Duration::from_secs(5),
-            tsig.map(Arc::new),
+            timeout,
+            tsig.clone(),
             None,
         );
-        let (client, bg) = AsyncClient::connect(stream).await?;
+        let (udp, bg) = AsyncClient::connect(stream).await?;
         let handle = tokio::spawn(bg);
 
-        Ok(Self { client, handle })
+        Ok(Self {
+            dst,
+            tsig,
+            timeout,
+            retries,
+            udp,
+            handle,
+            tcp: tokio::sync::Mutex::new(None),
+        })
     }
     pub async fn forward(
-        &mut self,
+        &self,
         zone: Name,
         domain: Name,
         duid: DhcId,
-        leased: Ipv4Addr,
+        leased: IpAddr,
         lease_length: u32,
     ) -> Result<(), UpdateError> {
         let ttl = calculate_ttl(lease_length);
@@ -55,13 +121,13 @@ impl Updater {
             ttl,
             false,
         )?;
-        let resp = self.client.send(message).first_answer().await?;
+        let resp = self.send(message).await?;
         if resp.response_code() == ResponseCode::NoError {
             Ok(())
         } else if resp.response_code() == ResponseCode::YXDomain {
             debug!(?resp, "got back YXDOMAIN, sending update with dhcid prereq");
             let new_msg = update_present(zone.clone(), domain.clone(), duid, leased, ttl, false)?;
-            let yx_resp = self.client.send(new_msg).first_answer().await?;
+            let yx_resp = self.send(new_msg).await?;
             if yx_resp.response_code() == ResponseCode::NoError {
                 info!(?domain, "got NOERROR, updated DNS");
                 Ok(())
@@ -73,24 +139,75 @@ impl Updater {
             Err(UpdateError::ResponseCode(resp.response_code()))
         }
     }
+    /// replace the PTR at `owner`, the name `reverse_ip` or `classless_reverse` gives
     pub async fn reverse(
-        &mut self,
+        &self,
         zone: Name,
+        owner: Name,
         domain: Name,
         duid: DhcId,
-        leased: Ipv4Addr,
         lease_length: u32,
     ) -> Result<(), UpdateError> {
         let ttl = calculate_ttl(lease_length);
 
-        let message = delete(zone, domain.clone(), duid.clone(), leased, ttl, false)?;
-        let resp = self.client.send(message).first_answer().await?;
+        let message = delete(zone, owner, domain.clone(), duid.clone(), ttl, false)?;
+        let resp = self.send(message).await?;
         if resp.response_code() == ResponseCode::NoError {
             Ok(())
         } else {
             Err(UpdateError::ResponseCode(resp.response_code()))
         }
     }
+
+    /// send `message`, retrying if it fails
+    async fn send(&self, message: Message) -> Result<DnsResponse, UpdateError> {
+        let tcp = message.to_vec()?.len() > MAX_UDP_LEN;
+        let mut attempt = 0;
+        loop {
+            let res = if tcp {
+                self.send_tcp(message.clone()).await
+            } else {
+                self.send_udp(message.clone()).await
+            };
+            match res {
+                Err(err) if attempt < self.retries => {
+                    attempt += 1;
+                    debug!(?err, attempt, dst = ?self.dst, "DNS update failed, retrying");
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn send_udp(&self, message: Message) -> Result<DnsResponse, UpdateError> {
+        let resp = self
+            .udp
+            .clone()
+            .send(message.clone())
+            .first_answer()
+            .await?;
+        if resp.truncated() {
+            debug!(dst = ?self.dst, "UDP response truncated, sending over TCP");
+            return self.send_tcp(message).await;
+        }
+        Ok(resp)
+    }
+
+    async fn send_tcp(&self, message: Message) -> Result<DnsResponse, UpdateError> {
+        let mut client = {
+            let mut tcp = self.tcp.lock().await;
+            match &*tcp {
+                Some(conn) if !conn.handle.is_finished() => conn.client.clone(),
+                _ => {
+                    let conn = TcpConn::connect(self.dst, self.tsig.clone(), self.timeout).await?;
+                    let client = conn.client.clone();
+                    *tcp = Some(conn);
+                    client
+                }
+            }
+        };
+        Ok(client.send(message).first_answer().await?)
+    }
 }
 
 impl Drop for Updater {
@@ -99,11 +216,57 @@ impl Drop for Updater {
     }
 }
 
+impl fmt::Debug for Updater {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Updater")
+            .field("dst", &self.dst)
+            .field("signed", &self.tsig.is_some())
+            .field("timeout", &self.timeout)
+            .field("retries", &self.retries)
+            .finish()
+    }
+}
+
+/// [`Updater`]s kept per DNS server & TSIG key name, so updates reuse the server's
+/// connections instead of opening new ones each time
+#[derive(Debug, Default)]
+pub struct Pool {
+    updaters: Mutex<HashMap<(SocketAddr, Option<String>), Arc<Updater>>>,
+}
+
+impl Pool {
+    /// the pooled updater for `dst` & `key`
+    pub fn get(&self, dst: SocketAddr, key: Option<&str>) -> Option<Arc<Updater>> {
+        self.updaters
+            .lock()
+            .unwrap()
+            .get(&(dst, key.map(str::to_owned)))
+            .cloned()
+    }
+
+    /// add `updater` to the pool, replacing any for the same server & key
+    pub fn insert(&self, key: Option<&str>, updater: Arc<Updater>) {
+        self.updaters
+            .lock()
+            .unwrap()
+            .insert((updater.dst, key.map(str::to_owned)), updater);
+    }
+
+    /// drop the updater for `dst` & `key`, ex. after it failed to reach the server. The
+    /// next update connects again
+    pub fn remove(&self, dst: SocketAddr, key: Option<&str>) {
+        self.updaters
+            .lock()
+            .unwrap()
+            .remove(&(dst, key.map(str::to_owned)));
+    }
+}
+
 pub fn update(
     zone_origin: Name,
     name: Name,
     duid: DhcId,
-    leased: Ipv4Addr,
+    leased: IpAddr,
     ttl: u32,
     use_edns: bool,
 ) -> Result<trust_dns_client::op::Message, NameError> {
@@ -118,7 +281,7 @@ pub fn update(
     prerequisite.set_dns_class(DNSClass::NONE);
     message.add_pre_requisite(prerequisite);
 
-    let a_record = Record::from_rdata(name.clone(), ttl, RData::A(leased));
+    let a_record = Record::from_rdata(name.clone(), ttl, addr_rdata(leased));
     let dhcid_record = Record::from_rdata(
         name.clone(),
         ttl,
@@ -137,7 +300,7 @@ pub fn update_present(
     zone_origin: Name,
     name: Name,
     duid: DhcId,
-    leased: Ipv4Addr,
+    leased: IpAddr,
     ttl: u32,
     use_edns: bool,
 ) -> Result<trust_dns_client::op::Message, NameError> {
@@ -163,7 +326,7 @@ pub fn update_present(
     );
     message.add_pre_requisite(dhcid_record);
 
-    let a_record = Record::from_rdata(name, ttl, RData::A(leased));
+    let a_record = Record::from_rdata(name, ttl, addr_rdata(leased));
     message.add_update(a_record);
 
     Ok(message)
@@ -171,9 +334,9 @@ pub fn update_present(
 
 pub fn delete(
     zone_origin: Name,
+    rev_ip: Name,
     name: Name,
     duid: DhcId,
-    leased: Ipv4Addr,
     ttl: u32,
     use_edns: bool,
 ) -> Result<trust_dns_client::op::Message, NameError> {
@@ -182,7 +345,6 @@ pub fn delete(
         rr::{rdata::NULL, RData, Record, RecordType},
     };
 
-    let rev_ip = Name::from_str(&reverse_ip(leased)).unwrap();
     let mut message = update_msg(zone_origin, use_edns);
 
     // delete
@@ -206,6 +368,15 @@ pub fn delete(
     Ok(message)
 }
 
+/// A record for v4, AAAA for v6
+fn addr_rdata(ip: IpAddr) -> trust_dns_client::rr::RData {
+    use trust_dns_client::rr::RData;
+    match ip {
+        IpAddr::V4(ip) => RData::A(ip),
+        IpAddr::V6(ip) => RData::AAAA(ip),
+    }
+}
+
 fn update_msg(zone_origin: Name, use_edns: bool) -> trust_dns_client::op::Message {
     use trust_dns_client::{
         op::{Edns, Message, MessageType, OpCode, Query, UpdateMessage},
@@ -263,7 +434,15 @@ pub fn reverse_ip<I: Into<IpAddr>>(ip: I) -> String {
     }
 }
 
-fn calculate_ttl(lease_length: u32) -> u32 {
+/// RFC 2317, the owner of `ip`'s PTR in a classless reverse zone: the last octet under
+/// the delegated `zone`, ex. `5.0-63.2.0.192.in-addr.arpa.` for `192.0.2.5`
+pub fn classless_reverse(ip: Ipv4Addr, zone: &Name) -> Result<Name, NameError> {
+    let zone = zone.to_string();
+    let zone = zone.trim_end_matches('.');
+    Name::from_str(&format!("{}.{zone}.", ip.octets()[3]))
+}
+
+pub(crate) fn calculate_ttl(lease_length: u32) -> u32 {
     // Per RFC 4702 DDNS RR TTL should be given by:
     // ((lease life time / 3) < 10 minutes) ? 10 minutes : (lease life time / 3)
     if lease_length < 1800 {
@@ -283,7 +462,7 @@ pub enum UpdateError {
 
 #[cfg(test)]
 mod test {
-    use std::net::Ipv6Addr;
+    use std::net::{Ipv4Addr, Ipv6Addr};
 
     use super::*;
     #[test]
@@ -294,6 +473,22 @@ mod test {
         )
     }
     #[test]
+    fn test_classless_reverse() {
+        let zone = Name::from_str("0-63.2.0.192.in-addr.arpa.").unwrap();
+        assert_eq!(
+            classless_reverse(Ipv4Addr::new(192, 0, 2, 5), &zone).unwrap(),
+            Name::from_str("5.0-63.2.0.192.in-addr.arpa.").unwrap()
+        );
+        // without the trailing dot
+        let zone = Name::from_str("64-127.2.0.192.in-addr.arpa").unwrap();
+        assert_eq!(
+            classless_reverse(Ipv4Addr::new(192, 0, 2, 70), &zone)
+                .unwrap()
+                .to_string(),
+            "70.64-127.2.0.192.in-addr.arpa."
+        );
+    }
+    #[test]
     fn test_rev_ip6() {
         assert_eq!(
             &reverse_ip(
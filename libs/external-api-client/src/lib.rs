@@ -0,0 +1,404 @@
+//! # External API client
+//!
+//! Typed client for dora's external api. The response types mirror the JSON the server
+//! returns, the full spec is served at `/openapi.json`.
+//!
+//! ```no_run
+//! # async fn run() -> external_api_client::Result<()> {
+//! let client = external_api_client::Client::new("http://127.0.0.1:3333");
+//! for range in client.utilization().await? {
+//!     println!("{} {}-{}: {} free", range.network, range.start, range.end, range.free);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub,
+    non_snake_case,
+    non_upper_case_globals
+)]
+
+use std::{net::Ipv4Addr, time::Duration};
+
+use reqwest::{header, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+pub mod models;
+
+use crate::models::*;
+
+/// client result type
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors returned by [`Client`]
+#[derive(Error, Debug)]
+pub enum Error {
+    /// the request couldn't be sent or the response body didn't decode
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// the server answered with an error status
+    #[error("server returned {status}: {body}")]
+    Status {
+        /// response status
+        status: StatusCode,
+        /// response body, usually a plain text message
+        body: String,
+        /// how long the server asked to wait before retrying, from `Retry-After`
+        retry_after: Option<Duration>,
+    },
+}
+
+impl Error {
+    /// the same request may succeed if it's retried, ex. the endpoint was busy (429) or
+    /// the server couldn't be reached. Wait [`Error::retry_after`] first if it's set
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Http(err) => err.is_connect() || err.is_timeout(),
+            Error::Status { status, .. } => matches!(
+                *status,
+                StatusCode::TOO_MANY_REQUESTS
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+        }
+    }
+
+    /// how long the server asked to wait before retrying
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Status { retry_after, .. } => *retry_after,
+            Error::Http(_) => None,
+        }
+    }
+}
+
+/// Client for a single dora instance
+#[derive(Debug, Clone)]
+pub struct Client {
+    base: String,
+    http: reqwest::Client,
+    admin_token: Option<String>,
+}
+
+impl Client {
+    /// `base` is the address of the external api, ex. `http://127.0.0.1:3333`
+    pub fn new(base: impl Into<String>) -> Self {
+        Self::with_client(base, reqwest::Client::new())
+    }
+
+    /// use an existing `reqwest::Client`, for custom timeouts or TLS settings
+    pub fn with_client(base: impl Into<String>, http: reqwest::Client) -> Self {
+        let mut base = base.into();
+        while base.ends_with('/') {
+            base.pop();
+        }
+        Self {
+            base,
+            http,
+            admin_token: None,
+        }
+    }
+
+    /// bearer token sent with admin-only requests
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = Some(token.into());
+        self
+    }
+
+    /// `/health`, an unhealthy server still returns a report
+    pub async fn health(&self) -> Result<HealthReport> {
+        let resp = self.http.get(self.url("/health")).send().await?;
+        if resp.status() == StatusCode::INTERNAL_SERVER_ERROR && is_json(&resp) {
+            return Ok(resp.json().await?);
+        }
+        json(resp).await
+    }
+
+    /// `/ping`
+    pub async fn ping(&self) -> Result<()> {
+        check(self.http.get(self.url("/ping")).send().await?).await?;
+        Ok(())
+    }
+
+    /// `/metrics-text`, prometheus text format
+    pub async fn metrics_text(&self) -> Result<String> {
+        Ok(
+            check(self.http.get(self.url("/metrics-text")).send().await?)
+                .await?
+                .text()
+                .await?,
+        )
+    }
+
+    /// `/openapi.json`
+    pub async fn openapi(&self) -> Result<serde_json::Value> {
+        json(self.http.get(self.url("/openapi.json")).send().await?).await
+    }
+
+    /// `POST /v1/reservations/import`. Reservations that fail validation are reported in
+    /// [`ImportResult::errors`], a body that doesn't parse is an [`Error::Status`]. Admin only
+    pub async fn import_reservations(
+        &self,
+        body: impl Into<String>,
+        format: ImportFormat,
+        dry_run: bool,
+    ) -> Result<ImportResult> {
+        let req = self
+            .http
+            .post(self.url("/v1/reservations/import"))
+            .query(&[("format", format.as_str()), ("dry_run", bool_str(dry_run))])
+            .body(body.into());
+        let resp = self.admin(req).send().await?;
+        if resp.status() == StatusCode::BAD_REQUEST && is_json(&resp) {
+            return Ok(resp.json().await?);
+        }
+        json(resp).await
+    }
+
+    /// `/v1/classes/stats`
+    pub async fn class_stats(&self) -> Result<Vec<ClassStats>> {
+        self.get("/v1/classes/stats").await
+    }
+
+    /// `/v1/v6/server-id`, a 404 [`Error::Status`] if v6 isn't configured
+    pub async fn server_id(&self) -> Result<ServerId> {
+        self.get("/v1/v6/server-id").await
+    }
+
+    /// `POST /v1/v6/server-id/rotate` with the configured `server_id` params. Admin only
+    pub async fn rotate_server_id(&self) -> Result<DuidRotation> {
+        let req = self.http.post(self.url("/v1/v6/server-id/rotate"));
+        json(self.admin(req).send().await?).await
+    }
+
+    /// `/v1/quarantine`
+    pub async fn quarantined(&self) -> Result<Vec<Quarantined>> {
+        self.get("/v1/quarantine").await
+    }
+
+    /// `DELETE /v1/quarantine/{ip}`, `false` if the IP wasn't quarantined. Admin only
+    pub async fn clear_quarantine(&self, ip: Ipv4Addr) -> Result<bool> {
+        let req = self.http.delete(self.url(&format!("/v1/quarantine/{ip}")));
+        deleted(self.admin(req).send().await?).await
+    }
+
+    /// `/v1/utilization`
+    pub async fn utilization(&self) -> Result<Vec<RangeUtilization>> {
+        self.get("/v1/utilization").await
+    }
+
+    /// `/v1/ranges`
+    pub async fn ranges(&self) -> Result<Vec<RangeInfo>> {
+        self.get("/v1/ranges").await
+    }
+
+    /// `/v1/leases`, pass the page's `next` as `after` to get the following page
+    pub async fn leases(&self, query: &LeasesQuery) -> Result<LeasePage> {
+        json(
+            self.http
+                .get(self.url("/v1/leases"))
+                .query(query)
+                .send()
+                .await?,
+        )
+        .await
+    }
+
+    /// `/v1/leases/fingerprints`
+    pub async fn fingerprints(&self) -> Result<Vec<LeaseFingerprint>> {
+        self.get("/v1/leases/fingerprints").await
+    }
+
+    /// `POST /v1/backup`, back up the leases database now. Admin only
+    pub async fn backup(&self) -> Result<Backup> {
+        let req = self.http.post(self.url("/v1/backup"));
+        json(self.admin(req).send().await?).await
+    }
+
+    /// `/v1/backup/latest`, an error with status 404 if there are no backups
+    pub async fn latest_backup(&self) -> Result<Backup> {
+        self.get("/v1/backup/latest").await
+    }
+
+    /// `/v1/leases/{ip}/history`
+    pub async fn lease_history(
+        &self,
+        ip: Ipv4Addr,
+        query: &HistoryQuery,
+    ) -> Result<Vec<LeaseHistoryEntry>> {
+        json(
+            self.http
+                .get(self.url(&format!("/v1/leases/{ip}/history")))
+                .query(query)
+                .send()
+                .await?,
+        )
+        .await
+    }
+
+    /// `/v1/exclusions`
+    pub async fn exclusions(&self) -> Result<Vec<ExclusionRange>> {
+        self.get("/v1/exclusions").await
+    }
+
+    /// `POST /v1/exclusions`. Admin only
+    pub async fn add_exclusion(&self, range: ExclusionRange) -> Result<ExclusionRange> {
+        let req = self.http.post(self.url("/v1/exclusions")).json(&range);
+        json(self.admin(req).send().await?).await
+    }
+
+    /// `DELETE /v1/exclusions`, `false` if there was no exclusion with exactly this range.
+    /// Admin only
+    pub async fn remove_exclusion(&self, range: ExclusionRange) -> Result<bool> {
+        let req = self.http.delete(self.url("/v1/exclusions")).query(&range);
+        deleted(self.admin(req).send().await?).await
+    }
+
+    /// `POST /v1/allocations`, hold the next free address in a network for a client that
+    /// hasn't booted yet. Admin only
+    pub async fn allocate(&self, req: &AllocationRequest) -> Result<Allocation> {
+        let req = self.http.post(self.url("/v1/allocations")).json(req);
+        json(self.admin(req).send().await?).await
+    }
+
+    /// `POST /v1/clock/acknowledge`, go back to the wall clock for lease expiry after a
+    /// clock jump. `None` if expiry wasn't frozen
+    pub async fn acknowledge_clock(&self) -> Result<Option<ClockJump>> {
+        let req = self.http.post(self.url("/v1/clock/acknowledge"));
+        let resp = self.admin(req).send().await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        json(resp).await.map(Some)
+    }
+
+    /// `/v1/config`, the parsed config with secrets masked
+    pub async fn config(&self) -> Result<serde_json::Value> {
+        self.get("/v1/config").await
+    }
+
+    /// `/v1/config?raw=true`, the config file as read including secrets. Admin only
+    pub async fn raw_config(&self) -> Result<String> {
+        let req = self
+            .http
+            .get(self.url("/v1/config"))
+            .query(&[("raw", "true")]);
+        Ok(check(self.admin(req).send().await?).await?.text().await?)
+    }
+
+    /// `POST /v1/decode`, `packet` is hex or base64. A packet that doesn't decode is an
+    /// [`Error::Status`]
+    pub async fn decode(&self, packet: impl Into<String>) -> Result<DecodedV4> {
+        json(
+            self.http
+                .post(self.url("/v1/decode"))
+                .body(packet.into())
+                .send()
+                .await?,
+        )
+        .await
+    }
+
+    /// `POST /v1/explain`, what the running config would answer a client with
+    pub async fn explain(&self, req: &ExplainRequest) -> Result<Explanation> {
+        json(
+            self.http
+                .post(self.url("/v1/explain"))
+                .json(req)
+                .send()
+                .await?,
+        )
+        .await
+    }
+
+    /// `/v1/debug/client-protection`
+    pub async fn client_protection(&self) -> Result<ClientProtectionReport> {
+        self.get("/v1/debug/client-protection").await
+    }
+
+    /// `DELETE /v1/debug/client-protection/{client_id}`, `false` if the client wasn't
+    /// tracked. `client_id` is hex, with or without `:` separators. Admin only
+    pub async fn clear_flood(&self, client_id: &str) -> Result<bool> {
+        let req = self
+            .http
+            .delete(self.url(&format!("/v1/debug/client-protection/{client_id}")));
+        deleted(self.admin(req).send().await?).await
+    }
+
+    /// `/v1/debug/memory`
+    pub async fn memory(&self) -> Result<MemoryReport> {
+        self.get("/v1/debug/memory").await
+    }
+
+    /// `/v1/debug/plugins`
+    pub async fn plugins(&self) -> Result<PluginChains> {
+        self.get("/v1/debug/plugins").await
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        json(self.http.get(self.url(path)).send().await?).await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base)
+    }
+
+    fn admin(&self, req: RequestBuilder) -> RequestBuilder {
+        match &self.admin_token {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        }
+    }
+}
+
+/// error status codes become [`Error::Status`]
+async fn check(resp: Response) -> Result<Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    // only the delay in seconds form, dora doesn't send dates
+    let retry_after = resp
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs);
+    Err(Error::Status {
+        status,
+        body: resp.text().await.unwrap_or_default(),
+        retry_after,
+    })
+}
+
+async fn json<T: DeserializeOwned>(resp: Response) -> Result<T> {
+    Ok(check(resp).await?.json().await?)
+}
+
+/// 204 is `true`, 404 is `false`
+async fn deleted(resp: Response) -> Result<bool> {
+    if resp.status() == StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
+    check(resp).await?;
+    Ok(true)
+}
+
+fn is_json(resp: &Response) -> bool {
+    resp.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
+
+fn bool_str(b: bool) -> &'static str {
+    if b {
+        "true"
+    } else {
+        "false"
+    }
+}
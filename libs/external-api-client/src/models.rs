@@ -0,0 +1,551 @@
+//! Request & response bodies. Timestamps are kept as the RFC 3339 strings the server sends.
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Health of a single component
+#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Health {
+    /// working
+    Good,
+    /// not working
+    Bad,
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Health::Good => "GOOD",
+            Health::Bad => "BAD",
+        })
+    }
+}
+
+/// Body of `/health`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone, Eq)]
+pub struct HealthReport {
+    /// overall health
+    pub status: Health,
+    /// the listener for ping check replies
+    pub icmp_listener: Health,
+}
+
+/// Input format for reservation import
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Copy, Clone, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// `mac,ip,hostname,options` rows
+    Csv,
+    /// a list of reservation objects
+    #[default]
+    Json,
+}
+
+impl ImportFormat {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ImportFormat::Csv => "csv",
+            ImportFormat::Json => "json",
+        }
+    }
+}
+
+/// Body of `/v1/reservations/import`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct ImportResult {
+    /// reservations written, always 0 on a dry run or if any were invalid
+    pub imported: usize,
+    /// nothing was written to storage
+    pub dry_run: bool,
+    /// reservations that failed validation
+    pub errors: Vec<Invalid>,
+}
+
+/// A reservation that failed validation
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct Invalid {
+    /// position in the input
+    pub index: usize,
+    /// reservation MAC
+    pub mac: String,
+    /// reserved IP
+    pub ip: Ipv4Addr,
+    /// why it was rejected
+    pub reason: String,
+}
+
+/// Counters for a single client class
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct ClassStats {
+    /// class name
+    pub name: String,
+    /// times the class expression evaluated to true
+    pub matched: u64,
+    /// responses that included options from this class
+    pub responses: u64,
+}
+
+/// The DHCPv6 server DUID
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct ServerId {
+    /// hex encoded DUID the server is currently using
+    pub active: String,
+    /// where the DUID is persisted, `None` if it is not persisted
+    pub path: Option<String>,
+    /// contents of the persisted file
+    pub persisted: Option<PersistIdentifier>,
+    /// the persisted DUID was rotated & takes effect on restart
+    pub restart_pending: bool,
+}
+
+/// A persisted server DUID
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct PersistIdentifier {
+    /// hex encoded DUID
+    pub identifier: String,
+    /// params the DUID was generated from, fields depend on `type`
+    pub duid_config: serde_json::Value,
+}
+
+/// Body of `/v1/v6/server-id/rotate`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct DuidRotation {
+    /// the DUID that was persisted before rotation, if any
+    pub previous: Option<PersistIdentifier>,
+    /// the newly persisted DUID
+    pub current: PersistIdentifier,
+    /// things to be aware of before restarting the server
+    pub warnings: Vec<String>,
+}
+
+/// Body of `/v1/clock/acknowledge`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Eq)]
+pub struct ClockJump {
+    /// seconds the wall clock was ahead of the clock lease expiry was frozen to,
+    /// negative if it was behind
+    pub offset_secs: i64,
+}
+
+/// An IP that is quarantined after too many probations
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct Quarantined {
+    /// quarantined IP
+    pub ip: IpAddr,
+    /// times the IP was probated
+    pub probations: u32,
+    /// RFC 3339 timestamp of the last probation
+    pub last_probated: String,
+}
+
+/// DHCP fingerprint of the client holding an active lease
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct LeaseFingerprint {
+    /// leased IP
+    pub ip: IpAddr,
+    /// hash of the PRL, vendor class & option codes, same as `pkt4.fingerprint`
+    pub fingerprint: String,
+    /// opt 55 (parameter request list) as comma separated codes
+    pub prl: String,
+    /// opt 60 (vendor class identifier)
+    pub vendor_class: Option<String>,
+}
+
+/// Query params for `/v1/leases`
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Eq)]
+pub struct LeasesQuery {
+    /// only entries in this state, `reserved`, `leased` or `probated`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    /// start after this IP, the `next` of the previous page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<Ipv4Addr>,
+    /// max entries in the page, at most 1000
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+/// An un-expired lease entry
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct Lease {
+    /// the entry's IP
+    pub ip: IpAddr,
+    /// subnet the IP was handed out from
+    pub network: IpAddr,
+    /// hex encoded client id
+    pub client_id: Option<String>,
+    /// `reserved`, `leased` or `probated`
+    pub state: String,
+    /// RFC 3339 expiry
+    pub expires_at: String,
+}
+
+/// A page of lease entries in IP order
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct LeasePage {
+    /// entries in this page
+    pub leases: Vec<Lease>,
+    /// pass as `after` to get the next page, `None` if this was the last one
+    pub next: Option<IpAddr>,
+}
+
+/// A backup of the leases database
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct Backup {
+    /// the backup file, on the server
+    pub path: String,
+    /// size in bytes
+    pub size: u64,
+    /// RFC 3339 time the backup finished
+    pub created_at: String,
+}
+
+/// Query params for `/v1/leases/{ip}/history`, timestamps are RFC 3339
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Eq)]
+pub struct HistoryQuery {
+    /// only changes at or after this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+    /// only changes at or before this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<String>,
+    /// max changes returned, the most recent are kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    /// only the change that left the IP held at this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub at: Option<String>,
+}
+
+/// A single change to an IP's lease entry
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct LeaseHistoryEntry {
+    /// hex encoded client id
+    pub client_id: Option<String>,
+    /// `reserved`, `leased`, `probated` or `removed`
+    pub state: String,
+    /// RFC 3339 expiry after the change, `None` if the entry was removed
+    pub expires_at: Option<String>,
+    /// RFC 3339 timestamp of the change
+    pub at: String,
+}
+
+/// An inclusive block of excluded addresses
+#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone, Eq)]
+pub struct ExclusionRange {
+    /// first excluded address
+    pub start: Ipv4Addr,
+    /// last excluded address
+    pub end: Ipv4Addr,
+}
+
+/// Body of `POST /v1/allocations`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct AllocationRequest {
+    /// network address of the subnet to allocate from, or any address in it
+    pub subnet: Ipv4Addr,
+    /// MAC of the client the address is held for
+    pub mac: String,
+    /// hex client id (opt 61) the client will send, if it sends one. Defaults to `mac`
+    pub client_id: Option<String>,
+    /// minutes the address is held for if the client doesn't claim it, at most a week
+    pub minutes: u32,
+}
+
+/// Body of `POST /v1/explain`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct ExplainRequest {
+    /// hex chaddr of the client
+    pub mac: String,
+    /// relay (giaddr) or interface address the client's packets arrive from, picks the
+    /// network
+    pub subnet: Ipv4Addr,
+    /// options the client sends as `code=value`, ex. `55=1,3,6`
+    pub options: Vec<String>,
+}
+
+/// What the config would answer a client with, from `/v1/explain`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct Explanation {
+    /// network the subnet is in, `None` if no network matches
+    pub network: Option<String>,
+    /// client classes that matched
+    pub classes: Vec<String>,
+    /// the client matched the `DROP` class & gets no answer
+    pub dropped: bool,
+    /// the client's reserved address
+    pub reserved: Option<Ipv4Addr>,
+    /// range the address comes from, if the client has no reservation
+    pub range: Option<AddrRange>,
+    /// lease time (opt 51)
+    pub lease_secs: Option<u64>,
+    /// renewal time (opt 58)
+    pub renew_secs: Option<u64>,
+    /// rebinding time (opt 59)
+    pub rebind_secs: Option<u64>,
+    /// options the client asked for that it would get, in request order
+    pub options: Vec<ExplainedOption>,
+    /// boot fields
+    pub boot: BootFields,
+}
+
+/// An inclusive range of addresses
+#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone, Eq)]
+pub struct AddrRange {
+    /// first address
+    pub start: Ipv4Addr,
+    /// last address
+    pub end: Ipv4Addr,
+}
+
+/// An option from `/v1/explain`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct ExplainedOption {
+    /// option code
+    pub code: u8,
+    /// option name
+    pub name: String,
+    /// the value as dora logs it
+    pub value: String,
+}
+
+/// `siaddr`/`sname`/`file`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct BootFields {
+    /// `siaddr`
+    pub next_server: Option<Ipv4Addr>,
+    /// `sname`
+    pub server_name: Option<String>,
+    /// `file`
+    pub file_name: Option<String>,
+}
+
+/// An address held for a client that hasn't booted yet
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct Allocation {
+    /// the held address
+    pub ip: Ipv4Addr,
+    /// subnet of the network the address is in
+    pub subnet: Ipv4Addr,
+    /// MAC the address is held for
+    pub mac: String,
+    /// hex client id the address is held for
+    pub client_id: String,
+    /// RFC 3339 time the address is released if the client hasn't claimed it
+    pub expires_at: String,
+}
+
+/// Address usage for a single range
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct RangeUtilization {
+    /// subnet the range belongs to
+    pub network: String,
+    /// first address in the range
+    pub start: Ipv4Addr,
+    /// last address in the range
+    pub end: Ipv4Addr,
+    /// addresses in the range, less exclusions
+    pub total: usize,
+    /// addresses available to hand out
+    pub free: usize,
+    /// un-expired leases
+    pub leased: usize,
+    /// offered but not yet leased
+    pub reserved: usize,
+    /// on probation or quarantined
+    pub probated: usize,
+}
+
+/// Lease times in seconds, an infinite lease is 4294967295
+#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone, Eq)]
+pub struct LeaseTimes {
+    /// used when the client doesn't ask for a lease time
+    pub default: u64,
+    /// shortest lease a client can ask for
+    pub min: u64,
+    /// longest lease a client can ask for
+    pub max: u64,
+}
+
+/// A configured range with its live usage
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct RangeInfo {
+    /// subnet the range belongs to
+    pub network: String,
+    /// first address in the range
+    pub start: Ipv4Addr,
+    /// last address in the range
+    pub end: Ipv4Addr,
+    /// lease times for addresses from the range
+    pub lease_time: LeaseTimes,
+    /// only clients in this class get addresses from the range
+    pub class: Option<String>,
+    /// classes whose clients can't get addresses from the range
+    pub restricted: Vec<String>,
+    /// the range's `except` list
+    pub exclusions: Vec<ExclusionRange>,
+    /// exclusions added at runtime that overlap the range
+    pub runtime_exclusions: Vec<ExclusionRange>,
+    /// addresses in the range, less config & runtime exclusions
+    pub total: usize,
+    /// addresses available to hand out
+    pub free: usize,
+    /// un-expired leases
+    pub leased: usize,
+    /// offered but not yet leased
+    pub reserved: usize,
+    /// on probation or quarantined
+    pub probated: usize,
+}
+
+/// A DHCPv4 packet decoded by `/v1/decode`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct DecodedV4 {
+    /// `BootRequest` or `BootReply`
+    pub opcode: String,
+    /// hardware type, ex. `Eth`
+    pub htype: String,
+    /// hardware address length
+    pub hlen: u8,
+    /// relay hops
+    pub hops: u8,
+    /// transaction id, ex. `0x00001234`
+    pub xid: String,
+    /// seconds since the client started
+    pub secs: u16,
+    /// broadcast flag
+    pub broadcast: bool,
+    /// client address
+    pub ciaddr: Ipv4Addr,
+    /// your (client) address
+    pub yiaddr: Ipv4Addr,
+    /// next server address
+    pub siaddr: Ipv4Addr,
+    /// relay address
+    pub giaddr: Ipv4Addr,
+    /// client hardware address, hex
+    pub chaddr: String,
+    /// server host name, if set
+    pub sname: Option<String>,
+    /// boot file name, if set
+    pub file: Option<String>,
+    /// options in the order received, split long options are joined
+    pub options: Vec<DecodedOption>,
+    /// code of an option cut off by the end of the packet
+    pub truncated: Option<u8>,
+}
+
+/// A v4 option from `/v1/decode`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct DecodedOption {
+    /// option code
+    pub code: u8,
+    /// option name, `Unknown` if the server doesn't know the code
+    pub name: String,
+    /// the decoded value as dora logs it, `None` if it failed to decode
+    pub value: Option<String>,
+    /// raw option data, hex
+    pub hex: String,
+    /// the raw data as text, if it's all printable
+    pub text: Option<String>,
+    /// why the option failed to decode
+    pub error: Option<String>,
+}
+
+/// Body of `/v1/debug/memory`, caches that aren't configured are `None`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MemoryReport {
+    /// jemalloc stats, `None` when the server was built without jemalloc
+    pub allocator: Option<AllocatorStats>,
+    /// addresses with a cached ping result, approximate
+    pub ping_cache: u64,
+    /// clients in the renew threshold cache
+    pub renew_cache: Option<usize>,
+    /// clients with flood limiter state
+    pub flood_keys: Option<usize>,
+}
+
+/// Body of `/v1/debug/plugins`, each server's plugins in the order they run. `v6` is
+/// `None` when the v6 server isn't running
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct PluginChains {
+    /// v4 server plugins
+    pub v4: Vec<PluginEntry>,
+    /// v6 server plugins
+    pub v6: Option<Vec<PluginEntry>>,
+}
+
+/// A plugin & the plugins it was registered to run after
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct PluginEntry {
+    /// plugin name
+    pub name: String,
+    /// plugins it runs after, `<unregistered>` for a dependency that isn't in the chain
+    pub depends_on: Vec<String>,
+}
+
+/// Global allocator stats, in bytes
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct AllocatorStats {
+    /// allocated by the application
+    pub allocated: u64,
+    /// in active pages
+    pub active: u64,
+    /// physically resident, including allocator metadata
+    pub resident: u64,
+    /// share of active pages that isn't allocated, `1 - allocated / active`
+    pub fragmentation: f64,
+}
+
+/// Body of `/v1/debug/client-protection`, sections that aren't configured are `None`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ClientProtectionReport {
+    /// renew threshold cache
+    pub renew_cache: Option<RenewCacheStats>,
+    /// flood limiter
+    pub flood: Option<FloodStats>,
+}
+
+/// Renew threshold cache counters, hits & misses are since startup
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RenewCacheStats {
+    /// clients in the cache
+    pub entries: usize,
+    /// renewals answered from the cache
+    pub hits: u64,
+    /// renewals that weren't cached or were past the threshold
+    pub misses: u64,
+    /// hits / (hits + misses)
+    pub hit_ratio: f64,
+}
+
+/// Flood limiter state
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct FloodStats {
+    /// what each limiter counts packets by, ex. `client_id` or `giaddr+chaddr`
+    #[serde(default)]
+    pub keys: Vec<String>,
+    /// keys with rate limit state, limited or not
+    pub tracked: usize,
+    /// clients currently being refused
+    pub limited: Vec<LimitedClient>,
+    /// pool utilization has switched at least one network to the tighter `pressure`
+    /// limit
+    pub under_pressure: bool,
+}
+
+/// A client the flood limiter is refusing
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Eq)]
+pub struct LimitedClient {
+    /// the limiter's key in hex, for a `client_id` limiter opt 61 or the chaddr. This is
+    /// what `DELETE /v1/debug/client-protection/{client_id}` takes
+    pub key_hex: String,
+    /// the limiter refusing it
+    #[serde(default)]
+    pub key: String,
+    /// milliseconds until the next packet from this client is answered
+    pub retry_after_ms: u64,
+}
@@ -8,12 +8,13 @@ pub use crate::icmp::{Decode, EchoReply, EchoRequest, Encode, Icmpv4, Icmpv6, IC
 use crate::{icmp::Proto, socket::Socket};
 
 use dora_core::metrics;
-use parking_lot::Mutex;
+use futures::{stream, Stream, StreamExt};
+use parking_lot::{Mutex, RwLock};
 use shutdown::Shutdown;
 use socket2::{Domain, Protocol, Type};
-use tokio::sync::{broadcast, oneshot};
+use tokio::sync::{broadcast, oneshot, Semaphore};
 use tokio::task;
-use tracing::{debug, error, trace, warn};
+use tracing::{debug, error, info, trace, warn};
 
 use core::fmt;
 use std::{
@@ -21,13 +22,26 @@ use std::{
     io,
     marker::PhantomData,
     net::{IpAddr, SocketAddr},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
 pub const DEFAULT_TOKEN_SIZE: usize = 24;
 pub type Token = [u8; DEFAULT_TOKEN_SIZE];
 pub type PingMap = Arc<Mutex<HashMap<Token, Ping>>>;
+/// the socket currently in use, swapped out when the listener is restarted
+type SharedSocket<M> = Arc<RwLock<Arc<IcmpEcho<M>>>>;
+
+/// consecutive recv errors before the socket is considered broken & recreated
+const MAX_RECV_ERRORS: usize = 8;
+/// first delay before recreating a broken socket, doubled on each failed attempt
+const MIN_RESTART_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+/// pings that can be waiting for a reply at once, across all pingers of a `Listener`
+pub const DEFAULT_MAX_CONCURRENT_PINGS: usize = 256;
 
 const ECHO_REQUEST_BUFFER_SIZE: usize = ICMP_HEADER_SIZE + DEFAULT_TOKEN_SIZE;
 type EchoRequestBuffer = [u8; ECHO_REQUEST_BUFFER_SIZE];
@@ -97,12 +111,16 @@ impl<P: Proto> IcmpEcho<P> {
 impl<P: Proto> Pinger<P> {
     fn new(
         host: IpAddr,
-        socket: Arc<IcmpEcho<P>>,
+        socket: SharedSocket<P>,
+        healthy: Arc<AtomicBool>,
+        limit: Arc<Semaphore>,
         map: PingMap,
         // shutdown: Shutdown,
     ) -> Pinger<P> {
         Self {
             socket,
+            healthy,
+            limit,
             host,
             ident: rand::random(),
             map,
@@ -120,6 +138,12 @@ impl<P: Proto> Pinger<P> {
     where
         for<'a> EchoRequest<'a>: Encode<P>,
     {
+        // nothing would receive the reply, don't make the caller wait out the timeout
+        if !self.healthy.load(Ordering::Acquire) {
+            return Err(errors::Error::ListenerDown);
+        }
+        // the semaphore is never closed
+        let _permit = self.limit.acquire().await.ok();
         let (tx, rx) = oneshot::channel();
         let payload = rand::random::<Token>();
 
@@ -143,7 +167,8 @@ impl<P: Proto> Pinger<P> {
         };
         let start = Instant::now();
 
-        self.socket.request(self.host, &req).await?;
+        let socket = self.socket.read().clone();
+        socket.request(self.host, &req).await?;
         debug!("sent echo request-- waiting for reply");
         match tokio::time::timeout(self.timeout, rx).await {
             Ok(Ok(reply)) => {
@@ -186,47 +211,138 @@ fn record_resp_metric(is_ipv4: bool, start: Instant) {
     }
 }
 
+/// receive replies & hand them to the waiting pinger. Returns once the socket has
+/// errored `MAX_RECV_ERRORS` times in a row
+async fn listen<P: Proto>(soc: Arc<IcmpEcho<P>>, map: PingMap) -> io::Error
+where
+    EchoReply: Decode<P>,
+{
+    let mut errors = 0;
+    loop {
+        match soc.reply().await {
+            Ok((reply, addr)) => {
+                errors = 0;
+                debug!(?addr, ?reply, "received reply");
+                let now = Instant::now();
+                if let Some(ping) = map.lock().remove(&reply.payload[..]) {
+                    let time = now - ping.sent;
+                    if let Err(err) = ping.tx.send(PingReply { reply, addr, time }) {
+                        error!(?err, "error on oneshot sender (receiver likely dropped)");
+                    }
+                } else {
+                    error!(
+                        ?reply,
+                        ?addr,
+                        "received reply that we've already received or that we've never sent"
+                    );
+                }
+            }
+            Err(err) if errors < MAX_RECV_ERRORS => {
+                errors += 1;
+                warn!(?err, errors, "error receiving on ICMP socket");
+            }
+            Err(err) => return err,
+        }
+    }
+}
+
+/// Runs the listener and restarts it with a new socket, backing off between attempts, if
+/// it exits or panics. Pings fail immediately while the listener is down
+async fn supervise<P>(
+    proto: &'static str,
+    socket: SharedSocket<P>,
+    healthy: Arc<AtomicBool>,
+    map: PingMap,
+    mut shutdown: Shutdown,
+    new_socket: fn() -> io::Result<IcmpEcho<P>>,
+) where
+    P: Proto + Send + Sync + 'static,
+    EchoReply: Decode<P>,
+{
+    let mut backoff = MIN_RESTART_BACKOFF;
+    loop {
+        let started = Instant::now();
+        let soc = socket.read().clone();
+        let mut handle = task::spawn(listen(soc, map.clone()));
+        tokio::select! {
+            ret = &mut handle => match ret {
+                Ok(err) => error!(?err, proto, "ICMP listener socket failed"),
+                Err(err) => error!(?err, proto, "ICMP listener task exited"),
+            },
+            _ = shutdown.recv() => {
+                debug!("ICMP listener shutdown received");
+                handle.abort();
+                return;
+            }
+        }
+        healthy.store(false, Ordering::Release);
+        metrics::ICMP_LISTENER_UP.with_label_values(&[proto]).set(0);
+        // drop the senders so in-flight pings fail now instead of at their timeout
+        map.lock().clear();
+        if started.elapsed() > MAX_RESTART_BACKOFF {
+            backoff = MIN_RESTART_BACKOFF;
+        }
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = shutdown.recv() => {
+                    debug!("ICMP listener shutdown received");
+                    return;
+                }
+            }
+            backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+            match new_socket() {
+                Ok(new) => {
+                    *socket.write() = Arc::new(new);
+                    break;
+                }
+                Err(err) => warn!(?err, proto, ?backoff, "failed to recreate ICMP socket"),
+            }
+        }
+        healthy.store(true, Ordering::Release);
+        metrics::ICMP_LISTENER_UP.with_label_values(&[proto]).set(1);
+        metrics::ICMP_LISTENER_RESTART_COUNT
+            .with_label_values(&[proto])
+            .inc();
+        info!(proto, "ICMP listener restarted");
+    }
+}
+
 // macro is just to copy-paste the contents for both Icmpv4 & Icmpv6
 macro_rules! impl_icmp {
-    ($t:ty) => {
+    ($t:ty, $proto:literal) => {
         impl Listener<$t> {
             pub fn new() -> errors::Result<Listener<$t>> {
-                let soc = Arc::new(IcmpEcho::<$t>::new()?);
+                Self::with_max_concurrent(DEFAULT_MAX_CONCURRENT_PINGS)
+            }
+
+            /// like `new`, with at most `max_concurrent` pings outstanding at once. Pings
+            /// over the limit wait for a slot before they are sent
+            pub fn with_max_concurrent(max_concurrent: usize) -> errors::Result<Listener<$t>> {
+                let socket = Arc::new(RwLock::new(Arc::new(IcmpEcho::<$t>::new()?)));
                 // when notify_shutdown is dropped, all pingers will shutdown
                 let (notify_shutdown, _) = broadcast::channel(1);
 
-                let r = soc.clone();
-                let mut shutdown = Shutdown::new(notify_shutdown.subscribe());
+                let shutdown = Shutdown::new(notify_shutdown.subscribe());
                 let map: PingMap = Arc::new(Mutex::new(HashMap::new()));
-
-                let task_map = map.clone();
-                task::spawn(async move {
-                    loop {
-                        tokio::select! {
-                            ret = r.reply() => {
-                                if let Ok((reply, addr)) = ret {
-                                    debug!(?addr, ?reply, "received reply");
-                                    let now = Instant::now();
-                                    if let Some(ping) = task_map.lock().remove(&reply.payload[..]) {
-                                        let time = now - ping.sent;
-                                        if let Err(err) = ping.tx.send(PingReply { reply, addr, time }) {
-                                            error!(?err, "error on oneshot sender (receiver likely dropped)");
-                                        }
-                                    } else {
-                                        error!(?reply, ?addr, "received reply that we've already received or that we've never sent");
-                                    }
-                                }
-                            }
-                            _ = shutdown.recv() => {
-                                debug!("ICMP listener shutdown received");
-                                break;
-                            }
-                        }
-                    }
-                });
+                let healthy = Arc::new(AtomicBool::new(true));
+                metrics::ICMP_LISTENER_UP
+                    .with_label_values(&[$proto])
+                    .set(1);
+
+                task::spawn(supervise(
+                    $proto,
+                    socket.clone(),
+                    healthy.clone(),
+                    map.clone(),
+                    shutdown,
+                    IcmpEcho::<$t>::new,
+                ));
 
                 Ok(Self {
-                    inner: soc,
+                    inner: socket,
+                    healthy,
+                    limit: Arc::new(Semaphore::new(max_concurrent.max(1))),
                     map,
                     // once Dropped, triggers shutdown in listener task (could also just abort()?)
                     notify_shutdown,
@@ -238,14 +354,49 @@ macro_rules! impl_icmp {
                 drop(self);
             }
 
+            /// `false` while the listener is being restarted, pings fail immediately
+            pub fn is_healthy(&self) -> bool {
+                self.healthy.load(Ordering::Acquire)
+            }
+
             pub fn pinger(&self, host: IpAddr) -> Pinger<$t> {
                 Pinger::new(
                     host,
                     self.inner.clone(),
+                    self.healthy.clone(),
+                    self.limit.clone(),
                     self.map.clone(),
                     // Shutdown::new(self.notify_shutdown.subscribe()),
                 )
             }
+
+            /// Ping each of `ips` once, with up to `concurrency` pings from this call in flight.
+            /// The listener's global limit applies on top of that. Results are yielded in the
+            /// order they complete
+            pub fn ping_many<I>(
+                &self,
+                ips: I,
+                timeout: Duration,
+                concurrency: usize,
+            ) -> impl Stream<Item = (IpAddr, errors::Result<PingReply>)> + Send + 'static
+            where
+                I: IntoIterator<Item = IpAddr>,
+            {
+                let pingers = ips
+                    .into_iter()
+                    .map(|ip| {
+                        let mut pinger = self.pinger(ip);
+                        pinger.timeout(timeout);
+                        pinger
+                    })
+                    .collect::<Vec<_>>();
+                stream::iter(pingers.into_iter().enumerate())
+                    .map(|(i, pinger)| async move {
+                        let res = pinger.ping(i as u16).await;
+                        (pinger.host, res)
+                    })
+                    .buffer_unordered(concurrency.max(1))
+            }
         }
     };
 }
@@ -304,7 +455,9 @@ impl IcmpEcho<Icmpv6> {
 
 /// A new pinger interface
 pub struct Pinger<M> {
-    socket: Arc<IcmpEcho<M>>,
+    socket: SharedSocket<M>,
+    healthy: Arc<AtomicBool>,
+    limit: Arc<Semaphore>,
     map: PingMap,
     host: IpAddr,
     // may get swapped out by kernel
@@ -336,7 +489,9 @@ impl Drop for Guard {
 /// new senders can be created with `pinger()`.
 #[derive(Debug)]
 pub struct Listener<M> {
-    inner: Arc<IcmpEcho<M>>,
+    inner: SharedSocket<M>,
+    healthy: Arc<AtomicBool>,
+    limit: Arc<Semaphore>,
     map: PingMap,
     // on Drop this will stop our spawned task, but it is never read
     #[allow(dead_code)]
@@ -357,8 +512,8 @@ pub struct PingReply {
     pub time: Duration,
 }
 
-impl_icmp!(Icmpv4);
-impl_icmp!(Icmpv6);
+impl_icmp!(Icmpv4, "v4");
+impl_icmp!(Icmpv6, "v6");
 
 #[cfg(test)]
 mod tests {
@@ -430,6 +585,42 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn test_listener_down() -> errors::Result<()> {
+        let listener = Listener::<Icmpv4>::new()?;
+        assert!(listener.is_healthy());
+        let pinger = listener.pinger("127.0.0.1".parse().unwrap());
+        listener.healthy.store(false, Ordering::Release);
+        let start = Instant::now();
+        assert!(matches!(
+            pinger.ping(0).await,
+            Err(errors::Error::ListenerDown)
+        ));
+        assert!(start.elapsed() < pinger.timeout);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_ping_many() -> errors::Result<()> {
+        let listener = Listener::<Icmpv4>::with_max_concurrent(2)?;
+        let ips = ["127.0.0.1", "127.0.0.2", "127.0.0.3", "127.0.0.4"]
+            .map(|ip| ip.parse::<IpAddr>().unwrap());
+        let results = listener
+            .ping_many(ips, Duration::from_millis(500), 3)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(results.len(), ips.len());
+        for (ip, res) in results {
+            assert!(ips.contains(&ip));
+            res?;
+        }
+        // all permits are returned
+        assert_eq!(listener.limit.available_permits(), 2);
+        Ok(())
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_multiping() -> errors::Result<()> {
@@ -0,0 +1,123 @@
+//! LDAP search, the first entry matching the filter is the client. Each lookup opens its
+//! own connection so a slow or restarted directory never holds up other lookups
+use config::wire::identity::Ldap as LdapConfig;
+use dora_core::{anyhow::Result, async_trait};
+use ldap3::{ldap_escape, LdapConnAsync, Scope, SearchEntry};
+
+use crate::{hex_join, Client, Identity, IdentityProvider};
+
+#[derive(Debug)]
+pub struct Ldap {
+    cfg: LdapConfig,
+}
+
+impl Ldap {
+    pub fn new(cfg: LdapConfig) -> Self {
+        Self { cfg }
+    }
+
+    /// the configured filter with the client's identifiers filled in
+    fn filter(&self, client: Client<'_>) -> String {
+        self.cfg
+            .filter
+            .replace("{mac}", &ldap_escape(client.mac()))
+            .replace("{client_id}", &ldap_escape(hex_join(client.client_id, "")))
+    }
+
+    fn identity(&self, entry: &SearchEntry) -> Identity {
+        let values = |attr: &Option<String>| {
+            attr.as_ref()
+                .and_then(|attr| entry.attrs.get(attr))
+                .cloned()
+                .unwrap_or_default()
+        };
+        Identity {
+            vlan: values(&self.cfg.vlan_attr)
+                .first()
+                .and_then(|vlan| vlan.trim().parse().ok()),
+            classes: values(&self.cfg.class_attr),
+        }
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for Ldap {
+    async fn lookup(&self, client: Client<'_>) -> Result<Option<Identity>> {
+        let (conn, mut ldap) = LdapConnAsync::new(&self.cfg.url).await?;
+        ldap3::drive!(conn);
+        if let (Some(dn), Some(password)) = (&self.cfg.bind_dn, &self.cfg.bind_password) {
+            ldap.simple_bind(dn, password.expose()).await?.success()?;
+        }
+        let mut attrs = [&self.cfg.vlan_attr, &self.cfg.class_attr]
+            .into_iter()
+            .flatten()
+            .map(|attr| attr.as_str())
+            .collect::<Vec<_>>();
+        if attrs.is_empty() {
+            // RFC 4511, no attributes
+            attrs.push("1.1");
+        }
+        let (entries, _) = ldap
+            .search(
+                &self.cfg.base_dn,
+                Scope::Subtree,
+                &self.filter(client),
+                attrs,
+            )
+            .await?
+            .success()?;
+        // the result is already in hand, a failed unbind doesn't matter
+        let _ = ldap.unbind().await;
+        Ok(entries
+            .into_iter()
+            .next()
+            .map(|entry| self.identity(&SearchEntry::construct(entry))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_ldap_entry() {
+        let ldap = Ldap::new(LdapConfig {
+            url: "ldap://127.0.0.1".to_owned(),
+            bind_dn: None,
+            bind_password: None,
+            base_dn: "ou=devices,dc=example,dc=org".to_owned(),
+            filter: "(|(macAddress={mac})(clientId={client_id}))".to_owned(),
+            vlan_attr: Some("vlanId".to_owned()),
+            class_attr: Some("deviceClass".to_owned()),
+        });
+        let client = Client {
+            chaddr: &[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff],
+            client_id: &[1, 0xaa, 0xbb],
+        };
+        assert_eq!(
+            ldap.filter(client),
+            "(|(macAddress=aa:bb:cc:dd:ee:ff)(clientId=01aabb))"
+        );
+
+        let entry = SearchEntry {
+            dn: "cn=printer-3,ou=devices,dc=example,dc=org".to_owned(),
+            attrs: HashMap::from([
+                ("vlanId".to_owned(), vec!["20".to_owned()]),
+                (
+                    "deviceClass".to_owned(),
+                    vec!["printers".to_owned(), "floor-3".to_owned()],
+                ),
+            ]),
+            bin_attrs: HashMap::new(),
+        };
+        assert_eq!(
+            ldap.identity(&entry),
+            Identity {
+                vlan: Some(20),
+                classes: vec!["printers".to_owned(), "floor-3".to_owned()],
+            }
+        );
+    }
+}
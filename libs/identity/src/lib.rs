@@ -0,0 +1,82 @@
+//! # Identity
+//!
+//! Backends that are asked about a client while it is classified. A client the backend
+//! knows is part of the `KNOWN` built-in class, and the attributes it returns become
+//! classes: every class tag as-is and the VLAN as `VLAN_<id>`.
+//!
+//! Lookups are bounded by the caller, backends don't apply their own timeout.
+#![warn(
+    missing_debug_implementations,
+    rust_2018_idioms,
+    unreachable_pub,
+    non_snake_case,
+    non_upper_case_globals
+)]
+use std::sync::Arc;
+
+use config::{
+    client_classes::client_classification::VLAN_PREFIX_CLASS,
+    wire::identity::{Backend, Identity as IdentityConfig},
+};
+use dora_core::{anyhow::Result, async_trait};
+
+mod ldap;
+mod radius;
+
+pub use crate::{ldap::Ldap, radius::Radius};
+
+/// the client being looked up
+#[derive(Debug, Clone, Copy)]
+pub struct Client<'a> {
+    pub chaddr: &'a [u8],
+    pub client_id: &'a [u8],
+}
+
+impl Client<'_> {
+    /// chaddr as `aa:bb:cc:dd:ee:ff`
+    pub fn mac(&self) -> String {
+        hex_join(self.chaddr, ":")
+    }
+}
+
+/// what a backend knows about a client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub vlan: Option<u16>,
+    /// class tags
+    pub classes: Vec<String>,
+}
+
+impl Identity {
+    /// classes the client is a member of, the class tags & `VLAN_<id>` if there is a VLAN
+    pub fn tags(&self) -> Vec<String> {
+        self.classes
+            .iter()
+            .cloned()
+            .chain(self.vlan.map(|vlan| format!("{VLAN_PREFIX_CLASS}{vlan}")))
+            .collect()
+    }
+}
+
+/// An external directory of clients
+#[async_trait]
+pub trait IdentityProvider: Send + Sync + 'static {
+    /// `Ok(None)` if the client isn't known to the backend
+    async fn lookup(&self, client: Client<'_>) -> Result<Option<Identity>>;
+}
+
+/// create the backend from the `identity` config
+pub fn from_config(cfg: &IdentityConfig) -> Arc<dyn IdentityProvider> {
+    match &cfg.backend {
+        Backend::Radius(radius) => Arc::new(Radius::new(radius)),
+        Backend::Ldap(ldap) => Arc::new(Ldap::new(ldap.clone())),
+    }
+}
+
+fn hex_join(bytes: &[u8], sep: &str) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(sep)
+}
@@ -0,0 +1,279 @@
+//! RADIUS MAC authentication (RFC 2865). The MAC is sent as both User-Name & User-Password
+//! the way switches do MAB, Filter-Id values are class tags & Tunnel-Private-Group-Id is
+//! the VLAN
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use config::wire::{identity::Radius as RadiusConfig, Secret};
+use dora_core::{
+    anyhow::{bail, Context, Result},
+    async_trait,
+    tokio::net::UdpSocket,
+    tracing::debug,
+};
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+
+use crate::{hex_join, Client, Identity, IdentityProvider};
+
+const ACCESS_REQUEST: u8 = 1;
+const ACCESS_ACCEPT: u8 = 2;
+const ACCESS_REJECT: u8 = 3;
+
+const USER_NAME: u8 = 1;
+const USER_PASSWORD: u8 = 2;
+const SERVICE_TYPE: u8 = 6;
+const FILTER_ID: u8 = 11;
+const CALLING_STATION_ID: u8 = 31;
+const NAS_IDENTIFIER: u8 = 32;
+const MESSAGE_AUTHENTICATOR: u8 = 80;
+const TUNNEL_PRIVATE_GROUP_ID: u8 = 81;
+
+/// Service-Type value used for MAC authentication
+const CALL_CHECK: u32 = 10;
+const HEADER_LEN: usize = 20;
+
+#[derive(Debug)]
+pub struct Radius {
+    server: SocketAddr,
+    secret: Secret,
+    nas_identifier: Option<String>,
+    id: AtomicU8,
+}
+
+impl Radius {
+    pub fn new(cfg: &RadiusConfig) -> Self {
+        Self {
+            server: cfg.server,
+            secret: cfg.secret.clone(),
+            nas_identifier: cfg.nas_identifier.clone(),
+            id: AtomicU8::new(rand::random()),
+        }
+    }
+
+    fn secret(&self) -> &[u8] {
+        self.secret.expose().as_bytes()
+    }
+
+    /// encode an Access-Request for `client`
+    fn request(&self, id: u8, authenticator: &[u8; 16], client: Client<'_>) -> Vec<u8> {
+        let user = hex_join(client.chaddr, "");
+        let mut pkt = vec![ACCESS_REQUEST, id, 0, 0];
+        pkt.extend_from_slice(authenticator);
+        push_attr(&mut pkt, USER_NAME, user.as_bytes());
+        push_attr(
+            &mut pkt,
+            USER_PASSWORD,
+            &hide_password(self.secret(), authenticator, user.as_bytes()),
+        );
+        push_attr(&mut pkt, SERVICE_TYPE, &CALL_CHECK.to_be_bytes());
+        push_attr(
+            &mut pkt,
+            CALLING_STATION_ID,
+            hex_join(client.chaddr, "-").to_uppercase().as_bytes(),
+        );
+        if let Some(nas) = &self.nas_identifier {
+            push_attr(&mut pkt, NAS_IDENTIFIER, nas.as_bytes());
+        }
+        // signed over the whole packet with the authenticator zeroed
+        let msg_auth = pkt.len() + 2;
+        push_attr(&mut pkt, MESSAGE_AUTHENTICATOR, &[0; 16]);
+        let len = pkt.len() as u16;
+        pkt[2..4].copy_from_slice(&len.to_be_bytes());
+        let sig = hmac_md5(self.secret(), &pkt);
+        pkt[msg_auth..msg_auth + 16].copy_from_slice(&sig);
+        pkt
+    }
+
+    /// `Ok(None)` for an Access-Reject. Returns an error for anything that isn't a
+    /// valid response to the request
+    fn response(&self, id: u8, authenticator: &[u8; 16], buf: &[u8]) -> Result<Option<Identity>> {
+        if buf.len() < HEADER_LEN {
+            bail!("RADIUS response too short");
+        }
+        let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        if buf[1] != id || len < HEADER_LEN || len > buf.len() {
+            bail!("RADIUS response doesn't match request");
+        }
+        let buf = &buf[..len];
+        // MD5(Code + ID + Length + Request Authenticator + Attributes + Secret)
+        let expected = Md5::new()
+            .chain_update(&buf[..4])
+            .chain_update(authenticator)
+            .chain_update(&buf[HEADER_LEN..])
+            .chain_update(self.secret())
+            .finalize();
+        if expected[..] != buf[4..HEADER_LEN] {
+            bail!("RADIUS response authenticator is invalid, check the shared secret");
+        }
+        match buf[0] {
+            ACCESS_ACCEPT => Ok(Some(identity(&buf[HEADER_LEN..])?)),
+            ACCESS_REJECT => Ok(None),
+            code => bail!("unexpected RADIUS response code {code}"),
+        }
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for Radius {
+    async fn lookup(&self, client: Client<'_>) -> Result<Option<Identity>> {
+        let id = self.id.fetch_add(1, Ordering::Relaxed);
+        let authenticator: [u8; 16] = rand::random();
+        let req = self.request(id, &authenticator, client);
+
+        let local: SocketAddr = if self.server.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let sock = UdpSocket::bind(local).await?;
+        sock.connect(self.server).await?;
+        sock.send(&req).await?;
+        let mut buf = [0; 4096];
+        loop {
+            let len = sock.recv(&mut buf).await?;
+            match self.response(id, &authenticator, &buf[..len]) {
+                Ok(identity) => return Ok(identity),
+                // keep waiting, the caller's timeout ends this
+                Err(err) => debug!(?err, "ignoring RADIUS response"),
+            }
+        }
+    }
+}
+
+fn push_attr(pkt: &mut Vec<u8>, ty: u8, val: &[u8]) {
+    pkt.push(ty);
+    pkt.push(val.len() as u8 + 2);
+    pkt.extend_from_slice(val);
+}
+
+/// RFC 2865 5.2 User-Password hiding
+fn hide_password(secret: &[u8], authenticator: &[u8; 16], password: &[u8]) -> Vec<u8> {
+    let mut padded = password.to_vec();
+    padded.resize(password.len().max(1).div_ceil(16) * 16, 0);
+    let mut out: Vec<u8> = Vec::with_capacity(padded.len());
+    for chunk in padded.chunks(16) {
+        let prev = match out.len() {
+            0 => &authenticator[..],
+            n => &out[n - 16..],
+        };
+        let b = Md5::new()
+            .chain_update(secret)
+            .chain_update(prev)
+            .finalize();
+        let hidden = chunk.iter().zip(b).map(|(p, b)| p ^ b).collect::<Vec<_>>();
+        out.extend(hidden);
+    }
+    out
+}
+
+fn hmac_md5(secret: &[u8], data: &[u8]) -> [u8; 16] {
+    let mut mac = Hmac::<Md5>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// attributes of an Access-Accept
+fn identity(mut attrs: &[u8]) -> Result<Identity> {
+    let mut identity = Identity::default();
+    while !attrs.is_empty() {
+        let len = *attrs.get(1).context("truncated RADIUS attribute")? as usize;
+        if len < 2 || len > attrs.len() {
+            bail!("invalid RADIUS attribute length");
+        }
+        let (ty, val) = (attrs[0], &attrs[2..len]);
+        match ty {
+            FILTER_ID => identity
+                .classes
+                .push(String::from_utf8_lossy(val).into_owned()),
+            TUNNEL_PRIVATE_GROUP_ID => {
+                // RFC 2868, an optional leading tag byte
+                let val = match val.first() {
+                    Some(tag) if *tag <= 0x1f => &val[1..],
+                    _ => val,
+                };
+                identity.vlan = std::str::from_utf8(val).ok().and_then(|s| s.parse().ok());
+            }
+            _ => {}
+        }
+        attrs = &attrs[len..];
+    }
+    Ok(identity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_radius_lookup() -> Result<()> {
+        let server = UdpSocket::bind("127.0.0.1:0").await?;
+        let radius = Radius::new(&RadiusConfig {
+            server: server.local_addr()?,
+            secret: "s3cret".into(),
+            nas_identifier: Some("dora".to_owned()),
+        });
+        let secret = radius.secret().to_vec();
+        // accepts aa:bb:cc:dd:ee:ff & rejects anything else
+        dora_core::tokio::spawn(async move {
+            let mut buf = [0; 4096];
+            loop {
+                let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+                let req = &buf[..len];
+                assert_eq!(req[0], ACCESS_REQUEST);
+                // first attribute is User-Name, the password follows it
+                let user = &req[22..22 + req[21] as usize - 2];
+                let pw_at = 20 + req[21] as usize;
+                let hidden = &req[pw_at + 2..pw_at + req[pw_at + 1] as usize];
+                let b = Md5::new()
+                    .chain_update(&secret)
+                    .chain_update(&req[4..20])
+                    .finalize();
+                let pw = hidden.iter().zip(b).map(|(h, b)| h ^ b).collect::<Vec<_>>();
+                assert_eq!(&pw[..user.len()], user);
+
+                let mut resp = vec![ACCESS_REJECT, req[1], 0, 0];
+                resp.extend_from_slice(&[0; 16]);
+                if user == b"aabbccddeeff" {
+                    resp[0] = ACCESS_ACCEPT;
+                    push_attr(&mut resp, FILTER_ID, b"printers");
+                    push_attr(&mut resp, TUNNEL_PRIVATE_GROUP_ID, b"\x0120");
+                }
+                let len = resp.len() as u16;
+                resp[2..4].copy_from_slice(&len.to_be_bytes());
+                let auth = Md5::new()
+                    .chain_update(&resp[..4])
+                    .chain_update(&req[4..20])
+                    .chain_update(&resp[20..])
+                    .chain_update(&secret)
+                    .finalize();
+                resp[4..20].copy_from_slice(&auth);
+                server.send_to(&resp, peer).await.unwrap();
+            }
+        });
+
+        let found = radius
+            .lookup(Client {
+                chaddr: &[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff],
+                client_id: &[],
+            })
+            .await?;
+        assert_eq!(
+            found,
+            Some(Identity {
+                vlan: Some(20),
+                classes: vec!["printers".to_owned()],
+            })
+        );
+        let found = radius
+            .lookup(Client {
+                chaddr: &[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01],
+                client_id: &[],
+            })
+            .await?;
+        assert_eq!(found, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+//! # Backups
+//!
+//! Online backups of the leases database. Each is made with [`Storage::backup`], a
+//! consistent copy taken while dora keeps writing. A copy of the database file made while
+//! it's in use can catch it between writes, or miss what's still in the WAL, and restore
+//! as a corrupt database. Backups are written to a temp file & renamed, so a file named
+//! like a backup is always complete. Only the newest `keep` are kept.
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Utc};
+use dora_core::{
+    metrics,
+    tokio::{fs, sync::Mutex},
+};
+use thiserror::Error;
+use tracing::{debug, info};
+
+use crate::{IpError, IpManager, Storage};
+
+const PREFIX: &str = "leases-";
+const SUFFIX: &str = ".db";
+
+/// a backup file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub path: PathBuf,
+    /// bytes
+    pub size: u64,
+    /// when the backup finished
+    pub created_at: SystemTime,
+}
+
+#[derive(Error, Debug)]
+pub enum BackupError<E> {
+    #[error("failed to write backup")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Storage(#[from] IpError<E>),
+}
+
+/// backups written to `dir`, named `leases-<UTC time>.db`
+#[derive(Debug, Clone)]
+pub struct Backups {
+    dir: PathBuf,
+    keep: usize,
+    /// backups are made one at a time
+    lock: Arc<Mutex<()>>,
+}
+
+impl Backups {
+    /// keeps the newest `keep` backups in `dir`, at least one
+    pub fn new(dir: impl Into<PathBuf>, keep: usize) -> Self {
+        Self {
+            dir: dir.into(),
+            keep: keep.max(1),
+            lock: Arc::default(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// back up the database now, then remove backups past `keep`
+    pub async fn run<T: Storage>(
+        &self,
+        ip_mgr: &IpManager<T>,
+    ) -> Result<BackupInfo, BackupError<T::Error>> {
+        let _guard = self.lock.lock().await;
+        let backup = self.write(ip_mgr).await;
+        match &backup {
+            Ok(backup) => {
+                metrics::BACKUP_COUNT.inc();
+                metrics::BACKUP_LAST.set(DateTime::<Utc>::from(backup.created_at).timestamp());
+            }
+            Err(_) => metrics::BACKUP_FAILURE_COUNT.inc(),
+        }
+        let backup = backup?;
+        self.prune().await?;
+        Ok(backup)
+    }
+
+    async fn write<T: Storage>(
+        &self,
+        ip_mgr: &IpManager<T>,
+    ) -> Result<BackupInfo, BackupError<T::Error>> {
+        fs::create_dir_all(&self.dir).await?;
+        let name = DateTime::<Utc>::from(SystemTime::now()).format("%Y%m%dT%H%M%S%.3fZ");
+        let path = self.dir.join(format!("{PREFIX}{name}{SUFFIX}"));
+        let mut tmp = path.clone();
+        tmp.as_mut_os_string().push(".tmp");
+        // left behind by a backup that was interrupted, the database won't overwrite it
+        remove(&tmp).await?;
+        if let Err(err) = ip_mgr.backup(&tmp).await {
+            let _ = remove(&tmp).await;
+            return Err(err.into());
+        }
+        fs::rename(&tmp, &path).await?;
+        let meta = fs::metadata(&path).await?;
+        let backup = BackupInfo {
+            path,
+            size: meta.len(),
+            created_at: meta.modified()?,
+        };
+        info!(path = %backup.path.display(), size = backup.size, "backed up leases database");
+        Ok(backup)
+    }
+
+    /// the newest backup, `None` if there are none
+    pub async fn latest(&self) -> io::Result<Option<BackupInfo>> {
+        Ok(self.list().await?.pop())
+    }
+
+    /// backups in `dir`, oldest first
+    pub async fn list(&self) -> io::Result<Vec<BackupInfo>> {
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut backups = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let is_backup = name
+                .to_str()
+                .is_some_and(|name| name.starts_with(PREFIX) && name.ends_with(SUFFIX));
+            if !is_backup {
+                continue;
+            }
+            let meta = entry.metadata().await?;
+            backups.push(BackupInfo {
+                path: entry.path(),
+                size: meta.len(),
+                created_at: meta.modified()?,
+            });
+        }
+        // names sort by the time they were made
+        backups.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(backups)
+    }
+
+    async fn prune(&self) -> io::Result<()> {
+        let backups = self.list().await?;
+        let extra = backups.len().saturating_sub(self.keep);
+        for backup in &backups[..extra] {
+            fs::remove_file(&backup.path).await?;
+            debug!(path = %backup.path.display(), "removed old backup");
+        }
+        Ok(())
+    }
+}
+
+/// remove `path` if it exists
+async fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, time::Duration};
+
+    use super::*;
+    use crate::{sqlite::SqliteDb, IpState, State};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_backups() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let ip = IpAddr::from([192, 168, 0, 150]);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        mgr.store
+            .insert(
+                ip,
+                [192, 168, 0, 0].into(),
+                &[1],
+                expires_at,
+                Some(IpState::Lease),
+            )
+            .await?;
+
+        let dir = std::env::temp_dir().join(format!("dora-backups-{}", std::process::id()));
+        let backups = Backups::new(&dir, 2);
+        assert_eq!(backups.latest().await?, None);
+        let mut made = Vec::new();
+        for _ in 0..3 {
+            made.push(backups.run(&mgr).await?);
+            // names have ms precision
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let kept = backups.list().await?;
+        assert_eq!(kept, made[1..]);
+        assert_eq!(backups.latest().await?.as_ref(), made.last());
+
+        let copy = SqliteDb::new(format!("sqlite://{}", made[2].path.display())).await?;
+        let state = copy.get(ip).await?;
+        std::fs::remove_dir_all(&dir)?;
+        assert!(matches!(state, Some(State::Leased(info)) if info.ip() == ip));
+        Ok(())
+    }
+}
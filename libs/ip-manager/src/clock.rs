@@ -0,0 +1,210 @@
+//! # clock
+//!
+//! Expiries are stored as wall clock times, so when the wall clock is stepped (NTP
+//! correction, VM resume) every expiry moves with it. A step forward can expire many
+//! leases at once & hand their addresses to other clients, a step back extends them.
+//!
+//! Once [`watch`] is called, the wall clock is compared to the monotonic clock each time
+//! it's read. If they drift apart by more than the threshold, expiry is frozen: [`now`]
+//! returns a time anchored before the jump that advances with the monotonic clock, and
+//! new expiries are computed from it too, so old & new leases stay consistent. It stays
+//! that way until an operator checks the wall clock & calls [`acknowledge`], or dora
+//! restarts, from then on leases expire by the wall clock & the jump takes effect.
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use dora_core::metrics;
+use tracing::{info, warn};
+
+static WATCH: Mutex<Option<ClockWatch>> = Mutex::new(None);
+
+/// start watching for wall clock jumps larger than `threshold`, freezing expiry
+/// until one is acknowledged
+pub fn watch(threshold: Duration) {
+    info!(?threshold, "watching for wall clock jumps");
+    *WATCH.lock().unwrap() = Some(ClockWatch::new(
+        threshold,
+        SystemTime::now(),
+        Instant::now(),
+    ));
+}
+
+/// the time expiries are compared to & computed from. This is the wall clock unless a
+/// jump was found & hasn't been acknowledged
+pub fn now() -> SystemTime {
+    let wall = SystemTime::now();
+    match WATCH.lock().unwrap().as_mut() {
+        Some(watch) => watch.observe(wall, Instant::now()),
+        None => wall,
+    }
+}
+
+/// seconds the wall clock is ahead of [`now`], negative if it's behind. `None` if expiry
+/// isn't frozen
+pub fn offset() -> Option<i64> {
+    let wall = SystemTime::now();
+    let mut watch = WATCH.lock().unwrap();
+    let watch = watch.as_mut()?;
+    let now = watch.observe(wall, Instant::now());
+    watch.frozen.map(|_| secs_between(now, wall))
+}
+
+/// go back to the wall clock after a jump, once it's been checked. Leases expire or are
+/// extended by the jump from then on. Returns the [`offset`] that was dropped, `None` if
+/// expiry wasn't frozen
+pub fn acknowledge() -> Option<i64> {
+    let wall = SystemTime::now();
+    WATCH
+        .lock()
+        .unwrap()
+        .as_mut()
+        .and_then(|watch| watch.acknowledge(wall, Instant::now()))
+}
+
+#[derive(Debug)]
+struct ClockWatch {
+    threshold: Duration,
+    /// wall & monotonic time of the last observation
+    wall: SystemTime,
+    mono: Instant,
+    frozen: Option<Frozen>,
+}
+
+/// expiries are compared to `anchor` plus the time since `since`
+#[derive(Debug, Clone, Copy)]
+struct Frozen {
+    anchor: SystemTime,
+    since: Instant,
+}
+
+impl Frozen {
+    fn at(&self, mono: Instant) -> SystemTime {
+        self.anchor + mono.saturating_duration_since(self.since)
+    }
+}
+
+impl ClockWatch {
+    fn new(threshold: Duration, wall: SystemTime, mono: Instant) -> Self {
+        Self {
+            threshold,
+            wall,
+            mono,
+            frozen: None,
+        }
+    }
+
+    /// record the clocks at `wall` & `mono`, returning the time expiries are compared to
+    fn observe(&mut self, wall: SystemTime, mono: Instant) -> SystemTime {
+        let expected = self.wall + mono.saturating_duration_since(self.mono);
+        let (drift, forward) = match wall.duration_since(expected) {
+            Ok(ahead) => (ahead, true),
+            Err(behind) => (behind.duration(), false),
+        };
+        self.wall = wall;
+        self.mono = mono;
+        if drift > self.threshold {
+            metrics::CLOCK_JUMP_COUNT.inc();
+            metrics::CLOCK_JUMP_FROZEN.set(1);
+            warn!(
+                ?drift,
+                forward,
+                expected = %rfc3339(expected),
+                wall = %rfc3339(wall),
+                "wall clock jumped, freezing lease expiry until the jump is acknowledged"
+            );
+            // a second jump keeps the original anchor
+            self.frozen.get_or_insert(Frozen {
+                anchor: expected,
+                since: mono,
+            });
+        }
+        match self.frozen {
+            Some(frozen) => frozen.at(mono),
+            None => wall,
+        }
+    }
+
+    fn acknowledge(&mut self, wall: SystemTime, mono: Instant) -> Option<i64> {
+        let now = self.observe(wall, mono);
+        self.frozen.take()?;
+        metrics::CLOCK_JUMP_FROZEN.set(0);
+        let offset = secs_between(now, wall);
+        info!(
+            offset,
+            "clock jump acknowledged, lease expiry uses the wall clock again"
+        );
+        Some(offset)
+    }
+}
+
+/// seconds from `from` to `to`, negative if `to` is earlier
+fn secs_between(from: SystemTime, to: SystemTime) -> i64 {
+    match to.duration_since(from) {
+        Ok(ahead) => ahead.as_secs() as i64,
+        Err(behind) => -(behind.duration().as_secs() as i64),
+    }
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_jump() {
+        let threshold = Duration::from_secs(30);
+        let wall = SystemTime::now();
+        let mono = Instant::now();
+        let mut watch = ClockWatch::new(threshold, wall, mono);
+        let secs = Duration::from_secs;
+
+        // clocks moving together, or drifting under the threshold
+        assert_eq!(
+            watch.observe(wall + secs(10), mono + secs(10)),
+            wall + secs(10)
+        );
+        assert_eq!(
+            watch.observe(wall + secs(30), mono + secs(20)),
+            wall + secs(30)
+        );
+        assert!(watch.frozen.is_none());
+        assert_eq!(watch.acknowledge(wall + secs(40), mono + secs(30)), None);
+
+        // step forward a day, expiry follows the monotonic clock from before the step
+        let day = secs(24 * 60 * 60);
+        assert_eq!(
+            watch.observe(wall + day + secs(50), mono + secs(40)),
+            wall + secs(50)
+        );
+        assert_eq!(
+            watch.observe(wall + day + secs(100), mono + secs(90)),
+            wall + secs(100)
+        );
+        // step back keeps the anchor
+        assert_eq!(watch.observe(wall, mono + secs(100)), wall + secs(110));
+        assert!(watch.frozen.is_some());
+        // & it doesn't wear off
+        let week = 7 * 24 * 60 * 60;
+        assert_eq!(
+            watch.observe(wall + secs(week), mono + secs(week + 10)),
+            wall + secs(week + 20)
+        );
+
+        // until it's acknowledged, then it's back to the wall clock
+        assert_eq!(
+            watch.acknowledge(wall + day + secs(week), mono + secs(week + 20)),
+            Some(day.as_secs() as i64 - 30)
+        );
+        assert!(watch.frozen.is_none());
+        assert_eq!(
+            watch.observe(wall + day + secs(week + 10), mono + secs(week + 30)),
+            wall + day + secs(week + 10)
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! # Host export
+//!
+//! Renders active leases as a hosts file or a BIND zone fragment, see `host_export` in
+//! the config. Hostnames come from clients, so anything that isn't a valid DNS name is
+//! left out rather than written into a file another server parses.
+use std::{
+    fmt::Write,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use config::wire::host_export::{HostExport, HostFormat};
+use dora_core::tokio::fs;
+
+/// render `hosts`, a list of IP & hostname ordered by IP
+pub fn render(cfg: &HostExport, hosts: &[(IpAddr, String)]) -> String {
+    let domain = cfg
+        .domain
+        .as_deref()
+        .map(|d| d.trim_matches('.'))
+        .filter(|d| !d.is_empty());
+    let mut out = String::new();
+    let comment = match cfg.format {
+        HostFormat::Hosts => '#',
+        HostFormat::Zone => ';',
+    };
+    let _ = writeln!(
+        out,
+        "{comment} generated by dora, changes will be overwritten"
+    );
+    for (ip, name) in hosts {
+        if !valid_hostname(name) {
+            continue;
+        }
+        let short = name.trim_end_matches('.');
+        // a name with a dot is taken as fully qualified
+        let fqdn = match domain {
+            Some(domain) if !short.contains('.') => Some(format!("{short}.{domain}")),
+            _ if short.contains('.') => Some(short.to_owned()),
+            _ => None,
+        };
+        let _ = match (cfg.format, fqdn) {
+            (HostFormat::Hosts, Some(fqdn)) if fqdn != short => {
+                writeln!(out, "{ip}\t{fqdn}\t{short}")
+            }
+            (HostFormat::Hosts, Some(fqdn)) => writeln!(out, "{ip}\t{fqdn}"),
+            (HostFormat::Hosts, None) => writeln!(out, "{ip}\t{short}"),
+            // unqualified names are relative to the including zone's origin
+            (HostFormat::Zone, fqdn) => writeln!(
+                out,
+                "{}\t{}\tIN\t{}\t{ip}",
+                fqdn.map(|f| f + ".").unwrap_or_else(|| short.to_owned()),
+                cfg.ttl,
+                if ip.is_ipv4() { "A" } else { "AAAA" }
+            ),
+        };
+    }
+    out
+}
+
+/// write `contents` to `path` by way of a temp file in the same directory, so readers
+/// never see a partial file
+pub async fn write(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut tmp = PathBuf::from(path);
+    tmp.as_mut_os_string().push(".tmp");
+    fs::write(&tmp, contents).await?;
+    fs::rename(&tmp, path).await
+}
+
+/// letters, digits & hyphens in labels of 1-63 characters, not starting or ending with a
+/// hyphen. A single trailing dot is allowed
+pub fn valid_hostname(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(format: HostFormat, domain: Option<&str>) -> HostExport {
+        HostExport {
+            path: "/tmp/hosts".into(),
+            format,
+            interval_secs: 60,
+            domain: domain.map(|d| d.to_owned()),
+            ttl: 300,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let hosts = vec![
+            ([192, 168, 0, 100].into(), "printer-3".to_owned()),
+            ([192, 168, 0, 101].into(), "laptop.corp.example.".to_owned()),
+            (
+                [192, 168, 0, 102].into(),
+                "bad name\n@ IN NS evil.".to_owned(),
+            ),
+        ];
+        assert_eq!(
+            render(&export(HostFormat::Hosts, Some("lab.example")), &hosts),
+            "# generated by dora, changes will be overwritten\n\
+             192.168.0.100\tprinter-3.lab.example\tprinter-3\n\
+             192.168.0.101\tlaptop.corp.example\n"
+        );
+        assert_eq!(
+            render(&export(HostFormat::Zone, None), &hosts),
+            "; generated by dora, changes will be overwritten\n\
+             printer-3\t300\tIN\tA\t192.168.0.100\n\
+             laptop.corp.example.\t300\tIN\tA\t192.168.0.101\n"
+        );
+    }
+
+    #[test]
+    fn test_valid_hostname() {
+        assert!(valid_hostname("printer-3"));
+        assert!(valid_hostname("a.b.c."));
+        assert!(!valid_hostname(""));
+        assert!(!valid_hostname("-lead"));
+        assert!(!valid_hostname("a..b"));
+        assert!(!valid_hostname("under_score"));
+    }
+}
@@ -12,7 +12,7 @@
 //!
 //! [`Storage`]: ip_manager::Storage
 //! [`IpManager`]: ip_manager::IpManager
-use config::v4::{NetRange, Network};
+use config::v4::{Exclusions, MultiNetworkPolicy, NetRange, Network, Oui};
 use icmp_ping::{Icmpv4, Listener, PingReply};
 
 use async_trait::async_trait;
@@ -21,23 +21,52 @@ use chrono::{offset::Utc, SecondsFormat};
 use thiserror::Error;
 use tracing::{debug, error, info, trace, warn};
 
+pub mod backup;
+pub mod clock;
+pub mod hosts;
+pub mod migrate;
+pub mod reservations;
 pub mod sqlite;
 
+pub use crate::reservations::Reservation;
+
 use core::fmt;
+use dora_core::{metrics, tokio::sync::RwLock};
 use std::{
-    collections::HashSet,
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     ops::RangeInclusive,
+    path::Path,
     sync::{
         atomic::{AtomicU16, Ordering},
         Arc,
     },
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
 const PING_TTL: u64 = 60;
+/// seconds an empty reservations table is trusted before storage is checked again, so
+/// reservations written by another process (ex. `dora-cfg`) are picked up
+const RESERVATIONS_RECHECK: u64 = 30;
+/// seconds from epoch to 9999-12-31T23:59:59Z, entries with this expiry never expire.
+/// Chosen to be in range for both sqlite's INTEGER and chrono
+const NEVER_EXPIRES_SECS: u64 = 253_402_300_799;
 pub type ClientId = Option<Vec<u8>>;
 
+/// Expiry time used for entries that never expire (infinite leases & BOOTP)
+pub fn never_expires() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(NEVER_EXPIRES_SECS)
+}
+
+/// Calculate the expiry time for a lease of `lease` duration starting now. An infinite
+/// lease time will return [`never_expires`]
+pub fn expires_at(lease: Duration) -> SystemTime {
+    if lease == config::INFINITE_LEASE {
+        never_expires()
+    } else {
+        clock::now() + lease
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
 pub struct ClientInfo {
     ip: IpAddr,
@@ -46,6 +75,51 @@ pub struct ClientInfo {
     expires_at: SystemTime,
 }
 
+impl ClientInfo {
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    pub fn id(&self) -> Option<&[u8]> {
+        self.id.as_deref()
+    }
+
+    /// the subnet the entry was handed out from
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    pub fn expires_at(&self) -> SystemTime {
+        self.expires_at
+    }
+}
+
+/// probation history for an IP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probation {
+    pub ip: IpAddr,
+    /// times probated since the count was last reset
+    pub count: u32,
+    pub last_probated: SystemTime,
+    /// a quarantined IP stays on probation until it's cleared
+    pub quarantined: bool,
+}
+
+/// count of un-expired entries in a range by state
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LeaseCounts {
+    pub leased: usize,
+    pub reserved: usize,
+    pub probated: usize,
+}
+
+impl LeaseCounts {
+    /// all addresses that can't be handed out
+    pub fn used(&self) -> usize {
+        self.leased + self.reserved + self.probated
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum IpState {
     Lease,
@@ -53,6 +127,144 @@ pub enum IpState {
     Reserve,
 }
 
+/// filter for paging through lease entries with [`Storage::select_page`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LeaseFilter {
+    /// only entries in this range
+    pub range: Option<RangeInclusive<IpAddr>>,
+    /// only entries in this state
+    pub state: Option<IpState>,
+    /// include expired entries
+    pub expired: bool,
+}
+
+/// position in a paged select, entries are returned in IP order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    /// start after this IP, `None` for the first page
+    pub after: Option<IpAddr>,
+    /// max entries in the page
+    pub limit: usize,
+}
+
+impl Cursor {
+    pub const DEFAULT_LIMIT: usize = 100;
+
+    /// first page with `limit` entries
+    pub fn first(limit: usize) -> Self {
+        Self { after: None, limit }
+    }
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Self::first(Self::DEFAULT_LIMIT)
+    }
+}
+
+/// a page of entries from [`Storage::select_page`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub entries: Vec<State>,
+    /// cursor for the next page, `None` if this was the last one
+    pub next: Option<Cursor>,
+}
+
+/// DHCP fingerprint of the client holding a lease
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaseFingerprint {
+    /// hash of the PRL, vendor class & option codes
+    pub fingerprint: String,
+    /// opt 55 as comma separated codes
+    pub prl: String,
+    /// opt 60
+    pub vendor_class: Option<String>,
+}
+
+/// an IPv6 address a SLAAC client registered with ADDR-REG-INFORM (RFC 9686)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddrRegistration {
+    pub ip: Ipv6Addr,
+    /// DUID from the client's Client Identifier option
+    pub client_id: Vec<u8>,
+    /// FQDN from the client's Client FQDN option
+    pub fqdn: Option<String>,
+    /// when the address's valid lifetime ends
+    pub expires_at: SystemTime,
+}
+
+/// a change to an IP's entry, storage records one whenever an entry is inserted, updated
+/// or removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub ip: IpAddr,
+    pub id: ClientId,
+    /// state after the change, `None` if the entry was removed
+    pub state: Option<IpState>,
+    /// expiry after the change, `None` if the entry was removed
+    pub expires_at: Option<SystemTime>,
+    /// when the change happened
+    pub at: SystemTime,
+}
+
+impl HistoryEntry {
+    /// did this entry hold the IP at `time`? Only meaningful for the last change
+    /// at or before `time`
+    pub fn active_at(&self, time: SystemTime) -> bool {
+        self.state.is_some()
+            && self.at <= time
+            && self.expires_at.is_some_and(|expires_at| expires_at > time)
+    }
+}
+
+/// filter for [`Storage::lease_history`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryFilter {
+    /// only changes at or after this time
+    pub since: Option<SystemTime>,
+    /// only changes at or before this time
+    pub until: Option<SystemTime>,
+    /// max entries, the most recent changes are kept
+    pub limit: usize,
+}
+
+impl Default for HistoryFilter {
+    fn default() -> Self {
+        Self {
+            since: None,
+            until: None,
+            limit: Cursor::DEFAULT_LIMIT,
+        }
+    }
+}
+
+/// a change from [`Storage::changes_after`], with the IP's entry before & after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// position in history, increases with each change
+    pub seq: u64,
+    /// the IP's previous change, `None` if it had none or it was pruned
+    pub before: Option<HistoryEntry>,
+    pub after: HistoryEntry,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl Change {
+    pub fn op(&self) -> ChangeOp {
+        match (&self.before, self.after.state) {
+            (_, None) => ChangeOp::Delete,
+            (Some(before), Some(_)) if before.state.is_some() => ChangeOp::Update,
+            _ => ChangeOp::Insert,
+        }
+    }
+}
+
 /// our sqlite impl doesn't properly support enums, so this
 /// converts our 3 state system into 2 bools.
 impl From<IpState> for (bool, bool) {
@@ -65,10 +277,22 @@ impl From<IpState> for (bool, bool) {
     }
 }
 
+/// An error from [`Storage`]. Retryable errors are transient, the same operation may
+/// succeed if it's tried again
+pub trait StorageError: std::error::Error + Send + Sync + 'static {
+    /// ex. the database is locked by another writer
+    fn is_retryable(&self) -> bool;
+    /// a concurrent insert took the row. The same write fails again, but allocating a
+    /// different IP may succeed
+    fn is_conflict(&self) -> bool {
+        false
+    }
+}
+
 #[async_trait]
 pub trait Storage: Send + Sync + 'static {
     // send/sync/static required for async trait bounds
-    type Error: std::error::Error + Send + Sync + 'static;
+    type Error: StorageError;
     /// updates if expired & ip matches or if ip & id match
     async fn update_expired(
         &self,
@@ -104,7 +328,7 @@ pub trait Storage: Send + Sync + 'static {
         &self,
         range: RangeInclusive<IpAddr>,
         // TODO not ipv4
-        exclusions: &HashSet<Ipv4Addr>,
+        exclusions: &Exclusions,
         network: IpAddr,
         id: &[u8],
         expires_at: SystemTime,
@@ -119,6 +343,15 @@ pub trait Storage: Send + Sync + 'static {
         expires_at: SystemTime,
         new_id: Option<&[u8]>,
     ) -> Result<Option<IpAddr>, Self::Error>;
+    /// updates if id & ip match and it expires after `since`, even if that's passed
+    async fn update_expired_since(
+        &self,
+        ip: IpAddr,
+        state: IpState,
+        id: &[u8],
+        expires_at: SystemTime,
+        since: SystemTime,
+    ) -> Result<Option<IpAddr>, Self::Error>;
     async fn update_ip(
         &self,
         ip: IpAddr,
@@ -126,7 +359,116 @@ pub trait Storage: Send + Sync + 'static {
         id: Option<&[u8]>,
         expires_at: SystemTime,
     ) -> Result<Option<State>, Self::Error>;
-    async fn count(&self, state: IpState) -> Result<usize, Self::Error>;
+    /// count un-expired entries in `range` by state
+    async fn count_range(&self, range: RangeInclusive<IpAddr>) -> Result<LeaseCounts, Self::Error>;
+    /// count un-expired entries in `range` with `state`, one state of [`Storage::count_range`]
+    async fn count_in_range(
+        &self,
+        range: RangeInclusive<IpAddr>,
+        state: IpState,
+    ) -> Result<usize, Self::Error> {
+        let counts = self.count_range(range).await?;
+        Ok(match state {
+            IpState::Lease => counts.leased,
+            IpState::Reserve => counts.reserved,
+            IpState::Probate => counts.probated,
+        })
+    }
+    /// delete all entries that expired before `before`, returning the number removed
+    async fn expire_before(&self, before: SystemTime) -> Result<u64, Self::Error>;
+    /// a page of entries matching `filter`, ordered by IP
+    async fn select_page(&self, filter: &LeaseFilter, cursor: Cursor) -> Result<Page, Self::Error>;
+    /// insert or replace reservations by ip, either all are written or none are
+    async fn insert_reservations(&self, reservations: &[Reservation]) -> Result<(), Self::Error>;
+    /// get the reservation for a MAC address
+    async fn get_reservation(&self, mac: &[u8]) -> Result<Option<Reservation>, Self::Error>;
+    /// `true` if any reservations have been imported
+    async fn has_reservations(&self) -> Result<bool, Self::Error>;
+    /// get the probation history for an IP
+    async fn get_probation(&self, ip: IpAddr) -> Result<Option<Probation>, Self::Error>;
+    /// insert or replace the probation history for an IP
+    async fn set_probation(&self, probation: &Probation) -> Result<(), Self::Error>;
+    /// all quarantined IPs
+    async fn quarantined(&self) -> Result<Vec<Probation>, Self::Error>;
+    /// remove the probation history & lease entry for a quarantined IP.
+    /// Returns false if the IP was not quarantined
+    async fn clear_quarantine(&self, ip: IpAddr) -> Result<bool, Self::Error>;
+    /// exclusions added at runtime, ordered by start
+    async fn exclusions(&self) -> Result<Vec<RangeInclusive<Ipv4Addr>>, Self::Error>;
+    /// persist an exclusion, adding an existing one does nothing
+    async fn add_exclusion(&self, range: RangeInclusive<Ipv4Addr>) -> Result<(), Self::Error>;
+    /// remove an exclusion with exactly this start & end. Returns false if there was none
+    async fn remove_exclusion(&self, range: RangeInclusive<Ipv4Addr>) -> Result<bool, Self::Error>;
+    /// changes to `ip` matching `filter`, oldest first
+    async fn lease_history(
+        &self,
+        ip: IpAddr,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<HistoryEntry>, Self::Error>;
+    /// delete history recorded before `before`, returning the number removed. With
+    /// `up_to`, changes with a later `seq` are kept
+    async fn prune_history(
+        &self,
+        before: SystemTime,
+        up_to: Option<u64>,
+    ) -> Result<u64, Self::Error>;
+    /// up to `limit` changes after `seq`, oldest first
+    async fn changes_after(&self, seq: u64, limit: usize) -> Result<Vec<Change>, Self::Error>;
+    /// the `seq` of the last change the change stream shipped, `None` if it never has
+    async fn change_cursor(&self) -> Result<Option<u64>, Self::Error>;
+    /// record that the change stream shipped every change up to `seq`
+    async fn set_change_cursor(&self, seq: u64) -> Result<(), Self::Error>;
+    /// record the hostname sent by the client holding `ip`, `None` removes it
+    async fn set_hostname(
+        &self,
+        ip: IpAddr,
+        id: &[u8],
+        hostname: Option<&str>,
+    ) -> Result<(), Self::Error>;
+    /// IP & hostname of un-expired leases with a hostname, ordered by IP
+    async fn active_hostnames(&self) -> Result<Vec<(IpAddr, String)>, Self::Error>;
+    /// record the fingerprint of the client holding `ip`
+    async fn set_fingerprint(
+        &self,
+        ip: IpAddr,
+        id: &[u8],
+        fingerprint: &LeaseFingerprint,
+    ) -> Result<(), Self::Error>;
+    /// IP & fingerprint of un-expired leases with a fingerprint, ordered by IP
+    async fn active_fingerprints(&self) -> Result<Vec<(IpAddr, LeaseFingerprint)>, Self::Error>;
+    /// record that `ip` was allocated to `id`, a client from `oui`
+    async fn set_oui(
+        &self,
+        ip: IpAddr,
+        network: IpAddr,
+        oui: [u8; 3],
+        id: &[u8],
+    ) -> Result<(), Self::Error>;
+    /// count un-expired entries in `network` held by clients from `oui`, other than `id`
+    async fn count_oui(
+        &self,
+        network: IpAddr,
+        oui: [u8; 3],
+        id: &[u8],
+    ) -> Result<usize, Self::Error>;
+    /// un-expired, un-probated entries held by `id` outside of `network`, ordered by IP
+    async fn leases_elsewhere(
+        &self,
+        id: &[u8],
+        network: IpAddr,
+    ) -> Result<Vec<IpAddr>, Self::Error>;
+    /// persisted counters as metric name, label value & count
+    async fn load_stats(&self) -> Result<Vec<(String, String, u64)>, Self::Error>;
+    /// insert or replace persisted counters, either all are written or none are
+    async fn save_stats(&self, stats: &[(String, String, u64)]) -> Result<(), Self::Error>;
+    /// insert or replace the registration of `reg.ip`
+    async fn register_addr(&self, reg: &AddrRegistration) -> Result<(), Self::Error>;
+    /// un-expired address registrations, ordered by IP
+    async fn addr_registrations(&self) -> Result<Vec<AddrRegistration>, Self::Error>;
+    /// move writes that are only in a journal into the database itself, ex. before exit
+    async fn checkpoint(&self) -> Result<(), Self::Error>;
+    /// write a consistent copy of the database to `path`, which must not exist
+    async fn backup(&self, path: &Path) -> Result<(), Self::Error>;
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -158,8 +500,14 @@ impl State {
 
 pub struct IpManager<T> {
     store: T,
-    icmpv4: Arc<IcmpInner>,
+    /// `None` if ping checks are disabled, see [`IpManager::without_ping`]
+    icmpv4: Option<Arc<IcmpInner>>,
     ping_cache: moka::future::Cache<IpAddr, Option<PingReply>>,
+    /// runtime exclusions from storage, loaded on first use & cleared when they change
+    exclusions: Arc<RwLock<Option<Exclusions>>>,
+    /// whether storage has any imported reservations & when that was checked. Loaded on
+    /// first use, set on import & re-checked after [`RESERVATIONS_RECHECK`] if there were none
+    has_reservations: Arc<RwLock<Option<(bool, Instant)>>>,
 }
 
 impl<T> fmt::Debug for IpManager<T> {
@@ -178,6 +526,8 @@ impl<T: Clone> Clone for IpManager<T> {
             store: self.store.clone(),
             icmpv4: self.icmpv4.clone(),
             ping_cache: self.ping_cache.clone(),
+            exclusions: self.exclusions.clone(),
+            has_reservations: self.has_reservations.clone(),
         }
     }
 }
@@ -199,9 +549,12 @@ where
         ip: IpAddr,
         timeout: Duration,
     ) -> Result<PingReply, icmp_ping::Error> {
-        let seq_cnt = self.icmpv4.seq_cnt.fetch_add(1, Ordering::Relaxed);
+        let Some(icmpv4) = &self.icmpv4 else {
+            return Err(icmp_ping::Error::ListenerDown);
+        };
+        let seq_cnt = icmpv4.seq_cnt.fetch_add(1, Ordering::Relaxed);
         // send a single ping
-        self.icmpv4
+        icmpv4
             .listener
             .pinger(ip)
             .timeout(timeout)
@@ -210,6 +563,20 @@ where
         // ping succeeded, meaning addr is in use
     }
 
+    /// whether the ICMP listener used for ping checks is running
+    pub fn icmp_healthy(&self) -> bool {
+        self.icmpv4
+            .as_ref()
+            .is_some_and(|icmpv4| icmpv4.listener.is_healthy())
+    }
+
+    /// `true` if `network` doesn't ping check or `ip` was recently pinged without a reply.
+    /// An address handed out without a ping, because the client already had it or the
+    /// ICMP listener was down, is not verified
+    pub fn ping_verified(&self, ip: IpAddr, network: &Network) -> bool {
+        !network.ping_check() || matches!(self.ping_cache.get(&ip), Some(None))
+    }
+
     /// used for tests to insert into ping cache
     #[cfg(test)]
     pub(crate) async fn ping_insert(&self, ip: IpAddr, reply: Option<PingReply>) {
@@ -219,7 +586,11 @@ where
     /// returns Ok(()) if ping failed or ping == false
     /// returns Err if ping succeeded
     pub async fn ping_check(&self, ip: IpAddr, network: &Network) -> Result<(), IpError<T::Error>> {
-        if network.ping_check() {
+        if network.ping_check() && !self.icmp_healthy() {
+            // don't cache the result, the address wasn't actually checked
+            debug!(?ip, "ICMP listener is down, skipping ping check");
+            Ok(())
+        } else if network.ping_check() {
             let fut = async {
                 match self.addr_in_use(ip, network.ping_timeout()).await {
                     Ok(reply) => {
@@ -248,18 +619,37 @@ where
     T: Storage,
 {
     pub fn new(store: T) -> Result<Self, icmp_ping::Error> {
+        let icmpv4 = IcmpInner {
+            seq_cnt: AtomicU16::new(1),
+            listener: Listener::<Icmpv4>::new()?,
+        };
         Ok(Self {
-            icmpv4: Arc::new(IcmpInner {
-                seq_cnt: AtomicU16::new(1),
-                listener: Listener::<Icmpv4>::new()?,
-            }),
+            icmpv4: Some(Arc::new(icmpv4)),
+            ..Self::without_ping(store)
+        })
+    }
+
+    /// an `IpManager` that never pings, for when an ICMP socket can't be opened, no
+    /// network has `ping_check` set or dora is a dry run. Addresses are handed out as if
+    /// the ICMP listener were down
+    pub fn without_ping(store: T) -> Self {
+        Self {
+            icmpv4: None,
             store,
             ping_cache: moka::future::CacheBuilder::new(1_000)
                 // time_to_idle?
                 .time_to_live(Duration::from_secs(PING_TTL))
                 .initial_capacity(1_000)
                 .build(),
-        })
+            exclusions: Arc::new(RwLock::new(None)),
+            has_reservations: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// number of ping results cached, this is an estimate as pending
+    /// insertions & evictions may not be counted yet
+    pub fn ping_cache_len(&self) -> u64 {
+        self.ping_cache.entry_count()
     }
 
     /// get the first available IP in a range with a given id/expiry/network
@@ -273,8 +663,9 @@ where
     ) -> Result<IpAddr, IpError<T::Error>> {
         const MAX_ATTEMPTS: usize = 2;
         let subnet = network.subnet().into();
-        // unfortunately the sqlite connection is sometimes unreliable under high contention, meaning
-        // we need to make a few attempts to get an address.
+        let exclusions = range.exclusions().merge(&self.dynamic_exclusions().await?);
+        // allocations in a range are serialized by storage, but a write can still fail if storage
+        // is busy (ex. another process has the db open), so we make a few attempts to get an address.
         let mut attempts = 0;
         loop {
             let ip_range = range.start().into()..=range.end().into();
@@ -296,7 +687,7 @@ where
                     .store
                     .insert_max_in_range(
                         ip_range.clone(),
-                        range.exclusions(),
+                        &exclusions,
                         subnet,
                         id,
                         expires_at,
@@ -307,30 +698,53 @@ where
                     Ok(ip) => ip.ok_or(IpError::RangeError {
                         range: ip_range.clone(),
                     })?,
+                    Err(err) if !err.is_retryable() && !err.is_conflict() => return Err(err.into()),
                     Err(err) => {
                         attempts += 1;
+                        if metrics::counted() {
+                            metrics::ALLOC_CONFLICT_COUNT.inc();
+                        }
                         warn!(?err, "error grabbing new IP-- retrying");
                         continue;
                     }
                 },
+                Err(err) if !err.is_retryable() && !err.is_conflict() => return Err(err.into()),
                 Err(err) => {
                     attempts += 1;
+                    if metrics::counted() {
+                        metrics::ALLOC_CONFLICT_COUNT.inc();
+                    }
                     warn!(?err, "error grabbing next expired IP-- retrying");
                     continue;
                 }
             };
             match ip {
                 IpAddr::V4(ipv4) => {
-                    if range.contains(&ipv4) {
+                    if range.contains(&ipv4) && !exclusions.contains(&ipv4) {
                         // ping_check will delete the expired entry if it's in use
                         match self.ping_check(ip, network).await {
-                            Ok(()) => return Ok(ip),
+                            Ok(()) => {
+                                if state == Some(IpState::Lease) {
+                                    self.leased_in(ip, id, network).await;
+                                }
+                                return Ok(ip);
+                            }
                             // ping success so insert probated IP
                             Err(err) => {
-                                let probation_time = SystemTime::now() + network.probation_period();
+                                let probation = match self.next_probation(ip, network).await {
+                                    Ok(probation) => probation,
+                                    Err(err) => {
+                                        attempts += 1;
+                                        error!(?err, "failed to get probation history");
+                                        continue;
+                                    }
+                                };
+                                let probation_time = probation_expiry(&probation, network);
                                 info!(
                                     ?err,
                                     probation_time = %DateTime::<Utc>::from(probation_time).to_rfc3339_opts(SecondsFormat::Secs, true),
+                                    count = probation.count,
+                                    quarantined = probation.quarantined,
                                     "ping succeeded. address is in use. marking IP on probation"
                                 );
                                 // update regardless of expiry/id because something is using the IP
@@ -342,6 +756,9 @@ where
                                     attempts += 1;
                                     error!(?err, "failed to probate IP on ping success");
                                     // not returning error because we must give client an IP
+                                } else if let Err(err) = self.store.set_probation(&probation).await
+                                {
+                                    error!(?err, "failed to record probation history");
                                 } else {
                                     debug!("IP put on probation, trying next");
                                 }
@@ -353,7 +770,7 @@ where
                         warn!(
                             ?range,
                             ?ipv4,
-                            "IP for client id returned from leases table is outside of network range"
+                            "IP for client id returned from leases table is outside of network range or excluded"
                         );
                         // entry for ip/id but the range doesn't match, remove the old entry
                         if let Err(err) = self.store.release_ip(ip, id).await {
@@ -372,7 +789,8 @@ where
     /// tries to take an ip for an id that's set to expire at some future time.
     /// If `ping` is set, will send a ping to the IP, returning an error if in use
     /// Returns
-    ///     `Err` if ip/id are already present or ping succeeded
+    ///     `Err(IpError::IdMismatch)` if the ip is held by another client
+    ///     `Err(IpError::AddrInUse)` if ping succeeded
     ///     `Ok(())` allocated IP successfully
     pub async fn try_ip(
         &self,
@@ -383,6 +801,7 @@ where
         network: &Network,
         state: Option<IpState>,
     ) -> Result<(), IpError<T::Error>> {
+        self.check_excluded(ip).await?;
         // TODO: there may be a way to remove this .get also
         if self.store.get(ip).await?.is_some() {
             return if self.store.update_expired(ip, state, id, expires_at).await? {
@@ -391,16 +810,22 @@ where
                     ?id,
                     "set reserved, found ip/id for this client or expired"
                 );
+                if state == Some(IpState::Lease) {
+                    self.leased_in(ip, id, network).await;
+                }
                 Ok(())
             } else {
                 debug!("IP not updated, couldn't find ip/id or in use");
-                Err(IpError::AddrInUse(ip))
+                Err(IpError::IdMismatch(ip))
             };
         };
         // if the entry doesn't exist yet & ping fails, insert it
         self.store.insert(ip, subnet, id, expires_at, state).await?;
         // not marking for probation because request IP can be sent at any time
         self.ping_check(ip, network).await?;
+        if state == Some(IpState::Lease) {
+            self.leased_in(ip, id, network).await;
+        }
 
         Ok(())
     }
@@ -425,7 +850,8 @@ where
     /// found, then if we're authoritative we will just try to insert the IP, and
     /// if not we return.
     /// Returns
-    ///     Err if ip/id don't match what's in storage or if it's expired
+    ///     Err if ip/id don't match what's in storage or if it's expired, ex. `IdMismatch`
+    ///     or `Expired`
     ///     Ok(()) entry created successfully for lease
     pub async fn try_lease(
         &self,
@@ -434,17 +860,36 @@ where
         expires_at: SystemTime,
         network: &Network,
     ) -> Result<(), IpError<T::Error>> {
-        match self
+        self.check_excluded(ip).await?;
+        let mut updated = self
             .store
             .update_unexpired(ip, IpState::Lease, id, expires_at, Some(id))
-            .await?
-        {
+            .await?;
+        if let (None, Some(grace)) = (updated, network.lease_grace()) {
+            // the client's lease ran out a moment ago, it's still theirs if no one else
+            // has been given the IP
+            let since = clock::now()
+                .checked_sub(grace)
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            updated = self
+                .store
+                .update_expired_since(ip, IpState::Lease, id, expires_at, since)
+                .await?;
+            if updated.is_some() {
+                debug!(?ip, ?id, ?grace, "re-granting expired lease within grace");
+                if metrics::counted() {
+                    metrics::LEASE_GRACE_REGRANT_COUNT.inc();
+                }
+            }
+        }
+        match updated {
             Some(ip) => {
                 debug!(
                     ?ip,
                     ?id,
                     "found ip for id-- updating expiry and setting leased"
                 );
+                self.leased_in(ip, id, network).await;
                 Ok(())
             }
             None if network.authoritative() => {
@@ -468,20 +913,112 @@ where
                 {
                     Ok(()) => {
                         trace!("inserted new IP");
+                        self.leased_in(ip, id, network).await;
                         Ok(())
                     }
                     Err(err) => {
+                        if metrics::counted() {
+                            metrics::ALLOC_CONFLICT_COUNT.inc();
+                        }
                         warn!(
                             ?err,
                             "insert failed, likely ip already exists & taken by another client"
                         );
-                        Err(IpError::AddrInUse(ip))
+                        match self.conflict(ip, id, network).await {
+                            // nothing holds the IP, the insert failed on its own
+                            IpError::Unreserved => Err(err.into()),
+                            conflict => Err(conflict),
+                        }
                     }
                 }
             }
             None => {
                 debug!(?ip, ?id, "no IP with this id found or expired");
-                Err(IpError::AddrInUse(ip))
+                Err(self.conflict(ip, id, network).await)
+            }
+        }
+    }
+
+    /// Why `ip` couldn't be leased to `id` in `network`, from the entry storage has for it
+    /// now.
+    ///     `IdMismatch` if it's held by another client
+    ///     `WrongNetwork` if the client's entry is from a different network
+    ///     `Expired` if the client's entry has expired
+    ///     `Unreserved` if there is no entry
+    ///     `AddrInUse` otherwise, ex. it's on probation
+    async fn conflict(&self, ip: IpAddr, id: &[u8], network: &Network) -> IpError<T::Error> {
+        let info = match self.store.get(ip).await {
+            Ok(Some(State::Leased(info) | State::Reserved(info))) => info,
+            Ok(Some(State::Probated(_))) => return IpError::AddrInUse(ip),
+            Ok(None) => return IpError::Unreserved,
+            Err(err) => return err.into(),
+        };
+        let subnet: IpAddr = network.subnet().into();
+        if info.id.as_deref() != Some(id) {
+            IpError::IdMismatch(ip)
+        } else if info.network != subnet {
+            IpError::WrongNetwork {
+                ip,
+                network: info.network,
+            }
+        } else if info.expires_at <= clock::now() {
+            IpError::Expired(ip)
+        } else {
+            IpError::AddrInUse(ip)
+        }
+    }
+
+    /// apply `network`'s `multi_network_leases` policy now that `id` leased `ip` there.
+    /// Failures are logged, the new lease stands either way
+    async fn leased_in(&self, ip: IpAddr, id: &[u8], network: &Network) {
+        let elsewhere = match self
+            .store
+            .leases_elsewhere(id, network.subnet().into())
+            .await
+        {
+            Ok(elsewhere) if elsewhere.is_empty() => return,
+            Ok(elsewhere) => elsewhere,
+            Err(err) => {
+                error!(?err, "failed to find leases in other networks");
+                return;
+            }
+        };
+        let policy = network.multi_network_leases();
+        if metrics::counted() {
+            metrics::NETWORK_MOVE_COUNT
+                .with_label_values(&[match policy {
+                    MultiNetworkPolicy::Release => "release",
+                    MultiNetworkPolicy::Allow => "allow",
+                }])
+                .inc();
+        }
+        match policy {
+            MultiNetworkPolicy::Allow => {
+                debug!(
+                    ?ip,
+                    ?id,
+                    ?elsewhere,
+                    "client also holds leases in other networks"
+                );
+            }
+            MultiNetworkPolicy::Release => {
+                for old in elsewhere {
+                    match self.store.release_ip(old, id).await {
+                        Ok(_) => {
+                            if metrics::counted() {
+                                metrics::NETWORK_MOVE_RELEASED_COUNT.inc();
+                            }
+                            info!(
+                                ?ip,
+                                ?old,
+                                ?id,
+                                subnet = ?network.subnet(),
+                                "client moved networks, released its old lease"
+                            );
+                        }
+                        Err(err) => error!(?err, ?old, "failed to release lease in other network"),
+                    }
+                }
             }
         }
     }
@@ -500,15 +1037,28 @@ where
         Ok(self.store.release_ip(ip, id).await?)
     }
 
+    /// Returns true if `ip` was offered or leased to `id` by us & hasn't expired.
+    /// Probated entries don't count, they were already declined or found in use
+    pub async fn is_leased_to(&self, ip: IpAddr, id: &[u8]) -> Result<bool, IpError<T::Error>> {
+        Ok(match self.store.get(ip).await? {
+            Some(State::Leased(info) | State::Reserved(info)) => {
+                info.id.as_deref() == Some(id) && info.expires_at > clock::now()
+            }
+            _ => false,
+        })
+    }
+
     /// Will mark IP for probation if it is un-expired and ip/id match
     /// we check to see if it has expired because a DECLINE happens after
-    /// an address has been ACKd.
+    /// an address has been ACKd. The probation period & quarantine come from `network`
     pub async fn probate_ip(
         &self,
         ip: IpAddr,
         id: &[u8],
-        expires_at: SystemTime,
+        network: &Network,
     ) -> Result<(), IpError<T::Error>> {
+        let probation = self.next_probation(ip, network).await?;
+        let expires_at = probation_expiry(&probation, network);
         match self
             .store
             .update_unexpired(ip, IpState::Probate, id, expires_at, None)
@@ -518,8 +1068,13 @@ where
                 debug!(
                     ?ip,
                     ?id,
+                    count = probation.count,
                     "found ip for id-- updating expiry and set PROBATION"
                 );
+                if probation.quarantined {
+                    warn!(?ip, count = probation.count, "IP quarantined");
+                }
+                self.store.set_probation(&probation).await?;
                 Ok(())
             }
             None => {
@@ -532,79 +1087,469 @@ where
             }
         }
     }
-}
 
-#[derive(Error, Debug)]
-pub enum IpError<E> {
-    #[error("ip is leased {0:?}")]
-    Leased(ClientInfo),
-    #[error("ip is reserved {0:?}")]
-    Reserved(ClientInfo),
-    #[error("ip is unreserved")]
-    Unreserved,
-    #[error("database error")]
-    DbError(#[from] E),
-    #[error("this address is already in use {0:?}")]
-    AddrInUse(IpAddr),
-    #[error("error getting next IP in range {range:?}")]
-    RangeError { range: RangeInclusive<IpAddr> },
-    #[error("error getting next IP in range {range:?} inside attempts {attempts:?}")]
-    MaxAttempts {
-        range: RangeInclusive<IpAddr>,
-        attempts: usize,
-    },
-}
+    /// The probation history for `ip` after one more probation, applying the network's
+    /// decay & max probation policy. Nothing is written to storage
+    async fn next_probation(
+        &self,
+        ip: IpAddr,
+        network: &Network,
+    ) -> Result<Probation, IpError<T::Error>> {
+        let now = clock::now();
+        let count = match self.store.get_probation(ip).await? {
+            // forgive probations older than the decay interval
+            Some(prev)
+                if network
+                    .probation_decay()
+                    .map(|decay| prev.last_probated + decay < now)
+                    .unwrap_or(false) =>
+            {
+                0
+            }
+            Some(prev) => prev.count,
+            None => 0,
+        }
+        .saturating_add(1);
+        Ok(Probation {
+            ip,
+            count,
+            last_probated: now,
+            quarantined: network
+                .max_probations()
+                .map(|max| count >= max)
+                .unwrap_or(false),
+        })
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::net::{SocketAddr, SocketAddrV4};
+    /// count un-expired leased/reserved/probated addresses in a range
+    pub async fn range_counts(&self, range: &NetRange) -> Result<LeaseCounts, IpError<T::Error>> {
+        Ok(self
+            .store
+            .count_range(range.start().into()..=range.end().into())
+            .await?)
+    }
 
-    use super::*;
-    use crate::sqlite::SqliteDb;
-    use config::LeaseTime;
-    use icmp_ping::{EchoReply, DEFAULT_TOKEN_SIZE};
-    use rand::Rng;
-    use tracing_test::traced_test;
+    /// count un-expired addresses in a range with `state`
+    pub async fn count_in_range(
+        &self,
+        range: &NetRange,
+        state: IpState,
+    ) -> Result<usize, IpError<T::Error>> {
+        Ok(self
+            .store
+            .count_in_range(range.start().into()..=range.end().into(), state)
+            .await?)
+    }
 
-    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+    /// delete entries that expired before `before`. Expired entries are reused in place by
+    /// [`IpManager::reserve_first`], so this only matters for keeping storage small. A client
+    /// whose entry was removed can't get its old IP back through the expired entry
+    pub async fn expire_before(&self, before: SystemTime) -> Result<u64, IpError<T::Error>> {
+        let removed = self.store.expire_before(before).await?;
+        if removed > 0 {
+            debug!(removed, "removed expired entries");
+        }
+        Ok(removed)
+    }
 
-    // get multiple first-available IPs in a range
-    // this mimics what happens when multiple clients simultaneously 'DISCOVER'
-    #[tokio::test]
-    #[traced_test]
-    async fn test_first_available() -> Result<()> {
-        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
-        let range = NetRange::new(
-            Ipv4Addr::new(192, 168, 1, 100)..=Ipv4Addr::new(192, 168, 1, 255),
-            LeaseTime::new(
-                Duration::from_secs(5),
-                Duration::from_secs(3),
-                Duration::from_secs(10),
-            ),
-        );
-        let mut network = Network::default();
-        network
-            .set_subnet("192.168.1.0/24".parse()?)
-            .set_ranges(vec![range.clone()]);
-        let client_id = &[1, 2, 3, 4, 5, 6];
-        let expires_at = SystemTime::now() + Duration::from_secs(60);
-        let ip = mgr
-            .reserve_first(&range, &network, client_id, expires_at, None)
-            .await?;
-        assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100)));
-        assert_eq!(
-            mgr.lookup_id(client_id).await?,
-            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100))
-        );
+    /// a page of entries matching `filter`, pass [`Page::next`] to get the following page
+    pub async fn select_page(
+        &self,
+        filter: &LeaseFilter,
+        cursor: Cursor,
+    ) -> Result<Page, IpError<T::Error>> {
+        Ok(self.store.select_page(filter, cursor).await?)
+    }
 
-        let client_id = &[2, 2, 3, 4, 5, 6];
-        let ip = mgr
-            .reserve_first(&range, &network, client_id, expires_at, None)
-            .await?;
-        assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 101)));
-        assert_eq!(
-            mgr.lookup_id(client_id).await?,
-            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 101))
+    /// changes to `ip` matching `filter`, oldest first
+    pub async fn lease_history(
+        &self,
+        ip: IpAddr,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<HistoryEntry>, IpError<T::Error>> {
+        Ok(self.store.lease_history(ip, filter).await?)
+    }
+
+    /// the change that left `ip` held at `time`, `None` if nothing held it then
+    pub async fn held_at(
+        &self,
+        ip: IpAddr,
+        time: SystemTime,
+    ) -> Result<Option<HistoryEntry>, IpError<T::Error>> {
+        let filter = HistoryFilter {
+            until: Some(time),
+            limit: 1,
+            ..HistoryFilter::default()
+        };
+        Ok(self
+            .store
+            .lease_history(ip, &filter)
+            .await?
+            .pop()
+            .filter(|entry| entry.active_at(time)))
+    }
+
+    /// delete lease history recorded before `before`. With `keep_unshipped` changes the
+    /// change stream hasn't shipped are kept, all of them if it never has
+    pub async fn prune_history(
+        &self,
+        before: SystemTime,
+        keep_unshipped: bool,
+    ) -> Result<u64, IpError<T::Error>> {
+        let up_to = if keep_unshipped {
+            Some(self.store.change_cursor().await?.unwrap_or(0))
+        } else {
+            None
+        };
+        let removed = self.store.prune_history(before, up_to).await?;
+        if removed > 0 {
+            debug!(removed, "pruned lease history");
+        }
+        Ok(removed)
+    }
+
+    /// record the hostname the client holding `ip` sent, used by the host export
+    pub async fn set_hostname(
+        &self,
+        ip: IpAddr,
+        id: &[u8],
+        hostname: Option<&str>,
+    ) -> Result<(), IpError<T::Error>> {
+        Ok(self.store.set_hostname(ip, id, hostname).await?)
+    }
+
+    /// IP & hostname of un-expired leases with a hostname, ordered by IP
+    pub async fn active_hostnames(&self) -> Result<Vec<(IpAddr, String)>, IpError<T::Error>> {
+        Ok(self.store.active_hostnames().await?)
+    }
+
+    /// record the fingerprint of the client holding `ip`, for inventory
+    pub async fn set_fingerprint(
+        &self,
+        ip: IpAddr,
+        id: &[u8],
+        fingerprint: &LeaseFingerprint,
+    ) -> Result<(), IpError<T::Error>> {
+        Ok(self.store.set_fingerprint(ip, id, fingerprint).await?)
+    }
+
+    /// IP & fingerprint of un-expired leases with a fingerprint, ordered by IP
+    pub async fn active_fingerprints(
+        &self,
+    ) -> Result<Vec<(IpAddr, LeaseFingerprint)>, IpError<T::Error>> {
+        Ok(self.store.active_fingerprints().await?)
+    }
+
+    /// record the OUI of the client `ip` was allocated to, for networks with `oui_limits`
+    pub async fn set_oui(
+        &self,
+        ip: IpAddr,
+        network: &Network,
+        oui: Oui,
+        id: &[u8],
+    ) -> Result<(), IpError<T::Error>> {
+        Ok(self
+            .store
+            .set_oui(ip, network.subnet().into(), oui.0, id)
+            .await?)
+    }
+
+    /// un-expired addresses in `network` held by clients from `oui`, not counting any
+    /// held by `id`
+    pub async fn oui_leases(
+        &self,
+        network: &Network,
+        oui: Oui,
+        id: &[u8],
+    ) -> Result<usize, IpError<T::Error>> {
+        Ok(self
+            .store
+            .count_oui(network.subnet().into(), oui.0, id)
+            .await?)
+    }
+
+    /// up to `limit` changes after `seq`, oldest first
+    pub async fn changes_after(
+        &self,
+        seq: u64,
+        limit: usize,
+    ) -> Result<Vec<Change>, IpError<T::Error>> {
+        Ok(self.store.changes_after(seq, limit).await?)
+    }
+
+    /// the `seq` of the last change the change stream shipped
+    pub async fn change_cursor(&self) -> Result<Option<u64>, IpError<T::Error>> {
+        Ok(self.store.change_cursor().await?)
+    }
+
+    /// record that the change stream shipped every change up to `seq`
+    pub async fn set_change_cursor(&self, seq: u64) -> Result<(), IpError<T::Error>> {
+        Ok(self.store.set_change_cursor(seq).await?)
+    }
+
+    /// counters saved by [`IpManager::save_stats`], as metric name, label value & count
+    pub async fn load_stats(&self) -> Result<Vec<(String, String, u64)>, IpError<T::Error>> {
+        Ok(self.store.load_stats().await?)
+    }
+
+    /// checkpoint counters that should survive a restart
+    pub async fn save_stats(
+        &self,
+        stats: &[(String, String, u64)],
+    ) -> Result<(), IpError<T::Error>> {
+        Ok(self.store.save_stats(stats).await?)
+    }
+
+    /// record an address registered by a SLAAC client, a later registration of the same
+    /// address replaces it
+    pub async fn register_addr(&self, reg: &AddrRegistration) -> Result<(), IpError<T::Error>> {
+        Ok(self.store.register_addr(reg).await?)
+    }
+
+    /// un-expired address registrations, ordered by IP
+    pub async fn addr_registrations(&self) -> Result<Vec<AddrRegistration>, IpError<T::Error>> {
+        Ok(self.store.addr_registrations().await?)
+    }
+
+    /// all quarantined IPs
+    pub async fn quarantined(&self) -> Result<Vec<Probation>, IpError<T::Error>> {
+        Ok(self.store.quarantined().await?)
+    }
+
+    /// release a quarantined IP so it can be leased again
+    /// Returns
+    ///     Ok(false) if the IP was not quarantined
+    pub async fn clear_quarantine(&self, ip: IpAddr) -> Result<bool, IpError<T::Error>> {
+        let cleared = self.store.clear_quarantine(ip).await?;
+        if cleared {
+            info!(?ip, "cleared quarantine");
+        }
+        Ok(cleared)
+    }
+
+    /// exclusions added at runtime, these apply on top of each range's `except` list
+    pub async fn exclusions(&self) -> Result<Vec<RangeInclusive<Ipv4Addr>>, IpError<T::Error>> {
+        Ok(self.store.exclusions().await?)
+    }
+
+    /// persist a new exclusion. Addresses that are already leased keep their lease,
+    /// but won't be renewed or handed out again
+    pub async fn add_exclusion(
+        &self,
+        range: RangeInclusive<Ipv4Addr>,
+    ) -> Result<(), IpError<T::Error>> {
+        self.store.add_exclusion(range.clone()).await?;
+        self.exclusions.write().await.take();
+        info!(?range, "added exclusion");
+        Ok(())
+    }
+
+    /// remove an exclusion previously added with [`IpManager::add_exclusion`]
+    /// Returns
+    ///     Ok(false) if there was no exclusion with this start & end
+    pub async fn remove_exclusion(
+        &self,
+        range: RangeInclusive<Ipv4Addr>,
+    ) -> Result<bool, IpError<T::Error>> {
+        let removed = self.store.remove_exclusion(range.clone()).await?;
+        if removed {
+            self.exclusions.write().await.take();
+            info!(?range, "removed exclusion");
+        }
+        Ok(removed)
+    }
+
+    /// runtime exclusions, loaded from storage if they aren't cached
+    async fn dynamic_exclusions(&self) -> Result<Exclusions, IpError<T::Error>> {
+        if let Some(exclusions) = self.exclusions.read().await.as_ref() {
+            return Ok(exclusions.clone());
+        }
+        let mut cached = self.exclusions.write().await;
+        // another task may have loaded them while we waited for the lock
+        if let Some(exclusions) = cached.as_ref() {
+            return Ok(exclusions.clone());
+        }
+        let exclusions = Exclusions::new(self.store.exclusions().await?);
+        *cached = Some(exclusions.clone());
+        Ok(exclusions)
+    }
+
+    /// `Err(IpError::Excluded)` if `ip` was excluded at runtime
+    async fn check_excluded(&self, ip: IpAddr) -> Result<(), IpError<T::Error>> {
+        match ip {
+            IpAddr::V4(v4) if self.dynamic_exclusions().await?.contains(&v4) => {
+                Err(IpError::Excluded(ip))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// write a batch of imported reservations to storage
+    pub async fn import_reservations(
+        &self,
+        reservations: &[Reservation],
+    ) -> Result<(), IpError<T::Error>> {
+        self.store.insert_reservations(reservations).await?;
+        if !reservations.is_empty() {
+            *self.has_reservations.write().await = Some((true, Instant::now()));
+        }
+        info!(count = reservations.len(), "imported reservations");
+        Ok(())
+    }
+
+    /// look up an imported reservation by MAC, storage is only queried if
+    /// any reservations have been imported
+    pub async fn reservation(&self, mac: &[u8]) -> Result<Option<Reservation>, IpError<T::Error>> {
+        if !self.has_reservations().await? {
+            return Ok(None);
+        }
+        Ok(self.store.get_reservation(mac).await?)
+    }
+
+    /// whether any reservations were imported, loaded from storage if it isn't cached.
+    /// Reservations are never removed, so only "none" goes stale
+    async fn has_reservations(&self) -> Result<bool, IpError<T::Error>> {
+        let fresh = |cached: Option<(bool, Instant)>| {
+            cached.filter(|(has, checked)| {
+                *has || checked.elapsed() < Duration::from_secs(RESERVATIONS_RECHECK)
+            })
+        };
+        if let Some((has, _)) = fresh(*self.has_reservations.read().await) {
+            return Ok(has);
+        }
+        let mut cached = self.has_reservations.write().await;
+        // another task may have loaded it while we waited for the lock
+        if let Some((has, _)) = fresh(*cached) {
+            return Ok(has);
+        }
+        let has = self.store.has_reservations().await?;
+        *cached = Some((has, Instant::now()));
+        Ok(has)
+    }
+
+    /// write journaled changes back to the database, so nothing is left to recover when
+    /// it's next opened
+    pub async fn checkpoint(&self) -> Result<(), IpError<T::Error>> {
+        Ok(self.store.checkpoint().await?)
+    }
+
+    /// write a consistent copy of the database to `path`, see [`backup::Backups`]
+    pub async fn backup(&self, path: &Path) -> Result<(), IpError<T::Error>> {
+        Ok(self.store.backup(path).await?)
+    }
+}
+
+/// when probation ends, quarantined IPs never come off probation on their own
+fn probation_expiry(probation: &Probation, network: &Network) -> SystemTime {
+    if probation.quarantined {
+        never_expires()
+    } else {
+        clock::now() + network.probation_period()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum IpError<E> {
+    #[error("ip is leased {0:?}")]
+    Leased(ClientInfo),
+    #[error("ip is reserved {0:?}")]
+    Reserved(ClientInfo),
+    #[error("ip is unreserved")]
+    Unreserved,
+    /// storage failed & trying again won't help
+    #[error("database error")]
+    DbError(#[source] E),
+    /// storage failed but the same call may succeed if it's retried
+    #[error("database busy")]
+    Busy(#[source] E),
+    #[error("ip {0:?} is held by another client")]
+    IdMismatch(IpAddr),
+    #[error("client's binding for ip {0:?} has expired")]
+    Expired(IpAddr),
+    #[error("client's binding for ip {ip:?} is in network {network:?}")]
+    WrongNetwork { ip: IpAddr, network: IpAddr },
+    #[error("this address is already in use {0:?}")]
+    AddrInUse(IpAddr),
+    #[error("error getting next IP in range {range:?}")]
+    RangeError { range: RangeInclusive<IpAddr> },
+    #[error("this address is excluded {0:?}")]
+    Excluded(IpAddr),
+    #[error("clients from OUI {0} are at their lease limit")]
+    OuiLimit(Oui),
+    #[error("error getting next IP in range {range:?} inside attempts {attempts:?}")]
+    MaxAttempts {
+        range: RangeInclusive<IpAddr>,
+        attempts: usize,
+    },
+}
+
+impl<E: StorageError> From<E> for IpError<E> {
+    fn from(err: E) -> Self {
+        if err.is_retryable() {
+            IpError::Busy(err)
+        } else {
+            IpError::DbError(err)
+        }
+    }
+}
+
+impl<E> IpError<E> {
+    /// true if the call that failed may succeed if it's made again
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, IpError::Busy(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, SocketAddrV4};
+
+    use super::*;
+    use crate::sqlite::SqliteDb;
+    use config::LeaseTime;
+    use icmp_ping::{EchoReply, DEFAULT_TOKEN_SIZE};
+    use rand::Rng;
+    use tracing_test::traced_test;
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    // get multiple first-available IPs in a range
+    // this mimics what happens when multiple clients simultaneously 'DISCOVER'
+    #[tokio::test]
+    #[traced_test]
+    async fn test_first_available() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let range = NetRange::new(
+            Ipv4Addr::new(192, 168, 1, 100)..=Ipv4Addr::new(192, 168, 1, 255),
+            LeaseTime::new(
+                Duration::from_secs(5),
+                Duration::from_secs(3),
+                Duration::from_secs(10),
+            ),
+        );
+        let mut network = Network::default();
+        network
+            .set_subnet("192.168.1.0/24".parse()?)
+            .set_ranges(vec![range.clone()]);
+        let client_id = &[1, 2, 3, 4, 5, 6];
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let ip = mgr
+            .reserve_first(&range, &network, client_id, expires_at, None)
+            .await?;
+        assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100)));
+        assert_eq!(
+            mgr.lookup_id(client_id).await?,
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100))
+        );
+
+        let client_id = &[2, 2, 3, 4, 5, 6];
+        let ip = mgr
+            .reserve_first(&range, &network, client_id, expires_at, None)
+            .await?;
+        assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 101)));
+        assert_eq!(
+            mgr.lookup_id(client_id).await?,
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 101))
         );
 
         let client_id = &[3, 2, 3, 4, 5, 6];
@@ -813,6 +1758,191 @@ mod tests {
         Ok(())
     }
 
+    // a client REQUESTs its lease back after it expired
+    #[tokio::test]
+    #[traced_test]
+    async fn test_lease_grace() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let range = NetRange::new(
+            Ipv4Addr::new(192, 168, 1, 100)..=Ipv4Addr::new(192, 168, 1, 255),
+            LeaseTime::new(
+                Duration::from_secs(5),
+                Duration::from_secs(3),
+                Duration::from_secs(10),
+            ),
+        );
+        let mut network = Network::default();
+        network
+            .set_subnet("192.168.1.0/24".parse()?)
+            .set_ranges(vec![range.clone()])
+            .set_authoritative(true);
+        let client_id = &[1, 2, 3, 4, 5, 6];
+        let ip: IpAddr = [192, 168, 1, 100].into();
+        let renew_at = SystemTime::now() + Duration::from_secs(60);
+        // lease expired 10s ago
+        mgr.try_lease(
+            ip,
+            client_id,
+            SystemTime::now() - Duration::from_secs(10),
+            &network,
+        )
+        .await?;
+
+        // no grace, the row is still there so it can't be inserted again
+        assert!(matches!(
+            mgr.try_lease(ip, client_id, renew_at, &network).await,
+            Err(IpError::Expired(_))
+        ));
+        // expired longer ago than the grace
+        network.set_lease_grace(Some(Duration::from_secs(5)));
+        assert!(matches!(
+            mgr.try_lease(ip, client_id, renew_at, &network).await,
+            Err(IpError::Expired(_))
+        ));
+        network.set_lease_grace(Some(Duration::from_secs(60)));
+        mgr.try_lease(ip, client_id, renew_at, &network).await?;
+        assert_eq!(mgr.lookup_id(client_id).await?, ip);
+
+        // another client was given the IP after it expired, it's not re-granted
+        mgr.try_lease(
+            ip,
+            client_id,
+            SystemTime::now() - Duration::from_secs(10),
+            &network,
+        )
+        .await?;
+        let other = &[1, 2, 3, 4, 5, 7];
+        let taken = mgr
+            .reserve_first(&range, &network, other, renew_at, None)
+            .await?;
+        assert_eq!(taken, ip);
+        assert!(matches!(
+            mgr.try_lease(ip, client_id, renew_at, &network).await,
+            Err(IpError::IdMismatch(_))
+        ));
+
+        Ok(())
+    }
+
+    // each way a REQUEST can conflict with storage has its own error
+    #[tokio::test]
+    #[traced_test]
+    async fn test_lease_conflicts() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let mut network = Network::default();
+        network
+            .set_subnet("192.168.1.0/24".parse()?)
+            .set_authoritative(true);
+        let subnet: IpAddr = network.subnet().into();
+        let client_id = &[1, 2, 3, 4, 5, 6];
+        let other = &[1, 2, 3, 4, 5, 7];
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let expired = SystemTime::now() - Duration::from_secs(10);
+
+        // held by another client
+        let ip: IpAddr = [192, 168, 1, 100].into();
+        mgr.try_lease(ip, other, expires_at, &network).await?;
+        assert!(matches!(
+            mgr.try_lease(ip, client_id, expires_at, &network).await,
+            Err(IpError::IdMismatch(_))
+        ));
+        assert!(matches!(
+            mgr.try_ip(ip, subnet, client_id, expires_at, &network, None)
+                .await,
+            Err(IpError::IdMismatch(_))
+        ));
+        // the client's own binding expired
+        let ip: IpAddr = [192, 168, 1, 101].into();
+        mgr.try_lease(ip, client_id, expired, &network).await?;
+        assert!(matches!(
+            mgr.try_lease(ip, client_id, expires_at, &network).await,
+            Err(IpError::Expired(_))
+        ));
+        // the client's binding is from another network
+        let ip: IpAddr = [192, 168, 1, 102].into();
+        let elsewhere: IpAddr = [10, 0, 0, 0].into();
+        mgr.store
+            .insert(ip, elsewhere, client_id, expired, Some(IpState::Lease))
+            .await?;
+        assert!(matches!(
+            mgr.try_lease(ip, client_id, expires_at, &network).await,
+            Err(IpError::WrongNetwork { network, .. }) if network == elsewhere
+        ));
+        // not authoritative, nothing to update
+        network.set_authoritative(false);
+        let ip: IpAddr = [192, 168, 1, 103].into();
+        assert!(matches!(
+            mgr.try_lease(ip, client_id, expires_at, &network).await,
+            Err(IpError::Unreserved)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_storage_error_retryable() -> Result<()> {
+        let db = SqliteDb::new("sqlite::memory:").await?;
+        let ip: IpAddr = [192, 168, 1, 100].into();
+        let subnet: IpAddr = [192, 168, 1, 0].into();
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        db.insert(ip, subnet, &[1], expires_at, None).await?;
+        // a concurrent insert took the IP, inserting it again won't succeed
+        let err = db
+            .insert(ip, subnet, &[2], expires_at, None)
+            .await
+            .unwrap_err();
+        assert!(err.is_conflict());
+        assert!(!err.is_retryable());
+        assert!(matches!(IpError::from(err), IpError::DbError(_)));
+        // acquiring already waited for the pool's timeout
+        assert!(!sqlx::Error::PoolTimedOut.is_retryable());
+        assert!(matches!(
+            IpError::from(sqlx::Error::PoolTimedOut),
+            IpError::DbError(_)
+        ));
+        assert!(matches!(
+            IpError::from(sqlx::Error::RowNotFound),
+            IpError::DbError(_)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_only() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("dora_test_replica_{}.db", std::process::id()));
+        let uri = format!("sqlite://{}", path.display());
+        // there's no database to read yet
+        assert!(SqliteDb::read_only(&uri, None).await.is_err());
+
+        let db = SqliteDb::new(&uri).await?;
+        let ip: IpAddr = [192, 168, 1, 100].into();
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        db.insert(
+            ip,
+            [192, 168, 1, 0].into(),
+            &[1],
+            expires_at,
+            Some(IpState::Lease),
+        )
+        .await?;
+        // the WAL is emptied into the database file
+        db.checkpoint().await?;
+        assert_eq!(
+            std::fs::metadata(format!("{}-wal", path.display()))?.len(),
+            0
+        );
+        let replica = SqliteDb::read_only(&uri, None).await?;
+        assert!(replica.get(ip).await?.is_some());
+        assert!(replica.delete(ip).await.is_err());
+
+        drop((db, replica));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+        Ok(())
+    }
+
     // reserve 2 ips then ack them both
     #[tokio::test]
     #[traced_test]
@@ -887,7 +2017,61 @@ mod tests {
         Ok(())
     }
 
-    // programmatically fill a range
+    #[tokio::test]
+    #[traced_test]
+    async fn test_multi_network_leases() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let lease = LeaseTime::new(
+            Duration::from_secs(5),
+            Duration::from_secs(3),
+            Duration::from_secs(10),
+        );
+        let range_a = NetRange::new(
+            Ipv4Addr::new(192, 168, 1, 100)..=Ipv4Addr::new(192, 168, 1, 255),
+            lease,
+        );
+        let range_b = NetRange::new(
+            Ipv4Addr::new(10, 10, 1, 100)..=Ipv4Addr::new(10, 10, 1, 255),
+            lease,
+        );
+        let mut network_a = Network::default();
+        network_a
+            .set_subnet("192.168.1.0/24".parse()?)
+            .set_ranges(vec![range_a.clone()]);
+        let mut network_b = Network::default();
+        network_b
+            .set_subnet("10.10.1.0/24".parse()?)
+            .set_ranges(vec![range_b.clone()])
+            .set_multi_network_leases(MultiNetworkPolicy::Allow);
+        let client_id = &[1, 2, 3, 4, 5, 6];
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let ip_a = mgr
+            .reserve_first(&range_a, &network_a, client_id, expires_at, None)
+            .await?;
+        mgr.try_lease(ip_a, client_id, expires_at, &network_a)
+            .await?;
+
+        // an offer in another network leaves the lease alone
+        let ip_b = mgr
+            .reserve_first(&range_b, &network_b, client_id, expires_at, None)
+            .await?;
+        assert_eq!(ip_b, IpAddr::V4(Ipv4Addr::new(10, 10, 1, 100)));
+        assert!(mgr.is_leased_to(ip_a, client_id).await?);
+        // network b allows leases elsewhere
+        mgr.try_lease(ip_b, client_id, expires_at, &network_b)
+            .await?;
+        assert!(mgr.is_leased_to(ip_a, client_id).await?);
+        assert!(mgr.is_leased_to(ip_b, client_id).await?);
+
+        // network a releases them, once the client leases there again
+        mgr.try_lease(ip_a, client_id, expires_at, &network_a)
+            .await?;
+        assert!(mgr.is_leased_to(ip_a, client_id).await?);
+        assert!(!mgr.is_leased_to(ip_b, client_id).await?);
+        Ok(())
+    }
+
+    // programmatically fill a range
     #[tokio::test]
     #[traced_test]
     async fn test_fill_range() -> Result<()> {
@@ -1006,7 +2190,8 @@ mod tests {
         let mut network = Network::default();
         network
             .set_subnet("192.168.1.0/24".parse()?)
-            .set_ranges(vec![range.clone()]);
+            .set_ranges(vec![range.clone()])
+            .set_probation_period(Duration::from_secs(180));
 
         // lease an IP
         let client_id = (1..6)
@@ -1025,8 +2210,7 @@ mod tests {
         assert_eq!(mgr.lookup_id(&client_id).await?, ip);
 
         // probate IP
-        mgr.probate_ip(ip, &client_id, SystemTime::now() + Duration::from_secs(180))
-            .await?;
+        mgr.probate_ip(ip, &client_id, &network).await?;
         assert!(mgr.lookup_id(&client_id).await.is_err());
 
         // try a new client, should skip probated IP
@@ -1104,6 +2288,22 @@ mod tests {
         Ok(())
     }
 
+    // a manager without ping must not probe (or cache) addresses on a ping_check network
+    #[tokio::test]
+    #[traced_test]
+    async fn test_without_ping() -> Result<()> {
+        let mgr = IpManager::without_ping(SqliteDb::new("sqlite::memory:").await?);
+        let mut network = Network::default();
+        network
+            .set_subnet("192.168.1.0/24".parse()?)
+            .set_ping_check(true);
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100));
+        mgr.ping_check(ip, &network).await?;
+        assert!(!mgr.icmp_healthy());
+        assert!(!mgr.ping_verified(ip, &network));
+        Ok(())
+    }
+
     // test bad lookup
     #[tokio::test]
     #[traced_test]
@@ -1131,4 +2331,692 @@ mod tests {
         assert!(mgr.lookup_id(&client_id).await.is_err());
         Ok(())
     }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_quarantine() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let range = NetRange::new(
+            Ipv4Addr::new(192, 168, 1, 100)..=Ipv4Addr::new(192, 168, 1, 255),
+            LeaseTime::new(
+                Duration::from_secs(5),
+                Duration::from_secs(3),
+                Duration::from_secs(10),
+            ),
+        );
+        let mut network = Network::default();
+        network
+            .set_subnet("192.168.1.0/24".parse()?)
+            .set_ranges(vec![range.clone()])
+            .set_probation_period(Duration::from_secs(180))
+            .set_max_probations(Some(2))
+            .set_probation_decay(Some(Duration::from_secs(100)));
+
+        let client_id = vec![1, 2, 3, 4, 5];
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let ip = mgr
+            .reserve_first(
+                &range,
+                &network,
+                &client_id,
+                expires_at,
+                Some(IpState::Lease),
+            )
+            .await?;
+
+        // first decline is a normal probation
+        mgr.probate_ip(ip, &client_id, &network).await?;
+        assert!(mgr.quarantined().await?.is_empty());
+
+        // client gets the IP back & declines again, hitting max_probations
+        mgr.store
+            .update_ip(ip, IpState::Lease, Some(&client_id), expires_at)
+            .await?;
+        mgr.probate_ip(ip, &client_id, &network).await?;
+        let quarantined = mgr.quarantined().await?;
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].count, 2);
+        assert_eq!(
+            mgr.store.get(ip).await?.unwrap().as_ref().expires_at,
+            never_expires()
+        );
+
+        assert!(mgr.clear_quarantine(ip).await?);
+        assert!(!mgr.clear_quarantine(ip).await?);
+        assert!(mgr.store.get(ip).await?.is_none());
+        assert!(mgr.quarantined().await?.is_empty());
+
+        // old probations are forgiven after probation_decay
+        mgr.store
+            .set_probation(&Probation {
+                ip,
+                count: 5,
+                last_probated: SystemTime::now() - Duration::from_secs(1_000),
+                quarantined: false,
+            })
+            .await?;
+        assert_eq!(mgr.next_probation(ip, &network).await?.count, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_range_counts() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let range = NetRange::new(
+            Ipv4Addr::new(192, 168, 1, 100)..=Ipv4Addr::new(192, 168, 1, 255),
+            LeaseTime::new(
+                Duration::from_secs(5),
+                Duration::from_secs(3),
+                Duration::from_secs(10),
+            ),
+        );
+        let mut network = Network::default();
+        network
+            .set_subnet("192.168.1.0/24".parse()?)
+            .set_ranges(vec![range.clone()])
+            .set_probation_period(Duration::from_secs(180));
+
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        for (id, state) in [
+            (1, Some(IpState::Lease)),
+            (2, Some(IpState::Lease)),
+            (3, None),
+        ] {
+            mgr.reserve_first(&range, &network, &[id], expires_at, state)
+                .await?;
+        }
+        let ip = mgr.lookup_id(&[1]).await?;
+        mgr.probate_ip(ip, &[1], &network).await?;
+
+        assert_eq!(
+            mgr.range_counts(&range).await?,
+            LeaseCounts {
+                leased: 1,
+                reserved: 1,
+                probated: 1,
+            }
+        );
+        Ok(())
+    }
+
+    // concurrent DISCOVERs in the same range each get a distinct address
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[traced_test]
+    async fn test_concurrent_reserve() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let range = NetRange::new(
+            Ipv4Addr::new(192, 168, 1, 100)..=Ipv4Addr::new(192, 168, 1, 255),
+            LeaseTime::new(
+                Duration::from_secs(5),
+                Duration::from_secs(3),
+                Duration::from_secs(10),
+            ),
+        );
+        let mut network = Network::default();
+        network
+            .set_subnet("192.168.1.0/24".parse()?)
+            .set_ranges(vec![range.clone()]);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+
+        let tasks = (0..32u8)
+            .map(|id| {
+                let (mgr, range, network) = (mgr.clone(), range.clone(), network.clone());
+                tokio::spawn(async move {
+                    mgr.reserve_first(&range, &network, &[id], expires_at, None)
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut ips = std::collections::HashSet::new();
+        for task in tasks {
+            assert!(ips.insert(task.await??));
+        }
+        assert_eq!(ips.len(), 32);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_select_page() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let network = IpAddr::from([192, 168, 1, 0]);
+        let now = SystemTime::now();
+        for i in 1..=5u8 {
+            let state = if i % 2 == 0 {
+                Some(IpState::Reserve)
+            } else {
+                Some(IpState::Lease)
+            };
+            mgr.store
+                .insert(
+                    IpAddr::from([192, 168, 1, 100 + i]),
+                    network,
+                    &[i],
+                    now + Duration::from_secs(60),
+                    state,
+                )
+                .await?;
+        }
+        // expired
+        mgr.store
+            .insert(
+                IpAddr::from([192, 168, 1, 110]),
+                network,
+                &[10],
+                now - Duration::from_secs(60),
+                Some(IpState::Lease),
+            )
+            .await?;
+
+        let filter = LeaseFilter::default();
+        let page = mgr.select_page(&filter, Cursor::first(2)).await?;
+        assert_eq!(page.entries.len(), 2);
+        let next = page.next.unwrap();
+        assert_eq!(next.after, Some(IpAddr::from([192, 168, 1, 102])));
+        let page = mgr.select_page(&filter, next).await?;
+        assert_eq!(
+            page.entries[0].as_ref().ip,
+            IpAddr::from([192, 168, 1, 103])
+        );
+        let page = mgr.select_page(&filter, page.next.unwrap()).await?;
+        assert_eq!(page.entries.len(), 1);
+        assert!(page.next.is_none());
+
+        let page = mgr
+            .select_page(
+                &LeaseFilter {
+                    state: Some(IpState::Lease),
+                    expired: true,
+                    ..Default::default()
+                },
+                Cursor::default(),
+            )
+            .await?;
+        assert_eq!(page.entries.len(), 4);
+        assert!(page.entries.iter().all(|e| matches!(e, State::Leased(_))));
+
+        let page = mgr
+            .select_page(
+                &LeaseFilter {
+                    range: Some(
+                        IpAddr::from([192, 168, 1, 102])..=IpAddr::from([192, 168, 1, 103]),
+                    ),
+                    ..Default::default()
+                },
+                Cursor::default(),
+            )
+            .await?;
+        assert_eq!(page.entries.len(), 2);
+
+        let range = NetRange::new(
+            Ipv4Addr::new(192, 168, 1, 100)..=Ipv4Addr::new(192, 168, 1, 255),
+            LeaseTime::new(
+                Duration::from_secs(5),
+                Duration::from_secs(3),
+                Duration::from_secs(10),
+            ),
+        );
+        assert_eq!(
+            mgr.range_counts(&range).await?,
+            LeaseCounts {
+                leased: 3,
+                reserved: 2,
+                probated: 0,
+            }
+        );
+        assert_eq!(mgr.count_in_range(&range, IpState::Lease).await?, 3);
+        assert_eq!(mgr.count_in_range(&range, IpState::Reserve).await?, 2);
+        assert_eq!(mgr.count_in_range(&range, IpState::Probate).await?, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_expire_before() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let network = IpAddr::from([192, 168, 1, 0]);
+        let now = SystemTime::now();
+        for (i, expires_at) in [
+            now - Duration::from_secs(120),
+            now - Duration::from_secs(60),
+            now + Duration::from_secs(60),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            mgr.store
+                .insert(
+                    IpAddr::from([192, 168, 1, 100 + i as u8]),
+                    network,
+                    &[i as u8],
+                    expires_at,
+                    Some(IpState::Lease),
+                )
+                .await?;
+        }
+        assert_eq!(mgr.expire_before(now - Duration::from_secs(90)).await?, 1);
+        assert_eq!(mgr.expire_before(now).await?, 1);
+        assert_eq!(mgr.expire_before(now).await?, 0);
+        assert!(mgr
+            .store
+            .get(IpAddr::from([192, 168, 1, 102]))
+            .await?
+            .is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_lease_history() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let ip = IpAddr::from([192, 168, 1, 100]);
+        let network = IpAddr::from([192, 168, 1, 0]);
+        let before = SystemTime::now() - Duration::from_secs(1);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        mgr.store
+            .insert(ip, network, &[1], expires_at, Some(IpState::Reserve))
+            .await?;
+        mgr.store
+            .update_ip(ip, IpState::Lease, Some(&[1]), expires_at)
+            .await?;
+        // no change, nothing recorded
+        mgr.store
+            .update_ip(ip, IpState::Lease, Some(&[1]), expires_at)
+            .await?;
+        mgr.store.delete(ip).await?;
+
+        let history = mgr.lease_history(ip, &HistoryFilter::default()).await?;
+        assert_eq!(
+            history.iter().map(|e| e.state).collect::<Vec<_>>(),
+            vec![Some(IpState::Reserve), Some(IpState::Lease), None]
+        );
+        assert!(history.iter().all(|e| e.id.as_deref() == Some(&[1][..])));
+        assert_eq!(
+            history[1].expires_at.map(|t| t.elapsed().is_err()),
+            Some(true)
+        );
+        assert!(history[2].expires_at.is_none());
+
+        // the most recent changes are kept
+        let filter = HistoryFilter {
+            limit: 2,
+            ..HistoryFilter::default()
+        };
+        let last = mgr.lease_history(ip, &filter).await?;
+        assert_eq!(last, history[1..]);
+
+        // removed by the time we ask, but held before that
+        assert!(mgr.held_at(ip, SystemTime::now()).await?.is_none());
+        assert!(mgr.held_at(ip, before).await?.is_none());
+        assert!(history[1].active_at(history[1].at));
+
+        assert_eq!(mgr.prune_history(before, false).await?, 0);
+        let later = SystemTime::now() + Duration::from_secs(1);
+        // the change stream hasn't shipped anything, nothing is pruned
+        assert_eq!(mgr.prune_history(later, true).await?, 0);
+        // only what it has shipped
+        let first = mgr.changes_after(0, 1).await?[0].seq;
+        mgr.set_change_cursor(first).await?;
+        assert_eq!(mgr.prune_history(later, true).await?, 1);
+        assert_eq!(mgr.prune_history(later, false).await?, 2);
+        assert!(mgr
+            .lease_history(ip, &HistoryFilter::default())
+            .await?
+            .is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_changes() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let (a, b) = (
+            IpAddr::from([192, 168, 1, 100]),
+            IpAddr::from([192, 168, 1, 101]),
+        );
+        let network = IpAddr::from([192, 168, 1, 0]);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        mgr.store
+            .insert(a, network, &[1], expires_at, Some(IpState::Reserve))
+            .await?;
+        mgr.store
+            .insert(b, network, &[2], expires_at, Some(IpState::Reserve))
+            .await?;
+        mgr.store
+            .update_ip(a, IpState::Lease, Some(&[1]), expires_at)
+            .await?;
+        mgr.store.delete(a).await?;
+
+        let changes = mgr.changes_after(0, 10).await?;
+        assert_eq!(
+            changes
+                .iter()
+                .map(|c| (c.after.ip, c.op()))
+                .collect::<Vec<_>>(),
+            vec![
+                (a, ChangeOp::Insert),
+                (b, ChangeOp::Insert),
+                (a, ChangeOp::Update),
+                (a, ChangeOp::Delete),
+            ]
+        );
+        assert!(changes.windows(2).all(|w| w[0].seq < w[1].seq));
+        // before is the same IP's previous change, not the previous row
+        assert_eq!(changes[2].before.as_ref(), Some(&changes[0].after));
+        assert_eq!(
+            changes[3].before.as_ref().and_then(|b| b.state),
+            Some(IpState::Lease)
+        );
+        assert_eq!(changes[3].after.id.as_deref(), Some(&[1][..]));
+
+        let rest = mgr.changes_after(changes[1].seq, 1).await?;
+        assert_eq!(rest, changes[2..3]);
+
+        assert_eq!(mgr.change_cursor().await?, None);
+        mgr.set_change_cursor(changes[3].seq).await?;
+        assert_eq!(mgr.change_cursor().await?, Some(changes[3].seq));
+        assert!(mgr.changes_after(changes[3].seq, 10).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_active_hostnames() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let network = IpAddr::from([192, 168, 1, 0]);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        for (i, state) in [IpState::Lease, IpState::Reserve, IpState::Lease]
+            .into_iter()
+            .enumerate()
+        {
+            let ip = IpAddr::from([192, 168, 1, 100 + i as u8]);
+            mgr.store
+                .insert(ip, network, &[i as u8], expires_at, Some(state))
+                .await?;
+            mgr.set_hostname(ip, &[i as u8], Some(&format!("host-{i}")))
+                .await?;
+        }
+        // a different client now holds .102
+        mgr.store
+            .update_ip(
+                IpAddr::from([192, 168, 1, 102]),
+                IpState::Lease,
+                Some(&[9]),
+                expires_at,
+            )
+            .await?;
+        assert_eq!(
+            mgr.active_hostnames().await?,
+            vec![(IpAddr::from([192, 168, 1, 100]), "host-0".to_owned())]
+        );
+        mgr.set_hostname(IpAddr::from([192, 168, 1, 100]), &[0], None)
+            .await?;
+        assert!(mgr.active_hostnames().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_active_fingerprints() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let network = IpAddr::from([192, 168, 1, 0]);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let fingerprint = |vendor_class: Option<&str>| LeaseFingerprint {
+            fingerprint: "0123456789abcdef".to_owned(),
+            prl: "1,3,6,15".to_owned(),
+            vendor_class: vendor_class.map(str::to_owned),
+        };
+        for i in 0..2 {
+            let ip = IpAddr::from([192, 168, 1, 100 + i]);
+            mgr.store
+                .insert(ip, network, &[i], expires_at, Some(IpState::Lease))
+                .await?;
+            mgr.set_fingerprint(ip, &[i], &fingerprint(None)).await?;
+        }
+        // a different client now holds .101, the new client's fingerprint replaces it
+        let ip = IpAddr::from([192, 168, 1, 101]);
+        mgr.store
+            .update_ip(ip, IpState::Lease, Some(&[9]), expires_at)
+            .await?;
+        assert_eq!(
+            mgr.active_fingerprints().await?,
+            vec![(IpAddr::from([192, 168, 1, 100]), fingerprint(None))]
+        );
+        mgr.set_fingerprint(ip, &[9], &fingerprint(Some("MSFT 5.0")))
+            .await?;
+        assert_eq!(
+            mgr.active_fingerprints().await?[1],
+            (ip, fingerprint(Some("MSFT 5.0")))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_addr_registrations() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let reg = |ip: &str, secs: u64| AddrRegistration {
+            ip: ip.parse().unwrap(),
+            client_id: vec![0, 1, 2, 3],
+            fqdn: Some("host.example.com.".to_owned()),
+            expires_at: SystemTime::now() + Duration::from_secs(secs),
+        };
+        mgr.register_addr(&reg("2001:db8::20", 3600)).await?;
+        mgr.register_addr(&reg("2001:db8::1:0", 3600)).await?;
+        // already expired
+        mgr.register_addr(&AddrRegistration {
+            expires_at: SystemTime::now() - Duration::from_secs(60),
+            ..reg("2001:db8::30", 0)
+        })
+        .await?;
+        let regs = mgr.addr_registrations().await?;
+        assert_eq!(
+            regs.iter()
+                .map(|reg| reg.ip.to_string())
+                .collect::<Vec<_>>(),
+            ["2001:db8::20", "2001:db8::1:0"]
+        );
+
+        // registering again replaces it
+        let mut renewed = reg("2001:db8::20", 7200);
+        renewed.fqdn = None;
+        mgr.register_addr(&renewed).await?;
+        let regs = mgr.addr_registrations().await?;
+        assert_eq!(regs.len(), 2);
+        assert_eq!(regs[0].fqdn, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_oui_leases() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let mut network = Network::default();
+        network.set_subnet("192.168.1.0/24".parse()?);
+        let subnet = IpAddr::from([192, 168, 1, 0]);
+        let oui = Oui([0, 0x11, 0x22]);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        for i in 0..3u8 {
+            let ip = IpAddr::from([192, 168, 1, 100 + i]);
+            mgr.store
+                .insert(ip, subnet, &[i], expires_at, Some(IpState::Lease))
+                .await?;
+            mgr.set_oui(ip, &network, oui, &[i]).await?;
+        }
+        assert_eq!(mgr.oui_leases(&network, oui, &[9]).await?, 3);
+        // a client's own entries don't count against it
+        assert_eq!(mgr.oui_leases(&network, oui, &[0]).await?, 2);
+        assert_eq!(mgr.oui_leases(&network, Oui([1, 2, 3]), &[9]).await?, 0);
+
+        // .101 handed to another client, .102 released
+        mgr.store
+            .update_ip(
+                IpAddr::from([192, 168, 1, 101]),
+                IpState::Lease,
+                Some(&[8]),
+                expires_at,
+            )
+            .await?;
+        mgr.store.delete(IpAddr::from([192, 168, 1, 102])).await?;
+        assert_eq!(mgr.oui_leases(&network, oui, &[9]).await?, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_stats() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        assert!(mgr.load_stats().await?.is_empty());
+        let name = "dhcpv4_sent_total_persistent".to_owned();
+        mgr.save_stats(&[(name.clone(), "ack".to_owned(), 5)])
+            .await?;
+        mgr.save_stats(&[
+            (name.clone(), "ack".to_owned(), 7),
+            (name.clone(), "offer".to_owned(), 2),
+        ])
+        .await?;
+        let mut stats = mgr.load_stats().await?;
+        stats.sort();
+        assert_eq!(
+            stats,
+            [
+                (name.clone(), "ack".to_owned(), 7),
+                (name, "offer".to_owned(), 2)
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_ping_verified() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let ip = IpAddr::from([192, 168, 1, 100]);
+        let mut network = Network::default();
+        network.set_subnet("192.168.1.0/24".parse()?);
+        assert!(mgr.ping_verified(ip, &network));
+
+        network.set_ping_check(true);
+        // never pinged
+        assert!(!mgr.ping_verified(ip, &network));
+        // pinged, no reply
+        mgr.ping_insert(ip, None).await;
+        assert!(mgr.ping_verified(ip, &network));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_imported_reservations() -> Result<()> {
+        let mgr = IpManager::without_ping(SqliteDb::new("sqlite::memory:").await?);
+        // another process sharing the database, ex. `dora-cfg import-reservations`
+        let other = IpManager::without_ping(mgr.store.clone());
+        let mac = dora_core::pnet::util::MacAddr::new(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01);
+        assert_eq!(mgr.reservation(&mac.octets()).await?, None);
+        // nothing imported, later lookups don't go to storage until the recheck
+        assert!(matches!(
+            *mgr.has_reservations.read().await,
+            Some((false, _))
+        ));
+
+        let res = Reservation {
+            mac,
+            ip: Ipv4Addr::new(192, 168, 1, 10),
+            hostname: Some("printer-3".to_owned()),
+            options: None,
+        };
+        other.import_reservations(&[res.clone()]).await?;
+        // still cached as none
+        assert_eq!(mgr.reservation(&mac.octets()).await?, None);
+        // found once the cached none is older than the recheck
+        *mgr.has_reservations.write().await = Some((
+            false,
+            Instant::now() - Duration::from_secs(RESERVATIONS_RECHECK),
+        ));
+        assert_eq!(mgr.reservation(&mac.octets()).await?, Some(res));
+        assert!(matches!(
+            *mgr.has_reservations.read().await,
+            Some((true, _))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_exclusions() -> Result<()> {
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let range = NetRange::new(
+            Ipv4Addr::new(192, 168, 1, 100)..=Ipv4Addr::new(192, 168, 1, 255),
+            LeaseTime::new(
+                Duration::from_secs(5),
+                Duration::from_secs(3),
+                Duration::from_secs(10),
+            ),
+        );
+        let mut network = Network::default();
+        network
+            .set_subnet("192.168.1.0/24".parse()?)
+            .set_ranges(vec![range.clone()]);
+
+        let excluded = Ipv4Addr::new(192, 168, 1, 100)..=Ipv4Addr::new(192, 168, 1, 149);
+        mgr.add_exclusion(excluded.clone()).await?;
+        // adding twice is a no-op
+        mgr.add_exclusion(excluded.clone()).await?;
+        assert_eq!(mgr.exclusions().await?, vec![excluded.clone()]);
+
+        // empty range starts after the excluded block
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let ip = mgr
+            .reserve_first(&range, &network, &[1], expires_at, None)
+            .await?;
+        assert_eq!(ip, IpAddr::from([192, 168, 1, 150]));
+
+        let requested = IpAddr::from([192, 168, 1, 120]);
+        assert!(matches!(
+            mgr.try_ip(
+                requested,
+                network.subnet().into(),
+                &[2],
+                expires_at,
+                &network,
+                None
+            )
+            .await,
+            Err(IpError::Excluded(_))
+        ));
+
+        assert!(mgr.remove_exclusion(excluded.clone()).await?);
+        assert!(!mgr.remove_exclusion(excluded).await?);
+        mgr.try_ip(
+            requested,
+            network.subnet().into(),
+            &[2],
+            expires_at,
+            &network,
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    // a key must never be silently ignored by a plain sqlite build
+    #[cfg(not(feature = "sqlcipher"))]
+    #[tokio::test]
+    async fn test_key_without_sqlcipher() {
+        let err = SqliteDb::with_key("sqlite::memory:", Some("secret"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, sqlx::Error::Configuration(_)));
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[tokio::test]
+    async fn test_key_sqlcipher() -> Result<()> {
+        let mgr =
+            IpManager::new(SqliteDb::with_key("sqlite::memory:", Some("it's secret")).await?)?;
+        assert!(mgr.quarantined().await?.is_empty());
+        Ok(())
+    }
 }
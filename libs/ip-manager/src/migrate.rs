@@ -0,0 +1,125 @@
+//! # Migrations
+//!
+//! The leases database schema is versioned by the files in `migrations/`, a migration's
+//! version is the timestamp in its name. [`run`] is called whenever a database is
+//! opened, it applies the migrations the database hasn't seen & records the version it
+//! ends up at in the `schema_version` table.
+//!
+//! Migrations only go forward. A database migrated by a newer dora has a version this
+//! build doesn't know, and opening it is refused rather than running against tables
+//! that may have changed. To roll back dora, restore a copy of the database taken
+//! before the upgrade.
+use std::time::SystemTime;
+
+use sqlx::{migrate::Migrator, SqlitePool};
+use tracing::info;
+
+use crate::clock;
+
+/// every migration in this build
+pub static MIGRATOR: Migrator = sqlx::migrate!("../../migrations");
+
+/// the schema version this build migrates to, the version of its latest migration
+pub fn latest() -> i64 {
+    MIGRATOR.iter().map(|m| m.version).max().unwrap_or_default()
+}
+
+/// the version recorded in `schema_version`, `None` for a new database or one last
+/// opened by a dora from before versions were recorded
+pub async fn current(pool: &SqlitePool) -> Result<Option<i64>, sqlx::Error> {
+    let table: Option<String> = sqlx::query_scalar(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'",
+    )
+    .fetch_optional(pool)
+    .await?;
+    if table.is_none() {
+        return Ok(None);
+    }
+    sqlx::query_scalar("SELECT version FROM schema_version WHERE id = 0")
+        .fetch_optional(pool)
+        .await
+}
+
+/// Migrate the database to [`latest`], returning the version. Fails without changing
+/// anything if the database is at a version newer than [`latest`]
+pub async fn run(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    let latest = latest();
+    let current = current(pool).await?;
+    if let Some(version) = current.filter(|version| *version > latest) {
+        return Err(too_new(version, latest));
+    }
+    MIGRATOR.run(pool).await?;
+    if current != Some(latest) {
+        let now = clock::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        sqlx::query(
+            "INSERT INTO schema_version (id, version, previous, updated_at) VALUES (0, ?1, ?2, ?3)
+             ON CONFLICT(id) DO UPDATE SET version = ?1, previous = ?2, updated_at = ?3",
+        )
+        .bind(latest)
+        .bind(current)
+        .bind(now)
+        .execute(pool)
+        .await?;
+        info!(from = ?current, to = latest, "migrated leases database schema");
+    }
+    Ok(latest)
+}
+
+/// Check the database is at [`latest`] without migrating it, for a database opened
+/// read-only
+pub async fn check(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    let latest = latest();
+    match current(pool).await? {
+        Some(version) if version == latest => Ok(version),
+        Some(version) if version > latest => Err(too_new(version, latest)),
+        version => Err(sqlx::Error::Configuration(
+            format!(
+                "leases database schema version {} is older than this dora's ({latest}) & \
+                 can't be migrated read-only",
+                version.map_or_else(|| "unknown".to_owned(), |v| v.to_string())
+            )
+            .into(),
+        )),
+    }
+}
+
+fn too_new(version: i64, latest: i64) -> sqlx::Error {
+    sqlx::Error::Configuration(
+        format!(
+            "leases database schema version {version} is newer than this dora supports \
+             ({latest}), it was migrated by a newer release. Upgrade dora or restore a copy \
+             of the database from before the upgrade"
+        )
+        .into(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run() -> Result<(), sqlx::Error> {
+        let pool = SqlitePool::connect("sqlite::memory:").await?;
+        assert_eq!(current(&pool).await?, None);
+        assert!(check(&pool).await.is_err());
+        assert_eq!(run(&pool).await?, latest());
+        assert_eq!(check(&pool).await?, latest());
+        assert_eq!(current(&pool).await?, Some(latest()));
+        // already up to date
+        assert_eq!(run(&pool).await?, latest());
+
+        // a newer dora migrated it
+        sqlx::query("UPDATE schema_version SET version = ?1")
+            .bind(latest() + 1)
+            .execute(&pool)
+            .await?;
+        let err = run(&pool).await.unwrap_err();
+        assert!(err.to_string().contains("newer than this dora supports"));
+        assert!(check(&pool).await.is_err());
+        assert_eq!(current(&pool).await?, Some(latest() + 1));
+        Ok(())
+    }
+}
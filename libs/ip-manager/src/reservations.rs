@@ -0,0 +1,206 @@
+//! # Reservation import
+//!
+//! Reservations can be imported in bulk from CSV or JSON and are written to
+//! the reservations table in storage. CSV is expected to have the columns
+//! `mac,ip,hostname,options` where `hostname` & `options` may be empty and
+//! `options` is a JSON map in the same format as the config `options.values`.
+//! A header row is optional.
+//!
+//! JSON is a list of objects with the same fields:
+//!
+//! ```json
+//! [{ "mac": "aa:bb:cc:dd:ee:ff", "ip": "192.168.0.10", "hostname": "printer-3" }]
+//! ```
+use std::{collections::HashSet, net::Ipv4Addr};
+
+use config::{v4, wire::v4::Opts};
+use dora_core::pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// A reservation that lives in storage instead of the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reservation {
+    pub mac: MacAddr,
+    pub ip: Ipv4Addr,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub options: Option<Opts>,
+}
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("line {line}: {msg}")]
+    Csv { line: usize, msg: String },
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// a reservation that failed validation
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize)]
+#[error("reservation {index} ({mac} -> {ip}): {reason}")]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Invalid {
+    /// index of the reservation in the batch
+    pub index: usize,
+    pub mac: String,
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = "ipv4"))]
+    pub ip: Ipv4Addr,
+    pub reason: String,
+}
+
+/// parse reservations from CSV with columns `mac,ip,hostname,options`
+pub fn parse_csv(input: &str) -> Result<Vec<Reservation>, ImportError> {
+    let mut reservations = Vec::new();
+    for (i, row) in input.lines().enumerate() {
+        let line = i + 1;
+        if row.trim().is_empty() {
+            continue;
+        }
+        let fields = split_row(row).map_err(|msg| ImportError::Csv { line, msg })?;
+        // optional header
+        if line == 1 && fields.first().map(|f| f.trim()) == Some("mac") {
+            continue;
+        }
+        let field = |idx: usize| fields.get(idx).map(|f| f.trim()).filter(|f| !f.is_empty());
+        let err = |msg: String| ImportError::Csv { line, msg };
+
+        let mac = field(0)
+            .ok_or_else(|| err("missing mac".into()))?
+            .parse::<MacAddr>()
+            .map_err(|e| err(format!("invalid mac: {e}")))?;
+        let ip = field(1)
+            .ok_or_else(|| err("missing ip".into()))?
+            .parse::<Ipv4Addr>()
+            .map_err(|e| err(format!("invalid ip: {e}")))?;
+        let options = field(3)
+            .map(serde_json::from_str::<Opts>)
+            .transpose()
+            .map_err(|e| err(format!("invalid options: {e}")))?;
+
+        reservations.push(Reservation {
+            mac,
+            ip,
+            hostname: field(2).map(|h| h.to_owned()),
+            options,
+        });
+    }
+    Ok(reservations)
+}
+
+/// parse reservations from a JSON list
+pub fn parse_json(input: &str) -> Result<Vec<Reservation>, ImportError> {
+    Ok(serde_json::from_str(input)?)
+}
+
+/// split a single CSV row, supports double-quoted fields with `""` as an escaped quote
+fn split_row(row: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut cur = String::new();
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cur.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut cur)),
+            c => cur.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field".into());
+    }
+    fields.push(cur);
+    Ok(fields)
+}
+
+/// Validate a batch of reservations against the running config. Reservations must be unique
+/// by mac & ip, belong to a configured network, must not be inside a dynamic range and must
+/// not take the ip or mac of a reservation in the config file
+pub fn validate(reservations: &[Reservation], cfg: &v4::Config) -> Vec<Invalid> {
+    let mut macs = HashSet::new();
+    let mut ips = HashSet::new();
+    reservations
+        .iter()
+        .enumerate()
+        .filter_map(|(index, res)| {
+            let reason = if !macs.insert(res.mac) {
+                Some("duplicate mac in batch".to_owned())
+            } else if !ips.insert(res.ip) {
+                Some("duplicate ip in batch".to_owned())
+            } else {
+                match cfg.network(res.ip) {
+                    None => Some("ip is not in a configured network".to_owned()),
+                    Some(net) if net.in_range(res.ip) => {
+                        Some("ip is inside a dynamic range".to_owned())
+                    }
+                    Some(net) if net.reservations().any(|r| r.ip() == res.ip) => {
+                        Some("ip is reserved in the config".to_owned())
+                    }
+                    Some(net) if net.has_reserved_mac(res.mac) => {
+                        Some("mac has a reservation in the config".to_owned())
+                    }
+                    Some(_) => None,
+                }
+            };
+            reason.map(|reason| Invalid {
+                index,
+                mac: res.mac.to_string(),
+                ip: res.ip,
+                reason,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SAMPLE_YAML: &str = include_str!("../../config/sample/config.yaml");
+
+    #[test]
+    fn test_parse_csv() {
+        let input = "mac,ip,hostname,options\n\
+            aa:bb:cc:dd:ee:01,192.168.0.200,printer-1,\n\
+            aa:bb:cc:dd:ee:02,192.168.0.201,,\"{\"\"routers\"\": {\"\"type\"\": \"\"ip\"\", \"\"value\"\": \"\"192.168.0.1\"\"}}\"\n";
+        let res = parse_csv(input).unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].hostname.as_deref(), Some("printer-1"));
+        assert!(res[0].options.is_none());
+        assert!(res[1].hostname.is_none());
+        assert!(res[1].options.is_some());
+
+        assert!(matches!(
+            parse_csv("aa:bb:cc:dd:ee:01,not-an-ip"),
+            Err(ImportError::Csv { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let cfg = config::DhcpConfig::parse_str(SAMPLE_YAML).unwrap();
+        let res = parse_json(
+            r#"[
+                { "mac": "aa:bb:cc:dd:ee:01", "ip": "192.168.0.200" },
+                { "mac": "aa:bb:cc:dd:ee:01", "ip": "192.168.0.201" },
+                { "mac": "aa:bb:cc:dd:ee:02", "ip": "192.168.0.100" },
+                { "mac": "aa:bb:cc:dd:ee:03", "ip": "172.16.0.1" },
+                { "mac": "aa:bb:cc:dd:ee:04", "ip": "192.168.0.170" },
+                { "mac": "aa:bb:cc:dd:ee:ff", "ip": "192.168.0.202" }
+            ]"#,
+        )
+        .unwrap();
+        let invalid = validate(&res, cfg.v4());
+        assert_eq!(
+            invalid.iter().map(|i| i.index).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5]
+        );
+        assert_eq!(invalid[3].reason, "ip is reserved in the config");
+        assert_eq!(invalid[4].reason, "mac has a reservation in the config");
+    }
+}
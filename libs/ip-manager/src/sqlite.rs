@@ -1,44 +1,239 @@
 use std::{
-    collections::HashSet,
+    collections::HashMap,
     net::{IpAddr, Ipv4Addr},
     ops::RangeInclusive,
+    path::Path,
     str::FromStr,
+    sync::{Arc, Mutex},
     time::{Duration, SystemTime},
 };
 
 use async_trait::async_trait;
+use config::v4::Exclusions;
+use dora_core::tokio::sync::Mutex as AsyncMutex;
 use sqlx::{
-    sqlite::{SqliteConnectOptions, SqlitePool},
+    pool::PoolConnection,
+    sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions},
     ConnectOptions, Sqlite,
 };
-use tracing::debug;
+use tracing::{debug, warn};
 
-use crate::{ClientInfo, IpState, State, Storage};
+use crate::{
+    clock, migrate, AddrRegistration, Change, ClientInfo, Cursor, HistoryEntry, HistoryFilter,
+    IpState, LeaseCounts, LeaseFilter, LeaseFingerprint, Page, Probation, Reservation, State,
+    Storage, StorageError,
+};
+
+/// connections in the pool, shared by DHCP, the external api & maintenance
+pub const POOL_SIZE: u32 = 10;
 
 #[derive(Debug)]
 pub struct SqliteDb {
     inner: SqlitePool,
+    /// allocations are serialized per range (start, end), so concurrent DISCOVERs for
+    /// the same range queue here instead of racing each other for the write lock
+    ranges: Arc<Mutex<HashMap<(i64, i64), Arc<AsyncMutex<()>>>>>,
 }
 
 impl Clone for SqliteDb {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            ranges: self.ranges.clone(),
         }
     }
 }
 
 impl SqliteDb {
     pub async fn new(uri: impl AsRef<str>) -> Result<Self, sqlx::Error> {
-        let mut opts = SqliteConnectOptions::from_str(uri.as_ref())?
+        Self::with_key(uri, None).await
+    }
+
+    /// Open the database, encrypted with SQLCipher if `key` is provided. A keyed
+    /// database can only be opened if the `sqlcipher` feature is enabled, the key is
+    /// never silently ignored by a plain sqlite build
+    pub async fn with_key(uri: impl AsRef<str>, key: Option<&str>) -> Result<Self, sqlx::Error> {
+        let inner = Self::connect(uri.as_ref(), key, false).await?;
+        migrate::run(&inner).await?;
+        Ok(Self::from_pool(inner))
+    }
+
+    /// Open an existing database read-only, ex. a copy of another server's for the
+    /// external api. Migrations aren't run, the database must already be at this
+    /// build's schema version
+    pub async fn read_only(uri: impl AsRef<str>, key: Option<&str>) -> Result<Self, sqlx::Error> {
+        let inner = Self::connect(uri.as_ref(), key, true).await?;
+        migrate::check(&inner).await?;
+        Ok(Self::from_pool(inner))
+    }
+
+    async fn connect(
+        uri: &str,
+        key: Option<&str>,
+        read_only: bool,
+    ) -> Result<SqlitePool, sqlx::Error> {
+        let mut opts = SqliteConnectOptions::from_str(uri)?
             .synchronous(sqlx::sqlite::SqliteSynchronous::Normal)
-            .create_if_missing(true);
-        // make sqlite log queries at trace level so we don't get a bloated log on `info`
-        opts.log_statements(tracing::log::LevelFilter::Trace);
+            .read_only(read_only)
+            .create_if_missing(!read_only);
+        match key {
+            Some(key) => {
+                // sqlx runs `key` before the other pragmas, as SQLCipher requires
+                opts = opts.pragma("key", format!("'{}'", key.replace('\'', "''")));
+                // statement logging would include the key pragma
+                opts.disable_statement_logging();
+            }
+            None => {
+                // make sqlite log queries at trace level so we don't get a bloated log on `info`
+                opts.log_statements(tracing::log::LevelFilter::Trace);
+            }
+        }
+
+        let inner = SqlitePoolOptions::new()
+            .max_connections(POOL_SIZE)
+            .connect_with(opts)
+            .await?;
+        if key.is_some() {
+            let cipher: Option<String> = sqlx::query_scalar("PRAGMA cipher_version")
+                .fetch_optional(&inner)
+                .await?;
+            if cipher.is_none() {
+                return Err(sqlx::Error::Configuration(
+                    "a database key was provided but sqlite was not built with SQLCipher, \
+                     enable the `sqlcipher` feature"
+                        .into(),
+                ));
+            }
+        }
+        Ok(inner)
+    }
+
+    fn from_pool(inner: SqlitePool) -> Self {
+        Self {
+            inner,
+            ranges: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
 
-        let inner = SqlitePool::connect_with(opts).await?;
-        sqlx::migrate!("../../migrations").run(&inner).await?;
-        Ok(Self { inner })
+    /// the allocation lock for a range, ranges come from config so the map stays small
+    fn range_lock(&self, start_ip: i64, end_ip: i64) -> Arc<AsyncMutex<()>> {
+        self.ranges
+            .lock()
+            .expect("range lock poisoned")
+            .entry((start_ip, end_ip))
+            .or_default()
+            .clone()
+    }
+
+    /// Fails if the database can't be written, ex. the file or its directory is read-only
+    /// for dora's user. sqlite opens a read-only file without complaint, this finds out
+    /// before the first lease does. Nothing is changed
+    pub async fn check_writable(&self) -> Result<(), sqlx::Error> {
+        let mut tx = ImmediateTx::begin(&self.inner).await?;
+        let res = sqlx::query("UPDATE schema_version SET updated_at = updated_at")
+            .execute(tx.conn())
+            .await;
+        tx.rollback().await;
+        res.map(|_| ())
+    }
+}
+
+/// A `BEGIN IMMEDIATE` transaction. Unlike `begin()`, which is deferred, this takes the
+/// write lock up front so a select followed by an insert can't interleave with another
+/// writer. sqlx's `Transaction` can't begin one, and a `PoolConnection` dropped with a
+/// transaction open goes back to the pool still holding the write lock, so every later
+/// writer gets SQLITE_BUSY. If this is dropped without `commit` or `rollback`, ex. the
+/// future using it was cancelled, the transaction is rolled back before the connection
+/// is reused
+struct ImmediateTx {
+    conn: Option<PoolConnection<Sqlite>>,
+}
+
+impl ImmediateTx {
+    async fn begin(pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        let mut conn = pool.acquire().await?;
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut conn).await?;
+        Ok(Self { conn: Some(conn) })
+    }
+
+    fn conn(&mut self) -> &mut PoolConnection<Sqlite> {
+        self.conn.as_mut().expect("transaction already finished")
+    }
+
+    /// if `COMMIT` fails the transaction is rolled back on drop
+    async fn commit(mut self) -> Result<(), sqlx::Error> {
+        sqlx::query("COMMIT").execute(self.conn()).await?;
+        // only taken once committed, a commit that's cancelled is rolled back on drop
+        self.conn.take();
+        Ok(())
+    }
+
+    async fn rollback(mut self) {
+        let rolled_back = rollback(self.conn()).await;
+        // only taken once finished, a rollback that's cancelled is retried on drop
+        let conn = self.conn.take();
+        if !rolled_back {
+            drop(conn.map(PoolConnection::detach));
+        }
+    }
+}
+
+impl Drop for ImmediateTx {
+    fn drop(&mut self) {
+        let Some(mut conn) = self.conn.take() else {
+            return;
+        };
+        match dora_core::tokio::runtime::Handle::try_current() {
+            Ok(rt) => {
+                rt.spawn(async move {
+                    if !rollback(&mut conn).await {
+                        drop(conn.detach());
+                    }
+                });
+            }
+            // closing the connection rolls back the transaction
+            Err(_) => drop(conn.detach()),
+        }
+    }
+}
+
+/// roll back the open transaction on `conn`, returns false if it failed & the connection
+/// mustn't be reused
+async fn rollback(conn: &mut PoolConnection<Sqlite>) -> bool {
+    match sqlx::query("ROLLBACK").execute(conn).await {
+        Ok(_) => true,
+        Err(err) => {
+            warn!(
+                ?err,
+                "failed to rollback transaction, closing the connection"
+            );
+            false
+        }
+    }
+}
+
+/// SQLITE_BUSY & SQLITE_LOCKED, another connection holds the lock
+const RETRYABLE_PRIMARY: [i32; 2] = [5, 6];
+/// SQLITE_CONSTRAINT_PRIMARYKEY & SQLITE_CONSTRAINT_UNIQUE, a concurrent insert took the
+/// same IP
+const CONFLICT_EXTENDED: [i32; 2] = [1555, 2067];
+
+/// the extended result code of a sqlite error
+fn sqlite_code(err: &sqlx::Error) -> Option<i32> {
+    match err {
+        sqlx::Error::Database(err) => err.code()?.parse().ok(),
+        _ => None,
+    }
+}
+
+// `PoolTimedOut` isn't retryable, acquiring already waited for the pool's timeout
+impl StorageError for sqlx::Error {
+    fn is_retryable(&self) -> bool {
+        sqlite_code(self).is_some_and(|code| RETRYABLE_PRIMARY.contains(&(code & 0xff)))
+    }
+
+    fn is_conflict(&self) -> bool {
+        sqlite_code(self).is_some_and(|code| CONFLICT_EXTENDED.contains(&code))
     }
 }
 
@@ -47,8 +242,8 @@ impl Storage for SqliteDb {
     // TODO: consider alternate error type
     type Error = sqlx::Error;
 
-    /// find the next expired IP in the range, or where client_id matches,
-    /// and update it with the new client_id & expiry & state
+    /// find the next expired IP in the range, or where client_id matches in the same
+    /// network, and update it with the new client_id & expiry & state
     /// NOTE: always sets probation = false
     async fn next_expired(
         &self,
@@ -59,18 +254,21 @@ impl Storage for SqliteDb {
         state: Option<IpState>,
     ) -> Result<Option<IpAddr>, Self::Error> {
         match (*range.start(), *range.end(), network) {
-            (IpAddr::V4(start), IpAddr::V4(end), IpAddr::V4(_network)) => {
+            (IpAddr::V4(start), IpAddr::V4(end), IpAddr::V4(network)) => {
                 let start_ip = u32::from(start) as i64;
                 let end_ip = u32::from(end) as i64;
-                let now = util::systime_epoch(SystemTime::now());
+                let now = util::systime_epoch(clock::now());
                 let (leased, _probate) = state.unwrap_or(IpState::Reserve).into();
 
+                let lock = self.range_lock(start_ip, end_ip);
+                let _guard = lock.lock().await;
                 Ok(util::update_next_expired(
                     &self.inner,
                     now,
                     id,
                     start_ip,
                     end_ip,
+                    u32::from(network) as i64,
                     util::systime_epoch(expires_at),
                     leased,
                 )
@@ -87,7 +285,7 @@ impl Storage for SqliteDb {
         &self,
         range: RangeInclusive<IpAddr>,
         // TODO should not mix Ip and Ipv4 in args
-        exclusions: &HashSet<Ipv4Addr>,
+        exclusions: &Exclusions,
         network: IpAddr,
         id: &[u8],
         expires_at: SystemTime,
@@ -104,40 +302,56 @@ impl Storage for SqliteDb {
                 let id = id.to_vec();
 
                 debug!("no expired entries, finding start of range");
+                let lock = self.range_lock(start_ip, end_ip);
+                let _guard = lock.lock().await;
                 // TRANSACTION START
-                let mut conn = self.inner.begin().await?;
-                // we only use this IP to find what the next available should be
-                let ip = match util::max_in_range(&mut conn, start_ip, end_ip).await? {
-                    Some(State::Leased(cur) | State::Reserved(cur) | State::Probated(cur)) => {
-                        let start = cur.ip;
-                        let end = *range.end();
-                        debug!(?start, "get next IP starting from");
-                        util::inc_ip(start, end, exclusions)
+                let mut tx = ImmediateTx::begin(&self.inner).await?;
+                let conn = tx.conn();
+                let res = async {
+                    // we only use this IP to find what the next available should be
+                    let ip = match util::max_in_range(&mut *conn, start_ip, end_ip).await? {
+                        Some(State::Leased(cur) | State::Reserved(cur) | State::Probated(cur)) => {
+                            let start = cur.ip;
+                            let end = *range.end();
+                            debug!(?start, "get next IP starting from");
+                            util::inc_ip(start, end, exclusions)
+                        }
+                        None => {
+                            debug!(start = ?range.start(), "using start of range");
+                            // no IPs in range, so it must be empty
+                            exclusions
+                                .skip(start)
+                                .filter(|ip| *ip <= end)
+                                .map(IpAddr::V4)
+                        }
+                    };
+                    if let Some(IpAddr::V4(v4_ip)) = ip {
+                        util::insert(
+                            &mut *conn,
+                            u32::from(v4_ip) as i64,
+                            u32::from(network) as i64,
+                            &id,
+                            util::systime_epoch(expires_at),
+                            state.map(|s| s.into()),
+                        )
+                        .await?;
+                    } else {
+                        debug!("unable to find start of range");
+                    }
+                    Ok::<_, sqlx::Error>(ip)
+                }
+                .await;
+                match res {
+                    Ok(Some(ip)) => {
+                        // TRANSACTION COMMIT
+                        tx.commit().await?;
+                        Ok(Some(ip))
                     }
-                    None => {
-                        debug!(start = ?range.start(), "using start of range");
-                        // no IPs in range, so it must be empty
-                        Some(*range.start())
+                    res => {
+                        // TRANSACTION ROLLBACK
+                        tx.rollback().await;
+                        res
                     }
-                };
-                if let Some(IpAddr::V4(v4_ip)) = ip {
-                    util::insert(
-                        &mut conn,
-                        u32::from(v4_ip) as i64,
-                        u32::from(network) as i64,
-                        &id,
-                        util::systime_epoch(expires_at),
-                        state.map(|s| s.into()),
-                    )
-                    .await?;
-                    // TRANSACTION COMMIT
-                    conn.commit().await?;
-                    Ok(ip)
-                } else {
-                    debug!("unable to find start of range");
-                    // TRANSACTION ROLLBACK
-                    conn.rollback().await?;
-                    Ok(None)
                 }
             }
             _ => {
@@ -160,7 +374,7 @@ impl Storage for SqliteDb {
                 u32::from(ip) as i64,
                 id,
                 util::systime_epoch(expires_at),
-                util::systime_epoch(SystemTime::now()),
+                util::systime_epoch(clock::now()),
                 lease,
                 probation,
             )
@@ -188,7 +402,7 @@ impl Storage for SqliteDb {
                     u32::from(ip) as i64,
                     id,
                     util::systime_epoch(expires_at),
-                    util::systime_epoch(SystemTime::now()),
+                    util::systime_epoch(clock::now()),
                     lease,
                     probation,
                     new_id,
@@ -201,6 +415,37 @@ impl Storage for SqliteDb {
         }
     }
 
+    async fn update_expired_since(
+        &self,
+        ip: IpAddr,
+        state: IpState,
+        id: &[u8],
+        expires_at: SystemTime,
+        since: SystemTime,
+    ) -> Result<Option<IpAddr>, Self::Error> {
+        let (lease, probation) = state.into();
+        match ip {
+            IpAddr::V4(ip) => {
+                // same query as `update_unexpired`, entries expiring after `since` count
+                // as unexpired
+                util::update_unexpired(
+                    &self.inner,
+                    u32::from(ip) as i64,
+                    id,
+                    util::systime_epoch(expires_at),
+                    util::systime_epoch(since),
+                    lease,
+                    probation,
+                    Some(id),
+                )
+                .await
+            }
+            _ => {
+                panic!("ipv6 not yet implemented");
+            }
+        }
+    }
+
     async fn update_ip(
         &self,
         ip: IpAddr,
@@ -262,7 +507,7 @@ impl Storage for SqliteDb {
     }
 
     async fn get_id(&self, id: &[u8]) -> Result<Option<IpAddr>, Self::Error> {
-        util::find_by_id(&self.inner, id, util::systime_epoch(SystemTime::now())).await
+        util::find_by_id(&self.inner, id, util::systime_epoch(clock::now())).await
     }
 
     async fn release_ip(&self, ip: IpAddr, id: &[u8]) -> Result<Option<ClientInfo>, Self::Error> {
@@ -291,15 +536,565 @@ impl Storage for SqliteDb {
             }
         }
     }
-    async fn count(&self, state: IpState) -> Result<usize, Self::Error> {
-        let (lease, probation) = state.into();
-        util::count(
-            &self.inner,
-            lease,
-            probation,
-            util::systime_epoch(SystemTime::now()),
+    async fn count_range(&self, range: RangeInclusive<IpAddr>) -> Result<LeaseCounts, Self::Error> {
+        match (range.start(), range.end()) {
+            (IpAddr::V4(start), IpAddr::V4(end)) => {
+                util::count_range(
+                    &self.inner,
+                    u32::from(*start) as i64,
+                    u32::from(*end) as i64,
+                    util::systime_epoch(clock::now()),
+                )
+                .await
+            }
+            _ => {
+                panic!("ipv6 not yet implemented");
+            }
+        }
+    }
+
+    async fn expire_before(&self, before: SystemTime) -> Result<u64, Self::Error> {
+        Ok(sqlx::query("DELETE FROM leases WHERE expires_at < ?1")
+            .bind(util::systime_epoch(before))
+            .execute(&self.inner)
+            .await?
+            .rows_affected())
+    }
+
+    async fn select_page(&self, filter: &LeaseFilter, cursor: Cursor) -> Result<Page, Self::Error> {
+        let (start, end) = match &filter.range {
+            Some(range) => match (range.start(), range.end()) {
+                (IpAddr::V4(start), IpAddr::V4(end)) => {
+                    (u32::from(*start) as i64, u32::from(*end) as i64)
+                }
+                _ => {
+                    panic!("ipv6 not yet implemented");
+                }
+            },
+            None => (0, u32::MAX as i64),
+        };
+        let after = match cursor.after {
+            Some(IpAddr::V4(ip)) => u32::from(ip) as i64,
+            Some(IpAddr::V6(_ip)) => {
+                panic!("ipv6 not yet implemented");
+            }
+            None => -1,
+        };
+        let (leased, probation) = filter.state.map(<(bool, bool)>::from).unzip();
+        // fetch one extra row to know if there is a next page
+        let mut entries = sqlx::query_as::<_, (i64, Option<Vec<u8>>, i64, i64, bool, bool)>(
+            "SELECT ip, client_id, network, expires_at, leased, probation FROM leases
+            WHERE ip > ?1 AND ip >= ?2 AND ip <= ?3 AND (?4 OR expires_at > ?5)
+            AND (?6 IS NULL OR (leased = ?6 AND probation = ?7))
+            ORDER BY ip LIMIT ?8",
         )
-        .await
+        .bind(after)
+        .bind(start)
+        .bind(end)
+        .bind(filter.expired)
+        .bind(util::systime_epoch(clock::now()))
+        .bind(leased)
+        .bind(probation)
+        .bind(cursor.limit as i64 + 1)
+        .fetch_all(&self.inner)
+        .await?
+        .into_iter()
+        .map(|(ip, id, network, expires_at, leased, probation)| {
+            let info = ClientInfo {
+                ip: IpAddr::V4(Ipv4Addr::from(ip as u32)),
+                id,
+                network: IpAddr::V4(Ipv4Addr::from(network as u32)),
+                expires_at: util::to_systime(expires_at),
+            };
+            util::into_clientinfo(info, leased, probation)
+        })
+        .collect::<Vec<_>>();
+        let next = if entries.len() > cursor.limit {
+            entries.truncate(cursor.limit);
+            entries.last().map(|last| Cursor {
+                after: Some(last.as_ref().ip),
+                limit: cursor.limit,
+            })
+        } else {
+            None
+        };
+        Ok(Page { entries, next })
+    }
+
+    async fn insert_reservations(&self, reservations: &[Reservation]) -> Result<(), Self::Error> {
+        // TRANSACTION START
+        let mut conn = self.inner.begin().await?;
+        for res in reservations {
+            util::insert_reservation(&mut conn, res).await?;
+        }
+        // TRANSACTION COMMIT
+        conn.commit().await?;
+        Ok(())
+    }
+
+    async fn get_reservation(&self, mac: &[u8]) -> Result<Option<Reservation>, Self::Error> {
+        util::find_reservation(&self.inner, mac).await
+    }
+
+    async fn has_reservations(&self) -> Result<bool, Self::Error> {
+        util::any_reservations(&self.inner).await
+    }
+
+    async fn get_probation(&self, ip: IpAddr) -> Result<Option<Probation>, Self::Error> {
+        match ip {
+            IpAddr::V4(ip) => util::find_probation(&self.inner, u32::from(ip) as i64).await,
+            IpAddr::V6(_ip) => {
+                panic!("ipv6 not yet implemented");
+            }
+        }
+    }
+
+    async fn set_probation(&self, probation: &Probation) -> Result<(), Self::Error> {
+        util::insert_probation(&self.inner, probation).await
+    }
+
+    async fn quarantined(&self) -> Result<Vec<Probation>, Self::Error> {
+        util::quarantined(&self.inner).await
+    }
+
+    async fn clear_quarantine(&self, ip: IpAddr) -> Result<bool, Self::Error> {
+        match ip {
+            IpAddr::V4(ip) => {
+                let ip = u32::from(ip) as i64;
+                // TRANSACTION START
+                let mut conn = self.inner.begin().await?;
+                let cleared =
+                    sqlx::query("DELETE FROM probation WHERE ip = ?1 AND quarantined = TRUE")
+                        .bind(ip)
+                        .execute(&mut conn)
+                        .await?
+                        .rows_affected()
+                        > 0;
+                if cleared {
+                    sqlx::query("DELETE FROM leases WHERE ip = ?1 AND probation = TRUE")
+                        .bind(ip)
+                        .execute(&mut conn)
+                        .await?;
+                }
+                // TRANSACTION COMMIT
+                conn.commit().await?;
+                Ok(cleared)
+            }
+            IpAddr::V6(_ip) => {
+                panic!("ipv6 not yet implemented");
+            }
+        }
+    }
+
+    async fn exclusions(&self) -> Result<Vec<RangeInclusive<Ipv4Addr>>, Self::Error> {
+        Ok(sqlx::query_as::<_, (i64, i64)>(
+            "SELECT start_ip, end_ip FROM exclusions ORDER BY start_ip, end_ip",
+        )
+        .fetch_all(&self.inner)
+        .await?
+        .into_iter()
+        .map(|(start, end)| Ipv4Addr::from(start as u32)..=Ipv4Addr::from(end as u32))
+        .collect())
+    }
+
+    async fn add_exclusion(&self, range: RangeInclusive<Ipv4Addr>) -> Result<(), Self::Error> {
+        sqlx::query("INSERT OR IGNORE INTO exclusions (start_ip, end_ip) VALUES (?1, ?2)")
+            .bind(u32::from(*range.start()) as i64)
+            .bind(u32::from(*range.end()) as i64)
+            .execute(&self.inner)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_exclusion(&self, range: RangeInclusive<Ipv4Addr>) -> Result<bool, Self::Error> {
+        Ok(
+            sqlx::query("DELETE FROM exclusions WHERE start_ip = ?1 AND end_ip = ?2")
+                .bind(u32::from(*range.start()) as i64)
+                .bind(u32::from(*range.end()) as i64)
+                .execute(&self.inner)
+                .await?
+                .rows_affected()
+                > 0,
+        )
+    }
+
+    async fn lease_history(
+        &self,
+        ip: IpAddr,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<HistoryEntry>, Self::Error> {
+        let ip = match ip {
+            IpAddr::V4(ip) => u32::from(ip) as i64,
+            IpAddr::V6(_ip) => {
+                panic!("ipv6 not yet implemented");
+            }
+        };
+        // the most recent `limit` changes in the window, returned oldest first
+        Ok(
+            sqlx::query_as::<_, (i64, Option<Vec<u8>>, bool, bool, bool, Option<i64>, i64)>(
+                "SELECT ip, client_id, leased, probation, removed, expires_at, at FROM (
+                SELECT * FROM lease_history
+                WHERE ip = ?1 AND (?2 IS NULL OR at >= ?2) AND (?3 IS NULL OR at <= ?3)
+                ORDER BY at DESC, id DESC LIMIT ?4
+            ) ORDER BY at, id",
+            )
+            .bind(ip)
+            .bind(filter.since.map(util::systime_epoch))
+            .bind(filter.until.map(util::systime_epoch))
+            .bind(filter.limit as i64)
+            .fetch_all(&self.inner)
+            .await?
+            .into_iter()
+            .map(util::into_history_entry)
+            .collect(),
+        )
+    }
+
+    async fn prune_history(
+        &self,
+        before: SystemTime,
+        up_to: Option<u64>,
+    ) -> Result<u64, Self::Error> {
+        Ok(
+            sqlx::query("DELETE FROM lease_history WHERE at < ?1 AND (?2 IS NULL OR id <= ?2)")
+                .bind(util::systime_epoch(before))
+                .bind(up_to.map(|seq| seq as i64))
+                .execute(&self.inner)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    async fn changes_after(&self, seq: u64, limit: usize) -> Result<Vec<Change>, Self::Error> {
+        // `before` is the IP's previous row, ids only increase so it's the largest below
+        type Row = (
+            i64,
+            i64,
+            Option<Vec<u8>>,
+            bool,
+            bool,
+            bool,
+            Option<i64>,
+            i64,
+            Option<i64>,
+            Option<Vec<u8>>,
+            Option<bool>,
+            Option<bool>,
+            Option<bool>,
+            Option<i64>,
+            Option<i64>,
+        );
+        Ok(sqlx::query_as::<_, Row>(
+            "SELECT h.id, h.ip, h.client_id, h.leased, h.probation, h.removed, h.expires_at, h.at,
+                p.ip, p.client_id, p.leased, p.probation, p.removed, p.expires_at, p.at
+            FROM lease_history h
+            LEFT JOIN lease_history p ON p.id = (
+                SELECT MAX(id) FROM lease_history WHERE ip = h.ip AND id < h.id
+            )
+            WHERE h.id > ?1 ORDER BY h.id LIMIT ?2",
+        )
+        .bind(seq as i64)
+        .bind(limit as i64)
+        .fetch_all(&self.inner)
+        .await?
+        .into_iter()
+        .map(|row| Change {
+            seq: row.0 as u64,
+            after: util::into_history_entry((row.1, row.2, row.3, row.4, row.5, row.6, row.7)),
+            before: match (row.8, row.10, row.11, row.12, row.14) {
+                (Some(ip), Some(leased), Some(probation), Some(removed), Some(at)) => Some(
+                    util::into_history_entry((ip, row.9, leased, probation, removed, row.13, at)),
+                ),
+                _ => None,
+            },
+        })
+        .collect())
+    }
+
+    async fn change_cursor(&self) -> Result<Option<u64>, Self::Error> {
+        Ok(
+            sqlx::query_scalar::<_, i64>("SELECT shipped FROM change_stream WHERE id = 0")
+                .fetch_optional(&self.inner)
+                .await?
+                .map(|seq| seq as u64),
+        )
+    }
+
+    async fn set_change_cursor(&self, seq: u64) -> Result<(), Self::Error> {
+        sqlx::query(
+            "INSERT INTO change_stream (id, shipped, updated_at) VALUES (0, ?1, ?2)
+            ON CONFLICT(id) DO UPDATE SET shipped = ?1, updated_at = ?2",
+        )
+        .bind(seq as i64)
+        .bind(util::systime_epoch(clock::now()))
+        .execute(&self.inner)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_hostname(
+        &self,
+        ip: IpAddr,
+        id: &[u8],
+        hostname: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        let ip = match ip {
+            IpAddr::V4(ip) => u32::from(ip) as i64,
+            IpAddr::V6(_ip) => {
+                panic!("ipv6 not yet implemented");
+            }
+        };
+        match hostname {
+            Some(hostname) => sqlx::query(
+                "INSERT OR REPLACE INTO lease_hostnames (ip, client_id, hostname) VALUES (?1, ?2, ?3)",
+            )
+            .bind(ip)
+            .bind(id)
+            .bind(hostname),
+            None => sqlx::query("DELETE FROM lease_hostnames WHERE ip = ?1").bind(ip),
+        }
+        .execute(&self.inner)
+        .await?;
+        Ok(())
+    }
+
+    async fn active_hostnames(&self) -> Result<Vec<(IpAddr, String)>, Self::Error> {
+        // the client id must still match, the name belongs to the client not the IP
+        Ok(sqlx::query_as::<_, (i64, String)>(
+            "SELECT h.ip, h.hostname FROM lease_hostnames h
+            JOIN leases l ON l.ip = h.ip AND l.client_id = h.client_id
+            WHERE l.leased = TRUE AND l.probation = FALSE AND l.expires_at > ?1
+            ORDER BY h.ip",
+        )
+        .bind(util::systime_epoch(clock::now()))
+        .fetch_all(&self.inner)
+        .await?
+        .into_iter()
+        .map(|(ip, hostname)| (IpAddr::V4(Ipv4Addr::from(ip as u32)), hostname))
+        .collect())
+    }
+
+    async fn set_fingerprint(
+        &self,
+        ip: IpAddr,
+        id: &[u8],
+        fingerprint: &LeaseFingerprint,
+    ) -> Result<(), Self::Error> {
+        let ip = match ip {
+            IpAddr::V4(ip) => u32::from(ip) as i64,
+            IpAddr::V6(_ip) => {
+                panic!("ipv6 not yet implemented");
+            }
+        };
+        sqlx::query(
+            "INSERT OR REPLACE INTO lease_fingerprints (ip, client_id, fingerprint, prl, vendor_class)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+        )
+        .bind(ip)
+        .bind(id)
+        .bind(&fingerprint.fingerprint)
+        .bind(&fingerprint.prl)
+        .bind(fingerprint.vendor_class.as_deref())
+        .execute(&self.inner)
+        .await?;
+        Ok(())
+    }
+
+    async fn active_fingerprints(&self) -> Result<Vec<(IpAddr, LeaseFingerprint)>, Self::Error> {
+        // the client id must still match, the fingerprint belongs to the client not the IP
+        Ok(sqlx::query_as::<_, (i64, String, String, Option<String>)>(
+            "SELECT f.ip, f.fingerprint, f.prl, f.vendor_class FROM lease_fingerprints f
+            JOIN leases l ON l.ip = f.ip AND l.client_id = f.client_id
+            WHERE l.leased = TRUE AND l.probation = FALSE AND l.expires_at > ?1
+            ORDER BY f.ip",
+        )
+        .bind(util::systime_epoch(clock::now()))
+        .fetch_all(&self.inner)
+        .await?
+        .into_iter()
+        .map(|(ip, fingerprint, prl, vendor_class)| {
+            (
+                IpAddr::V4(Ipv4Addr::from(ip as u32)),
+                LeaseFingerprint {
+                    fingerprint,
+                    prl,
+                    vendor_class,
+                },
+            )
+        })
+        .collect())
+    }
+
+    async fn set_oui(
+        &self,
+        ip: IpAddr,
+        network: IpAddr,
+        oui: [u8; 3],
+        id: &[u8],
+    ) -> Result<(), Self::Error> {
+        match (ip, network) {
+            (IpAddr::V4(ip), IpAddr::V4(network)) => {
+                sqlx::query(
+                    "INSERT OR REPLACE INTO oui_leases (ip, network, oui, client_id) VALUES (?1, ?2, ?3, ?4)",
+                )
+                .bind(u32::from(ip) as i64)
+                .bind(u32::from(network) as i64)
+                .bind(&oui[..])
+                .bind(id)
+                .execute(&self.inner)
+                .await?;
+                Ok(())
+            }
+            _ => {
+                panic!("ipv6 not yet implemented");
+            }
+        }
+    }
+
+    async fn count_oui(
+        &self,
+        network: IpAddr,
+        oui: [u8; 3],
+        id: &[u8],
+    ) -> Result<usize, Self::Error> {
+        let network = match network {
+            IpAddr::V4(network) => u32::from(network) as i64,
+            IpAddr::V6(_network) => {
+                panic!("ipv6 not yet implemented");
+            }
+        };
+        // offers count too, an OUI at its limit can't be offered more addresses. The
+        // client id must still match, the IP may have been handed to someone else since
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM oui_leases o
+            JOIN leases l ON l.ip = o.ip AND l.client_id = o.client_id
+            WHERE o.network = ?1 AND o.oui = ?2 AND o.client_id != ?3
+            AND l.probation = FALSE AND l.expires_at > ?4",
+        )
+        .bind(network)
+        .bind(&oui[..])
+        .bind(id)
+        .bind(util::systime_epoch(clock::now()))
+        .fetch_one(&self.inner)
+        .await?;
+        Ok(count as usize)
+    }
+
+    async fn leases_elsewhere(
+        &self,
+        id: &[u8],
+        network: IpAddr,
+    ) -> Result<Vec<IpAddr>, Self::Error> {
+        let network = match network {
+            IpAddr::V4(network) => u32::from(network) as i64,
+            IpAddr::V6(_network) => {
+                panic!("ipv6 not yet implemented");
+            }
+        };
+        Ok(sqlx::query_scalar::<_, i64>(
+            "SELECT ip FROM leases
+            WHERE client_id = ?1 AND network != ?2 AND expires_at > ?3 AND probation = FALSE
+            ORDER BY ip",
+        )
+        .bind(id)
+        .bind(network)
+        .bind(util::systime_epoch(clock::now()))
+        .fetch_all(&self.inner)
+        .await?
+        .into_iter()
+        .map(|ip| IpAddr::V4(Ipv4Addr::from(ip as u32)))
+        .collect())
+    }
+
+    async fn load_stats(&self) -> Result<Vec<(String, String, u64)>, Self::Error> {
+        Ok(
+            sqlx::query_as::<_, (String, String, i64)>("SELECT name, label, value FROM stats")
+                .fetch_all(&self.inner)
+                .await?
+                .into_iter()
+                .map(|(name, label, value)| (name, label, value as u64))
+                .collect(),
+        )
+    }
+
+    async fn save_stats(&self, stats: &[(String, String, u64)]) -> Result<(), Self::Error> {
+        let mut conn = self.inner.begin().await?;
+        for (name, label, value) in stats {
+            sqlx::query("INSERT OR REPLACE INTO stats (name, label, value) VALUES (?1, ?2, ?3)")
+                .bind(name)
+                .bind(label)
+                .bind(*value as i64)
+                .execute(&mut conn)
+                .await?;
+        }
+        conn.commit().await?;
+        Ok(())
+    }
+
+    async fn register_addr(&self, reg: &AddrRegistration) -> Result<(), Self::Error> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO addr_registrations (ip, client_id, fqdn, expires_at)
+            VALUES (?1, ?2, ?3, ?4)",
+        )
+        .bind(&reg.ip.octets()[..])
+        .bind(&reg.client_id)
+        .bind(reg.fqdn.as_deref())
+        .bind(util::systime_epoch(reg.expires_at))
+        .execute(&self.inner)
+        .await?;
+        Ok(())
+    }
+
+    async fn addr_registrations(&self) -> Result<Vec<AddrRegistration>, Self::Error> {
+        Ok(
+            sqlx::query_as::<_, (Vec<u8>, Vec<u8>, Option<String>, i64)>(
+                "SELECT ip, client_id, fqdn, expires_at FROM addr_registrations
+                WHERE expires_at > ?1 ORDER BY ip",
+            )
+            .bind(util::systime_epoch(clock::now()))
+            .fetch_all(&self.inner)
+            .await?
+            .into_iter()
+            .filter_map(|(ip, client_id, fqdn, expires_at)| {
+                Some(AddrRegistration {
+                    ip: <[u8; 16]>::try_from(ip).ok()?.into(),
+                    client_id,
+                    fqdn,
+                    expires_at: util::to_systime(expires_at),
+                })
+            })
+            .collect(),
+        )
+    }
+
+    async fn checkpoint(&self) -> Result<(), Self::Error> {
+        // TRUNCATE waits for readers & writers, then empties the WAL file
+        let (busy, frames, moved): (i64, i64, i64) =
+            sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+                .fetch_one(&self.inner)
+                .await?;
+        if busy != 0 {
+            warn!(
+                frames,
+                moved, "WAL checkpoint was blocked, it will be finished on next open"
+            );
+        } else {
+            debug!(frames, "checkpointed WAL");
+        }
+        Ok(())
+    }
+
+    async fn backup(&self, path: &Path) -> Result<(), Self::Error> {
+        let path = path.to_str().ok_or_else(|| {
+            sqlx::Error::Configuration(format!("backup path {path:?} is not UTF-8").into())
+        })?;
+        // copies pages in one read transaction, so writers aren't blocked & the copy is
+        // consistent. With SQLCipher the copy is encrypted with the same key
+        sqlx::query("VACUUM INTO ?1")
+            .bind(path)
+            .execute(&self.inner)
+            .await?;
+        Ok(())
     }
 }
 
@@ -322,6 +1117,141 @@ mod util {
         SystemTime::UNIX_EPOCH + Duration::from_secs(time as u64)
     }
 
+    /// reservations table isn't covered by the offline query data, so these
+    /// queries are checked at runtime
+    pub async fn insert_reservation<'a, E>(conn: E, res: &Reservation) -> Result<(), sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = Sqlite>,
+    {
+        let options = res
+            .options
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(|err| sqlx::Error::Protocol(err.to_string()))?;
+        sqlx::query(
+            "INSERT OR REPLACE INTO reservations (ip, mac, hostname, options) VALUES (?1, ?2, ?3, ?4)",
+        )
+        .bind(u32::from(res.ip) as i64)
+        .bind(res.mac.octets().to_vec())
+        .bind(res.hostname.as_deref())
+        .bind(options)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find_reservation(
+        pool: &SqlitePool,
+        mac: &[u8],
+    ) -> Result<Option<Reservation>, sqlx::Error> {
+        let row: Option<(i64, Vec<u8>, Option<String>, Option<String>)> =
+            sqlx::query_as("SELECT ip, mac, hostname, options FROM reservations WHERE mac = ?1")
+                .bind(mac)
+                .fetch_optional(pool)
+                .await?;
+        row.map(|(ip, mac, hostname, options)| {
+            let mac: [u8; 6] = mac
+                .try_into()
+                .map_err(|_| sqlx::Error::Protocol("reservation mac is not 6 bytes".into()))?;
+            Ok(Reservation {
+                ip: Ipv4Addr::from(ip as u32),
+                mac: mac.into(),
+                hostname,
+                options: options
+                    .map(|opts| serde_json::from_str(&opts))
+                    .transpose()
+                    .map_err(|err| sqlx::Error::Decode(Box::new(err)))?,
+            })
+        })
+        .transpose()
+    }
+
+    pub async fn any_reservations(pool: &SqlitePool) -> Result<bool, sqlx::Error> {
+        let (any,): (bool,) = sqlx::query_as("SELECT EXISTS(SELECT 1 FROM reservations)")
+            .fetch_one(pool)
+            .await?;
+        Ok(any)
+    }
+
+    pub fn into_history_entry(
+        (ip, id, leased, probation, removed, expires_at, at): (
+            i64,
+            Option<Vec<u8>>,
+            bool,
+            bool,
+            bool,
+            Option<i64>,
+            i64,
+        ),
+    ) -> HistoryEntry {
+        HistoryEntry {
+            ip: IpAddr::V4(Ipv4Addr::from(ip as u32)),
+            id,
+            state: (!removed).then(|| match (leased, probation) {
+                (_, true) => IpState::Probate,
+                (true, false) => IpState::Lease,
+                (false, false) => IpState::Reserve,
+            }),
+            expires_at: expires_at.filter(|_| !removed).map(to_systime),
+            at: to_systime(at),
+        }
+    }
+
+    /// probation table isn't covered by the offline query data either
+    pub async fn insert_probation(
+        pool: &SqlitePool,
+        probation: &Probation,
+    ) -> Result<(), sqlx::Error> {
+        let ip = match probation.ip {
+            IpAddr::V4(ip) => u32::from(ip) as i64,
+            IpAddr::V6(_ip) => panic!("ipv6 not yet implemented"),
+        };
+        sqlx::query(
+            "INSERT OR REPLACE INTO probation (ip, count, last_probated, quarantined) VALUES (?1, ?2, ?3, ?4)",
+        )
+        .bind(ip)
+        .bind(probation.count as i64)
+        .bind(systime_epoch(probation.last_probated))
+        .bind(probation.quarantined)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    fn into_probation((ip, count, last_probated, quarantined): (i64, i64, i64, bool)) -> Probation {
+        Probation {
+            ip: IpAddr::V4(Ipv4Addr::from(ip as u32)),
+            count: count as u32,
+            last_probated: to_systime(last_probated),
+            quarantined,
+        }
+    }
+
+    pub async fn find_probation(
+        pool: &SqlitePool,
+        ip: i64,
+    ) -> Result<Option<Probation>, sqlx::Error> {
+        Ok(sqlx::query_as(
+            "SELECT ip, count, last_probated, quarantined FROM probation WHERE ip = ?1",
+        )
+        .bind(ip)
+        .fetch_optional(pool)
+        .await?
+        .map(into_probation))
+    }
+
+    pub async fn quarantined(pool: &SqlitePool) -> Result<Vec<Probation>, sqlx::Error> {
+        Ok(sqlx::query_as(
+            "SELECT ip, count, last_probated, quarantined FROM probation WHERE quarantined = TRUE ORDER BY ip",
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(into_probation)
+        .collect())
+    }
+
     pub async fn delete<'a, E>(conn: E, ip: i64) -> Result<(), sqlx::Error>
     where
         E: sqlx::Executor<'a, Database = Sqlite>,
@@ -426,21 +1356,35 @@ mod util {
             }))
     }
 
-    /// return a count of all rows where leased & probation & un-expired
-    pub async fn count(
+    /// count un-expired rows between start & end, grouped by state
+    pub async fn count_range(
         pool: &SqlitePool,
-        leased: bool,
-        probation: bool,
-        expires_at: i64,
-    ) -> Result<usize, sqlx::Error> {
-        Ok(sqlx::query_scalar!(
-            "SELECT COUNT(ip) as count_ip FROM leases WHERE leased = ?1 AND probation = ?2 AND expires_at > ?3",
-            leased,
-            probation,
-            expires_at
+        start_ip: i64,
+        end_ip: i64,
+        now: i64,
+    ) -> Result<LeaseCounts, sqlx::Error> {
+        let rows: Vec<(bool, bool, i64)> = sqlx::query_as(
+            "SELECT leased, probation, COUNT(ip) FROM leases WHERE ip >= ?1 AND ip <= ?2 AND expires_at > ?3 GROUP BY leased, probation",
         )
-        .fetch_one(pool)
-        .await? as usize)
+        .bind(start_ip)
+        .bind(end_ip)
+        .bind(now)
+        .fetch_all(pool)
+        .await?;
+        Ok(rows.into_iter().fold(
+            LeaseCounts::default(),
+            |mut counts, (leased, probation, n)| {
+                let n = n as usize;
+                if leased {
+                    counts.leased += n;
+                } else if probation {
+                    counts.probated += n;
+                } else {
+                    counts.reserved += n;
+                }
+                counts
+            },
+        ))
     }
 
     /// return the info for this client_id and if it's un-expired
@@ -464,8 +1408,9 @@ mod util {
         .map(|cur| IpAddr::V4(Ipv4Addr::from(cur.ip as u32))))
     }
 
-    /// returns the first expired IP in a range, or where the id matches
-    /// expires_at can refer to IPs under probation
+    /// returns the first expired IP in a range, or where the id matches in `network`.
+    /// The client's entries in other networks are left to the `multi_network_leases`
+    /// policy. expires_at can refer to IPs under probation
     pub async fn update_next_expired<'a, E>(
         conn: E,
         // select
@@ -473,6 +1418,7 @@ mod util {
         id: &[u8],
         start_ip: i64,
         end_ip: i64,
+        network: i64,
         // update
         expires_at: i64,
         leased: bool,
@@ -482,7 +1428,7 @@ mod util {
     {
         // leased = false -> we got a discover but not yet ACK'd
         // leased = true -> we have ACK'd
-        Ok(sqlx::query!(
+        Ok(sqlx::query_scalar::<_, i64>(
             r#"
             UPDATE leases
             SET
@@ -492,21 +1438,23 @@ mod util {
                    SELECT ip
                     FROM leases
                     WHERE
-                        ((expires_at < ?1) AND (ip >= ?2 AND ip <= ?3)) OR (client_id = ?4)
+                        ((expires_at < ?1) AND (ip >= ?2 AND ip <= ?3))
+                        OR (client_id = ?4 AND network = ?7)
                     ORDER BY ip LIMIT 1
                 )
             RETURNING ip
             "#,
-            now,
-            start_ip,
-            end_ip,
-            id,
-            leased,
-            expires_at,
         )
+        .bind(now)
+        .bind(start_ip)
+        .bind(end_ip)
+        .bind(id)
+        .bind(leased)
+        .bind(expires_at)
+        .bind(network)
         .fetch_optional(conn)
         .await?
-        .map(|cur| IpAddr::V4(Ipv4Addr::from(cur.ip as u32))))
+        .map(|ip| IpAddr::V4(Ipv4Addr::from(ip as u32))))
     }
 
     /// updates an entry if the ip & id match and not expired
@@ -630,12 +1578,13 @@ mod util {
         }))
     }
 
-    /// get the next IP between start and end, skipping any exclusions
-    pub fn inc_ip(start: IpAddr, end: IpAddr, exclusions: &HashSet<Ipv4Addr>) -> Option<IpAddr> {
+    /// get the next IP after start and up to end, skipping any exclusions
+    pub fn inc_ip(start: IpAddr, end: IpAddr, exclusions: &Exclusions) -> Option<IpAddr> {
         match (start, end) {
             (IpAddr::V4(ip), IpAddr::V4(end)) => {
-                NetRangeIter::new(ipnet::Ipv4AddrRange::new(ip, end), exclusions)
-                    .nth(1)
+                let next = Ipv4Addr::from(u32::from(ip).checked_add(1)?);
+                NetRangeIter::new(next..=end, exclusions)
+                    .next()
                     .map(|ip| ip.into())
             }
             (IpAddr::V6(ip), IpAddr::V6(end)) => {
@@ -645,7 +1594,7 @@ mod util {
             _ => None,
         }
     }
-    fn into_clientinfo(info: ClientInfo, leased: bool, probation: bool) -> State {
+    pub fn into_clientinfo(info: ClientInfo, leased: bool, probation: bool) -> State {
         if leased {
             State::Leased(info)
         } else if probation {
@@ -694,3 +1643,35 @@ mod util {
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_cancelled_tx_rolls_back() -> Result<()> {
+        // a file, in-memory databases use a shared cache which doesn't wait on locks
+        let dir = std::env::temp_dir().join(format!("dora-tx-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let db = SqliteDb::new(format!("sqlite://{}", dir.join("leases.db").display())).await?;
+        let ip = Ipv4Addr::new(192, 168, 0, 150);
+
+        let cancelled = tokio::time::timeout(Duration::from_millis(50), async {
+            let mut tx = ImmediateTx::begin(&db.inner).await?;
+            util::insert(tx.conn(), u32::from(ip) as i64, 0, &[1], 0, None).await?;
+            // ex. the request's budget runs out
+            std::future::pending::<()>().await;
+            Ok::<_, sqlx::Error>(())
+        })
+        .await;
+        assert!(cancelled.is_err());
+
+        // the write lock was released & the insert undone
+        db.check_writable().await?;
+        assert_eq!(db.get(IpAddr::V4(ip)).await?, None);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,451 @@
+//! # BER
+//!
+//! The subset of BER that SNMPv2c messages use: definite lengths, the universal
+//! INTEGER, OCTET STRING, NULL, OID & SEQUENCE types and the SNMP application types.
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_ID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const IP_ADDRESS: u8 = 0x40;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIMETICKS: u8 = 0x43;
+const OPAQUE: u8 = 0x44;
+const COUNTER64: u8 = 0x46;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const NO_SUCH_INSTANCE: u8 = 0x81;
+const END_OF_MIB_VIEW: u8 = 0x82;
+
+pub(crate) const GET_REQUEST: u8 = 0xa0;
+pub(crate) const GET_NEXT_REQUEST: u8 = 0xa1;
+pub(crate) const RESPONSE: u8 = 0xa2;
+pub(crate) const SET_REQUEST: u8 = 0xa3;
+pub(crate) const GET_BULK_REQUEST: u8 = 0xa5;
+
+/// an error decoding a message
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum BerError {
+    #[error("message is truncated")]
+    Truncated,
+    #[error("expected tag {expected:#04x}, found {found:#04x}")]
+    Tag { expected: u8, found: u8 },
+    #[error("unsupported length encoding")]
+    Length,
+    #[error("integer out of range")]
+    Integer,
+    #[error("invalid object identifier")]
+    Oid,
+    #[error("unsupported value type {0:#04x}")]
+    Value(u8),
+}
+
+/// an object identifier, ordered the way GETNEXT walks them
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Oid(pub Vec<u32>);
+
+impl Oid {
+    /// this OID followed by `arcs`
+    pub fn join(&self, arcs: &[u32]) -> Self {
+        let mut oid = self.0.clone();
+        oid.extend_from_slice(arcs);
+        Self(oid)
+    }
+
+    pub fn starts_with(&self, prefix: &Oid) -> bool {
+        self.0.starts_with(&prefix.0)
+    }
+}
+
+impl FromStr for Oid {
+    type Err = BerError;
+
+    /// dotted decimal, ex. `1.3.6.1.4.1`. A leading `.` is allowed
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let arcs = s
+            .strip_prefix('.')
+            .unwrap_or(s)
+            .split('.')
+            .map(|arc| arc.parse().map_err(|_| BerError::Oid))
+            .collect::<Result<Vec<u32>, _>>()?;
+        // the first two arcs are encoded together
+        match arcs[..] {
+            [0 | 1, second, ..] if second < 40 => Ok(Self(arcs)),
+            [2, _, ..] => Ok(Self(arcs)),
+            _ => Err(BerError::Oid),
+        }
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arcs = self.0.iter().map(|arc| arc.to_string()).collect::<Vec<_>>();
+        write!(f, "{}", arcs.join("."))
+    }
+}
+
+/// the value of a variable binding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Value {
+    Integer(i64),
+    OctetString(Vec<u8>),
+    Null,
+    Oid(Oid),
+    Gauge32(u32),
+    TimeTicks(u32),
+    Counter64(u64),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+impl Value {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Integer(n) => tlv(out, INTEGER, &signed(*n)),
+            Value::OctetString(bytes) => tlv(out, OCTET_STRING, bytes),
+            Value::Null => tlv(out, NULL, &[]),
+            Value::Oid(oid) => tlv(out, OBJECT_ID, &encode_oid(oid)),
+            Value::Gauge32(n) => tlv(out, GAUGE32, &unsigned(*n as u64)),
+            Value::TimeTicks(n) => tlv(out, TIMETICKS, &unsigned(*n as u64)),
+            Value::Counter64(n) => tlv(out, COUNTER64, &unsigned(*n)),
+            Value::NoSuchObject => tlv(out, NO_SUCH_OBJECT, &[]),
+            Value::NoSuchInstance => tlv(out, NO_SUCH_INSTANCE, &[]),
+            Value::EndOfMibView => tlv(out, END_OF_MIB_VIEW, &[]),
+        }
+    }
+
+    fn decode(tag: u8, content: &[u8]) -> Result<Self, BerError> {
+        Ok(match tag {
+            INTEGER => Value::Integer(decode_int(content)?),
+            OCTET_STRING => Value::OctetString(content.to_vec()),
+            NULL => Value::Null,
+            OBJECT_ID => Value::Oid(decode_oid(content)?),
+            GAUGE32 => Value::Gauge32(decode_uint(content, 4)? as u32),
+            TIMETICKS => Value::TimeTicks(decode_uint(content, 4)? as u32),
+            COUNTER64 => Value::Counter64(decode_uint(content, 8)?),
+            NO_SUCH_OBJECT => Value::NoSuchObject,
+            NO_SUCH_INSTANCE => Value::NoSuchInstance,
+            END_OF_MIB_VIEW => Value::EndOfMibView,
+            // read only agent, values in requests aren't used. They're kept as their
+            // encoded bytes so a SET can still be answered
+            IP_ADDRESS | COUNTER32 | OPAQUE => Value::OctetString(content.to_vec()),
+            tag => return Err(BerError::Value(tag)),
+        })
+    }
+}
+
+/// an SNMP message, `kind` is the PDU tag. For GETBULK `error_status` is non-repeaters
+/// & `error_index` max-repetitions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Message {
+    pub(crate) version: i64,
+    pub(crate) community: Vec<u8>,
+    pub(crate) kind: u8,
+    pub(crate) request_id: i64,
+    pub(crate) error_status: i64,
+    pub(crate) error_index: i64,
+    pub(crate) varbinds: Vec<(Oid, Value)>,
+}
+
+impl Message {
+    pub(crate) fn decode(buf: &[u8]) -> Result<Self, BerError> {
+        let mut msg = Reader::new(Reader::new(buf).expect(SEQUENCE)?);
+        let version = decode_int(msg.expect(INTEGER)?)?;
+        let community = msg.expect(OCTET_STRING)?.to_vec();
+        let (kind, pdu) = msg.next()?;
+        let mut pdu = Reader::new(pdu);
+        let request_id = decode_int(pdu.expect(INTEGER)?)?;
+        let error_status = decode_int(pdu.expect(INTEGER)?)?;
+        let error_index = decode_int(pdu.expect(INTEGER)?)?;
+        let mut list = Reader::new(pdu.expect(SEQUENCE)?);
+        let mut varbinds = Vec::new();
+        while !list.is_empty() {
+            let mut varbind = Reader::new(list.expect(SEQUENCE)?);
+            let oid = decode_oid(varbind.expect(OBJECT_ID)?)?;
+            let (tag, value) = varbind.next()?;
+            varbinds.push((oid, Value::decode(tag, value)?));
+        }
+        Ok(Self {
+            version,
+            community,
+            kind,
+            request_id,
+            error_status,
+            error_index,
+            varbinds,
+        })
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut list = Vec::new();
+        for (oid, value) in &self.varbinds {
+            let mut varbind = Vec::new();
+            tlv(&mut varbind, OBJECT_ID, &encode_oid(oid));
+            value.encode(&mut varbind);
+            tlv(&mut list, SEQUENCE, &varbind);
+        }
+        let mut pdu = Vec::new();
+        tlv(&mut pdu, INTEGER, &signed(self.request_id));
+        tlv(&mut pdu, INTEGER, &signed(self.error_status));
+        tlv(&mut pdu, INTEGER, &signed(self.error_index));
+        tlv(&mut pdu, SEQUENCE, &list);
+
+        let mut msg = Vec::new();
+        tlv(&mut msg, INTEGER, &signed(self.version));
+        tlv(&mut msg, OCTET_STRING, &self.community);
+        tlv(&mut msg, self.kind, &pdu);
+        let mut out = Vec::with_capacity(msg.len() + 4);
+        tlv(&mut out, SEQUENCE, &msg);
+        out
+    }
+}
+
+/// reads tag-length-value triples out of a buffer
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// the next tag & its content
+    fn next(&mut self) -> Result<(u8, &'a [u8]), BerError> {
+        let [tag, first, rest @ ..] = self.buf else {
+            return Err(BerError::Truncated);
+        };
+        let (len, rest) = if first & 0x80 == 0 {
+            (*first as usize, rest)
+        } else {
+            let n = (first & 0x7f) as usize;
+            if n == 0 || n > 4 {
+                return Err(BerError::Length);
+            }
+            if rest.len() < n {
+                return Err(BerError::Truncated);
+            }
+            let len = rest[..n]
+                .iter()
+                .fold(0usize, |len, b| (len << 8) | *b as usize);
+            (len, &rest[n..])
+        };
+        if rest.len() < len {
+            return Err(BerError::Truncated);
+        }
+        let (content, rest) = rest.split_at(len);
+        self.buf = rest;
+        Ok((*tag, content))
+    }
+
+    /// the content of the next tag, which must be `tag`
+    fn expect(&mut self, expected: u8) -> Result<&'a [u8], BerError> {
+        match self.next()? {
+            (found, content) if found == expected => Ok(content),
+            (found, _) => Err(BerError::Tag { expected, found }),
+        }
+    }
+}
+
+fn tlv(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+    out.push(tag);
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+}
+
+/// two's complement in the fewest bytes
+fn signed(n: i64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let mut start = 0;
+    while start < bytes.len() - 1 {
+        let redundant = (bytes[start] == 0 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0);
+        if !redundant {
+            break;
+        }
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+/// unsigned types are still INTEGERs, a leading 0 is kept when the top bit is set
+fn unsigned(n: u64) -> Vec<u8> {
+    let mut bytes = vec![0];
+    bytes.extend_from_slice(&n.to_be_bytes());
+    let skip = bytes
+        .windows(2)
+        .take_while(|w| w[0] == 0 && w[1] & 0x80 == 0)
+        .count();
+    bytes[skip..].to_vec()
+}
+
+fn decode_int(content: &[u8]) -> Result<i64, BerError> {
+    if content.is_empty() || content.len() > 8 {
+        return Err(BerError::Integer);
+    }
+    let init = if content[0] & 0x80 != 0 { -1 } else { 0 };
+    Ok(content.iter().fold(init, |n: i64, b| (n << 8) | *b as i64))
+}
+
+/// an unsigned value that fits in `size` bytes
+fn decode_uint(content: &[u8], size: usize) -> Result<u64, BerError> {
+    let content = match content {
+        [0, rest @ ..] => rest,
+        _ => content,
+    };
+    if content.len() > size || content.first().is_some_and(|b| b & 0x80 != 0) {
+        return Err(BerError::Integer);
+    }
+    Ok(content.iter().fold(0, |n: u64, b| (n << 8) | *b as u64))
+}
+
+fn encode_oid(oid: &Oid) -> Vec<u8> {
+    let mut out = Vec::new();
+    let (first, rest) = match &oid.0[..] {
+        [a, b, rest @ ..] => (a * 40 + b, rest),
+        [a] => (a * 40, &[][..]),
+        [] => (0, &[][..]),
+    };
+    for arc in std::iter::once(first).chain(rest.iter().copied()) {
+        let mut shift = 28;
+        while shift > 0 && arc >> shift == 0 {
+            shift -= 7;
+        }
+        while shift > 0 {
+            out.push(0x80 | (arc >> shift) as u8);
+            shift -= 7;
+        }
+        out.push((arc & 0x7f) as u8);
+    }
+    out
+}
+
+fn decode_oid(content: &[u8]) -> Result<Oid, BerError> {
+    let mut arcs = Vec::new();
+    let mut arc: u32 = 0;
+    for (i, b) in content.iter().enumerate() {
+        if arc > u32::MAX >> 7 {
+            return Err(BerError::Oid);
+        }
+        arc = (arc << 7) | (b & 0x7f) as u32;
+        if b & 0x80 != 0 {
+            if i == content.len() - 1 {
+                return Err(BerError::Oid);
+            }
+            continue;
+        }
+        if arcs.is_empty() {
+            let first = (arc / 40).min(2);
+            arcs.push(first);
+            arcs.push(arc - first * 40);
+        } else {
+            arcs.push(arc);
+        }
+        arc = 0;
+    }
+    if arcs.is_empty() {
+        return Err(BerError::Oid);
+    }
+    Ok(Oid(arcs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integers() {
+        for (n, bytes) in [
+            (0, &[0x00][..]),
+            (127, &[0x7f]),
+            (128, &[0x00, 0x80]),
+            (-1, &[0xff]),
+            (-129, &[0xff, 0x7f]),
+            (1 << 31, &[0x00, 0x80, 0x00, 0x00, 0x00]),
+        ] {
+            assert_eq!(signed(n), bytes);
+            assert_eq!(decode_int(bytes).unwrap(), n);
+        }
+        assert_eq!(unsigned(0), [0]);
+        assert_eq!(unsigned(u32::MAX as u64), [0x00, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(decode_uint(&unsigned(u64::MAX), 8).unwrap(), u64::MAX);
+        assert!(decode_uint(&unsigned(u64::MAX), 4).is_err());
+    }
+
+    #[test]
+    fn test_oid() {
+        let oid: Oid = "1.3.6.1.4.1.13315.100.1".parse().unwrap();
+        let encoded = encode_oid(&oid);
+        assert_eq!(
+            encoded,
+            [0x2b, 0x06, 0x01, 0x04, 0x01, 0xe8, 0x03, 0x64, 0x01]
+        );
+        assert_eq!(decode_oid(&encoded).unwrap(), oid);
+        assert_eq!(oid.to_string(), "1.3.6.1.4.1.13315.100.1");
+        assert_eq!(".1.3.6".parse::<Oid>().unwrap(), Oid(vec![1, 3, 6]));
+        assert!("1.3.x".parse::<Oid>().is_err());
+        assert!("3.1".parse::<Oid>().is_err());
+        // GETNEXT order
+        assert!(Oid(vec![1, 3, 6, 1]) < Oid(vec![1, 3, 6, 1, 0]));
+        assert!(Oid(vec![1, 3, 6, 1, 9]) < Oid(vec![1, 3, 6, 2]));
+    }
+
+    #[test]
+    fn test_message() {
+        // snmpget -v2c -c public localhost 1.3.6.1.2.1.1.3.0
+        let req = [
+            0x30, 0x29, 0x02, 0x01, 0x01, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', 0xa0,
+            0x1c, 0x02, 0x04, 0x12, 0x34, 0x56, 0x78, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30,
+            0x0e, 0x30, 0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x03, 0x00, 0x05,
+            0x00,
+        ];
+        let msg = Message::decode(&req).unwrap();
+        assert_eq!(msg.version, 1);
+        assert_eq!(msg.community, b"public");
+        assert_eq!(msg.kind, GET_REQUEST);
+        assert_eq!(msg.request_id, 0x1234_5678);
+        assert_eq!(
+            msg.varbinds,
+            [("1.3.6.1.2.1.1.3.0".parse().unwrap(), Value::Null)]
+        );
+        assert_eq!(msg.encode(), req);
+
+        // long lengths & every value type survive a round trip
+        let resp = Message {
+            kind: RESPONSE,
+            varbinds: vec![
+                (Oid(vec![1, 3, 1]), Value::OctetString(vec![b'a'; 300])),
+                (Oid(vec![1, 3, 2]), Value::Integer(-5)),
+                (Oid(vec![1, 3, 3]), Value::Oid(Oid(vec![1, 3, 6, 1]))),
+                (Oid(vec![1, 3, 4]), Value::Gauge32(u32::MAX)),
+                (Oid(vec![1, 3, 5]), Value::TimeTicks(100)),
+                (Oid(vec![1, 3, 6]), Value::Counter64(u64::MAX)),
+                (Oid(vec![1, 3, 7]), Value::NoSuchInstance),
+                (Oid(vec![1, 3, 8]), Value::EndOfMibView),
+            ],
+            ..msg
+        };
+        assert_eq!(Message::decode(&resp.encode()).unwrap(), resp);
+        assert_eq!(
+            Message::decode(&req[..req.len() - 1]),
+            Err(BerError::Truncated)
+        );
+    }
+}
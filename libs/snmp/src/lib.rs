@@ -0,0 +1,382 @@
+//! # SNMP
+//!
+//! A read-only SNMPv2c agent for network management systems that can only poll SNMP.
+//! Values are read from the prometheus registry for each request, so they're the same
+//! ones `/metrics` returns. GET, GETNEXT & GETBULK are answered, SETs are refused with
+//! `notWritable`. Requests with another version or community are dropped.
+//!
+//! Under the base OID (`1.3.6.1.4.1.13315.100.1` unless it's configured):
+//!
+//! - `<base>.1.1.<column>.<a>.<b>.<c>.<d>.<prefix>` is a row per network from the
+//!   `lease_count` gauges, indexed by the network's address & prefix length. Columns are
+//!   1 network, 2 leased, 3 reserved, 4 probated, 5 free & 6 percent of addresses in use
+//! - `<base>.2.1.<column>.<index>` is a row per counter & gauge series, histograms by
+//!   their sample count. Column 1 is the series in prometheus text format, ex.
+//!   `sent_type_counts{message_type="Offer"}`, column 2 its value as a `Counter64` or
+//!   `Gauge32`. The index is a 32-bit FNV-1a hash of the series, so it's the same across
+//!   restarts
+//!
+//! `sysDescr`, `sysObjectID` (the base OID) & `sysUpTime` are answered too, so the agent
+//! can be discovered.
+#![warn(
+    missing_debug_implementations,
+    rust_2018_idioms,
+    unreachable_pub,
+    non_snake_case,
+    non_upper_case_globals
+)]
+use std::{
+    io,
+    net::SocketAddr,
+    ops::Bound::{Excluded, Unbounded},
+};
+
+use dora_core::{
+    metrics::START_TIME,
+    tokio::net::UdpSocket,
+    tracing::{debug, warn},
+};
+use prometheus::proto::MetricFamily;
+
+mod ber;
+mod mib;
+
+pub use crate::ber::{BerError, Oid};
+
+use crate::{
+    ber::{Message, Value, GET_BULK_REQUEST, GET_NEXT_REQUEST, GET_REQUEST, RESPONSE, SET_REQUEST},
+    mib::Mib,
+};
+
+/// the `version` field of an SNMPv2c message
+const VERSION_2C: i64 = 1;
+/// largest UDP payload, a response that doesn't fit is answered with `tooBig`
+const MAX_MSG_SIZE: usize = 65_507;
+/// most variable bindings a GETBULK is answered with, regardless of max-repetitions
+const MAX_BULK_VARBINDS: usize = 100;
+const TOO_BIG: i64 = 1;
+const NOT_WRITABLE: i64 = 17;
+
+/// SNMPv2c agent answering from the prometheus registry
+#[derive(Debug)]
+pub struct Agent {
+    socket: std::net::UdpSocket,
+    handler: Handler,
+}
+
+impl Agent {
+    /// bind the agent's socket, nothing is answered until it's [`served`]
+    ///
+    /// [`served`]: Agent::serve
+    pub fn bind(addr: SocketAddr, community: impl Into<Vec<u8>>, base: Oid) -> io::Result<Self> {
+        Ok(Self {
+            socket: std::net::UdpSocket::bind(addr)?,
+            handler: Handler {
+                community: community.into(),
+                base,
+            },
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// answer requests until the task is dropped
+    pub async fn serve(self) -> io::Result<()> {
+        self.socket.set_nonblocking(true)?;
+        let socket = UdpSocket::from_std(self.socket)?;
+        let mut buf = vec![0; u16::MAX as usize];
+        loop {
+            let (len, peer) = match socket.recv_from(&mut buf).await {
+                Ok(recv) => recv,
+                Err(err) => {
+                    warn!(?err, "failed to receive snmp request");
+                    continue;
+                }
+            };
+            let Some(resp) = self.handler.respond(&buf[..len], prometheus::gather) else {
+                continue;
+            };
+            if let Err(err) = socket.send_to(&resp, peer).await {
+                warn!(?err, ?peer, "failed to send snmp response");
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Handler {
+    community: Vec<u8>,
+    base: Oid,
+}
+
+impl Handler {
+    /// the encoded response to `buf`, `None` if it's dropped. `gather` is only called
+    /// once the community has been checked
+    fn respond(&self, buf: &[u8], gather: impl FnOnce() -> Vec<MetricFamily>) -> Option<Vec<u8>> {
+        let req = match Message::decode(buf) {
+            Ok(req) => req,
+            Err(err) => {
+                debug!(?err, "dropping malformed snmp message");
+                return None;
+            }
+        };
+        if req.version != VERSION_2C {
+            debug!(
+                version = req.version,
+                "dropping snmp message, only v2c is supported"
+            );
+            return None;
+        }
+        if req.community != self.community {
+            debug!("dropping snmp message with unknown community");
+            return None;
+        }
+        let mib = || mib::build(&self.base, &gather(), START_TIME.elapsed());
+        let (error_status, error_index, varbinds) = match req.kind {
+            GET_REQUEST => {
+                let mib = mib();
+                let varbinds = req
+                    .varbinds
+                    .iter()
+                    .map(|(oid, _)| (oid.clone(), get(&mib, oid)))
+                    .collect();
+                (0, 0, varbinds)
+            }
+            GET_NEXT_REQUEST => {
+                let mib = mib();
+                let varbinds = req
+                    .varbinds
+                    .iter()
+                    .map(|(oid, _)| next(&mib, oid))
+                    .collect();
+                (0, 0, varbinds)
+            }
+            GET_BULK_REQUEST => {
+                let mib = mib();
+                let varbinds = bulk(&mib, &req.varbinds, req.error_status, req.error_index);
+                (0, 0, varbinds)
+            }
+            SET_REQUEST => (NOT_WRITABLE, 1, req.varbinds.clone()),
+            kind => {
+                debug!(kind, "dropping unsupported snmp pdu");
+                return None;
+            }
+        };
+        let mut resp = Message {
+            kind: RESPONSE,
+            error_status,
+            error_index,
+            varbinds,
+            ..req
+        };
+        let mut out = resp.encode();
+        if out.len() > MAX_MSG_SIZE {
+            resp.error_status = TOO_BIG;
+            resp.error_index = 0;
+            resp.varbinds.clear();
+            out = resp.encode();
+        }
+        Some(out)
+    }
+}
+
+/// the value of `oid`, `noSuchInstance` if it's in a table or scalar that exists
+fn get(mib: &Mib, oid: &Oid) -> Value {
+    if let Some(value) = mib.get(oid) {
+        return value.clone();
+    }
+    let parent = Oid(oid.0[..oid.0.len().saturating_sub(1)].to_vec());
+    match mib.range(&parent..).next() {
+        Some((next, _)) if next.starts_with(&parent) => Value::NoSuchInstance,
+        _ => Value::NoSuchObject,
+    }
+}
+
+/// the first OID after `oid` & its value
+fn next(mib: &Mib, oid: &Oid) -> (Oid, Value) {
+    mib.range((Excluded(oid), Unbounded))
+        .next()
+        .map(|(oid, value)| (oid.clone(), value.clone()))
+        .unwrap_or_else(|| (oid.clone(), Value::EndOfMibView))
+}
+
+/// a GETNEXT for the first `non_repeaters` bindings, then up to `max_repetitions`
+/// for the rest, each continuing from the last
+fn bulk(
+    mib: &Mib,
+    varbinds: &[(Oid, Value)],
+    non_repeaters: i64,
+    max_repetitions: i64,
+) -> Vec<(Oid, Value)> {
+    let non_repeaters = (non_repeaters.max(0) as usize).min(varbinds.len());
+    let (single, repeated) = varbinds.split_at(non_repeaters);
+    let mut out = single
+        .iter()
+        .map(|(oid, _)| next(mib, oid))
+        .collect::<Vec<_>>();
+    let mut last = repeated
+        .iter()
+        .map(|(oid, _)| oid.clone())
+        .collect::<Vec<_>>();
+    for _ in 0..max_repetitions.max(0) {
+        if last.is_empty() || out.len() + last.len() > MAX_BULK_VARBINDS {
+            break;
+        }
+        let row = last.iter().map(|oid| next(mib, oid)).collect::<Vec<_>>();
+        let done = row.iter().all(|(_, value)| *value == Value::EndOfMibView);
+        last = row.iter().map(|(oid, _)| oid.clone()).collect();
+        out.extend(row);
+        if done {
+            break;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use dora_core::config::cli::DEFAULT_SNMP_BASE_OID;
+    use prometheus::{IntCounter, Registry};
+
+    use super::*;
+
+    fn handler() -> Handler {
+        Handler {
+            community: b"public".to_vec(),
+            base: DEFAULT_SNMP_BASE_OID.parse().unwrap(),
+        }
+    }
+
+    fn families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        for (name, n) in [("a_count", 1), ("b_count", 2)] {
+            let counter = IntCounter::new(name, name).unwrap();
+            counter.inc_by(n);
+            registry.register(Box::new(counter)).unwrap();
+        }
+        registry.gather()
+    }
+
+    fn request(kind: u8, community: &str, oids: &[Oid], bulk: (i64, i64)) -> Message {
+        Message {
+            version: VERSION_2C,
+            community: community.into(),
+            kind,
+            request_id: 42,
+            error_status: bulk.0,
+            error_index: bulk.1,
+            varbinds: oids.iter().map(|oid| (oid.clone(), Value::Null)).collect(),
+        }
+    }
+
+    fn send(handler: &Handler, req: Message) -> Option<Message> {
+        handler
+            .respond(&req.encode(), families)
+            .map(|resp| Message::decode(&resp).unwrap())
+    }
+
+    #[test]
+    fn test_respond() {
+        let handler = handler();
+        let base = handler.base.clone();
+        let uptime: Oid = "1.3.6.1.2.1.1.3.0".parse().unwrap();
+
+        let resp = send(
+            &handler,
+            request(GET_REQUEST, "public", &[uptime.clone()], (0, 0)),
+        )
+        .unwrap();
+        assert_eq!(resp.kind, RESPONSE);
+        assert_eq!(resp.request_id, 42);
+        assert!(matches!(resp.varbinds[..], [(ref oid, Value::TimeTicks(_))] if *oid == uptime));
+        let resp = send(
+            &handler,
+            request(
+                GET_REQUEST,
+                "public",
+                &[uptime.join(&[1]), "1.3.6.1.2.1.2.0".parse().unwrap()],
+                (0, 0),
+            ),
+        )
+        .unwrap();
+        assert_eq!(resp.varbinds[0].1, Value::NoSuchInstance);
+        assert_eq!(resp.varbinds[1].1, Value::NoSuchObject);
+
+        // walk the metric table, names then values
+        let mut oid = base.clone();
+        let mut walked = Vec::new();
+        loop {
+            let resp = send(
+                &handler,
+                request(GET_NEXT_REQUEST, "public", &[oid], (0, 0)),
+            )
+            .unwrap();
+            let (next, value) = resp.varbinds.into_iter().next().unwrap();
+            if value == Value::EndOfMibView {
+                break;
+            }
+            walked.push(value);
+            oid = next;
+        }
+        assert_eq!(walked.len(), 4);
+        assert!(walked.contains(&Value::OctetString(b"a_count".to_vec())));
+        assert!(walked.contains(&Value::Counter64(2)));
+
+        // sysDescr as a non-repeater, then the rest of the view
+        let resp = send(
+            &handler,
+            request(
+                GET_BULK_REQUEST,
+                "public",
+                &["1.3.6.1.2.1.1".parse().unwrap(), base.clone()],
+                (1, 10),
+            ),
+        )
+        .unwrap();
+        assert_eq!(resp.varbinds[0].0, "1.3.6.1.2.1.1.1.0".parse().unwrap());
+        assert_eq!(resp.varbinds.len(), 6);
+        assert_eq!(resp.varbinds[5].1, Value::EndOfMibView);
+
+        let resp = send(
+            &handler,
+            request(SET_REQUEST, "public", &[uptime.clone()], (0, 0)),
+        )
+        .unwrap();
+        assert_eq!((resp.error_status, resp.error_index), (NOT_WRITABLE, 1));
+
+        // wrong community & v1 are dropped
+        assert!(send(
+            &handler,
+            request(GET_REQUEST, "private", &[uptime.clone()], (0, 0))
+        )
+        .is_none());
+        let mut v1 = request(GET_REQUEST, "public", &[uptime], (0, 0));
+        v1.version = 0;
+        assert!(send(&handler, v1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let agent = Agent::bind(
+            "127.0.0.1:0".parse().unwrap(),
+            "public",
+            DEFAULT_SNMP_BASE_OID.parse().unwrap(),
+        )
+        .unwrap();
+        let addr = agent.local_addr().unwrap();
+        let task = tokio::spawn(agent.serve());
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let uptime: Oid = "1.3.6.1.2.1.1.3.0".parse().unwrap();
+        let req = request(GET_REQUEST, "public", &[uptime], (0, 0));
+        socket.send_to(&req.encode(), addr).await.unwrap();
+        let mut buf = vec![0; 1500];
+        let len = socket.recv(&mut buf).await.unwrap();
+        let resp = Message::decode(&buf[..len]).unwrap();
+        assert_eq!(resp.request_id, 42);
+        assert!(matches!(resp.varbinds[..], [(_, Value::TimeTicks(_))]));
+        task.abort();
+    }
+}
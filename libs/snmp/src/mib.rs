@@ -0,0 +1,218 @@
+//! # MIB
+//!
+//! Every value the agent answers with, built from a prometheus registry snapshot. The
+//! whole view is rebuilt for each request, GETNEXT & GETBULK walk it in OID order.
+use std::{collections::BTreeMap, time::Duration};
+
+use ipnet::Ipv4Net;
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+
+use crate::ber::{Oid, Value};
+
+/// system group of SNMPv2-MIB
+const SYSTEM: &[u32] = &[1, 3, 6, 1, 2, 1, 1];
+/// `<base>.1.1` pool utilization table
+const POOL_ENTRY: &[u32] = &[1, 1];
+/// `<base>.2.1` metric table
+const METRIC_ENTRY: &[u32] = &[2, 1];
+/// states of the `lease_count` gauge, in column order
+const LEASE_STATES: [&str; 4] = ["leased", "reserved", "probated", "free"];
+
+/// OIDs & their values, in GETNEXT order
+pub(crate) type Mib = BTreeMap<Oid, Value>;
+
+/// the view of `families` under `base`. `uptime` is reported as `sysUpTime`
+pub(crate) fn build(base: &Oid, families: &[MetricFamily], uptime: Duration) -> Mib {
+    let mut mib = Mib::new();
+    let system = Oid(SYSTEM.to_vec());
+    mib.insert(
+        system.join(&[1, 0]),
+        Value::OctetString(format!("dora DHCP server {}", env!("CARGO_PKG_VERSION")).into()),
+    );
+    mib.insert(system.join(&[2, 0]), Value::Oid(base.clone()));
+    mib.insert(
+        system.join(&[3, 0]),
+        Value::TimeTicks((uptime.as_millis() / 10).min(u32::MAX as u128) as u32),
+    );
+    pools(&mut mib, &base.join(POOL_ENTRY), families);
+    metrics(&mut mib, &base.join(METRIC_ENTRY), families);
+    mib
+}
+
+/// one row per network of `lease_count`, indexed by the network's address & prefix
+fn pools(mib: &mut Mib, entry: &Oid, families: &[MetricFamily]) {
+    let Some(family) = families.iter().find(|f| f.get_name() == "lease_count") else {
+        return;
+    };
+    let mut networks: BTreeMap<Ipv4Net, [u64; 4]> = BTreeMap::new();
+    for metric in family.get_metric() {
+        let (Some(network), Some(state)) = (label(metric, "network"), label(metric, "state"))
+        else {
+            continue;
+        };
+        let (Ok(network), Some(i)) = (
+            network.parse::<Ipv4Net>(),
+            LEASE_STATES.iter().position(|s| *s == state),
+        ) else {
+            continue;
+        };
+        networks.entry(network).or_default()[i] = metric.get_gauge().get_value().max(0.) as u64;
+    }
+    for (network, counts) in networks {
+        let [a, b, c, d] = network.addr().octets();
+        let index = [
+            a as u32,
+            b as u32,
+            c as u32,
+            d as u32,
+            network.prefix_len() as u32,
+        ];
+        let total: u64 = counts.iter().sum();
+        let free = counts[3];
+        let used_pct = if total == 0 {
+            0
+        } else {
+            (total - free) * 100 / total
+        };
+        let column = |col: u32| {
+            let mut oid = entry.join(&[col]);
+            oid.0.extend_from_slice(&index);
+            oid
+        };
+        mib.insert(column(1), Value::OctetString(network.to_string().into()));
+        for (col, count) in (2..).zip(counts) {
+            mib.insert(column(col), gauge(count as f64));
+        }
+        mib.insert(column(6), Value::Gauge32(used_pct as u32));
+    }
+}
+
+/// one row per counter & gauge series, histograms & summaries by their sample count
+fn metrics(mib: &mut Mib, entry: &Oid, families: &[MetricFamily]) {
+    for family in families {
+        for metric in family.get_metric() {
+            let (name, value) = match family.get_field_type() {
+                MetricType::COUNTER => (
+                    family.get_name().to_owned(),
+                    Value::Counter64(metric.get_counter().get_value().max(0.) as u64),
+                ),
+                MetricType::GAUGE => (
+                    family.get_name().to_owned(),
+                    gauge(metric.get_gauge().get_value()),
+                ),
+                MetricType::UNTYPED => (
+                    family.get_name().to_owned(),
+                    gauge(metric.get_untyped().get_value()),
+                ),
+                MetricType::HISTOGRAM => (
+                    format!("{}_count", family.get_name()),
+                    Value::Counter64(metric.get_histogram().get_sample_count()),
+                ),
+                MetricType::SUMMARY => (
+                    format!("{}_count", family.get_name()),
+                    Value::Counter64(metric.get_summary().get_sample_count()),
+                ),
+            };
+            let series = series(&name, metric);
+            let index = fnv1a(series.as_bytes());
+            let name_oid = entry.join(&[1, index]);
+            // the first series with an index keeps it
+            if mib.contains_key(&name_oid) {
+                continue;
+            }
+            mib.insert(name_oid, Value::OctetString(series.into()));
+            mib.insert(entry.join(&[2, index]), value);
+        }
+    }
+}
+
+/// the series in prometheus text format, ex. `sent_type_counts{message_type="Offer"}`
+fn series(name: &str, metric: &Metric) -> String {
+    let labels = metric.get_label();
+    if labels.is_empty() {
+        return name.to_owned();
+    }
+    let labels = labels
+        .iter()
+        .map(|l| format!("{}={:?}", l.get_name(), l.get_value()))
+        .collect::<Vec<_>>();
+    format!("{name}{{{}}}", labels.join(","))
+}
+
+/// 32-bit FNV-1a, the metric table's index. It doesn't depend on which other series
+/// exist, so a series keeps its index across restarts
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash: u32, b| {
+        (hash ^ *b as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn label<'a>(metric: &'a Metric, name: &str) -> Option<&'a str> {
+    metric
+        .get_label()
+        .iter()
+        .find(|l| l.get_name() == name)
+        .map(|l| l.get_value())
+}
+
+/// gauges are unsigned, negative values are reported as 0
+fn gauge(value: f64) -> Value {
+    Value::Gauge32(value.clamp(0., u32::MAX as f64) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{IntCounterVec, IntGaugeVec, Opts, Registry};
+
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let registry = Registry::new();
+        let leases =
+            IntGaugeVec::new(Opts::new("lease_count", "leases"), &["network", "state"]).unwrap();
+        let sent =
+            IntCounterVec::new(Opts::new("sent_type_counts", "sent"), &["message_type"]).unwrap();
+        registry.register(Box::new(leases.clone())).unwrap();
+        registry.register(Box::new(sent.clone())).unwrap();
+        for (state, count) in [
+            ("leased", 60),
+            ("reserved", 10),
+            ("probated", 5),
+            ("free", 25),
+        ] {
+            leases
+                .with_label_values(&["192.168.0.0/24", state])
+                .set(count);
+        }
+        sent.with_label_values(&["Offer"]).inc_by(7);
+
+        let base: Oid = "1.3.6.1.4.1.13315.100.1".parse().unwrap();
+        let mib = build(&base, &registry.gather(), Duration::from_secs(2));
+        assert_eq!(
+            mib[&"1.3.6.1.2.1.1.3.0".parse::<Oid>().unwrap()],
+            Value::TimeTicks(200)
+        );
+
+        let row = |col: u32| base.join(&[1, 1, col, 192, 168, 0, 0, 24]);
+        assert_eq!(mib[&row(1)], Value::OctetString(b"192.168.0.0/24".to_vec()));
+        assert_eq!(mib[&row(2)], Value::Gauge32(60));
+        assert_eq!(mib[&row(5)], Value::Gauge32(25));
+        assert_eq!(mib[&row(6)], Value::Gauge32(75));
+
+        let series = r#"sent_type_counts{message_type="Offer"}"#;
+        let index = fnv1a(series.as_bytes());
+        assert_eq!(
+            mib[&base.join(&[2, 1, 1, index])],
+            Value::OctetString(series.into())
+        );
+        assert_eq!(mib[&base.join(&[2, 1, 2, index])], Value::Counter64(7));
+        // lease_count rows are in the metric table too
+        assert_eq!(
+            mib.keys()
+                .filter(|oid| oid.starts_with(&base.join(&[2, 1, 1])))
+                .count(),
+            5
+        );
+    }
+}
@@ -60,7 +60,7 @@ use std::{
 use thiserror::Error;
 
 /// Used to keep track of # of parents and child nodes in `DependencyTree`
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Node<T> {
     num_parents: usize,
     children: Vec<T>,
@@ -153,6 +153,20 @@ where
             .num_parents = num_parents;
     }
 
+    /// Get the item added at `key`
+    pub fn get(&self, key: &K) -> Option<&T> {
+        self.items.get(key)
+    }
+
+    /// Keys in the order [`topological_sort`] returns their items, without consuming
+    /// the tree. Keys that were only given as a dependency are included.
+    /// A cloned map iterates in the same order, so the two agree
+    ///
+    /// [`topological_sort`]: DependencyTree::topological_sort
+    pub fn sorted_keys(&self) -> Result<Vec<K>, TopoSortError> {
+        Self::_topological_sort(self.dep_tree.clone())
+    }
+
     fn _topological_sort(mut dep_tree: HashMap<K, Node<K>>) -> Result<Vec<K>, TopoSortError> {
         // track visited nodes
         let mut visited = dep_tree.keys().cloned().collect::<HashSet<_>>();
@@ -379,4 +393,39 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn sorted_keys_match_sort() {
+        let mut tree = DependencyTree::new();
+        tree.add(TypeId::of::<A>(), Box::new(A) as Box<dyn Any>, []);
+        tree.add(
+            TypeId::of::<B>(),
+            Box::new(B) as Box<dyn Any>,
+            [TypeId::of::<A>()],
+        );
+        tree.add(
+            TypeId::of::<C>(),
+            Box::new(C) as Box<dyn Any>,
+            [TypeId::of::<A>()],
+        );
+        // E is never added
+        tree.add(
+            TypeId::of::<D>(),
+            Box::new(D) as Box<dyn Any>,
+            [TypeId::of::<B>(), TypeId::of::<E>()],
+        );
+        let keys = tree
+            .sorted_keys()
+            .unwrap()
+            .into_iter()
+            .filter(|key| tree.get(key).is_some())
+            .collect::<Vec<_>>();
+        let sorted = tree
+            .topological_sort()
+            .unwrap()
+            .iter()
+            .map(|item| (**item).type_id())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, sorted);
+    }
 }
@@ -0,0 +1,10 @@
+-- reservations imported at runtime (external api or dora-cfg), these are
+-- used in addition to reservations in the config file. `options` is stored
+-- as JSON in the same format as the config options map
+CREATE TABLE IF NOT EXISTS reservations(
+    ip INTEGER NOT NULL,
+    mac BLOB NOT NULL UNIQUE,
+    hostname TEXT,
+    options TEXT,
+    PRIMARY KEY(ip)
+);
@@ -0,0 +1,9 @@
+-- probation history per IP, used to quarantine IPs that are repeatedly
+-- declined or found in use by ping check. `last_probated` is seconds from epoch
+CREATE TABLE IF NOT EXISTS probation(
+    ip INTEGER NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    last_probated INTEGER NOT NULL,
+    quarantined BOOLEAN NOT NULL DEFAULT 0,
+    PRIMARY KEY(ip)
+);
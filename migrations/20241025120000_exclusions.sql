@@ -0,0 +1,8 @@
+-- v4 exclusions added at runtime through the external api, these are used in
+-- addition to the `except` list of each range in the config. Both ends are
+-- inclusive
+CREATE TABLE IF NOT EXISTS exclusions(
+    start_ip INTEGER NOT NULL,
+    end_ip INTEGER NOT NULL,
+    PRIMARY KEY(start_ip, end_ip)
+);
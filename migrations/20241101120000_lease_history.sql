@@ -0,0 +1,39 @@
+-- append-only history of every change to the leases table, written by triggers
+-- so nothing that touches `leases` can skip it. `removed` rows record the entry
+-- being deleted. `at` & `expires_at` are seconds from epoch. Old rows are pruned
+-- by the retention task, nothing else updates or deletes from this table
+CREATE TABLE IF NOT EXISTS lease_history(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ip INTEGER NOT NULL,
+    client_id BLOB,
+    leased BOOLEAN NOT NULL DEFAULT 0,
+    probation BOOLEAN NOT NULL DEFAULT 0,
+    removed BOOLEAN NOT NULL DEFAULT 0,
+    expires_at INTEGER,
+    at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_lease_history_ip_at ON lease_history (ip, at);
+CREATE INDEX IF NOT EXISTS idx_lease_history_at ON lease_history (at);
+
+CREATE TRIGGER IF NOT EXISTS lease_history_insert AFTER INSERT ON leases
+BEGIN
+    INSERT INTO lease_history (ip, client_id, leased, probation, expires_at, at)
+    VALUES (NEW.ip, NEW.client_id, NEW.leased, NEW.probation, NEW.expires_at, CAST(strftime('%s', 'now') AS INTEGER));
+END;
+
+-- renewals are recorded too, they extend how long the client held the IP
+CREATE TRIGGER IF NOT EXISTS lease_history_update AFTER UPDATE ON leases
+WHEN OLD.client_id IS NOT NEW.client_id
+    OR OLD.leased IS NOT NEW.leased
+    OR OLD.probation IS NOT NEW.probation
+    OR OLD.expires_at IS NOT NEW.expires_at
+BEGIN
+    INSERT INTO lease_history (ip, client_id, leased, probation, expires_at, at)
+    VALUES (NEW.ip, NEW.client_id, NEW.leased, NEW.probation, NEW.expires_at, CAST(strftime('%s', 'now') AS INTEGER));
+END;
+
+CREATE TRIGGER IF NOT EXISTS lease_history_delete AFTER DELETE ON leases
+BEGIN
+    INSERT INTO lease_history (ip, client_id, removed, at)
+    VALUES (OLD.ip, OLD.client_id, 1, CAST(strftime('%s', 'now') AS INTEGER));
+END;
@@ -0,0 +1,9 @@
+-- hostname sent by the client that holds a lease, only recorded when
+-- `host_export` is configured. `client_id` must match the lease entry for the
+-- hostname to be exported, so a name is never exported for the next client
+CREATE TABLE IF NOT EXISTS lease_hostnames(
+    ip INTEGER NOT NULL,
+    client_id BLOB NOT NULL,
+    hostname TEXT NOT NULL,
+    PRIMARY KEY(ip)
+);
@@ -0,0 +1,17 @@
+-- the MAC vendor prefix (OUI) of clients on networks with `oui_limits`,
+-- written at allocation. An entry only counts towards the limit while
+-- `client_id` still matches an un-expired entry in `leases`, so nothing has to
+-- be cleaned up when a lease expires or is handed to another client
+CREATE TABLE IF NOT EXISTS oui_leases(
+    ip INTEGER NOT NULL,
+    network INTEGER NOT NULL,
+    oui BLOB NOT NULL,
+    client_id BLOB NOT NULL,
+    PRIMARY KEY(ip)
+);
+CREATE INDEX IF NOT EXISTS idx_oui_leases_network_oui ON oui_leases (network, oui);
+
+CREATE TRIGGER IF NOT EXISTS oui_leases_delete AFTER DELETE ON leases
+BEGIN
+    DELETE FROM oui_leases WHERE ip = OLD.ip;
+END;
@@ -0,0 +1,9 @@
+-- counters checkpointed when `stats_checkpoint_secs` is set, restored at
+-- startup so the `_total_persistent` metrics survive restarts. `label` is the
+-- value of the metric's single label
+CREATE TABLE IF NOT EXISTS stats(
+    name TEXT NOT NULL,
+    label TEXT NOT NULL,
+    value INTEGER NOT NULL,
+    PRIMARY KEY(name, label)
+);
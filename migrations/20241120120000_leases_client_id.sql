@@ -0,0 +1,3 @@
+-- leases are looked up by client id when a client leases an address, to find
+-- the leases it holds in other networks
+CREATE INDEX IF NOT EXISTS idx_leases_client_id ON leases (client_id);
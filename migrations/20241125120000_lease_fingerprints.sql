@@ -0,0 +1,11 @@
+-- DHCP fingerprint of the client that holds a lease, only recorded when
+-- `store_fingerprints` is configured. `client_id` must match the lease entry,
+-- like lease_hostnames
+CREATE TABLE IF NOT EXISTS lease_fingerprints(
+    ip INTEGER NOT NULL,
+    client_id BLOB NOT NULL,
+    fingerprint TEXT NOT NULL,
+    prl TEXT NOT NULL,
+    vendor_class TEXT,
+    PRIMARY KEY(ip)
+);
@@ -0,0 +1,10 @@
+-- IPv6 addresses registered by SLAAC clients with ADDR-REG-INFORM (RFC 9686).
+-- `ip` is the 16 address bytes, so rows sort by address. `expires_at` is
+-- seconds from epoch, from the valid lifetime the client sent
+CREATE TABLE IF NOT EXISTS addr_registrations(
+    ip BLOB NOT NULL,
+    client_id BLOB NOT NULL,
+    fqdn TEXT,
+    expires_at INTEGER NOT NULL,
+    PRIMARY KEY(ip)
+);
@@ -0,0 +1,12 @@
+-- The schema version of the leases database, a single row written after
+-- migrations run at startup. `version` is the latest migration applied,
+-- `previous` the version before that run, if it changed anything. A dora
+-- that doesn't know `version` refuses to open the database rather than run
+-- against a schema it can't read, see `ip_manager::migrate`
+CREATE TABLE IF NOT EXISTS schema_version(
+    id INTEGER NOT NULL CHECK (id = 0),
+    version INTEGER NOT NULL,
+    previous INTEGER,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY(id)
+);
@@ -0,0 +1,10 @@
+-- how far the change stream has shipped `lease_history`, the id of the last row
+-- written to its sink. A single row, only present once the stream has run. The
+-- stream resumes after it on restart, so a sink can see a change twice but never
+-- miss one that hasn't been pruned
+CREATE TABLE IF NOT EXISTS change_stream(
+    id INTEGER NOT NULL CHECK (id = 0),
+    shipped INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY(id)
+);
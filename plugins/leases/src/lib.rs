@@ -10,14 +10,24 @@
 #![allow(clippy::cognitive_complexity, clippy::too_many_arguments)]
 
 const OFFER_TIME: Duration = Duration::from_secs(60);
+/// times an ip-manager call is made again when storage is busy
+const STORAGE_RETRIES: u32 = 2;
+/// wait before the first retry, it grows with each one
+const STORAGE_RETRY_DELAY: Duration = Duration::from_millis(5);
+/// time budget DDNS leaves for the rest of the plugins & sending the response
+const DDNS_RESERVE: Duration = Duration::from_millis(100);
 
 use std::{
+    collections::HashMap,
     fmt,
+    future::Future,
     net::{IpAddr, Ipv4Addr},
+    sync::Mutex,
     time::{Duration, SystemTime},
 };
 
-use client_protection::RenewThreshold;
+use client_classification::Fingerprint;
+use client_protection::{FloodCache, RenewThreshold};
 use ddns::{dhcid::DhcId, DdnsUpdate};
 use dora_core::{
     anyhow::anyhow,
@@ -25,6 +35,10 @@ use dora_core::{
     dhcproto::v4::{DhcpOption, Message, MessageType, OptionCode},
     metrics,
     prelude::*,
+    tokio::{
+        self,
+        sync::{Mutex as AsyncMutex, OwnedMutexGuard},
+    },
     tracing::warn,
 };
 use message_type::MatchedClasses;
@@ -32,10 +46,10 @@ use register_derive::Register;
 use static_addr::StaticAddr;
 
 use config::{
-    v4::{NetRange, Network},
-    DhcpConfig,
+    v4::{Fill, NetRange, Network, Oui},
+    LiveConfig,
 };
-use ip_manager::{IpError, IpManager, IpState, Storage};
+use ip_manager::{clock, IpError, IpManager, IpState, LeaseFingerprint, Storage, StorageError};
 
 #[derive(Register)]
 #[register(msg(Message))]
@@ -44,10 +58,16 @@ pub struct Leases<S>
 where
     S: Storage,
 {
-    cfg: Arc<DhcpConfig>,
+    cfg: LiveConfig,
     ddns: DdnsUpdate,
     ip_mgr: Arc<IpManager<S>>,
-    renew_cache: Option<RenewThreshold<Vec<u8>>>,
+    renew_cache: Option<Arc<RenewThreshold<Vec<u8>>>>,
+    decline_cache: Option<FloodCache<Vec<u8>>>,
+    /// allocations for clients from a limited OUI are serialized per OUI, so concurrent
+    /// DISCOVERs can't both see room under the limit
+    oui_locks: Mutex<HashMap<Oui, Arc<AsyncMutex<()>>>>,
+    /// responses aren't sent, so neither are DDNS updates
+    dry_run: bool,
 }
 
 impl<S> fmt::Debug for Leases<S>
@@ -63,15 +83,36 @@ impl<S> Leases<S>
 where
     S: Storage,
 {
-    pub fn new(cfg: Arc<DhcpConfig>, ip_mgr: Arc<IpManager<S>>) -> Self {
+    /// the renew & decline caches are sized from the config at this point, they're kept
+    /// when a staged config is swapped in
+    pub fn new(cfg: impl Into<LiveConfig>, ip_mgr: Arc<IpManager<S>>) -> Self {
+        let cfg = cfg.into();
+        let current = cfg.load();
         Self {
-            renew_cache: cfg.v4().cache_threshold().map(RenewThreshold::new),
+            renew_cache: current
+                .v4()
+                .cache_threshold()
+                .map(|threshold| Arc::new(RenewThreshold::new(threshold))),
+            decline_cache: current.v4().decline_threshold().map(FloodCache::new),
+            oui_locks: Mutex::new(HashMap::new()),
             ip_mgr,
             cfg,
             ddns: DdnsUpdate::new(),
+            dry_run: false,
         }
     }
 
+    /// skip DDNS updates, for `--dry-run`
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// the renew cache, if `cache_threshold` is configured, to report stats from elsewhere
+    pub fn renew_cache(&self) -> Option<Arc<RenewThreshold<Vec<u8>>>> {
+        self.renew_cache.clone()
+    }
+
     pub fn cache_threshold(&self, id: &[u8]) -> Option<Duration> {
         self.renew_cache
             .as_ref()
@@ -94,24 +135,68 @@ where
             });
     }
 
+    /// take the allocation lock for a limited OUI, OUIs come from config so the map
+    /// stays small
+    async fn lock_oui(&self, limit: Option<(Oui, u32)>) -> Option<OwnedMutexGuard<()>> {
+        let (oui, _) = limit?;
+        let lock = self
+            .oui_locks
+            .lock()
+            .expect("oui lock poisoned")
+            .entry(oui)
+            .or_default()
+            .clone();
+        Some(lock.lock_owned().await)
+    }
+
+    /// `true` if other clients from `oui` already hold `max` addresses in `network`.
+    /// A storage error doesn't refuse the client
+    async fn oui_at_limit(&self, client_id: &[u8], network: &Network, oui: Oui, max: u32) -> bool {
+        match self.ip_mgr.oui_leases(network, oui, client_id).await {
+            Ok(count) if count >= max as usize => {
+                debug!(%oui, count, max, subnet = ?network.subnet(), "OUI lease limit reached");
+                if metrics::counted() {
+                    metrics::OUI_LIMITED_COUNT.inc();
+                }
+                true
+            }
+            Ok(_) => false,
+            Err(err) => {
+                error!(?err, "failed to count OUI leases");
+                false
+            }
+        }
+    }
+
+    /// record the OUI of the client `ip` was allocated to, if the OUI is limited
+    async fn set_oui(&self, ip: Ipv4Addr, client_id: &[u8], network: &Network, oui: Option<Oui>) {
+        if let Some(oui) = oui {
+            if let Err(err) = self
+                .ip_mgr
+                .set_oui(ip.into(), network, oui, client_id)
+                .await
+            {
+                error!(?err, "failed to record lease OUI");
+            }
+        }
+    }
+
     fn set_lease(
         &self,
         ctx: &mut MsgContext<Message>,
-        (lease, t1, t2): (Duration, Duration, Duration),
+        (lease, t1, t2): (Duration, Option<Duration>, Option<Duration>),
         ip: Ipv4Addr,
         expires_at: SystemTime,
         classes: Option<&[String]>,
         range: &NetRange,
     ) -> Result<()> {
-        ctx.resp_msg_mut()
-            .context("response message must be set before leases is run")?
-            .set_yiaddr(ip);
-        ctx.populate_opts_lease(
-            &self.cfg.v4().collect_opts(range.opts(), classes),
-            lease,
-            t1,
-            t2,
-        );
+        let cfg = self.cfg.load();
+        let resp = ctx
+            .resp_msg_mut()
+            .context("response message must be set before leases is run")?;
+        resp.set_yiaddr(ip);
+        range.boot().apply(resp);
+        ctx.populate_opts_lease(&cfg.v4().collect_opts(range.opts(), classes), lease, t1, t2);
         ctx.set_local(ExpiresAt(expires_at));
         Ok(())
     }
@@ -124,17 +209,18 @@ where
 {
     #[instrument(level = "debug", skip_all)]
     async fn handle(&self, ctx: &mut MsgContext<Message>) -> Result<Action> {
+        let cfg = self.cfg.load();
         let req = ctx.msg();
 
-        let client_id = self.cfg.v4().client_id(req).to_vec(); // to_vec required b/c of borrowck error
+        let client_id = cfg.v4().client_id(req).to_vec(); // to_vec required b/c of borrowck error
         let subnet = ctx.subnet()?;
         // look up that subnet from our config
-        let network = self.cfg.v4().network(subnet);
+        let network = cfg.v4().network(subnet);
         let classes = ctx.get_local::<MatchedClasses>().map(|c| c.0.to_owned());
         let resp_has_yiaddr = matches!(ctx.resp_msg(), Some(msg) if !msg.yiaddr().is_unspecified());
         let rapid_commit =
-            ctx.msg().opts().get(OptionCode::RapidCommit).is_some() && self.cfg.v4().rapid_commit();
-        let bootp = self.cfg.v4().bootp_enabled();
+            ctx.msg().opts().get(OptionCode::RapidCommit).is_some() && cfg.v4().rapid_commit();
+        let bootp = cfg.v4().bootp_enabled();
 
         match (req.opts().msg_type(), network) {
             // if yiaddr is set, then a previous plugin has already given the message an IP (like static)
@@ -143,9 +229,18 @@ where
             }
             // giaddr has matched one of our configured subnets
             (Some(MessageType::Discover), Some(net)) => {
+                if let Some(action) = self.reservation(ctx, net, classes.as_deref()).await? {
+                    return Ok(action);
+                }
                 self.discover(ctx, &client_id, net, classes, rapid_commit)
                     .await
             }
+            (Some(MessageType::Request), Some(net)) if !resp_has_yiaddr => {
+                if let Some(action) = self.reservation(ctx, net, classes.as_deref()).await? {
+                    return Ok(action);
+                }
+                self.request(ctx, &client_id, net, classes).await
+            }
             (Some(MessageType::Request), Some(net)) => {
                 self.request(ctx, &client_id, net, classes).await
             }
@@ -175,8 +270,7 @@ where
         classes: Option<Vec<String>>,
     ) -> Result<Action> {
         // BOOTP addresses are forever
-        // TODO: we should probably set the expiry time to NULL but for now, 40 years in the future
-        let expires_at = SystemTime::now() + Duration::from_secs(60 * 60 * 24 * 7 * 12 * 40);
+        let expires_at = ip_manager::never_expires();
         let state = Some(IpState::Lease);
         let resp = self
             .first_available(ctx, client_id, network, classes, expires_at, state)
@@ -197,13 +291,20 @@ where
         state: Option<IpState>,
     ) -> Result<Action> {
         let classes = classes.as_deref();
+        let limit = network.oui_limit(ctx.msg().chaddr());
+        let _guard = self.lock_oui(limit).await;
+        if let Some((oui, max)) = limit {
+            if self.oui_at_limit(client_id, network, oui, max).await {
+                return Ok(Action::NoResponse);
+            }
+        }
+        let oui = limit.map(|(oui, _)| oui);
         // requested ip included in message, try to reserve
         if let Some(ip) = ctx.requested_ip() {
             // within our range. `range` makes sure IP is not in exclude list
             if let Some(range) = network.range(ip, classes) {
-                match self
-                    .ip_mgr
-                    .try_ip(
+                match retry_busy(|| {
+                    self.ip_mgr.try_ip(
                         ip.into(),
                         network.subnet().into(),
                         client_id,
@@ -211,7 +312,8 @@ where
                         network,
                         state,
                     )
-                    .await
+                })
+                .await
                 {
                     Ok(_) => {
                         debug!(
@@ -222,6 +324,7 @@ where
                             subnet = ?network.subnet(),
                            "reserved IP for client-- sending offer"
                         );
+                        self.set_oui(ip, client_id, network, oui).await;
                         let lease = range.lease().determine_lease(ctx.requested_lease_time());
                         self.set_lease(ctx, lease, ip, expires_at, classes, range)?;
                         return Ok(Action::Continue);
@@ -238,11 +341,12 @@ where
             }
         }
         // no requested IP, so find the next available
-        for range in network.ranges_with_class(classes) {
-            match self
-                .ip_mgr
-                .reserve_first(range, network, client_id, expires_at, state)
-                .await
+        for range in self.fill_order(network, classes).await {
+            match retry_busy(|| {
+                self.ip_mgr
+                    .reserve_first(range, network, client_id, expires_at, state)
+            })
+            .await
             {
                 Ok(IpAddr::V4(ip)) => {
                     debug!(
@@ -253,6 +357,7 @@ where
                         subnet = ?network.subnet(),
                         "reserved IP for client-- sending offer"
                     );
+                    self.set_oui(ip, client_id, network, oui).await;
                     let lease = range.lease().determine_lease(ctx.requested_lease_time());
                     self.set_lease(ctx, lease, ip, expires_at, classes, range)?;
                     return Ok(Action::Continue);
@@ -262,7 +367,7 @@ where
                     error!(?err);
                 }
                 _ => {
-                    // all other errors try next
+                    // all other errors try next, a DISCOVER is never NAKed
                 }
             }
         }
@@ -270,6 +375,80 @@ where
         Ok(Action::NoResponse)
     }
 
+    /// ranges matching `classes` in the order the network's `fill` tries them, config order
+    /// if the addresses in use can't be counted
+    async fn fill_order<'a>(
+        &self,
+        network: &'a Network,
+        classes: Option<&'a [String]>,
+    ) -> Vec<&'a NetRange> {
+        let ranges = network.ranges_with_class(classes).collect::<Vec<_>>();
+        if network.fill() == Fill::Sequential || ranges.len() < 2 {
+            return ranges;
+        }
+        let mut used = Vec::with_capacity(ranges.len());
+        for range in &ranges {
+            match self.ip_mgr.range_counts(range).await {
+                Ok(counts) => used.push((*range, counts.used())),
+                Err(err) => {
+                    error!(
+                        ?err,
+                        "failed to count addresses in use, trying ranges in config order"
+                    );
+                    return ranges;
+                }
+            }
+        }
+        network.fill().order(used)
+    }
+
+    /// Imported reservations (see [`ip_manager::reservations`]) are handled like reservations
+    /// in the config file. They are never inside a dynamic range, so nothing is written to the
+    /// leases table. Returns `None` if the client has no reservation in this network.
+    async fn reservation(
+        &self,
+        ctx: &mut MsgContext<Message>,
+        network: &Network,
+        classes: Option<&[String]>,
+    ) -> Result<Option<Action>> {
+        let cfg = self.cfg.load();
+        let res = match self.ip_mgr.reservation(ctx.msg().chaddr()).await {
+            Ok(Some(res)) => res,
+            Ok(None) => return Ok(None),
+            Err(err) => {
+                error!(?err, "failed to look up reservation");
+                return Ok(None);
+            }
+        };
+        // reservation may belong to a different network than the one the message came from
+        if !matches!(cfg.v4().network(res.ip), Some(net) if net.subnet() == network.subnet()) {
+            return Ok(None);
+        }
+        if ctx.msg().opts().msg_type() == Some(MessageType::Request)
+            && ctx.requested_ip() != Some(res.ip)
+        {
+            debug!(requested = ?ctx.requested_ip(), reserved = ?res.ip, "reserved ip does not match");
+            if !network.authoritative() {
+                return Ok(Some(Action::NoResponse));
+            }
+            ctx.update_resp_msg(MessageType::Nak)
+                .context("failed to set msg type")?;
+            return Ok(Some(Action::Respond));
+        }
+        let mut opts = res.options.map(|opts| opts.0).unwrap_or_default();
+        if let Some(hostname) = res.hostname {
+            opts.insert(DhcpOption::Hostname(hostname));
+        }
+        // imported reservations have no lease config, they get the network's
+        let (lease, t1, t2) = network.lease().determine_lease(ctx.requested_lease_time());
+        debug!(ip = ?res.ip, mac = %res.mac, "using imported reservation");
+        ctx.resp_msg_mut()
+            .context("response message must be set before leases is run")?
+            .set_yiaddr(res.ip);
+        ctx.populate_opts_lease(&cfg.v4().collect_opts(&opts, classes), lease, t1, t2);
+        Ok(Some(Action::Continue))
+    }
+
     async fn discover(
         &self,
         ctx: &mut MsgContext<Message>,
@@ -279,14 +458,62 @@ where
         rapid_commit: bool,
     ) -> Result<Action> {
         // give 60 seconds between discover & request, TODO: configurable?
-        let expires_at = SystemTime::now() + OFFER_TIME;
-        let state = if rapid_commit {
-            Some(IpState::Lease)
-        } else {
-            None
+        let expires_at = clock::now() + OFFER_TIME;
+        // always reserve as an offer first, the lease is only committed below once we
+        // know the address is good
+        let action = self
+            .first_available(ctx, client_id, network, classes.clone(), expires_at, None)
+            .await?;
+        if !rapid_commit {
+            return Ok(action);
+        }
+        let ip = match ctx.resp_msg() {
+            Some(resp) if !resp.yiaddr().is_unspecified() => resp.yiaddr(),
+            _ => return Ok(action),
         };
-        self.first_available(ctx, client_id, network, classes, expires_at, state)
+        if let Err(reason) = self
+            .commit_offer(ctx, client_id, ip, network, classes.as_deref())
             .await
+        {
+            debug!(
+                ?ip,
+                ?client_id,
+                reason,
+                "not committing rapid commit lease-- sending OFFER"
+            );
+            ctx.update_resp_msg(MessageType::Offer)
+                .context("failed to set msg type")?;
+        }
+        Ok(action)
+    }
+
+    /// Commit the offered `ip` as a lease. Fails if the address wasn't ping checked,
+    /// the client then gets a normal OFFER instead of an ACK it may have to DECLINE
+    async fn commit_offer(
+        &self,
+        ctx: &mut MsgContext<Message>,
+        client_id: &[u8],
+        ip: Ipv4Addr,
+        network: &Network,
+        classes: Option<&[String]>,
+    ) -> Result<(), &'static str> {
+        if !self.ip_mgr.ping_verified(ip.into(), network) {
+            return Err("ping check pending or skipped");
+        }
+        let range = network.range(ip, classes).ok_or("ip not in range")?;
+        let lease = range.lease().determine_lease(ctx.requested_lease_time());
+        let expires_at = ip_manager::expires_at(lease.0);
+        if let Err(err) = retry_busy(|| {
+            self.ip_mgr
+                .try_lease(ip.into(), client_id, expires_at, network)
+        })
+        .await
+        {
+            debug!(?err, "failed to commit lease");
+            return Err("failed to commit lease");
+        }
+        ctx.set_local(ExpiresAt(expires_at));
+        Ok(())
     }
 
     async fn request(
@@ -296,6 +523,7 @@ where
         network: &Network,
         classes: Option<Vec<String>>,
     ) -> Result<Action> {
+        let cfg = self.cfg.load();
         // requested ip comes from opts or ciaddr
         let ip = match ctx.requested_ip() {
             Some(ip) => ip,
@@ -318,15 +546,21 @@ where
         if let Some(range) = range {
             // if we got a recent renewal and the threshold has not past yet, return the existing lease time
             // TODO: move to ip-manager?
-            if let Some(remaining) = self.cache_threshold(client_id) {
-                metrics::RENEW_CACHE_HIT.inc();
+            // infinite leases are never cached, there is no remaining time to hand out
+            let cached = self
+                .cache_threshold(client_id)
+                .filter(|_| !range.lease().is_infinite());
+            if cached.is_none() && self.renew_cache.is_some() && metrics::counted() {
+                metrics::RENEW_CACHE_MISS.inc();
+            }
+            if let Some(remaining) = cached {
+                if metrics::counted() {
+                    metrics::RENEW_CACHE_HIT.inc();
+                }
                 // lease was already handed out so it is valid for this range
-                let lease = (
-                    remaining,
-                    config::renew(remaining),
-                    config::rebind(remaining),
-                );
-                let expires_at = SystemTime::now() + lease.0;
+                let (t1, t2) = range.lease().renewal(remaining);
+                let lease = (remaining, t1, t2);
+                let expires_at = clock::now() + lease.0;
                 debug!(
                     ?ip,
                     ?client_id,
@@ -339,13 +573,28 @@ where
             }
             // no lease info found -- calculate the lease time
             let lease = range.lease().determine_lease(ctx.requested_lease_time());
-            let expires_at = SystemTime::now() + lease.0;
+            let expires_at = ip_manager::expires_at(lease.0);
 
-            match self
-                .ip_mgr
-                .try_lease(ip.into(), client_id, expires_at, network)
-                .await
-            {
+            let limit = network.oui_limit(ctx.msg().chaddr());
+            let guard = self.lock_oui(limit).await;
+            let res = match limit {
+                Some((oui, max)) if self.oui_at_limit(client_id, network, oui, max).await => {
+                    Err(IpError::OuiLimit(oui))
+                }
+                _ => {
+                    retry_busy(|| {
+                        self.ip_mgr
+                            .try_lease(ip.into(), client_id, expires_at, network)
+                    })
+                    .await
+                }
+            };
+            if res.is_ok() {
+                self.set_oui(ip, client_id, network, limit.map(|(oui, _)| oui))
+                    .await;
+            }
+            drop(guard);
+            match res {
                 Ok(_) => {
                     debug!(
                         ?ip,
@@ -357,30 +606,75 @@ where
                     );
                     self.set_lease(ctx, lease, ip, expires_at, classes, range)?;
                     // insert lease into cache
-                    self.cache_insert(client_id, lease.0);
+                    if !range.lease().is_infinite() {
+                        self.cache_insert(client_id, lease.0);
+                    }
+                    if cfg.v4().host_export().is_some() {
+                        let hostname = hostname(ctx.msg());
+                        if let Err(err) = self
+                            .ip_mgr
+                            .set_hostname(ip.into(), client_id, hostname.as_deref())
+                            .await
+                        {
+                            error!(?err, "failed to store lease hostname");
+                        }
+                    }
+                    if cfg.v4().store_fingerprints() {
+                        let fingerprint = Fingerprint::new(ctx.msg());
+                        let fingerprint = LeaseFingerprint {
+                            fingerprint: fingerprint.hash(),
+                            prl: fingerprint.prl_str(),
+                            vendor_class: fingerprint.vendor_class,
+                        };
+                        if let Err(err) = self
+                            .ip_mgr
+                            .set_fingerprint(ip.into(), client_id, &fingerprint)
+                            .await
+                        {
+                            error!(?err, "failed to store lease fingerprint");
+                        }
+                    }
 
                     // do ddns update. Consider this as a plugin?
-                    let dhcid = dhcid(self.cfg.v4(), ctx.msg());
-                    if let Err(err) = self
-                        .ddns
-                        .update(ctx, dhcid, self.cfg.v4().ddns(), range, ip)
-                        .await
-                    {
-                        error!(?err, "error during ddns update");
+                    let dhcid = dhcid(cfg.v4(), ctx.msg());
+                    let budget = ctx.budget();
+                    if self.dry_run {
+                        debug!("dry run, skipping ddns update");
+                    } else {
+                        // a slow DNS server shouldn't cost the client its ACK, the update
+                        // gives up before the handlers' deadline with time left to send it
+                        match budget
+                            .bound_reserving(
+                                DDNS_RESERVE,
+                                self.ddns.update(ctx, dhcid, cfg.v4().ddns(), range, ip),
+                            )
+                            .await
+                        {
+                            Ok(Err(err)) => error!(?err, "error during ddns update"),
+                            Err(_) => warn!(?budget, "ddns update ran out of time budget"),
+                            Ok(Ok(())) => {}
+                        }
                     }
                     return Ok(Action::Continue);
                 }
                 // ip not reserved or chaddr doesn't match
-                Err(err) if network.authoritative() => {
-                    debug!(?err, "can't give out lease");
-                    ctx.update_resp_msg(MessageType::Nak)
-                        .context("failed to set msg type")?;
-                    return Ok(Action::Respond);
-                }
-                Err(err) => {
-                    debug!(?err, "can't give out lease & not authoritative");
-                    ctx.resp_msg_mut().take();
-                }
+                Err(err) => match recovery(&err) {
+                    Recovery::Nak if network.authoritative() => {
+                        debug!(?err, "can't give out lease");
+                        ctx.update_resp_msg(MessageType::Nak)
+                            .context("failed to set msg type")?;
+                        return Ok(Action::Respond);
+                    }
+                    Recovery::Nak => {
+                        debug!(?err, "can't give out lease & not authoritative");
+                        ctx.resp_msg_mut().take();
+                    }
+                    // storage failed, the client's lease may still be good so it isn't NAKed
+                    Recovery::Retry | Recovery::NextRange => {
+                        error!(?err, "can't give out lease, storage failed");
+                        ctx.resp_msg_mut().take();
+                    }
+                },
             }
             Ok(Action::Continue)
         } else {
@@ -413,17 +707,47 @@ where
         } else {
             Err(anyhow!("decline has no option 50 (requested IP)"))
         }?;
-        let expires_at = SystemTime::now() + network.probation_period();
+        // only probate an IP we handed to this client, otherwise anyone could
+        // probate any address by putting it in opt 50
+        if !network.ranges().iter().any(|r| r.contains(declined_ip))
+            || !self
+                .ip_mgr
+                .is_leased_to((*declined_ip).into(), client_id)
+                .await?
+        {
+            if metrics::counted() {
+                metrics::DECLINE_REJECTED_COUNT
+                    .with_label_values(&["not_offered"])
+                    .inc();
+            }
+            warn!(
+                ?declined_ip,
+                ?client_id,
+                "DECLINE for an IP not offered to this client, ignoring"
+            );
+            return Ok(Action::NoResponse);
+        }
+        if let Some(cache) = &self.decline_cache {
+            if !cache.is_allowed(&client_id.to_vec()) {
+                if metrics::counted() {
+                    metrics::DECLINE_REJECTED_COUNT
+                        .with_label_values(&["rate_limited"])
+                        .inc();
+                }
+                warn!(
+                    ?declined_ip,
+                    ?client_id,
+                    "client reached decline threshold, ignoring"
+                );
+                return Ok(Action::NoResponse);
+            }
+        }
         self.ip_mgr
-            .probate_ip((*declined_ip).into(), client_id, expires_at)
+            .probate_ip((*declined_ip).into(), client_id, network)
             .await?;
         // IP is decline, remove from cache
         self.cache_remove(ctx.msg().chaddr());
-        debug!(
-            ?declined_ip,
-            expires_at = %print_time(expires_at),
-            "added declined IP with probation set"
-        );
+        debug!(?declined_ip, "added declined IP with probation set");
         Ok(Action::Continue)
     }
 }
@@ -432,10 +756,75 @@ where
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
 pub struct ExpiresAt(pub SystemTime);
 
+/// How a handler recovers from an [`IpError`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Recovery {
+    /// storage was busy, make the same call again
+    Retry,
+    /// the address can't be given to this client, a REQUEST is NAKed
+    Nak,
+    /// allocating from the range failed, a DISCOVER tries the next range & a REQUEST
+    /// gets no response
+    NextRange,
+}
+
+fn recovery<E>(err: &IpError<E>) -> Recovery {
+    match err {
+        IpError::Busy(_) => Recovery::Retry,
+        IpError::IdMismatch(_)
+        | IpError::Expired(_)
+        | IpError::WrongNetwork { .. }
+        | IpError::Leased(_)
+        | IpError::Reserved(_)
+        | IpError::Unreserved
+        | IpError::AddrInUse(_)
+        | IpError::Excluded(_)
+        | IpError::OuiLimit(_) => Recovery::Nak,
+        IpError::DbError(_) | IpError::RangeError { .. } | IpError::MaxAttempts { .. } => {
+            Recovery::NextRange
+        }
+    }
+}
+
+/// Run `op`, making the call again up to [`STORAGE_RETRIES`] times while storage is busy
+async fn retry_busy<T, E, F, Fut>(mut op: F) -> Result<T, IpError<E>>
+where
+    E: StorageError,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, IpError<E>>>,
+{
+    let mut retries = 0;
+    loop {
+        match op().await {
+            Err(err) if recovery(&err) == Recovery::Retry && retries < STORAGE_RETRIES => {
+                retries += 1;
+                if metrics::counted() {
+                    metrics::STORAGE_RETRY_COUNT.inc();
+                }
+                debug!(?err, retries, "storage busy, retrying");
+                tokio::time::sleep(STORAGE_RETRY_DELAY * retries).await;
+            }
+            res => return res,
+        }
+    }
+}
+
 fn print_time(expires_at: SystemTime) -> String {
     DateTime::<Utc>::from(expires_at).to_rfc3339_opts(SecondsFormat::Secs, true)
 }
 
+/// name the client sent in opt 81 (client FQDN), otherwise opt 12 (hostname)
+fn hostname(msg: &Message) -> Option<String> {
+    match (
+        msg.opts().get(OptionCode::ClientFQDN),
+        msg.opts().get(OptionCode::Hostname),
+    ) {
+        (Some(DhcpOption::ClientFQDN(fqdn)), _) => Some(fqdn.domain().to_string()),
+        (_, Some(DhcpOption::Hostname(hostname))) => Some(hostname.clone()),
+        _ => None,
+    }
+}
+
 /// If opt 61 (client id) exists return that, otherwise return `chaddr` from the message
 /// header.
 pub fn dhcid(cfg: &config::v4::Config, msg: &Message) -> DhcId {
@@ -452,6 +841,7 @@ pub fn dhcid(cfg: &config::v4::Config, msg: &Message) -> DhcId {
 
 #[cfg(test)]
 mod tests {
+    use config::DhcpConfig;
     use dora_core::dhcproto::v4;
     use ip_manager::sqlite::SqliteDb;
     use tracing_test::traced_test;
@@ -489,9 +879,106 @@ mod tests {
             .unwrap()
             .opts()
             .has_msg_type(v4::MessageType::Nak));
+
+        // not authoritative, no response instead of a NAK
+        let cfg = DhcpConfig::parse_str(&format!("authoritative: false\n{SAMPLE_YAML}"))?;
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let leases = Leases::new(Arc::new(cfg), mgr);
+        let mut ctx = message_type::util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Request,
+        )?;
+        assert_eq!(leases.handle(&mut ctx).await?, Action::NoResponse);
         Ok(())
     }
 
+    #[test]
+    fn test_recovery() {
+        let ip: IpAddr = [192, 168, 0, 100].into();
+        for (err, expected) in [
+            (IpError::Busy(TestError), Recovery::Retry),
+            (IpError::IdMismatch(ip), Recovery::Nak),
+            (IpError::Expired(ip), Recovery::Nak),
+            (
+                IpError::WrongNetwork {
+                    ip,
+                    network: [10, 0, 0, 0].into(),
+                },
+                Recovery::Nak,
+            ),
+            (IpError::AddrInUse(ip), Recovery::Nak),
+            (IpError::Unreserved, Recovery::Nak),
+            (IpError::DbError(TestError), Recovery::NextRange),
+            (IpError::RangeError { range: ip..=ip }, Recovery::NextRange),
+        ] {
+            assert_eq!(recovery(&err), expected, "{err:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_busy() {
+        // busy once, then succeeds
+        let mut calls = 0;
+        let res = retry_busy(|| {
+            calls += 1;
+            let res = if calls == 1 {
+                Err(IpError::Busy(TestError))
+            } else {
+                Ok(())
+            };
+            async move { res }
+        })
+        .await;
+        assert!(res.is_ok());
+        assert_eq!(calls, 2);
+        // gives up after STORAGE_RETRIES
+        let mut calls = 0;
+        let res: Result<(), _> = retry_busy(|| {
+            calls += 1;
+            async { Err(IpError::Busy(TestError)) }
+        })
+        .await;
+        assert!(matches!(res, Err(IpError::Busy(_))));
+        assert_eq!(calls, STORAGE_RETRIES + 1);
+        // conflicts aren't retried
+        let mut calls = 0;
+        let res: Result<(), IpError<TestError>> = retry_busy(|| {
+            calls += 1;
+            async { Err(IpError::Unreserved) }
+        })
+        .await;
+        assert!(matches!(res, Err(IpError::Unreserved)));
+        assert_eq!(calls, 1);
+    }
+
+    #[derive(Debug)]
+    struct TestError;
+
+    impl fmt::Display for TestError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "test error")
+        }
+    }
+
+    impl std::error::Error for TestError {}
+
+    impl StorageError for TestError {
+        fn is_retryable(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_hostname() {
+        let mut msg = v4::Message::default();
+        assert_eq!(hostname(&msg), None);
+        msg.opts_mut()
+            .insert(DhcpOption::Hostname("printer-3".to_owned()));
+        assert_eq!(hostname(&msg).as_deref(), Some("printer-3"));
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_discover() -> Result<()> {
@@ -527,6 +1014,105 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn test_oui_limit() -> Result<()> {
+        let yaml = SAMPLE_YAML.replace(
+            "    192.168.0.0/24:\n",
+            "    192.168.0.0/24:\n        oui_limits:\n            - oui: \"01:02:03\"\n              max_leases: 1\n",
+        );
+        let cfg = DhcpConfig::parse_str(yaml).unwrap();
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let leases = Leases::new(Arc::new(cfg.clone()), mgr);
+        let msg = |msg_type: v4::MessageType, chaddr: &[u8]| -> Result<MsgContext<Message>> {
+            let mut ctx = message_type::util::blank_ctx(
+                "192.168.0.1:67".parse()?,
+                "192.168.0.1".parse()?,
+                "192.168.0.1".parse()?,
+                msg_type,
+            )?;
+            ctx.msg_mut().set_chaddr(chaddr);
+            let resp_type = match msg_type {
+                v4::MessageType::Discover => v4::MessageType::Offer,
+                _ => v4::MessageType::Ack,
+            };
+            ctx.resp_msg_mut()
+                .unwrap()
+                .opts_mut()
+                .insert(v4::DhcpOption::MessageType(resp_type));
+            Ok(ctx)
+        };
+
+        let mut ctx = msg(v4::MessageType::Discover, &[1, 2, 3, 4, 5, 6])?;
+        assert_eq!(leases.handle(&mut ctx).await?, Action::Continue);
+        let offered = ctx.resp_msg().unwrap().yiaddr();
+        assert!(!offered.is_unspecified());
+
+        // same OUI, different client
+        let limited = metrics::OUI_LIMITED_COUNT.get();
+        let mut ctx = msg(v4::MessageType::Discover, &[1, 2, 3, 9, 9, 9])?;
+        assert_eq!(leases.handle(&mut ctx).await?, Action::NoResponse);
+        let mut ctx = msg(v4::MessageType::Request, &[1, 2, 3, 9, 9, 9])?;
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::RequestedIpAddress("192.168.0.140".parse()?));
+        leases.handle(&mut ctx).await?;
+        assert!(ctx
+            .resp_msg()
+            .unwrap()
+            .opts()
+            .has_msg_type(v4::MessageType::Nak));
+        assert_eq!(metrics::OUI_LIMITED_COUNT.get(), limited + 2);
+
+        // the client holding the address can still lease it, other OUIs are unaffected
+        let mut ctx = msg(v4::MessageType::Request, &[1, 2, 3, 4, 5, 6])?;
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::RequestedIpAddress(offered));
+        leases.handle(&mut ctx).await?;
+        assert!(ctx
+            .resp_msg()
+            .unwrap()
+            .opts()
+            .has_msg_type(v4::MessageType::Ack));
+        let mut ctx = msg(v4::MessageType::Discover, &[4, 5, 6, 9, 9, 9])?;
+        assert_eq!(leases.handle(&mut ctx).await?, Action::Continue);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_rapid_commit() -> Result<()> {
+        let cfg = DhcpConfig::parse_str(format!("rapid_commit: true\n{SAMPLE_YAML}")).unwrap();
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let leases = Leases::new(Arc::new(cfg.clone()), mgr);
+        let mut ctx = message_type::util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Discover,
+        )?;
+        ctx.msg_mut().opts_mut().insert(v4::DhcpOption::RapidCommit);
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::RequestedIpAddress("192.168.0.100".parse()?));
+        ctx.resp_msg_mut()
+            .unwrap()
+            .opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Ack)); // ack is set in msg type plugin
+
+        leases.handle(&mut ctx).await?;
+        // no ping check on this network, so the lease is committed
+        assert!(ctx
+            .resp_msg()
+            .unwrap()
+            .opts()
+            .has_msg_type(v4::MessageType::Ack));
+        let ExpiresAt(expires_at) = *ctx.get_local::<ExpiresAt>().unwrap();
+        assert!(expires_at > SystemTime::now() + OFFER_TIME);
+        Ok(())
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_release() -> Result<()> {
@@ -606,4 +1192,156 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_imported_reservation() -> Result<()> {
+        let cfg = DhcpConfig::parse_str(SAMPLE_YAML).unwrap();
+        let mgr = IpManager::new(SqliteDb::new("sqlite::memory:").await?)?;
+        let res = ip_manager::reservations::parse_csv("aa:bb:cc:dd:ee:01,192.168.0.200,,")?;
+        mgr.import_reservations(&res).await?;
+        let leases = Leases::new(Arc::new(cfg.clone()), Arc::new(mgr));
+
+        let mut ctx = message_type::util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Request,
+        )?;
+        ctx.msg_mut()
+            .set_chaddr(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01]);
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::RequestedIpAddress("192.168.0.200".parse()?));
+        ctx.resp_msg_mut()
+            .unwrap()
+            .opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Ack)); // ack is set in msg type plugin
+
+        leases.handle(&mut ctx).await?;
+        assert!(ctx
+            .resp_msg()
+            .unwrap()
+            .opts()
+            .has_msg_type(v4::MessageType::Ack));
+        assert_eq!(
+            ctx.resp_msg().unwrap().yiaddr(),
+            Ipv4Addr::new(192, 168, 0, 200)
+        );
+        // the network's range lease time, not the 1 day default
+        assert_eq!(
+            ctx.resp_msg()
+                .unwrap()
+                .opts()
+                .get(v4::OptionCode::AddressLeaseTime),
+            Some(&v4::DhcpOption::AddressLeaseTime(3600))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_ddns_stalled() -> Result<()> {
+        // takes updates & never answers
+        let dns = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        let yaml = format!(
+            "ddns:\n    timeout_ms: 5000\n    tsig_keys: {{}}\n    forward:\n        - name: example.com.\n          ip: {dns}\n    reverse:\n        - name: 168.192.in-addr.arpa.\n          ip: {dns}\n{SAMPLE_YAML}",
+            dns = dns.local_addr()?
+        );
+        let cfg = DhcpConfig::parse_str(yaml)?;
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let leases = Leases::new(Arc::new(cfg), mgr);
+        let msg = |msg_type, resp_type| -> Result<MsgContext<Message>> {
+            let mut ctx = message_type::util::blank_ctx(
+                "192.168.0.1:67".parse()?,
+                "192.168.0.1".parse()?,
+                "192.168.0.1".parse()?,
+                msg_type,
+            )?;
+            ctx.msg_mut()
+                .opts_mut()
+                .insert(v4::DhcpOption::RequestedIpAddress("192.168.0.100".parse()?));
+            ctx.resp_msg_mut()
+                .unwrap()
+                .opts_mut()
+                .insert(v4::DhcpOption::MessageType(resp_type));
+            Ok(ctx)
+        };
+        let mut ctx = msg(v4::MessageType::Discover, v4::MessageType::Offer)?;
+        leases.handle(&mut ctx).await?;
+
+        let mut ctx = msg(v4::MessageType::Request, v4::MessageType::Ack)?;
+        let opts = ctx.msg_mut().opts_mut();
+        opts.insert(v4::DhcpOption::AddressLeaseTime(3600));
+        opts.insert(v4::DhcpOption::ClientFQDN(v4::fqdn::ClientFQDN::new(
+            v4::fqdn::FqdnFlags::default().set_s(true),
+            "host.example.com.".parse()?,
+        )));
+        // bounded the way the server runs plugins
+        ctx.set_deadline(std::time::Instant::now() + Duration::from_millis(300));
+        let budget = ctx.budget();
+        let action = budget.bound(leases.handle(&mut ctx)).await;
+        assert!(
+            matches!(action, Ok(Ok(Action::Continue))),
+            "handler ran out of budget"
+        );
+        assert!(ctx
+            .resp_msg()
+            .unwrap()
+            .opts()
+            .has_msg_type(v4::MessageType::Ack));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_decline() -> Result<()> {
+        let cfg = DhcpConfig::parse_str(SAMPLE_YAML).unwrap();
+        let mgr = Arc::new(IpManager::new(SqliteDb::new("sqlite::memory:").await?)?);
+        let leases = Leases::new(Arc::new(cfg.clone()), mgr.clone());
+        let ip = Ipv4Addr::new(192, 168, 0, 100);
+
+        let mut ctx = message_type::util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Request,
+        )?;
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::RequestedIpAddress(ip));
+        ctx.resp_msg_mut()
+            .unwrap()
+            .opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Ack)); // ack is set in msg type plugin
+        leases.handle(&mut ctx).await?;
+        let client_id = ctx.msg().chaddr().to_vec();
+        assert!(mgr.is_leased_to(ip.into(), &client_id).await?);
+
+        let decline = |chaddr: &[u8]| -> Result<MsgContext<Message>> {
+            let mut ctx = message_type::util::blank_ctx(
+                "192.168.0.1:67".parse()?,
+                "192.168.0.1".parse()?,
+                "192.168.0.1".parse()?,
+                v4::MessageType::Decline,
+            )?;
+            ctx.msg_mut().set_chaddr(chaddr);
+            ctx.msg_mut()
+                .opts_mut()
+                .insert(v4::DhcpOption::RequestedIpAddress(ip));
+            Ok(ctx)
+        };
+
+        // another client can't probate an IP it was never given
+        let rejected = metrics::DECLINE_REJECTED_COUNT.with_label_values(&["not_offered"]);
+        let before = rejected.get();
+        leases.handle(&mut decline(&[6, 5, 4, 3, 2, 1])?).await?;
+        assert_eq!(rejected.get(), before + 1);
+        assert!(mgr.is_leased_to(ip.into(), &client_id).await?);
+
+        // the client that holds the lease can
+        leases.handle(&mut decline(&client_id)?).await?;
+        assert!(!mgr.is_leased_to(ip.into(), &client_id).await?);
+        Ok(())
+    }
 }
@@ -9,28 +9,91 @@
 #![deny(rustdoc::broken_intra_doc_links)]
 #![allow(clippy::cognitive_complexity)]
 
-use client_protection::FloodCache;
+use client_classification::Fingerprint;
+use client_protection::{FloodKeyParts, FloodLimiter};
 use dora_core::{
     dhcproto::{
-        v4::{DhcpOption, Message, MessageType, Opcode, OptionCode},
-        v6,
+        v4::{DhcpOption, DhcpOptions, Message, MessageType, Opcode, OptionCode, UnknownOption},
+        v6, Encodable, Name,
     },
     metrics,
     prelude::*,
+    pretty::V4Msg,
+    server::context::Budget,
     tracing::warn,
 };
 use register_derive::Register;
-use std::{fmt::Debug, net::Ipv4Addr};
+use std::{
+    fmt::Debug,
+    net::{IpAddr, Ipv4Addr},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-use config::{client_classes, DhcpConfig};
+use config::{
+    client_classes, http_boot,
+    v4::{Boot, Insane, KnownPolicy, Looped, Network, SanityPolicy},
+    LiveConfig,
+};
+use ddns::{dhcid::DhcId, DdnsUpdate};
+use identity::{Identity, IdentityProvider};
+use ip_manager::{clock, AddrRegistration, IpManager, Storage};
 
 #[derive(Register)]
 #[register(msg(Message))]
 #[register(msg(v6::Message))]
 #[register(plugin())]
 pub struct MsgType {
-    cfg: Arc<DhcpConfig>,
-    flood: Option<FloodCache<Vec<u8>>>,
+    cfg: LiveConfig,
+    flood: Vec<Arc<FloodLimiter>>,
+    known: Option<Arc<dyn KnownClients>>,
+    identity: Option<Arc<dyn IdentityProvider>>,
+    registry: Option<Arc<dyn AddrRegistry>>,
+    loop_log: Mutex<LoopLog>,
+    ddns: DdnsUpdate,
+    dry_run: bool,
+}
+
+/// when a looped message was last logged & how many have been dropped since
+#[derive(Debug, Default)]
+struct LoopLog {
+    last: Option<Instant>,
+    suppressed: u64,
+}
+
+/// Looks up whether the server knows about a client, used for the `KNOWN` & `UNKNOWN`
+/// built-in client classes. Which lookups count depends on the `known_clients` config
+#[async_trait]
+pub trait KnownClients: Send + Sync + 'static {
+    /// `true` if the client has an imported reservation
+    async fn has_reservation(&self, chaddr: &[u8]) -> bool;
+    /// `true` if the client has an active lease
+    async fn has_lease(&self, client_id: &[u8]) -> bool;
+}
+
+#[async_trait]
+impl<S: Storage> KnownClients for IpManager<S> {
+    // storage errors are treated as unknown
+    async fn has_reservation(&self, chaddr: &[u8]) -> bool {
+        matches!(self.reservation(chaddr).await, Ok(Some(_)))
+    }
+    async fn has_lease(&self, client_id: &[u8]) -> bool {
+        self.lookup_id(client_id).await.is_ok()
+    }
+}
+
+/// Stores addresses SLAAC clients register with ADDR-REG-INFORM (RFC 9686)
+#[async_trait]
+pub trait AddrRegistry: Send + Sync + 'static {
+    async fn register(&self, reg: AddrRegistration) -> Result<()>;
+}
+
+#[async_trait]
+impl<S: Storage> AddrRegistry for IpManager<S> {
+    async fn register(&self, reg: AddrRegistration) -> Result<()> {
+        Ok(self.register_addr(&reg).await?)
+    }
 }
 
 impl Debug for MsgType {
@@ -40,18 +103,211 @@ impl Debug for MsgType {
 }
 
 impl MsgType {
-    pub fn new(cfg: Arc<DhcpConfig>) -> Result<Self> {
+    /// flood limiters are built from the config at this point, they're kept when a
+    /// staged config is swapped in
+    pub fn new(cfg: impl Into<LiveConfig>) -> Result<Self> {
+        let cfg = cfg.into();
         Ok(Self {
-            flood: cfg.v4().flood_threshold().map(FloodCache::new),
+            flood: cfg
+                .load()
+                .v4()
+                .flood_thresholds()
+                .iter()
+                .map(|threshold| Arc::new(FloodLimiter::new(threshold.clone())))
+                .collect(),
             cfg,
+            known: None,
+            identity: None,
+            registry: None,
+            loop_log: Mutex::new(LoopLog::default()),
+            ddns: DdnsUpdate::new(),
+            dry_run: false,
         })
     }
 
-    pub fn flood_check(&self, id: &Vec<u8>) -> bool {
+    /// skip DDNS updates for address registrations, for `--dry-run`
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// store v6 address registrations in `registry`. Without this they're answered but
+    /// not kept
+    pub fn with_addr_registry(mut self, registry: Arc<dyn AddrRegistry>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// look up leases & imported reservations when evaluating `KNOWN`/`UNKNOWN`. Without
+    /// this only reservations in the config are used
+    pub fn with_known_clients(mut self, known: Arc<dyn KnownClients>) -> Self {
+        self.known = Some(known);
+        self
+    }
+
+    /// ask `identity` about clients during classification, lookups are cut off after the
+    /// `identity.timeout_ms` budget from the config
+    pub fn with_identity(mut self, identity: Arc<dyn IdentityProvider>) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// look the client up with the identity backend. A timeout or backend error leaves
+    /// the client unidentified rather than delaying or failing the response. The lookup
+    /// is bounded by the identity timeout or what's left of the message's budget
+    async fn identify(&self, req: &Message, client_id: &[u8], budget: Budget) -> Option<Identity> {
+        let dhcp_cfg = self.cfg.load();
+        let (provider, cfg) = self.identity.as_ref().zip(dhcp_cfg.v4().identity())?;
+        let client = identity::Client {
+            chaddr: req.chaddr(),
+            client_id,
+        };
+        let (result, identity) = match budget
+            .bound_max(cfg.timeout(), provider.lookup(client))
+            .await
+        {
+            Ok(Ok(Some(identity))) => ("known", Some(identity)),
+            Ok(Ok(None)) => ("unknown", None),
+            Ok(Err(err)) => {
+                warn!(?err, "identity lookup failed");
+                ("error", None)
+            }
+            Err(_) => {
+                debug!(budget = ?cfg.timeout(), "identity lookup timed out");
+                ("timeout", None)
+            }
+        };
+        if metrics::counted() {
+            metrics::IDENTITY_LOOKUP_COUNT
+                .with_label_values(&[result])
+                .inc();
+        }
+        identity
+    }
+
+    /// determine if the client is known, only done if a client class references `KNOWN`/`UNKNOWN`
+    async fn known(
+        &self,
+        ctx: &MsgContext<Message>,
+        network: Option<&Network>,
+        client_id: &[u8],
+        identified: bool,
+    ) -> Option<bool> {
+        let cfg = self.cfg.load();
+        if !cfg.v4().classes()?.uses_known() {
+            return None;
+        }
+        if identified {
+            return Some(true);
+        }
+        let req = ctx.msg();
+        let reserved =
+            matches!(network, Some(net) if net.has_reservation(req.chaddr(), req.opts()));
+        Some(match &self.known {
+            Some(known) if !reserved => {
+                known.has_reservation(req.chaddr()).await
+                    || (cfg.v4().known_clients() == KnownPolicy::LeaseOrReservation
+                        && known.has_lease(client_id).await)
+            }
+            _ => reserved,
+        })
+    }
+
+    /// options to answer an INFORM from `addr` with, from the range `addr` is in or the
+    /// client's reservation for it. Lease options are left out, the client's lease is
+    /// whatever it got from whichever server gave it `addr`
+    fn inform_opts(
+        &self,
+        network: &Network,
+        req: &Message,
+        addr: Ipv4Addr,
+        classes: Option<&[String]>,
+    ) -> Option<DhcpOptions> {
+        let cfg = self.cfg.load();
+        let opts = match cfg.v4().range(addr, addr, classes) {
+            Some(range) => range.opts(),
+            None => <[u8; 6]>::try_from(req.chaddr())
+                .ok()
+                .and_then(|[a, b, c, d, e, f]| {
+                    network.get_reserved_mac(MacAddr::new(a, b, c, d, e, f), classes)
+                })
+                .or_else(|| network.search_reserved_opt(req.opts(), classes))
+                .filter(|res| res.ip() == addr)?
+                .opts(),
+        };
+        let mut opts = cfg.v4().collect_opts(opts, classes);
+        for code in [
+            OptionCode::AddressLeaseTime,
+            OptionCode::Renewal,
+            OptionCode::Rebinding,
+        ] {
+            opts.remove(code);
+        }
+        Some(opts)
+    }
+
+    /// RFC 3074, only DISCOVER & REQUEST without a server id are balanced. Anything else
+    /// is either addressed to a specific server or a renewal
+    fn load_balance_check(&self, req: &Message, msg_type: Option<MessageType>) -> bool {
+        let cfg = self.cfg.load();
+        let Some(lb) = cfg.v4().load_balance() else {
+            return true;
+        };
+        let balanced = match msg_type {
+            Some(MessageType::Discover) => true,
+            Some(MessageType::Request) => req.opts().get(OptionCode::ServerIdentifier).is_none(),
+            _ => false,
+        };
+        // the RFC hashes opt 61 if present, regardless of `chaddr_only`, so both
+        // servers agree on the bucket
+        let id = match req.opts().get(OptionCode::ClientIdentifier) {
+            Some(DhcpOption::ClientIdentifier(id)) => &id[..],
+            _ => req.chaddr(),
+        };
+        !balanced || lb.serves(id, req.secs())
+    }
+
+    /// the flood limiters from `flood_protection_threshold`, so their state can be
+    /// inspected & cleared from elsewhere
+    pub fn flood_limiters(&self) -> Vec<Arc<FloodLimiter>> {
+        self.flood.clone()
+    }
+
+    /// Count the packet against every limiter, each has its own quota. Returns the
+    /// limiters that refused it, empty if it's allowed
+    pub fn flood_check(&self, parts: &FloodKeyParts<'_>) -> Vec<&str> {
+        // not short-circuited, every limiter sees every packet
         self.flood
-            .as_ref()
-            .map(|flood| flood.is_allowed(id))
-            .unwrap_or(true)
+            .iter()
+            .filter(|flood| !flood.is_allowed(parts))
+            .map(|flood| flood.name())
+            .collect()
+    }
+
+    /// count a message dropped by loop protection, logging at most one per interval
+    fn log_looped(&self, (looped, interval): (Looped, Duration), req: &Message) {
+        if metrics::counted() {
+            metrics::LOOP_DROP_COUNT
+                .with_label_values(&[looped.as_str()])
+                .inc();
+        }
+        let mut log = self.loop_log.lock().unwrap();
+        let now = Instant::now();
+        if log
+            .last
+            .is_some_and(|last| now.duration_since(last) < interval)
+        {
+            log.suppressed += 1;
+            return;
+        }
+        warn!(
+            reason = looped.as_str(),
+            suppressed = log.suppressed,
+            req = %V4Msg(req),
+            "message came from one of our own MACs or addresses, a relay may be looping replies back. dropping"
+        );
+        log.last = Some(now);
+        log.suppressed = 0;
     }
 }
 
@@ -59,15 +315,26 @@ impl MsgType {
 impl Plugin<Message> for MsgType {
     #[instrument(level = "debug", skip_all)]
     async fn handle(&self, ctx: &mut MsgContext<Message>) -> Result<Action> {
+        let cfg = self.cfg.load();
         // set the interface, using data from config
         // MsgType plugin must run first because future plugins use this data
         let meta = ctx.meta();
-        let interface = self
-            .cfg
+        let interface = cfg
             .v4()
             .find_network(meta.ifindex)
             .context("interface message was received on does not exist?")?;
         ctx.set_interface(interface);
+        // the network may override where the response is sent
+        if let Some(reply_to) = cfg
+            .v4()
+            .network(ctx.subnet()?)
+            .and_then(|net| net.reply_to())
+        {
+            ctx.set_local(reply_to);
+        }
+        if let Some(order) = cfg.v4().option_order() {
+            ctx.set_local(order.clone());
+        }
 
         let req = ctx.msg();
         let msg_type = req.opts().msg_type();
@@ -78,30 +345,169 @@ impl Plugin<Message> for MsgType {
             msg_type = ?msg_type,
             src_addr = %ctx.src_addr(),
             ?subnet,
-            req = %ctx.msg(),
+            req = %V4Msg(ctx.msg()),
+            fingerprint = %Fingerprint::new(req),
         );
 
-        let client_id = self.cfg.v4().client_id(req).to_vec(); // to_vec required b/c of borrowck error
-        if !self.flood_check(&client_id) {
-            metrics::FLOOD_THRESHOLD_COUNT.inc();
+        if let Some(looped) = cfg
+            .v4()
+            .loop_protection()
+            .and_then(|lp| Some((lp.check(req)?, lp.log_interval())))
+        {
+            self.log_looped(looped, req);
+            return Ok(Action::NoResponse);
+        }
+        if let Some(checks) = cfg.v4().sanity_checks() {
+            if let Some(insane) = checks.check(req) {
+                if metrics::counted() {
+                    metrics::SANITY_CHECK_COUNT
+                        .with_label_values(&[insane.as_str()])
+                        .inc();
+                }
+                match checks.policy() {
+                    SanityPolicy::Drop => {
+                        debug!(check = insane.as_str(), "bogus packet, not responding");
+                        return Ok(Action::NoResponse);
+                    }
+                    SanityPolicy::Log => {
+                        warn!(check = insane.as_str(), req = %V4Msg(ctx.msg()), "bogus packet");
+                    }
+                    SanityPolicy::Tag => {
+                        debug!(check = insane.as_str(), "bogus packet, tagging as SUSPECT");
+                        ctx.set_local(insane);
+                    }
+                }
+            }
+        }
+        if let Some(shed) = cfg.v4().load_shedding() {
+            // RELEASE & DECLINE give addresses back & are never retried
+            let retried = !matches!(
+                msg_type,
+                Some(MessageType::Release) | Some(MessageType::Decline)
+            );
+            let state = ctx.state();
+            if retried && shed.sheds(state.live_msgs(), state.live_limit(), ctx.msg().secs()) {
+                if metrics::counted() {
+                    metrics::LOAD_SHED_COUNT.inc();
+                }
+                debug!(
+                    secs = ctx.msg().secs(),
+                    live = state.live_msgs(),
+                    "server is busy & client hasn't retried long enough, not responding"
+                );
+                return Ok(Action::NoResponse);
+            }
+        }
+        let req = ctx.msg();
+        let client_id = cfg.v4().client_id(req).to_vec(); // to_vec required b/c of borrowck error
+        let limited = self.flood_check(&FloodKeyParts {
+            client_id: &client_id,
+            chaddr: req.chaddr(),
+            giaddr: req.giaddr(),
+            source: ctx.src_addr().ip(),
+            network: cfg.v4().network(subnet).map(|net| net.subnet()),
+        });
+        if !limited.is_empty() {
+            if metrics::counted() {
+                metrics::FLOOD_THRESHOLD_COUNT.inc();
+            }
+            for key in &limited {
+                if metrics::counted() {
+                    metrics::FLOOD_THRESHOLD_KEY_COUNT
+                        .with_label_values(&[*key])
+                        .inc();
+                }
+            }
             debug!(
                 ?client_id,
+                ?limited,
                 "client is chatty, engaging rate limit and not responding"
             );
             return Ok(Action::NoResponse);
         }
+        if !self.load_balance_check(req, msg_type) {
+            if metrics::counted() {
+                metrics::LOAD_BALANCE_SKIP_COUNT.inc();
+            }
+            debug!(
+                ?client_id,
+                "client belongs to load balancing peer, not responding"
+            );
+            return Ok(Action::NoResponse);
+        }
+        // look up which network the message belongs to
+        let network = cfg.v4().network(subnet);
+        if let Some(denied) = network
+            .and_then(|net| net.relays())
+            .and_then(|relays| relays.check(ctx.msg()))
+        {
+            if metrics::counted() {
+                metrics::RELAY_DENIED_COUNT
+                    .with_label_values(&[denied.as_str()])
+                    .inc();
+            }
+            debug!(
+                giaddr = %ctx.msg().giaddr(),
+                reason = denied.as_str(),
+                "relay not allowed for network, not responding"
+            );
+            return Ok(Action::NoResponse);
+        }
+        if msg_type == Some(MessageType::Discover)
+            && client_classification::is_random_mac(ctx.msg())
+            && metrics::counted()
+        {
+            metrics::RANDOM_MAC_DISCOVER_COUNT.inc();
+        }
+        // evaluate client classes, classes can override the server id
+        let identity = self.identify(ctx.msg(), &client_id, ctx.budget()).await;
+        let known = self
+            .known(ctx, network, &client_id, identity.is_some())
+            .await;
+        let tags = identity.as_ref().map(|id| id.tags()).unwrap_or_default();
+        let matched = util::client_classes(cfg.v4(), ctx, known, tags)?;
+        count_matches(
+            cfg.v4()
+                .classes()
+                .zip(matched.as_deref())
+                .map(|(classes, matched)| classes.configured(matched)),
+        );
+        if let Some(identity) = identity {
+            ctx.set_local(identity);
+        }
+        // workarounds for broken clients, applied once every plugin has run
+        let mut transforms = cfg.v4().transforms(matched.as_deref());
+        // UEFI HTTP boot clients get their URL & vendor class no matter what options the
+        // range or classes set
+        let boot_url = network.and_then(|net| net.http_boot_url(ctx.msg().opts()));
+        if let Some(url) = boot_url {
+            debug!(?url, "answering UEFI HTTP boot client");
+            transforms
+                .get_or_insert_with(Default::default)
+                .insert
+                .extend(http_boot::opts(url));
+        }
+        if let Some(transforms) = transforms {
+            ctx.set_local(transforms);
+        }
+        let req = ctx.msg();
         // otherwise our interface IP as the id
-        let cfg_server_id = self
-            .cfg
+        let cfg_server_id = cfg
             .v4()
-            .server_id(meta.ifindex, subnet)
+            .server_id(meta.ifindex, subnet, matched.as_deref())
             .context("cannot find server_id")?;
-        // look up which network the message belongs to
-        let network = self.cfg.v4().network(subnet);
-        let sname = network.and_then(|net| net.server_name());
-        let fname = network.and_then(|net| net.file_name());
+        // classes & network, the range or reservation can still override these
+        let mut boot = cfg.v4().boot(network, matched.as_deref());
+        if let Some(url) = boot_url.filter(|url| url.len() <= Boot::MAX_FILE_NAME) {
+            boot.file_name = Some(url.to_owned());
+        }
         // message that will be returned
-        let mut resp = util::new_msg(req, cfg_server_id, sname, fname);
+        let mut resp = util::new_msg(
+            req,
+            boot.next_server.unwrap_or(cfg_server_id),
+            boot.server_name.as_deref(),
+            boot.file_name.as_deref(),
+        );
 
         // determine the server id to use in the response message
         let resp_server_id = RespServerId::new(cfg_server_id, req);
@@ -122,8 +528,6 @@ impl Plugin<Message> for MsgType {
             return Ok(Action::NoResponse);
         }
 
-        // evaluate client classes
-        let matched = util::client_classes(self.cfg.v4(), ctx)?;
         let addr = {
             let ciaddr = ctx.msg().ciaddr();
             if !ciaddr.is_unspecified() {
@@ -134,7 +538,7 @@ impl Plugin<Message> for MsgType {
             }
         };
         let rapid_commit =
-            ctx.msg().opts().get(OptionCode::RapidCommit).is_some() && self.cfg.v4().rapid_commit();
+            ctx.msg().opts().get(OptionCode::RapidCommit).is_some() && cfg.v4().rapid_commit();
 
         match msg_type {
             Some(MessageType::Discover) if rapid_commit => {
@@ -156,22 +560,37 @@ impl Plugin<Message> for MsgType {
                 resp.opts_mut()
                     .insert(DhcpOption::MessageType(MessageType::Ack));
             }
-            // got INFORM & we are authoritative, give a response
+            // got INFORM & we are authoritative, RFC 2131 4.3.5: the client already has an
+            // address, ACK with its config only. The ACK has no yiaddr or lease time & echoes
+            // ciaddr. It's sent by the usual rules, to the relay if there is one & otherwise
+            // to ciaddr, unless the network's `reply_to` overrides them. No other plugin
+            // runs, so no lease is touched
             Some(MessageType::Inform) if matches!(network, Some(net) if net.authoritative()) => {
+                if dropped(matched.as_deref()) {
+                    return Ok(Action::NoResponse);
+                }
+                let network = network.context("authoritative network must exist")?;
+                let Some(opts) = self.inform_opts(network, req, addr, matched.as_deref()) else {
+                    warn!(msg_type = ?MessageType::Inform, "couldn't match appropriate range with INFORM message");
+                    return Ok(Action::NoResponse);
+                };
                 resp.opts_mut()
                     .insert(DhcpOption::MessageType(MessageType::Ack));
-
-                if let Some(range) = self.cfg.v4().range(addr, addr, matched.as_deref()) {
-                    ctx.set_resp_msg(resp);
-                    ctx.populate_opts(
-                        &self.cfg.v4().collect_opts(range.opts(), matched.as_deref()),
-                    );
-                    if let Some(classes) = matched {
-                        ctx.set_local(MatchedClasses(classes));
-                    }
-                    return Ok(Action::Respond);
+                resp.set_ciaddr(req.ciaddr());
+                ctx.set_resp_msg(resp);
+                ctx.populate_opts(&opts);
+                if let Some(classes) = matched {
+                    count_responses(cfg.v4().classes().map(|c| c.configured(&classes)));
+                    ctx.set_local(MatchedClasses(classes));
                 }
-                warn!(msg_type = ?MessageType::Inform, "couldn't match appropriate range with INFORM message");
+                return Ok(Action::Respond);
+            }
+            Some(MessageType::Inform) => {
+                debug!(
+                    ?subnet,
+                    "INFORM on a non-authoritative or unknown network, ignoring"
+                );
+                return Ok(Action::NoResponse);
             }
             Some(MessageType::Decline) => {
                 if let Some(DhcpOption::RequestedIpAddress(ip)) =
@@ -185,7 +604,7 @@ impl Plugin<Message> for MsgType {
                     return Ok(Action::NoResponse);
                 }
             }
-            None if req.opcode() == Opcode::BootRequest && self.cfg.v4().bootp_enabled() => {
+            None if req.opcode() == Opcode::BootRequest && cfg.v4().bootp_enabled() => {
                 // No message type but BOOTREQUEST, this is a BOOTP message
                 ctx.set_resp_msg(resp);
                 return Ok(Action::Continue);
@@ -196,22 +615,194 @@ impl Plugin<Message> for MsgType {
             }
         }
 
+        if dropped(matched.as_deref()) {
+            return Ok(Action::NoResponse);
+        }
         if let Some(classes) = matched {
-            if classes
-                .iter()
-                .any(|class| class == client_classes::client_classification::DROP_CLASS)
-            {
-                // contains DROP class, drop packet
-                debug!("DROP class matched");
-                return Ok(Action::NoResponse);
-            }
+            count_responses(cfg.v4().classes().map(|c| c.configured(&classes)));
             ctx.set_local(MatchedClasses(classes));
         }
+        // RFC 8925: the network is IPv6-only preferred & the client asked for opt 108,
+        // respond with V6ONLY_WAIT and no yiaddr, skipping address allocation
+        if let (Some(MessageType::Discover | MessageType::Request), Some(wait)) =
+            (msg_type, network.and_then(|net| net.v6_only_wait()))
+        {
+            if util::requested_opt(ctx.msg(), OptionCode::from(V6_ONLY_PREFERRED)) {
+                debug!(
+                    ?wait,
+                    "client is IPv6-only capable, responding with opt 108"
+                );
+                resp.opts_mut()
+                    .insert(DhcpOption::Unknown(UnknownOption::new(
+                        OptionCode::from(V6_ONLY_PREFERRED),
+                        (wait.as_secs() as u32).to_be_bytes().to_vec(),
+                    )));
+                ctx.set_resp_msg(resp);
+                return Ok(Action::Respond);
+            }
+        }
         ctx.set_resp_msg(resp);
         Ok(Action::Continue)
     }
 }
 
+/// IPv6-Only Preferred option code (RFC 8925)
+pub const V6_ONLY_PREFERRED: u8 = 108;
+/// RFC 9686 message types
+pub const ADDR_REG_INFORM: u8 = 36;
+pub const ADDR_REG_REPLY: u8 = 37;
+/// ADDR-REG-ENABLE option code (RFC 9686), tells clients the server accepts registrations
+pub const ADDR_REG_ENABLE: u16 = 148;
+/// v6 Client FQDN option code (RFC 4704)
+const V6_CLIENT_FQDN: u16 = 39;
+
+impl MsgType {
+    /// RFC 9686: a SLAAC client registering an address it configured. The registration is
+    /// stored & optionally added to DNS, the ADDR-REG-REPLY echoes the IA Address back
+    async fn addr_reg_inform(
+        &self,
+        ctx: &mut MsgContext<v6::Message>,
+        ifindex: u32,
+    ) -> Result<Action> {
+        let cfg = self.cfg.load();
+        let Some(reg_cfg) = cfg.v6().addr_registration() else {
+            debug!("address registration is off, ignoring ADDR-REG-INFORM");
+            return Ok(Action::NoResponse);
+        };
+        let invalid = |reason: &str| -> Result<Action> {
+            if metrics::counted() {
+                metrics::ADDR_REG_COUNT
+                    .with_label_values(&["invalid"])
+                    .inc();
+            }
+            debug!(reason, "discarding ADDR-REG-INFORM");
+            Ok(Action::NoResponse)
+        };
+        let req = ctx.msg();
+        let Some(v6::DhcpOption::ClientId(client_id)) = req.opts().get(v6::OptionCode::ClientId)
+        else {
+            return invalid("no client id");
+        };
+        if req.opts().get(v6::OptionCode::ServerId).is_some() {
+            return invalid("has a server id");
+        }
+        let addrs = req
+            .opts()
+            .iter()
+            .filter_map(|opt| match opt {
+                v6::DhcpOption::IAAddr(ia) => Some(ia),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let &[ia] = addrs.as_slice() else {
+            return invalid("must have exactly one IA Address");
+        };
+        // only the host using an address can register it, & it must belong on this link.
+        // TODO: check the relay peer-address instead once RelayForw is decoded
+        if ctx.src_addr().ip() != IpAddr::V6(ia.addr) {
+            return invalid("address isn't the source address");
+        }
+        if !matches!(cfg.v6().get_network(ifindex), Some(net) if net.contains(&ia.addr)) {
+            return invalid("address isn't on the link");
+        }
+        let (client_id, ia) = (client_id.clone(), ia.clone());
+        let fqdn = client_fqdn(req);
+        debug!(addr = %ia.addr, ?fqdn, valid = ia.valid_life, "address registered");
+
+        if let Some(registry) = &self.registry {
+            let reg = AddrRegistration {
+                ip: ia.addr,
+                client_id: client_id.clone(),
+                fqdn: fqdn.as_ref().map(|fqdn| fqdn.to_string()),
+                expires_at: clock::now() + Duration::from_secs(ia.valid_life as u64),
+            };
+            if let Err(err) = registry.register(reg).await {
+                error!(?err, "failed to store address registration");
+            }
+        }
+        // v6 has no `ddns` section of its own, registrations use the top-level servers &
+        // keys that v4 leases are updated with
+        if let (true, Some(ddns), Some(fqdn)) = (reg_cfg.ddns, cfg.v4().ddns(), fqdn) {
+            if self.dry_run {
+                debug!("dry run, skipping ddns update");
+            } else {
+                let update = self.ddns.register_v6(
+                    ddns,
+                    DhcId::duid(client_id.clone()),
+                    fqdn,
+                    ia.addr,
+                    ia.valid_life,
+                );
+                match ctx.budget().bound(update).await {
+                    Ok(Err(err)) => error!(?err, "error during ddns update"),
+                    Err(_) => warn!("ddns update ran out of time budget"),
+                    Ok(Ok(())) => {}
+                }
+            }
+        }
+        if metrics::counted() {
+            metrics::ADDR_REG_COUNT
+                .with_label_values(&["registered"])
+                .inc();
+        }
+
+        let mut resp =
+            v6::Message::new_with_id(v6::MessageType::Unknown(ADDR_REG_REPLY), ctx.msg().xid());
+        resp.opts_mut()
+            .insert(v6::DhcpOption::ServerId(cfg.v6().server_id().to_vec()));
+        resp.opts_mut().insert(v6::DhcpOption::ClientId(client_id));
+        resp.opts_mut().insert(v6::DhcpOption::IAAddr(ia));
+        ctx.set_resp_msg(resp);
+        Ok(Action::Respond)
+    }
+
+    /// add ADDR-REG-ENABLE to the response if registration is on & the client asked for it
+    fn addr_reg_enable(&self, ctx: &mut MsgContext<v6::Message>) {
+        let cfg = self.cfg.load();
+        if cfg.v6().addr_registration().is_none() {
+            return;
+        }
+        let requested = matches!(
+            ctx.msg().opts().get(v6::OptionCode::ORO),
+            Some(v6::DhcpOption::ORO(oro)) if oro.opts.contains(&ADDR_REG_ENABLE.into())
+        );
+        if let (true, Some(resp)) = (requested, ctx.resp_msg_mut()) {
+            resp.opts_mut()
+                .insert(v6::DhcpOption::Unknown(v6::UnknownOption::new(
+                    ADDR_REG_ENABLE.into(),
+                    Vec::new(),
+                )));
+        }
+    }
+}
+
+/// the domain in a v6 Client FQDN option, `None` unless it's fully qualified. The option
+/// is taken from its encoding so it works however it was decoded
+fn client_fqdn(req: &v6::Message) -> Option<Name> {
+    let opt = req
+        .opts()
+        .iter()
+        .find(|opt| u16::from(v6::OptionCode::from(*opt)) == V6_CLIENT_FQDN)?;
+    let buf = opt.to_vec().ok()?;
+    // code, len & flags come before the name
+    let mut rest = buf.get(5..)?;
+    let mut labels = Vec::new();
+    loop {
+        // running out before the root label means a partial name
+        let (&len, tail) = rest.split_first()?;
+        if len == 0 {
+            break;
+        }
+        let label = tail.get(..len as usize)?;
+        labels.push(std::str::from_utf8(label).ok()?);
+        rest = &tail[len as usize..];
+    }
+    if labels.is_empty() {
+        return None;
+    }
+    Name::from_str(&format!("{}.", labels.join("."))).ok()
+}
+
 /// supports 3 variants:
 /// CfgServerId - the server id retrieved from the config
 /// ServerIdOverride - the server id override retrieved from the RAI in the message
@@ -255,7 +846,11 @@ impl RespServerId {
 }
 
 pub mod util {
-    use config::{client_classes::client_classification::PacketDetails, v4::Config};
+    use config::{
+        client_classes::client_classification::{Now, PacketDetails},
+        v4::Config,
+    };
+    use dora_core::chrono::{Datelike, Local, Timelike};
 
     use super::*;
 
@@ -312,13 +907,33 @@ pub mod util {
                 }
             },
             len: meta.len,
+            now: now(),
+            suspect: false,
+            tags: Vec::new(),
         })
     }
 
-    pub fn client_classes(cfg: &Config, ctx: &MsgContext<Message>) -> Result<Option<Vec<String>>> {
+    /// current server local time for `now.*` class expressions
+    fn now() -> Now {
+        let now = Local::now();
+        Now {
+            hour: now.hour(),
+            weekday: now.weekday().num_days_from_sunday(),
+        }
+    }
+
+    pub fn client_classes(
+        cfg: &Config,
+        ctx: &MsgContext<Message>,
+        known: Option<bool>,
+        tags: Vec<String>,
+    ) -> Result<Option<Vec<String>>> {
+        let mut pkt = util::packet_details(cfg, ctx.meta())?;
+        pkt.suspect = ctx.get_local::<Insane>().is_some();
+        pkt.tags = tags;
         // TODO: what should we do if there is an error processing client classes?
         Ok(cfg
-            .eval_client_classes(ctx.msg(), util::packet_details(cfg, ctx.meta())?)
+            .eval_client_classes(ctx.msg(), pkt, known)
             .and_then(|classes| match classes {
                 Ok(classes) => {
                     debug!(matched_classes = ?classes, "matched classes");
@@ -338,6 +953,14 @@ pub mod util {
     use dora_core::server::msg::SerialMsg;
     use unix_udp_sock::RecvMeta;
 
+    /// returns true if `code` is in the parameter request list of `req`
+    pub fn requested_opt(req: &Message, code: OptionCode) -> bool {
+        matches!(
+            req.opts().get(OptionCode::ParameterRequestList),
+            Some(DhcpOption::ParameterRequestList(prl)) if prl.contains(&code)
+        )
+    }
+
     /// for testing
     pub fn blank_ctx(
         recv_addr: SocketAddr,
@@ -397,19 +1020,19 @@ pub mod util {
 impl Plugin<v6::Message> for MsgType {
     #[instrument(level = "debug", skip_all)]
     async fn handle(&self, ctx: &mut MsgContext<v6::Message>) -> Result<Action> {
+        let cfg = self.cfg.load();
         // import message type variants
         use v6::MessageType::*;
         // set the interface, using data from config
         // MsgType plugin must run first because future plugins use this data
         let meta = ctx.meta();
-        let interface = self
-            .cfg
+        let interface = cfg
             .v6()
             .get_interface_link_local(meta.ifindex)
             .context("no link-local address on interface?")?;
         ctx.set_interface(interface);
 
-        if let Some(global_unicast) = self.cfg.v6().get_interface_global(meta.ifindex) {
+        if let Some(global_unicast) = cfg.v6().get_interface_global(meta.ifindex) {
             ctx.set_global(global_unicast);
         }
 
@@ -424,12 +1047,12 @@ impl Plugin<v6::Message> for MsgType {
             req = %ctx.msg(),
         );
 
-        // let network = self.cfg.v6().get_network(meta.ifindex);
+        // let network = cfg.v6().get_network(meta.ifindex);
 
         // create initial response with reply type
         let mut resp = v6::Message::new_with_id(Reply, req.xid());
 
-        let server_id = self.cfg.v6().server_id();
+        let server_id = cfg.v6().server_id();
         // TODO RelayForw type
         // TODO: make sure we handle client ids as specified - https://www.rfc-editor.org/rfc/rfc8415#section-16.1
         let req_sid = req.opts().get(v6::OptionCode::ServerId);
@@ -442,6 +1065,30 @@ impl Plugin<v6::Message> for MsgType {
         resp.opts_mut()
             .insert(v6::DhcpOption::ServerId(server_id.to_vec()));
 
+        let matched = match cfg.v6().eval_client_classes(req) {
+            Some(Ok(classes)) => {
+                debug!(matched_classes = ?classes, "matched classes");
+                Some(classes)
+            }
+            Some(Err(err)) => {
+                error!(?err, "error processing v6 client classes");
+                None
+            }
+            None => None,
+        };
+        count_matches(
+            cfg.v6()
+                .classes()
+                .zip(matched.as_deref())
+                .map(|(classes, matched)| classes.configured(matched)),
+        );
+        if dropped(matched.as_deref()) {
+            return Ok(Action::NoResponse);
+        }
+        if msg_type == Unknown(ADDR_REG_INFORM) {
+            return self.addr_reg_inform(ctx, meta.ifindex).await;
+        }
+
         match msg_type {
             // discard if it has these types but NO server id
             // https://www.rfc-editor.org/rfc/rfc8415#section-16.6
@@ -449,9 +1096,19 @@ impl Plugin<v6::Message> for MsgType {
                 return Ok(Action::NoResponse);
             }
             InformationRequest => {
-                if let Some(opts) = self.cfg.v6().get_opts(meta.ifindex) {
+                // TODO: pass the relay link-address once RelayForw is decoded, until then
+                // the network is picked by the receiving interface
+                let opts = cfg
+                    .v6()
+                    .collect_opts(meta.ifindex, None, matched.as_deref());
+                if let Some(opts) = opts {
+                    if let Some(classes) = matched {
+                        count_responses(cfg.v6().classes().map(|c| c.configured(&classes)));
+                        ctx.set_local(MatchedClasses(classes));
+                    }
                     ctx.set_resp_msg(resp);
-                    ctx.populate_opts(opts);
+                    ctx.populate_opts(&opts);
+                    self.addr_reg_enable(ctx);
                     return Ok(Action::Respond);
                 }
 
@@ -460,6 +1117,35 @@ impl Plugin<v6::Message> for MsgType {
                     "couldn't match any options with INFORMATION-REQUEST message"
                 );
             }
+            Solicit => {
+                // with no pool the client could use there is nothing to advertise, leave
+                // the client to other servers
+                let has_pool = cfg
+                    .v6()
+                    .select_network(meta.ifindex, None)
+                    .is_some_and(|net| net.ranges_with_class(matched.as_deref()).next().is_some());
+                if !has_pool {
+                    debug!("no v6 address pool for the client, ignoring SOLICIT");
+                    return Ok(Action::NoResponse);
+                }
+                // v6 has its own rapid commit setting, the v4 `rapid_commit` doesn't apply
+                let rapid_commit = cfg.v6().rapid_commit(meta.ifindex);
+                let resp = solicit_resp(req, rapid_commit, server_id);
+                debug!(resp_type = ?resp.msg_type(), rapid_commit, "answering SOLICIT");
+                let opts = cfg
+                    .v6()
+                    .collect_opts(meta.ifindex, None, matched.as_deref());
+                if let Some(classes) = matched {
+                    count_responses(cfg.v6().classes().map(|c| c.configured(&classes)));
+                    ctx.set_local(MatchedClasses(classes));
+                }
+                ctx.set_resp_msg(resp);
+                if let Some(opts) = opts {
+                    ctx.populate_opts(&opts);
+                }
+                self.addr_reg_enable(ctx);
+                return Ok(Action::Respond);
+            }
             _ => {
                 debug!("currently unsupported message type");
                 return Ok(Action::NoResponse);
@@ -475,14 +1161,94 @@ impl Plugin<v6::Message> for MsgType {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MatchedClasses(pub Vec<String>);
 
+/// the client matched the `DROP` class & gets no response
+fn dropped(matched: Option<&[String]>) -> bool {
+    let dropped = matched.is_some_and(|classes| {
+        classes
+            .iter()
+            .any(|class| class == client_classes::client_classification::DROP_CLASS)
+    });
+    if dropped {
+        debug!("DROP class matched");
+    }
+    dropped
+}
+
+/// count the configured classes that matched, once per message. The config helpers that
+/// evaluate classes don't count, they're also used by the API & shadow evaluation
+fn count_matches<'a>(configured: Option<impl Iterator<Item = (&'a str, bool)>>) {
+    if metrics::counted() {
+        for (name, _) in configured.into_iter().flatten() {
+            metrics::CLASS_MATCH_COUNT.with_label_values(&[name]).inc();
+        }
+    }
+}
+
+/// count the matched classes whose options go in the response
+fn count_responses<'a>(configured: Option<impl Iterator<Item = (&'a str, bool)>>) {
+    if metrics::counted() {
+        for (name, _) in configured
+            .into_iter()
+            .flatten()
+            .filter(|(_, has_opts)| *has_opts)
+        {
+            metrics::CLASS_RESPONSE_COUNT
+                .with_label_values(&[name])
+                .inc();
+        }
+    }
+}
+
+/// The response to a Solicit: a Reply carrying Rapid Commit if the client sent the option
+/// & `rapid_commit` is enabled, otherwise an Advertise (RFC 8415 18.3.1). v6 addresses
+/// aren't assigned yet, so a client asking for any IA gets a NoAddrsAvail status in an
+/// Advertise, a Reply would commit the client to a server that has nothing for it.
+fn solicit_resp(req: &v6::Message, rapid_commit: bool, server_id: &[u8]) -> v6::Message {
+    let wants_ia = [
+        v6::OptionCode::IANA,
+        v6::OptionCode::IATA,
+        v6::OptionCode::IAPD,
+    ]
+    .into_iter()
+    .any(|code| req.opts().get(code).is_some());
+    let rapid_commit =
+        rapid_commit && !wants_ia && req.opts().get(v6::OptionCode::RapidCommit).is_some();
+    let msg_type = if rapid_commit {
+        v6::MessageType::Reply
+    } else {
+        v6::MessageType::Advertise
+    };
+    let mut resp = v6::Message::new_with_id(msg_type, req.xid());
+    resp.opts_mut()
+        .insert(v6::DhcpOption::ServerId(server_id.to_vec()));
+    if rapid_commit {
+        resp.opts_mut().insert(v6::DhcpOption::RapidCommit);
+    }
+    if wants_ia {
+        resp.opts_mut()
+            .insert(v6::DhcpOption::StatusCode(v6::StatusCode {
+                status: v6::Status::NoAddrsAvail,
+                msg: "no addresses available".to_owned(),
+            }));
+    }
+    resp
+}
+
 #[cfg(test)]
 mod tests {
     use util::get_server_id_override;
 
-    use dora_core::dhcproto::v4::{self, relay};
+    use dora_core::{
+        dhcproto::{
+            v4::{self, relay},
+            Encodable,
+        },
+        server::context::ReplyTo,
+    };
     use tracing_test::traced_test;
 
     use super::*;
+    use config::DhcpConfig;
 
     static SAMPLE_YAML: &str = include_str!("../../../libs/config/sample/config.yaml");
 
@@ -547,6 +1313,558 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_v6_solicit_rapid_commit() {
+        let server_id = [0, 1, 2, 3];
+        let mut req = v6::Message::new(v6::MessageType::Solicit);
+
+        // no Rapid Commit from the client, Advertise even if enabled
+        for enabled in [false, true] {
+            let resp = solicit_resp(&req, enabled, &server_id);
+            assert_eq!(resp.msg_type(), v6::MessageType::Advertise);
+            assert_eq!(resp.xid(), req.xid());
+            assert!(resp.opts().get(v6::OptionCode::RapidCommit).is_none());
+            assert!(resp.opts().get(v6::OptionCode::StatusCode).is_none());
+        }
+
+        req.opts_mut().insert(v6::DhcpOption::RapidCommit);
+        let resp = solicit_resp(&req, false, &server_id);
+        assert_eq!(resp.msg_type(), v6::MessageType::Advertise);
+        assert!(resp.opts().get(v6::OptionCode::RapidCommit).is_none());
+
+        let resp = solicit_resp(&req, true, &server_id);
+        assert_eq!(resp.msg_type(), v6::MessageType::Reply);
+        assert_eq!(
+            resp.opts().get(v6::OptionCode::RapidCommit),
+            Some(&v6::DhcpOption::RapidCommit)
+        );
+        assert_eq!(
+            resp.opts().get(v6::OptionCode::ServerId),
+            Some(&v6::DhcpOption::ServerId(server_id.to_vec()))
+        );
+
+        // nothing to commit, a NoAddrsAvail is never rapid committed
+        req.opts_mut().insert(v6::DhcpOption::IANA(v6::IANA {
+            id: 1,
+            t1: 0,
+            t2: 0,
+            opts: v6::DhcpOptions::new(),
+        }));
+        let resp = solicit_resp(&req, true, &server_id);
+        assert_eq!(resp.msg_type(), v6::MessageType::Advertise);
+        assert!(resp.opts().get(v6::OptionCode::RapidCommit).is_none());
+        assert!(matches!(
+            resp.opts().get(v6::OptionCode::StatusCode),
+            Some(v6::DhcpOption::StatusCode(v6::StatusCode {
+                status: v6::Status::NoAddrsAvail,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_v6_client_fqdn() {
+        let fqdn = |data: &[u8]| {
+            let mut req = v6::Message::new(v6::MessageType::Unknown(ADDR_REG_INFORM));
+            req.opts_mut()
+                .insert(v6::DhcpOption::Unknown(v6::UnknownOption::new(
+                    V6_CLIENT_FQDN.into(),
+                    data.to_vec(),
+                )));
+            client_fqdn(&req)
+        };
+        assert_eq!(
+            fqdn(b"\x01\x04host\x07example\x03com\x00"),
+            Some(Name::from_str("host.example.com.").unwrap())
+        );
+        // partial names & empty names aren't used
+        assert_eq!(fqdn(b"\x01\x04host"), None);
+        assert_eq!(fqdn(b"\x01\x00"), None);
+        assert_eq!(fqdn(b"\x01\x09host"), None);
+        assert_eq!(
+            client_fqdn(&v6::Message::new(v6::MessageType::Unknown(ADDR_REG_INFORM))),
+            None
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_v6_only_preferred() -> Result<()> {
+        let cfg = DhcpConfig::parse_str(V6_ONLY_YAML).unwrap();
+        let plugin = MsgType::new(Arc::new(cfg.clone()))?;
+        let mut ctx = util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Discover,
+        )?;
+        // no opt 108 in PRL, allocate as normal
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::Continue);
+
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::ParameterRequestList(vec![
+                v4::OptionCode::SubnetMask,
+                v4::OptionCode::from(V6_ONLY_PREFERRED),
+            ]));
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::Respond);
+
+        let resp = ctx.resp_msg().unwrap();
+        assert!(resp.opts().has_msg_type(v4::MessageType::Offer));
+        assert!(resp.yiaddr().is_unspecified());
+        assert_eq!(
+            resp.opts()
+                .get(OptionCode::from(V6_ONLY_PREFERRED))
+                .unwrap()
+                .to_vec()?,
+            vec![V6_ONLY_PREFERRED, 4, 0, 0, 0x0e, 0x10]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_http_boot() -> Result<()> {
+        use dora_core::server::context::RespTransforms;
+
+        let url = "http://192.168.0.5/efi/shimx64.efi";
+        let cfg = DhcpConfig::parse_str(HTTP_BOOT_YAML).unwrap();
+        let plugin = MsgType::new(Arc::new(cfg.clone()))?;
+        let mut ctx = util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Discover,
+        )?;
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::Unknown(UnknownOption::new(
+                OptionCode::from(http_boot::ARCH),
+                vec![0, 0x10],
+            )));
+        // a PXE client of the same architecture isn't an HTTP boot client
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::ClassIdentifier(
+                b"PXEClient:Arch:00007:UNDI:003016".to_vec(),
+            ));
+        plugin.handle(&mut ctx).await?;
+        assert!(ctx
+            .get_local::<RespTransforms>()
+            .is_some_and(|transforms| transforms.insert.is_empty()));
+
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::ClassIdentifier(
+                b"HTTPClient:Arch:00016:UNDI:003001".to_vec(),
+            ));
+        plugin.handle(&mut ctx).await?;
+        let transforms = ctx.get_local::<RespTransforms>().unwrap();
+        assert_eq!(transforms.insert, http_boot::opts(url).to_vec());
+        // a class's transforms are kept
+        assert!(transforms.broadcast);
+        let resp = ctx.resp_msg().unwrap();
+        assert!(resp
+            .fname()
+            .is_some_and(|file| file.starts_with(url.as_bytes())));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_load_balance() -> Result<()> {
+        // a secondary with every bucket on the primary only answers after max_secs
+        let cfg = DhcpConfig::parse_str(format!(
+            "{SAMPLE_YAML}\nload_balance:\n    role: secondary\n    split: 256\n    max_secs: 5\n"
+        ))?;
+        let plugin = MsgType::new(Arc::new(cfg))?;
+        let mut ctx = util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Discover,
+        )?;
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::NoResponse);
+
+        ctx.msg_mut().set_secs(5);
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::Continue);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_load_shedding() -> Result<()> {
+        let cfg = DhcpConfig::parse_str(format!(
+            "{SAMPLE_YAML}\nload_shedding:\n    high_water: 50\n    min_secs: 4\n"
+        ))?;
+        let plugin = MsgType::new(Arc::new(cfg))?;
+        let mut ctx = util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Discover,
+        )?;
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::Continue);
+
+        // half of the 10 live messages in use
+        for _ in 0..5 {
+            ctx.state().inc_live_msgs().await;
+        }
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::NoResponse);
+        ctx.msg_mut().set_secs(4);
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::Continue);
+        for _ in 0..5 {
+            ctx.state().dec_live_msgs();
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_sanity_checks() -> Result<()> {
+        let cfg =
+            DhcpConfig::parse_str(format!("{SAMPLE_YAML}\nsanity_checks:\n    policy: drop\n"))?;
+        let plugin = MsgType::new(Arc::new(cfg))?;
+        let mut ctx = util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Discover,
+        )?;
+        ctx.msg_mut().set_xid(0);
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::NoResponse);
+        ctx.msg_mut().set_xid(1);
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::Continue);
+
+        // tagged packets continue, marked for the SUSPECT class
+        let cfg =
+            DhcpConfig::parse_str(format!("{SAMPLE_YAML}\nsanity_checks:\n    policy: tag\n"))?;
+        let plugin = MsgType::new(Arc::new(cfg))?;
+        ctx.msg_mut().set_secs(u16::MAX);
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::Continue);
+        assert_eq!(ctx.get_local::<Insane>(), Some(&Insane::Secs));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_drop_class() -> Result<()> {
+        let cfg = DhcpConfig::parse_str(DROP_YAML)?;
+        let plugin = MsgType::new(Arc::new(cfg))?;
+        let mut ctx = util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Discover,
+        )?;
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::ClassIdentifier(b"guest".to_vec()));
+        // restricted clients still get a response, the leases plugin has no range to offer
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::Continue);
+        assert!(ctx
+            .get_local::<MatchedClasses>()
+            .unwrap()
+            .0
+            .contains(&"guests".to_owned()));
+
+        // DROP is checked before any allocation, regardless of other class restrictions
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::Hostname("guest-1".to_owned()));
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::NoResponse);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_inform() -> Result<()> {
+        // a lease time configured as an option must not end up in the ACK
+        let yaml = SAMPLE_YAML.replacen(
+            "                        40:",
+            "                        51:\n                            type: u32\n                            value: 3600\n                        40:",
+            1,
+        );
+        let plugin = MsgType::new(Arc::new(DhcpConfig::parse_str(&yaml)?))?;
+        let mut ctx = util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Inform,
+        )?;
+        ctx.msg_mut().set_ciaddr([192, 168, 0, 120]);
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::ParameterRequestList(vec![
+                v4::OptionCode::SubnetMask,
+                v4::OptionCode::Router,
+                v4::OptionCode::AddressLeaseTime,
+            ]));
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::Respond);
+
+        let resp = ctx.resp_msg().unwrap();
+        assert!(resp.opts().has_msg_type(v4::MessageType::Ack));
+        assert_eq!(resp.ciaddr(), Ipv4Addr::new(192, 168, 0, 120));
+        assert!(resp.yiaddr().is_unspecified());
+        assert!(resp.opts().get(v4::OptionCode::Router).is_some());
+        assert!(resp.opts().get(v4::OptionCode::AddressLeaseTime).is_none());
+        // sent by the usual rules, to giaddr if relayed & otherwise to ciaddr
+        assert_eq!(ctx.get_local::<ReplyTo>(), None);
+
+        // `blank_ctx` sets giaddr, the relayed INFORM keeps the network's reply target
+        let broadcast = yaml.replacen(
+            "    192.168.0.0/24:\n",
+            "    192.168.0.0/24:\n        reply_to: broadcast\n",
+            1,
+        );
+        assert_ne!(broadcast, yaml);
+        let relayed = MsgType::new(Arc::new(DhcpConfig::parse_str(&broadcast)?))?;
+        let mut relayed_ctx = util::blank_ctx(
+            "192.168.0.2:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Inform,
+        )?;
+        relayed_ctx.msg_mut().set_ciaddr([192, 168, 0, 120]);
+        assert_eq!(relayed.handle(&mut relayed_ctx).await?, Action::Respond);
+        assert_eq!(
+            relayed_ctx.get_local::<ReplyTo>(),
+            Some(&ReplyTo::Broadcast)
+        );
+
+        // outside any range & without a reservation, there's nothing to answer with
+        ctx.msg_mut().set_ciaddr([192, 168, 0, 200]);
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::NoResponse);
+
+        // not authoritative by default, or by the network overriding the default
+        ctx.msg_mut().set_ciaddr([192, 168, 0, 120]);
+        let not_authoritative = format!("authoritative: false\n{yaml}");
+        let plugin = MsgType::new(Arc::new(DhcpConfig::parse_str(&not_authoritative)?))?;
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::NoResponse);
+        let overridden = not_authoritative.replacen(
+            "    192.168.0.0/24:\n",
+            "    192.168.0.0/24:\n        authoritative: true\n",
+            1,
+        );
+        assert_ne!(overridden, not_authoritative);
+        let plugin = MsgType::new(Arc::new(DhcpConfig::parse_str(&overridden)?))?;
+        assert_eq!(plugin.handle(&mut ctx).await?, Action::Respond);
+        Ok(())
+    }
+
+    /// every client has a lease, nobody has an imported reservation
+    #[derive(Debug)]
+    struct Leased;
+
+    #[async_trait]
+    impl KnownClients for Leased {
+        async fn has_reservation(&self, _chaddr: &[u8]) -> bool {
+            false
+        }
+        async fn has_lease(&self, _client_id: &[u8]) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_unknown_quarantine() -> Result<()> {
+        let classes = |plugin: MsgType, mut ctx: MsgContext<Message>| async move {
+            plugin.handle(&mut ctx).await?;
+            Ok::<_, anyhow::Error>(ctx.get_local::<MatchedClasses>().unwrap().0.clone())
+        };
+        let ctx = || {
+            util::blank_ctx(
+                "192.168.0.1:67".parse().unwrap(),
+                "192.168.0.1".parse().unwrap(),
+                "192.168.0.1".parse().unwrap(),
+                v4::MessageType::Discover,
+            )
+        };
+        // ranges limited to KNOWN/UNKNOWN, without a client_classes section
+        let cfg = DhcpConfig::parse_str(QUARANTINE_YAML)?;
+        let plugin = MsgType::new(Arc::new(cfg.clone()))?.with_known_clients(Arc::new(Leased));
+        let matched = classes(plugin, ctx()?).await?;
+        assert!(matched.contains(&"KNOWN".to_owned()));
+
+        // a lease from the quarantine range doesn't make the client known
+        let cfg = DhcpConfig::parse_str(format!("{QUARANTINE_YAML}known_clients: reservation\n"))?;
+        let plugin = MsgType::new(Arc::new(cfg.clone()))?.with_known_clients(Arc::new(Leased));
+        let matched = classes(plugin, ctx()?).await?;
+        assert!(matched.contains(&"UNKNOWN".to_owned()));
+        let range = cfg
+            .v4()
+            .network([192, 168, 0, 1])
+            .unwrap()
+            .ranges_with_class(Some(&matched))
+            .next()
+            .unwrap();
+        assert_eq!(range.start(), Ipv4Addr::new(192, 168, 0, 200));
+        assert_eq!(range.lease().get_default().as_secs(), 300);
+        Ok(())
+    }
+
+    /// identifies every client, after `delay`
+    #[derive(Debug)]
+    struct Directory {
+        delay: std::time::Duration,
+    }
+
+    #[async_trait]
+    impl IdentityProvider for Directory {
+        async fn lookup(&self, _client: identity::Client<'_>) -> Result<Option<Identity>> {
+            tokio::time::sleep(self.delay).await;
+            Ok(Some(Identity {
+                vlan: Some(20),
+                classes: vec!["printers".to_owned()],
+            }))
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_identity() -> Result<()> {
+        let cfg = DhcpConfig::parse_str(format!(
+            "{QUARANTINE_YAML}identity:\n    timeout_ms: 50\n    radius:\n        server: 127.0.0.1:1812\n        secret: s3cret\n"
+        ))?;
+        let ctx = || {
+            util::blank_ctx(
+                "192.168.0.1:67".parse().unwrap(),
+                "192.168.0.1".parse().unwrap(),
+                "192.168.0.1".parse().unwrap(),
+                v4::MessageType::Discover,
+            )
+        };
+        let plugin = MsgType::new(Arc::new(cfg.clone()))?.with_identity(Arc::new(Directory {
+            delay: std::time::Duration::ZERO,
+        }));
+        let mut found = ctx()?;
+        plugin.handle(&mut found).await?;
+        let matched = &found.get_local::<MatchedClasses>().unwrap().0;
+        for class in ["KNOWN", "printers", "VLAN_20"] {
+            assert!(matched.contains(&class.to_owned()));
+        }
+        assert_eq!(found.get_local::<Identity>().unwrap().vlan, Some(20));
+
+        // over budget, the client stays unknown instead of delaying the response
+        let plugin = MsgType::new(Arc::new(cfg))?.with_identity(Arc::new(Directory {
+            delay: std::time::Duration::from_secs(5),
+        }));
+        let mut slow = ctx()?;
+        plugin.handle(&mut slow).await?;
+        let matched = &slow.get_local::<MatchedClasses>().unwrap().0;
+        assert!(matched.contains(&"UNKNOWN".to_owned()));
+        assert!(!matched.contains(&"printers".to_owned()));
+        assert!(slow.get_local::<Identity>().is_none());
+        Ok(())
+    }
+
+    static QUARANTINE_YAML: &str = r#"
+networks:
+    192.168.0.0/24:
+        ranges:
+            -
+                start: 192.168.0.100
+                end: 192.168.0.150
+                class: KNOWN
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        1:
+                            type: ip
+                            value: 192.168.0.1
+            -
+                start: 192.168.0.200
+                end: 192.168.0.250
+                class: UNKNOWN
+                config:
+                    lease_time:
+                        default: 300
+                options:
+                    values:
+                        1:
+                            type: ip
+                            value: 192.168.0.1
+                        6:
+                            type: ip
+                            value: [ 192.168.0.5 ]
+"#;
+
+    static DROP_YAML: &str = r#"
+networks:
+    192.168.0.0/24:
+        ranges:
+            -
+                start: 192.168.0.100
+                end: 192.168.0.150
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        1:
+                            type: ip
+                            value: 192.168.0.1
+client_classes:
+    v4:
+        -
+            name: guests
+            assert: "option[60].exists"
+            deny:
+                - 192.168.0.0/24
+        -
+            name: DROP
+            assert: "member('guests') and option[12].exists"
+"#;
+
+    static HTTP_BOOT_YAML: &str = r#"
+networks:
+    192.168.0.0/24:
+        http_boot:
+            x64: http://192.168.0.5/efi/shimx64.efi
+            arm64: http://192.168.0.5/efi/shimaa64.efi
+        ranges:
+            -
+                start: 192.168.0.100
+                end: 192.168.0.150
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        67:
+                            type: str
+                            value: pxelinux.0
+client_classes:
+    v4:
+        -
+            name: uefi
+            assert: "option[60].exists"
+            transforms:
+                - broadcast
+"#;
+
+    static V6_ONLY_YAML: &str = r#"
+networks:
+    192.168.0.0/24:
+        ipv6_only_preferred: true
+        v6_only_wait: 3600
+        ranges:
+            -
+                start: 192.168.0.100
+                end: 192.168.0.150
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        1:
+                            type: ip
+                            value: 192.168.0.1
+"#;
+
     // ensure the server identifier override is written to the response server identifier when they match
     #[tokio::test]
     #[traced_test]
@@ -0,0 +1,370 @@
+#![warn(
+    missing_debug_implementations,
+    // missing_docs, // we shall remove thee, someday!
+    rust_2018_idioms,
+    unreachable_pub,
+    non_snake_case,
+    non_upper_case_globals
+)]
+#![deny(rustdoc::broken_intra_doc_links)]
+#![allow(clippy::cognitive_complexity)]
+//! # Shadow config
+//!
+//! Once a response has been sent, each message is run again through the same plugins
+//! (`MsgType`, `StaticAddr` & `Leases`) built from a second "shadow" config. For
+//! DISCOVERs & REQUESTs, the response type, network, pool (range or reservation), lease
+//! time, requested options & boot fields of both responses are compared, differences
+//! are logged to the `dora::shadow` target and counted in `shadow_diff_count`. This can
+//! be used to check a config migration doesn't change behavior before switching to it.
+//!
+//! The shadow plugins allocate from their own in-memory lease db, fed by the same
+//! traffic, so the running leases are never touched. Addresses will differ between the
+//! two, which is why the pool is compared rather than the address. Clients that had a
+//! lease before the shadow db started, or have an imported reservation, can show
+//! differences until they come back through DISCOVER. Nothing is sent, pinged or
+//! registered in DNS, and the shadow run isn't counted in the other metrics.
+use std::{net::Ipv4Addr, ops::RangeInclusive, time::Duration};
+
+use dora_core::{
+    dhcproto::v4::{DhcpOption, Message, MessageType, OptionCode},
+    handler::PostResponse,
+    metrics,
+    prelude::*,
+    pretty,
+    server::context::RespTransforms,
+};
+use ipnet::Ipv4Net;
+
+use config::{v4::Config, DhcpConfig, LiveConfig};
+use ip_manager::{sqlite::SqliteDb, IpManager};
+use leases::Leases;
+use message_type::MsgType;
+use static_addr::StaticAddr;
+
+/// compares each response against what the shadow config would have done
+#[derive(Debug)]
+pub struct Shadow {
+    cfg: LiveConfig,
+    shadow: Arc<DhcpConfig>,
+    msg_type: MsgType,
+    static_addr: StaticAddr,
+    leases: Leases<SqliteDb>,
+}
+
+impl Shadow {
+    /// `msg_type` must be built from `shadow`, with the same known clients & identity
+    /// provider as the running one
+    pub async fn new(
+        cfg: impl Into<LiveConfig>,
+        shadow: Arc<DhcpConfig>,
+        msg_type: MsgType,
+    ) -> Result<Self> {
+        let scratch = Arc::new(IpManager::without_ping(
+            SqliteDb::new("sqlite::memory:").await?,
+        ));
+        Ok(Self {
+            static_addr: StaticAddr::new(Arc::clone(&shadow))?,
+            leases: Leases::new(Arc::clone(&shadow), scratch).with_dry_run(true),
+            msg_type,
+            cfg: cfg.into(),
+            shadow,
+        })
+    }
+
+    /// run a copy of the message through the shadow plugins, returning the response
+    /// they would send
+    async fn evaluate(&self, ctx: &MsgContext<Message>) -> Option<Message> {
+        let mut ctx = ctx.replay();
+        metrics::uncounted(async {
+            let plugins: [&dyn Plugin<Message>; 3] =
+                [&self.msg_type, &self.static_addr, &self.leases];
+            for plugin in plugins {
+                match plugin.handle(&mut ctx).await {
+                    Ok(Action::Respond) => break,
+                    Ok(Action::Continue) => {}
+                    Ok(Action::NoResponse) => return None,
+                    Err(err) => {
+                        debug!(?err, plugin = plugin.name(), "shadow plugin failed");
+                        return None;
+                    }
+                }
+            }
+            let transforms = ctx.get_local::<RespTransforms>().cloned();
+            let mut resp = ctx.resp_msg_mut().take()?;
+            if let Some(transforms) = transforms {
+                transforms.apply(&mut resp);
+            }
+            Some(resp)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl PostResponse<Message> for Shadow {
+    #[instrument(level = "debug", skip_all)]
+    async fn handle(&self, ctx: MsgContext<Message>) {
+        // every message goes through, so the shadow leases follow the running ones
+        let resp = self.evaluate(&ctx).await;
+        if !matches!(
+            ctx.msg().opts().msg_type(),
+            Some(MessageType::Discover | MessageType::Request)
+        ) {
+            return;
+        }
+        let subnet = match ctx.subnet() {
+            Ok(subnet) => subnet,
+            Err(err) => {
+                debug!(?err, "no subnet, skipping shadow evaluation");
+                return;
+            }
+        };
+        let running = Evaluation::new(self.cfg.load().v4(), subnet, ctx.msg(), ctx.resp_msg());
+        let shadow = Evaluation::new(self.shadow.v4(), subnet, ctx.msg(), resp.as_ref());
+        metrics::SHADOW_EVAL_COUNT.inc();
+
+        let diffs = running.diff(&shadow);
+        if diffs.is_empty() {
+            trace!("shadow config matches");
+            return;
+        }
+        for field in &diffs {
+            metrics::SHADOW_DIFF_COUNT.with_label_values(&[field]).inc();
+        }
+        info!(
+            target: "dora::shadow",
+            xid = ctx.msg().xid(),
+            chaddr = %pretty::Hex(ctx.msg().chaddr()),
+            ?diffs,
+            ?running,
+            ?shadow,
+            "shadow config would respond differently"
+        );
+    }
+}
+
+/// where the client's address comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pool {
+    Reserved(Ipv4Addr),
+    Range(RangeInclusive<Ipv4Addr>),
+}
+
+/// what a response means in terms of a config
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Evaluation {
+    /// response message type, `None` if there was no response
+    response: Option<MessageType>,
+    network: Option<Ipv4Net>,
+    pool: Option<Pool>,
+    /// lease time, T1 & T2
+    lease: (Option<Duration>, Option<Duration>, Option<Duration>),
+    /// options the client asked for that the response carries, in request order
+    opts: Vec<DhcpOption>,
+    /// siaddr, sname & file
+    boot: (Ipv4Addr, Option<Vec<u8>>, Option<Vec<u8>>),
+}
+
+impl Evaluation {
+    fn new(cfg: &Config, subnet: Ipv4Addr, req: &Message, resp: Option<&Message>) -> Self {
+        let network = cfg.networks().find(|(net, _)| net.contains(&subnet));
+        let Some(resp) = resp else {
+            return Self {
+                network: network.map(|(net, _)| *net),
+                ..Self::default()
+            };
+        };
+        let ip = resp.yiaddr();
+        let pool = network
+            .filter(|_| !ip.is_unspecified())
+            .and_then(
+                |(_, network)| match network.reservations().find(|res| res.ip() == ip) {
+                    Some(res) => Some(Pool::Reserved(res.ip())),
+                    None => network
+                        .ranges()
+                        .iter()
+                        .find(|range| range.contains(&ip))
+                        .map(|range| Pool::Range(range.addrs())),
+                },
+            );
+        let secs = |code| match resp.opts().get(code) {
+            Some(
+                DhcpOption::AddressLeaseTime(secs)
+                | DhcpOption::Renewal(secs)
+                | DhcpOption::Rebinding(secs),
+            ) => Some(Duration::from_secs(*secs as u64)),
+            _ => None,
+        };
+        let opts = match req.opts().get(OptionCode::ParameterRequestList) {
+            Some(DhcpOption::ParameterRequestList(codes)) => codes
+                .iter()
+                .filter_map(|code| resp.opts().get(*code).cloned())
+                .collect(),
+            _ => Vec::new(),
+        };
+        Self {
+            response: resp.opts().msg_type(),
+            network: network.map(|(net, _)| *net),
+            pool,
+            lease: (
+                secs(OptionCode::AddressLeaseTime),
+                secs(OptionCode::Renewal),
+                secs(OptionCode::Rebinding),
+            ),
+            opts,
+            boot: (
+                resp.siaddr(),
+                resp.sname().map(<[u8]>::to_vec),
+                resp.fname().map(<[u8]>::to_vec),
+            ),
+        }
+    }
+
+    /// names of the fields that differ from `other`
+    fn diff(&self, other: &Self) -> Vec<&'static str> {
+        [
+            ("response", self.response != other.response),
+            ("network", self.network != other.network),
+            ("pool", self.pool != other.pool),
+            ("lease", self.lease != other.lease),
+            ("options", self.opts != other.opts),
+            ("boot", self.boot != other.boot),
+        ]
+        .into_iter()
+        .filter(|(_, differs)| *differs)
+        .map(|(field, _)| field)
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static RUNNING: &str = r#"
+networks:
+    192.168.0.0/24:
+        ranges:
+            -
+                start: 192.168.0.100
+                end: 192.168.0.199
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        3:
+                            type: ip
+                            value: [192.168.0.1]
+                        6:
+                            type: ip
+                            value: [1.1.1.1]
+        reservations:
+            -
+                ip: 192.168.0.50
+                config:
+                    lease_time:
+                        default: 3600
+                options:
+                    values:
+                        3:
+                            type: ip
+                            value: [192.168.0.1]
+                match:
+                    chaddr: aa:bb:cc:dd:ee:ff
+"#;
+
+    fn discover(chaddr: [u8; 6]) -> Result<MsgContext<Message>> {
+        let mut ctx = message_type::util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            MessageType::Discover,
+        )?;
+        ctx.msg_mut().set_chaddr(&chaddr);
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(DhcpOption::ParameterRequestList(vec![
+                OptionCode::Router,
+                OptionCode::DomainNameServer,
+            ]));
+        Ok(ctx)
+    }
+
+    async fn shadow(running: &str, shadow: &str) -> Result<Shadow> {
+        let shadow = Arc::new(DhcpConfig::parse_str(shadow)?);
+        let msg_type = MsgType::new(Arc::clone(&shadow))?;
+        Shadow::new(Arc::new(DhcpConfig::parse_str(running)?), shadow, msg_type).await
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_shadow_diff() -> Result<()> {
+        // pool split in two, different dns server & a longer lease
+        let split = RUNNING
+            .replace("end: 192.168.0.199", "end: 192.168.0.149")
+            .replace("value: [1.1.1.1]", "value: [8.8.8.8]")
+            .replace(
+                "            -\n                start: 192.168.0.100",
+                "            -\n                start: 192.168.0.150\n                end: 192.168.0.199\n                config:\n                    lease_time:\n                        default: 7200\n                options:\n                    values: {}\n            -\n                start: 192.168.0.100",
+            );
+        // the running config evaluated as a shadow of itself, to get its responses
+        let running = shadow(RUNNING, RUNNING).await?;
+        let shadow = shadow(RUNNING, &split).await?;
+        let subnet = Ipv4Addr::new(192, 168, 0, 1);
+        let eval = |cfg: &DhcpConfig, ctx: &MsgContext<Message>, resp: Option<&Message>| {
+            Evaluation::new(cfg.v4(), subnet, ctx.msg(), resp)
+        };
+
+        // reservation is the same in both
+        let ctx = discover([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff])?;
+        let res = eval(&running.shadow, &ctx, running.evaluate(&ctx).await.as_ref());
+        assert_eq!(res.response, Some(MessageType::Offer));
+        assert_eq!(res.pool, Some(Pool::Reserved([192, 168, 0, 50].into())));
+        let other = eval(&shadow.shadow, &ctx, shadow.evaluate(&ctx).await.as_ref());
+        assert!(res.diff(&other).is_empty());
+
+        // the shadow config offers from its new first range, with its lease & no options
+        let ctx = discover([0x00, 0x11, 0x22, 0x33, 0x44, 0x55])?;
+        let res = eval(&running.shadow, &ctx, running.evaluate(&ctx).await.as_ref());
+        assert_eq!(res.opts.len(), 2);
+        let other = eval(&shadow.shadow, &ctx, shadow.evaluate(&ctx).await.as_ref());
+        assert_eq!(
+            other.pool,
+            Some(Pool::Range(
+                [192, 168, 0, 150].into()..=[192, 168, 0, 199].into()
+            ))
+        );
+        assert_eq!(res.diff(&other), ["pool", "lease", "options"]);
+
+        // no network, neither responds
+        let ctx = discover([0x00, 0x11, 0x22, 0x33, 0x44, 0x55])?;
+        let none = Evaluation::new(
+            running.cfg.load().v4(),
+            [10, 0, 0, 1].into(),
+            ctx.msg(),
+            None,
+        );
+        assert_eq!(none.network, None);
+        assert_eq!(&none.diff(&res)[..3], ["response", "network", "pool"]);
+
+        // the post-response handler logs the difference
+        let ctx = discover([0x00, 0x11, 0x22, 0x33, 0x44, 0x66])?;
+        let mut live = ctx.replay();
+        live.set_resp_msg(running.evaluate(&ctx).await.unwrap());
+        PostResponse::handle(&shadow, live).await;
+        assert!(logs_contain("shadow config would respond differently"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shadow_uncounted() -> Result<()> {
+        let shadow = shadow(RUNNING, RUNNING).await?;
+        // locally administered mac, counted as a random mac DISCOVER by MsgType
+        let ctx = discover([0x02, 0x11, 0x22, 0x33, 0x44, 0x55])?;
+        let before = metrics::RANDOM_MAC_DISCOVER_COUNT.get();
+        assert!(shadow.evaluate(&ctx).await.is_some());
+        assert_eq!(metrics::RANDOM_MAC_DISCOVER_COUNT.get(), before);
+        Ok(())
+    }
+}
@@ -15,19 +15,19 @@ use dora_core::{
 };
 use register_derive::Register;
 
-use config::{v4::Reserved, DhcpConfig};
+use config::{v4::Reserved, LiveConfig};
 use message_type::{MatchedClasses, MsgType};
 
 #[derive(Debug, Register)]
 #[register(msg(Message))]
 #[register(plugin(MsgType))]
 pub struct StaticAddr {
-    cfg: Arc<DhcpConfig>,
+    cfg: LiveConfig,
 }
 
 impl StaticAddr {
-    pub fn new(cfg: Arc<DhcpConfig>) -> Result<Self> {
-        Ok(Self { cfg })
+    pub fn new(cfg: impl Into<LiveConfig>) -> Result<Self> {
+        Ok(Self { cfg: cfg.into() })
     }
 }
 
@@ -35,6 +35,7 @@ impl StaticAddr {
 impl Plugin<Message> for StaticAddr {
     #[instrument(level = "debug", skip_all)]
     async fn handle(&self, ctx: &mut MsgContext<Message>) -> Result<Action> {
+        let cfg = self.cfg.load();
         let req = ctx.msg();
         let chaddr = req.chaddr().to_vec();
 
@@ -43,13 +44,13 @@ impl Plugin<Message> for StaticAddr {
         // matched classes clone necessary because of ctx borrowck
         let classes = ctx.get_local::<MatchedClasses>().map(|m| m.0.to_owned());
         let classes = classes.as_deref();
-        if let Some(net) = self.cfg.v4().network(subnet) {
+        if let Some(net) = cfg.v4().network(subnet) {
             // determine if we have a reservation based on mac
             if chaddr.len() == 6 {
                 let mac = MacAddr::new(
                     chaddr[0], chaddr[1], chaddr[2], chaddr[3], chaddr[4], chaddr[5],
                 );
-                let bootp = self.cfg.v4().bootp_enabled();
+                let bootp = cfg.v4().bootp_enabled();
                 if let Some(res) = net.get_reserved_mac(mac, classes) {
                     // mac is present in our config
                     return match req.opts().msg_type() {
@@ -88,18 +89,16 @@ impl StaticAddr {
         classes: Option<&[String]>,
         res: &Reserved,
     ) -> Result<Action> {
+        let cfg = self.cfg.load();
         let static_ip = res.ip();
         let (lease, t1, t2) = res.lease().determine_lease(ctx.requested_lease_time());
         debug!(?static_ip, ?chaddr, "use static requested ip");
-        ctx.resp_msg_mut()
-            .context("response message must be set before static is run")?
-            .set_yiaddr(static_ip);
-        ctx.populate_opts_lease(
-            &self.cfg.v4().collect_opts(res.opts(), classes),
-            lease,
-            t1,
-            t2,
-        );
+        let resp = ctx
+            .resp_msg_mut()
+            .context("response message must be set before static is run")?;
+        resp.set_yiaddr(static_ip);
+        res.boot().apply(resp);
+        ctx.populate_opts_lease(&cfg.v4().collect_opts(res.opts(), classes), lease, t1, t2);
         Ok(Action::Continue)
     }
 
@@ -112,13 +111,16 @@ impl StaticAddr {
         classes: Option<&[String]>,
         res: &Reserved,
     ) -> Result<Action> {
+        let cfg = self.cfg.load();
         let static_ip = res.ip();
         debug!(?static_ip, ?chaddr, "BOOTREPLY using static ip");
-        ctx.resp_msg_mut()
-            .context("response message must be set before static is run")?
-            .set_yiaddr(static_ip);
+        let resp = ctx
+            .resp_msg_mut()
+            .context("response message must be set before static is run")?;
+        resp.set_yiaddr(static_ip);
+        res.boot().apply(resp);
         // populate opts with no lease time info
-        ctx.populate_opts(&self.cfg.v4().collect_opts(res.opts(), classes));
+        ctx.populate_opts(&cfg.v4().collect_opts(res.opts(), classes));
         // remove options that aren't allowed in a BOOTP response
         ctx.filter_dhcp_opts();
         Ok(Action::Respond)
@@ -131,6 +133,7 @@ impl StaticAddr {
         classes: Option<&[String]>,
         res: &Reserved,
     ) -> Result<Action> {
+        let cfg = self.cfg.load();
         let static_ip = res.ip();
         // requested ip comes from opts or ciaddr
         let ip = if let Some(ip) = ctx.requested_ip() {
@@ -155,15 +158,12 @@ impl StaticAddr {
 
         let (lease, t1, t2) = res.lease().determine_lease(ctx.requested_lease_time());
         dbg!(ip);
-        ctx.resp_msg_mut()
-            .context("response message must be set before static plugin is run")?
-            .set_yiaddr(ip);
-        ctx.populate_opts_lease(
-            &self.cfg.v4().collect_opts(res.opts(), classes),
-            lease,
-            t1,
-            t2,
-        );
+        let resp = ctx
+            .resp_msg_mut()
+            .context("response message must be set before static plugin is run")?;
+        resp.set_yiaddr(ip);
+        res.boot().apply(resp);
+        ctx.populate_opts_lease(&cfg.v4().collect_opts(res.opts(), classes), lease, t1, t2);
         trace!(?ip, "populating response with static ip");
 
         Ok(Action::Continue)
@@ -174,6 +174,7 @@ impl StaticAddr {
 mod tests {
     use std::net::Ipv4Addr;
 
+    use config::DhcpConfig;
     use dora_core::dhcproto::v4;
     use tracing_test::traced_test;
 
@@ -228,4 +229,28 @@ mod tests {
         );
         Ok(())
     }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_discover_hostname() -> Result<()> {
+        let cfg = DhcpConfig::parse_str(SAMPLE_YAML).unwrap();
+        let plugin = StaticAddr::new(Arc::new(cfg.clone()))?;
+        let mut ctx = util::blank_ctx(
+            "192.168.0.1:67".parse()?,
+            "192.168.0.1".parse()?,
+            "192.168.0.1".parse()?,
+            v4::MessageType::Discover,
+        )?;
+        // MAC has no reservation, hostname does
+        ctx.msg_mut().set_chaddr(&hex::decode(b"112233445566")?);
+        ctx.msg_mut()
+            .opts_mut()
+            .insert(v4::DhcpOption::Hostname("PRINTER-3".to_owned()));
+        plugin.handle(&mut ctx).await?;
+
+        assert_eq!(
+            ctx.resp_msg().unwrap().yiaddr(),
+            Ipv4Addr::new(192, 168, 0, 180)
+        );
+        Ok(())
+    }
 }